@@ -1,57 +1,566 @@
 use crate::result::{BsvDbBaseError, BsvDbBaseResult};
-use bitcoinsv::bitcoin::BlockchainId;
-use config::{Config, File, FileFormat};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, File, FileFormat};
+use hex::FromHex;
 use serde::Deserialize;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::path::Path;
 
+/// The names of the block sources that can be configured for import.
+pub const IMPORT_SOURCES: [&str; 3] = ["rpc", "bsvdb", "p2p"];
+
+/// The names of the write modes of a replicated block archive.
+pub const REPLICA_WRITE_MODES: [&str; 2] = ["all", "quorum"];
+
+/// The names of the responses of the chain store when a write trips its guardrail, see
+/// [ChainStoreConfig::guardrail].
+pub const GUARDRAIL_ACTIONS: [&str; 4] = ["off", "log", "force", "reject"];
+
+/// The default of [BlockArchiveConfig::max_buffered_block_bytes], 1 GiB.
+pub const DEFAULT_MAX_BUFFERED_BLOCK_BYTES: u64 = 1 << 30;
+
+/// The foundationdb API versions that can be selected with [ChainStoreConfig::fdb_api_version].
+///
+/// The bindings are built for the 7.1 client, whose API version is 710. Older API versions keep
+/// the behaviour of older clients, but the client library must still be able to talk to the
+/// cluster.
+pub const FDB_API_VERSIONS: RangeInclusive<i32> = 510..=710;
+
+/// The default of [ChainStoreConfig::fdb_api_version], the API version of the 7.1 client.
+pub const DEFAULT_FDB_API_VERSION: i32 = 710;
+
+/// The names of the supported blockchains.
+pub const BLOCKCHAINS: [&str; 4] = ["mainnet", "testnet", "stn", "regtest"];
+
+/// A configuration value that is not valid, see [BSVDBConfig::validate()].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigViolation {
+    /// The TOML path of the offending key, e.g. "import.max_parallel".
+    pub key: String,
+    /// What is wrong with the value.
+    pub message: String,
+}
+
+impl ConfigViolation {
+    fn new(key: &str, message: impl Into<String>) -> Self {
+        ConfigViolation {
+            key: String::from(key),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 #[allow(unused)]
 pub struct BlockArchiveConfig {
     pub enabled: bool,
     pub root_path: String,
+    /// Store blocks directly in the root directory, without sub-directories.
+    pub flat: bool,
+    /// Make stored blocks read-only, and immutable on Linux when the process is permitted to, so
+    /// that they can not be changed or deleted by accident.
+    pub immutable_blocks: bool,
+    /// The root paths of copies of the archive. Reads fail over to them, in order, when the
+    /// archive at root_path can not be read, and blocks are written to all of them.
+    pub replicas: Vec<String>,
+    /// How many copies must store a block when there are replicas, "all" or "quorum".
+    pub replica_write_mode: String,
+    /// Keep an index file of the stored blocks, which is read to list the blocks instead of
+    /// walking the directories.
+    pub use_index: bool,
+    /// The size of the largest block that is held in memory to be stored, such as by a write to
+    /// the replicas. Larger blocks must be streamed from a reader.
+    pub max_buffered_block_bytes: u64,
+    /// The maximum number of files that the archive has open at once, opens wait until one is
+    /// closed. 0 for half of the soft limit of the process on open files.
+    pub max_open_files: u64,
+}
+
+impl Default for BlockArchiveConfig {
+    fn default() -> Self {
+        BlockArchiveConfig {
+            enabled: false,
+            root_path: String::from("~/.bsvdb/blockstore"),
+            flat: false,
+            immutable_blocks: false,
+            replicas: Vec::new(),
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: DEFAULT_MAX_BUFFERED_BLOCK_BYTES,
+            max_open_files: 0,
+        }
+    }
+}
+
+impl BlockArchiveConfig {
+    /// Check the values, returning the violations found.
+    pub fn validate(&self) -> Vec<ConfigViolation> {
+        let mut v = Vec::new();
+        if self.enabled && self.root_path.is_empty() {
+            v.push(ConfigViolation::new(
+                "block_archive.root_path",
+                "required when the block archive is enabled",
+            ));
+        }
+        for replica in self.replicas.iter() {
+            if replica.is_empty() || *replica == self.root_path {
+                v.push(ConfigViolation::new(
+                    "block_archive.replicas",
+                    format!("\"{}\" is not a separate root path", replica),
+                ));
+            }
+        }
+        if !REPLICA_WRITE_MODES.contains(&self.replica_write_mode.as_str()) {
+            v.push(ConfigViolation::new(
+                "block_archive.replica_write_mode",
+                format!(
+                    "unknown write mode \"{}\", expected one of {}",
+                    self.replica_write_mode,
+                    REPLICA_WRITE_MODES.join(", ")
+                ),
+            ));
+        }
+        if self.max_buffered_block_bytes == 0 {
+            v.push(ConfigViolation::new(
+                "block_archive.max_buffered_block_bytes",
+                "must be at least 1",
+            ));
+        }
+        v
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 #[allow(unused)]
 pub struct ChainStoreConfig {
     pub enabled: bool,
+    /// A directory path in foundationdb that the root_path is placed under, to keep systems that
+    /// share a cluster apart, e.g. "myapp". Empty for no prefix.
+    pub prefix: String,
     pub root_path: String,
+    /// Hex encoded genesis block header, overrides the built-in genesis for regtest.
+    pub genesis_header: String,
+    /// The number of shards that the BlockInfo keys are spread across when a new ChainStore is
+    /// created, 0 for no sharding. Existing ChainStores keep their layout.
+    pub info_shards: u64,
+    /// The maximum number of times a walk through the chain restarts its transaction after it
+    /// became too old, before it fails.
+    pub max_trx_resets: u32,
+    /// The maximum number of read operations that are queued or being processed at once, further
+    /// reads fail with an overloaded error.
+    pub max_reads: u64,
+    /// The maximum number of streams of BlockInfos that are queued or being produced at once.
+    pub max_streams: u64,
+    /// The maximum number of write operations that are queued or being processed at once. Writes
+    /// are queued separately, so that they are not held up by reads.
+    pub max_writes: u64,
+    /// The distance from the most work tip, in blocks of height and of work, within which another
+    /// tip is competing with it and there is a split.
+    pub split_distance: u64,
+    /// The number of seconds after a tip was received that it can be part of a split, older
+    /// tips are stale forks.
+    pub split_window: u64,
+    /// The foundationdb API version that is selected when the network is started, one of
+    /// [FDB_API_VERSIONS]. It can not be newer than the installed client library supports.
+    pub fdb_api_version: i32,
+    /// What the ChainStore does when a write trips the guardrail against runaway imports, one of
+    /// [GUARDRAIL_ACTIONS]: "off" only counts the blocks, "log" logs a warning, "force" rejects
+    /// the write unless it is forced and "reject" always rejects it.
+    pub guardrail: String,
+    /// The maximum number of blocks that are stored in a minute before the guardrail trips, 0
+    /// for no limit.
+    pub guardrail_max_rate: u64,
+    /// The maximum number of blocks that a stored block can be below the most work tip before
+    /// the guardrail trips, 0 for no limit. Not checked in initial sync mode.
+    pub guardrail_max_depth: u64,
+    /// The number of milliseconds that a read of an interactive command waits for the cluster
+    /// before it fails, rather than waiting as long as foundationdb allows.
+    pub interactive_timeout_ms: u64,
+    /// The number of BlockInfos that a walk through the chain reads at once, guessing that the
+    /// parent of a block has the id before it.
+    pub walk_batch_size: u64,
+    /// The number of milliseconds after which a walk through the chain starts a fresh
+    /// transaction, before foundationdb ends it as too old after five seconds.
+    pub walk_trx_rotate_ms: u64,
+    /// The number of reads after which a walk through the chain starts a fresh transaction, so
+    /// that a fast walk does not build up a transaction with many reads.
+    pub walk_trx_rotate_reads: u64,
+}
+
+impl Default for ChainStoreConfig {
+    fn default() -> Self {
+        ChainStoreConfig {
+            enabled: false,
+            prefix: String::new(),
+            root_path: String::new(),
+            genesis_header: String::new(),
+            info_shards: 16,
+            max_trx_resets: 100,
+            max_reads: 512,
+            max_streams: 32,
+            max_writes: 128,
+            split_distance: 3,
+            split_window: 3600,
+            fdb_api_version: DEFAULT_FDB_API_VERSION,
+            guardrail: String::from("off"),
+            guardrail_max_rate: 0,
+            guardrail_max_depth: 0,
+            interactive_timeout_ms: 5000,
+            walk_batch_size: 64,
+            walk_trx_rotate_ms: 4000,
+            walk_trx_rotate_reads: 10_000,
+        }
+    }
+}
+
+impl ChainStoreConfig {
+    /// The foundationdb directory path of the ChainStore, the prefix followed by the root_path.
+    ///
+    /// Both are split on "/", leading and trailing "/" are ignored.
+    pub fn directory_path(&self) -> Vec<String> {
+        let mut r = Vec::new();
+        let prefix = self.prefix.trim_matches('/');
+        if !prefix.is_empty() {
+            r.extend(prefix.split('/').map(String::from));
+        }
+        r.extend(
+            self.root_path
+                .trim_matches('/')
+                .split('/')
+                .map(String::from),
+        );
+        r
+    }
+
+    /// Check the values, returning the violations found.
+    ///
+    /// Whether a genesis header is allowed depends on the blockchain, which is checked by
+    /// [BSVDBConfig::validate()].
+    pub fn validate(&self) -> Vec<ConfigViolation> {
+        let mut v = Vec::new();
+        for (key, path) in [
+            ("chain_store.prefix", &self.prefix),
+            ("chain_store.root_path", &self.root_path),
+        ] {
+            let path = path.trim_matches('/');
+            if !path.is_empty() && path.split('/').any(|d| d.is_empty()) {
+                v.push(ConfigViolation::new(
+                    key,
+                    "contains an empty directory name",
+                ));
+            }
+        }
+        if !self.genesis_header.is_empty() && BlockHeader::from_hex(&self.genesis_header).is_err() {
+            v.push(ConfigViolation::new(
+                "chain_store.genesis_header",
+                "not a hex encoded block header",
+            ));
+        }
+        for (key, limit) in [
+            ("chain_store.max_reads", self.max_reads),
+            ("chain_store.max_streams", self.max_streams),
+            ("chain_store.max_writes", self.max_writes),
+            (
+                "chain_store.interactive_timeout_ms",
+                self.interactive_timeout_ms,
+            ),
+            ("chain_store.walk_batch_size", self.walk_batch_size),
+            ("chain_store.walk_trx_rotate_ms", self.walk_trx_rotate_ms),
+            (
+                "chain_store.walk_trx_rotate_reads",
+                self.walk_trx_rotate_reads,
+            ),
+        ] {
+            if limit == 0 {
+                v.push(ConfigViolation::new(key, "must be at least 1"));
+            }
+        }
+        if !FDB_API_VERSIONS.contains(&self.fdb_api_version) {
+            v.push(ConfigViolation::new(
+                "chain_store.fdb_api_version",
+                format!(
+                    "must be from {} to {}",
+                    FDB_API_VERSIONS.start(),
+                    FDB_API_VERSIONS.end()
+                ),
+            ));
+        }
+        if !GUARDRAIL_ACTIONS.contains(&self.guardrail.as_str()) {
+            v.push(ConfigViolation::new(
+                "chain_store.guardrail",
+                format!(
+                    "unknown action \"{}\", expected one of {}",
+                    self.guardrail,
+                    GUARDRAIL_ACTIONS.join(", ")
+                ),
+            ));
+        }
+        v
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(unused)]
+pub struct ImportConfig {
+    /// The block sources to try, in priority order.
+    pub sources: Vec<String>,
+    /// The RPC URI of an SV Node, used by the "rpc" source.
+    pub rpc_uri: String,
+    /// The maximum number of blocks fetched concurrently.
+    pub max_parallel: usize,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        ImportConfig {
+            sources: vec![String::from("rpc")],
+            rpc_uri: String::new(),
+            max_parallel: 4,
+        }
+    }
+}
+
+impl ImportConfig {
+    /// Check the values, returning the violations found.
+    pub fn validate(&self) -> Vec<ConfigViolation> {
+        let mut v = Vec::new();
+        if self.sources.is_empty() {
+            v.push(ConfigViolation::new(
+                "import.sources",
+                "at least one source is required",
+            ));
+        }
+        for source in self.sources.iter() {
+            if !IMPORT_SOURCES.contains(&source.as_str()) {
+                v.push(ConfigViolation::new(
+                    "import.sources",
+                    format!(
+                        "unknown source \"{}\", expected one of {}",
+                        source,
+                        IMPORT_SOURCES.join(", ")
+                    ),
+                ));
+            }
+        }
+        if self.max_parallel == 0 {
+            v.push(ConfigViolation::new(
+                "import.max_parallel",
+                "must be at least 1",
+            ));
+        }
+        v
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(unused)]
+pub struct TracingConfig {
+    /// The OTLP endpoint that traces are exported to, tracing is not exported if empty.
+    pub endpoint: String,
+    /// The fraction of traces that are sampled, between 0.0 and 1.0.
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            endpoint: String::new(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl TracingConfig {
+    /// Check the values, returning the violations found.
+    pub fn validate(&self) -> Vec<ConfigViolation> {
+        let mut v = Vec::new();
+        if !self.endpoint.is_empty()
+            && !self.endpoint.starts_with("http://")
+            && !self.endpoint.starts_with("https://")
+        {
+            v.push(ConfigViolation::new(
+                "tracing.endpoint",
+                "must be an http:// or https:// URL",
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            v.push(ConfigViolation::new(
+                "tracing.sample_ratio",
+                "must be between 0.0 and 1.0",
+            ));
+        }
+        v
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(unused)]
+pub struct RpcServerConfig {
+    /// The address and port that the JSON-RPC server listens on.
+    pub bind: String,
+    /// The user name that clients authenticate with, a cookie file is used if it is empty.
+    pub user: String,
+    /// The password of the user.
+    pub password: String,
+    /// The file that the credentials are written to when no user is configured, which clients
+    /// read to authenticate, as with the .cookie file of an SV Node.
+    pub cookie_file: String,
+}
+
+impl Default for RpcServerConfig {
+    fn default() -> Self {
+        RpcServerConfig {
+            bind: String::from("127.0.0.1:8332"),
+            user: String::new(),
+            password: String::new(),
+            cookie_file: String::from("~/.bsvdb.cookie"),
+        }
+    }
+}
+
+impl RpcServerConfig {
+    /// Check the values, returning the violations found.
+    pub fn validate(&self) -> Vec<ConfigViolation> {
+        let mut v = Vec::new();
+        if self.bind.parse::<SocketAddr>().is_err() {
+            v.push(ConfigViolation::new(
+                "rpc_server.bind",
+                "must be an address and port, e.g. 127.0.0.1:8332",
+            ));
+        }
+        if self.user.is_empty() != self.password.is_empty() {
+            v.push(ConfigViolation::new(
+                "rpc_server.password",
+                "the user and the password must both be set, or neither",
+            ));
+        }
+        if self.user.contains(':') {
+            v.push(ConfigViolation::new(
+                "rpc_server.user",
+                "must not contain a \":\"",
+            ));
+        }
+        if self.user.is_empty() && self.cookie_file.is_empty() {
+            v.push(ConfigViolation::new(
+                "rpc_server.cookie_file",
+                "required when no user is set",
+            ));
+        }
+        v
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 #[allow(unused)]
 pub struct BSVDBConfig {
     pub verbose: bool,
     pub blockchain: String,
     pub block_archive: BlockArchiveConfig,
     pub chain_store: ChainStoreConfig,
+    pub import: ImportConfig,
+    pub tracing: TracingConfig,
+    pub rpc_server: RpcServerConfig,
+}
+
+impl Default for BSVDBConfig {
+    fn default() -> Self {
+        BSVDBConfig {
+            verbose: false,
+            blockchain: String::from("mainnet"),
+            block_archive: BlockArchiveConfig::default(),
+            chain_store: ChainStoreConfig::default(),
+            import: ImportConfig::default(),
+            tracing: TracingConfig::default(),
+            rpc_server: RpcServerConfig::default(),
+        }
+    }
 }
 
 impl BSVDBConfig {
+    /// Load and validate the configuration.
+    ///
+    /// The default values are overridden by ~/.bsvdb.toml, then by bsvdb.toml in the current
+    /// directory, then by the config_path file if given. Unknown keys are an error.
     pub fn new(config_path: Option<String>) -> BsvDbBaseResult<Self> {
+        let r = Self::load(config_path)?;
+        match r.validate() {
+            Ok(()) => Ok(r),
+            Err(v) => Err(BsvDbBaseError::InvalidConfig(v)),
+        }
+    }
+
+    /// Load the configuration without validating it, see [BSVDBConfig::new()].
+    pub fn load(config_path: Option<String>) -> BsvDbBaseResult<Self> {
         let s1 = Config::builder()
             .add_source(File::from_str(DEFAULT_CONFIG, FileFormat::Toml))
-            .add_source(File::from(Path::new("~/.bsvdb.toml")).required(false))
+            .add_source(File::from(Path::new(&expand_home("~/.bsvdb.toml"))).required(false))
             .add_source(File::new("bsvdb.toml", FileFormat::Toml).required(false));
         let s2 = match config_path {
             Some(cf) => s1.add_source(File::new(cf.as_str(), FileFormat::Toml)),
             None => s1,
         };
-        let r: BSVDBConfig = s2.build()?.try_deserialize()?;
-        if r.blockchain != "mainnet"
-            && r.blockchain != "testnet"
-            && r.blockchain != "stn"
-            && r.blockchain != "regtest"
-        {
-            Err(BsvDbBaseError::BlockchainUnknown)
+        Self::build(s2)
+    }
+
+    // deserialize the configuration and expand the paths
+    fn build(builder: ConfigBuilder<DefaultState>) -> BsvDbBaseResult<Self> {
+        let mut r: BSVDBConfig = builder.build()?.try_deserialize()?;
+        r.block_archive.root_path = expand_home(&r.block_archive.root_path);
+        for replica in r.block_archive.replicas.iter_mut() {
+            *replica = expand_home(replica);
+        }
+        r.rpc_server.cookie_file = expand_home(&r.rpc_server.cookie_file);
+        Ok(r)
+    }
+
+    /// Check the configuration, returning every violation found.
+    pub fn validate(&self) -> Result<(), Vec<ConfigViolation>> {
+        let mut v = Vec::new();
+        if !BLOCKCHAINS.contains(&self.blockchain.as_str()) {
+            v.push(ConfigViolation::new(
+                "blockchain",
+                format!(
+                    "unknown blockchain \"{}\", expected one of {}",
+                    self.blockchain,
+                    BLOCKCHAINS.join(", ")
+                ),
+            ));
+        }
+        v.extend(self.block_archive.validate());
+        if !self.chain_store.genesis_header.is_empty() && self.blockchain != "regtest" {
+            v.push(ConfigViolation::new(
+                "chain_store.genesis_header",
+                "only allowed when blockchain is \"regtest\"",
+            ));
+        }
+        v.extend(self.chain_store.validate());
+        v.extend(self.import.validate());
+        v.extend(self.tracing.validate());
+        v.extend(self.rpc_server.validate());
+        if v.is_empty() {
+            Ok(())
         } else {
-            Ok(r)
+            Err(v)
         }
     }
 
     /// Get the BlockChainId from the configuration.
     //
-    // It assumes that the blockchain config value is a valid value. This is checked in new().
+    // It assumes that the blockchain config value is a valid value. This is checked in validate().
     pub fn get_blockchain_id(&self) -> BlockchainId {
         // todo: this should be provided as a function by BlockchainId
         if self.blockchain == "mainnet" {
@@ -65,6 +574,21 @@ impl BSVDBConfig {
         }
     }
 
+    /// Get the hash of the genesis block of the configured chain.
+    ///
+    /// This is the hash of chain_store.genesis_header if it is set, for private chains with their
+    /// own genesis block, otherwise the hash of the built-in genesis block. It assumes that the
+    /// genesis header is valid, this is checked in validate().
+    pub fn genesis_hash(&self) -> BlockHash {
+        if self.chain_store.genesis_header.is_empty() {
+            BlockHeader::get_genesis(self.get_blockchain_id()).hash()
+        } else {
+            BlockHeader::from_hex(&self.chain_store.genesis_header)
+                .unwrap()
+                .hash()
+        }
+    }
+
     /// Get the root path for the ChainStore, accounting for default based on BlockChain
     pub fn get_chain_store_root_path(&self) -> String {
         if self.chain_store.root_path.is_empty() {
@@ -96,15 +620,257 @@ impl BSVDBConfig {
     }
 }
 
+// expand a leading "~/" to the home directory
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => String::from(path),
+    }
+}
+
+// The defaults are given by the Default implementations, this is a minimal example.
 const DEFAULT_CONFIG: &str = r#"
-verbose = false
 blockchain = "mainnet"
 
 [block_archive]
 enabled = false
-root_path = "~/.bsvdb/blockstore"
-
-[chain_store]
-enabled = false
-root_path = ""
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> BsvDbBaseResult<BSVDBConfig> {
+        BSVDBConfig::build(Config::builder().add_source(File::from_str(toml, FileFormat::Toml)))
+    }
+
+    fn violation(key: &str, message: &str) -> ConfigViolation {
+        ConfigViolation::new(key, message)
+    }
+
+    // The example configuration sets every key.
+    #[test]
+    fn full_config() {
+        let c = parse(include_str!("../../bsvdb_full.toml")).unwrap();
+        assert!(c.validate().is_ok());
+        assert!(c.block_archive.enabled);
+        assert!(!c.block_archive.immutable_blocks);
+        assert_eq!(c.block_archive.root_path, "/mnt/local/data/mainnet");
+        assert!(c.block_archive.replicas.is_empty());
+        assert_eq!(c.block_archive.replica_write_mode, "all");
+        assert!(!c.block_archive.use_index);
+        assert_eq!(c.block_archive.max_buffered_block_bytes, 1_073_741_824);
+        assert_eq!(c.block_archive.max_open_files, 0);
+        assert_eq!(c.chain_store.root_path, "bsvmain");
+        assert!(c.chain_store.prefix.is_empty());
+        assert_eq!(c.chain_store.info_shards, 16);
+        assert_eq!(c.chain_store.max_trx_resets, 100);
+        assert_eq!(c.chain_store.max_reads, 512);
+        assert_eq!(c.chain_store.max_streams, 32);
+        assert_eq!(c.chain_store.max_writes, 128);
+        assert_eq!(c.chain_store.split_distance, 3);
+        assert_eq!(c.chain_store.split_window, 3600);
+        assert_eq!(c.chain_store.fdb_api_version, 710);
+        assert_eq!(c.chain_store.guardrail, "log");
+        assert_eq!(c.chain_store.guardrail_max_rate, 6000);
+        assert_eq!(c.chain_store.guardrail_max_depth, 1000);
+        assert_eq!(c.chain_store.interactive_timeout_ms, 5000);
+        assert_eq!(c.chain_store.walk_batch_size, 64);
+        assert_eq!(c.chain_store.walk_trx_rotate_ms, 4000);
+        assert_eq!(c.chain_store.walk_trx_rotate_reads, 10_000);
+        assert_eq!(c.import.sources, vec![String::from("rpc")]);
+        assert_eq!(c.import.max_parallel, 4);
+        assert_eq!(c.tracing.sample_ratio, 1.0);
+        assert_eq!(c.rpc_server.bind, "127.0.0.1:8332");
+        assert!(c.rpc_server.user.is_empty());
+        assert!(c.rpc_server.cookie_file.ends_with("/.bsvdb.cookie"));
+    }
+
+    // Keys that are not given take the default values.
+    #[test]
+    fn defaults() {
+        let c = parse(DEFAULT_CONFIG).unwrap();
+        assert!(c.validate().is_ok());
+        assert_eq!(c.blockchain, "mainnet");
+        assert!(!c.block_archive.enabled);
+        assert!(!c.block_archive.root_path.starts_with('~'));
+        assert!(!c.chain_store.enabled);
+        assert_eq!(c.chain_store.info_shards, 16);
+        assert_eq!(c.chain_store.guardrail, "off");
+        assert_eq!(c.import.sources, vec![String::from("rpc")]);
+        assert_eq!(c.import.max_parallel, 4);
+        assert!(c.tracing.endpoint.is_empty());
+        let c = parse("").unwrap();
+        assert_eq!(c.blockchain, "mainnet");
+        assert_eq!(c.tracing.sample_ratio, 1.0);
+    }
+
+    // Every violation is reported, in the order of the keys.
+    #[test]
+    fn invalid_values() {
+        let c = parse(
+            r#"
+            [block_archive]
+            enabled = true
+            root_path = ""
+            replicas = ["/mnt/copy", ""]
+            replica_write_mode = "most"
+            max_buffered_block_bytes = 0
+            [chain_store]
+            prefix = "myapp//bsvdb"
+            genesis_header = "00"
+            max_writes = 0
+            interactive_timeout_ms = 0
+            walk_batch_size = 0
+            fdb_api_version = 720
+            guardrail = "warn"
+            [import]
+            sources = ["rpc", "ftp"]
+            max_parallel = 0
+            [tracing]
+            endpoint = "localhost:4317"
+            sample_ratio = 1.5
+            [rpc_server]
+            bind = "localhost"
+            user = "a:b"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            c.validate().unwrap_err(),
+            vec![
+                violation(
+                    "block_archive.root_path",
+                    "required when the block archive is enabled"
+                ),
+                violation("block_archive.replicas", "\"\" is not a separate root path"),
+                violation(
+                    "block_archive.replica_write_mode",
+                    "unknown write mode \"most\", expected one of all, quorum"
+                ),
+                violation(
+                    "block_archive.max_buffered_block_bytes",
+                    "must be at least 1"
+                ),
+                violation(
+                    "chain_store.genesis_header",
+                    "only allowed when blockchain is \"regtest\""
+                ),
+                violation("chain_store.prefix", "contains an empty directory name"),
+                violation(
+                    "chain_store.genesis_header",
+                    "not a hex encoded block header"
+                ),
+                violation("chain_store.max_writes", "must be at least 1"),
+                violation("chain_store.interactive_timeout_ms", "must be at least 1"),
+                violation("chain_store.walk_batch_size", "must be at least 1"),
+                violation("chain_store.fdb_api_version", "must be from 510 to 710"),
+                violation(
+                    "chain_store.guardrail",
+                    "unknown action \"warn\", expected one of off, log, force, reject"
+                ),
+                violation(
+                    "import.sources",
+                    "unknown source \"ftp\", expected one of rpc, bsvdb, p2p"
+                ),
+                violation("import.max_parallel", "must be at least 1"),
+                violation("tracing.endpoint", "must be an http:// or https:// URL"),
+                violation("tracing.sample_ratio", "must be between 0.0 and 1.0"),
+                violation(
+                    "rpc_server.bind",
+                    "must be an address and port, e.g. 127.0.0.1:8332"
+                ),
+                violation(
+                    "rpc_server.password",
+                    "the user and the password must both be set, or neither"
+                ),
+                violation("rpc_server.user", "must not contain a \":\""),
+            ]
+        );
+        let c = parse("blockchain = \"foonet\"\n[import]\nsources = []").unwrap();
+        assert_eq!(
+            c.validate().unwrap_err(),
+            vec![
+                violation(
+                    "blockchain",
+                    "unknown blockchain \"foonet\", expected one of mainnet, testnet, stn, regtest"
+                ),
+                violation("import.sources", "at least one source is required"),
+            ]
+        );
+        // a custom genesis header is allowed for regtest
+        let c = parse(
+            r#"
+            blockchain = "regtest"
+            [chain_store]
+            genesis_header = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000"
+            "#,
+        )
+        .unwrap();
+        assert!(c.validate().is_ok());
+    }
+
+    // A misspelled key is an error rather than being ignored.
+    #[test]
+    fn unknown_keys() {
+        let r = parse("[block_archive]\nroot_paht = \"/data\"");
+        assert!(
+            matches!(r, Err(BsvDbBaseError::ConfigError(e)) if e.to_string().contains("root_paht"))
+        );
+        let r = parse("[imports]\nmax_parallel = 2");
+        assert!(
+            matches!(r, Err(BsvDbBaseError::ConfigError(e)) if e.to_string().contains("imports"))
+        );
+        let r = parse("verbos = true");
+        assert!(r.is_err());
+    }
+
+    // The prefix is placed in front of the root path.
+    #[test]
+    fn directory_path() {
+        let mut c = ChainStoreConfig {
+            root_path: String::from("bsvmain"),
+            ..ChainStoreConfig::default()
+        };
+        assert_eq!(c.directory_path(), vec!["bsvmain"]);
+        c.prefix = String::from("/myapp/");
+        c.root_path = String::from("bsvmain/chainstore/");
+        assert_eq!(c.directory_path(), vec!["myapp", "bsvmain", "chainstore"]);
+        c.prefix = String::from("tenant/myapp");
+        c.root_path = String::from("bsvmain");
+        assert_eq!(c.directory_path(), vec!["tenant", "myapp", "bsvmain"]);
+        assert!(c.validate().is_empty());
+    }
+
+    #[test]
+    fn genesis_hash() {
+        let c = parse("blockchain = \"testnet\"").unwrap();
+        assert_eq!(
+            c.genesis_hash(),
+            BlockHeader::get_genesis(BlockchainId::Test).hash()
+        );
+        let c = parse(
+            r#"
+            blockchain = "regtest"
+            [chain_store]
+            genesis_header = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2003000000"
+            "#,
+        )
+        .unwrap();
+        assert_ne!(
+            c.genesis_hash(),
+            BlockHeader::get_genesis(BlockchainId::Regtest).hash()
+        );
+    }
+
+    #[test]
+    fn home_expansion() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(
+            expand_home("~/a/b"),
+            format!("{}/a/b", home.trim_end_matches('/'))
+        );
+        assert_eq!(expand_home("/a/~/b"), "/a/~/b");
+        assert_eq!(expand_home("bsvmain"), "bsvmain");
+    }
+}
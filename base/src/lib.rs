@@ -1,5 +1,14 @@
 mod config;
+mod network;
 mod result;
+mod versions;
 
-pub use config::{BSVDBConfig, BlockArchiveConfig, ChainStoreConfig};
-pub use result::{BsvDbBaseResult, BsvDbBaseError};
+pub use config::{
+    BSVDBConfig, BlockArchiveConfig, ChainStoreConfig, ConfigViolation, ImportConfig,
+    RpcServerConfig, TracingConfig, BLOCKCHAINS, DEFAULT_FDB_API_VERSION,
+    DEFAULT_MAX_BUFFERED_BLOCK_BYTES, FDB_API_VERSIONS, GUARDRAIL_ACTIONS, IMPORT_SOURCES,
+    REPLICA_WRITE_MODES,
+};
+pub use network::network_magic;
+pub use result::{BsvDbBaseError, BsvDbBaseResult};
+pub use versions::{compare_versions, VersionHistory, VersionRecord, MAX_VERSIONS};
@@ -0,0 +1,34 @@
+use bitcoinsv::bitcoin::BlockchainId;
+
+/// The magic bytes of the network, in the order that they appear in P2P messages and in block
+/// files that frame each block with the magic and its size.
+pub fn network_magic(id: BlockchainId) -> [u8; 4] {
+    match id {
+        BlockchainId::Main => [0xe3, 0xe1, 0xf3, 0xe8],
+        BlockchainId::Test => [0xf4, 0xe5, 0xf3, 0xf4],
+        BlockchainId::Stn => [0xfb, 0xce, 0xc4, 0xf9],
+        BlockchainId::Regtest => [0xda, 0xb5, 0xbf, 0xfa],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic() {
+        assert_eq!(network_magic(BlockchainId::Main), [0xe3, 0xe1, 0xf3, 0xe8]);
+        // the magic of each network is different
+        let all = [
+            BlockchainId::Main,
+            BlockchainId::Test,
+            BlockchainId::Stn,
+            BlockchainId::Regtest,
+        ];
+        for (i, a) in all.iter().enumerate() {
+            for b in all[i + 1..].iter() {
+                assert_ne!(network_magic(*a), network_magic(*b));
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::ConfigViolation;
 use config::ConfigError;
 
 /// Standard Result used in the library
@@ -6,22 +7,28 @@ pub type BsvDbBaseResult<T> = std::result::Result<T, BsvDbBaseError>;
 /// Standard error type used in the library
 #[derive(Debug)]
 pub enum BsvDbBaseError {
-    /// The blockchain specified is not recognized.
-    BlockchainUnknown,
     /// The BlockArchive component is not enabled.
     BlockArchiveNotEnabled,
     /// The ChainStore component is not enabled.
     ChainStoreNotEnabled,
     ConfigError(ConfigError),
+    /// The configuration has invalid values.
+    InvalidConfig(Vec<ConfigViolation>),
 }
 
 impl std::fmt::Display for BsvDbBaseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            BsvDbBaseError::BlockchainUnknown => write!(f, "Blockchain not recognized."),
             BsvDbBaseError::BlockArchiveNotEnabled => write!(f, "BlockArchive not enabled."),
             BsvDbBaseError::ChainStoreNotEnabled => write!(f, "ChainStore not enabled"),
             BsvDbBaseError::ConfigError(err) => write!(f, "Config error: {}", err),
+            BsvDbBaseError::InvalidConfig(violations) => {
+                write!(f, "Invalid configuration:")?;
+                for v in violations {
+                    write!(f, "\n  {}", v)?;
+                }
+                Ok(())
+            }
         }
     }
 }
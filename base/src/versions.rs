@@ -0,0 +1,180 @@
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The version history of a store records which versions of the software have written to it, to
+// help explain the oddities of a store. Each version is recorded once, with when it first and last
+// wrote to the store, and only the MAX_VERSIONS most recently seen versions are kept.
+
+/// The number of distinct versions that the version history of a store keeps.
+pub const MAX_VERSIONS: usize = 16;
+
+/// A version of the software that has written to a store, see [VersionHistory].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRecord {
+    /// The version of the crate that wrote to the store, such as "0.1.0".
+    pub version: String,
+    /// The git commit that the crate was built from, if it was set in the BSVDB_GIT_HASH
+    /// environment variable when it was built.
+    pub git_hash: Option<String>,
+    /// The features of the crate that were enabled.
+    pub features: Vec<String>,
+    /// When the version first wrote to the store, in seconds since the epoch.
+    pub first_seen: u64,
+    /// When the version last wrote to the store, in seconds since the epoch.
+    pub last_seen: u64,
+}
+
+impl VersionRecord {
+    /// A record of the version, which is seen at the time now.
+    pub fn new(
+        version: &str,
+        git_hash: Option<&str>,
+        features: &[&str],
+        now: u64,
+    ) -> VersionRecord {
+        VersionRecord {
+            version: String::from(version),
+            git_hash: git_hash.map(String::from),
+            features: features.iter().map(|f| String::from(*f)).collect(),
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    /// The current time in seconds since the epoch, as recorded in the history.
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// The versions of the software that have written to a store, most recently seen first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionHistory {
+    records: Vec<VersionRecord>,
+}
+
+impl VersionHistory {
+    /// A history of the records, in any order.
+    pub fn new(mut records: Vec<VersionRecord>) -> VersionHistory {
+        records.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        VersionHistory { records }
+    }
+
+    /// The records, most recently seen first.
+    pub fn records(&self) -> &[VersionRecord] {
+        &self.records
+    }
+
+    /// The record of the version, if it is in the history.
+    pub fn get(&self, version: &str) -> Option<&VersionRecord> {
+        self.records.iter().find(|r| r.version == version)
+    }
+
+    /// Record that the writer wrote to the store.
+    ///
+    /// A version that is already in the history keeps its first seen time, the rest of its record
+    /// is replaced. The least recently seen versions are removed to keep at most [MAX_VERSIONS],
+    /// they are returned.
+    pub fn record(&mut self, mut writer: VersionRecord) -> Vec<VersionRecord> {
+        if let Some(i) = self
+            .records
+            .iter()
+            .position(|r| r.version == writer.version)
+        {
+            writer.first_seen = self.records.remove(i).first_seen;
+        }
+        self.records.push(writer);
+        self.records.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        self.records.split_off(self.records.len().min(MAX_VERSIONS))
+    }
+
+    /// The newest version in the history that is newer than version, see [compare_versions()].
+    ///
+    /// A store that was written by a newer version may have changed in ways that this version does
+    /// not know about.
+    pub fn newer_than(&self, version: &str) -> Option<&VersionRecord> {
+        self.records
+            .iter()
+            .filter(|r| compare_versions(&r.version, version) == Ordering::Greater)
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+    }
+}
+
+/// Compare two versions such as "0.1.0" by their dot separated parts, numerically for the parts
+/// that are numbers and as text for the others.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let o = match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                _ => x.cmp(y),
+            },
+        };
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(version: &str, now: u64) -> VersionRecord {
+        VersionRecord::new(version, None, &[], now)
+    }
+
+    // A known version keeps its first seen time, the least recently seen versions are removed.
+    #[test]
+    fn record_versions() {
+        let mut h = VersionHistory::default();
+        assert!(h.record(record("0.1.0", 100)).is_empty());
+        assert!(h
+            .record(VersionRecord::new("0.2.0", Some("abc"), &["x"], 200))
+            .is_empty());
+        assert!(h.record(record("0.1.0", 300)).is_empty());
+        assert_eq!(h.records().len(), 2);
+        assert_eq!(h.records()[0].version, "0.1.0");
+        assert_eq!(h.get("0.1.0").unwrap().first_seen, 100);
+        assert_eq!(h.get("0.1.0").unwrap().last_seen, 300);
+        let v2 = h.get("0.2.0").unwrap();
+        assert_eq!((v2.first_seen, v2.last_seen), (200, 200));
+        assert_eq!(v2.git_hash.as_deref(), Some("abc"));
+        assert_eq!(v2.features, vec![String::from("x")]);
+        for i in 0..MAX_VERSIONS as u64 - 2 {
+            assert!(h.record(record(&format!("1.{}.0", i), 400 + i)).is_empty());
+        }
+        let evicted = h.record(record("2.0.0", 1000));
+        let v2 = VersionRecord::new("0.2.0", Some("abc"), &["x"], 200);
+        assert_eq!(evicted, vec![v2]);
+        assert_eq!(h.records().len(), MAX_VERSIONS);
+        assert_eq!(h.records()[0].version, "2.0.0");
+        assert!(h.get("0.2.0").is_none());
+        assert_eq!(h, VersionHistory::new(h.records().to_vec()));
+    }
+
+    #[test]
+    fn compare() {
+        assert_eq!(compare_versions("0.1.0", "0.1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("0.10.0", "0.9.0"), Ordering::Greater);
+        assert_eq!(compare_versions("0.1", "0.1.0"), Ordering::Less);
+        assert_eq!(
+            compare_versions("1.0.0-beta", "1.0.0-alpha"),
+            Ordering::Greater
+        );
+        let mut h = VersionHistory::default();
+        h.record(record("0.1.0", 100));
+        h.record(record("0.3.0", 200));
+        h.record(record("0.2.0", 300));
+        assert!(h.newer_than("0.3.0").is_none());
+        assert_eq!(h.newer_than("0.1.0").unwrap().version, "0.3.0");
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+// the size of an encoded block header
+const HEADER_SIZE: usize = 80;
+
+/// The kind of an artifact, which is data other than a canonical block that is stored in the
+/// [BlockArchive](crate::BlockArchive).
+///
+/// Artifacts of each kind are stored separately from blocks and from artifacts of other kinds.
+///
+/// The enum is open, kinds that are not known to this library can be used with [ArtifactKind::Other].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArtifactKind {
+    /// A candidate block template, starting with the block header.
+    BlockTemplate,
+    /// A block header with a proof, such as a merkle proof of a transaction.
+    HeaderWithProof,
+    /// Any other kind of artifact, identified by name.
+    Other(String),
+}
+
+impl ArtifactKind {
+    /// The name of the kind, which is also the name of the directory that it is stored in.
+    pub fn name(&self) -> &str {
+        match self {
+            ArtifactKind::BlockTemplate => "template",
+            ArtifactKind::HeaderWithProof => "header-proof",
+            ArtifactKind::Other(name) => name,
+        }
+    }
+
+    /// Get the kind from its name.
+    pub fn from_name(name: &str) -> Result<ArtifactKind> {
+        match name {
+            "template" => Ok(ArtifactKind::BlockTemplate),
+            "header-proof" => Ok(ArtifactKind::HeaderWithProof),
+            _ => {
+                check_name(name)?;
+                Ok(ArtifactKind::Other(String::from(name)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ArtifactKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The identifier of an artifact, unique within the kind of the artifact.
+///
+/// Identifiers are used as file names so they are restricted to ASCII letters, digits, '-', '_',
+/// and '.', and can not start with a '.'.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ArtifactId(String);
+
+impl ArtifactId {
+    /// Create an identifier, checking that it is valid.
+    pub fn new(id: &str) -> Result<ArtifactId> {
+        check_name(id)?;
+        Ok(ArtifactId(String::from(id)))
+    }
+
+    /// The identifier as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ArtifactId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// check that a name can be safely used as a file name
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidArtifact(format!("invalid name: {}", name)))
+    }
+}
+
+/// A function that checks the contents of an artifact before it is stored.
+pub type ArtifactValidator = fn(&[u8]) -> Result<()>;
+
+/// The registry of artifact kinds and the validators for their contents.
+///
+/// The default registry contains the kinds known to this library. Artifacts of kinds that have
+/// not been registered are stored without validation.
+#[derive(Debug, Clone)]
+pub struct ArtifactRegistry {
+    validators: HashMap<String, ArtifactValidator>,
+}
+
+impl ArtifactRegistry {
+    /// Register a kind of artifact, replacing the validator if the kind is already registered.
+    pub fn register(&mut self, kind: &ArtifactKind, validator: ArtifactValidator) {
+        self.validators.insert(String::from(kind.name()), validator);
+    }
+
+    /// Check whether the kind has been registered.
+    pub fn is_known(&self, kind: &ArtifactKind) -> bool {
+        self.validators.contains_key(kind.name())
+    }
+
+    /// Validate the contents of an artifact.
+    pub fn validate(&self, kind: &ArtifactKind, artifact: &[u8]) -> Result<()> {
+        match self.validators.get(kind.name()) {
+            Some(v) => v(artifact),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for ArtifactRegistry {
+    fn default() -> Self {
+        let mut r = ArtifactRegistry {
+            validators: HashMap::new(),
+        };
+        r.register(&ArtifactKind::BlockTemplate, starts_with_header);
+        r.register(&ArtifactKind::HeaderWithProof, starts_with_header);
+        r
+    }
+}
+
+// validator for artifacts that start with a block header
+fn starts_with_header(artifact: &[u8]) -> Result<()> {
+    if artifact.len() < HEADER_SIZE {
+        Err(Error::InvalidArtifact(String::from(
+            "artifact is too short to contain a block header",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_names() {
+        for k in [ArtifactKind::BlockTemplate, ArtifactKind::HeaderWithProof] {
+            assert_eq!(ArtifactKind::from_name(k.name()).unwrap(), k);
+        }
+        assert_eq!(
+            ArtifactKind::from_name("custom").unwrap(),
+            ArtifactKind::Other(String::from("custom"))
+        );
+        assert!(ArtifactKind::from_name("../blocks").is_err());
+    }
+
+    #[test]
+    fn artifact_ids() {
+        assert!(ArtifactId::new("template-1.raw").is_ok());
+        assert!(ArtifactId::new("").is_err());
+        assert!(ArtifactId::new(".hidden").is_err());
+        assert!(ArtifactId::new("a/b").is_err());
+    }
+
+    #[test]
+    fn registry_validation() {
+        let mut r = ArtifactRegistry::default();
+        assert!(r
+            .validate(&ArtifactKind::BlockTemplate, &[0u8; 79])
+            .is_err());
+        assert!(r.validate(&ArtifactKind::BlockTemplate, &[0u8; 80]).is_ok());
+        let custom = ArtifactKind::Other(String::from("custom"));
+        assert!(!r.is_known(&custom));
+        assert!(r.validate(&custom, &[]).is_ok());
+        r.register(&custom, |a| {
+            if a.is_empty() {
+                Err(Error::InvalidArtifact(String::from("empty")))
+            } else {
+                Ok(())
+            }
+        });
+        assert!(r.is_known(&custom));
+        assert!(r.validate(&custom, &[]).is_err());
+    }
+}
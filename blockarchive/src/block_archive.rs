@@ -1,19 +1,61 @@
+use crate::{ArtifactId, ArtifactKind, Error, Result, HEADER_SIZE};
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockHash, BlockHeader, FullBlockStream, Tx};
+use bsvdb_base::DEFAULT_MAX_BUFFERED_BLOCK_BYTES;
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use async_trait::async_trait;
-use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
 use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
 use tokio_stream::Stream;
-use crate::Result;
-
 
 /// The BlockArchive stores blocks, where a block is a BlockHeader and the transactions
 /// that are required to validate the block.
 ///
 /// The BlockArchive has very little knowledge of the structure of block, it only knows how to
 /// store and retrieve blocks.
+///
+/// All functions return futures that are Send, and the readers and streams that they produce are
+/// also Send, so that they can be moved into spawned tasks. Implementations must preserve this.
+///
+/// Example code:
+/// ```
+/// # use bsvdb_base::BlockArchiveConfig;
+/// # use bsvdb_blockarchive::{BlockArchive, SimpleFileBasedBlockArchive};
+/// # use tokio::io::AsyncReadExt;
+/// # use tokio_stream::StreamExt;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = BlockArchiveConfig {
+///     enabled: true,
+///     root_path: String::from("../testdata/blockarchive"),
+///     ..BlockArchiveConfig::default()
+/// };
+/// let mut archive = SimpleFileBasedBlockArchive::new(&config).await.unwrap();
+/// // the stream of block hashes can be consumed in another task
+/// let mut results = archive.block_list().await.unwrap();
+/// let block_hash = tokio::spawn(async move { results.next().await })
+///     .await
+///     .unwrap()
+///     .unwrap()
+///     .unwrap();
+/// // as can the reader for a block
+/// let mut reader = archive.get_block(&block_hash).await.unwrap();
+/// let j = tokio::spawn(async move {
+///     let mut buf = Vec::new();
+///     reader.read_to_end(&mut buf).await.unwrap();
+///     buf.len()
+/// });
+/// assert!(j.await.unwrap() > 0);
+/// // and the archive itself
+/// let j = tokio::spawn(async move { archive.block_exists(&block_hash).await });
+/// assert!(j.await.unwrap().unwrap());
+/// # }
+/// ```
 #[async_trait]
 pub trait BlockArchive {
     /// Get a block from the archive.
@@ -21,7 +63,7 @@ pub trait BlockArchive {
     /// Returns a reader for the encoded block.
     ///
     /// This function does not do any checking of the block, it merely returns a reader for the
-    /// bytes in the block.
+    /// bytes in the block. The block is read as the reader is read, it is not held in memory.
     async fn get_block(&self, block_hash: &BlockHash) -> Result<Box<dyn AsyncRead + Unpin + Send>>;
 
     /// Check if a block exists in the archive.
@@ -32,7 +74,40 @@ pub trait BlockArchive {
     /// Expects a reader for the encoded block.
     ///
     /// This function does not do any checking of the block, it stores the bytes of the block as is.
-    async fn store_block(&self, block_hash: &BlockHash, block: &mut Box<dyn AsyncRead + Unpin + Send>) -> Result<()>;
+    ///
+    /// This is the function to use for large blocks. A [SimpleFileBasedBlockArchive] streams the
+    /// block from the reader to its file, so the block is never held in memory. A
+    /// [ReplicatedBlockArchive] reads the block into memory so that it can be written to each
+    /// replica, and returns Error::BlockTooLarge if it is larger than
+    /// [max_buffered_bytes()](Self::max_buffered_bytes).
+    ///
+    /// [SimpleFileBasedBlockArchive]: crate::SimpleFileBasedBlockArchive
+    /// [ReplicatedBlockArchive]: crate::ReplicatedBlockArchive
+    async fn store_block(
+        &self,
+        block_hash: &BlockHash,
+        block: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()>;
+
+    /// Store a block that is already in memory, see [store_block()](Self::store_block).
+    ///
+    /// Returns Error::BlockTooLarge if the block is larger than
+    /// [max_buffered_bytes()](Self::max_buffered_bytes), such blocks should not be read into
+    /// memory and should be stored from a reader with store_block() instead.
+    async fn store_block_bytes(&self, block_hash: &BlockHash, block: Vec<u8>) -> Result<()> {
+        let limit = self.max_buffered_bytes();
+        if block.len() as u64 > limit {
+            return Err(Error::BlockTooLarge(limit));
+        }
+        let mut reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(std::io::Cursor::new(block));
+        self.store_block(block_hash, &mut reader).await
+    }
+
+    /// The size of the largest block that is held in memory to be stored, see
+    /// [store_block_bytes()](Self::store_block_bytes).
+    fn max_buffered_bytes(&self) -> u64 {
+        DEFAULT_MAX_BUFFERED_BLOCK_BYTES
+    }
 
     /// Get the size of a block in the archive.
     async fn block_size(&self, block_hash: &BlockHash) -> Result<usize>;
@@ -40,60 +115,308 @@ pub trait BlockArchive {
     /// Get the header of a block in the archive.
     async fn block_header(&self, block_hash: &BlockHash) -> Result<BlockHeader>;
 
+    /// Get the headers of several blocks in the archive.
+    ///
+    /// At most max_parallel headers are read concurrently. The results are in the same order as
+    /// the hashes, a block that can not be read does not stop the others from being read.
+    async fn block_headers(
+        &self,
+        block_hashes: &[BlockHash],
+        max_parallel: usize,
+    ) -> Vec<Result<BlockHeader>> {
+        futures::stream::iter(block_hashes)
+            .map(|h| self.block_header(h))
+            .buffered(max_parallel.max(1))
+            .collect()
+            .await
+    }
+
+    /// Get the bytes of several blocks in the archive.
+    ///
+    /// At most concurrency blocks are read at the same time. The results are in the same order as
+    /// the hashes and each is paired with its hash, a block that can not be read does not stop
+    /// the others from being read.
+    ///
+    /// Every block is returned in memory, so this is only suitable for small blocks, use
+    /// get_block() to read large blocks.
+    async fn get_blocks(
+        &self,
+        block_hashes: &[BlockHash],
+        concurrency: usize,
+    ) -> Vec<(BlockHash, Result<Vec<u8>>)> {
+        futures::stream::iter(block_hashes)
+            .map(|h| async move {
+                let bytes: Result<Vec<u8>> = async {
+                    let mut reader = self.get_block(h).await?;
+                    let mut buf = Vec::new();
+                    reader.read_to_end(&mut buf).await?;
+                    Ok(buf)
+                }
+                .await;
+                (*h, bytes)
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Get the transactions of a block, each with its offset from the start of the block and its
+    /// length in bytes.
+    ///
+    /// The offsets and lengths allow a single transaction to be read again by seeking to it,
+    /// rather than reading the whole block, e.g. to build an index of transactions. The block is
+    /// read as the stream is read, it is not held in memory.
+    async fn block_txs_with_offsets(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<(Tx, u64, u64)>> + Send>>> {
+        let (_, start) = read_tx_count(&mut self.get_block(block_hash).await?).await?;
+        let block = FullBlockStream::new(self.get_block(block_hash).await?).await?;
+        let txs = block.scan(start, |offset, tx| {
+            let item = tx.map_err(Error::from).and_then(|tx| {
+                let length = tx.to_binary_buf()?.len() as u64;
+                let r = (tx, *offset, length);
+                *offset += length;
+                Ok(r)
+            });
+            futures::future::ready(Some(item))
+        });
+        Ok(Box::pin(txs))
+    }
+
+    /// Get a single transaction of a block by its index in the block, the coinbase is index 0.
+    ///
+    /// The transactions before it are read and discarded, the block is not held in memory.
+    /// Returns [Error::TxIndexOutOfRange] if the block has fewer transactions.
+    async fn get_tx_by_index(&self, block_hash: &BlockHash, index: u32) -> Result<Tx> {
+        let (num_tx, _) = read_tx_count(&mut self.get_block(block_hash).await?).await?;
+        if index as u64 >= num_tx {
+            return Err(Error::TxIndexOutOfRange(index, num_tx));
+        }
+        let mut txs = FullBlockStream::new(self.get_block(block_hash).await?)
+            .await?
+            .skip(index as usize);
+        match txs.next().await {
+            Some(tx) => Ok(tx?),
+            // the block is shorter than its count of transactions
+            None => Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into())),
+        }
+    }
+
     /// Get a list of all the blocks in the archive.
     ///
-    /// It returns a stream of block hashes.
+    /// It returns a stream of block hashes. An error walking the archive is returned as an item
+    /// of the stream, and again by [BlockHashListStream::finish()], which must be called after
+    /// the stream has ended to check that the list is complete.
     ///
     /// Example code:
     ///     let mut results = archive.block_list().await.unwrap();
     ///     while let Some(block_hash) = results.next().await {
-    ///       println!("{}", block_hash);
+    ///       println!("{}", block_hash?);
     ///     }
-    async fn block_list(&mut self) -> Result<Pin<Box<dyn BlockHashListStream<Item=BlockHash>>>>;
+    ///     let summary = results.finish().await?;
+    async fn block_list(
+        &mut self,
+    ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>>;
+
+    /// Store an artifact in the archive.
+    ///
+    /// Artifacts are data other than canonical blocks, such as block templates. They are stored
+    /// separately from blocks and are never returned by the block functions.
+    ///
+    /// The artifact is validated if a validator is registered for its kind, for which it is read
+    /// into memory.
+    async fn store_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+        artifact: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()>;
+
+    /// Get an artifact from the archive.
+    ///
+    /// Returns a reader for the artifact.
+    async fn get_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>>;
+
+    /// Get the identifiers of all the artifacts of a kind, in order.
+    async fn list_artifacts(&self, kind: &ArtifactKind) -> Result<Vec<ArtifactId>>;
+}
+
+// Read the header and the number of transactions from the start of a block, returning the number
+// of transactions and the offset of the first transaction.
+async fn read_tx_count<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u64, u64)> {
+    let mut prefix = [0; HEADER_SIZE + 1];
+    reader.read_exact(&mut prefix).await?;
+    // the count is a varint, whose length is given by its first byte
+    let len = match prefix[HEADER_SIZE] {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        n => return Ok((n as u64, HEADER_SIZE as u64 + 1)),
+    };
+    let mut count = [0; 8];
+    reader.read_exact(&mut count[..len]).await?;
+    Ok((
+        u64::from_le_bytes(count),
+        HEADER_SIZE as u64 + 1 + len as u64,
+    ))
+}
+
+/// Compute the merkle root of the transactions of a block, reading every transaction.
+pub async fn compute_merkle_root(mut block: FullBlockStream) -> Result<BlockHash> {
+    // collect transaction hashes
+    let mut hashes = VecDeque::new();
+    while let Some(tx) = block.next().await {
+        hashes.push_back(tx?.hash());
+    }
+    // calculate merkle root, an odd hash at the end of a level is paired with itself
+    while hashes.len() > 1 {
+        let mut n = hashes.len();
+        while n > 0 {
+            n -= 1;
+            let h1 = hashes.pop_front().unwrap();
+            let h2 = if n == 0 {
+                h1
+            } else {
+                n -= 1;
+                hashes.pop_front().unwrap()
+            };
+            let mut c = [0; 64];
+            c[..32].copy_from_slice(&h1.hash);
+            c[32..].copy_from_slice(&h2.hash);
+            hashes.push_back(BlockHash::sha256d(&c));
+        }
+    }
+    // a block has at least the coinbase transaction
+    hashes
+        .pop_front()
+        .ok_or_else(|| Error::IoError(std::io::ErrorKind::UnexpectedEof.into()))
+}
+
+/// A reader for an encoded block that can start part way through the block, so that a store that
+/// was interrupted can be resumed, see
+/// [SimpleFileBasedBlockArchive::store_block_resumable()](crate::SimpleFileBasedBlockArchive::store_block_resumable).
+///
+/// It is implemented for every reader that can seek, such as a file or a cursor. A source that
+/// fetches the block, such as over RPC, can implement it by requesting the range of the block from
+/// the offset.
+#[async_trait]
+pub trait ResumableRead: AsyncRead + Unpin + Send {
+    /// Position the reader at the offset in the block, which is at most the size of the block.
+    ///
+    /// Returns false if the reader can not start from the offset, the reader must then still be
+    /// at the start of the block.
+    async fn resume_at(&mut self, offset: u64) -> Result<bool>;
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncSeek + Unpin + Send> ResumableRead for T {
+    async fn resume_at(&mut self, offset: u64) -> Result<bool> {
+        self.seek(std::io::SeekFrom::Start(offset)).await?;
+        Ok(true)
+    }
 }
 
 /// A stream of block hashes, returned by [BlockArchive::block_list].
 ///
-/// Implemented as a trait for future extensibility.
-pub trait BlockHashListStream: Stream<Item = BlockHash> {}
+/// Implemented as a trait for future extensibility. Implementations must be Send so that the
+/// stream can be consumed in a different task.
+///
+/// Errors are delivered as items of the stream as soon as they occur. The stream ends after an
+/// error, so a consumer that stops at the end of the stream must call [BlockHashListStream::finish()]
+/// to find out whether it saw the complete list.
+pub trait BlockHashListStream: Stream<Item = Result<BlockHash>> + Send {
+    /// Wait for the listing to complete and return its outcome, either the error that stopped it
+    /// or a summary of the walk.
+    ///
+    /// This should be called after the stream has returned None. If it is called earlier, then
+    /// the listing is stopped and the summary only covers the part that was walked.
+    fn finish(self: Pin<Box<Self>>) -> Pin<Box<dyn Future<Output = Result<WalkSummary>> + Send>>;
+}
+
+/// A summary of the walk of an archive that produced a [BlockHashListStream].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkSummary {
+    /// The number of directories that were read.
+    pub dirs_visited: u64,
+    /// The number of files that were seen, including the files that were skipped.
+    pub files_seen: u64,
+    /// The number of files that were skipped because they are not .bin files.
+    pub skipped_not_bin: u64,
+    /// The number of files that were skipped because their name is not a block hash.
+    pub skipped_invalid_name: u64,
+    /// The number of files that were skipped because they are not stored in the location for
+    /// their hash, and so can not be retrieved.
+    pub skipped_wrong_location: u64,
+    /// The paths of the files that were skipped because they are in the wrong location.
+    pub misplaced: Vec<PathBuf>,
+    /// Whether the hashes were read from an index instead of walking the directories, the other
+    /// counts are then zero.
+    pub from_index: bool,
+}
 
 /// An implementation of the [BlockHashListStream] trait.
 ///
 /// Built for the SimpleFileBasedBlockArchive but expected to be useful elsewhere.
-/// It expects a background task to be created which sends block hashes to a channel. This stream
-/// reads the block hashes from the channel.
+/// It expects a background task to be created which sends block hashes, or an error, to a
+/// channel. This stream reads the block hashes from the channel, and the outcome of the task is
+/// returned by [BlockHashListStream::finish()].
 pub struct BlockHashListStreamFromChannel {
     // The receiver to which the background task sends block hashes.
-    receiver: Receiver<BlockHash>,
-    // Handle to the background task that reads the block hashes.
-    handle: JoinHandle<Result<()>>,
+    receiver: Receiver<Result<BlockHash>>,
+    // Handle to the background task that reads the block hashes, taken by finish().
+    handle: Option<JoinHandle<Result<WalkSummary>>>,
 }
 
 impl BlockHashListStreamFromChannel {
     /// Create a new BlockHashListStreamFromChannel, with a receiving end of a channel and a handle
     /// to the background process. The handle is used to close the background task when the stream
     /// is dropped.
-    pub fn new(receiver: Receiver<BlockHash>, handle: JoinHandle<Result<()>>) -> BlockHashListStreamFromChannel {
-        BlockHashListStreamFromChannel { receiver, handle }
+    pub fn new(
+        receiver: Receiver<Result<BlockHash>>,
+        handle: JoinHandle<Result<WalkSummary>>,
+    ) -> BlockHashListStreamFromChannel {
+        BlockHashListStreamFromChannel {
+            receiver,
+            handle: Some(handle),
+        }
     }
 }
 
 impl Stream for BlockHashListStreamFromChannel {
-    type Item = BlockHash;
+    type Item = Result<BlockHash>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         Pin::new(&mut self.receiver).poll_recv(cx)
     }
 }
 
-impl BlockHashListStream for BlockHashListStreamFromChannel {}
+impl BlockHashListStream for BlockHashListStreamFromChannel {
+    fn finish(self: Pin<Box<Self>>) -> Pin<Box<dyn Future<Output = Result<WalkSummary>> + Send>> {
+        let mut s = Pin::into_inner(self);
+        // a background task that is still sending stops when the receiver is closed
+        s.receiver.close();
+        let handle = s.handle.take();
+        Box::pin(async move {
+            match handle {
+                Some(h) => h.await.map_err(|e| Error::TaskFailed(format!("{}", e)))?,
+                None => Err(Error::TaskFailed(String::from("listing already finished"))),
+            }
+        })
+    }
+}
 
 impl Drop for BlockHashListStreamFromChannel {
     // close the handle to the background task when the stream is dropped
     fn drop(&mut self) {
-        if self.handle.is_finished() {
-            return;
+        if let Some(handle) = self.handle.as_ref() {
+            if !handle.is_finished() {
+                handle.abort();
+            }
         }
-        self.handle.abort();
     }
 }
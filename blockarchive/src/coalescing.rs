@@ -0,0 +1,836 @@
+use crate::block_archive::BlockHashListStream;
+use crate::{ArtifactId, ArtifactKind, BlockArchive, Error, Result};
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
+use bsvdb_base::DEFAULT_MAX_BUFFERED_BLOCK_BYTES;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+use tokio::sync::{mpsc, watch};
+
+// the size of the chunks in which a block is read from the underlying archive and passed to readers
+const CHUNK_SIZE: usize = 64 * 1024;
+// the number of chunks that are queued for a reader that is not keeping up
+const READER_QUEUE: usize = 4;
+
+// the number of the next spool file, so that every read has its own file
+static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+/// The options of a [CoalescingBlockArchive].
+#[derive(Debug, Clone)]
+pub struct CoalescingOptions {
+    /// The number of bytes of a block that are kept in memory while it is read, the rest of a
+    /// larger block is written to a spool file. Defaults to 1 GiB.
+    pub max_buffered_bytes: u64,
+    /// The directory of the spool files. Defaults to the temporary directory of the system.
+    pub spool_dir: PathBuf,
+}
+
+impl Default for CoalescingOptions {
+    fn default() -> Self {
+        CoalescingOptions {
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BLOCK_BYTES,
+            spool_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// The number of reads that were coalesced by a [CoalescingBlockArchive], since it was created.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoalescingStats {
+    /// The number of blocks that were read from the underlying archive.
+    pub block_reads: u64,
+    /// The number of headers that were read from the underlying archive.
+    pub header_reads: u64,
+    /// The number of requests that shared a read that was already in progress.
+    pub merged_requests: u64,
+    /// The number of blocks that were too large to be kept in memory and were spooled.
+    pub spooled_blocks: u64,
+    /// The number of bytes that were written to spool files.
+    pub spooled_bytes: u64,
+}
+
+impl CoalescingStats {
+    /// The statistics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut s = String::new();
+        let metrics = [
+            (
+                "bsvdb_blockarchive_coalesced_block_reads_total",
+                "Blocks read from the underlying archive.",
+                self.block_reads,
+            ),
+            (
+                "bsvdb_blockarchive_coalesced_header_reads_total",
+                "Headers read from the underlying archive.",
+                self.header_reads,
+            ),
+            (
+                "bsvdb_blockarchive_coalesced_merged_requests_total",
+                "Requests that shared a read already in progress.",
+                self.merged_requests,
+            ),
+            (
+                "bsvdb_blockarchive_coalesced_spooled_blocks_total",
+                "Blocks that were spooled to a file while they were read.",
+                self.spooled_blocks,
+            ),
+            (
+                "bsvdb_blockarchive_coalesced_spooled_bytes_total",
+                "Bytes written to spool files.",
+                self.spooled_bytes,
+            ),
+        ];
+        for (name, help, value) in metrics {
+            writeln!(s, "# HELP {} {}", name, help).unwrap();
+            writeln!(s, "# TYPE {} counter", name).unwrap();
+            writeln!(s, "{} {}", name, value).unwrap();
+        }
+        s
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    block_reads: AtomicU64,
+    header_reads: AtomicU64,
+    merged_requests: AtomicU64,
+    spooled_blocks: AtomicU64,
+    spooled_bytes: AtomicU64,
+}
+
+// The progress of a block that is being read.
+#[derive(Debug, Default)]
+struct Progress {
+    // whether the underlying archive has returned a reader for the block
+    opened: bool,
+    // the number of bytes that have been read and can be passed to readers
+    len: u64,
+    // the outcome of the read, once it has finished
+    done: Option<std::result::Result<(), Arc<Error>>>,
+}
+
+// The bytes of a block that is being read. The first bytes are kept in memory, up to the limit,
+// and the rest are appended to the spool file.
+#[derive(Debug, Default)]
+struct Data {
+    memory: Vec<u8>,
+    spool: Option<PathBuf>,
+}
+
+// A block that is being read from the underlying archive, shared by every request for it.
+#[derive(Debug)]
+struct BlockFlight {
+    progress: watch::Sender<Progress>,
+    data: Mutex<Data>,
+}
+
+// A header that is being read from the underlying archive, None until it has been read.
+type HeaderFlight = watch::Sender<Option<std::result::Result<BlockHeader, Arc<Error>>>>;
+
+type Flights<T> = Arc<Mutex<HashMap<BlockHash, Arc<T>>>>;
+
+/// A block archive that shares identical concurrent reads of another block archive.
+///
+/// When a block, or its header, is requested while a request for it is already being read from
+/// the underlying archive, the later request shares that read instead of starting another. This
+/// avoids reading a new block once for each of the many clients that request it as soon as it is
+/// announced.
+///
+/// A shared block is read by a background task, which keeps the first
+/// [max_buffered_bytes](CoalescingOptions::max_buffered_bytes) in memory and writes the rest to a
+/// spool file. Every request gets its own reader, which returns the bytes as soon as they have
+/// been read, without waiting for the whole block. The block is released, and the spool file
+/// removed, when the read has finished and every reader has been dropped.
+///
+/// A failed read is returned to every request that shared it and is not remembered, so the next
+/// request reads the block again. Completed reads are not cached either.
+///
+/// The other functions are passed to the underlying archive.
+pub struct CoalescingBlockArchive<A> {
+    inner: A,
+    options: CoalescingOptions,
+    blocks: Flights<BlockFlight>,
+    headers: Flights<HeaderFlight>,
+    counters: Arc<Counters>,
+}
+
+impl<A: BlockArchive + Send + Sync> CoalescingBlockArchive<A> {
+    /// Share the reads of the archive.
+    pub fn new(inner: A, options: CoalescingOptions) -> Self {
+        CoalescingBlockArchive {
+            inner,
+            options,
+            blocks: Arc::new(Mutex::new(HashMap::new())),
+            headers: Arc::new(Mutex::new(HashMap::new())),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// The underlying archive.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The number of reads that were coalesced, e.g. for metrics.
+    pub fn stats(&self) -> CoalescingStats {
+        let c = &self.counters;
+        CoalescingStats {
+            block_reads: c.block_reads.load(Ordering::Relaxed),
+            header_reads: c.header_reads.load(Ordering::Relaxed),
+            merged_requests: c.merged_requests.load(Ordering::Relaxed),
+            spooled_blocks: c.spooled_blocks.load(Ordering::Relaxed),
+            spooled_bytes: c.spooled_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    // Join the read of the block that is in progress, or start one. Returns the read and whether
+    // this request leads it.
+    fn join<T>(&self, flights: &Flights<T>, hash: &BlockHash, new: fn() -> T) -> (Arc<T>, bool) {
+        let mut flights = flights.lock().unwrap();
+        match flights.get(hash) {
+            Some(f) => {
+                self.counters
+                    .merged_requests
+                    .fetch_add(1, Ordering::Relaxed);
+                (f.clone(), false)
+            }
+            None => {
+                let f = Arc::new(new());
+                flights.insert(*hash, f.clone());
+                (f, true)
+            }
+        }
+    }
+}
+
+// Remove a read that has finished, so that later requests start a new one.
+fn land<T>(flights: &Flights<T>, hash: &BlockHash, flight: &Arc<T>) {
+    let mut flights = flights.lock().unwrap();
+    if flights.get(hash).is_some_and(|f| Arc::ptr_eq(f, flight)) {
+        flights.remove(hash);
+    }
+}
+
+// A copy of an error for a request that shared the read that failed.
+fn copy_error(e: &Error) -> Error {
+    match e {
+        Error::BlockNotFound(h) => Error::BlockNotFound(*h),
+        Error::Timeout => Error::Timeout,
+        Error::IoError(e) => Error::IoError(std::io::Error::new(e.kind(), e.to_string())),
+        e => Error::TaskFailed(e.to_string()),
+    }
+}
+
+fn io_error(e: &Error) -> std::io::Error {
+    match e {
+        Error::IoError(e) => std::io::Error::new(e.kind(), e.to_string()),
+        e => std::io::Error::other(e.to_string()),
+    }
+}
+
+// Runs a function when it is dropped, unless it has been disarmed, so that a read whose leader is
+// cancelled fails instead of leaving the requests that share it waiting.
+struct Abandon<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Abandon<F> {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for Abandon<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            f();
+        }
+    }
+}
+
+fn cancelled() -> Arc<Error> {
+    Arc::new(Error::TaskFailed(String::from(
+        "the shared read was cancelled",
+    )))
+}
+
+impl BlockFlight {
+    fn new() -> BlockFlight {
+        BlockFlight {
+            progress: watch::channel(Progress::default()).0,
+            data: Mutex::new(Data::default()),
+        }
+    }
+
+    fn finish(&self, result: std::result::Result<(), Arc<Error>>) {
+        self.progress.send_modify(|p| p.done = Some(result));
+    }
+
+    // Read the block from the reader into memory and the spool file.
+    async fn fill(
+        &self,
+        reader: &mut Box<dyn AsyncRead + Unpin + Send>,
+        options: &CoalescingOptions,
+        counters: &Counters,
+    ) -> Result<()> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut spool: Option<File> = None;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            let spill = {
+                let mut data = self.data.lock().unwrap();
+                if data.spool.is_none()
+                    && (data.memory.len() + n) as u64 <= options.max_buffered_bytes
+                {
+                    data.memory.extend_from_slice(&buf[..n]);
+                    None
+                } else {
+                    // the path is recorded first, so that the file is removed even if it fails
+                    let path = data.spool.get_or_insert_with(|| {
+                        options.spool_dir.join(format!(
+                            "bsvdb-{}-{}.spool",
+                            std::process::id(),
+                            NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)
+                        ))
+                    });
+                    Some(path.clone())
+                }
+            };
+            if let Some(path) = spill {
+                if spool.is_none() {
+                    spool = Some(File::create(&path).await?);
+                    counters.spooled_blocks.fetch_add(1, Ordering::Relaxed);
+                }
+                let f = spool.as_mut().expect("the spool file was created");
+                f.write_all(&buf[..n]).await?;
+                // readers open the file separately, so the bytes are passed on before they are
+                // made available
+                f.flush().await?;
+                counters
+                    .spooled_bytes
+                    .fetch_add(n as u64, Ordering::Relaxed);
+            }
+            self.progress.send_modify(|p| p.len += n as u64);
+        }
+    }
+
+    // Read the bytes from pos, up to a chunk and at most to end, which have been read.
+    async fn read_at(
+        &self,
+        pos: u64,
+        end: u64,
+        file: &mut Option<File>,
+    ) -> std::io::Result<Vec<u8>> {
+        let end = end.min(pos + CHUNK_SIZE as u64);
+        let (spool_start, path) = {
+            let data = self.data.lock().unwrap();
+            let in_memory = data.memory.len() as u64;
+            if pos < in_memory {
+                return Ok(data.memory[pos as usize..end.min(in_memory) as usize].to_vec());
+            }
+            (in_memory, data.spool.clone())
+        };
+        if file.is_none() {
+            let path = path.expect("the bytes that are not in memory are spooled");
+            *file = Some(File::open(path).await?);
+        }
+        let f = file.as_mut().expect("the spool file is open");
+        f.seek(SeekFrom::Start(pos - spool_start)).await?;
+        let mut buf = vec![0; (end - pos) as usize];
+        f.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    // Pass the block to a reader as it is read, until it has been read or the reader is dropped.
+    async fn feed(self: Arc<Self>, tx: mpsc::Sender<std::io::Result<Vec<u8>>>) {
+        let mut progress = self.progress.subscribe();
+        let mut pos = 0;
+        let mut file = None;
+        loop {
+            let (len, done) = {
+                let p = progress.borrow_and_update();
+                (p.len, p.done.clone())
+            };
+            while pos < len {
+                let chunk = self.read_at(pos, len, &mut file).await;
+                if let Ok(c) = &chunk {
+                    pos += c.len() as u64;
+                }
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    return;
+                }
+            }
+            match done {
+                Some(Ok(())) => return,
+                Some(Err(e)) => {
+                    let _ = tx.send(Err(io_error(&e))).await;
+                    return;
+                }
+                None => {
+                    // the sender is held by the flight, which is held here
+                    let _ = progress.changed().await;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for BlockFlight {
+    fn drop(&mut self) {
+        if let Some(path) = &self.data.get_mut().unwrap().spool {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// A reader for a shared block, which reads the chunks that are passed to it.
+struct ChannelReader {
+    rx: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for ChannelReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.pos == self.chunk.len() {
+            match ready!(self.rx.poll_recv(cx)) {
+                Some(Ok(c)) => {
+                    self.chunk = c;
+                    self.pos = 0;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(self.chunk.len() - self.pos);
+        buf.put_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl<A: BlockArchive + Send + Sync> BlockArchive for CoalescingBlockArchive<A> {
+    async fn get_block(&self, block_hash: &BlockHash) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        let (flight, leader) = self.join(&self.blocks, block_hash, BlockFlight::new);
+        if leader {
+            self.counters.block_reads.fetch_add(1, Ordering::Relaxed);
+            let abandon = Abandon(Some({
+                let (blocks, flight, hash) = (self.blocks.clone(), flight.clone(), *block_hash);
+                move || {
+                    land(&blocks, &hash, &flight);
+                    flight.finish(Err(cancelled()));
+                }
+            }));
+            let r = self.inner.get_block(block_hash).await;
+            abandon.disarm();
+            match r {
+                Ok(mut reader) => {
+                    flight.progress.send_modify(|p| p.opened = true);
+                    let (f, blocks, hash) = (flight.clone(), self.blocks.clone(), *block_hash);
+                    let (options, counters) = (self.options.clone(), self.counters.clone());
+                    tokio::spawn(async move {
+                        let r = f.fill(&mut reader, &options, &counters).await;
+                        land(&blocks, &hash, &f);
+                        f.finish(r.map_err(Arc::new));
+                    });
+                }
+                Err(e) => {
+                    land(&self.blocks, block_hash, &flight);
+                    flight.finish(Err(Arc::new(copy_error(&e))));
+                    return Err(e);
+                }
+            }
+        } else {
+            let mut progress = flight.progress.subscribe();
+            let p = progress
+                .wait_for(|p| p.opened || p.done.is_some())
+                .await
+                .map_err(|_| copy_error(&cancelled()))?;
+            if let Some(Err(e)) = &p.done {
+                if !p.opened {
+                    return Err(copy_error(e));
+                }
+            }
+        }
+        let (tx, rx) = mpsc::channel(READER_QUEUE);
+        tokio::spawn(flight.feed(tx));
+        Ok(Box::new(ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }))
+    }
+
+    async fn block_exists(&self, block_hash: &BlockHash) -> Result<bool> {
+        self.inner.block_exists(block_hash).await
+    }
+
+    async fn store_block(
+        &self,
+        block_hash: &BlockHash,
+        block: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()> {
+        self.inner.store_block(block_hash, block).await
+    }
+
+    async fn store_block_bytes(&self, block_hash: &BlockHash, block: Vec<u8>) -> Result<()> {
+        self.inner.store_block_bytes(block_hash, block).await
+    }
+
+    fn max_buffered_bytes(&self) -> u64 {
+        self.inner.max_buffered_bytes()
+    }
+
+    async fn block_size(&self, block_hash: &BlockHash) -> Result<usize> {
+        self.inner.block_size(block_hash).await
+    }
+
+    async fn block_header(&self, block_hash: &BlockHash) -> Result<BlockHeader> {
+        let (flight, leader) = self.join(&self.headers, block_hash, || watch::channel(None).0);
+        if !leader {
+            let mut result = flight.subscribe();
+            let r = result
+                .wait_for(|r| r.is_some())
+                .await
+                .map_err(|_| copy_error(&cancelled()))?;
+            return match &*r {
+                Some(Ok(header)) => Ok(header.clone()),
+                Some(Err(e)) => Err(copy_error(e)),
+                None => unreachable!("the header has been read"),
+            };
+        }
+        self.counters.header_reads.fetch_add(1, Ordering::Relaxed);
+        let abandon = Abandon(Some({
+            let (headers, flight, hash) = (self.headers.clone(), flight.clone(), *block_hash);
+            move || {
+                land(&headers, &hash, &flight);
+                flight.send_replace(Some(Err(cancelled())));
+            }
+        }));
+        let r = self.inner.block_header(block_hash).await;
+        abandon.disarm();
+        land(&self.headers, block_hash, &flight);
+        let shared = match &r {
+            Ok(header) => Ok(header.clone()),
+            Err(e) => Err(Arc::new(copy_error(e))),
+        };
+        flight.send_replace(Some(shared));
+        r
+    }
+
+    async fn block_list(
+        &mut self,
+    ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>> {
+        self.inner.block_list().await
+    }
+
+    async fn store_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+        artifact: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()> {
+        self.inner.store_artifact(kind, id, artifact).await
+    }
+
+    async fn get_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        self.inner.get_artifact(kind, id).await
+    }
+
+    async fn list_artifacts(&self, kind: &ArtifactKind) -> Result<Vec<ArtifactId>> {
+        self.inner.list_artifacts(kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimpleFileBasedBlockArchive;
+    use bsvdb_base::BlockArchiveConfig;
+    use hex::FromHex;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::sync::Semaphore;
+
+    // An archive of the test data that counts its reads, which wait until the gate is opened and
+    // fail while fail is set.
+    struct CountingArchive {
+        inner: SimpleFileBasedBlockArchive,
+        gate: Semaphore,
+        fail: AtomicBool,
+        block_reads: AtomicU64,
+        header_reads: AtomicU64,
+    }
+
+    impl CountingArchive {
+        async fn new() -> CountingArchive {
+            let c = BlockArchiveConfig {
+                enabled: true,
+                root_path: String::from("../testdata/blockarchive"),
+                ..BlockArchiveConfig::default()
+            };
+            CountingArchive {
+                inner: SimpleFileBasedBlockArchive::new(&c).await.unwrap(),
+                gate: Semaphore::new(0),
+                fail: AtomicBool::new(false),
+                block_reads: AtomicU64::new(0),
+                header_reads: AtomicU64::new(0),
+            }
+        }
+
+        async fn pass_gate(&self, block_hash: &BlockHash) -> Result<()> {
+            let _permit = self.gate.acquire().await.unwrap();
+            if self.fail.load(Ordering::Relaxed) {
+                return Err(Error::BlockNotFound(*block_hash));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl BlockArchive for CountingArchive {
+        async fn get_block(
+            &self,
+            block_hash: &BlockHash,
+        ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+            self.block_reads.fetch_add(1, Ordering::Relaxed);
+            self.pass_gate(block_hash).await?;
+            self.inner.get_block(block_hash).await
+        }
+
+        async fn block_exists(&self, block_hash: &BlockHash) -> Result<bool> {
+            self.inner.block_exists(block_hash).await
+        }
+
+        async fn store_block(
+            &self,
+            block_hash: &BlockHash,
+            block: &mut Box<dyn AsyncRead + Unpin + Send>,
+        ) -> Result<()> {
+            self.inner.store_block(block_hash, block).await
+        }
+
+        async fn block_size(&self, block_hash: &BlockHash) -> Result<usize> {
+            self.inner.block_size(block_hash).await
+        }
+
+        async fn block_header(&self, block_hash: &BlockHash) -> Result<BlockHeader> {
+            self.header_reads.fetch_add(1, Ordering::Relaxed);
+            self.pass_gate(block_hash).await?;
+            self.inner.block_header(block_hash).await
+        }
+
+        async fn block_list(
+            &mut self,
+        ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>> {
+            self.inner.block_list().await
+        }
+
+        async fn store_artifact(
+            &self,
+            kind: &ArtifactKind,
+            id: &ArtifactId,
+            artifact: &mut Box<dyn AsyncRead + Unpin + Send>,
+        ) -> Result<()> {
+            self.inner.store_artifact(kind, id, artifact).await
+        }
+
+        async fn get_artifact(
+            &self,
+            kind: &ArtifactKind,
+            id: &ArtifactId,
+        ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+            self.inner.get_artifact(kind, id).await
+        }
+
+        async fn list_artifacts(&self, kind: &ArtifactKind) -> Result<Vec<ArtifactId>> {
+            self.inner.list_artifacts(kind).await
+        }
+    }
+
+    type Archive = Arc<CoalescingBlockArchive<CountingArchive>>;
+
+    async fn open(options: CoalescingOptions) -> Archive {
+        Arc::new(CoalescingBlockArchive::new(
+            CountingArchive::new().await,
+            options,
+        ))
+    }
+
+    fn hash() -> BlockHash {
+        BlockHash::from_hex("00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048")
+            .unwrap()
+    }
+
+    fn block_bytes() -> Vec<u8> {
+        std::fs::read(
+            "../testdata/blockarchive/48/60/\
+            00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048.bin",
+        )
+        .unwrap()
+    }
+
+    // Start n reads of the block and wait until they share the first, which is held at the gate.
+    async fn read_concurrently(
+        archive: &Archive,
+        n: u64,
+    ) -> Vec<tokio::task::JoinHandle<Result<Vec<u8>>>> {
+        let tasks = (0..n)
+            .map(|_| {
+                let archive = archive.clone();
+                tokio::spawn(async move {
+                    let mut reader = archive.get_block(&hash()).await?;
+                    let mut buf = Vec::new();
+                    reader.read_to_end(&mut buf).await?;
+                    Ok(buf)
+                })
+            })
+            .collect();
+        while archive.stats().merged_requests < n - 1 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        tasks
+    }
+
+    // Concurrent reads of a block share one read of the underlying archive.
+    #[tokio::test]
+    async fn test_coalesced_reads() {
+        let archive = open(CoalescingOptions::default()).await;
+        let tasks = read_concurrently(&archive, 50).await;
+        archive.inner().gate.add_permits(100);
+        let expected = block_bytes();
+        for t in tasks {
+            assert_eq!(t.await.unwrap().unwrap(), expected);
+        }
+        assert_eq!(archive.inner().block_reads.load(Ordering::Relaxed), 1);
+        let stats = archive.stats();
+        assert_eq!(stats.block_reads, 1);
+        assert_eq!(stats.merged_requests, 49);
+        assert_eq!(stats.spooled_blocks, 0);
+        assert!(stats
+            .to_prometheus()
+            .contains("bsvdb_blockarchive_coalesced_merged_requests_total 49\n"));
+        // the read has finished, so the next request reads the block again
+        let mut buf = Vec::new();
+        let mut reader = archive.get_block(&hash()).await.unwrap();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, expected);
+        assert_eq!(archive.inner().block_reads.load(Ordering::Relaxed), 2);
+    }
+
+    // A block that is larger than the memory limit is shared through a spool file, which is
+    // removed once it has been read.
+    #[tokio::test]
+    async fn test_spooled_reads() {
+        let spool = tempdir().unwrap();
+        let archive = open(CoalescingOptions {
+            max_buffered_bytes: 100,
+            spool_dir: spool.path().to_path_buf(),
+        })
+        .await;
+        let tasks = read_concurrently(&archive, 10).await;
+        archive.inner().gate.add_permits(100);
+        let expected = block_bytes();
+        for t in tasks {
+            assert_eq!(t.await.unwrap().unwrap(), expected);
+        }
+        let stats = archive.stats();
+        assert_eq!(stats.block_reads, 1);
+        assert_eq!(stats.spooled_blocks, 1);
+        assert_eq!(stats.spooled_bytes, expected.len() as u64);
+        // the read task may still hold the block for a moment after the readers have finished
+        for _ in 0..1000 {
+            if std::fs::read_dir(spool.path()).unwrap().next().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(std::fs::read_dir(spool.path()).unwrap().next().is_none());
+    }
+
+    // The failure of the shared read is returned to every request, and the next request tries
+    // again.
+    #[tokio::test]
+    async fn test_leader_failure() {
+        let archive = open(CoalescingOptions::default()).await;
+        archive.inner().fail.store(true, Ordering::Relaxed);
+        let tasks = read_concurrently(&archive, 50).await;
+        archive.inner().gate.add_permits(100);
+        for t in tasks {
+            match t.await.unwrap() {
+                Err(Error::BlockNotFound(h)) => assert_eq!(h, hash()),
+                r => panic!("unexpected result {:?}", r.map(|b| b.len())),
+            }
+        }
+        assert_eq!(archive.inner().block_reads.load(Ordering::Relaxed), 1);
+        archive.inner().fail.store(false, Ordering::Relaxed);
+        let mut buf = Vec::new();
+        let mut reader = archive.get_block(&hash()).await.unwrap();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, block_bytes());
+        assert_eq!(archive.inner().block_reads.load(Ordering::Relaxed), 2);
+    }
+
+    // A request that is cancelled while it leads a read does not leave the others waiting.
+    #[tokio::test]
+    async fn test_leader_cancelled() {
+        let archive = open(CoalescingOptions::default()).await;
+        let leader = {
+            let archive = archive.clone();
+            tokio::spawn(async move { archive.get_block(&hash()).await.map(|_| ()) })
+        };
+        while archive.inner().block_reads.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let follower = {
+            let archive = archive.clone();
+            tokio::spawn(async move { archive.get_block(&hash()).await.map(|_| ()) })
+        };
+        while archive.stats().merged_requests == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        leader.abort();
+        assert!(matches!(follower.await.unwrap(), Err(Error::TaskFailed(_))));
+    }
+
+    // Concurrent reads of a header share one read of the underlying archive.
+    #[tokio::test]
+    async fn test_coalesced_headers() {
+        let archive = open(CoalescingOptions::default()).await;
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let archive = archive.clone();
+                tokio::spawn(async move { archive.block_header(&hash()).await })
+            })
+            .collect();
+        while archive.stats().merged_requests < 19 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        archive.inner().gate.add_permits(100);
+        for t in tasks {
+            assert_eq!(t.await.unwrap().unwrap().hash(), hash());
+        }
+        assert_eq!(archive.inner().header_reads.load(Ordering::Relaxed), 1);
+        assert_eq!(archive.stats().header_reads, 1);
+    }
+}
@@ -0,0 +1,659 @@
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The severity of a [Finding], from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Information that does not need any action.
+    Note,
+    /// A problem that should be looked at, the data is still usable.
+    Warning,
+    /// A problem with the data.
+    Error,
+}
+
+impl Severity {
+    /// The name of the severity, which is also the SARIF level.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    /// The exit code of a command whose most severe unsuppressed finding has this severity.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Severity::Note => 0,
+            Severity::Warning => 1,
+            Severity::Error => 2,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The kind of problem that a [Finding] reports.
+///
+/// Each code has a stable identifier, such as "BA003", which does not change between releases
+/// and can be used to track or suppress findings. Identifiers starting with "BA" are found in the
+/// block archive, identifiers starting with "CS" are found in the chain store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FindingCode {
+    /// BA001, a block file that is not stored in the location for its hash.
+    MisplacedFile,
+    /// BA002, a stored block whose header does not hash to the block hash.
+    HashMismatch,
+    /// BA003, a stored block whose merkle root does not match the transactions.
+    MerkleMismatch,
+    /// BA004, a stored block that could not be read.
+    UnreadableBlock,
+    /// BA005, a selected block that is not in the archive.
+    MissingBlock,
+    /// BA006, a stored block in an immutable archive that is not write protected.
+    UnprotectedBlock,
+    /// BA010, a stored block whose parent is not in the archive.
+    OrphanSubtree,
+    /// CS001, a BlockInfo that can not be reached from genesis.
+    UnreachableBlock,
+    /// CS002, a maintained counter that does not match the stored BlockInfos.
+    CounterDrift,
+}
+
+impl FindingCode {
+    /// All codes, in order of their identifiers.
+    pub const ALL: [FindingCode; 9] = [
+        FindingCode::MisplacedFile,
+        FindingCode::HashMismatch,
+        FindingCode::MerkleMismatch,
+        FindingCode::UnreadableBlock,
+        FindingCode::MissingBlock,
+        FindingCode::UnprotectedBlock,
+        FindingCode::OrphanSubtree,
+        FindingCode::UnreachableBlock,
+        FindingCode::CounterDrift,
+    ];
+
+    /// The stable identifier of the code.
+    pub fn id(&self) -> &'static str {
+        match self {
+            FindingCode::MisplacedFile => "BA001",
+            FindingCode::HashMismatch => "BA002",
+            FindingCode::MerkleMismatch => "BA003",
+            FindingCode::UnreadableBlock => "BA004",
+            FindingCode::MissingBlock => "BA005",
+            FindingCode::UnprotectedBlock => "BA006",
+            FindingCode::OrphanSubtree => "BA010",
+            FindingCode::UnreachableBlock => "CS001",
+            FindingCode::CounterDrift => "CS002",
+        }
+    }
+
+    /// The name of the code.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FindingCode::MisplacedFile => "MisplacedFile",
+            FindingCode::HashMismatch => "HashMismatch",
+            FindingCode::MerkleMismatch => "MerkleMismatch",
+            FindingCode::UnreadableBlock => "UnreadableBlock",
+            FindingCode::MissingBlock => "MissingBlock",
+            FindingCode::UnprotectedBlock => "UnprotectedBlock",
+            FindingCode::OrphanSubtree => "OrphanSubtree",
+            FindingCode::UnreachableBlock => "UnreachableBlock",
+            FindingCode::CounterDrift => "CounterDrift",
+        }
+    }
+
+    /// The severity of the findings with this code.
+    pub fn severity(&self) -> Severity {
+        match self {
+            FindingCode::MisplacedFile => Severity::Warning,
+            FindingCode::UnprotectedBlock => Severity::Warning,
+            FindingCode::OrphanSubtree => Severity::Warning,
+            FindingCode::CounterDrift => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// A description of the problem.
+    pub fn description(&self) -> &'static str {
+        match self {
+            FindingCode::MisplacedFile => {
+                "A block file is not stored in the location for its hash, so it can not be read."
+            }
+            FindingCode::HashMismatch => {
+                "The header of a stored block does not hash to the hash that it is stored under."
+            }
+            FindingCode::MerkleMismatch => {
+                "The merkle root of the transactions of a stored block does not match the header."
+            }
+            FindingCode::UnreadableBlock => "A stored block could not be read or decoded.",
+            FindingCode::MissingBlock => "A selected block is not in the archive.",
+            FindingCode::UnprotectedBlock => {
+                "A stored block in an archive with immutable_blocks set is writable."
+            }
+            FindingCode::OrphanSubtree => {
+                "The parent of a stored block is not in the archive, the block is the root of an \
+                 orphaned subtree."
+            }
+            FindingCode::UnreachableBlock => {
+                "A BlockInfo can not be reached from genesis by following the next_ids."
+            }
+            FindingCode::CounterDrift => {
+                "A maintained counter does not match the value computed from the BlockInfos."
+            }
+        }
+    }
+
+    /// Get the code from its identifier.
+    pub fn from_id(id: &str) -> Option<FindingCode> {
+        FindingCode::ALL.into_iter().find(|c| c.id() == id)
+    }
+}
+
+impl fmt::Display for FindingCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+/// A problem found by a check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The kind of problem.
+    pub code: FindingCode,
+    /// The severity, which is the severity of the code.
+    pub severity: Severity,
+    /// What the problem was found in, such as a block hash, a file path, or a BlockInfo id.
+    pub subject: String,
+    /// Further details, such as the expected and the actual values.
+    pub details: BTreeMap<String, String>,
+    /// When the problem was first detected, in seconds since the unix epoch.
+    pub detected: u64,
+}
+
+impl Finding {
+    /// Create a finding that is detected now.
+    pub fn new(code: FindingCode, subject: impl ToString) -> Finding {
+        Finding {
+            code,
+            severity: code.severity(),
+            subject: subject.to_string(),
+            details: BTreeMap::new(),
+            detected: now(),
+        }
+    }
+
+    /// Add a detail to the finding.
+    pub fn with_detail(mut self, key: &str, value: impl ToString) -> Finding {
+        self.details.insert(String::from(key), value.to_string());
+        self
+    }
+}
+
+/// An accepted finding, which does not affect the exit code until it expires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suppression {
+    /// The code of the accepted finding.
+    pub code: FindingCode,
+    /// The subject of the accepted finding.
+    pub subject: String,
+    /// When the suppression expires, in seconds since the unix epoch. It does not expire if None.
+    pub expires: Option<u64>,
+}
+
+/// A list of [Suppression]s, usually read from a suppression file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Suppressions {
+    pub suppressions: Vec<Suppression>,
+}
+
+impl Suppressions {
+    /// Parse the contents of a suppression file.
+    ///
+    /// Each line has a code identifier, a subject, and optionally an expiry date as YYYY-MM-DD,
+    /// separated by whitespace. The suppression expires at the start of the expiry date, in UTC.
+    /// Empty lines and lines starting with '#' are ignored.
+    pub fn parse(s: &str) -> Result<Suppressions> {
+        let mut suppressions = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |m: &str| Error::InvalidSuppression(format!("line {}: {}", n + 1, m));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(invalid(
+                    "expected a code, a subject and an optional expiry date",
+                ));
+            }
+            let code =
+                FindingCode::from_id(fields[0]).ok_or_else(|| invalid("unknown finding code"))?;
+            let expires = match fields.get(2) {
+                Some(d) => Some(parse_date(d).ok_or_else(|| invalid("expiry is not YYYY-MM-DD"))?),
+                None => None,
+            };
+            suppressions.push(Suppression {
+                code,
+                subject: String::from(fields[1]),
+                expires,
+            });
+        }
+        Ok(Suppressions { suppressions })
+    }
+
+    /// Read and parse a suppression file.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Suppressions> {
+        Suppressions::parse(&tokio::fs::read_to_string(path).await?)
+    }
+
+    /// Check whether the finding is suppressed at the time, in seconds since the unix epoch.
+    pub fn is_suppressed(&self, finding: &Finding, time: u64) -> bool {
+        self.suppressions.iter().any(|s| {
+            s.code == finding.code
+                && s.subject == finding.subject
+                && s.expires.is_none_or(|e| time < e)
+        })
+    }
+}
+
+/// The format in which a [Reporter] writes the findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One line of text for each finding.
+    Text,
+    /// One JSON object for each finding, on its own line.
+    JsonLines,
+    /// A SARIF 2.1.0 log, written when the reporter is finished.
+    Sarif,
+}
+
+/// Writes the findings of a check in a [ReportFormat], and tracks the most severe finding that
+/// is not suppressed.
+///
+/// The messages of the check that are not findings, such as progress, are written with
+/// [Reporter::note()]. They are written to the output for the text format. For the other formats
+/// they are written to the writer given to [Reporter::with_notes()], so that the output can be
+/// parsed, or discarded if there is none.
+pub struct Reporter<W: Write> {
+    out: W,
+    format: ReportFormat,
+    suppressions: Suppressions,
+    // where the notes are written for the formats other than text
+    notes: Option<Box<dyn Write + Send>>,
+    // the findings reported so far, with whether they are suppressed
+    findings: Vec<(Finding, bool)>,
+}
+
+impl<W: Write> Reporter<W> {
+    /// Create a new Reporter that writes to the output.
+    pub fn new(out: W, format: ReportFormat, suppressions: Suppressions) -> Reporter<W> {
+        Reporter {
+            out,
+            format,
+            suppressions,
+            notes: None,
+            findings: Vec::new(),
+        }
+    }
+
+    /// Write the notes to the writer for the formats other than text, rather than discarding them.
+    pub fn with_notes(mut self, notes: impl Write + Send + 'static) -> Reporter<W> {
+        self.notes = Some(Box::new(notes));
+        self
+    }
+
+    /// Report a finding.
+    pub fn report(&mut self, finding: Finding) -> Result<()> {
+        let suppressed = self.suppressions.is_suppressed(&finding, now());
+        match self.format {
+            ReportFormat::Text => writeln!(self.out, "{}", text_line(&finding, suppressed))?,
+            ReportFormat::JsonLines => writeln!(self.out, "{}", json_line(&finding, suppressed))?,
+            ReportFormat::Sarif => {}
+        }
+        self.findings.push((finding, suppressed));
+        Ok(())
+    }
+
+    /// Write a message that is not a finding.
+    pub fn note(&mut self, message: &str) -> Result<()> {
+        match (self.format, self.notes.as_mut()) {
+            (ReportFormat::Text, _) => writeln!(self.out, "{}", message)?,
+            (_, Some(notes)) => writeln!(notes, "{}", message)?,
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    /// The findings reported so far.
+    pub fn findings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().map(|(f, _)| f)
+    }
+
+    /// The exit code for the most severe finding that is not suppressed, 0 if there is none.
+    pub fn exit_code(&self) -> i32 {
+        self.findings
+            .iter()
+            .filter(|(_, suppressed)| !suppressed)
+            .map(|(f, _)| f.severity.exit_code())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Finish the report, writing the SARIF log if that is the format, and return the exit code.
+    pub fn finish(mut self) -> Result<i32> {
+        if self.format == ReportFormat::Sarif {
+            writeln!(self.out, "{}", sarif_log(&self.findings))?;
+        }
+        if let Some(notes) = self.notes.as_mut() {
+            notes.flush()?;
+        }
+        self.out.flush()?;
+        Ok(self.exit_code())
+    }
+}
+
+// the current time in seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// parse a YYYY-MM-DD date into seconds since the unix epoch at the start of the day
+fn parse_date(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    // the year has at most four digits, larger years would overflow the calculation
+    if !(0..=9999).contains(&y) || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    // days from the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400).ok()
+}
+
+// a finding as a line of text
+fn text_line(f: &Finding, suppressed: bool) -> String {
+    let mut s = format!(
+        "{}: {} {} {}: {}",
+        f.severity.name().to_uppercase(),
+        f.code.id(),
+        f.code.name(),
+        f.subject,
+        f.code.description()
+    );
+    if !f.details.is_empty() {
+        let details: Vec<String> = f
+            .details
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        s.push_str(&format!(" ({})", details.join(", ")));
+    }
+    if suppressed {
+        s.push_str(" [suppressed]");
+    }
+    s
+}
+
+// a finding as a JSON object on a single line
+fn json_line(f: &Finding, suppressed: bool) -> String {
+    format!(
+        "{{\"code\":{},\"name\":{},\"severity\":{},\"subject\":{},\"details\":{},\"detected\":{},\"suppressed\":{}}}",
+        json_string(f.code.id()),
+        json_string(f.code.name()),
+        json_string(f.severity.name()),
+        json_string(&f.subject),
+        json_details(&f.details),
+        f.detected,
+        suppressed
+    )
+}
+
+// the SARIF 2.1.0 log of the findings, with a rule for every code
+fn sarif_log(findings: &[(Finding, bool)]) -> String {
+    let rules: Vec<String> = FindingCode::ALL
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"id\":{},\"name\":{},\"shortDescription\":{{\"text\":{}}},\"defaultConfiguration\":{{\"level\":{}}}}}",
+                json_string(c.id()),
+                json_string(c.name()),
+                json_string(c.description()),
+                json_string(c.severity().name())
+            )
+        })
+        .collect();
+    let results: Vec<String> = findings
+        .iter()
+        .map(|(f, suppressed)| {
+            let suppressions = if *suppressed {
+                ",\"suppressions\":[{\"kind\":\"external\"}]"
+            } else {
+                ""
+            };
+            format!(
+                "{{\"ruleId\":{},\"level\":{},\"message\":{{\"text\":{}}},\"locations\":[{{\"logicalLocations\":[{{\"name\":{}}}]}}],\"properties\":{{\"details\":{},\"detected\":{}}}{}}}",
+                json_string(f.code.id()),
+                json_string(f.severity.name()),
+                json_string(f.code.description()),
+                json_string(&f.subject),
+                json_details(&f.details),
+                f.detected,
+                suppressions
+            )
+        })
+        .collect();
+    format!(
+        "{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":\"bsvdb\",\"rules\":[{}]}}}},\"results\":[{}]}}]}}",
+        rules.join(","),
+        results.join(",")
+    )
+}
+
+// the details as a JSON object
+fn json_details(details: &BTreeMap<String, String>) -> String {
+    let v: Vec<String> = details
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), json_string(v)))
+        .collect();
+    format!("{{{}}}", v.join(","))
+}
+
+// a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The identifiers are stable and unique.
+    #[test]
+    fn stable_codes() {
+        let ids: Vec<&str> = FindingCode::ALL.iter().map(|c| c.id()).collect();
+        assert_eq!(
+            ids,
+            vec!["BA001", "BA002", "BA003", "BA004", "BA005", "BA006", "BA010", "CS001", "CS002"]
+        );
+        for c in FindingCode::ALL {
+            assert_eq!(FindingCode::from_id(c.id()), Some(c));
+            assert!(!c.description().is_empty());
+        }
+        assert_eq!(FindingCode::from_id("BA999"), None);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951_868_800));
+        assert_eq!(parse_date("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01"), None);
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("99999999999999999-01-01"), None);
+    }
+
+    // Suppressions match the code and the subject, until they expire.
+    #[test]
+    fn suppressions() {
+        let s = Suppressions::parse(
+            "# accepted\n\nBA010 0000aa\nBA001 ./48/x.bin 2000-01-01\nCS001 12 2999-01-01\n",
+        )
+        .unwrap();
+        assert_eq!(s.suppressions.len(), 3);
+        let t = now();
+        assert!(s.is_suppressed(&Finding::new(FindingCode::OrphanSubtree, "0000aa"), t));
+        assert!(!s.is_suppressed(&Finding::new(FindingCode::MissingBlock, "0000aa"), t));
+        assert!(!s.is_suppressed(&Finding::new(FindingCode::OrphanSubtree, "0000bb"), t));
+        // expired
+        assert!(!s.is_suppressed(&Finding::new(FindingCode::MisplacedFile, "./48/x.bin"), t));
+        assert!(s.is_suppressed(&Finding::new(FindingCode::UnreachableBlock, "12"), t));
+
+        for bad in ["BA999 x", "BA001", "BA001 x 2024-1", "BA001 x 2024-01-01 y"] {
+            assert!(matches!(
+                Suppressions::parse(bad),
+                Err(Error::InvalidSuppression(_))
+            ));
+        }
+    }
+
+    // The exit code reflects the most severe finding that is not suppressed.
+    #[test]
+    fn exit_code() {
+        let s = Suppressions::parse("BA003 aa").unwrap();
+        let mut r = Reporter::new(Vec::new(), ReportFormat::Text, s);
+        assert_eq!(r.exit_code(), 0);
+        r.report(Finding::new(FindingCode::OrphanSubtree, "bb"))
+            .unwrap();
+        assert_eq!(r.exit_code(), 1);
+        r.report(Finding::new(FindingCode::MerkleMismatch, "aa"))
+            .unwrap();
+        assert_eq!(r.exit_code(), 1);
+        r.report(Finding::new(FindingCode::MerkleMismatch, "bb"))
+            .unwrap();
+        assert_eq!(r.finish().unwrap(), 2);
+    }
+
+    fn finding() -> Finding {
+        let mut f = Finding::new(FindingCode::MisplacedFile, "a/\"b\".bin")
+            .with_detail("expected", "c\\d.bin");
+        f.detected = 1_700_000_000;
+        f
+    }
+
+    #[test]
+    fn text_format() {
+        let mut out = Vec::new();
+        let mut r = Reporter::new(&mut out, ReportFormat::Text, Suppressions::default());
+        r.note("checking").unwrap();
+        r.report(finding()).unwrap();
+        r.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "checking\nWARNING: BA001 MisplacedFile a/\"b\".bin: {} (expected=c\\d.bin)\n",
+                FindingCode::MisplacedFile.description()
+            )
+        );
+    }
+
+    // Each finding is a single line with a fixed set of keys, notes are not written.
+    #[test]
+    fn json_lines_format() {
+        let mut out = Vec::new();
+        let s = Suppressions::parse("BA001 a/\"b\".bin").unwrap();
+        let mut r = Reporter::new(&mut out, ReportFormat::JsonLines, s);
+        r.note("checking").unwrap();
+        r.report(finding()).unwrap();
+        assert_eq!(r.finish().unwrap(), 0);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"code\":\"BA001\",\"name\":\"MisplacedFile\",\"severity\":\"warning\",\
+             \"subject\":\"a/\\\"b\\\".bin\",\"details\":{\"expected\":\"c\\\\d.bin\"},\
+             \"detected\":1700000000,\"suppressed\":true}\n"
+        );
+    }
+
+    // The notes of the formats other than text are written to the notes writer.
+    #[test]
+    fn notes_writer() {
+        #[derive(Clone, Default)]
+        struct Shared(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let notes = Shared::default();
+        let mut out = Vec::new();
+        let mut r = Reporter::new(&mut out, ReportFormat::JsonLines, Suppressions::default())
+            .with_notes(notes.clone());
+        r.note("checking").unwrap();
+        r.finish().unwrap();
+        assert!(out.is_empty());
+        assert_eq!(notes.0.lock().unwrap().as_slice(), b"checking\n");
+    }
+
+    // The SARIF log has a rule for every code and a result for every finding.
+    #[test]
+    fn sarif_format() {
+        let mut out = Vec::new();
+        let mut r = Reporter::new(&mut out, ReportFormat::Sarif, Suppressions::default());
+        r.report(finding()).unwrap();
+        r.finish().unwrap();
+        let s = String::from_utf8(out).unwrap();
+        assert!(s.starts_with(
+            "{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\
+             \"runs\":[{\"tool\":{\"driver\":{\"name\":\"bsvdb\",\"rules\":[{\"id\":\"BA001\",\
+             \"name\":\"MisplacedFile\",\"shortDescription\":{\"text\":"
+        ));
+        assert_eq!(
+            s.matches("\"defaultConfiguration\"").count(),
+            FindingCode::ALL.len()
+        );
+        assert!(s.ends_with(&format!(
+            "\"results\":[{{\"ruleId\":\"BA001\",\"level\":\"warning\",\"message\":{{\"text\":{}}},\
+             \"locations\":[{{\"logicalLocations\":[{{\"name\":\"a/\\\"b\\\".bin\"}}]}}],\
+             \"properties\":{{\"details\":{{\"expected\":\"c\\\\d.bin\"}},\"detected\":1700000000}}}}]}}]}}\n",
+            json_string(FindingCode::MisplacedFile.description())
+        )));
+    }
+}
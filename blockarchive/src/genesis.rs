@@ -0,0 +1,50 @@
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockHeader, BlockchainId};
+
+// The coinbase transaction of the genesis block, the only transaction in the block. It is the
+// same for every chain, only the headers differ.
+const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+/// Get the encoded genesis block of a chain.
+///
+/// Archives usually do not store the genesis block because it is built into every node. The
+/// block is built from the built-in header and the coinbase transaction.
+pub fn genesis_block(chain: BlockchainId) -> Vec<u8> {
+    let mut block = BlockHeader::get_genesis(chain).to_binary_buf().unwrap();
+    // the number of transactions
+    block.push(1);
+    block.extend(hex::decode(GENESIS_COINBASE).unwrap());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::BlockHash;
+
+    #[test]
+    fn mainnet_genesis() {
+        let block = genesis_block(BlockchainId::Main);
+        assert_eq!(block.len(), 285);
+        let expected = std::fs::read(
+            "../testdata/blockarchive/6f/e2/000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f.bin",
+        )
+        .unwrap();
+        assert_eq!(block, expected);
+    }
+
+    #[test]
+    fn genesis_merkle_root() {
+        let coinbase = hex::decode(GENESIS_COINBASE).unwrap();
+        for chain in [
+            BlockchainId::Main,
+            BlockchainId::Test,
+            BlockchainId::Stn,
+            BlockchainId::Regtest,
+        ] {
+            let block = genesis_block(chain);
+            let header = BlockHeader::get_genesis(chain);
+            assert_eq!(&block[..80], header.to_binary_buf().unwrap().as_slice());
+            assert_eq!(header.merkle_root, BlockHash::sha256d(&coinbase));
+        }
+    }
+}
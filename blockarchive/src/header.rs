@@ -0,0 +1,218 @@
+use crate::Result;
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockHash, BlockHeader};
+use ring::digest::{digest, SHA256};
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// The archive only needs to name block files by their hashes and to read the headers at the start
+// of the files, which are the fixed 80 byte layout. These are handled here rather than by the
+// bitcoinsv types, so that the names and locations of the block files do not depend on how that
+// crate parses and formats hashes.
+
+/// The size of an encoded block header, in bytes.
+pub const HEADER_SIZE: usize = 80;
+
+/// A block hash, as the bytes produced by the hash function.
+///
+/// It is written as hex in display order, the reverse of the order of the bytes, as block hashes
+/// conventionally are and as the names of the block files are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ArchiveBlockHash(pub [u8; 32]);
+
+impl ArchiveBlockHash {
+    /// The double SHA-256 hash of the data.
+    pub fn sha256d(data: &[u8]) -> ArchiveBlockHash {
+        let first = digest(&SHA256, data);
+        let second = digest(&SHA256, first.as_ref());
+        let mut hash = [0; 32];
+        hash.copy_from_slice(second.as_ref());
+        ArchiveBlockHash(hash)
+    }
+
+    /// Parse a hash written as hex in display order. It must be exactly 64 hex digits, of either
+    /// case.
+    pub fn from_hex(s: &str) -> Option<ArchiveBlockHash> {
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut hash = [0; 32];
+        for (i, pair) in s.as_bytes().chunks(2).enumerate() {
+            let pair = std::str::from_utf8(pair).ok()?;
+            hash[31 - i] = u8::from_str_radix(pair, 16).ok()?;
+        }
+        Some(ArchiveBlockHash(hash))
+    }
+
+    /// The prefix of the hash, the 16 bit number formed by its first two bytes, which are the
+    /// names of the directories of the block file.
+    pub fn prefix(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]])
+    }
+}
+
+impl fmt::Display for ArchiveBlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter().rev() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<BlockHash> for ArchiveBlockHash {
+    fn from(value: BlockHash) -> Self {
+        ArchiveBlockHash(value.hash)
+    }
+}
+
+impl From<ArchiveBlockHash> for BlockHash {
+    fn from(value: ArchiveBlockHash) -> Self {
+        BlockHash::from(value.0.as_slice())
+    }
+}
+
+/// A block header, in the 80 byte layout at the start of every block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveBlockHeader {
+    pub version: u32,
+    pub prev_hash: ArchiveBlockHash,
+    pub merkle_root: ArchiveBlockHash,
+    pub timestamp: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl ArchiveBlockHeader {
+    /// Parse the header from the start of the bytes, None if there are fewer than 80 bytes.
+    pub fn parse(bytes: &[u8]) -> Option<ArchiveBlockHeader> {
+        let bytes = bytes.get(..HEADER_SIZE)?;
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let hash_at = |i: usize| ArchiveBlockHash(bytes[i..i + 32].try_into().unwrap());
+        Some(ArchiveBlockHeader {
+            version: u32_at(0),
+            prev_hash: hash_at(4),
+            merkle_root: hash_at(36),
+            timestamp: u32_at(68),
+            bits: u32_at(72),
+            nonce: u32_at(76),
+        })
+    }
+
+    /// Read the header from the start of the reader, which fails with an UnexpectedEof IoError if
+    /// it ends first.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<ArchiveBlockHeader> {
+        let mut bytes = [0; HEADER_SIZE];
+        reader.read_exact(&mut bytes).await?;
+        Ok(Self::parse(&bytes).expect("a full header"))
+    }
+
+    /// The encoded header.
+    pub fn serialize(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.prev_hash.0);
+        bytes[36..68].copy_from_slice(&self.merkle_root.0);
+        bytes[68..72].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        bytes[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    /// The hash of the block.
+    pub fn hash(&self) -> ArchiveBlockHash {
+        ArchiveBlockHash::sha256d(&self.serialize())
+    }
+}
+
+impl From<&BlockHeader> for ArchiveBlockHeader {
+    fn from(value: &BlockHeader) -> Self {
+        let bytes = value.to_binary_buf().expect("a header can be encoded");
+        ArchiveBlockHeader::parse(&bytes).expect("a full header")
+    }
+}
+
+impl From<&ArchiveBlockHeader> for BlockHeader {
+    fn from(value: &ArchiveBlockHeader) -> Self {
+        BlockHeader::from_binary_buf(&value.serialize()).expect("a full header")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the blocks of the test archive whose headers are compared
+    const FIXTURES: [&str; 4] = [
+        "6f/e2/000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        "6f/e2/00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f",
+        "48/60/00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        "48/60/00000000000005f20cad0d16326669b06c37c990e72372741cd9a1ff79b58a33",
+    ];
+
+    // The hashes, fields and encodings of the headers of the fixture blocks match those of the
+    // bitcoinsv types.
+    #[test]
+    fn header_parity() {
+        for f in FIXTURES {
+            let block = std::fs::read(format!("../testdata/blockarchive/{}.bin", f)).unwrap();
+            let local = ArchiveBlockHeader::parse(&block).unwrap();
+            let bsv = BlockHeader::from_binary_buf(&block[..HEADER_SIZE]).unwrap();
+            assert_eq!(local.hash().to_string(), bsv.hash().to_string());
+            assert_eq!(BlockHash::from(local.hash()), bsv.hash());
+            assert_eq!(BlockHash::from(local.prev_hash), bsv.prev_hash);
+            assert_eq!(BlockHash::from(local.merkle_root), bsv.merkle_root);
+            assert_eq!(local.timestamp, bsv.timestamp);
+            assert_eq!(local.bits, bsv.bits);
+            assert_eq!(local.nonce, bsv.nonce);
+            assert_eq!(local.serialize().as_slice(), &block[..HEADER_SIZE]);
+            assert_eq!(ArchiveBlockHeader::from(&bsv), local);
+            assert_eq!(BlockHeader::from(&local), bsv);
+            assert_eq!(
+                ArchiveBlockHash::sha256d(&block),
+                ArchiveBlockHash::from(BlockHash::sha256d(&block))
+            );
+        }
+        assert_eq!(ArchiveBlockHeader::parse(&[0; HEADER_SIZE - 1]), None);
+    }
+
+    // Hashes are written and parsed in display order, as the bitcoinsv hashes are.
+    #[test]
+    fn hash_hex() {
+        for f in FIXTURES {
+            let s = &f[6..];
+            let h = ArchiveBlockHash::from_hex(s).unwrap();
+            let bsv: BlockHash = s.parse().unwrap();
+            assert_eq!(h.to_string(), s);
+            assert_eq!(BlockHash::from(h), bsv);
+            assert_eq!(ArchiveBlockHash::from(bsv), h);
+            assert_eq!(ArchiveBlockHash::from_hex(&s.to_uppercase()), Some(h));
+        }
+        let h = ArchiveBlockHash::from_hex(&FIXTURES[2][6..]).unwrap();
+        assert_eq!(h.prefix(), 0x4860);
+        let s = &FIXTURES[0][6..];
+        let bad = [
+            s[..62].to_string(),
+            s[2..].to_string(),
+            format!("{}00", s),
+            s.replace('a', "g"),
+            // from_str_radix() accepts a sign
+            format!("+{}", &s[1..]),
+            "é".repeat(32),
+        ];
+        for b in bad {
+            assert_eq!(ArchiveBlockHash::from_hex(&b), None, "{}", b);
+        }
+    }
+
+    // A header is read from the start of a reader, which must hold a full header.
+    #[tokio::test]
+    async fn read_header() {
+        let block = std::fs::read(format!("../testdata/blockarchive/{}.bin", FIXTURES[2])).unwrap();
+        let h = ArchiveBlockHeader::read(&mut block.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(h.hash().to_string(), &FIXTURES[2][6..]);
+        let r = ArchiveBlockHeader::read(&mut &block[..40]).await;
+        assert!(matches!(r, Err(crate::Error::IoError(_))));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{Result, SimpleFileBasedBlockArchive};
+use bitcoinsv::bitcoin::BlockHash;
+use hex::{FromHex, ToHex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+// The intents log pairs the storage of a block in the archive with the insertion of its BlockInfo
+// in the chain store. An intent is recorded before the block is written and cleared after both
+// have succeeded, so after a crash only the blocks with an outstanding intent need to be checked.
+//
+// The log is a text file with a header line, a line "<hash> <size> <time>" for each intent, where
+// the size is "-" if it is not known, and a line with the hash prefixed by "-" for each cleared
+// intent. Lines are only appended, until there are more than COMPACT_LINES lines, when the log is
+// rewritten with only the outstanding intents.
+
+// the name of the log in the state directory of the archive, and its first line
+const LOG_FILE: &str = "intents.log";
+const LOG_HEADER: &str = "bsvdb-intents 1";
+// the file that a compacted log is written to before it replaces the log
+const LOG_TMP_FILE: &str = "intents.log.tmp";
+// the number of lines after which the log is compacted
+const COMPACT_LINES: usize = 1000;
+
+/// An intent to store a block in both the archive and the chain store, see [IntentLog].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Intent {
+    /// The hash of the block.
+    pub hash: BlockHash,
+    /// The size of the block, if it was known when the intent was recorded.
+    pub expected_size: Option<u64>,
+    /// When the intent was recorded, in seconds since the epoch.
+    pub time: u64,
+}
+
+impl Intent {
+    // the line of the intent in the log
+    fn line(&self) -> String {
+        let size = match self.expected_size {
+            Some(s) => s.to_string(),
+            None => String::from("-"),
+        };
+        format!(
+            "{} {} {}",
+            self.hash.encode_hex::<String>(),
+            size,
+            self.time
+        )
+    }
+
+    // parse a line of the log, returning None if it is not an intent
+    fn parse(line: &str) -> Option<Intent> {
+        let mut fields = line.split(' ');
+        let hash = BlockHash::from_hex(fields.next()?).ok()?;
+        let expected_size = match fields.next()? {
+            "-" => None,
+            s => Some(s.parse().ok()?),
+        };
+        let time = fields.next()?.parse().ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Intent {
+            hash,
+            expected_size,
+            time,
+        })
+    }
+}
+
+/// A persistent log of the blocks that are being stored in both the archive and the chain store.
+///
+/// A writer records an intent before it stores a block in the archive, and clears it after the
+/// block has been stored and its BlockInfo has been inserted in the chain store. If the writer is
+/// interrupted, the outstanding intents are the only blocks for which the two stores can
+/// disagree, and each can be resolved by checking the block in the archive.
+///
+/// The log is kept in the state directory of the archive, see
+/// [SimpleFileBasedBlockArchive::state_path()]. The writes are serialized within the process, the
+/// log must not be shared by writers in different processes.
+#[derive(Debug)]
+pub struct IntentLog {
+    dir: PathBuf,
+    // the number of lines in the log, excluding the header
+    lines: Mutex<usize>,
+}
+
+impl IntentLog {
+    /// Open the intents log of the archive, creating it if it does not exist.
+    pub async fn open(archive: &SimpleFileBasedBlockArchive) -> Result<IntentLog> {
+        let dir = archive.state_path();
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(LOG_FILE);
+        let lines = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => {
+                // end a line that was being written during a crash, so that it is not continued
+                if !text.is_empty() && !text.ends_with('\n') {
+                    let mut f = tokio::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&path)
+                        .await?;
+                    f.write_all(b"\n").await?;
+                    f.sync_data().await?;
+                }
+                text.lines().count().saturating_sub(1)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Self::write_log(&dir, &[]).await?;
+                0
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(IntentLog {
+            dir,
+            lines: Mutex::new(lines),
+        })
+    }
+
+    /// Record the intent to store a block, with its size if it is known.
+    pub async fn record(&self, block_hash: &BlockHash, expected_size: Option<u64>) -> Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let intent = Intent {
+            hash: *block_hash,
+            expected_size,
+            time,
+        };
+        self.append(&intent.line()).await
+    }
+
+    /// Clear the intent to store a block, once it is in both stores or has been discarded.
+    pub async fn clear(&self, block_hash: &BlockHash) -> Result<()> {
+        self.append(&format!("-{}", block_hash.encode_hex::<String>()))
+            .await
+    }
+
+    /// The outstanding intents, in the order in which they were recorded.
+    ///
+    /// An intent that is recorded more than once is returned once, with its last size and time.
+    /// Lines that can not be parsed, such as a line that was being written during a crash, are
+    /// ignored.
+    pub async fn outstanding(&self) -> Result<Vec<Intent>> {
+        let _lines = self.lines.lock().await;
+        self.read().await
+    }
+
+    /// Rewrite the log with only the outstanding intents.
+    pub async fn compact(&self) -> Result<()> {
+        let mut lines = self.lines.lock().await;
+        let intents = self.read().await?;
+        Self::write_log(&self.dir, &intents).await?;
+        *lines = intents.len();
+        Ok(())
+    }
+
+    // Append a line to the log, compacting it when it has become too long.
+    async fn append(&self, line: &str) -> Result<()> {
+        let mut lines = self.lines.lock().await;
+        let mut f = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(LOG_FILE))
+            .await?;
+        // a single write, which is flushed to disk before the block is written
+        f.write_all(format!("{}\n", line).as_bytes()).await?;
+        f.sync_data().await?;
+        *lines += 1;
+        if *lines > COMPACT_LINES {
+            let intents = self.read().await?;
+            Self::write_log(&self.dir, &intents).await?;
+            *lines = intents.len();
+        }
+        Ok(())
+    }
+
+    // Read the outstanding intents from the log.
+    async fn read(&self) -> Result<Vec<Intent>> {
+        let text = tokio::fs::read_to_string(self.dir.join(LOG_FILE)).await?;
+        // the position of each outstanding intent, and the intent
+        let mut outstanding = HashMap::new();
+        for (i, line) in text.lines().skip(1).enumerate() {
+            if let Some(hex) = line.strip_prefix('-') {
+                if let Ok(h) = BlockHash::from_hex(hex) {
+                    outstanding.remove(&h);
+                }
+            } else if let Some(intent) = Intent::parse(line) {
+                outstanding.insert(intent.hash, (i, intent));
+            }
+        }
+        let mut intents: Vec<(usize, Intent)> = outstanding.into_values().collect();
+        intents.sort_by_key(|(i, _)| *i);
+        Ok(intents.into_iter().map(|(_, intent)| intent).collect())
+    }
+
+    // Write a log of the intents to a temporary file which replaces the log.
+    async fn write_log(dir: &Path, intents: &[Intent]) -> Result<()> {
+        let mut text = String::from(LOG_HEADER);
+        text.push('\n');
+        for intent in intents {
+            text.push_str(&intent.line());
+            text.push('\n');
+        }
+        let tmp = dir.join(LOG_TMP_FILE);
+        let mut f = tokio::fs::File::create(&tmp).await?;
+        f.write_all(text.as_bytes()).await?;
+        f.sync_all().await?;
+        tokio::fs::rename(&tmp, dir.join(LOG_FILE)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsvdb_base::BlockArchiveConfig;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    async fn archive(root: &tempfile::TempDir) -> SimpleFileBasedBlockArchive {
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        SimpleFileBasedBlockArchive::new(&c).await.unwrap()
+    }
+
+    fn hash(i: u8) -> BlockHash {
+        BlockHash::sha256d(&[i])
+    }
+
+    // Intents are outstanding until they are cleared, and survive reopening the log.
+    #[tokio::test]
+    async fn record_and_clear() {
+        let root = tempdir().unwrap();
+        let archive = archive(&root).await;
+        let log = IntentLog::open(&archive).await.unwrap();
+        assert!(log.outstanding().await.unwrap().is_empty());
+        log.record(&hash(1), Some(215)).await.unwrap();
+        log.record(&hash(2), None).await.unwrap();
+        log.record(&hash(3), None).await.unwrap();
+        log.clear(&hash(2)).await.unwrap();
+        // a partial line from a crash is ignored
+        let path = archive.state_path().join(LOG_FILE);
+        let mut f = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .unwrap();
+        f.write_all(b"0000").await.unwrap();
+        drop(f);
+
+        let log = IntentLog::open(&archive).await.unwrap();
+        log.record(&hash(4), None).await.unwrap();
+        let intents = log.outstanding().await.unwrap();
+        assert_eq!(intents.len(), 3);
+        assert_eq!(intents[0].hash, hash(1));
+        assert_eq!(intents[0].expected_size, Some(215));
+        assert_eq!(intents[1].hash, hash(3));
+        assert_eq!(intents[1].expected_size, None);
+        assert_eq!(intents[2].hash, hash(4));
+        assert_eq!(Intent::parse(&intents[0].line()), Some(intents[0].clone()));
+        // the state directory does not hold blocks
+        assert!(archive
+            .fragmentation_report()
+            .await
+            .unwrap()
+            .empty_dirs
+            .is_empty());
+    }
+
+    // The log is compacted so that it stays bounded, concurrent writers do not lose intents.
+    #[tokio::test]
+    async fn bounded() {
+        let root = tempdir().unwrap();
+        let archive = archive(&root).await;
+        let log = Arc::new(IntentLog::open(&archive).await.unwrap());
+        let mut tasks = Vec::new();
+        for t in 0..4u8 {
+            let log = log.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..200u8 {
+                    let h = BlockHash::sha256d(&[t, i]);
+                    log.record(&h, Some(i as u64)).await.unwrap();
+                    if i % 100 != 0 {
+                        log.clear(&h).await.unwrap();
+                    }
+                }
+            }));
+        }
+        for t in tasks {
+            t.await.unwrap();
+        }
+        let intents = log.outstanding().await.unwrap();
+        assert_eq!(intents.len(), 8);
+        let path = archive.state_path().join(LOG_FILE);
+        let lines = tokio::fs::read_to_string(&path)
+            .await
+            .unwrap()
+            .lines()
+            .count();
+        assert!(lines <= COMPACT_LINES + 1);
+        log.compact().await.unwrap();
+        let text = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(text.lines().count(), 9);
+        assert_eq!(log.outstanding().await.unwrap(), intents);
+    }
+}
@@ -1,8 +1,50 @@
+mod artifact;
 mod block_archive;
+mod coalescing;
+mod findings;
+mod genesis;
+mod header;
+mod intents;
+mod open_files;
+mod partition;
+mod protect;
+mod replicated;
 mod sfb_archive;
+mod versions;
 
-pub use block_archive::{BlockArchive, BlockHashListStream};
-pub use sfb_archive::SimpleFileBasedBlockArchive;
+pub use artifact::{ArtifactId, ArtifactKind, ArtifactRegistry, ArtifactValidator};
+pub use block_archive::{
+    compute_merkle_root, BlockArchive, BlockHashListStream, BlockHashListStreamFromChannel,
+    ResumableRead, WalkSummary,
+};
+pub use coalescing::{CoalescingBlockArchive, CoalescingOptions, CoalescingStats};
+pub use findings::{
+    Finding, FindingCode, ReportFormat, Reporter, Severity, Suppression, Suppressions,
+};
+pub use genesis::genesis_block;
+pub use header::{ArchiveBlockHash, ArchiveBlockHeader, HEADER_SIZE};
+pub use intents::{Intent, IntentLog};
+pub use open_files::OpenFileLimit;
+pub use partition::Partition;
+pub use replicated::{
+    replicas_to_prometheus, CircuitState, PartialWrite, ReplicaOptions, ReplicaStatus,
+    ReplicatedBlockArchive, WriteMode,
+};
+pub use sfb_archive::{FragReport, SimpleFileBasedBlockArchive};
 
 mod result;
 pub use result::{Error, Result};
+
+use bitcoinsv::bitcoin::BlockHash;
+use std::pin::Pin;
+
+// Compile-time checks that the public handles and streams can be shared between tasks.
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SimpleFileBasedBlockArchive>();
+    assert_send_sync::<ReplicatedBlockArchive<SimpleFileBasedBlockArchive>>();
+    assert_send_sync::<CoalescingBlockArchive<SimpleFileBasedBlockArchive>>();
+    assert_send_sync::<BlockHashListStreamFromChannel>();
+    assert_send::<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>>();
+};
@@ -0,0 +1,204 @@
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf, SeekFrom};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// The limit on the number of files that the archive has open at once.
+//
+// Reading many blocks concurrently, such as by a check of the whole archive, opens a file for each
+// block and can exceed the limit of the process on open files, which fails the opens with "too
+// many open files". Every file of the archive is opened with a permit of the limit, which is held
+// until the file is closed, so opens wait for a permit instead. The directories that are walked to
+// list the blocks are not counted, a walk has at most a few open at once.
+
+// the share of the soft limit of the process that the archive may use, the rest is left for
+// sockets, the chain store and other files
+const SHARE_OF_RLIMIT: u64 = 2;
+// the limit when the limit of the process can not be read
+const FALLBACK_LIMIT: usize = 256;
+
+/// A limit on the number of files that an archive has open at once, see
+/// [BlockArchiveConfig::max_open_files](bsvdb_base::BlockArchiveConfig::max_open_files).
+///
+/// Clones share the limit. A task that holds as many readers of the archive as the limit waits
+/// forever to open another.
+#[derive(Debug, Clone)]
+pub struct OpenFileLimit {
+    permits: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl OpenFileLimit {
+    /// A limit of at most limit files, or of the default limit if limit is 0, see
+    /// [OpenFileLimit::default_limit()].
+    pub fn new(limit: u64) -> OpenFileLimit {
+        let limit = match limit {
+            0 => Self::default_limit(),
+            n => n.min(Semaphore::MAX_PERMITS as u64) as usize,
+        };
+        OpenFileLimit {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+
+    /// The default limit, half of the soft limit of the process on open files, or 256 if it can
+    /// not be read.
+    pub fn default_limit() -> usize {
+        match platform::soft_rlimit_nofile() {
+            Some(n) => (n / SHARE_OF_RLIMIT).clamp(1, Semaphore::MAX_PERMITS as u64) as usize,
+            None => FALLBACK_LIMIT,
+        }
+    }
+
+    /// The maximum number of files that are open at once.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of files that are open.
+    pub fn open(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+
+    /// Open the file for reading, once a permit is available.
+    pub(crate) async fn open_file(&self, path: impl AsRef<Path>) -> io::Result<LimitedFile> {
+        let permit = self.acquire().await;
+        let file = File::open(path).await?;
+        Ok(LimitedFile {
+            file,
+            _permit: permit,
+        })
+    }
+
+    /// Create or truncate the file for writing, once a permit is available.
+    pub(crate) async fn create_file(&self, path: impl AsRef<Path>) -> io::Result<LimitedFile> {
+        let permit = self.acquire().await;
+        let file = File::create(path).await?;
+        Ok(LimitedFile {
+            file,
+            _permit: permit,
+        })
+    }
+
+    /// Open the file with the options, once a permit is available.
+    pub(crate) async fn open_with(
+        &self,
+        options: &tokio::fs::OpenOptions,
+        path: impl AsRef<Path>,
+    ) -> io::Result<LimitedFile> {
+        let permit = self.acquire().await;
+        let file = options.open(path).await?;
+        Ok(LimitedFile {
+            file,
+            _permit: permit,
+        })
+    }
+
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+/// A file of the archive, which holds its permit of the [OpenFileLimit] until it is dropped.
+#[derive(Debug)]
+pub(crate) struct LimitedFile {
+    pub file: File,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for LimitedFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for LimitedFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.file).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.file).poll_complete(cx)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    /// The soft limit of the process on open files, None if it can not be read or is unlimited.
+    pub fn soft_rlimit_nofile() -> Option<u64> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+            || limit.rlim_cur == libc::RLIM_INFINITY
+        {
+            return None;
+        }
+        Some(limit.rlim_cur as u64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    pub fn soft_rlimit_nofile() -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    const BLOCK: &str = "../testdata/blockarchive/6f/e2/00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f.bin";
+
+    // A file holds its permit until it is dropped, a further open waits for a permit.
+    #[tokio::test]
+    async fn limit() {
+        let limit = OpenFileLimit::new(2);
+        assert_eq!(limit.limit(), 2);
+        let mut a = limit.open_file(BLOCK).await.unwrap();
+        let b = limit.open_file(BLOCK).await.unwrap();
+        assert_eq!(limit.open(), 2);
+        let r = tokio::time::timeout(Duration::from_millis(50), limit.open_file(BLOCK)).await;
+        assert!(r.is_err(), "expected the open to wait");
+        drop(b);
+        let c = limit.open_file(BLOCK).await.unwrap();
+        assert_eq!(limit.open(), 2);
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, std::fs::read(BLOCK).unwrap());
+        drop((a, c));
+        assert_eq!(limit.open(), 0);
+        // a failed open does not keep its permit
+        assert!(limit.open_file("../testdata/missing.bin").await.is_err());
+        assert_eq!(limit.open(), 0);
+    }
+
+    // The default limit is taken from the limit of the process.
+    #[test]
+    fn default_limit() {
+        let limit = OpenFileLimit::new(0);
+        assert_eq!(limit.limit(), OpenFileLimit::default_limit());
+        assert!(limit.limit() >= 1);
+        #[cfg(target_os = "linux")]
+        if let Some(n) = platform::soft_rlimit_nofile() {
+            assert_eq!(limit.limit() as u64, (n / 2).max(1));
+        }
+    }
+}
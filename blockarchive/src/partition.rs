@@ -0,0 +1,161 @@
+use crate::ArchiveBlockHash;
+use bitcoinsv::bitcoin::BlockHash;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+// the number of hash prefixes, a prefix is the first two bytes of the hash
+const PREFIXES: u32 = 1 << 16;
+
+/// One of N disjoint parts of the block hashes, so that a check of the archive can be split
+/// across several machines, see the block_list_partition() of
+/// [SimpleFileBasedBlockArchive](crate::SimpleFileBasedBlockArchive).
+///
+/// The prefix of a hash is the 16 bit number formed by its first two bytes, which are the names
+/// of the two levels of directories of the block in the archive. For example, the prefix of the
+/// block in "/48/60/" is 0x4860. The K-th partition of N, written "K/N" with K from 1 to N,
+/// holds the prefixes from floor((K-1) * 65536 / N) up to, but not including,
+/// floor(K * 65536 / N). The boundaries only depend on K and N and will not change between
+/// versions, so the results of different versions can be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    index: u32,
+    count: u32,
+}
+
+impl Partition {
+    /// The partition with all the block hashes.
+    pub const WHOLE: Partition = Partition { index: 1, count: 1 };
+
+    /// The index-th of count partitions, None unless 1 <= index <= count <= 65536.
+    pub fn new(index: u32, count: u32) -> Option<Partition> {
+        if (1..=PREFIXES).contains(&count) && (1..=count).contains(&index) {
+            Some(Partition { index, count })
+        } else {
+            None
+        }
+    }
+
+    /// The number of the partition, from 1.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The number of partitions.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Whether this is the only partition.
+    pub fn is_whole(&self) -> bool {
+        self.count == 1
+    }
+
+    /// The prefixes of the hashes in the partition.
+    pub fn prefixes(&self) -> Range<u32> {
+        let bound = |k: u32| (k as u64 * PREFIXES as u64 / self.count as u64) as u32;
+        bound(self.index - 1)..bound(self.index)
+    }
+
+    /// The prefix of a block hash.
+    pub fn prefix(block_hash: &BlockHash) -> u32 {
+        ArchiveBlockHash::from(*block_hash).prefix() as u32
+    }
+
+    /// The partition of count partitions that holds the block hash, None unless
+    /// 1 <= count <= 65536.
+    pub fn containing(block_hash: &BlockHash, count: u32) -> Option<Partition> {
+        // the first partition whose upper bound is above the prefix
+        let prefix = Self::prefix(block_hash) as u64 + 1;
+        let index = (prefix * count as u64).div_ceil(PREFIXES as u64);
+        Partition::new(index as u32, count)
+    }
+
+    /// Whether the block hash is in the partition.
+    pub fn contains(&self, block_hash: &BlockHash) -> bool {
+        self.prefixes().contains(&Self::prefix(block_hash))
+    }
+
+    // Whether any of the prefixes from first to last, inclusive, is in the partition.
+    pub(crate) fn overlaps(&self, first: u32, last: u32) -> bool {
+        let prefixes = self.prefixes();
+        first < prefixes.end && last >= prefixes.start
+    }
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid partition '{}', expected K/N with 1 <= K <= N", s);
+        let (index, count) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let index = index.parse().map_err(|_| invalid())?;
+        let count = count.parse().map_err(|_| invalid())?;
+        Partition::new(index, count).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The boundaries are fixed, the partitions are disjoint and cover every prefix.
+    #[test]
+    fn boundaries() {
+        let p: Partition = "2/3".parse().unwrap();
+        assert_eq!(p.prefixes(), 21845..43690);
+        assert_eq!(p.to_string(), "2/3");
+        assert_eq!(Partition::WHOLE.prefixes(), 0..65536);
+        for n in [1, 2, 3, 7, 256, 1000, 65536] {
+            let mut next = 0;
+            for k in 1..=n {
+                let r = Partition::new(k, n).unwrap().prefixes();
+                assert_eq!(r.start, next);
+                next = r.end;
+            }
+            assert_eq!(next, 65536);
+        }
+        for s in ["0/3", "4/3", "1/0", "1/65537", "1", "a/b"] {
+            assert!(s.parse::<Partition>().is_err());
+        }
+    }
+
+    // The prefix is given by the directories of the block in the archive.
+    #[test]
+    fn prefix() {
+        let h: BlockHash = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+            .parse()
+            .unwrap();
+        assert_eq!(Partition::prefix(&h), 0x4860);
+        assert!("1/3".parse::<Partition>().unwrap().contains(&h));
+        assert!(!"2/3".parse::<Partition>().unwrap().contains(&h));
+        let p: Partition = "2/3".parse().unwrap();
+        assert!(p.overlaps(0x5500, 0x55ff));
+        assert!(!p.overlaps(0x4800, 0x48ff));
+    }
+
+    // Every hash is in the partition that is found for it.
+    #[test]
+    fn containing() {
+        for prefix in [0u32, 1, 0x4860, 21844, 21845, 43690, 65535] {
+            let mut bytes = [0u8; 32];
+            bytes[0] = (prefix >> 8) as u8;
+            bytes[1] = prefix as u8;
+            let h = BlockHash::from(bytes.as_slice());
+            for n in [1, 2, 3, 7, 256, 1000, 65536] {
+                let p = Partition::containing(&h, n).unwrap();
+                assert_eq!(p.count(), n);
+                assert!(p.contains(&h), "{} in {}", prefix, p);
+            }
+        }
+        let h = BlockHash::from([0u8; 32].as_slice());
+        assert_eq!(Partition::containing(&h, 0), None);
+        assert_eq!(Partition::containing(&h, 65537), None);
+    }
+}
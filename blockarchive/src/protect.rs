@@ -0,0 +1,77 @@
+use std::io;
+use std::path::Path;
+use std::sync::Once;
+
+// Write protection of stored blocks, used when the archive is configured with immutable_blocks.
+//
+// A protected file is read-only. On Linux the immutable flag is also set when the process has the
+// CAP_LINUX_IMMUTABLE capability, which stops even root from changing or deleting the file until
+// the flag is cleared with "chattr -i". Without the capability, or on a filesystem that does not
+// support the flag, only the permissions are changed and a note is logged once.
+
+// the note that the immutable flag could not be set is only logged once
+static FLAG_NOTE: Once = Once::new();
+
+/// Make the file read-only and, if set_flag is true and it is permitted, immutable.
+pub(crate) async fn protect(path: &Path, set_flag: bool) -> io::Result<()> {
+    let mut perms = tokio::fs::metadata(path).await?.permissions();
+    perms.set_readonly(true);
+    tokio::fs::set_permissions(path, perms).await?;
+    if set_flag {
+        if let Err(e) = platform::set_immutable(path) {
+            FLAG_NOTE.call_once(|| {
+                tracing::info!(
+                    "immutable flag not set on {}, blocks are only read-only: {}",
+                    path.display(),
+                    e
+                )
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check whether the file is protected, which only checks that it is read-only.
+pub(crate) async fn is_protected(path: &Path) -> io::Result<bool> {
+    Ok(tokio::fs::metadata(path).await?.permissions().readonly())
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    // from linux/fs.h
+    const FS_IOC_GETFLAGS: libc::c_ulong = 0x8008_6601;
+    const FS_IOC_SETFLAGS: libc::c_ulong = 0x4008_6602;
+    const FS_IMMUTABLE_FL: libc::c_int = 0x0000_0010;
+
+    // set the immutable flag of the file
+    pub fn set_immutable(path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+        let mut flags: libc::c_int = 0;
+        // SAFETY: the file descriptor is open and the kernel writes an int to flags
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS as _, &mut flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        flags |= FS_IMMUTABLE_FL;
+        // SAFETY: the file descriptor is open and the kernel reads an int from flags
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS as _, &flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    // the immutable flag is not supported on this platform
+    pub fn set_immutable(_path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,838 @@
+use crate::block_archive::BlockHashListStream;
+use crate::{
+    ArtifactId, ArtifactKind, BlockArchive, Error, OpenFileLimit, Result,
+    SimpleFileBasedBlockArchive,
+};
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
+use bsvdb_base::{BlockArchiveConfig, DEFAULT_MAX_BUFFERED_BLOCK_BYTES};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::future::Future;
+use std::io::Cursor;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinHandle;
+
+// the maximum number of partial writes that are kept until they are taken, older ones are dropped
+const MAX_PARTIAL_WRITES: usize = 10_000;
+
+/// How many replicas must store a block or artifact for a write to succeed, see
+/// [ReplicatedBlockArchive].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Every replica must store it.
+    All,
+    /// A majority of the replicas must store it.
+    Quorum,
+}
+
+impl WriteMode {
+    /// Get the mode from its name in the configuration, "all" or "quorum".
+    pub fn from_name(name: &str) -> Option<WriteMode> {
+        match name {
+            "all" => Some(WriteMode::All),
+            "quorum" => Some(WriteMode::Quorum),
+            _ => None,
+        }
+    }
+
+    /// The name of the mode in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WriteMode::All => "all",
+            WriteMode::Quorum => "quorum",
+        }
+    }
+
+    /// The number of replicas, out of the total, that must store a write.
+    pub fn required(&self, replicas: usize) -> usize {
+        match self {
+            WriteMode::All => replicas,
+            WriteMode::Quorum => replicas / 2 + 1,
+        }
+    }
+}
+
+/// The options of a [ReplicatedBlockArchive].
+#[derive(Debug, Clone)]
+pub struct ReplicaOptions {
+    /// How many replicas must store a write.
+    pub write_mode: WriteMode,
+    /// How long a replica is skipped by reads after it fails. Defaults to 30 seconds.
+    pub cooldown: Duration,
+    /// How long a replica has to respond before it is treated as failed. Defaults to 60 seconds.
+    pub timeout: Duration,
+    /// The size of the largest block that is read into memory to be written to the replicas.
+    /// Defaults to 1 GiB.
+    pub max_buffered_bytes: u64,
+}
+
+impl Default for ReplicaOptions {
+    fn default() -> Self {
+        ReplicaOptions {
+            write_mode: WriteMode::All,
+            cooldown: Duration::from_secs(30),
+            timeout: Duration::from_secs(60),
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BLOCK_BYTES,
+        }
+    }
+}
+
+/// The state of the circuit breaker of a replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The replica is in use.
+    Closed,
+    /// The replica failed recently and is only read if every other replica fails.
+    Open,
+    /// The cooldown has passed since the replica failed, the next read tries it again.
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// The name of the state, for output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+/// The health of a replica, see [ReplicatedBlockArchive::status()].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicaStatus {
+    /// The name of the replica, the root path for a file based archive.
+    pub name: String,
+    /// The state of its circuit breaker.
+    pub state: CircuitState,
+    /// The number of failures since it last succeeded.
+    pub consecutive_failures: u64,
+    /// The total number of failures.
+    pub failures: u64,
+    /// The number of reads that failed on it and were passed to the next replica.
+    pub failovers: u64,
+    /// The number of writes that it missed but that were stored by other replicas.
+    pub missed_writes: u64,
+    /// The most recent failure.
+    pub last_error: Option<String>,
+}
+
+/// A write that was stored by some replicas but not all of them.
+///
+/// It is returned in Error::PartialWrite when too few replicas stored it, and is kept for
+/// [ReplicatedBlockArchive::take_partial_writes()] in either case, so that a repair pass can copy
+/// it to the replicas that missed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialWrite {
+    /// The block hash, or the kind and identifier of the artifact, e.g. "template/candidate-1".
+    pub key: String,
+    /// The names of the replicas that stored it, or already had it.
+    pub stored: Vec<String>,
+    /// The names of the replicas that failed, with the error.
+    pub failed: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} stored by {}, failed on ",
+            self.key,
+            self.stored.join(", ")
+        )?;
+        for (i, (name, e)) in self.failed.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} ({})", name, e)?;
+        }
+        Ok(())
+    }
+}
+
+// Whether an error means that the replica failed, rather than that the request was refused.
+fn is_failure(e: &Error) -> bool {
+    matches!(e, Error::IoError(_) | Error::Timeout)
+}
+
+// the circuit breaker and counters of a replica
+#[derive(Default)]
+struct Health {
+    // the replica is skipped until this time, set when it fails
+    open_until: Option<Instant>,
+    consecutive_failures: u64,
+    failures: u64,
+    failovers: u64,
+    missed_writes: u64,
+    last_error: Option<String>,
+}
+
+impl Health {
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            Some(t) if t > now => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+            None => CircuitState::Closed,
+        }
+    }
+}
+
+struct Replica<A> {
+    name: String,
+    archive: A,
+    health: Mutex<Health>,
+}
+
+impl<A> Replica<A> {
+    fn state(&self) -> CircuitState {
+        self.health.lock().unwrap().state(Instant::now())
+    }
+
+    // Run an operation on the replica, with the timeout, and record whether it failed.
+    async fn call<T, F>(&self, options: &ReplicaOptions, f: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send,
+    {
+        let r = tokio::time::timeout(options.timeout, f)
+            .await
+            .unwrap_or_else(|_| Err(Error::Timeout));
+        self.record(&r, options.cooldown);
+        r
+    }
+
+    // Record the result of an operation, opening the circuit if the replica failed and closing it
+    // otherwise.
+    fn record<T>(&self, r: &Result<T>, cooldown: Duration) {
+        let mut health = self.health.lock().unwrap();
+        match r {
+            Err(e) if is_failure(e) => {
+                health.open_until = Some(Instant::now() + cooldown);
+                health.consecutive_failures += 1;
+                health.failures += 1;
+                health.last_error = Some(e.to_string());
+            }
+            _ => {
+                health.open_until = None;
+                health.consecutive_failures = 0;
+            }
+        }
+    }
+}
+
+/// A block archive that is replicated across several archives, such as copies of a
+/// [SimpleFileBasedBlockArchive] on different disks.
+///
+/// Reads are served by the first replica that can. A replica that fails with an IO error or a
+/// timeout is passed over for the next one and its circuit is opened, so that it is only read if
+/// every other replica fails, until the cooldown has passed or a probe finds that it responds
+/// again, see [ReplicatedBlockArchive::probe()]. Error::BlockNotFound is only returned if no
+/// replica has the block and none of them failed.
+///
+/// Writes go to every replica at the same time. The block or artifact is read into memory first so
+/// that it can be written more than once, a block that is larger than the max_buffered_bytes of
+/// the [ReplicaOptions] is refused with Error::BlockTooLarge. A replica that already has it counts as having stored
+/// it, so a write that failed can be retried. The write succeeds when the replicas required by the
+/// [WriteMode] have stored it. If some but too few did, Error::PartialWrite is returned. A write
+/// that some replicas missed is also kept for [ReplicatedBlockArchive::take_partial_writes()], even
+/// if it succeeded.
+///
+/// Lists come from a single replica, the first that can start the list.
+///
+/// Example code:
+///     let archive = Arc::new(ReplicatedBlockArchive::from_config(&config).await?);
+///     let probe = ReplicatedBlockArchive::spawn_probe(&archive, Duration::from_secs(10));
+///     let block = archive.get_block(&block_hash).await?;
+pub struct ReplicatedBlockArchive<A> {
+    replicas: Vec<Replica<A>>,
+    options: ReplicaOptions,
+    partial_writes: Mutex<VecDeque<PartialWrite>>,
+}
+
+impl<A: BlockArchive + Send + Sync> ReplicatedBlockArchive<A> {
+    /// Create a replicated archive from the named replicas, in the order that they are read.
+    ///
+    /// Panics if there are no replicas.
+    pub fn new(replicas: Vec<(String, A)>, options: ReplicaOptions) -> Self {
+        assert!(!replicas.is_empty(), "at least one replica is required");
+        ReplicatedBlockArchive {
+            replicas: replicas
+                .into_iter()
+                .map(|(name, archive)| Replica {
+                    name,
+                    archive,
+                    health: Mutex::new(Health::default()),
+                })
+                .collect(),
+            options,
+            partial_writes: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The replicas, in order, e.g. to configure them.
+    pub fn replicas_mut(&mut self) -> impl Iterator<Item = &mut A> {
+        self.replicas.iter_mut().map(|r| &mut r.archive)
+    }
+
+    /// The health of each replica, in order.
+    pub fn status(&self) -> Vec<ReplicaStatus> {
+        let now = Instant::now();
+        self.replicas
+            .iter()
+            .map(|r| {
+                let health = r.health.lock().unwrap();
+                ReplicaStatus {
+                    name: r.name.clone(),
+                    state: health.state(now),
+                    consecutive_failures: health.consecutive_failures,
+                    failures: health.failures,
+                    failovers: health.failovers,
+                    missed_writes: health.missed_writes,
+                    last_error: health.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Take the writes that some replicas missed since they were last taken, oldest first.
+    ///
+    /// At most 10,000 are kept, older ones are dropped.
+    pub fn take_partial_writes(&self) -> Vec<PartialWrite> {
+        self.partial_writes.lock().unwrap().drain(..).collect()
+    }
+
+    /// Check that every replica responds, opening the circuit of those that fail and closing it
+    /// for those that respond.
+    pub async fn probe(&self) {
+        // any hash will do, the probe only checks that the replica responds
+        let hash = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        for r in self.replicas.iter() {
+            let _ = r.call(&self.options, r.archive.block_exists(&hash)).await;
+        }
+    }
+
+    // The indexes of the replicas in the order that they are read, those with an open circuit
+    // last.
+    fn read_order(&self) -> Vec<usize> {
+        let (mut order, open): (Vec<usize>, Vec<usize>) =
+            (0..self.replicas.len()).partition(|i| self.replicas[*i].state() != CircuitState::Open);
+        order.extend(open);
+        order
+    }
+
+    // Read from the first replica that can, see [ReplicatedBlockArchive].
+    async fn read<'a, T, F, Fut>(&'a self, op: F) -> Result<T>
+    where
+        T: Send,
+        F: Fn(&'a A) -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+    {
+        let order = self.read_order();
+        let mut missing = None;
+        let mut failure = None;
+        for (n, i) in order.iter().enumerate() {
+            let r = &self.replicas[*i];
+            match r.call(&self.options, op(&r.archive)).await {
+                Ok(v) => return Ok(v),
+                Err(e @ (Error::BlockNotFound(_) | Error::ArtifactNotFound)) => missing = Some(e),
+                Err(e) if is_failure(&e) => {
+                    if n + 1 < order.len() {
+                        r.health.lock().unwrap().failovers += 1;
+                        tracing::warn!("replica {} failed, trying the next: {}", r.name, e);
+                    }
+                    failure = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(failure.or(missing).expect("there is at least one replica"))
+    }
+
+    // Write to every replica, see [ReplicatedBlockArchive].
+    async fn write<'a, F, Fut>(&'a self, key: String, data: Vec<u8>, op: F) -> Result<()>
+    where
+        F: Fn(&'a A, Box<dyn AsyncRead + Unpin + Send>) -> Fut + Send,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let data: Arc<[u8]> = data.into();
+        let results = futures::future::join_all(self.replicas.iter().map(|r| {
+            let reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(Cursor::new(data.clone()));
+            r.call(&self.options, op(&r.archive, reader))
+        }))
+        .await;
+        let mut stored = Vec::new();
+        let mut failed = Vec::new();
+        let mut existed = 0;
+        let mut exists = None;
+        let mut first_error = None;
+        for (r, result) in self.replicas.iter().zip(results) {
+            match result {
+                Ok(()) => stored.push(r.name.clone()),
+                // the replica already has it, e.g. from an earlier partial write
+                Err(e @ (Error::BlockExists | Error::ArtifactExists)) => {
+                    stored.push(r.name.clone());
+                    existed += 1;
+                    exists = Some(e);
+                }
+                Err(e) => {
+                    failed.push((r, e.to_string()));
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if failed.is_empty() {
+            return match exists {
+                // every replica already had it
+                Some(e) if existed == self.replicas.len() => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if stored.is_empty() {
+            return Err(first_error.unwrap());
+        }
+        for (r, _) in failed.iter() {
+            r.health.lock().unwrap().missed_writes += 1;
+        }
+        let partial = PartialWrite {
+            key,
+            stored,
+            failed: failed
+                .into_iter()
+                .map(|(r, e)| (r.name.clone(), e))
+                .collect(),
+        };
+        tracing::warn!("partial write: {}", partial);
+        {
+            let mut partial_writes = self.partial_writes.lock().unwrap();
+            if partial_writes.len() == MAX_PARTIAL_WRITES {
+                partial_writes.pop_front();
+            }
+            partial_writes.push_back(partial.clone());
+        }
+        if partial.stored.len() >= self.options.write_mode.required(self.replicas.len()) {
+            Ok(())
+        } else {
+            Err(Error::PartialWrite(partial))
+        }
+    }
+}
+
+impl<A: BlockArchive + Send + Sync + 'static> ReplicatedBlockArchive<A> {
+    /// Probe the replicas every interval in a background task, see
+    /// [ReplicatedBlockArchive::probe()].
+    ///
+    /// The task ends when the archive is dropped.
+    pub fn spawn_probe(archive: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let archive = Arc::downgrade(archive);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match archive.upgrade() {
+                    Some(a) => a.probe().await,
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+impl ReplicatedBlockArchive<SimpleFileBasedBlockArchive> {
+    /// Open the archive at the root path of the configuration, followed by its replicas.
+    ///
+    /// The replicas have the same layout and write protection as the archive, and every one of
+    /// them must be reachable when it is opened.
+    pub async fn from_config(config: &BlockArchiveConfig) -> Result<Self> {
+        let mut replicas = Vec::new();
+        // the replicas share the limit on open files, which is a limit of the process
+        let open_files = OpenFileLimit::new(config.max_open_files);
+        for root_path in std::iter::once(&config.root_path).chain(config.replicas.iter()) {
+            let c = BlockArchiveConfig {
+                root_path: root_path.clone(),
+                ..config.clone()
+            };
+            let mut archive = SimpleFileBasedBlockArchive::new(&c).await?;
+            archive.open_files = open_files.clone();
+            replicas.push((root_path.clone(), archive));
+        }
+        let options = ReplicaOptions {
+            // the write mode has been validated with the configuration
+            write_mode: WriteMode::from_name(&config.replica_write_mode).unwrap_or(WriteMode::All),
+            max_buffered_bytes: config.max_buffered_block_bytes,
+            ..ReplicaOptions::default()
+        };
+        Ok(Self::new(replicas, options))
+    }
+}
+
+#[async_trait]
+impl<A: BlockArchive + Send + Sync> BlockArchive for ReplicatedBlockArchive<A> {
+    async fn get_block(&self, block_hash: &BlockHash) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        self.read(|a| a.get_block(block_hash)).await
+    }
+
+    async fn block_exists(&self, block_hash: &BlockHash) -> Result<bool> {
+        // a replica that does not have the block is treated as missing it, so the next is asked
+        let r = self
+            .read(|a| async move {
+                match a.block_exists(block_hash).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(Error::BlockNotFound(*block_hash)),
+                    Err(e) => Err(e),
+                }
+            })
+            .await;
+        match r {
+            Ok(()) => Ok(true),
+            Err(Error::BlockNotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn store_block(
+        &self,
+        block_hash: &BlockHash,
+        block: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()> {
+        // read one byte more than the limit, to find out whether the block is larger
+        let limit = self.options.max_buffered_bytes;
+        let mut buf = Vec::new();
+        block
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut buf)
+            .await?;
+        if buf.len() as u64 > limit {
+            return Err(Error::BlockTooLarge(limit));
+        }
+        self.write(block_hash.to_string(), buf, |a, mut reader| async move {
+            a.store_block(block_hash, &mut reader).await
+        })
+        .await
+    }
+
+    fn max_buffered_bytes(&self) -> u64 {
+        self.options.max_buffered_bytes
+    }
+
+    async fn block_size(&self, block_hash: &BlockHash) -> Result<usize> {
+        self.read(|a| a.block_size(block_hash)).await
+    }
+
+    async fn block_header(&self, block_hash: &BlockHash) -> Result<BlockHeader> {
+        self.read(|a| a.block_header(block_hash)).await
+    }
+
+    async fn block_list(
+        &mut self,
+    ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>> {
+        let mut failure = None;
+        for i in self.read_order() {
+            let r = &mut self.replicas[i];
+            let result = tokio::time::timeout(self.options.timeout, r.archive.block_list())
+                .await
+                .unwrap_or_else(|_| Err(Error::Timeout));
+            r.record(&result, self.options.cooldown);
+            match result {
+                Ok(s) => return Ok(s),
+                Err(e) if is_failure(&e) => failure = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(failure.expect("there is at least one replica"))
+    }
+
+    async fn store_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+        artifact: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        artifact.read_to_end(&mut buf).await?;
+        let key = format!("{}/{}", kind, id);
+        self.write(key, buf, |a, mut reader| async move {
+            a.store_artifact(kind, id, &mut reader).await
+        })
+        .await
+    }
+
+    async fn get_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        self.read(|a| a.get_artifact(kind, id)).await
+    }
+
+    async fn list_artifacts(&self, kind: &ArtifactKind) -> Result<Vec<ArtifactId>> {
+        self.read(|a| a.list_artifacts(kind)).await
+    }
+}
+
+/// Render the health of the replicas in the Prometheus text exposition format.
+pub fn replicas_to_prometheus(status: &[ReplicaStatus]) -> String {
+    let mut s = String::new();
+    let metrics: [(&str, &str, &str, fn(&ReplicaStatus) -> u64); 4] = [
+        (
+            "bsvdb_blockarchive_replica_up",
+            "Whether the circuit of the replica is not open.",
+            "gauge",
+            |r| (r.state != CircuitState::Open) as u64,
+        ),
+        (
+            "bsvdb_blockarchive_replica_failures_total",
+            "Failures of the replica.",
+            "counter",
+            |r| r.failures,
+        ),
+        (
+            "bsvdb_blockarchive_replica_failovers_total",
+            "Reads that failed over from the replica to the next.",
+            "counter",
+            |r| r.failovers,
+        ),
+        (
+            "bsvdb_blockarchive_replica_missed_writes_total",
+            "Writes that the replica missed and other replicas stored.",
+            "counter",
+            |r| r.missed_writes,
+        ),
+    ];
+    for (name, help, kind, value) in metrics {
+        writeln!(s, "# HELP {} {}", name, help).unwrap();
+        writeln!(s, "# TYPE {} {}", name, kind).unwrap();
+        for r in status.iter() {
+            let label = r.name.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(s, "{}{{replica=\"{}\"}} {}", name, label, value(r)).unwrap();
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::FromHex;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    // The directories of n replicas in the root directory.
+    fn replica_dirs(root: &Path, n: usize) -> Vec<PathBuf> {
+        (0..n).map(|i| root.join(format!("replica{}", i))).collect()
+    }
+
+    // Open a replicated archive of file based archives in the directories, creating them.
+    async fn open(
+        dirs: &[PathBuf],
+        write_mode: WriteMode,
+    ) -> ReplicatedBlockArchive<SimpleFileBasedBlockArchive> {
+        let mut replicas = Vec::new();
+        for dir in dirs {
+            std::fs::create_dir_all(dir).unwrap();
+            let c = BlockArchiveConfig {
+                enabled: true,
+                root_path: String::from(dir.to_str().unwrap()),
+                ..BlockArchiveConfig::default()
+            };
+            replicas.push((
+                c.root_path.clone(),
+                SimpleFileBasedBlockArchive::new(&c).await.unwrap(),
+            ));
+        }
+        let options = ReplicaOptions {
+            write_mode,
+            ..ReplicaOptions::default()
+        };
+        ReplicatedBlockArchive::new(replicas, options)
+    }
+
+    // Make a replica fail by replacing its directory with a file, which unlike removing the
+    // permissions also works when the tests are run as root.
+    fn break_replica(dir: &Path) {
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::write(dir, "").unwrap();
+    }
+
+    // Make a broken replica work again, it is empty.
+    fn repair_replica(dir: &Path) {
+        std::fs::remove_file(dir).unwrap();
+        std::fs::create_dir(dir).unwrap();
+    }
+
+    fn hash() -> BlockHash {
+        BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+            .unwrap()
+    }
+
+    fn reader(data: &[u8]) -> Box<dyn AsyncRead + Unpin + Send> {
+        Box::new(Cursor::new(data.to_vec()))
+    }
+
+    async fn read_block(
+        archive: &ReplicatedBlockArchive<SimpleFileBasedBlockArchive>,
+        h: &BlockHash,
+    ) -> Result<Vec<u8>> {
+        let mut r = archive.get_block(h).await?;
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn write_modes() {
+        assert_eq!(WriteMode::from_name("quorum"), Some(WriteMode::Quorum));
+        assert_eq!(WriteMode::from_name("most"), None);
+        assert_eq!(WriteMode::All.required(3), 3);
+        assert_eq!(WriteMode::Quorum.required(1), 1);
+        assert_eq!(WriteMode::Quorum.required(2), 2);
+        assert_eq!(WriteMode::Quorum.required(3), 2);
+    }
+
+    // A block larger than the limit is not read into memory, whether it is given as a reader or
+    // as bytes.
+    #[tokio::test]
+    async fn test_block_too_large() {
+        let root = tempdir().unwrap();
+        let dirs = replica_dirs(root.path(), 2);
+        let mut archive = open(&dirs, WriteMode::All).await;
+        archive.options.max_buffered_bytes = 10;
+        let h = hash();
+        let r = archive.store_block(&h, &mut reader(&[1u8; 11])).await;
+        assert!(matches!(r, Err(Error::BlockTooLarge(10))));
+        let r = archive.store_block_bytes(&h, vec![1u8; 11]).await;
+        assert!(matches!(r, Err(Error::BlockTooLarge(10))));
+        assert!(!archive.block_exists(&h).await.unwrap());
+        archive.store_block_bytes(&h, vec![1u8; 10]).await.unwrap();
+        assert_eq!(read_block(&archive, &h).await.unwrap(), vec![1u8; 10]);
+    }
+
+    // A read fails over to the next replica, the failed replica is skipped until a probe finds
+    // that it responds again.
+    #[tokio::test]
+    async fn test_read_failover() {
+        let root = tempdir().unwrap();
+        let dirs = replica_dirs(root.path(), 2);
+        let (a, b) = (&dirs[0], &dirs[1]);
+        let archive = open(&dirs, WriteMode::All).await;
+        let h = hash();
+        archive
+            .store_block(&h, &mut reader(b"block"))
+            .await
+            .unwrap();
+        break_replica(a);
+        assert_eq!(read_block(&archive, &h).await.unwrap(), b"block");
+        let status = archive.status();
+        assert_eq!(status[0].state, CircuitState::Open);
+        assert_eq!(status[0].failures, 1);
+        assert_eq!(status[0].failovers, 1);
+        assert_eq!(status[1].state, CircuitState::Closed);
+        // the open replica is not tried while another can serve the read
+        assert_eq!(archive.block_size(&h).await.unwrap(), 5);
+        assert!(archive.block_exists(&h).await.unwrap());
+        assert_eq!(archive.status()[0].failures, 1);
+        // the probe finds that it is still failing
+        archive.probe().await;
+        assert_eq!(archive.status()[0].consecutive_failures, 2);
+        repair_replica(a);
+        archive.probe().await;
+        let status = archive.status();
+        assert_eq!(status[0].state, CircuitState::Closed);
+        assert_eq!(status[0].consecutive_failures, 0);
+        assert_eq!(status[0].failures, 2);
+        let s = replicas_to_prometheus(&status);
+        assert!(s.contains(&format!(
+            "bsvdb_blockarchive_replica_failovers_total{{replica=\"{}\"}} 1\n",
+            a.display()
+        )));
+    }
+
+    // A block is only not found if no replica has it and none failed.
+    #[tokio::test]
+    async fn test_not_found() {
+        let root = tempdir().unwrap();
+        let dirs = replica_dirs(root.path(), 2);
+        let mut archive = open(&dirs, WriteMode::All).await;
+        let h = hash();
+        // stored in the second replica only
+        let second = archive.replicas_mut().nth(1).unwrap();
+        second.store_block(&h, &mut reader(b"block")).await.unwrap();
+        assert_eq!(read_block(&archive, &h).await.unwrap(), b"block");
+        assert!(archive.block_exists(&h).await.unwrap());
+        let unknown =
+            BlockHash::from_hex("0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap();
+        assert!(matches!(
+            archive.block_header(&unknown).await,
+            Err(Error::BlockNotFound(_))
+        ));
+        assert!(!archive.block_exists(&unknown).await.unwrap());
+        break_replica(&dirs[0]);
+        assert!(matches!(
+            archive.block_header(&unknown).await,
+            Err(Error::IoError(_))
+        ));
+        assert!(archive.block_exists(&unknown).await.is_err());
+    }
+
+    // A write that some replicas miss is reported, and can be retried once they are repaired.
+    #[tokio::test]
+    async fn test_partial_write() {
+        let root = tempdir().unwrap();
+        let dirs = replica_dirs(root.path(), 2);
+        let (a, b) = (&dirs[0], &dirs[1]);
+        let archive = open(&dirs, WriteMode::All).await;
+        let h = hash();
+        break_replica(b);
+        match archive.store_block(&h, &mut reader(b"block")).await {
+            Err(Error::PartialWrite(p)) => {
+                assert_eq!(p.key, h.to_string());
+                assert_eq!(p.stored, vec![a.display().to_string()]);
+                assert_eq!(p.failed.len(), 1);
+                assert_eq!(p.failed[0].0, b.display().to_string());
+            }
+            r => panic!("expected a partial write, got {:?}", r),
+        }
+        assert_eq!(archive.take_partial_writes().len(), 1);
+        assert!(archive.take_partial_writes().is_empty());
+        assert_eq!(archive.status()[1].missed_writes, 1);
+        repair_replica(b);
+        archive
+            .store_block(&h, &mut reader(b"block"))
+            .await
+            .unwrap();
+        assert!(archive.take_partial_writes().is_empty());
+        // both replicas have it now
+        assert!(matches!(
+            archive.store_block(&h, &mut reader(b"block")).await,
+            Err(Error::BlockExists)
+        ));
+    }
+
+    // In quorum mode a write succeeds when a majority of the replicas store it.
+    #[tokio::test]
+    async fn test_quorum_write() {
+        let root = tempdir().unwrap();
+        let dirs = replica_dirs(root.path(), 3);
+        let archive = open(&dirs, WriteMode::Quorum).await;
+        let h = hash();
+        break_replica(&dirs[2]);
+        archive
+            .store_block(&h, &mut reader(b"block"))
+            .await
+            .unwrap();
+        let partial = archive.take_partial_writes();
+        assert_eq!(partial.len(), 1);
+        assert_eq!(partial[0].stored.len(), 2);
+        break_replica(&dirs[1]);
+        let h2 =
+            BlockHash::from_hex("0000000000000000000000000000000000000000000000000000000000000001")
+                .unwrap();
+        assert!(matches!(
+            archive.store_block(&h2, &mut reader(b"block")).await,
+            Err(Error::PartialWrite(_))
+        ));
+    }
+}
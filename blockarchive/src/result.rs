@@ -1,13 +1,47 @@
+use crate::PartialWrite;
+use bitcoinsv::bitcoin::BlockHash;
+
 /// Standard Result used in the library
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Standard error type used in the library
 #[derive(Debug)]
 pub enum Error {
-    /// The block was not found in the archive.
-    BlockNotFound,
+    /// The block with the hash was not found in the archive.
+    BlockNotFound(BlockHash),
     /// The block already exists in the archive. This error may be returned by [BlockArchive::store_block].
     BlockExists,
+    /// The block is larger than the limit, in bytes, of a block that is held in memory, see
+    /// [BlockArchive::store_block_bytes](crate::BlockArchive::store_block_bytes).
+    BlockTooLarge(u64),
+    /// The block is write protected and can not be removed, see
+    /// [SimpleFileBasedBlockArchive](crate::SimpleFileBasedBlockArchive).
+    BlockProtected,
+    /// The layout of the archive does not match the configured layout.
+    LayoutMismatch,
+    /// The artifact was not found in the archive.
+    ArtifactNotFound,
+    /// The artifact already exists in the archive. This error may be returned by [BlockArchive::store_artifact].
+    ArtifactExists,
+    /// The artifact, or its kind or identifier, is not valid.
+    InvalidArtifact(String),
+    /// A background task of the archive failed or was cancelled.
+    TaskFailed(String),
+    /// A suppression file could not be parsed, see [Suppressions::parse](crate::Suppressions::parse).
+    InvalidSuppression(String),
+    /// A replica of the archive did not respond in time, see
+    /// [ReplicatedBlockArchive](crate::ReplicatedBlockArchive).
+    Timeout,
+    /// A write was stored by some replicas of the archive but fewer than required, see
+    /// [ReplicatedBlockArchive](crate::ReplicatedBlockArchive).
+    PartialWrite(PartialWrite),
+    /// The index of a transaction is not less than the number of transactions in the block, which
+    /// is the second value, see [BlockArchive::get_tx_by_index](crate::BlockArchive::get_tx_by_index).
+    TxIndexOutOfRange(u32, u64),
+    /// A block that was stored by resuming its temporary file does not match the merkle root of
+    /// its header, the temporary file has been removed, see
+    /// [SimpleFileBasedBlockArchive::store_block_resumable](crate::SimpleFileBasedBlockArchive::store_block_resumable).
+    ResumeMismatch(BlockHash),
     IoError(std::io::Error),
     BitcoinSVError(bitcoinsv::BsvError),
 }
@@ -15,8 +49,32 @@ pub enum Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Error::BlockNotFound => write!(f, "Block not found"),
+            Error::BlockNotFound(h) => write!(f, "Block not found: {}", h),
             Error::BlockExists => write!(f, "Block exists"),
+            Error::BlockTooLarge(limit) => write!(
+                f,
+                "Block is larger than {} bytes, store it from a reader",
+                limit
+            ),
+            Error::BlockProtected => write!(f, "Block is write protected"),
+            Error::LayoutMismatch => write!(f, "Archive layout does not match configuration"),
+            Error::ArtifactNotFound => write!(f, "Artifact not found"),
+            Error::ArtifactExists => write!(f, "Artifact exists"),
+            Error::InvalidArtifact(s) => write!(f, "Invalid artifact: {}", s),
+            Error::TaskFailed(s) => write!(f, "Background task failed: {}", s),
+            Error::InvalidSuppression(s) => write!(f, "Invalid suppression: {}", s),
+            Error::Timeout => write!(f, "Timed out"),
+            Error::PartialWrite(p) => write!(f, "Partial write: {}", p),
+            Error::TxIndexOutOfRange(index, num_tx) => write!(
+                f,
+                "Transaction index {} is out of range, the block has {} transactions",
+                index, num_tx
+            ),
+            Error::ResumeMismatch(h) => write!(
+                f,
+                "Resumed block does not match its merkle root, it must be stored again: {}",
+                h
+            ),
             Error::IoError(err) => write!(f, "IO error: {}", err),
             Error::BitcoinSVError(err) => write!(f, "Bitcoin SV error: {}", err),
         }
@@ -1,15 +1,25 @@
-use crate::block_archive::{BlockHashListStream, BlockHashListStreamFromChannel};
-use crate::{BlockArchive, Error, Result};
+use crate::artifact::check_name;
+use crate::block_archive::{BlockHashListStream, BlockHashListStreamFromChannel, WalkSummary};
+use crate::open_files::{LimitedFile, OpenFileLimit};
+use crate::protect::{is_protected, protect};
+use crate::{
+    compute_merkle_root, genesis_block, ArchiveBlockHash, ArchiveBlockHeader, ArtifactId,
+    ArtifactKind, ArtifactRegistry, BlockArchive, Error, Partition, Result, ResumableRead,
+};
 use async_trait::async_trait;
-use bitcoinsv::bitcoin::{AsyncEncodable, BlockHash, BlockHeader};
-use bsvdb_base::BlockArchiveConfig;
-use hex::{FromHex, ToHex};
-use std::path::PathBuf;
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId, FullBlockStream};
+use bsvdb_base::{BlockArchiveConfig, VersionRecord};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use tokio::fs::File;
-use tokio::io::AsyncRead;
+use std::time::SystemTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::OnceCell;
 use tokio_stream::wrappers::ReadDirStream;
 use tokio_stream::StreamExt;
+use tracing::{field, instrument, Span};
 
 // the absolute maximum number of blocks that will be stored
 // this is used to limit the size of the channel used to send block hashes
@@ -17,6 +27,47 @@ use tokio_stream::StreamExt;
 // if this is too small, the background process will wait for the channel to be read
 const MAX_BLOCKS: usize = 2_000_000;
 
+// the name of the file in the root directory that records the layout of the archive
+const LAYOUT_FILE: &str = ".layout";
+const LAYOUT_FLAT: &str = "flat";
+const LAYOUT_FANOUT: &str = "fanout";
+
+// the name of the directory in the root directory that contains the artifacts
+const ARTIFACTS_DIR: &str = "artifacts";
+
+// the name of the directory in the root directory that contains the state of the writers
+const STATE_DIR: &str = "state";
+
+// the name of the index file in the root directory, and its first line, see use_index
+const INDEX_FILE: &str = "archive.idx";
+const INDEX_HEADER: &str = "bsvdb-archive-index 1";
+// the file that a new index is written to before it replaces the index
+const INDEX_TMP_FILE: &str = "archive.idx.tmp";
+
+// the suffix of the file that a block is written to before it replaces the block file
+const BLOCK_TMP_SUFFIX: &str = ".tmp";
+
+/// The use of space by a [SimpleFileBasedBlockArchive], see
+/// [SimpleFileBasedBlockArchive::fragmentation_report()].
+///
+/// Each block is stored in its own file, so there is no dead space within the files. The space
+/// that can be reclaimed is in the files that are never read and the empty directories.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FragReport {
+    /// The number of block files.
+    pub blocks: u64,
+    /// The total size of the block files.
+    pub block_bytes: u64,
+    /// The number of other files in the block directories, such as temporary files, which are
+    /// never read.
+    pub other_files: u64,
+    /// The total size of the other files.
+    pub other_bytes: u64,
+    /// The block directories that contain no files, including in their sub-directories. A
+    /// directory is listed before its parent.
+    pub empty_dirs: Vec<PathBuf>,
+}
+
 /// A simple file-based block archive.
 ///
 /// Blocks are stored in a directory structure based on the block hash. The first level of directories
@@ -27,6 +78,15 @@ const MAX_BLOCKS: usize = 2_000_000;
 ///
 /// Example: /31/c5/00000000000000000124a294b9e1e65224f0636ffd4dadac777bed5e709dc531.bin
 ///
+/// If the archive is configured as flat, then blocks are stored directly in the root directory with
+/// no sub-directories. This is simpler for small archives, such as regtest or test fixtures.
+///
+/// Example: /00000000000000000124a294b9e1e65224f0636ffd4dadac777bed5e709dc531.bin
+///
+/// The layout is recorded in a file in the root directory when a new archive is created and an
+/// archive can not be opened with a different layout. Archives without this file are assumed to
+/// use the two level directory structure.
+///
 /// This is simplistic to get started. It is not efficient for large numbers of small blocks.
 ///
 /// Example code:
@@ -35,10 +95,60 @@ const MAX_BLOCKS: usize = 2_000_000;
 ///
 /// Note that if block files are stored in the wrong location then they are not recognised by the
 /// archive.
+///
+/// Artifacts are stored in a sub-directory for each kind in the "artifacts" directory, in a file
+/// named after the artifact identifier. This directory is never scanned for blocks.
+///
+/// Example: /artifacts/template/candidate-1
+///
+/// The writers of the archive keep their state, such as the [IntentLog](crate::IntentLog), in the
+/// "state" directory. This directory is also never scanned for blocks.
+///
+/// If genesis is set, then the genesis block of that chain is returned by the block functions
+/// when it is not stored, see [genesis_block()](crate::genesis_block). It is not returned by
+/// block_list(), which only lists the stored blocks.
+///
+/// If immutable is set, then stored blocks are made read-only. On Linux they are also made
+/// immutable if immutable_flag is set and the process has the CAP_LINUX_IMMUTABLE capability,
+/// otherwise a note is logged and only the permissions are changed. An immutable block can only
+/// be changed or deleted after the flag is cleared with "chattr -i".
+///
+/// A block is written to a temporary file next to the block file, with a ".tmp" suffix, which is
+/// renamed to the block file once the block is complete, so that a block file is never partial.
+/// An interrupted store leaves the temporary file, which is replaced by the next store of the
+/// block or is resumed by [store_block_resumable()](Self::store_block_resumable).
+///
+/// If use_index is set, then block_list() reads the hashes from an index file, "archive.idx" in
+/// the root directory, instead of walking the directories. The index has a line with the hash of
+/// each stored block, and a line with the hash prefixed by "-" for each removed block, which are
+/// appended by store_block() and remove_block(). It is not used when a block directory has been
+/// modified after it, by a writer that does not keep the index, and the directories are walked
+/// instead and the index is rebuilt. A writer that does not keep the index is only detected until
+/// the index is next appended to, run "ba index rebuild" after using one.
 #[derive(Debug)]
 pub struct SimpleFileBasedBlockArchive {
     /// The root of the file store
     pub root_path: PathBuf,
+    /// Whether blocks are stored directly in the root directory.
+    pub flat: bool,
+    /// The known kinds of artifacts, used to validate artifacts before they are stored.
+    pub artifact_kinds: ArtifactRegistry,
+    /// The chain whose genesis block is returned when it is not stored.
+    pub genesis: Option<BlockchainId>,
+    /// Whether stored blocks are write protected.
+    pub immutable: bool,
+    /// Whether the write protection includes the immutable flag on Linux, as well as the
+    /// permissions. Defaults to true.
+    pub immutable_flag: bool,
+    /// Whether the index file is kept and read by block_list().
+    pub use_index: bool,
+    /// The size of the largest block that is stored by store_block_bytes().
+    pub max_buffered_bytes: u64,
+    /// The limit on the number of files that are open at once, which is shared by the replicas
+    /// of a [ReplicatedBlockArchive](crate::ReplicatedBlockArchive).
+    pub open_files: OpenFileLimit,
+    // set once the version of the library has been recorded, see versions()
+    version_recorded: OnceCell<()>,
 }
 
 impl SimpleFileBasedBlockArchive {
@@ -47,101 +157,719 @@ impl SimpleFileBasedBlockArchive {
         let root_path = PathBuf::from(config.root_path.clone());
         // Check if the root_path is accessible
         match tokio::fs::metadata(&root_path).await {
-            Ok(_) => Ok(SimpleFileBasedBlockArchive { root_path }),
+            Ok(_) => {
+                Self::check_layout(&root_path, config.flat).await?;
+                Ok(SimpleFileBasedBlockArchive {
+                    root_path,
+                    flat: config.flat,
+                    artifact_kinds: ArtifactRegistry::default(),
+                    genesis: None,
+                    immutable: config.immutable_blocks,
+                    immutable_flag: true,
+                    use_index: config.use_index,
+                    max_buffered_bytes: config.max_buffered_block_bytes,
+                    open_files: OpenFileLimit::new(config.max_open_files),
+                    version_recorded: OnceCell::new(),
+                })
+            }
             Err(e) => {
                 Err(e.into()) // Convert the error into your custom error type
             }
         }
     }
 
-    // Get the path for a block.
-    fn get_path_from_hash(&self, hash: &BlockHash) -> PathBuf {
-        let mut path = self.root_path.clone();
-        let s: String = hash.encode_hex();
-        path.push(&s[62..]);
-        path.push(&s[60..62]);
+    // Check that the layout recorded in the archive matches the configured layout, recording the
+    // layout if the archive is new.
+    async fn check_layout(root_path: &Path, flat: bool) -> Result<()> {
+        let expected = if flat { LAYOUT_FLAT } else { LAYOUT_FANOUT };
+        let layout_path = root_path.join(LAYOUT_FILE);
+        match tokio::fs::read_to_string(&layout_path).await {
+            Ok(l) => {
+                if l.trim() == expected {
+                    Ok(())
+                } else {
+                    Err(Error::LayoutMismatch)
+                }
+            }
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    let mut dir = tokio::fs::read_dir(root_path).await?;
+                    if dir.next_entry().await?.is_none() {
+                        // new archive, record the layout
+                        tokio::fs::write(&layout_path, expected).await?;
+                        Ok(())
+                    } else if flat {
+                        // existing archives without a layout file are not flat
+                        Err(Error::LayoutMismatch)
+                    } else {
+                        Ok(())
+                    }
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// Check whether a stored block is write protected, see [SimpleFileBasedBlockArchive].
+    ///
+    /// Only the permissions are checked. Returns Error::BlockNotFound if the block is not stored.
+    pub async fn block_protected(&self, block_hash: &BlockHash) -> Result<bool> {
+        match is_protected(&self.get_path_from_hash(block_hash)).await {
+            Ok(p) => Ok(p),
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => Err(Error::BlockNotFound(*block_hash)),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// The path of the file in which a block is stored, whether or not it is stored.
+    pub fn block_path(&self, block_hash: &BlockHash) -> PathBuf {
+        self.get_path_from_hash(block_hash)
+    }
+
+    /// The directory in which the writers of the archive keep their state, whether or not it has
+    /// been created.
+    pub fn state_path(&self) -> PathBuf {
+        self.root_path.join(STATE_DIR)
+    }
+
+    /// Remove a block from the archive, returning the size of the block.
+    ///
+    /// Returns Error::BlockProtected if the block is write protected, and Error::BlockNotFound if
+    /// it is not stored. The directory of the block is left in place, see
+    /// [compact()](Self::compact).
+    #[instrument(level = "debug", skip(self), fields(block_hash = %block_hash, path))]
+    pub async fn remove_block(&self, block_hash: &BlockHash) -> Result<u64> {
+        let path = self.get_path_from_hash(block_hash);
+        record_path(&path);
+        if self.block_protected(block_hash).await? {
+            return Err(Error::BlockProtected);
+        }
+        let size = tokio::fs::metadata(&path).await?.len();
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::BlockNotFound(*block_hash))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.append_index(&format!("-{}", ArchiveBlockHash::from(*block_hash)))
+            .await?;
+        Ok(size)
+    }
+
+    /// Store a block, resuming the temporary file of an earlier store of the block that was
+    /// interrupted, see [SimpleFileBasedBlockArchive]. Returns the offset in the block from which
+    /// it was read, 0 if it was stored from the start.
+    ///
+    /// The temporary file is resumed if:
+    /// * it holds at least the header of the block, and the hash of the header is the block hash.
+    /// * the reader can start at the end of the temporary file, see
+    ///   [ResumableRead::resume_at()].
+    ///
+    /// Otherwise the block is stored from the start, as by store_block(). The reader must give the
+    /// same bytes as the earlier store.
+    ///
+    /// Resuming is only safe after a clean interruption, such as the process stopping or the
+    /// connection to the source failing, where the temporary file holds the bytes that were
+    /// written to it. After a crash of the machine, rather than of the process, the end of the
+    /// temporary file may not have reached the disk, so it should be removed rather than resumed.
+    /// A resumed block is checked before it replaces the block file, by hashing its header and
+    /// computing the merkle root of its transactions, which reads the whole block. If the check
+    /// fails then the temporary file is removed and Error::ResumeMismatch is returned, the block
+    /// must then be stored again from the start.
+    #[instrument(
+        level = "debug",
+        skip(self, block),
+        fields(block_hash = %block_hash, path, bytes)
+    )]
+    pub async fn store_block_resumable(
+        &self,
+        block_hash: &BlockHash,
+        block: &mut dyn ResumableRead,
+    ) -> Result<u64> {
+        if self.block_exists(block_hash).await? {
+            return Err(Error::BlockExists);
+        }
+        let path = self.get_path_from_hash(block_hash);
+        record_path(&path);
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        let tmp = Self::get_tmp_path(&path);
+        let offset = match self.partial_len(&tmp, block_hash).await? {
+            Some(len) if block.resume_at(len).await? => len,
+            _ => 0,
+        };
+        let mut file = if offset > 0 {
+            self.open_files
+                .open_with(OpenOptions::new().append(true), &tmp)
+                .await?
+        } else {
+            self.open_files.create_file(&tmp).await?
+        };
+        let bytes = tokio::io::copy(block, &mut file.file).await?;
+        Span::current().record("bytes", offset + bytes);
+        if offset > 0 {
+            // the check reads through a second handle of the file, which does not need another
+            // permit from the limit of open files
+            file.file.flush().await?;
+            let reader = file.file.try_clone().await?;
+            if !Self::check_resumed(reader, block_hash).await? {
+                drop(file);
+                tokio::fs::remove_file(&tmp).await?;
+                return Err(Error::ResumeMismatch(*block_hash));
+            }
+        }
+        self.finish_block(file, &tmp, &path, block_hash).await?;
+        Ok(offset)
+    }
+
+    /// List the blocks in a partition of the archive, see [Partition].
+    ///
+    /// This is like block_list(), but only the directories of the partition are walked. The index
+    /// is read if it is used and up to date, but it is not rebuilt.
+    pub async fn block_list_partition(
+        &self,
+        partition: Partition,
+    ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(MAX_BLOCKS);
+        if self.use_index {
+            if let Some(hashes) = Self::read_index(&self.root_path, self.flat).await? {
+                let handle = tokio::spawn(async move {
+                    for h in hashes.into_iter().filter(|h| partition.contains(h)) {
+                        if tx.send(Ok(h)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(WalkSummary {
+                        from_index: true,
+                        ..WalkSummary::default()
+                    })
+                });
+                return Ok(Box::pin(BlockHashListStreamFromChannel::new(rx, handle)));
+            }
+        }
+        let handle = tokio::spawn(Self::block_list_bgrnd(
+            self.root_path.clone(),
+            self.flat,
+            Some(partition),
+            false,
+            tx,
+        ));
+        Ok(Box::pin(BlockHashListStreamFromChannel::new(rx, handle)))
+    }
+
+    /// Rebuild the index file from a walk of the directories, returning the number of blocks.
+    ///
+    /// The index is written whether or not use_index is set.
+    pub async fn rebuild_index(&self) -> Result<u64> {
+        // the hashes are collected as they are walked, nothing is sent to the closed channel
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        rx.close();
+        let mut hashes = Vec::new();
+        Self::walk(
+            self.root_path.clone(),
+            self.flat,
+            None,
+            &tx,
+            Some(&mut hashes),
+        )
+        .await?;
+        Self::write_index(&self.root_path, &hashes).await?;
+        Ok(hashes.len() as u64)
+    }
+
+    // Write the index file of the hashes.
+    //
+    // The index is written to a temporary file which replaces it. That modifies the root
+    // directory, so the index is given the modification time of the root directory if it is
+    // later, otherwise it would be considered out of date.
+    async fn write_index(root_path: &Path, hashes: &[BlockHash]) -> Result<()> {
+        let mut text = String::with_capacity((hashes.len() + 1) * 65);
+        text.push_str(INDEX_HEADER);
+        text.push('\n');
+        for h in hashes {
+            text.push_str(&ArchiveBlockHash::from(*h).to_string());
+            text.push('\n');
+        }
+        let tmp = root_path.join(INDEX_TMP_FILE);
+        let path = root_path.join(INDEX_FILE);
+        tokio::fs::write(&tmp, text).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        let root_modified = tokio::fs::metadata(root_path).await?.modified()?;
+        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        if file.metadata()?.modified()? < root_modified {
+            file.set_modified(root_modified)?;
+        }
+        Ok(())
+    }
+
+    // Append a line to the index file, if the index is used and has been created.
+    async fn append_index(&self, line: &str) -> Result<()> {
+        if !self.use_index {
+            return Ok(());
+        }
+        let open = self
+            .open_files
+            .open_with(
+                OpenOptions::new().append(true),
+                self.root_path.join(INDEX_FILE),
+            )
+            .await;
+        match open {
+            Ok(mut f) => {
+                // a single write, so that concurrent appends are not interleaved
+                f.file.write_all(format!("{}\n", line).as_bytes()).await?;
+                f.file.flush().await?;
+                Ok(())
+            }
+            // it is created by the next block_list()
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Read the hashes from the index file, in the order in which they were added. Returns None if
+    // there is no index, it is not valid, or a block directory has been modified after it.
+    async fn read_index(root_path: &Path, flat: bool) -> Result<Option<Vec<BlockHash>>> {
+        let path = root_path.join(INDEX_FILE);
+        let modified = match tokio::fs::metadata(&path).await {
+            Ok(m) => m.modified()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if Self::modified_after(root_path, flat, modified).await? {
+            return Ok(None);
+        }
+        let text = tokio::fs::read_to_string(&path).await?;
+        let mut lines = text.lines();
+        if lines.next() != Some(INDEX_HEADER) {
+            return Ok(None);
+        }
+        // the position of each hash that is present
+        let mut present = HashMap::new();
+        for (i, line) in lines.enumerate() {
+            let (removed, hex) = match line.strip_prefix('-') {
+                Some(h) => (true, h),
+                None => (false, line),
+            };
+            let Some(h) = ArchiveBlockHash::from_hex(hex).map(BlockHash::from) else {
+                return Ok(None);
+            };
+            if removed {
+                present.remove(&h);
+            } else {
+                present.entry(h).or_insert(i);
+            }
+        }
+        let mut hashes: Vec<(BlockHash, usize)> = present.into_iter().collect();
+        hashes.sort_by_key(|(_, i)| *i);
+        Ok(Some(hashes.into_iter().map(|(h, _)| h).collect()))
+    }
+
+    // Whether the root directory or a block directory has been modified after the time.
+    async fn modified_after(root_path: &Path, flat: bool, time: SystemTime) -> Result<bool> {
+        if tokio::fs::metadata(root_path).await?.modified()? > time {
+            return Ok(true);
+        }
+        if flat {
+            return Ok(false);
+        }
+        let skip = [root_path.join(ARTIFACTS_DIR), root_path.join(STATE_DIR)];
+        let mut stack = vec![(root_path.to_path_buf(), 0)];
+        while let Some((dir, depth)) = stack.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if skip.contains(&path) || !entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                if entry.metadata().await?.modified()? > time {
+                    return Ok(true);
+                }
+                // the blocks are in the second level of directories
+                if depth == 0 {
+                    stack.push((path, 1));
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Report the use of space by the archive, see [FragReport].
+    ///
+    /// The artifacts and the state directory are not included.
+    pub async fn fragmentation_report(&self) -> Result<FragReport> {
+        let mut report = FragReport::default();
+        let skip = [
+            self.root_path.join(ARTIFACTS_DIR),
+            self.root_path.join(STATE_DIR),
+            self.root_path.join(LAYOUT_FILE),
+            self.root_path.join(INDEX_FILE),
+        ];
+        Self::scan_dir(&self.root_path, &skip, &mut report).await?;
+        Ok(report)
+    }
+
+    /// Remove the empty block directories, returning the report from before they were removed.
+    ///
+    /// This should not be run while blocks are being stored, a block can not be stored if its
+    /// directory is removed after it was created.
+    pub async fn compact(&self) -> Result<FragReport> {
+        let report = self.fragmentation_report().await?;
+        for dir in report.empty_dirs.iter() {
+            match tokio::fs::remove_dir(dir).await {
+                Ok(()) => {}
+                // a block has been stored in it since it was scanned
+                Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(report)
+    }
+
+    // Add the files in the directory and its sub-directories to the report, except for the skipped
+    // paths, returning whether it contains no files.
+    fn scan_dir<'a>(
+        dir: &'a Path,
+        skip: &'a [PathBuf],
+        report: &'a mut FragReport,
+    ) -> Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            let mut empty = true;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if skip.contains(&path) {
+                    empty = false;
+                } else if entry.file_type().await?.is_dir() {
+                    if Self::scan_dir(&path, skip, report).await? {
+                        report.empty_dirs.push(path);
+                    } else {
+                        empty = false;
+                    }
+                } else {
+                    empty = false;
+                    let len = entry.metadata().await?.len();
+                    if path.extension().is_some_and(|e| e == "bin") {
+                        report.blocks += 1;
+                        report.block_bytes += len;
+                    } else {
+                        report.other_files += 1;
+                        report.other_bytes += len;
+                    }
+                }
+            }
+            Ok(empty)
+        })
+    }
+
+    // Get the genesis block if it is the requested block and the fallback is enabled.
+    fn genesis_fallback(&self, block_hash: &BlockHash) -> Option<Vec<u8>> {
+        self.genesis
+            .filter(|chain| BlockHeader::get_genesis(*chain).hash() == *block_hash)
+            .map(genesis_block)
+    }
+
+    // Get the path for a block, given the hex encoded hash.
+    fn get_path_from_hex(root_path: &Path, flat: bool, s: &str) -> PathBuf {
+        let mut path = root_path.to_path_buf();
+        if !flat {
+            path.push(&s[62..]);
+            path.push(&s[60..62]);
+        }
         path.push(s);
         path.set_extension("bin");
         path
     }
 
+    // Get the path for a block.
+    fn get_path_from_hash(&self, hash: &BlockHash) -> PathBuf {
+        let s = ArchiveBlockHash::from(*hash).to_string();
+        Self::get_path_from_hex(&self.root_path, self.flat, &s)
+    }
+
+    // Get the path of the temporary file that a block is written to.
+    fn get_tmp_path(path: &Path) -> PathBuf {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(BLOCK_TMP_SUFFIX);
+        PathBuf::from(tmp)
+    }
+
+    // The length of the temporary file of a block, if it starts with the header of the block.
+    async fn partial_len(&self, tmp: &Path, block_hash: &BlockHash) -> Result<Option<u64>> {
+        let mut file = match self.open_files.open_file(tmp).await {
+            Ok(f) => f.file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let len = file.metadata().await?.len();
+        match ArchiveBlockHeader::read(&mut file).await {
+            Ok(h) if h.hash() == ArchiveBlockHash::from(*block_hash) => Ok(Some(len)),
+            _ => Ok(None),
+        }
+    }
+
+    // Check a block that was completed from a temporary file, its header must hash to the block
+    // hash and the merkle root of its transactions must match the header. A block that can not be
+    // decoded fails the check.
+    async fn check_resumed(mut file: File, block_hash: &BlockHash) -> Result<bool> {
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        let reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(file);
+        let block = match FullBlockStream::new(reader).await {
+            Ok(b) => b,
+            Err(_) => return Ok(false),
+        };
+        if block.block_header.hash() != *block_hash {
+            return Ok(false);
+        }
+        let header_root = block.block_header.merkle_root;
+        Ok(matches!(compute_merkle_root(block).await, Ok(root) if root == header_root))
+    }
+
+    // Replace the block file with the completed temporary file, then protect and index it.
+    async fn finish_block(
+        &self,
+        mut file: LimitedFile,
+        tmp: &Path,
+        path: &Path,
+        block_hash: &BlockHash,
+    ) -> Result<()> {
+        // the writes must be finished, and on disk, before the file is renamed, which also closes
+        // it, otherwise a crash could leave a partial block file under the name of the block
+        file.file.flush().await?;
+        file.file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(tmp, path).await?;
+        if self.immutable {
+            protect(path, self.immutable_flag).await?;
+        }
+        self.append_index(&ArchiveBlockHash::from(*block_hash).to_string())
+            .await?;
+        // the first block stored by the handle records its version, a failure does not fail the
+        // store and is tried again by the next block
+        let _ = self
+            .version_recorded
+            .get_or_try_init(|| async {
+                let writer = Self::library_version(VersionRecord::now());
+                match self.record_version(writer).await {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        tracing::warn!("failed recording the version of the archive: {}", e);
+                        Err(e)
+                    }
+                }
+            })
+            .await;
+        Ok(())
+    }
+
+    // Get the directory for a kind of artifact.
+    fn get_artifact_dir(&self, kind: &ArtifactKind) -> Result<PathBuf> {
+        // the name of an Other kind has not been checked
+        check_name(kind.name())?;
+        Ok(self.root_path.join(ARTIFACTS_DIR).join(kind.name()))
+    }
+
     // Get a list of all blocks in the background, sending results to the channel.
+    //
+    // An error is sent to the channel, so that the consumer sees it straight away, and also
+    // returned as the result of the task.
+    //
+    // If rebuild_index is set, then the walk is finished even if the receiver is dropped, and the
+    // index is written from the hashes. If partition is given, then only the blocks in it are
+    // listed, and the index is never written.
+    async fn block_list_bgrnd(
+        root_path: PathBuf,
+        flat: bool,
+        partition: Option<Partition>,
+        rebuild_index: bool,
+        transmit: tokio::sync::mpsc::Sender<Result<BlockHash>>,
+    ) -> Result<WalkSummary> {
+        let rebuild_index = rebuild_index && partition.is_none();
+        let mut hashes = Vec::new();
+        let collect = if rebuild_index {
+            Some(&mut hashes)
+        } else {
+            None
+        };
+        let r = Self::walk(root_path.clone(), flat, partition, &transmit, collect).await;
+        if rebuild_index && r.is_ok() {
+            // the listing has succeeded, an index that could not be written is rebuilt next time
+            if let Err(e) = Self::write_index(&root_path, &hashes).await {
+                tracing::warn!(
+                    "failed to write the index of {}: {}",
+                    root_path.display(),
+                    e
+                );
+            }
+        }
+        match r {
+            Err(Error::IoError(e)) => {
+                let eager = std::io::Error::new(e.kind(), e.to_string());
+                // the receiver may have been dropped, the error is still returned
+                let _ = transmit.send(Err(Error::IoError(eager))).await;
+                Err(Error::IoError(e))
+            }
+            r => r,
+        }
+    }
+
+    // Whether a block directory can hold the blocks of the partition. Only the directories that
+    // are named after the first and second bytes of a hash can hold blocks.
+    fn dir_in_partition(root_path: &Path, dir: &Path, partition: Partition) -> bool {
+        let Ok(relative) = dir.strip_prefix(root_path) else {
+            return false;
+        };
+        let bytes: Vec<Option<u32>> = relative
+            .iter()
+            .map(|c| {
+                c.to_str()
+                    .filter(|n| n.len() == 2)
+                    .and_then(|n| u32::from_str_radix(n, 16).ok())
+            })
+            .collect();
+        match bytes[..] {
+            [Some(a)] => partition.overlaps(a << 8, a << 8 | 0xff),
+            [Some(a), Some(b)] => partition.prefixes().contains(&(a << 8 | b)),
+            _ => false,
+        }
+    }
+
+    // Walk the archive, sending the hashes of the blocks to the channel, and adding them to
+    // collect if it is given. The walk stops when the receiver is dropped, unless the hashes are
+    // collected. If partition is given, then the directories of the other partitions are not
+    // walked.
     // Do not return blocks that are stored in the wrong location because these
     // won't be retrievable by get_block().
-    async fn block_list_bgrnd(
+    async fn walk(
         root_path: PathBuf,
-        transmit: tokio::sync::mpsc::Sender<BlockHash>,
-    ) -> Result<()> {
+        flat: bool,
+        partition: Option<Partition>,
+        transmit: &tokio::sync::mpsc::Sender<Result<BlockHash>>,
+        mut collect: Option<&mut Vec<BlockHash>>,
+    ) -> Result<WalkSummary> {
+        let skip = [root_path.join(ARTIFACTS_DIR), root_path.join(STATE_DIR)];
+        let mut summary = WalkSummary::default();
         let mut stack = Vec::new();
         stack.push(root_path.clone());
         while let Some(path) = stack.pop() {
-            let dir = tokio::fs::read_dir(path).await?;
+            // include the directory in the error, the io error does not say which file failed
+            let with_path = |e: std::io::Error| {
+                std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+            };
+            let dir = tokio::fs::read_dir(&path).await.map_err(with_path)?;
+            summary.dirs_visited += 1;
             let mut stream = ReadDirStream::new(dir);
             // it would be fun to spawn a new task for each directory, but that would be a bit daft
             while let Some(entry) = stream.next().await {
-                let entry = entry?;
+                let entry = entry.map_err(with_path)?;
                 let path = entry.path();
                 if path.is_dir() {
-                    stack.push(path);
+                    // flat archives have no sub-directories, artifacts and state are not blocks
+                    if !flat
+                        && !skip.contains(&path)
+                        && partition.is_none_or(|p| Self::dir_in_partition(&root_path, &path, p))
+                    {
+                        stack.push(path);
+                    }
                 } else {
+                    summary.files_seen += 1;
                     // ignore files which are not .bin files
                     if path.extension().is_none() || path.extension().unwrap() != "bin" {
+                        summary.skipped_not_bin += 1;
                         continue;
                     }
                     let f_name = path.file_stem().unwrap().to_str().unwrap();
-                    match BlockHash::from_hex(f_name) {
-                        Ok(h) => {
+                    match ArchiveBlockHash::from_hex(f_name).map(BlockHash::from) {
+                        Some(h) => {
                             // ignore files that are not in the correct location
-                            let correct_path = root_path
-                                .join(&f_name[62..])
-                                .join(&f_name[60..62])
-                                .join(f_name)
-                                .with_extension("bin");
+                            let correct_path = Self::get_path_from_hex(&root_path, flat, f_name);
                             if path != correct_path {
+                                summary.skipped_wrong_location += 1;
+                                summary.misplaced.push(path);
+                                continue;
+                            }
+                            if partition.is_some_and(|p| !p.contains(&h)) {
                                 continue;
                             }
-                            match transmit.send(h).await {
+                            if let Some(hashes) = collect.as_mut() {
+                                hashes.push(h);
+                            }
+                            if transmit.is_closed() && collect.is_some() {
+                                continue;
+                            }
+                            match transmit.send(Ok(h)).await {
                                 Ok(_) => {}
-                                Err(_) => return Ok(()), // this is not an error, the receiver has merely dropped
+                                Err(_) if collect.is_some() => {}
+                                Err(_) => return Ok(summary), // this is not an error, the receiver has merely dropped
                             }
                         }
                         // ignore files which are not valid block hashes
-                        Err(_) => continue,
+                        None => {
+                            summary.skipped_invalid_name += 1;
+                            continue;
+                        }
                     };
                 }
             }
         }
-        Ok(())
+        Ok(summary)
     }
 }
 
+// record the path of the block file in the current span
+fn record_path(path: &Path) {
+    Span::current().record("path", field::display(path.display()));
+}
+
 #[async_trait]
 impl BlockArchive for SimpleFileBasedBlockArchive {
+    #[instrument(level = "debug", skip(self), fields(block_hash = %block_hash, path, bytes))]
     async fn get_block(&self, block_hash: &BlockHash) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
         let path = self.get_path_from_hash(block_hash);
-        match File::open(path).await {
-            Ok(f) => Ok(Box::new(f)),
+        record_path(&path);
+        match self.open_files.open_file(path).await {
+            Ok(f) => {
+                let span = Span::current();
+                if !span.is_disabled() {
+                    if let Ok(m) = f.file.metadata().await {
+                        span.record("bytes", m.len());
+                    }
+                }
+                Ok(Box::new(f))
+            }
             Err(e) => match e.kind() {
                 // if the file does not exist, return a BlockNotFound error
-                std::io::ErrorKind::NotFound => Err(Error::BlockNotFound),
+                std::io::ErrorKind::NotFound => match self.genesis_fallback(block_hash) {
+                    Some(b) => Ok(Box::new(std::io::Cursor::new(b))),
+                    None => Err(Error::BlockNotFound(*block_hash)),
+                },
                 _ => Err(e.into()),
             },
         }
     }
 
     /// Check if a block exists in the archive.
+    #[instrument(level = "debug", skip(self), fields(block_hash = %block_hash, path))]
     async fn block_exists(&self, block_hash: &BlockHash) -> Result<bool> {
         let path = self.get_path_from_hash(block_hash);
+        record_path(&path);
         match tokio::fs::metadata(path).await {
             Ok(_) => Ok(true),
             Err(e) => match e.kind() {
                 // if the file does not exist, return false
-                std::io::ErrorKind::NotFound => Ok(false),
+                std::io::ErrorKind::NotFound => Ok(self.genesis_fallback(block_hash).is_some()),
                 _ => Err(e.into()),
             },
         }
     }
 
+    #[instrument(
+        level = "debug",
+        skip(self, block),
+        fields(block_hash = %block_hash, path, bytes)
+    )]
     async fn store_block(
         &self,
         block_hash: &BlockHash,
@@ -151,33 +879,57 @@ impl BlockArchive for SimpleFileBasedBlockArchive {
             return Err(Error::BlockExists);
         }
         let path = self.get_path_from_hash(block_hash);
+        record_path(&path);
         // create the directory structure if it does not exist
         tokio::fs::create_dir_all(path.parent().unwrap()).await?;
-        // store the block in a file
-        let mut file = File::create(path).await?;
-        tokio::io::copy(block, &mut file).await?;
-        Ok(())
+        // store the block in a temporary file, replacing any left by an interrupted store
+        let tmp = Self::get_tmp_path(&path);
+        let mut file = self.open_files.create_file(&tmp).await?;
+        let bytes = tokio::io::copy(block, &mut file.file).await?;
+        Span::current().record("bytes", bytes);
+        self.finish_block(file, &tmp, &path, block_hash).await
+    }
+
+    fn max_buffered_bytes(&self) -> u64 {
+        self.max_buffered_bytes
     }
 
+    #[instrument(level = "debug", skip(self), fields(block_hash = %block_hash, path, bytes))]
     async fn block_size(&self, block_hash: &BlockHash) -> Result<usize> {
         let path = self.get_path_from_hash(block_hash);
+        record_path(&path);
         match tokio::fs::metadata(path).await {
-            Ok(m) => Ok(m.len() as usize),
+            Ok(m) => {
+                Span::current().record("bytes", m.len());
+                Ok(m.len() as usize)
+            }
             Err(e) => match e.kind() {
                 // if the file does not exist, return a BlockNotFound error
-                std::io::ErrorKind::NotFound => Err(Error::BlockNotFound),
+                std::io::ErrorKind::NotFound => match self.genesis_fallback(block_hash) {
+                    Some(b) => Ok(b.len()),
+                    None => Err(Error::BlockNotFound(*block_hash)),
+                },
                 _ => Err(e.into()),
             },
         }
     }
 
+    #[instrument(level = "debug", skip(self), fields(block_hash = %block_hash, path))]
     async fn block_header(&self, block_hash: &BlockHash) -> Result<BlockHeader> {
         let path = self.get_path_from_hash(block_hash);
-        match File::open(path).await {
-            Ok(mut file) => Ok(BlockHeader::async_from_binary(&mut file).await?),
+        record_path(&path);
+        match self.open_files.open_file(path).await {
+            Ok(mut file) => Ok(BlockHeader::from(
+                &ArchiveBlockHeader::read(&mut file).await?,
+            )),
             Err(e) => match e.kind() {
                 // if the file does not exist, return a BlockNotFound error
-                std::io::ErrorKind::NotFound => Err(Error::BlockNotFound),
+                std::io::ErrorKind::NotFound => match self.genesis {
+                    Some(chain) if BlockHeader::get_genesis(chain).hash() == *block_hash => {
+                        Ok(BlockHeader::get_genesis(chain))
+                    }
+                    _ => Err(Error::BlockNotFound(*block_hash)),
+                },
                 _ => Err(e.into()),
             },
         }
@@ -185,28 +937,125 @@ impl BlockArchive for SimpleFileBasedBlockArchive {
 
     /// Get a list of all the blocks in the archive.
     ///
-    /// It returns a stream of block hashes.
+    /// It returns a stream of block hashes, see [BlockArchive::block_list()].
     ///
     /// Example code:
     ///     let mut results = archive.block_list().await.unwrap();
     ///     while let Some(block_hash) = results.next().await {
-    ///       println!("{}", block_hash);
+    ///       println!("{}", block_hash?);
     ///     }
+    ///     let summary = results.finish().await?;
     ///
     /// This function does not return blocks that are stored in the wrong location because these
-    /// won't be retrievable by get_block().
-    async fn block_list(&mut self) -> Result<Pin<Box<dyn BlockHashListStream<Item = BlockHash>>>> {
+    /// won't be retrievable by get_block(). They are counted in the summary.
+    ///
+    /// If use_index is set, the hashes are read from the index file when it is up to date, and
+    /// otherwise the index is rebuilt from the walk, see [SimpleFileBasedBlockArchive].
+    async fn block_list(
+        &mut self,
+    ) -> Result<Pin<Box<dyn BlockHashListStream<Item = Result<BlockHash>>>>> {
         // make the channel large enough to buffer all hashes, including testnet
         // so that the background task can collect all buffer hashes despite how slow the consumer is
         let (tx, rx) = tokio::sync::mpsc::channel(MAX_BLOCKS);
-        let handle = tokio::spawn(Self::block_list_bgrnd(self.root_path.clone(), tx));
+        if self.use_index {
+            if let Some(hashes) = Self::read_index(&self.root_path, self.flat).await? {
+                let handle = tokio::spawn(async move {
+                    for h in hashes {
+                        if tx.send(Ok(h)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(WalkSummary {
+                        from_index: true,
+                        ..WalkSummary::default()
+                    })
+                });
+                return Ok(Box::pin(BlockHashListStreamFromChannel::new(rx, handle)));
+            }
+        }
+        let handle = tokio::spawn(Self::block_list_bgrnd(
+            self.root_path.clone(),
+            self.flat,
+            None,
+            self.use_index,
+            tx,
+        ));
         Ok(Box::pin(BlockHashListStreamFromChannel::new(rx, handle)))
     }
+
+    #[instrument(level = "debug", skip(self, artifact), fields(kind = %kind, id = %id, path, bytes))]
+    async fn store_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+        artifact: &mut Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Result<()> {
+        let path = self.get_artifact_dir(kind)?.join(id.as_str());
+        record_path(&path);
+        if tokio::fs::try_exists(&path).await? {
+            return Err(Error::ArtifactExists);
+        }
+        // the artifact is validated before anything is written
+        let mut buf = Vec::new();
+        artifact.read_to_end(&mut buf).await?;
+        self.artifact_kinds.validate(kind, &buf)?;
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        tokio::fs::write(&path, &buf).await?;
+        Span::current().record("bytes", buf.len());
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self), fields(kind = %kind, id = %id, path))]
+    async fn get_artifact(
+        &self,
+        kind: &ArtifactKind,
+        id: &ArtifactId,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        let path = self.get_artifact_dir(kind)?.join(id.as_str());
+        record_path(&path);
+        match self.open_files.open_file(path).await {
+            Ok(f) => Ok(Box::new(f)),
+            Err(e) => match e.kind() {
+                // if the file does not exist, return an ArtifactNotFound error
+                std::io::ErrorKind::NotFound => Err(Error::ArtifactNotFound),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    async fn list_artifacts(&self, kind: &ArtifactKind) -> Result<Vec<ArtifactId>> {
+        let dir = self.get_artifact_dir(kind)?;
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(d) => d,
+            Err(e) => match e.kind() {
+                // no artifacts of this kind have been stored
+                std::io::ErrorKind::NotFound => return Ok(vec![]),
+                _ => return Err(e.into()),
+            },
+        };
+        let mut ids = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            // ignore files which are not valid identifiers, such as temporary files
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| ArtifactId::new(n).ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoinsv::bitcoin::{AsyncEncodable, Tx};
     use hex::FromHex;
     use std::io::Cursor;
     use tempfile::tempdir;
@@ -216,6 +1065,13 @@ mod tests {
         BlockArchiveConfig {
             enabled: true,
             root_path: String::from("../testdata/blockarchive"),
+            flat: false,
+            immutable_blocks: false,
+            replicas: vec![],
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: 1 << 30,
+            max_open_files: 0,
         }
     }
 
@@ -239,10 +1095,48 @@ mod tests {
         let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
         let mut results = archive.block_list().await.unwrap();
         let mut count = 0;
-        while let Some(_) = results.next().await {
+        while let Some(h) = results.next().await {
+            h.unwrap();
             count += 1;
         }
         assert_eq!(count, 3);
+        let summary = results.finish().await.unwrap();
+        assert_eq!(summary.files_seen, 7);
+        assert_eq!(summary.skipped_wrong_location, 2);
+        assert_eq!(summary.misplaced.len(), 2);
+        assert_eq!(summary.skipped_invalid_name, 1);
+        assert_eq!(summary.skipped_not_bin, 1);
+    }
+
+    // The partitions list disjoint parts of the blocks, and only walk their own directories.
+    #[tokio::test]
+    async fn test_block_list_partition() {
+        let c = get_testdata_config();
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let mut all = Vec::new();
+        let mut dirs = Vec::new();
+        for k in 1..=3 {
+            let p = Partition::new(k, 3).unwrap();
+            let mut results = archive.block_list_partition(p).await.unwrap();
+            let mut part = Vec::new();
+            while let Some(h) = results.next().await {
+                part.push(h.unwrap());
+            }
+            assert!(part.iter().all(|h| p.contains(h)));
+            all.extend(part);
+            dirs.push(results.finish().await.unwrap().dirs_visited);
+        }
+        // 48/60 is in the first partition and 6f/e2 in the second
+        assert_eq!(dirs, vec![3, 3, 1]);
+        all.sort();
+        let mut expected = Vec::new();
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let mut results = archive.block_list().await.unwrap();
+        while let Some(h) = results.next().await {
+            expected.push(h.unwrap());
+        }
+        expected.sort();
+        assert_eq!(all, expected);
     }
 
     // Test the block list function with no blocks.
@@ -253,14 +1147,78 @@ mod tests {
         let c = BlockArchiveConfig {
             enabled: true,
             root_path: String::from(root.path().to_str().unwrap()),
+            flat: false,
+            immutable_blocks: false,
+            replicas: vec![],
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: 1 << 30,
+            max_open_files: 0,
         };
         let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
         let mut results = archive.block_list().await.unwrap();
         let mut count = 0;
-        while let Some(_) = results.next().await {
+        while results.next().await.is_some() {
             count += 1;
         }
         assert_eq!(count, 0);
+        let summary = results.finish().await.unwrap();
+        assert_eq!(summary.dirs_visited, 1);
+    }
+
+    // An error walking the archive is returned by the stream and by finish().
+    #[tokio::test]
+    async fn test_block_list_walk_error() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("archive");
+        std::fs::create_dir(&path).unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(path.to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        let mut results = archive.block_list().await.unwrap();
+        let r = results.next().await.unwrap();
+        assert!(matches!(r, Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound));
+        assert!(results.next().await.is_none());
+        let r = results.finish().await;
+        assert!(matches!(r, Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    // An unreadable sub-directory is not silently skipped.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_block_list_unreadable_dir() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let locked = root.path().join("ab");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // permissions are not enforced for root, in which case there is nothing to test
+        if std::fs::read_dir(&locked).is_err() {
+            let mut results = archive.block_list().await.unwrap();
+            let mut errors = Vec::new();
+            while let Some(r) = results.next().await {
+                if let Err(e) = r {
+                    errors.push(e);
+                }
+            }
+            assert_eq!(errors.len(), 1);
+            assert!(format!("{}", errors[0]).contains("ab"));
+            let r = results.finish().await;
+            assert!(
+                matches!(r, Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::PermissionDenied)
+            );
+        }
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
     }
 
     // Test the archive with a non-existent root directory.
@@ -269,6 +1227,13 @@ mod tests {
         let c = BlockArchiveConfig {
             enabled: true,
             root_path: String::from("../testdata/nonexistent"),
+            flat: false,
+            immutable_blocks: false,
+            replicas: vec![],
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: 1 << 30,
+            max_open_files: 0,
         };
         let archive = SimpleFileBasedBlockArchive::new(&c).await;
         assert!(archive.is_err());
@@ -288,7 +1253,7 @@ mod tests {
         assert_eq!(buf.len(), 227);
     }
 
-    // Test unknown block, should return Error:BlockNotFound
+    // Test unknown block, should return Error:BlockNotFound with the hash
     #[tokio::test]
     async fn test_unknown_block() {
         let c = get_testdata_config();
@@ -297,13 +1262,12 @@ mod tests {
             BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
                 .unwrap();
         let block = archive.get_block(&h).await;
-        match block {
-            Ok(_) => assert!(false),
-            Err(e) => match e {
-                Error::BlockNotFound => assert!(true),
-                _ => assert!(false),
-            },
-        }
+        assert!(matches!(block, Err(Error::BlockNotFound(b)) if b == h));
+        let e = archive.get_block(&h).await.err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "Block not found: 0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1"
+        );
     }
 
     // Test block exists
@@ -349,6 +1313,13 @@ mod tests {
         let c = BlockArchiveConfig {
             enabled: true,
             root_path: String::from(root_path.path().to_str().unwrap()),
+            flat: false,
+            immutable_blocks: false,
+            replicas: vec![],
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: 1 << 30,
+            max_open_files: 0,
         };
         let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
         let h =
@@ -375,6 +1346,13 @@ mod tests {
         let c = BlockArchiveConfig {
             enabled: true,
             root_path: String::from(root_path.path().to_str().unwrap()),
+            flat: false,
+            immutable_blocks: false,
+            replicas: vec![],
+            replica_write_mode: String::from("all"),
+            use_index: false,
+            max_buffered_block_bytes: 1 << 30,
+            max_open_files: 0,
         };
         let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
         let h =
@@ -393,13 +1371,225 @@ mod tests {
         let store = archive
             .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
             .await;
-        match store {
-            Ok(_) => assert!(false),
-            Err(e) => match e {
-                Error::BlockExists => assert!(true),
-                _ => assert!(false),
-            },
+        assert!(matches!(store, Err(Error::BlockExists)));
+    }
+
+    // An interrupted store is resumed from its temporary file if the file starts with the header
+    // of the block, and is otherwise stored from the start.
+    #[tokio::test]
+    async fn test_store_block_resumable() {
+        let root_path = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let block = genesis_block(BlockchainId::Main);
+        for (chain, offset) in [(BlockchainId::Main, 100), (BlockchainId::Regtest, 0)] {
+            // the temporary file holds the start of the main genesis block, which only matches
+            // the header of the main genesis block
+            let h = BlockHeader::get_genesis(chain).hash();
+            let tmp = SimpleFileBasedBlockArchive::get_tmp_path(&archive.block_path(&h));
+            std::fs::create_dir_all(tmp.parent().unwrap()).unwrap();
+            std::fs::write(&tmp, &block[..100]).unwrap();
+            let stored = genesis_block(chain);
+            let mut reader = Cursor::new(stored.clone());
+            let r = archive.store_block_resumable(&h, &mut reader).await;
+            assert_eq!(r.unwrap(), offset);
+            assert!(!tmp.exists());
+            let mut buf = Vec::new();
+            let mut reader = archive.get_block(&h).await.unwrap();
+            reader.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, stored);
+        }
+        let mut reader = Cursor::new(block);
+        let h = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        let r = archive.store_block_resumable(&h, &mut reader).await;
+        assert!(matches!(r, Err(Error::BlockExists)));
+    }
+
+    // A resumed block whose temporary file was corrupted fails the check of its merkle root, the
+    // temporary file is removed so that the next store starts from the beginning.
+    #[tokio::test]
+    async fn test_store_block_resumable_mismatch() {
+        let root_path = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let block = genesis_block(BlockchainId::Main);
+        let h = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        let tmp = SimpleFileBasedBlockArchive::get_tmp_path(&archive.block_path(&h));
+        std::fs::create_dir_all(tmp.parent().unwrap()).unwrap();
+        // the header is intact but a byte of the coinbase transaction is not
+        let mut partial = block[..100].to_vec();
+        partial[90] ^= 0xff;
+        std::fs::write(&tmp, &partial).unwrap();
+        let mut reader = Cursor::new(block.clone());
+        let r = archive.store_block_resumable(&h, &mut reader).await;
+        assert!(matches!(r, Err(Error::ResumeMismatch(m)) if m == h));
+        assert!(!tmp.exists());
+        assert!(!archive.block_exists(&h).await.unwrap());
+        let mut reader = Cursor::new(block.clone());
+        let r = archive.store_block_resumable(&h, &mut reader).await;
+        assert_eq!(r.unwrap(), 0);
+    }
+
+    // Blocks stored in an immutable archive are read-only. Only the permissions are checked, so
+    // that the test does not need privileges.
+    #[tokio::test]
+    async fn test_immutable_store_block() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root_path = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path.path().to_str().unwrap()),
+            immutable_blocks: true,
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        archive.immutable_flag = false;
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let block = "This is a block".as_bytes().to_vec();
+        let block_cursor = Box::new(Cursor::new(block.clone()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        assert!(archive.block_protected(&h).await.unwrap());
+        let mut buf = Vec::new();
+        archive
+            .get_block(&h)
+            .await
+            .unwrap()
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, block);
+        // a block that was made writable is no longer protected
+        let path = archive.get_path_from_hash(&h);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(!archive.block_protected(&h).await.unwrap());
+        let other =
+            BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
+                .unwrap();
+        let r = archive.block_protected(&other).await;
+        assert!(matches!(r, Err(Error::BlockNotFound(_))));
+    }
+
+    // Removing a block returns its size, a protected block is not removed.
+    #[tokio::test]
+    async fn test_remove_block() {
+        let root_path = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let block = "This is a block".as_bytes().to_vec();
+        let block_cursor = Box::new(Cursor::new(block.clone()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        assert_eq!(archive.remove_block(&h).await.unwrap(), 15);
+        assert!(!archive.block_exists(&h).await.unwrap());
+        let r = archive.remove_block(&h).await;
+        assert!(matches!(r, Err(Error::BlockNotFound(_))));
+        archive.immutable = true;
+        archive.immutable_flag = false;
+        let block_cursor = Box::new(Cursor::new(block.clone()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        let r = archive.remove_block(&h).await;
+        assert!(matches!(r, Err(Error::BlockProtected)));
+        assert!(archive.block_exists(&h).await.unwrap());
+    }
+
+    // The index is created by the first listing, appended to by store and remove, and rebuilt when
+    // a block directory is changed by a writer that does not keep it.
+    #[tokio::test]
+    async fn test_block_index() {
+        async fn list(archive: &mut SimpleFileBasedBlockArchive) -> (Vec<BlockHash>, bool) {
+            let mut results = archive.block_list().await.unwrap();
+            let mut hashes = Vec::new();
+            while let Some(h) = results.next().await {
+                hashes.push(h.unwrap());
+            }
+            hashes.sort();
+            (hashes, results.finish().await.unwrap().from_index)
         }
+        async fn store(archive: &SimpleFileBasedBlockArchive, h: &BlockHash) {
+            let block_cursor = Box::new(Cursor::new(b"This is a block".to_vec()));
+            archive
+                .store_block(h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+                .await
+                .unwrap();
+        }
+        let root_path = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path.path().to_str().unwrap()),
+            use_index: true,
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let mut hashes: Vec<BlockHash> = (1..=4u8)
+            .map(|i| BlockHash::from_hex(format!("{:064x}", i)).unwrap())
+            .collect();
+        for h in hashes[..2].iter() {
+            store(&archive, h).await;
+        }
+        assert!(!root_path.path().join(INDEX_FILE).exists());
+        let mut expected = hashes[..2].to_vec();
+        expected.sort();
+        assert_eq!(list(&mut archive).await, (expected.clone(), false));
+        assert_eq!(list(&mut archive).await, (expected, true));
+        // store and remove are recorded in the index
+        store(&archive, &hashes[2]).await;
+        archive.remove_block(&hashes[0]).await.unwrap();
+        let mut expected = hashes[1..3].to_vec();
+        expected.sort();
+        assert_eq!(list(&mut archive).await, (expected.clone(), true));
+        // a block that is copied into the archive is found by a walk, which rebuilds the index
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let path = archive.block_path(&hashes[3]);
+        tokio::fs::create_dir_all(path.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(&path, b"This is a block").await.unwrap();
+        expected.push(hashes[3]);
+        expected.sort();
+        assert_eq!(list(&mut archive).await, (expected.clone(), false));
+        assert_eq!(list(&mut archive).await, (expected.clone(), true));
+        let index = tokio::fs::read_to_string(root_path.path().join(INDEX_FILE))
+            .await
+            .unwrap();
+        assert_eq!(index.lines().count(), 4);
+        assert!(index.starts_with(INDEX_HEADER));
+        // a rebuild writes the index whether or not it is used
+        archive.use_index = false;
+        hashes.truncate(1);
+        store(&archive, &hashes[0]).await;
+        assert_eq!(archive.rebuild_index().await.unwrap(), 4);
+        archive.use_index = true;
+        let (listed, from_index) = list(&mut archive).await;
+        assert_eq!((listed.len(), from_index), (4, true));
+        // the index is not counted as a file in the block directories
+        let report = archive.fragmentation_report().await.unwrap();
+        assert_eq!(report.other_files, 0);
     }
 
     // Test getting the size of a block
@@ -423,15 +1613,142 @@ mod tests {
             BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
                 .unwrap();
         let size = archive.block_size(&h).await;
-        match size {
-            Ok(_) => assert!(false),
-            Err(e) => match e {
-                Error::BlockNotFound => assert!(true),
-                _ => assert!(false),
-            },
+        assert!(matches!(size, Err(Error::BlockNotFound(b)) if b == h));
+    }
+
+    fn get_flat_config(root_path: &str) -> BlockArchiveConfig {
+        BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root_path),
+            flat: true,
+            ..BlockArchiveConfig::default()
         }
     }
 
+    // Test storing, retrieving, and listing blocks in a flat archive.
+    #[tokio::test]
+    async fn test_flat_store_block() {
+        let root = tempdir().unwrap();
+        let c = get_flat_config(root.path().to_str().unwrap());
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let path = archive.get_path_from_hash(&h);
+        assert_eq!(
+            path,
+            root.path()
+                .join("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f.bin")
+        );
+        let block = "This is a block".as_bytes().to_vec();
+        let block_cursor = Box::new(Cursor::new(block.clone()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        assert!(path.exists());
+        let mut stored_block = archive.get_block(&h).await.unwrap();
+        let mut buf = Vec::new();
+        stored_block.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, block);
+        let mut results = archive.block_list().await.unwrap();
+        assert_eq!(results.next().await.unwrap().unwrap(), h);
+        assert!(results.next().await.is_none());
+    }
+
+    // A flat archive can not be opened as a fanned-out archive and vice versa.
+    #[tokio::test]
+    async fn test_layout_mismatch() {
+        let root = tempdir().unwrap();
+        let mut c = get_flat_config(root.path().to_str().unwrap());
+        SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        c.flat = false;
+        let r = SimpleFileBasedBlockArchive::new(&c).await;
+        assert!(matches!(r, Err(Error::LayoutMismatch)));
+        // existing archive without a layout file
+        let r =
+            SimpleFileBasedBlockArchive::new(&get_flat_config("../testdata/blockarchive")).await;
+        assert!(matches!(r, Err(Error::LayoutMismatch)));
+    }
+
+    // Test that artifacts are stored separately from blocks and from other kinds of artifacts.
+    #[tokio::test]
+    async fn test_artifacts() {
+        let root = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let block = "This is a block".as_bytes().to_vec();
+        let block_cursor = Box::new(Cursor::new(block));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        // an artifact named like a block file
+        let kind = ArtifactKind::BlockTemplate;
+        let id = ArtifactId::new(&format!("{}.bin", h)).unwrap();
+        let template = vec![7u8; 100];
+        let cursor = Box::new(Cursor::new(template.clone()));
+        archive
+            .store_artifact(
+                &kind,
+                &id,
+                &mut (cursor as Box<dyn AsyncRead + Unpin + Send>),
+            )
+            .await
+            .unwrap();
+        assert!(root
+            .path()
+            .join("artifacts/template")
+            .join(id.as_str())
+            .exists());
+        let mut stored = archive.get_artifact(&kind, &id).await.unwrap();
+        let mut buf = Vec::new();
+        stored.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, template);
+        assert_eq!(
+            archive.list_artifacts(&kind).await.unwrap(),
+            vec![id.clone()]
+        );
+        // the namespaces are isolated
+        let other = ArtifactKind::HeaderWithProof;
+        assert!(archive.list_artifacts(&other).await.unwrap().is_empty());
+        assert!(matches!(
+            archive.get_artifact(&other, &id).await,
+            Err(Error::ArtifactNotFound)
+        ));
+        let mut results = archive.block_list().await.unwrap();
+        assert_eq!(results.next().await.unwrap().unwrap(), h);
+        assert!(results.next().await.is_none());
+        // existing and invalid artifacts are rejected
+        let cursor = Box::new(Cursor::new(template.clone()));
+        let r = archive
+            .store_artifact(
+                &kind,
+                &id,
+                &mut (cursor as Box<dyn AsyncRead + Unpin + Send>),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::ArtifactExists)));
+        let short_id = ArtifactId::new("short").unwrap();
+        let cursor = Box::new(Cursor::new(vec![0u8; 10]));
+        let r = archive
+            .store_artifact(
+                &kind,
+                &short_id,
+                &mut (cursor as Box<dyn AsyncRead + Unpin + Send>),
+            )
+            .await;
+        assert!(matches!(r, Err(Error::InvalidArtifact(_))));
+        assert_eq!(archive.list_artifacts(&kind).await.unwrap(), vec![id]);
+    }
+
     // Testing getting a header
     #[tokio::test]
     async fn test_block_header() {
@@ -463,12 +1780,250 @@ mod tests {
             BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
                 .unwrap();
         let header = archive.block_header(&h).await;
-        match header {
-            Ok(_) => assert!(false),
-            Err(e) => match e {
-                Error::BlockNotFound => assert!(true),
-                _ => assert!(false),
-            },
+        assert!(matches!(header, Err(Error::BlockNotFound(b)) if b == h));
+    }
+
+    // Headers are returned in the order of the hashes, with an error for an unknown block.
+    #[tokio::test]
+    async fn test_block_headers() {
+        let c = get_testdata_config();
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let hashes: Vec<BlockHash> = [
+            "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f",
+            "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1",
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        ]
+        .iter()
+        .map(|h| BlockHash::from_hex(h).unwrap())
+        .collect();
+        let headers = archive.block_headers(&hashes, 2).await;
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[0].as_ref().unwrap().hash(), hashes[0]);
+        assert!(matches!(headers[1], Err(Error::BlockNotFound(b)) if b == hashes[1]));
+        assert_eq!(headers[2].as_ref().unwrap().hash(), hashes[2]);
+    }
+
+    #[tokio::test]
+    async fn test_get_blocks() {
+        let c = get_testdata_config();
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let hashes: Vec<BlockHash> = [
+            "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f",
+            "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1",
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        ]
+        .iter()
+        .map(|h| BlockHash::from_hex(h).unwrap())
+        .collect();
+        let blocks = archive.get_blocks(&hashes, 2).await;
+        assert_eq!(blocks.len(), 3);
+        for (i, (hash, bytes)) in blocks.iter().enumerate() {
+            assert_eq!(*hash, hashes[i]);
+            if i == 1 {
+                assert!(matches!(bytes, Err(Error::BlockNotFound(b)) if b == hash));
+            } else {
+                let bytes = bytes.as_ref().unwrap();
+                assert_eq!(bytes.len(), archive.block_size(hash).await.unwrap());
+                assert_eq!(BlockHash::sha256d(&bytes[..80]), *hash);
+            }
+        }
+    }
+
+    // The readers of the blocks hold their files open, further opens wait until one is closed.
+    #[tokio::test]
+    async fn test_max_open_files() {
+        let c = BlockArchiveConfig {
+            max_open_files: 1,
+            ..get_testdata_config()
+        };
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        assert_eq!(archive.open_files.limit(), 1);
+        let hash =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let reader = archive.get_block(&hash).await.unwrap();
+        assert_eq!(archive.open_files.open(), 1);
+        let r = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            archive.block_header(&hash),
+        )
+        .await;
+        assert!(r.is_err(), "expected the open to wait");
+        drop(reader);
+        assert_eq!(archive.open_files.open(), 0);
+        // concurrent reads take turns
+        let headers = futures::future::join_all((0..8).map(|_| archive.block_header(&hash))).await;
+        assert!(headers.into_iter().all(|h| h.unwrap().hash() == hash));
+        // the files of a block that was not found are not held
+        let unknown =
+            BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
+                .unwrap();
+        assert!(archive.get_block(&unknown).await.is_err());
+        assert_eq!(archive.open_files.open(), 0);
+    }
+
+    // The offsets and lengths of the transactions locate them in the block.
+    #[tokio::test]
+    async fn test_block_txs_with_offsets() {
+        let c = get_testdata_config();
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        for h in [
+            "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f",
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        ] {
+            let hash = BlockHash::from_hex(h).unwrap();
+            let mut block = Vec::new();
+            archive
+                .get_block(&hash)
+                .await
+                .unwrap()
+                .read_to_end(&mut block)
+                .await
+                .unwrap();
+            let txs: Vec<_> = archive
+                .block_txs_with_offsets(&hash)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert!(!txs.is_empty());
+            // the transactions follow the header and the count, one after another, to the end
+            assert_eq!(txs[0].1, 81);
+            let mut end = txs[0].1;
+            for (tx, offset, length) in txs.iter() {
+                assert_eq!(*offset, end);
+                let bytes = &block[*offset as usize..(offset + length) as usize];
+                assert_eq!(Tx::from_binary_buf(bytes).unwrap().hash(), tx.hash());
+                end = offset + length;
+            }
+            assert_eq!(end, block.len() as u64);
+        }
+        let unknown =
+            BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
+                .unwrap();
+        let r = archive.block_txs_with_offsets(&unknown).await;
+        assert!(matches!(r, Err(Error::BlockNotFound(b)) if b == unknown));
+    }
+
+    // A transaction is read by its index, which must be less than the number of transactions.
+    #[tokio::test]
+    async fn test_get_tx_by_index() {
+        let c = get_testdata_config();
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let hash =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let txs: Vec<_> = archive
+            .block_txs_with_offsets(&hash)
+            .await
+            .unwrap()
+            .map(|t| t.unwrap().0)
+            .collect()
+            .await;
+        for (i, tx) in txs.iter().enumerate() {
+            let t = archive.get_tx_by_index(&hash, i as u32).await.unwrap();
+            assert_eq!(t.hash(), tx.hash());
         }
+        let n = txs.len() as u32;
+        let r = archive.get_tx_by_index(&hash, n).await;
+        assert!(matches!(r, Err(Error::TxIndexOutOfRange(i, c)) if i == n && c == n as u64));
+        let unknown =
+            BlockHash::from_hex("0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1")
+                .unwrap();
+        let r = archive.get_tx_by_index(&unknown, 0).await;
+        assert!(matches!(r, Err(Error::BlockNotFound(b)) if b == unknown));
+    }
+
+    // The genesis block is returned when it is not stored, if the fallback is enabled.
+    #[tokio::test]
+    async fn test_genesis_fallback() {
+        let root = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let mut archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let genesis = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        assert!(!archive.block_exists(&genesis).await.unwrap());
+        assert!(matches!(
+            archive.get_block(&genesis).await,
+            Err(Error::BlockNotFound(_))
+        ));
+        archive.genesis = Some(BlockchainId::Main);
+        assert!(archive.block_exists(&genesis).await.unwrap());
+        assert_eq!(archive.block_size(&genesis).await.unwrap(), 285);
+        assert_eq!(
+            archive.block_header(&genesis).await.unwrap().hash(),
+            genesis
+        );
+        let mut reader = archive.get_block(&genesis).await.unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, genesis_block(BlockchainId::Main));
+        // only the genesis block of the configured chain
+        let test_genesis = BlockHeader::get_genesis(BlockchainId::Test).hash();
+        assert!(!archive.block_exists(&test_genesis).await.unwrap());
+        // it is not listed
+        let mut results = archive.block_list().await.unwrap();
+        assert!(results.next().await.is_none());
+    }
+
+    // The report counts the block files and other files, and compact removes the empty
+    // directories.
+    #[tokio::test]
+    async fn test_compact() {
+        let root = tempdir().unwrap();
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let block_cursor = Box::new(Cursor::new(b"This is a block".to_vec()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        std::fs::write(root.path().join("6f/e2/block.tmp"), "tmp").unwrap();
+        std::fs::create_dir_all(root.path().join("ab/cd")).unwrap();
+        std::fs::create_dir_all(root.path().join("6f/00")).unwrap();
+        std::fs::create_dir_all(root.path().join("artifacts/template")).unwrap();
+        let report = archive.fragmentation_report().await.unwrap();
+        assert_eq!(report.blocks, 1);
+        assert_eq!(report.block_bytes, 15);
+        assert_eq!(report.other_files, 1);
+        assert_eq!(report.other_bytes, 3);
+        let mut empty = report.empty_dirs.clone();
+        empty.sort();
+        assert_eq!(
+            empty,
+            vec![
+                root.path().join("6f/00"),
+                root.path().join("ab"),
+                root.path().join("ab/cd")
+            ]
+        );
+        // a directory is listed before its parent
+        let cd = report.empty_dirs.iter().position(|d| d.ends_with("ab/cd"));
+        let ab = report.empty_dirs.iter().position(|d| d.ends_with("ab"));
+        assert!(cd < ab);
+        assert_eq!(archive.compact().await.unwrap(), report);
+        assert!(!root.path().join("ab").exists());
+        assert!(!root.path().join("6f/00").exists());
+        assert!(root.path().join("artifacts/template").exists());
+        assert!(archive.block_exists(&h).await.unwrap());
+        assert!(archive
+            .fragmentation_report()
+            .await
+            .unwrap()
+            .empty_dirs
+            .is_empty());
     }
 }
@@ -0,0 +1,194 @@
+use crate::{Result, SimpleFileBasedBlockArchive};
+use bsvdb_base::{VersionHistory, VersionRecord};
+use tokio::io::AsyncWriteExt;
+
+// The version history of the archive is kept in a text file in its state directory, with a header
+// line and a line "<version> <first seen> <last seen> <git hash> <features>" for each version,
+// where the git hash and the comma separated features are "-" if there are none. The file is
+// rewritten when a version is recorded, which a handle does when it stores its first block.
+
+// the name of the history in the state directory of the archive, and its first line
+const VERSIONS_FILE: &str = "versions.txt";
+const VERSIONS_HEADER: &str = "bsvdb-versions 1";
+// the file that the history is written to before it replaces the history
+const VERSIONS_TMP_FILE: &str = "versions.txt.tmp";
+
+impl SimpleFileBasedBlockArchive {
+    /// The record of this library in the version history, seen at the time now, see
+    /// [versions()](Self::versions).
+    ///
+    /// The git hash is taken from the BSVDB_GIT_HASH environment variable when the library is
+    /// built, if it is set.
+    pub fn library_version(now: u64) -> VersionRecord {
+        VersionRecord::new(
+            env!("CARGO_PKG_VERSION"),
+            option_env!("BSVDB_GIT_HASH"),
+            &[],
+            now,
+        )
+    }
+
+    /// Get the versions of this library that have stored blocks in the archive, most recently
+    /// seen first.
+    ///
+    /// A handle records the version of its library when it stores its first block, see
+    /// [library_version()](Self::library_version). A version that is already in the history only
+    /// has its last seen time updated. The history keeps the
+    /// [MAX_VERSIONS](bsvdb_base::MAX_VERSIONS) most recently seen versions. It is empty if no
+    /// version has been recorded. Lines that can not be parsed are ignored.
+    pub async fn versions(&self) -> Result<VersionHistory> {
+        match tokio::fs::read_to_string(self.state_path().join(VERSIONS_FILE)).await {
+            Ok(text) => Ok(VersionHistory::new(
+                text.lines().skip(1).filter_map(parse_line).collect(),
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(VersionHistory::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record that the writer wrote to the archive, returning the updated history, see
+    /// [versions()](Self::versions).
+    ///
+    /// The history is rewritten, it must not be recorded by writers in different processes at the
+    /// same time.
+    pub async fn record_version(&self, writer: VersionRecord) -> Result<VersionHistory> {
+        let mut history = self.versions().await?;
+        history.record(writer);
+        let mut text = String::from(VERSIONS_HEADER);
+        text.push('\n');
+        for r in history.records() {
+            text.push_str(&line(r));
+            text.push('\n');
+        }
+        let dir = self.state_path();
+        tokio::fs::create_dir_all(&dir).await?;
+        let tmp = dir.join(VERSIONS_TMP_FILE);
+        let mut f = tokio::fs::File::create(&tmp).await?;
+        f.write_all(text.as_bytes()).await?;
+        f.sync_all().await?;
+        tokio::fs::rename(&tmp, dir.join(VERSIONS_FILE)).await?;
+        Ok(history)
+    }
+}
+
+// the line of a version in the history
+fn line(r: &VersionRecord) -> String {
+    let features = match r.features.is_empty() {
+        true => String::from("-"),
+        false => r.features.join(","),
+    };
+    format!(
+        "{} {} {} {} {}",
+        r.version,
+        r.first_seen,
+        r.last_seen,
+        r.git_hash.as_deref().unwrap_or("-"),
+        features
+    )
+}
+
+// parse a line of the history, returning None if it is not a version
+fn parse_line(line: &str) -> Option<VersionRecord> {
+    let mut fields = line.split(' ');
+    let version = String::from(fields.next()?);
+    let first_seen = fields.next()?.parse().ok()?;
+    let last_seen = fields.next()?.parse().ok()?;
+    let git_hash = match fields.next()? {
+        "-" => None,
+        h => Some(String::from(h)),
+    };
+    let features = match fields.next()? {
+        "-" => vec![],
+        f => f.split(',').map(String::from).collect(),
+    };
+    if version.is_empty() || fields.next().is_some() {
+        return None;
+    }
+    Some(VersionRecord {
+        version,
+        git_hash,
+        features,
+        first_seen,
+        last_seen,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockArchive;
+    use bitcoinsv::bitcoin::BlockHash;
+    use bsvdb_base::{BlockArchiveConfig, MAX_VERSIONS};
+    use hex::FromHex;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+    use tokio::io::AsyncRead;
+
+    async fn archive(root: &tempfile::TempDir) -> SimpleFileBasedBlockArchive {
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        SimpleFileBasedBlockArchive::new(&c).await.unwrap()
+    }
+
+    // The versions survive reopening the archive, a known version only has its last seen time
+    // updated, and the history is bounded.
+    #[tokio::test]
+    async fn record_versions() {
+        let root = tempdir().unwrap();
+        let archive = archive(&root).await;
+        assert!(archive.versions().await.unwrap().records().is_empty());
+        let old = VersionRecord::new("0.0.1", Some("abc"), &["x", "y"], 100);
+        archive.record_version(old).await.unwrap();
+        let new = VersionRecord::new("99.0.0", None, &[], 200);
+        archive.record_version(new).await.unwrap();
+        let old = VersionRecord::new("0.0.1", Some("abc"), &["x", "y"], 300);
+        archive.record_version(old).await.unwrap();
+        // a partial line from a crash is ignored
+        let path = archive.state_path().join(VERSIONS_FILE);
+        let mut text = tokio::fs::read_to_string(&path).await.unwrap();
+        text.push_str("0.0.3 1");
+        tokio::fs::write(&path, text).await.unwrap();
+
+        let archive = self::archive(&root).await;
+        let history = archive.versions().await.unwrap();
+        assert_eq!(history.records().len(), 2);
+        let old = history.get("0.0.1").unwrap();
+        assert_eq!((old.first_seen, old.last_seen), (100, 300));
+        assert_eq!(old.git_hash.as_deref(), Some("abc"));
+        assert_eq!(old.features, vec![String::from("x"), String::from("y")]);
+        assert_eq!(parse_line(&line(old)).as_ref(), Some(old));
+        let new = history.get("99.0.0").unwrap();
+        assert_eq!((new.first_seen, new.last_seen), (200, 200));
+        assert!(new.git_hash.is_none() && new.features.is_empty());
+        for i in 0..MAX_VERSIONS as u64 - 1 {
+            let v = VersionRecord::new(&format!("1.{}.0", i), None, &[], 1000 + i);
+            archive.record_version(v).await.unwrap();
+        }
+        let history = archive.versions().await.unwrap();
+        assert_eq!(history.records().len(), MAX_VERSIONS);
+        assert!(history.get("99.0.0").is_none());
+        assert!(history.get("0.0.1").is_some());
+    }
+
+    // A handle records the version of its library when it stores its first block.
+    #[tokio::test]
+    async fn store_records_version() {
+        let root = tempdir().unwrap();
+        let archive = archive(&root).await;
+        let h =
+            BlockHash::from_hex("00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f")
+                .unwrap();
+        let block_cursor = Box::new(Cursor::new("This is a block".as_bytes().to_vec()));
+        archive
+            .store_block(&h, &mut (block_cursor as Box<dyn AsyncRead + Unpin + Send>))
+            .await
+            .unwrap();
+        let history = archive.versions().await.unwrap();
+        let library = SimpleFileBasedBlockArchive::library_version(0).version;
+        assert_eq!(history.records().len(), 1);
+        assert!(history.get(&library).unwrap().last_seen > 0);
+    }
+}
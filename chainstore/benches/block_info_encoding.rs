@@ -0,0 +1,96 @@
+// benchmarks on the encoding of BlockInfos
+//
+// A header-only chain of 100,000 generated headers is encoded in the full and in the compact
+// encoding. The BlockInfos have the median time and chain work that the ChainStore computes for
+// a header, but none of the fields of its block. The total size of the records of each encoding
+// is printed, then the encoding and decoding of the chain are timed. No cluster is needed.
+
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_chainstore::test_util::{generate_chain, header_info};
+use bsvdb_chainstore::{BlockInfo, BlockValidity, FDBChainStore};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NUM_BLOCKS: usize = 100_000;
+// the work of a block at the lowest difficulty, 0x100010001
+const BLOCK_WORK: u128 = 4_295_032_833;
+
+// the BlockInfos of the chain, as they are stored after a header sync
+fn header_only_chain() -> Vec<BlockInfo<u64>> {
+    generate_chain(NUM_BLOCKS, BlockchainId::Main)
+        .iter()
+        .enumerate()
+        .map(|(n, h)| {
+            let id = n as u64 + 1;
+            let mut b = header_info(h, BlockValidity::ValidHeader);
+            b.id = id;
+            b.height = id;
+            b.prev_id = id - 1;
+            b.next_ids = if n + 1 < NUM_BLOCKS {
+                vec![id + 1]
+            } else {
+                vec![]
+            };
+            b.median_time = Some(h.timestamp as u64);
+            let work = (id as u128 + 1) * BLOCK_WORK;
+            b.chain_work = Some([[0u8; 16], work.to_be_bytes()].concat());
+            b
+        })
+        .collect()
+}
+
+fn benchmark(c: &mut Criterion) {
+    let chain = header_only_chain();
+    let full: Vec<Vec<u8>> = chain
+        .iter()
+        .map(|b| FDBChainStore::block_info_record_full(b, 0).1)
+        .collect();
+    let compact: Vec<Vec<u8>> = chain
+        .iter()
+        .map(|b| FDBChainStore::block_info_record(b, 0).1)
+        .collect();
+    let full_size: usize = full.iter().map(|v| v.len()).sum();
+    let compact_size: usize = compact.iter().map(|v| v.len()).sum();
+    println!(
+        "{} header-only BlockInfos: full {} bytes, compact {} bytes, {:.1}% smaller",
+        NUM_BLOCKS,
+        full_size,
+        compact_size,
+        100.0 * (full_size - compact_size) as f64 / full_size as f64
+    );
+    c.bench_function("encode_full", |b| {
+        b.iter(|| {
+            for i in chain.iter() {
+                FDBChainStore::block_info_record_full(i, 0);
+            }
+        });
+    });
+    c.bench_function("encode_compact", |b| {
+        b.iter(|| {
+            for i in chain.iter() {
+                FDBChainStore::block_info_record(i, 0);
+            }
+        });
+    });
+    c.bench_function("decode_full", |b| {
+        b.iter(|| {
+            for v in full.iter() {
+                FDBChainStore::decode_block_info_record(&[], v).unwrap();
+            }
+        });
+    });
+    c.bench_function("decode_compact", |b| {
+        b.iter(|| {
+            for v in compact.iter() {
+                FDBChainStore::decode_block_info_record(&[], v).unwrap();
+            }
+        });
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark
+}
+
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+// benchmarks on get_block_brief and get_block_briefs
+//
+// A store is filled with a chain of 100,000 generated headers, then each benchmark checks
+// whether every block of the chain is known. The full lookups unpack the whole BlockInfo,
+// including the header, the briefs only decode the id, height and validity. The batched briefs
+// make the lookups of 1,000 hashes together in one transaction. The single lookups are limited
+// to MAX_PARALLEL at a time, so that they are not rejected by the admission control.
+
+use bitcoinsv::bitcoin::{BlockHash, BlockchainId};
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::test_util::{generate_chain, header_info};
+use bsvdb_chainstore::{BlockValidity, ChainStore, FDBChainStore};
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{stream, StreamExt};
+use rand::random;
+use tokio::runtime::Runtime;
+
+const NUM_BLOCKS: usize = 100_000;
+const MAX_PARALLEL: usize = 256;
+
+// create a store with a random root path and store the chain in it, a branch at a time
+async fn setup() -> (FDBChainStore, Vec<BlockHash>) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("benchmark{}", random::<u32>()),
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, _j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let chain = generate_chain(NUM_BLOCKS, BlockchainId::Main);
+    let mut tip = chain_store.summary().await.unwrap().genesis_hash;
+    for branch in chain.chunks(1_000) {
+        let blocks = branch
+            .iter()
+            .map(|h| header_info(h, BlockValidity::ValidHeader))
+            .collect();
+        chain_store.apply_branch(blocks, tip).await.unwrap();
+        tip = branch.last().unwrap().hash();
+    }
+    let hashes = chain.iter().map(|h| h.hash()).collect();
+    (chain_store, hashes)
+}
+
+async fn parallel_get_block_info(chain_store: &FDBChainStore, block_hashes: &[BlockHash]) {
+    let mut r = stream::iter(block_hashes)
+        .map(|b| chain_store.get_block_info_by_hash(*b))
+        .buffer_unordered(MAX_PARALLEL);
+    while let Some(i) = r.next().await {
+        assert!(i.unwrap().is_some());
+    }
+}
+
+async fn parallel_get_block_brief(chain_store: &FDBChainStore, block_hashes: &[BlockHash]) {
+    let mut r = stream::iter(block_hashes)
+        .map(|b| chain_store.get_block_brief(*b))
+        .buffer_unordered(MAX_PARALLEL);
+    while let Some(i) = r.next().await {
+        assert!(i.unwrap().is_some());
+    }
+}
+
+async fn get_block_briefs(chain_store: &FDBChainStore, block_hashes: &[BlockHash]) {
+    let briefs = chain_store
+        .get_block_briefs(block_hashes.to_vec())
+        .await
+        .unwrap();
+    assert!(briefs.iter().all(|b| b.is_some()));
+}
+
+fn benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    let (chain_store, hashes) = rt.block_on(setup());
+    c.bench_function("parallel_get_block_info", |b| {
+        b.iter(|| rt.block_on(parallel_get_block_info(&chain_store, &hashes)));
+    });
+    c.bench_function("parallel_get_block_brief", |b| {
+        b.iter(|| rt.block_on(parallel_get_block_brief(&chain_store, &hashes)));
+    });
+    c.bench_function("get_block_briefs", |b| {
+        b.iter(|| rt.block_on(get_block_briefs(&chain_store, &hashes)));
+    });
+    drop(network);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark
+}
+
+criterion_main!(benches);
@@ -28,8 +28,9 @@ async fn setup_get_block_info() -> (FDBChainStore, JoinHandle<()>) {
     let config = ChainStoreConfig {
         enabled: true,
         root_path: root,
+        ..ChainStoreConfig::default()
     };
-    let mut chain_store = FDBChainStore::new(&config, BlockchainId::Main)
+    let chain_store = FDBChainStore::new(&config, BlockchainId::Main)
         .await
         .unwrap();
     chain_store
@@ -58,7 +59,7 @@ async fn global_setup() -> (NetworkAutoStop, Vec<BlockHash>) {
     let file = File::open("../testdata/blockhashes").expect("failed to open blockhashes file");
     let reader = BufReader::new(file);
 
-    let mut strings: Vec<String> = reader
+    let strings: Vec<String> = reader
         .lines()
         .take(10_000)
         .collect::<Result<Vec<String>, io::Error>>()
@@ -69,7 +70,8 @@ async fn global_setup() -> (NetworkAutoStop, Vec<BlockHash>) {
     }
     println!("loaded {} hashes", hashes.len());
 
-    (unsafe { foundationdb::boot() }, hashes)
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    (network, hashes)
 }
 
 async fn global_teardown(network: NetworkAutoStop) {
@@ -80,11 +82,11 @@ fn benchmark(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
     let (network, block_hashes) = rt.block_on(global_setup());
     c.bench_function("parallel_get_block_info", |b| {
-        let (mut chain_store, j) = rt.block_on(setup_get_block_info());
+        let (mut chain_store, _j) = rt.block_on(setup_get_block_info());
         b.iter(|| rt.block_on(parallel_get_block_info(&mut chain_store, &block_hashes)));
     });
     c.bench_function("serial_get_block_info", |b| {
-        let (mut chain_store, j) = rt.block_on(setup_get_block_info());
+        let (mut chain_store, _j) = rt.block_on(setup_get_block_info());
         b.iter(|| rt.block_on(serial_get_block_info(&mut chain_store, &block_hashes)));
     });
     rt.block_on(global_teardown(network));
@@ -0,0 +1,76 @@
+// benchmarks on opening a ChainStore
+//
+// This is the startup cost of every CLI command. A new store resolves and creates its directories
+// in a single transaction, later handles on the same store reuse the cached directories. The
+// difference grows with the latency to the cluster, run this against a remote cluster to see it,
+// a local test cluster answers the directory lookups in microseconds.
+
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::FDBChainStore;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::random;
+use tokio::runtime::Runtime;
+
+fn config(root_path: String) -> ChainStoreConfig {
+    ChainStoreConfig {
+        enabled: true,
+        root_path,
+        ..ChainStoreConfig::default()
+    }
+}
+
+// open a handle on an existing store and shut it down again
+async fn open_existing(config: &ChainStoreConfig) {
+    let (chain_store, j) = FDBChainStore::new_if_exists(config, BlockchainId::Main)
+        .await
+        .unwrap();
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+// create a store with a new root path, whose directories are not cached
+async fn open_new(roots: &mut Vec<String>) {
+    let root = format!("benchmark{}", random::<u32>());
+    let (chain_store, j) = FDBChainStore::new(&config(root.clone()), BlockchainId::Main)
+        .await
+        .unwrap();
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    roots.push(root);
+}
+
+// remove the directories of the stores created by the benchmarks
+async fn remove_roots(roots: &[String]) {
+    let db = foundationdb::Database::default().unwrap();
+    let d = foundationdb::directory::DirectoryLayer::default();
+    for root in roots {
+        let trx = db.create_trx().unwrap();
+        d.remove_if_exists(&trx, &[root.clone()]).await.unwrap();
+        trx.commit().await.unwrap();
+    }
+}
+
+fn benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    let mut roots = vec![];
+    rt.block_on(open_new(&mut roots));
+    let existing = config(roots[0].clone());
+    c.bench_function("open_new", |b| {
+        b.iter(|| rt.block_on(open_new(&mut roots)));
+    });
+    c.bench_function("open_existing", |b| {
+        b.iter(|| rt.block_on(open_existing(&existing)));
+    });
+    rt.block_on(remove_roots(&roots));
+    drop(network);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark
+}
+
+criterion_main!(benches);
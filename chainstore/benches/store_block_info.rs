@@ -0,0 +1,87 @@
+// benchmarks on store_block_info
+//
+// Stores a chain of headers sequentially, as during initial sync, with unsharded and sharded
+// BlockInfo keys. This needs a real cluster with multiple storage servers to show the effect of
+// sharding, a single process test cluster has only one storage server.
+
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rand::random;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+// the number of blocks stored in each iteration
+const CHAIN_LENGTH: u64 = 1_000;
+
+async fn setup_store_block_info(info_shards: u64) -> (FDBChainStore, JoinHandle<()>) {
+    let r_id: u16 = random();
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("benchmark{}", r_id),
+        info_shards,
+        ..ChainStoreConfig::default()
+    };
+    FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap()
+}
+
+// extend the most work tip with a chain of new headers, storing them one at a time
+async fn store_chain(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let mut prev = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    for _ in 0..CHAIN_LENGTH {
+        let mut hdr = prev.header.clone();
+        hdr.prev_hash = prev.hash;
+        let info = BlockInfo {
+            id: 0,
+            hash: hdr.hash(),
+            header: hdr,
+            height: 0,
+            prev_id: 0,
+            next_ids: vec![],
+            size: None,
+            num_tx: None,
+            median_time: None,
+            chain_work: None,
+            total_tx: None,
+            total_size: None,
+            total_fees: None,
+            miner: None,
+            validity: BlockValidity::ValidHeader,
+            received_time: None,
+            source: None,
+        };
+        prev = chain_store.store_block_info(info).await.unwrap();
+    }
+}
+
+fn benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    let mut group = c.benchmark_group("store_block_info");
+    group.throughput(Throughput::Elements(CHAIN_LENGTH));
+    for shards in [0, 16] {
+        let (chain_store, _j) = rt.block_on(setup_store_block_info(shards));
+        group.bench_function(format!("shards_{}", shards), |b| {
+            b.iter(|| rt.block_on(store_chain(&chain_store)));
+        });
+        rt.block_on(chain_store.shutdown()).unwrap();
+    }
+    group.finish();
+    drop(network);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark
+}
+
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+// benchmarks on get_block_infos
+//
+// A store is filled with a chain of 100,000 generated headers, then each benchmark walks the whole
+// chain down from its tip. The walk with a batch size of 1 reads one block at a time, as the walk
+// did before it was batched, the other uses the default batch size. After the benchmarks the p99
+// of the time between the blocks of a walk is printed for each, the stalls of a walk are where it
+// waits for the next batch.
+
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::test_util::{generate_chain, header_info};
+use bsvdb_chainstore::{BlockValidity, ChainStore, FDBChainStore};
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::StreamExt;
+use rand::random;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const NUM_BLOCKS: usize = 100_000;
+
+// create a store with a random root path and store the chain in it, a branch at a time, returns
+// the config of the store and the id of the tip
+async fn setup() -> (ChainStoreConfig, u64) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("benchmark{}", random::<u32>()),
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, _j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let chain = generate_chain(NUM_BLOCKS, BlockchainId::Main);
+    let mut tip = chain_store.summary().await.unwrap().genesis_hash;
+    for branch in chain.chunks(1_000) {
+        let blocks = branch
+            .iter()
+            .map(|h| header_info(h, BlockValidity::ValidHeader))
+            .collect();
+        chain_store.apply_branch(blocks, tip).await.unwrap();
+        tip = branch.last().unwrap().hash();
+    }
+    let tip_id = chain_store.get_chain_state().await.unwrap().most_work_tip;
+    (config, tip_id)
+}
+
+// a handle on the store that walks in batches of batch_size
+async fn open(config: &ChainStoreConfig, batch_size: u64) -> FDBChainStore {
+    let config = ChainStoreConfig {
+        walk_batch_size: batch_size,
+        ..config.clone()
+    };
+    FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap()
+        .0
+}
+
+// walk the chain from the tip, returns the time between each block and the one before it
+async fn walk(chain_store: &FDBChainStore, tip: u64) -> Vec<Duration> {
+    let mut gaps = Vec::with_capacity(NUM_BLOCKS + 1);
+    let mut r = chain_store.get_block_infos(tip, None).await.unwrap();
+    let mut last = Instant::now();
+    while let Some(i) = r.next().await {
+        i.unwrap();
+        gaps.push(last.elapsed());
+        last = Instant::now();
+    }
+    assert_eq!(gaps.len(), NUM_BLOCKS + 1);
+    gaps
+}
+
+fn p99(mut gaps: Vec<Duration>) -> Duration {
+    gaps.sort();
+    gaps[gaps.len() * 99 / 100]
+}
+
+fn benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    let (config, tip) = rt.block_on(setup());
+    let single = rt.block_on(open(&config, 1));
+    let batched = rt.block_on(open(&config, ChainStoreConfig::default().walk_batch_size));
+    c.bench_function("walk_chain_single", |b| {
+        b.iter(|| rt.block_on(walk(&single, tip)));
+    });
+    c.bench_function("walk_chain_batched", |b| {
+        b.iter(|| rt.block_on(walk(&batched, tip)));
+    });
+    for (name, chain_store) in [("single", &single), ("batched", &batched)] {
+        let gaps = rt.block_on(walk(chain_store, tip));
+        println!("walk_chain_{}: p99 between blocks {:?}", name, p99(gaps));
+    }
+    drop(network);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark
+}
+
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+use crate::{Error, Result};
+use bsvdb_base::ChainStoreConfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The classes of the operations of a ChainStore, each class has its own limit on the number of
+/// operations that are queued or being processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationClass {
+    /// Operations that read from the ChainStore and return a single reply.
+    Read,
+    /// Operations that return a stream of BlockInfos.
+    Stream,
+    /// Operations that write to the ChainStore.
+    Write,
+}
+
+impl OperationClass {
+    /// All of the classes, in the order that they are reported.
+    pub const ALL: [OperationClass; 3] = [
+        OperationClass::Read,
+        OperationClass::Stream,
+        OperationClass::Write,
+    ];
+
+    /// The name of the class, as used in the metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperationClass::Read => "read",
+            OperationClass::Stream => "stream",
+            OperationClass::Write => "write",
+        }
+    }
+}
+
+impl std::fmt::Display for OperationClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The admission metrics of one class of operations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMetrics {
+    pub class: OperationClass,
+    /// The number of operations that are queued or being processed.
+    pub in_flight: u64,
+    /// The maximum number of operations that can be queued or processed at once.
+    pub limit: u64,
+    /// The number of operations that were rejected because the class was saturated.
+    pub rejected: u64,
+}
+
+// the counters of one class
+struct ClassState {
+    limit: u64,
+    in_flight: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Limits the number of operations of each class that are queued for the actor or being
+/// processed by it, shared between the handles and the actor.
+///
+/// An operation is admitted before it is sent to the actor and holds a [Permit] until it
+/// completes. When a class is saturated, further operations of that class fail immediately
+/// instead of waiting for space in the queue.
+pub(crate) struct Admission {
+    classes: [ClassState; 3],
+}
+
+impl Admission {
+    pub(crate) fn new(config: &ChainStoreConfig) -> Self {
+        let state = |limit| ClassState {
+            limit,
+            in_flight: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        };
+        Admission {
+            classes: [
+                state(config.max_reads),
+                state(config.max_streams),
+                state(config.max_writes),
+            ],
+        }
+    }
+
+    /// The maximum number of operations of the class that can be admitted at once.
+    pub(crate) fn limit(&self, class: OperationClass) -> u64 {
+        self.classes[class as usize].limit
+    }
+
+    /// Admit an operation of the class, returning Error::Overloaded if the class is saturated.
+    ///
+    /// The queue depth is only used for the error.
+    pub(crate) fn admit(
+        self: &Arc<Self>,
+        class: OperationClass,
+        queue_depth: u64,
+    ) -> Result<Permit> {
+        let state = &self.classes[class as usize];
+        let admitted = state
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < state.limit).then_some(n + 1)
+            });
+        match admitted {
+            Ok(_) => Ok(Permit {
+                admission: self.clone(),
+                class,
+            }),
+            Err(_) => {
+                state.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(Error::Overloaded(class, queue_depth))
+            }
+        }
+    }
+
+    /// Take a snapshot of the metrics of each class.
+    pub(crate) fn snapshot(&self) -> Vec<ClassMetrics> {
+        OperationClass::ALL
+            .iter()
+            .map(|class| {
+                let state = &self.classes[*class as usize];
+                ClassMetrics {
+                    class: *class,
+                    in_flight: state.in_flight.load(Ordering::Relaxed),
+                    limit: state.limit,
+                    rejected: state.rejected.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+/// An admitted operation, the operation is counted as in flight until the permit is dropped.
+pub(crate) struct Permit {
+    admission: Arc<Admission>,
+    class: OperationClass,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.admission.classes[self.class as usize]
+            .in_flight
+            .fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission() -> Arc<Admission> {
+        Arc::new(Admission::new(&ChainStoreConfig {
+            max_reads: 2,
+            max_streams: 1,
+            max_writes: 1,
+            ..ChainStoreConfig::default()
+        }))
+    }
+
+    #[test]
+    fn limits() {
+        let a = admission();
+        let r1 = a.admit(OperationClass::Read, 0).unwrap();
+        let _r2 = a.admit(OperationClass::Read, 1).unwrap();
+        let r = a.admit(OperationClass::Read, 2);
+        assert!(matches!(r, Err(Error::Overloaded(OperationClass::Read, 2))));
+        // the other classes are not affected
+        let _w = a.admit(OperationClass::Write, 2).unwrap();
+        // a dropped permit makes room
+        drop(r1);
+        let _r3 = a.admit(OperationClass::Read, 2).unwrap();
+        let m = a.snapshot();
+        assert_eq!(
+            m[0],
+            ClassMetrics {
+                class: OperationClass::Read,
+                in_flight: 2,
+                limit: 2,
+                rejected: 1,
+            }
+        );
+        assert_eq!(m[1].in_flight, 0);
+        assert_eq!(m[2].in_flight, 1);
+        assert_eq!(m[2].rejected, 0);
+    }
+
+    // Permits can be taken and dropped from many threads without losing count.
+    #[test]
+    fn concurrent() {
+        let a = admission();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let a = a.clone();
+                std::thread::spawn(move || {
+                    let mut admitted = 0;
+                    for _ in 0..1000 {
+                        if let Ok(p) = a.admit(OperationClass::Read, 0) {
+                            admitted += 1;
+                            drop(p);
+                        }
+                    }
+                    admitted
+                })
+            })
+            .collect();
+        let admitted: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        let m = a.snapshot();
+        assert_eq!(m[0].in_flight, 0);
+        assert_eq!(admitted + m[0].rejected, 8000);
+    }
+}
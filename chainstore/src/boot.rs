@@ -0,0 +1,30 @@
+use crate::{Error, Result};
+use bsvdb_base::{ChainStoreConfig, FDB_API_VERSIONS};
+use foundationdb::api::{get_max_api_version, FdbApiBuilder, NetworkAutoStop};
+
+/// Start the foundationdb network with the API version in the config, see
+/// [ChainStoreConfig::fdb_api_version]. It replaces foundationdb::boot(), which always selects the
+/// newest API version of the bindings.
+///
+/// The network runs until the returned handle is dropped, and can only be started once in a
+/// process.
+///
+/// It fails with [Error::UnsupportedApiVersion] if the version is outside
+/// [FDB_API_VERSIONS] or is newer than the installed client
+/// library supports. A client library that can not talk to the cluster is not detected here, the
+/// connection to the cluster then waits until it times out.
+///
+/// # Safety
+/// The same as for foundationdb::boot(): the handle must be dropped before the program exits,
+/// and not from a thread that is run by the network.
+pub unsafe fn boot(config: &ChainStoreConfig) -> Result<NetworkAutoStop> {
+    let version = config.fdb_api_version;
+    let max = get_max_api_version().min(*FDB_API_VERSIONS.end());
+    if version < *FDB_API_VERSIONS.start() || version > max {
+        return Err(Error::UnsupportedApiVersion(version, max));
+    }
+    let network = FdbApiBuilder::default()
+        .set_runtime_version(version)
+        .build()?;
+    Ok(network.boot()?)
+}
@@ -1,8 +1,11 @@
-use crate::Result;
+use crate::work;
+use crate::{Error, Result};
 use async_trait::async_trait;
 use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
-use futures::Stream;
+use futures::{Stream, StreamExt};
+use std::fmt::Debug;
 use std::future::Future;
+use std::hash::Hash;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::sync::mpsc::Receiver;
@@ -17,7 +20,8 @@ use tokio::sync::mpsc::Receiver;
 /// Each ChainStore is associated with a particular blockchain (e.g. mainnet, testnet, etc).
 ///
 /// All functions return a future that can be safely sent between threads, enabling parallel execution
-/// of the futures returned by the functions.
+/// of the futures returned by the functions. Streams returned by the functions are also Send, so
+/// that they can be consumed in a different task. Implementations must preserve these guarantees.
 ///
 /// The initialization of the ChainStore is not defined here, each implementation may have
 /// different initialization needs. However, each new ChainStore must be initialized with the genesis block
@@ -25,10 +29,26 @@ use tokio::sync::mpsc::Receiver;
 ///
 /// Each implementation of ChainStore is expected to support async parallelization, as described
 /// in the development notes (Developing Parallel Access to Databases)[/docs/dev-parallel-dbs.md].
+///
+/// The BlockId does not have to be an integer, an implementation can use the block hash or a
+/// composite key. Code that is shared between implementations only relies on the bounds of
+/// [ChainStore::BlockId]; the generic checks in test_util::check_chain_store() are run against
+/// both the FDBChainStore and an implementation keyed by the block hash. Some operations can be
+/// less efficient when the ids are not small integers:
+/// * the ids are stored in every BlockInfo, as the prev_id and the next_ids, and in the
+///   ChainState, so larger ids take more space and a hash id is a second copy of the hash.
+/// * ids that are not ordered by when the block was stored, such as hashes, do not give a useful
+///   order to a scan by id, so [ChainStore::list_blocks_by_validity()] and the walks of the
+///   chain can not read nearby blocks together.
+/// * the genesis block does not have a fixed id, it is found through the chain state or its hash
+///   rather than as id 0, see [BlockInfo::genesis_info_with_id()].
 #[async_trait]
 pub trait ChainStore {
     /// The BlockId is a unique identifier for a block in the ChainStore.
-    type BlockId;
+    ///
+    /// It is only compared, hashed, cloned and printed, shared code makes no other assumptions
+    /// about it.
+    type BlockId: Clone + Eq + Hash + Send + Sync + Debug;
 
     /// Returns the current state of the blockchain.
     fn get_chain_state(
@@ -49,10 +69,30 @@ pub trait ChainStore {
         hash: BlockHash,
     ) -> impl Future<Output = Result<Option<BlockInfo<Self::BlockId>>>> + Send;
 
+    /// Returns the id, height and validity of the block with the given hash, without the rest of
+    /// the block info.
+    ///
+    /// This is cheaper than [ChainStore::get_block_info_by_hash()] when only the existence of the
+    /// block is needed, such as when checking which of a set of blocks are already stored.
+    fn get_block_brief(
+        &self,
+        hash: BlockHash,
+    ) -> impl Future<Output = Result<Option<BlockBrief<Self::BlockId>>>> + Send;
+
+    /// Returns the briefs of the blocks with the given hashes, in the same order, with None for
+    /// each block that is not in the ChainStore.
+    ///
+    /// Implementations are expected to pipeline the lookups rather than make them one at a time.
+    fn get_block_briefs(
+        &self,
+        hashes: Vec<BlockHash>,
+    ) -> impl Future<Output = Result<Vec<Option<BlockBrief<Self::BlockId>>>>> + Send;
+
     /// Returns the block infos for the block and its ancestors.
     ///
     /// Return at most max_blocks block infos, if given, otherwise return all block infos to the
-    /// genesis block.
+    /// genesis block. If the walk fails, for example because an ancestor is missing, then the
+    /// error is the last item of the stream.
     async fn get_block_infos(
         &self,
         db_id: Self::BlockId,
@@ -89,9 +129,118 @@ pub trait ChainStore {
         &self,
         block_info: BlockInfo<Self::BlockId>,
     ) -> impl Future<Output = Result<BlockInfo<Self::BlockId>>> + Send;
+
+    /// Store several block infos in the ChainStore as a single atomic change, returning the
+    /// updated BlockInfo structures in the same order.
+    ///
+    /// The blocks are stored in order, each as with [ChainStore::store_block_info()], so the
+    /// parent of each block must either be in the ChainStore already or come before it. Unlike
+    /// [ChainStore::apply_branch()] the blocks do not have to extend the most work tip, the
+    /// ChainState is updated as each block is stored. If a block can not be stored then none of
+    /// them are.
+    ///
+    /// Implementations may limit the number of blocks, see the implementation for details.
+    fn store_block_infos(
+        &self,
+        blocks: Vec<BlockInfo<Self::BlockId>>,
+    ) -> impl Future<Output = Result<Vec<BlockInfo<Self::BlockId>>>> + Send;
+
+    /// Works out how the block would be linked if it was stored with
+    /// [ChainStore::store_block_info()], without writing anything.
+    ///
+    /// This allows blocks to be checked before they are stored, for example to put a batch of
+    /// blocks into an order in which each parent is stored before its children. A missing parent
+    /// is returned as [LinkCheck::ParentNotFound] rather than an error.
+    fn check_would_link(
+        &self,
+        info: &BlockInfo<Self::BlockId>,
+    ) -> impl Future<Output = Result<LinkCheck<Self::BlockId>>> + Send;
+
+    /// Returns summary statistics of the ChainStore.
+    ///
+    /// Implementations are expected to maintain the statistics as blocks are stored, rather than
+    /// scanning the entire ChainStore.
+    fn summary(&self) -> impl Future<Output = Result<StoreSummary>> + Send;
+
+    /// Store a branch of blocks and make the last block the most work tip, as a single atomic
+    /// change.
+    ///
+    /// The blocks must be in order, each block the child of the block before it, and the parent of
+    /// the first block must already be in the ChainStore. The blocks are stored as with
+    /// [ChainStore::store_block_info()], blocks that already exist are updated.
+    ///
+    /// The branch is only applied if the hash of the most work tip is expect_current_tip, which
+    /// is the tip that the caller validated the branch against. If the tip has moved then nothing
+    /// is changed and [BranchOutcome::Outdated] is returned, so that the caller can validate the
+    /// branch again. Readers see either the old main chain or the new main chain, never a mixture.
+    ///
+    /// Returns Error::InvalidBranch if the blocks do not form a branch, if the last block is not
+    /// valid, or if it does not have more work than the most work tip.
+    ///
+    /// Implementations may limit the size of a branch, see the implementation for details.
+    fn apply_branch(
+        &self,
+        blocks: Vec<BlockInfo<Self::BlockId>>,
+        expect_current_tip: BlockHash,
+    ) -> impl Future<Output = Result<BranchOutcome<Self::BlockId>>> + Send;
+
+    /// Returns the block infos of all blocks with the given validity.
+    ///
+    /// This scans every block info so it may take a long time for large ChainStores. The block
+    /// infos are not in a defined order.
+    fn list_blocks_by_validity(
+        &self,
+        validity: BlockValidity,
+    ) -> impl Stream<Item = Result<BlockInfo<Self::BlockId>>> + Send;
+
+    /// Returns the block infos of the main chain, from the genesis block up to the most work tip.
+    ///
+    /// The main chain is the one that ends at the most work tip when the stream is started,
+    /// blocks that are stored later are not included.
+    fn stream_main_chain(&self) -> impl Stream<Item = Result<BlockInfo<Self::BlockId>>> + Send;
+
+    /// Returns the ids of the blocks of the main chain whose chain work is not the chain work of
+    /// their parent plus the work given by the bits of their header, from the genesis block up.
+    ///
+    /// Each block is checked against the stored chain work of its parent, so a wrong chain work is
+    /// reported at the block that has it and not at its descendants. A block whose parent has no
+    /// chain work can not be checked, and the chain work of the genesis block, if it has one, must
+    /// be the work of its header. This reads the whole main chain, see
+    /// [ChainStore::stream_main_chain()].
+    fn audit_chain_work(&self) -> impl Future<Output = Result<Vec<Self::BlockId>>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut blocks = std::pin::pin!(self.stream_main_chain());
+            let mut mismatched = Vec::new();
+            let mut parent_work: Option<Vec<u8>> = None;
+            while let Some(block) = blocks.next().await {
+                let block = block?;
+                let parent = match block.height {
+                    0 => block.chain_work.as_ref().map(|_| Vec::new()),
+                    _ => parent_work,
+                };
+                if !work::chain_work_agrees(
+                    parent.as_deref(),
+                    block.chain_work.as_deref(),
+                    block.header.bits,
+                ) {
+                    mismatched.push(block.id);
+                }
+                parent_work = block.chain_work;
+            }
+            Ok(mismatched)
+        }
+    }
 }
 
 /// The BlockValidity enum describes the validity of a block.
+///
+/// The validity is persisted as its discriminant, a u8. Discriminants 0 to 5 are in
+/// use, 6 to 255 are reserved for validities added later, which can only be written with a new
+/// layout version. A discriminant that is not known is an error when it is read, rather than
+/// being read as Unknown, see [Error::UnknownEnumVariant].
 #[derive(Debug, Clone, PartialEq)]
 pub enum BlockValidity {
     /// The validity is unknown
@@ -109,7 +258,7 @@ pub enum BlockValidity {
 }
 
 /// The BlockInfo struct contains information about a block.
-// todo: add version and total_fees fields
+// todo: add version field
 #[derive(Debug, Clone, PartialEq)]
 pub struct BlockInfo<BlockId> {
     pub id: BlockId,
@@ -121,11 +270,40 @@ pub struct BlockInfo<BlockId> {
     pub size: Option<u64>,
     pub num_tx: Option<u64>,
     pub median_time: Option<u64>,
+    /// The total work of the chain up to and including the block, as a big-endian number. When
+    /// the block is stored without it, it is computed from the chain work of the parent, and left
+    /// empty if the parent has none.
     pub chain_work: Option<Vec<u8>>,
     pub total_tx: Option<u64>,
     pub total_size: Option<u64>,
+    /// The fees claimed by the coinbase of the block, which is the value of its outputs less the
+    /// block subsidy. The fees paid by the transactions would need their inputs, which are not
+    /// kept, so a block whose coinbase claims less than it could has the lower figure. An update
+    /// that does not set it keeps the original fees.
+    pub total_fees: Option<u64>,
     pub miner: Option<String>,
     pub validity: BlockValidity,
+    /// The time, in seconds since the Unix epoch, when the BlockInfo was first stored. This is
+    /// when the block was seen, not the timestamp claimed by the header.
+    pub received_time: Option<u64>,
+    /// Where the block was imported from, such as "rpc" or "nodeindex", if it is known. An update
+    /// that does not set it keeps the original source.
+    pub source: Option<String>,
+}
+
+/// The BlockBrief struct contains the fields of a BlockInfo that are needed to check whether a
+/// block is known, returned by [ChainStore::get_block_brief].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockBrief<BlockId> {
+    pub id: BlockId,
+    pub height: u64,
+    /// Whether the block is on the main chain, if that is known without walking the chain.
+    ///
+    /// The most work tip is on the main chain. An invalid block, a tip that is not the most work
+    /// tip, and a block above the most work tip are not. Otherwise it is left to the
+    /// implementation, which may return None.
+    pub on_main_chain: Option<bool>,
+    pub validity: BlockValidity,
 }
 
 /// The ChainState struct contains the current tips of the blockchain.
@@ -133,7 +311,9 @@ pub struct BlockInfo<BlockId> {
 pub struct ChainState<BlockId> {
     /// The block id of the tip with the most proof-of-work.
     ///
-    /// If multiple tips have the most proof-of-work, then this is one of them.
+    /// If multiple tips have the most proof-of-work, then this is one of them. A tip whose chain
+    /// work is not known is only chosen if the chain work of the current tip is not known either,
+    /// in which case the heights are compared.
     pub most_work_tip: BlockId,
     /// The block ids of tips that are currently not dormant. Includes the most work tip.
     pub active_tips: Vec<BlockId>,
@@ -143,20 +323,96 @@ pub struct ChainState<BlockId> {
     pub invalid_tips: Vec<BlockId>,
 }
 
-impl From<u8> for BlockValidity {
-    fn from(value: u8) -> Self {
-        if value == 1 {
-            BlockValidity::Valid
-        } else if value == 2 {
-            BlockValidity::ValidHeader
-        } else if value == 3 {
-            BlockValidity::Invalid
-        } else if value == 4 {
-            BlockValidity::HeaderInvalid
-        } else if value == 5 {
-            BlockValidity::InvalidAncestor
-        } else {
-            BlockValidity::Unknown
+/// Summary statistics of a ChainStore, returned by [ChainStore::summary].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreSummary {
+    /// The total number of blocks.
+    pub num_blocks: u64,
+    /// The number of blocks with each validity.
+    pub validity_counts: Vec<(BlockValidity, u64)>,
+    /// The number of tips, i.e. blocks without children.
+    pub num_tips: u64,
+    /// The height of the most work tip. The number of blocks on the main chain is one more than
+    /// this.
+    pub tip_height: u64,
+    /// The hash of the genesis block.
+    pub genesis_hash: BlockHash,
+}
+
+/// How a block would be linked if it was stored, returned by [ChainStore::check_would_link].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkCheck<BlockId> {
+    /// The parent of the block is not in the ChainStore, so the block can not be stored yet.
+    ParentNotFound,
+    /// The block would be linked to its parent.
+    Links {
+        /// The height that the block would have.
+        height: u64,
+        /// The id of the parent.
+        prev_id: BlockId,
+        /// The validity that the block would have, which depends on the validity of the parent.
+        validity: BlockValidity,
+        /// The id of the block if it is already stored, in which case it would be updated.
+        existing_id: Option<BlockId>,
+    },
+}
+
+/// The outcome of [ChainStore::apply_branch].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BranchOutcome<BlockId> {
+    /// The branch was stored and its last block is now the most work tip.
+    Applied {
+        /// The id of the last block that the branch has in common with the previous main chain.
+        fork_point: BlockId,
+        /// The ids of the blocks that are no longer on the main chain, lowest first. Empty if the
+        /// branch extends the previous main chain.
+        displaced: Vec<BlockId>,
+        /// The blocks of the branch as they were stored, in order.
+        blocks: Vec<BlockInfo<BlockId>>,
+    },
+    /// The most work tip was not the expected tip and nothing was changed.
+    Outdated {
+        /// The hash of the current most work tip.
+        current_tip: BlockHash,
+    },
+}
+
+impl BlockValidity {
+    /// All the BlockValidity values.
+    pub const ALL: [BlockValidity; 6] = [
+        BlockValidity::Unknown,
+        BlockValidity::Valid,
+        BlockValidity::ValidHeader,
+        BlockValidity::Invalid,
+        BlockValidity::HeaderInvalid,
+        BlockValidity::InvalidAncestor,
+    ];
+}
+
+impl BlockValidity {
+    /// Decode a persisted validity, read from a record of the layout version, failing with
+    /// Error::UnknownEnumVariant if the discriminant is not known.
+    pub(crate) fn decode(value: i64, layout: u64) -> Result<BlockValidity> {
+        u8::try_from(value)
+            .ok()
+            .and_then(|v| BlockValidity::try_from(v).ok())
+            .ok_or(Error::UnknownEnumVariant("validity", value, layout))
+    }
+}
+
+/// Fails with the discriminant if it is not known, see [BlockValidity].
+impl TryFrom<u8> for BlockValidity {
+    type Error = u8;
+
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        match value {
+            0 => Ok(BlockValidity::Unknown),
+            1 => Ok(BlockValidity::Valid),
+            2 => Ok(BlockValidity::ValidHeader),
+            3 => Ok(BlockValidity::Invalid),
+            4 => Ok(BlockValidity::HeaderInvalid),
+            5 => Ok(BlockValidity::InvalidAncestor),
+            v => Err(v),
         }
     }
 }
@@ -175,15 +431,36 @@ impl From<BlockValidity> for u8 {
 }
 
 impl BlockInfo<u64> {
-    /// Get the BlockInfo for the genesis block.
+    /// Get the BlockInfo for the genesis block, with id 0.
     pub fn genesis_info(block_chain: BlockchainId) -> BlockInfo<u64> {
+        Self::genesis_info_with_id(block_chain, 0)
+    }
+
+    /// Get a BlockInfo for a custom genesis block, with id 0, see
+    /// [BlockInfo::custom_genesis_info_with_id()].
+    pub fn custom_genesis_info(header: BlockHeader) -> BlockInfo<u64> {
+        Self::custom_genesis_info_with_id(header, 0)
+    }
+
+    /// Check that the BlockInfo is a self-consistent genesis block with id 0, see
+    /// [BlockInfo::check_genesis_with_id()].
+    pub fn check_genesis(&self) -> Result<()> {
+        self.check_genesis_with_id(&0)
+    }
+}
+
+impl<BlockId: Clone + PartialEq> BlockInfo<BlockId> {
+    /// Get the BlockInfo for the genesis block, with the id that the ChainStore gives to it.
+    ///
+    /// The genesis block has no parent, its prev_id is its own id.
+    pub fn genesis_info_with_id(block_chain: BlockchainId, id: BlockId) -> BlockInfo<BlockId> {
         let g_hdr = BlockHeader::get_genesis(block_chain);
         let mut info = BlockInfo {
-            id: 0,
+            prev_id: id.clone(),
+            id,
             hash: g_hdr.hash(),
             header: g_hdr,
             height: 0,
-            prev_id: 0,
             next_ids: vec![],
             size: Some(285),
             num_tx: Some(1),
@@ -194,8 +471,11 @@ impl BlockInfo<u64> {
             ),
             total_tx: Some(1),
             total_size: Some(285),
+            total_fees: None,
             miner: Some(String::from("Satoshi Nakamoto")),
             validity: BlockValidity::Valid,
+            received_time: None,
+            source: None,
         };
         match block_chain {
             BlockchainId::Main => info,
@@ -217,22 +497,75 @@ impl BlockInfo<u64> {
             }
         }
     }
+
+    /// Get a BlockInfo for a custom genesis block, such as for a regtest network with custom
+    /// parameters, with the id that the ChainStore gives to it.
+    ///
+    /// Metadata that can not be derived from the header is set as for a genesis block with a
+    /// single transaction, or is left empty.
+    pub fn custom_genesis_info_with_id(header: BlockHeader, id: BlockId) -> BlockInfo<BlockId> {
+        BlockInfo {
+            prev_id: id.clone(),
+            id,
+            hash: header.hash(),
+            header,
+            height: 0,
+            next_ids: vec![],
+            size: None,
+            num_tx: Some(1),
+            median_time: None,
+            chain_work: None,
+            total_tx: Some(1),
+            total_size: None,
+            total_fees: None,
+            miner: None,
+            validity: BlockValidity::Valid,
+            received_time: None,
+            source: None,
+        }
+    }
+
+    /// Check that the BlockInfo is a self-consistent genesis block with the id.
+    ///
+    /// The hash must match the header, the header must not have a parent, the height must be
+    /// zero, and the id and prev_id must be the id.
+    pub fn check_genesis_with_id(&self, id: &BlockId) -> Result<()> {
+        if self.hash != self.header.hash() {
+            return Err(Error::InvalidGenesis(String::from(
+                "hash does not match header",
+            )));
+        }
+        if self.header.prev_hash.hash.iter().any(|b| *b != 0) {
+            return Err(Error::InvalidGenesis(String::from(
+                "header has a previous block",
+            )));
+        }
+        if self.id != *id || self.prev_id != *id {
+            return Err(Error::InvalidGenesis(String::from(
+                "id and prev_id must be the genesis id",
+            )));
+        }
+        if self.height != 0 {
+            return Err(Error::InvalidGenesis(String::from("height must be zero")));
+        }
+        Ok(())
+    }
 }
 
 /// A stream of BlockInfos, returned by [ChainStore::get_block_infos].
 #[async_trait]
-pub trait BlockInfoStream<T>: Stream<Item = BlockInfo<T>> + Send {}
+pub trait BlockInfoStream<T>: Stream<Item = Result<BlockInfo<T>>> + Send {}
 
 /// An implementation of [BlockInfoStream].
 ///
-/// It expects a background task to be created which sends block hashes to a channel. This stream
-/// reads the BlockInfos from the channel.
+/// It expects a background task to be created which sends BlockInfos, or an error, to a channel.
+/// This stream reads the results from the channel.
 pub struct BlockInfoStreamFromChannel<T>
 where
     T: Send,
 {
     // The receiver to which the background task sends block infos.
-    receiver: Receiver<BlockInfo<T>>,
+    receiver: Receiver<Result<BlockInfo<T>>>,
 }
 
 impl<T> BlockInfoStreamFromChannel<T>
@@ -240,7 +573,7 @@ where
     T: Send,
 {
     /// Create a new BlockInfoStreamFromChannel, with a receiving end of a channel.
-    pub fn new(receiver: Receiver<BlockInfo<T>>) -> BlockInfoStreamFromChannel<T> {
+    pub fn new(receiver: Receiver<Result<BlockInfo<T>>>) -> BlockInfoStreamFromChannel<T> {
         BlockInfoStreamFromChannel { receiver }
     }
 }
@@ -249,7 +582,7 @@ impl<T> Stream for BlockInfoStreamFromChannel<T>
 where
     T: Send,
 {
-    type Item = BlockInfo<T>;
+    type Item = Result<BlockInfo<T>>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         Pin::new(&mut self.receiver).poll_recv(cx)
@@ -0,0 +1,60 @@
+use foundationdb::directory::DirectoryOutput;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+// The foundationdb directories of a ChainStore are resolved through the directory layer, which
+// takes a round trip to the cluster for each level of the path. They are cached for the life of
+// the process, so that opening a ChainStore that was opened before does not resolve them again.
+//
+// The cache is keyed by the cluster file and the directory path. The database is opened with the
+// default cluster file, which is chosen by the FDB_CLUSTER_FILE environment variable.
+
+/// The directories of a ChainStore.
+#[derive(Clone)]
+pub(crate) struct Directories {
+    /// The root directory, which holds the chain state, the counters and the layout.
+    pub(crate) chain: DirectoryOutput,
+    /// The BlockInfo directory.
+    pub(crate) infos: DirectoryOutput,
+    /// The hash index directory.
+    pub(crate) h_index: DirectoryOutput,
+    /// The reorg log directory.
+    pub(crate) reorgs: DirectoryOutput,
+}
+
+type CacheKey = (String, Vec<String>);
+
+static CACHE: OnceLock<Mutex<HashMap<CacheKey, Directories>>> = OnceLock::new();
+
+impl Directories {
+    /// The cached directories of the ChainStore with the directory path, if any.
+    pub(crate) fn cached(path: &[String]) -> Option<Directories> {
+        Self::cache().lock().unwrap().get(&Self::key(path)).cloned()
+    }
+
+    /// Cache the directories of the ChainStore with the directory path.
+    ///
+    /// Only directories that have been committed may be cached, so that every handle uses the
+    /// same directories.
+    pub(crate) fn insert(path: &[String], directories: Directories) {
+        Self::cache()
+            .lock()
+            .unwrap()
+            .insert(Self::key(path), directories);
+    }
+
+    /// Drop the cached directories of the ChainStore with the directory path, which must be done
+    /// when they are moved or removed.
+    pub(crate) fn invalidate(path: &[String]) {
+        Self::cache().lock().unwrap().remove(&Self::key(path));
+    }
+
+    fn cache() -> &'static Mutex<HashMap<CacheKey, Directories>> {
+        CACHE.get_or_init(Default::default)
+    }
+
+    fn key(path: &[String]) -> CacheKey {
+        let cluster_file = std::env::var("FDB_CLUSTER_FILE").unwrap_or_default();
+        (cluster_file, path.to_vec())
+    }
+}
@@ -1,774 +1,4913 @@
-use crate::chain_store::{BlockInfoStreamFromChannel, ChainState};
+use crate::admission::{Admission, OperationClass, Permit};
+use crate::chain_store::{
+    BlockBrief, BlockInfoStreamFromChannel, BranchOutcome, ChainState, LinkCheck, StoreSummary,
+};
+use crate::directories::Directories;
+use crate::forks::{Fork, ForkScanner};
+use crate::guardrail::{Guardrail, GuardrailStats, InsertOptions};
+use crate::metrics::{Metrics, MetricsRecorder};
+use crate::profile::{OperationProfile, TrxProfile};
+use crate::quality::{ChainQuality, QualityScanner, Window};
+use crate::reachability::{
+    IdSet, LinkRepair, ReachabilityFix, ReachabilityReport, UnreachableBlock,
+};
+use crate::snapshot::{Snapshot, SNAPSHOT_VERSION};
+use crate::split::{find_split, ChainEvent, Split, SplitWatch};
+use crate::work;
 use crate::{BlockInfo, BlockValidity, ChainStore, Error, Result};
 use async_trait::async_trait;
 use bitcoinsv::bitcoin::{AsyncEncodable, BlockHash, BlockHeader, BlockchainId};
-use bsvdb_base::ChainStoreConfig;
+use bsvdb_base::{ChainStoreConfig, VersionHistory, VersionRecord};
 use foundationdb::directory::{Directory, DirectoryOutput};
+use foundationdb::options::MutationType;
 use foundationdb::tuple::{pack, unpack, Bytes, Element};
-use foundationdb::Transaction;
+use foundationdb::{RangeOption, Transaction};
+use futures::future::{ready, try_join_all};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use hex::FromHex;
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot::channel as oneshot_channel;
 use tokio::sync::oneshot::Sender as OneshotSender;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug_span, field, Instrument, Span};
 
 /// FDBChainStore is an implementation of ChainStore for foundationdb.
 ///
-/// Applications must call foundationdb::boot() before using this struct.
-/// See https://docs.rs/foundationdb/latest/foundationdb/fn.boot.html.
+/// Applications must start the foundationdb network with [boot()](crate::boot()) before using
+/// this struct, which selects the API version in the config.
 ///
 /// It uses the foundationdb tuple encoding so that the database can be read by multiple
 /// languages.
+///
+/// The FDBChainStore is a light-weight handle to a background actor. It is Send and Sync and can
+/// be cloned and moved into spawned tasks, as can the futures and streams that it returns.
+///
+/// Example code:
+/// ```no_run
+/// # use bitcoinsv::bitcoin::BlockchainId;
+/// # use bsvdb_base::ChainStoreConfig;
+/// # use bsvdb_chainstore::{ChainStore, FDBChainStore};
+/// # use futures::StreamExt;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config = ChainStoreConfig {
+///     enabled: true,
+///     root_path: String::from("bsvmain"),
+///     ..ChainStoreConfig::default()
+/// };
+/// let network = unsafe { bsvdb_chainstore::boot(&config) }.unwrap();
+/// let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main).await.unwrap();
+/// // clone the handle into another task
+/// let c2 = chain_store.clone();
+/// let state = tokio::spawn(async move { c2.get_chain_state().await })
+///     .await
+///     .unwrap()
+///     .unwrap();
+/// // spawn the future directly
+/// let info = tokio::spawn(chain_store.get_block_info(state.most_work_tip));
+/// assert!(info.await.unwrap().unwrap().is_some());
+/// // consume the stream in another task
+/// let mut stream = chain_store.get_block_infos(state.most_work_tip, Some(10)).await.unwrap();
+/// let k = tokio::spawn(async move { stream.next().await });
+/// assert!(k.await.unwrap().unwrap().is_ok());
+/// chain_store.shutdown().await.unwrap();
+/// j.await.unwrap();
+/// drop(network);
+/// # }
+/// ```
 #[derive(Clone)]
 pub struct FDBChainStore {
-    sender: Sender<(FDBChainStoreMessage, OneshotSender<FDBChainStoreReply>)>,
+    sender: ActorSender,
+    metrics: Arc<MetricsRecorder>,
+    guardrail: Arc<std::sync::Mutex<Guardrail>>,
+    events: broadcast::Sender<ChainEvent>,
 }
 
 impl FDBChainStore {
+    // the number of events that are kept for subscribers that have not received them
+    const EVENTS_CAPACITY: usize = 100;
+    /// The newest layout version of the ChainStore that this library knows, see
+    /// [FDBChainStore::layout_version()].
+    pub const LAYOUT_VERSION: u64 = FDBChainStoreActor::LAYOUT_LATEST;
+
     /// Create a new FDBChainStore.
     ///
     /// The root directory supplied as a parameter must be dedicated to the ChainStore. If the
     /// ChainStore is part of a larger system, then this is probably a sub-directory of the larger
-    /// systems directory. (e.g.: vec!["bsvmain", "chainstore"])
+    /// systems directory. (e.g.: vec!["bsvmain", "chainstore"]) When the foundationdb cluster is
+    /// shared with other systems, the prefix in the config keeps the directories apart, see
+    /// [ChainStoreConfig::directory_path()].
+    ///
+    /// If the configuration contains a genesis header, then it overrides the built-in genesis
+    /// block. This is only allowed for regtest.
+    ///
+    /// If the ChainStore is already initialized, then its genesis block must be the genesis block
+    /// of chain, or the one in the configuration, otherwise Error::NetworkMismatch is returned.
+    /// This catches a store of one network being opened with the configuration of another.
+    ///
+    /// The foundationdb directories of the ChainStore are resolved in a single transaction and
+    /// cached for the life of the process, later handles on the same root path reuse them. They
+    /// are resolved again if the ChainStore is no longer found in them.
     pub async fn new(
         config: &ChainStoreConfig,
         chain: BlockchainId,
     ) -> Result<(Self, JoinHandle<()>)> {
-        let (tx, rx) = channel(1_000);
-        let mut actor = FDBChainStoreActor::new(config, chain, rx).await?;
+        Self::new_with_genesis(config, chain, Self::config_genesis(config)?).await
+    }
+
+    /// Create a new FDBChainStore, with a custom genesis block.
+    ///
+    /// The genesis block overrides the built-in genesis block when the ChainStore is initialized.
+    /// It is only allowed for regtest and must be self-consistent, see
+    /// [BlockInfo::check_genesis()]. If genesis is None, then the built-in genesis block is used.
+    /// An existing ChainStore must have been initialized with the same genesis block.
+    pub async fn new_with_genesis(
+        config: &ChainStoreConfig,
+        chain: BlockchainId,
+        genesis: Option<BlockInfo<<Self as ChainStore>::BlockId>>,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        let genesis = match genesis {
+            None => BlockInfo::genesis_info(chain),
+            Some(g) => {
+                if !matches!(chain, BlockchainId::Regtest) {
+                    return Err(Error::InvalidGenesis(String::from(
+                        "custom genesis is only allowed for regtest",
+                    )));
+                }
+                g.check_genesis()?;
+                g
+            }
+        };
+        Self::open(config, genesis, true, false, OperationProfile::Background).await
+    }
+
+    /// Open an existing FDBChainStore.
+    ///
+    /// Unlike [FDBChainStore::new()], this does not initialize a new ChainStore, it returns
+    /// Error::NotInitialized if the ChainStore has not already been initialized. This catches
+    /// mistakes such as using the wrong root path. Like [FDBChainStore::new()], the genesis block
+    /// of the ChainStore is checked against chain and the configuration.
+    pub async fn new_if_exists(
+        config: &ChainStoreConfig,
+        chain: BlockchainId,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        Self::open(
+            config,
+            Self::config_genesis(config)?.unwrap_or_else(|| BlockInfo::genesis_info(chain)),
+            false,
+            false,
+            OperationProfile::Background,
+        )
+        .await
+    }
+
+    /// Open an existing FDBChainStore in read-only mode.
+    ///
+    /// Like [FDBChainStore::new_if_exists()], this returns Error::NotInitialized if the
+    /// ChainStore has not already been initialized. Nothing is written to the database by the
+    /// handle, methods that would write, such as [ChainStore::store_block_info()] or
+    /// [FDBChainStore::check_counters()] with fix set, return Error::ReadOnly. This allows
+    /// untrusted or analytics code to read a ChainStore that is maintained by another process.
+    pub async fn new_readonly(
+        config: &ChainStoreConfig,
+        chain: BlockchainId,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        Self::open(
+            config,
+            Self::config_genesis(config)?.unwrap_or_else(|| BlockInfo::genesis_info(chain)),
+            false,
+            true,
+            OperationProfile::Background,
+        )
+        .await
+    }
+
+    /// Open an existing FDBChainStore in read-only mode, for a command that a user is waiting on.
+    ///
+    /// It is opened like [FDBChainStore::new_readonly()], with the
+    /// [OperationProfile::Interactive] profile: opening the handle and reading the chain state,
+    /// the summary, a split or a single block give up after
+    /// [ChainStoreConfig::interactive_timeout_ms] with Error::BackendSlow, rather than waiting
+    /// for a degraded or unreachable cluster. The other handles keep waiting as long as
+    /// foundationdb allows.
+    pub async fn new_interactive(
+        config: &ChainStoreConfig,
+        chain: BlockchainId,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        Self::open(
+            config,
+            Self::config_genesis(config)?.unwrap_or_else(|| BlockInfo::genesis_info(chain)),
+            false,
+            true,
+            OperationProfile::Interactive,
+        )
+        .await
+    }
+
+    // The custom genesis block of the configuration, None if it does not have one.
+    fn config_genesis(
+        config: &ChainStoreConfig,
+    ) -> Result<Option<BlockInfo<<Self as ChainStore>::BlockId>>> {
+        if config.genesis_header.is_empty() {
+            return Ok(None);
+        }
+        let hdr = BlockHeader::from_hex(&config.genesis_header)
+            .map_err(|_| Error::InvalidGenesis(String::from("could not decode genesis header")))?;
+        Ok(Some(BlockInfo::custom_genesis_info(hdr)))
+    }
+
+    // Start the actor, initializing the ChainStore with the genesis block if create is true, or
+    // checking that it was initialized with it if it already exists.
+    async fn open(
+        config: &ChainStoreConfig,
+        genesis: BlockInfo<<Self as ChainStore>::BlockId>,
+        create: bool,
+        read_only: bool,
+        profile: OperationProfile,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        let admission = Arc::new(Admission::new(config));
+        // the admitted messages always fit in the queues, so sending them never waits, the extra
+        // space is for the shutdown message
+        let (write_tx, write_rx) = channel(admission.limit(OperationClass::Write) as usize);
+        let (tx, rx) = channel(
+            (admission.limit(OperationClass::Read) + admission.limit(OperationClass::Stream))
+                as usize
+                + 1,
+        );
+        let (events, _) = broadcast::channel(Self::EVENTS_CAPACITY);
+        let profile = TrxProfile::new(profile, config);
+        let mut actor = profile
+            .open(FDBChainStoreActor::new(
+                config,
+                genesis,
+                create,
+                read_only,
+                rx,
+                write_rx,
+                events.clone(),
+                profile,
+            ))
+            .await?;
+        let metrics = actor.metrics.clone();
+        let guardrail = actor.guardrail.clone();
         let j = tokio::spawn(async move { actor.run().await });
-        Ok((FDBChainStore { sender: tx }, j))
+        Ok((
+            FDBChainStore {
+                sender: ActorSender {
+                    sender: tx,
+                    write_sender: write_tx,
+                    admission,
+                },
+                metrics,
+                guardrail,
+                events,
+            },
+            j,
+        ))
     }
 
-    /// Shutdown the FDBChainStore, cleaning up and terminating background processes.
-    pub async fn shutdown(&self) -> Result<()> {
-        let (tx, rx) = oneshot_channel();
-        self.sender
-            .send((FDBChainStoreMessage::Shutdown, tx))
-            .await
-            .map_err(|e| Error::SendError(format!("{}", e)))?;
-        match rx.await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Error::from(e)),
+    /// Subscribe to the events of the chain.
+    ///
+    /// The events are raised by the actor, so they cover the changes made through all of the
+    /// handles that share it. A subscriber that falls more than 100 events behind misses the
+    /// oldest, see [broadcast::Receiver::recv()].
+    ///
+    /// A split is looked for after every write, see [FDBChainStore::get_split()].
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Get the tips that are competing with the most work tip, None if there is no split.
+    ///
+    /// The main chain is searched back from the most work tip for forks, see [find_split()] for
+    /// when a tip is competing. The distance and window are set by
+    /// [ChainStoreConfig::split_distance] and [ChainStoreConfig::split_window].
+    pub fn get_split(&self) -> impl Future<Output = Result<Option<Split>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_split");
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::Split, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::SplitReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_split")),
+                Err(e) => Err(Error::from(e)),
+            }
         }
+        .instrument(span)
     }
-}
 
-#[async_trait]
-impl ChainStore for FDBChainStore {
-    type BlockId = u64;
+    /// Get a snapshot of the metrics of the ChainStore.
+    ///
+    /// The metrics are recorded by the actor for every message that it handles, so they cover
+    /// all of the handles that share the actor. Operations are counted when they complete.
+    /// The admission metrics of each class of operation are included, see
+    /// [ChainStoreConfig::max_reads]. See [Metrics::to_prometheus()] for export to Prometheus.
+    pub fn metrics_snapshot(&self) -> Metrics {
+        let mut m = self.metrics.snapshot();
+        m.queue_depth = self.sender.queue_depth();
+        m.classes = self.sender.admission.snapshot();
+        m.guardrail = self.guardrail_stats();
+        m
+    }
 
-    #[allow(refining_impl_trait)]
-    fn get_chain_state(
+    /// Get the state of the guardrail against runaway imports, see
+    /// [ChainStoreConfig::guardrail].
+    ///
+    /// The guardrail is shared by all of the handles of the actor.
+    pub fn guardrail_stats(&self) -> GuardrailStats {
+        self.guardrail.lock().unwrap().stats()
+    }
+
+    /// Enable or disable initial sync mode, in which the guardrail does not check how far below
+    /// the most work tip the stored blocks are. The blocks are still counted.
+    ///
+    /// This is enabled by the commands that sync or backfill the ChainStore, which store blocks
+    /// far below the tip as a matter of course. It applies to all of the handles of the actor.
+    pub fn set_initial_sync(&self, enabled: bool) {
+        self.guardrail.lock().unwrap().set_initial_sync(enabled);
+    }
+
+    /// Store the block info, as [ChainStore::store_block_info()] does, with options for the
+    /// guardrail.
+    ///
+    /// The write fails with Error::GuardrailTripped if it trips the guardrail and the guardrail is
+    /// configured to reject it, see [ChainStoreConfig::guardrail] and [InsertOptions::force].
+    pub fn store_block_info_with_options(
         &self,
-    ) -> Pin<Box<dyn Future<Output = Result<ChainState<<Self as ChainStore>::BlockId>>> + Send>>
+        block_info: BlockInfo<<Self as ChainStore>::BlockId>,
+        options: InsertOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<BlockInfo<<Self as ChainStore>::BlockId>>> + Send>>
     {
         let sender = self.sender.clone();
-        Box::pin(async move {
+        let span = debug_span!("store_block_info", hash = %block_info.hash);
+        let f = async move {
             let (tx, rx) = oneshot_channel();
             sender
-                .send((FDBChainStoreMessage::ChainState, tx))
-                .await
-                .map_err(|e| Error::SendError(format!("{}", e)))?;
+                .send((
+                    FDBChainStoreMessage::StoreBlockInfo(block_info, options),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
             match rx.await {
-                Ok(FDBChainStoreReply::ChainStateReply(s)) => Ok(s),
-                Ok(_) => Err(Error::Internal("received unexpected reply".into())),
+                Ok(FDBChainStoreReply::BlockInfoReply(Some(r))) => Ok(r),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("store_block_info")),
                 Err(e) => Err(Error::from(e)),
             }
-        })
+        };
+        Box::pin(f.instrument(span))
     }
 
-    #[allow(refining_impl_trait)]
-    fn get_block_info(
+    /// Store several block infos, as [ChainStore::store_block_infos()] does, with options for the
+    /// guardrail.
+    ///
+    /// The blocks are checked against the guardrail as a whole, see
+    /// [FDBChainStore::store_block_info_with_options()].
+    pub fn store_block_infos_with_options(
         &self,
-        db_id: Self::BlockId,
-    ) -> Pin<
-        Box<dyn Future<Output = Result<Option<BlockInfo<<Self as ChainStore>::BlockId>>>> + Send>,
-    > {
+        blocks: Vec<BlockInfo<<Self as ChainStore>::BlockId>>,
+        options: InsertOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<BlockInfo<<Self as ChainStore>::BlockId>>>> + Send>>
+    {
         let sender = self.sender.clone();
-        Box::pin(async move {
+        let span = debug_span!("store_block_infos", blocks = blocks.len());
+        let f = async move {
             let (tx, rx) = oneshot_channel();
             sender
-                .send((FDBChainStoreMessage::BlockInfo(db_id), tx))
-                .await
-                .map_err(|e| Error::SendError(format!("{}", e)))?;
+                .send((
+                    FDBChainStoreMessage::StoreBlockInfos(blocks, options),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
             match rx.await {
-                Ok(FDBChainStoreReply::BlockInfoReply(r)) => Ok(r),
-                Ok(_) => Err(Error::Internal("received unexpected reply".into())),
+                Ok(FDBChainStoreReply::StoredReply(r)) => Ok(r),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("store_block_infos")),
                 Err(e) => Err(Error::from(e)),
             }
-        })
+        };
+        Box::pin(f.instrument(span))
     }
 
-    #[allow(refining_impl_trait)]
-    fn get_block_info_by_hash(
+    /// Store a branch of blocks, as [ChainStore::apply_branch()] does, with options for the
+    /// guardrail.
+    ///
+    /// The branch is checked against the guardrail as a whole, see
+    /// [FDBChainStore::store_block_info_with_options()].
+    pub fn apply_branch_with_options(
         &self,
-        block_hash: BlockHash,
-    ) -> Pin<
-        Box<dyn Future<Output = Result<Option<BlockInfo<<Self as ChainStore>::BlockId>>>> + Send>,
-    > {
+        blocks: Vec<BlockInfo<<Self as ChainStore>::BlockId>>,
+        expect_current_tip: BlockHash,
+        options: InsertOptions,
+    ) -> Pin<Box<dyn Future<Output = Result<BranchOutcome<<Self as ChainStore>::BlockId>>> + Send>>
+    {
         let sender = self.sender.clone();
-        Box::pin(async move {
+        let span = debug_span!("apply_branch", blocks = blocks.len(), expect = %expect_current_tip);
+        let f = async move {
             let (tx, rx) = oneshot_channel();
             sender
-                .send((FDBChainStoreMessage::BlockInfoByHash(block_hash), tx))
-                .await
-                .map_err(|e| Error::SendError(format!("{}", e)))?;
+                .send((
+                    FDBChainStoreMessage::ApplyBranch(blocks, expect_current_tip, options),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
             match rx.await {
-                Ok(FDBChainStoreReply::BlockInfoReply(r)) => Ok(r),
-                Ok(_) => Err(Error::Internal("received unexpected reply".into())),
+                Ok(FDBChainStoreReply::BranchReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("apply_branch")),
                 Err(e) => Err(Error::from(e)),
             }
-        })
+        };
+        Box::pin(f.instrument(span))
     }
 
-    // return a BlockInfoStream which will stream the BlockInfo's from db_id downwards, for max_blocks or until reaching Genesis
-    // it returns the BlockInfoStream directly, not a future to the BlockInfoStream.
-    //
-    // There are three channels involved here, the first two of which are normal for every command and the last one is specfic to this command:
-    //  1. The self.sender channel which is used for sending commands to the Actor
-    //  2. The (tx,rx) one shot channel which is used for sending back the results - in this case we dont really need this and just send back an empty reply, but we have to conform to the command standard
-    //  3. A temporary channel for the stream of results which are sent by a task spawned by the Actor and are presented to the caller through the BlockInfoStream interface.
-    async fn get_block_infos(
-        &self,
-        db_id: Self::BlockId,
-        max_blocks: Option<u64>,
-    ) -> Result<BlockInfoStreamFromChannel<Self::BlockId>> {
+    /// Check whether the ChainStore was already initialized when it was opened.
+    ///
+    /// Returns false if the ChainStore was initialized by the call to [FDBChainStore::new()]
+    /// that created this handle, i.e. it is a brand-new ChainStore.
+    pub fn is_initialized(&self) -> impl Future<Output = Result<bool>> + Send {
         let sender = self.sender.clone();
-        let (tx, rx) = oneshot_channel();
-        let (r_tx, r_rx) = channel(1000);
-        sender
-            .send((
-                FDBChainStoreMessage::BlockInfos(db_id, max_blocks, r_tx),
-                tx,
-            ))
-            .await
-            .map_err(|e| Error::SendError(format!("{}", e)))?;
-        match rx.await {
-            Ok(FDBChainStoreReply::BlockInfosReply) => {
-                let r = BlockInfoStreamFromChannel::new(r_rx);
-                Ok(r)
+        let span = debug_span!("is_initialized");
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::IsInitialized, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BoolReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("is_initialized")),
+                Err(e) => Err(Error::from(e)),
             }
-            Ok(_) => Err(Error::Internal("received unexpected reply".into())),
-            Err(e) => Err(Error::from(e)),
         }
+        .instrument(span)
     }
 
-    /// Store the block info in the ChainStore, returning an updated BlockInfo structure and updating
-    /// the ChainState as required.
-    ///
-    /// Implementation of [ChainStore::store_block_info()], see there for more information.
+    /// Get the number of shards that the BlockInfo keys are spread across, 0 if they are not
+    /// sharded.
     ///
-    /// Calls the actor function StoreBlockInfo().
-    #[allow(refining_impl_trait)]
-    fn store_block_info(
-        &self,
-        block_info: BlockInfo<Self::BlockId>,
-    ) -> Pin<Box<dyn Future<Output = Result<BlockInfo<Self::BlockId>>> + Send>> {
+    /// This is fixed when the ChainStore is created, the configuration only applies to new
+    /// ChainStores. Use [FDBChainStore::migrate_info_shards()] to change it.
+    pub fn info_shards(&self) -> impl Future<Output = Result<u64>> + Send {
         let sender = self.sender.clone();
-        Box::pin(async move {
+        let span = debug_span!("info_shards");
+        async move {
             let (tx, rx) = oneshot_channel();
             sender
-                .send((FDBChainStoreMessage::StoreBlockInfo(block_info), tx))
-                .await
-                .map_err(|e| Error::SendError(format!("{}", e)))?;
+                .send((FDBChainStoreMessage::InfoShards, tx, Span::current()))
+                .await?;
             match rx.await {
-                Ok(FDBChainStoreReply::BlockInfoReply(Some(r))) => Ok(r),
-                Ok(_) => Err(Error::Internal("received unexpected reply".into())),
+                Ok(FDBChainStoreReply::ShardsReply(r)) => Ok(r),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("info_shards")),
                 Err(e) => Err(Error::from(e)),
             }
-        })
+        }
+        .instrument(span)
     }
-}
 
-#[derive(Debug)]
-enum FDBChainStoreMessage {
-    ChainState,
-    BlockInfo(<FDBChainStore as ChainStore>::BlockId),
-    BlockInfoByHash(BlockHash),
-    BlockInfos(
-        <FDBChainStore as ChainStore>::BlockId,
-        Option<u64>,
-        Sender<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
-    ),
-    StoreBlockInfo(BlockInfo<<FDBChainStore as ChainStore>::BlockId>),
-    Shutdown,
-}
+    /// Get the layout version of the ChainStore, 1 if the BlockInfo keys are not sharded and 2 if
+    /// they are, see [FDBChainStore::LAYOUT_VERSION].
+    pub async fn layout_version(&self) -> Result<u64> {
+        Ok(match self.info_shards().await? {
+            0 => FDBChainStoreActor::LAYOUT_UNSHARDED,
+            _ => FDBChainStoreActor::LAYOUT_SHARDED,
+        })
+    }
 
-#[derive(Debug)]
-enum FDBChainStoreReply {
-    ChainStateReply(ChainState<<FDBChainStore as ChainStore>::BlockId>),
-    BlockInfoReply(Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>),
-    BlockInfosReply,
-    Done,
-}
+    /// The record of this library in the version history, seen at the time now, see
+    /// [FDBChainStore::versions()].
+    ///
+    /// The git hash is taken from the BSVDB_GIT_HASH environment variable when the library is
+    /// built, if it is set.
+    pub fn library_version(now: u64) -> VersionRecord {
+        let mut features = vec![];
+        if cfg!(feature = "test-util") {
+            features.push("test-util");
+        }
+        VersionRecord::new(
+            env!("CARGO_PKG_VERSION"),
+            option_env!("BSVDB_GIT_HASH"),
+            &features,
+            now,
+        )
+    }
 
-/// the chain store actor
-///
-/// todo: update to use minactor
-struct FDBChainStoreActor {
-    receiver: Receiver<(FDBChainStoreMessage, OneshotSender<FDBChainStoreReply>)>,
-    db: foundationdb::Database,
-    // root directory for chainstore
-    chain_dir: DirectoryOutput,
-    // BlockInfo directory
-    infos_dir: DirectoryOutput,
-    // hash index directory
-    h_index_dir: DirectoryOutput,
-    // next_id with lock
-    next_id_lock: Arc<Mutex<u8>>,
-}
+    /// Get the versions of this library that have written to the ChainStore, most recently seen
+    /// first.
+    ///
+    /// A handle that can write records the version of its library when it is opened, see
+    /// [FDBChainStore::library_version()]. A version that is already in the history only has its
+    /// last seen time updated. The history keeps the
+    /// [MAX_VERSIONS](bsvdb_base::MAX_VERSIONS) most recently seen versions.
+    pub fn versions(&self) -> impl Future<Output = Result<VersionHistory>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("versions");
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::Versions, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::VersionsReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("versions")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
 
-impl FDBChainStoreActor {
-    // BlockInfo directory - key = BlockId, value = BlockInfo
-    const INFOS_DIR: &'static str = "infos";
-    // Hash index directory - key = BlockHash, value = BlockId
-    const H_INDEX_DIR: &'static str = "hindex";
-    // ChainState key name
-    const STATE_KEY: &'static str = "statekey";
-    // NextId key name
-    const NEXT_ID_KEY: &'static str = "nextid";
+    /// Record that the writer wrote to the ChainStore, as is done for this library when a handle
+    /// that can write is opened, returning the updated history, see [FDBChainStore::versions()].
+    pub fn record_version(
+        &self,
+        writer: VersionRecord,
+    ) -> impl Future<Output = Result<VersionHistory>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("record_version", version = %writer.version);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::RecordVersion(writer),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::VersionsReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("record_version")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
 
-    /// Create a new FDBChainStore.
+    /// Convert an existing ChainStore so that the BlockInfo keys are spread across the given
+    /// number of shards, 0 for no sharding.
     ///
-    /// The root directory supplied as a parameter must be dedicated to the ChainStore. If the
-    /// ChainStore is part of a larger system, then this is probably a sub-directory of the larger
-    /// systems directory. (e.g.: vec!["bsvmain", "chainstore"])
-    pub async fn new(
-        config: &ChainStoreConfig,
-        chain: BlockchainId,
-        receiver: Receiver<(FDBChainStoreMessage, OneshotSender<FDBChainStoreReply>)>,
-    ) -> Result<FDBChainStoreActor> {
-        let root_dir: Vec<String> = config.root_path.split('/').map(String::from).collect();
-        let db = foundationdb::Database::default()?;
-        let r_dir = foundationdb::directory::DirectoryLayer::default();
-        // ensure chain dir exists and fetch it
-        let trx = db.create_trx()?;
-        let chain_dir = r_dir.create_or_open(&trx, &root_dir, None, None).await?;
-        trx.commit().await?;
-        // ensure infos dir exists and fetch it
-        let trx = db.create_trx()?;
-        let i = vec![String::from(Self::INFOS_DIR)];
-        let infos_dir = chain_dir.create_or_open(&trx, &i, None, None).await?;
-        trx.commit().await?;
-        // ensure h_index dir exists and fetch it
-        let trx = db.create_trx()?;
-        let i = vec![String::from(Self::H_INDEX_DIR)];
-        let h_index_dir = chain_dir.create_or_open(&trx, &i, None, None).await?;
-        trx.commit().await?;
-        Self::ensure_db_initialized(&db, &chain_dir, infos_dir.clone(), &h_index_dir, chain)
+    /// The BlockInfos are copied to a new directory which then replaces the existing one. This
+    /// must not be run while the ChainStore is in use. Other processes that have opened the
+    /// ChainStore cache its directories and must be restarted.
+    pub async fn migrate_info_shards(config: &ChainStoreConfig, shards: u64) -> Result<()> {
+        FDBChainStoreActor::migrate_info_shards(config, shards)
+            .instrument(debug_span!("migrate_info_shards", shards))
+            .await
+    }
+
+    /// Shutdown the FDBChainStore, cleaning up and terminating background processes.
+    pub async fn shutdown(&self) -> Result<()> {
+        let (tx, rx) = oneshot_channel();
+        self.sender
+            .send((FDBChainStoreMessage::Shutdown, tx, Span::current()))
             .await?;
-        Ok(FDBChainStoreActor {
-            receiver,
-            db,
-            chain_dir,
-            infos_dir,
-            h_index_dir,
-            next_id_lock: Arc::new(Mutex::new(0)),
-        })
+        match rx.await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::from(e)),
+        }
     }
 
-    // ensure that database is initialized
-    async fn ensure_db_initialized(
-        db: &foundationdb::Database,
-        chain_dir: &DirectoryOutput,
-        info_dir: DirectoryOutput,
+    /// Check whether the block is on the active main chain, i.e. whether it is the most work tip
+    /// or one of its ancestors.
+    ///
+    /// Returns false if the block is not in the ChainStore.
+    pub fn is_on_main_chain(
+        &self,
+        db_id: <Self as ChainStore>::BlockId,
+    ) -> impl Future<Output = Result<bool>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("is_on_main_chain", db_id);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::IsOnMainChain(db_id),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BoolReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("is_on_main_chain")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Get the hash of the parent of the block and the hash of its child on the main chain, for
+    /// navigating back and forth along the chain.
+    ///
+    /// The parent is None for the genesis block. The child is the one on the path to the most
+    /// work tip, None if the block is a tip or is not on the main chain. Returns
+    /// Error::BlockInfoNotFound if the block is not in the ChainStore.
+    pub fn get_neighbors(
+        &self,
+        db_id: <Self as ChainStore>::BlockId,
+    ) -> impl Future<Output = Result<(Option<BlockHash>, Option<BlockHash>)>> + Send {
+        let chain_store = self.clone();
+        let span = debug_span!("get_neighbors", db_id);
+        async move {
+            let b_info = chain_store
+                .get_block_info(db_id)
+                .await?
+                .ok_or(Error::BlockInfoNotFound(db_id))?;
+            let prev = (b_info.height > 0).then_some(b_info.header.prev_hash);
+            // at most one child is on the main chain
+            for id in b_info.next_ids {
+                if chain_store.is_on_main_chain(id).await? {
+                    let child = chain_store
+                        .get_block_info(id)
+                        .await?
+                        .ok_or(Error::BlockInfoNotFound(id))?;
+                    return Ok((prev, Some(child.hash)));
+                }
+            }
+            Ok((prev, None))
+        }
+        .instrument(span)
+    }
+
+    /// Check the maintained counters against values recomputed by scanning every BlockInfo.
+    ///
+    /// Returns the counters that have drifted. If fix is true then the drifted counters are reset
+    /// to the recomputed values. The scan can take a long time for large stores and counters
+    /// should not be fixed while blocks are being stored.
+    pub fn check_counters(
+        &self,
+        fix: bool,
+    ) -> impl Future<Output = Result<Vec<CounterDrift>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("check_counters", fix);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::CheckCounters(fix),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::CounterCheckReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("check_counters")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Find the BlockInfos that can not be reached from genesis by following the next_ids.
+    /// WARNING: this may take a long time.
+    ///
+    /// Every BlockInfo is scanned, and the blocks reachable from genesis are found with a
+    /// forward pass over the next_ids. The ids are tracked in bitsets so the memory used is
+    /// small, apart from the unreachable BlockInfos themselves.
+    ///
+    /// If fix is set then the unreachable BlockInfos are deleted or linked to their parent, and
+    /// the counters are then recomputed, see [ReachabilityFix].
+    pub fn check_reachability(
+        &self,
+        fix: Option<ReachabilityFix>,
+    ) -> impl Future<Output = Result<ReachabilityReport>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("check_reachability", fix = ?fix);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::CheckReachability(fix),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::ReachabilityReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("check_reachability")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Repair the links between blocks and their children. WARNING: this may take a long time.
+    ///
+    /// Every BlockInfo is scanned. A block that is not listed in the next_ids of its parent is
+    /// added to them, and next_ids that refer to BlockInfos that do not exist are removed. The
+    /// counters are recomputed if any repairs are made. Returns the repairs, in order of the
+    /// parent id.
+    pub fn repair_links(&self) -> impl Future<Output = Result<Vec<LinkRepair>>> + Send {
+        self.send_repair_links(true)
+    }
+
+    /// Find the repairs that [FDBChainStore::repair_links()] would make, without making them.
+    /// WARNING: this may take a long time.
+    pub fn check_links(&self) -> impl Future<Output = Result<Vec<LinkRepair>>> + Send {
+        self.send_repair_links(false)
+    }
+
+    fn send_repair_links(&self, fix: bool) -> impl Future<Output = Result<Vec<LinkRepair>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("repair_links", fix);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::RepairLinks(fix), tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::LinkRepairReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("repair_links")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Find every fork in the ChainStore, relative to the current most work tip.
+    ///
+    /// This scans every BlockInfo and keeps a compact summary of each block in memory, so it can
+    /// take a long time for large stores. See [ForkScanner::forks()] for the structure of the
+    /// result.
+    pub fn forks(&self) -> impl Future<Output = Result<Vec<Fork>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("forks");
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::Forks, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::ForksReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("forks")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Compute the chain quality metrics over a trailing window, see [ChainQuality].
+    ///
+    /// This scans every BlockInfo, like [forks()](Self::forks), and reads the reorg log, so it
+    /// can take a long time for large stores.
+    pub fn chain_quality(
+        &self,
+        window: Window,
+    ) -> impl Future<Output = Result<ChainQuality>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("chain_quality", %window);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::Quality(window), tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::QualityReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("chain_quality")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Take a snapshot of the chain metadata around a block, for offline analysis.
+    ///
+    /// The snapshot contains the BlockInfos of the blocks within radius blocks of the center,
+    /// following both parents and children so that forks near the center are included, together
+    /// with the ChainState, the summary and the layout. Everything is read in one transaction so
+    /// the snapshot is consistent. Returns Error::BlockNotFound if the center block is not in the
+    /// ChainStore.
+    pub fn snapshot(
+        &self,
+        center: BlockHash,
+        radius: u64,
+    ) -> impl Future<Output = Result<Snapshot>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("snapshot", %center, radius);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::Snapshot(center, radius),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::SnapshotReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("snapshot")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Get the stored bytes of a BlockInfo without decoding them, for debugging.
+    ///
+    /// This allows a record that can not be decoded to be inspected. Returns None if there is no
+    /// BlockInfo with the id.
+    pub fn get_raw_block_info(
+        &self,
+        db_id: <Self as ChainStore>::BlockId,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_raw_block_info", db_id);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::RawBlockInfo(db_id),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::RawReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_raw_block_info")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Get the most recent reorgs, most recent first.
+    ///
+    /// A reorg is recorded when the most work tip changes to a block that does not descend from
+    /// the previous most work tip.
+    pub fn get_reorg_log(
+        &self,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<ReorgEvent>>> + Send {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_reorg_log", limit);
+        async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::ReorgLog(limit), tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::ReorgLogReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_reorg_log")),
+                Err(e) => Err(Error::from(e)),
+            }
+        }
+        .instrument(span)
+    }
+
+    /// Encode the record of a BlockInfo, in a ChainStore with the given number of shards.
+    ///
+    /// Returns the key, without the prefix of the "infos" directory, and the value. The encoding
+    /// is part of the layout of the ChainStore that other readers depend on, it must not change.
+    ///
+    /// The value of a BlockInfo without a size, count of transactions, totals or miner, as for a
+    /// header whose block has not been seen, is in a compact encoding that starts with 0x40 and
+    /// omits those fields. Readers must accept it as well as the full encoding, see
+    /// [FDBChainStore::block_info_record_full()].
+    pub fn block_info_record(
+        block_info: &BlockInfo<<Self as ChainStore>::BlockId>,
+        shards: u64,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let key = Self::block_info_key(block_info, shards);
+        (key, FDBChainStoreActor::encode_block_info(block_info))
+    }
+
+    /// Encode the record of a BlockInfo in the full encoding, whatever its fields, as BlockInfos
+    /// were stored before the compact encoding was added, see
+    /// [FDBChainStore::block_info_record()].
+    pub fn block_info_record_full(
+        block_info: &BlockInfo<<Self as ChainStore>::BlockId>,
+        shards: u64,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let key = Self::block_info_key(block_info, shards);
+        (key, FDBChainStoreActor::encode_block_info_full(block_info))
+    }
+
+    // the key of the record of a BlockInfo, see block_info_record()
+    fn block_info_key(
+        block_info: &BlockInfo<<Self as ChainStore>::BlockId>,
+        shards: u64,
+    ) -> Vec<u8> {
+        if shards == 0 {
+            pack(&block_info.id)
+        } else {
+            pack(&(block_info.id % shards, block_info.id))
+        }
+    }
+
+    /// Encode the hash index record of a block.
+    ///
+    /// Returns the key, without the prefix of the "hindex" directory, and the value, see
+    /// [FDBChainStore::block_info_record()].
+    pub fn hash_index_record(
+        block_hash: &BlockHash,
+        block_id: <Self as ChainStore>::BlockId,
+    ) -> (Vec<u8>, Vec<u8>) {
+        (
+            pack(&block_hash.to_binary_buf().unwrap()),
+            FDBChainStoreActor::encode_h_index(block_id),
+        )
+    }
+
+    /// Decode the value of a BlockInfo record, see [FDBChainStore::block_info_record()].
+    ///
+    /// Returns Error::CorruptRecord if the value is not a valid BlockInfo, the key is only used in
+    /// the error.
+    pub fn decode_block_info_record(
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<BlockInfo<<Self as ChainStore>::BlockId>> {
+        FDBChainStoreActor::decode_block_info(key, value)
+    }
+
+    /// Decode the id, height and validity of a BlockInfo record without unpacking the rest of it,
+    /// as for [ChainStore::get_block_brief()]. The on_main_chain field is None.
+    ///
+    /// Returns Error::CorruptRecord if the fields can not be decoded. The header is not checked, so
+    /// a record that [FDBChainStore::decode_block_info_record()] rejects may be decoded.
+    pub fn decode_block_brief_record(
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<BlockBrief<<Self as ChainStore>::BlockId>> {
+        let p = FDBChainStoreActor::decode_block_info_prefix(key, value)?;
+        Ok(BlockBrief {
+            id: p.id,
+            height: p.height,
+            on_main_chain: None,
+            validity: p.validity,
+        })
+    }
+
+    /// Encode the value of the ChainState record.
+    pub fn chain_state_record(state: &ChainState<<Self as ChainStore>::BlockId>) -> Vec<u8> {
+        FDBChainStoreActor::encode_chain_state(state)
+    }
+
+    /// Decode the value of the ChainState record, see
+    /// [FDBChainStore::decode_block_info_record()].
+    pub fn decode_chain_state_record(
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<ChainState<<Self as ChainStore>::BlockId>> {
+        FDBChainStoreActor::decode_chain_state(key, value)
+    }
+}
+
+/// A change of the most work tip to a block which does not descend from the previous tip, as
+/// returned by [FDBChainStore::get_reorg_log].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    /// The time of the reorg, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// The most work tip before the reorg.
+    pub old_tip: u64,
+    /// The most work tip after the reorg.
+    pub new_tip: u64,
+    /// The height of the last block common to the old and new chains.
+    pub fork_height: u64,
+    /// The number of blocks of the old chain that are no longer on the main chain.
+    pub orphaned_count: u64,
+}
+
+/// A counter whose maintained value differs from the value computed by a full scan, as returned
+/// by [FDBChainStore::check_counters].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterDrift {
+    /// The name of the counter.
+    pub name: String,
+    /// The maintained value.
+    pub stored: u64,
+    /// The value computed by scanning.
+    pub computed: u64,
+}
+
+#[async_trait]
+impl ChainStore for FDBChainStore {
+    type BlockId = u64;
+
+    #[allow(refining_impl_trait)]
+    fn get_chain_state(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<ChainState<<Self as ChainStore>::BlockId>>> + Send>>
+    {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_chain_state");
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::ChainState, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::ChainStateReply(s)) => Ok(s),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_chain_state")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    #[allow(refining_impl_trait)]
+    fn get_block_info(
+        &self,
+        db_id: Self::BlockId,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<Option<BlockInfo<<Self as ChainStore>::BlockId>>>> + Send>,
+    > {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_block_info", db_id);
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::BlockInfo(db_id), tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BlockInfoReply(r)) => Ok(r),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_block_info")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    #[allow(refining_impl_trait)]
+    fn get_block_info_by_hash(
+        &self,
+        block_hash: BlockHash,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<Option<BlockInfo<<Self as ChainStore>::BlockId>>>> + Send>,
+    > {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_block_info_by_hash", hash = %block_hash);
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::BlockInfoByHash(block_hash),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BlockInfoReply(r)) => Ok(r),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_block_info_by_hash")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    fn get_block_brief(
+        &self,
+        hash: BlockHash,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<BlockBrief<Self::BlockId>>>> + Send>> {
+        let f = self.get_block_briefs(vec![hash]);
+        Box::pin(async move { Ok(f.await?.pop().flatten()) })
+    }
+
+    /// Returns the briefs of the blocks with the given hashes.
+    ///
+    /// The blocks are looked up in batches of 1,000 with a transaction for each batch, so a long
+    /// list of hashes is not limited by the transaction time limit. The gets of a batch are made
+    /// together, so that they are pipelined.
+    fn get_block_briefs(
+        &self,
+        hashes: Vec<BlockHash>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Option<BlockBrief<Self::BlockId>>>>> + Send>> {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_block_briefs", count = hashes.len());
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::BlockBriefs(hashes),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BriefsReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("get_block_briefs")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    // return a BlockInfoStream which will stream the BlockInfo's from db_id downwards, for max_blocks or until reaching Genesis
+    // it returns the BlockInfoStream directly, not a future to the BlockInfoStream.
+    //
+    // There are three channels involved here, the first two of which are normal for every command and the last one is specfic to this command:
+    //  1. The self.sender channel which is used for sending commands to the Actor
+    //  2. The (tx,rx) one shot channel which is used for sending back the results - in this case we dont really need this and just send back an empty reply, but we have to conform to the command standard
+    //  3. A temporary channel for the stream of results which are sent by a task spawned by the Actor and are presented to the caller through the BlockInfoStream interface.
+    async fn get_block_infos(
+        &self,
+        db_id: Self::BlockId,
+        max_blocks: Option<u64>,
+    ) -> Result<BlockInfoStreamFromChannel<Self::BlockId>> {
+        let sender = self.sender.clone();
+        let span = debug_span!("get_block_infos", db_id, max_blocks = ?max_blocks);
+        let (tx, rx) = oneshot_channel();
+        let (r_tx, r_rx) = channel(1000);
+        sender
+            .send((
+                FDBChainStoreMessage::BlockInfos(db_id, max_blocks, r_tx),
+                tx,
+                span,
+            ))
+            .await?;
+        match rx.await {
+            Ok(FDBChainStoreReply::BlockInfosReply) => {
+                let r = BlockInfoStreamFromChannel::new(r_rx);
+                Ok(r)
+            }
+            Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+            Ok(r) => Err(r.unexpected("get_block_infos")),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    /// Store the block info in the ChainStore, returning an updated BlockInfo structure and updating
+    /// the ChainState as required.
+    ///
+    /// Implementation of [ChainStore::store_block_info()], see there for more information.
+    ///
+    /// Calls the actor function StoreBlockInfo(), see
+    /// [FDBChainStore::store_block_info_with_options()].
+    #[allow(refining_impl_trait)]
+    fn store_block_info(
+        &self,
+        block_info: BlockInfo<Self::BlockId>,
+    ) -> Pin<Box<dyn Future<Output = Result<BlockInfo<Self::BlockId>>> + Send>> {
+        self.store_block_info_with_options(block_info, InsertOptions::default())
+    }
+
+    /// Store several block infos in the ChainStore in a single transaction, returning the updated
+    /// BlockInfo structures.
+    ///
+    /// Implementation of [ChainStore::store_block_infos()], see there for more information.
+    ///
+    /// The blocks are written in one foundationdb transaction, with the same limits as
+    /// [ChainStore::apply_branch()], so batches of a few thousand blocks are fine.
+    ///
+    /// Calls the actor function StoreBlockInfos(), see
+    /// [FDBChainStore::store_block_infos_with_options()].
+    #[allow(refining_impl_trait)]
+    fn store_block_infos(
+        &self,
+        blocks: Vec<BlockInfo<Self::BlockId>>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<BlockInfo<Self::BlockId>>>> + Send>> {
+        self.store_block_infos_with_options(blocks, InsertOptions::default())
+    }
+
+    /// Works out how the block would be linked if it was stored, without storing it.
+    ///
+    /// Implementation of [ChainStore::check_would_link()], see there for more information.
+    ///
+    /// Calls the actor function CheckWouldLink().
+    #[allow(refining_impl_trait)]
+    fn check_would_link(
+        &self,
+        info: &BlockInfo<Self::BlockId>,
+    ) -> Pin<Box<dyn Future<Output = Result<LinkCheck<Self::BlockId>>> + Send>> {
+        let sender = self.sender.clone();
+        let info = info.clone();
+        let span = debug_span!("check_would_link", hash = %info.hash);
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::CheckWouldLink(info),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::LinkCheckReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("check_would_link")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    /// Store a branch of blocks and make the last block the most work tip, in a single
+    /// transaction.
+    ///
+    /// Implementation of [ChainStore::apply_branch()], see there for more information.
+    ///
+    /// The whole branch is written in one foundationdb transaction, which is limited to 10MB and
+    /// 5 seconds. Each block writes about 200 bytes, so branches of a few thousand blocks are fine
+    /// but very long branches should be stored with [ChainStore::store_block_info()] instead.
+    ///
+    /// Calls the actor function ApplyBranch(), see [FDBChainStore::apply_branch_with_options()].
+    #[allow(refining_impl_trait)]
+    fn apply_branch(
+        &self,
+        blocks: Vec<BlockInfo<Self::BlockId>>,
+        expect_current_tip: BlockHash,
+    ) -> Pin<Box<dyn Future<Output = Result<BranchOutcome<Self::BlockId>>> + Send>> {
+        self.apply_branch_with_options(blocks, expect_current_tip, InsertOptions::default())
+    }
+
+    /// Returns summary statistics of the ChainStore.
+    ///
+    /// The counts are maintained as blocks are stored, this does not scan the ChainStore.
+    #[allow(refining_impl_trait)]
+    fn summary(&self) -> Pin<Box<dyn Future<Output = Result<StoreSummary>> + Send>> {
+        let sender = self.sender.clone();
+        let span = debug_span!("summary");
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((FDBChainStoreMessage::Summary, tx, Span::current()))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::SummaryReply(r)) => r,
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("summary")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Box::pin(f.instrument(span))
+    }
+
+    /// Returns the block infos of all blocks with the given validity, in order of BlockId.
+    ///
+    /// The scan is started when the stream is first polled.
+    #[allow(refining_impl_trait)]
+    fn list_blocks_by_validity(
+        &self,
+        validity: BlockValidity,
+    ) -> Pin<Box<dyn Stream<Item = Result<BlockInfo<Self::BlockId>>> + Send>> {
+        let sender = self.sender.clone();
+        let span = debug_span!("list_blocks_by_validity", validity = ?validity);
+        let (r_tx, r_rx) = channel(1000);
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::BlockInfosByValidity(validity, r_tx),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BlockInfosReply) => Ok(()),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("list_blocks_by_validity")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        // an error starting the scan is the only item before the results
+        let start = stream::once(f.instrument(span)).filter_map(|r| ready(r.err().map(Err)));
+        Box::pin(start.chain(ReceiverStream::new(r_rx)))
+    }
+
+    /// Returns the block infos of the main chain, from the genesis block up to the most work tip.
+    ///
+    /// The main chain is read when the stream is first polled.
+    #[allow(refining_impl_trait)]
+    fn stream_main_chain(
+        &self,
+    ) -> Pin<Box<dyn Stream<Item = Result<BlockInfo<Self::BlockId>>> + Send>> {
+        let sender = self.sender.clone();
+        let span = debug_span!("stream_main_chain");
+        let (r_tx, r_rx) = channel(1000);
+        let f = async move {
+            let (tx, rx) = oneshot_channel();
+            sender
+                .send((
+                    FDBChainStoreMessage::MainChainInfos(r_tx),
+                    tx,
+                    Span::current(),
+                ))
+                .await?;
+            match rx.await {
+                Ok(FDBChainStoreReply::BlockInfosReply) => Ok(()),
+                Ok(FDBChainStoreReply::ErrorReply(e)) => Err(e),
+                Ok(r) => Err(r.unexpected("stream_main_chain")),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        // an error starting the scan is the only item before the results
+        let start = stream::once(f.instrument(span)).filter_map(|r| ready(r.err().map(Err)));
+        Box::pin(start.chain(ReceiverStream::new(r_rx)))
+    }
+}
+
+/// A message for the actor, with the channel for the reply and the span of the caller.
+type ActorMessage = (
+    FDBChainStoreMessage,
+    OneshotSender<FDBChainStoreReply>,
+    Span,
+);
+
+/// A message in the queue of the actor, with the permit that it was admitted with.
+type QueuedMessage = (
+    FDBChainStoreMessage,
+    OneshotSender<FDBChainStoreReply>,
+    Span,
+    Option<Permit>,
+);
+
+/// The sending side of the queues of the actor, which admits each message before it is queued.
+///
+/// Writes have their own queue, which the actor takes from first, so that a flood of reads
+/// does not hold them up.
+#[derive(Clone)]
+struct ActorSender {
+    sender: Sender<QueuedMessage>,
+    write_sender: Sender<QueuedMessage>,
+    admission: Arc<Admission>,
+}
+
+impl ActorSender {
+    /// Admit the message and queue it for the actor.
+    ///
+    /// Returns Error::Overloaded without queueing the message if its class is saturated.
+    async fn send(&self, (msg, reply, span): ActorMessage) -> Result<()> {
+        let class = msg.class();
+        let permit = match class {
+            Some(c) => Some(self.admission.admit(c, self.queue_depth())?),
+            None => None,
+        };
+        let sender = match class {
+            Some(OperationClass::Write) => &self.write_sender,
+            _ => &self.sender,
+        };
+        sender
+            .send((msg, reply, span, permit))
+            .await
+            .map_err(|e| Error::SendError(format!("{}", e)))
+    }
+
+    /// The number of messages waiting in the queues for the actor.
+    fn queue_depth(&self) -> u64 {
+        [&self.sender, &self.write_sender]
+            .iter()
+            .map(|s| (s.max_capacity() - s.capacity()) as u64)
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum FDBChainStoreMessage {
+    ChainState,
+    BlockInfo(<FDBChainStore as ChainStore>::BlockId),
+    BlockInfoByHash(BlockHash),
+    BlockBriefs(Vec<BlockHash>),
+    RawBlockInfo(<FDBChainStore as ChainStore>::BlockId),
+    BlockInfos(
+        <FDBChainStore as ChainStore>::BlockId,
+        Option<u64>,
+        Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ),
+    BlockInfosByValidity(
+        BlockValidity,
+        Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ),
+    MainChainInfos(Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>),
+    StoreBlockInfo(
+        BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        InsertOptions,
+    ),
+    StoreBlockInfos(
+        Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+        InsertOptions,
+    ),
+    ApplyBranch(
+        Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+        BlockHash,
+        InsertOptions,
+    ),
+    CheckWouldLink(BlockInfo<<FDBChainStore as ChainStore>::BlockId>),
+    IsOnMainChain(<FDBChainStore as ChainStore>::BlockId),
+    Summary,
+    CheckCounters(bool),
+    CheckReachability(Option<ReachabilityFix>),
+    RepairLinks(bool),
+    Forks,
+    Quality(Window),
+    ReorgLog(usize),
+    Snapshot(BlockHash, u64),
+    IsInitialized,
+    InfoShards,
+    Split,
+    Versions,
+    RecordVersion(VersionRecord),
+    Shutdown,
+}
+
+impl FDBChainStoreMessage {
+    /// The name of the message, used for tracing.
+    fn name(&self) -> &'static str {
+        match self {
+            FDBChainStoreMessage::ChainState => "ChainState",
+            FDBChainStoreMessage::BlockInfo(_) => "BlockInfo",
+            FDBChainStoreMessage::BlockInfoByHash(_) => "BlockInfoByHash",
+            FDBChainStoreMessage::BlockBriefs(_) => "BlockBriefs",
+            FDBChainStoreMessage::RawBlockInfo(_) => "RawBlockInfo",
+            FDBChainStoreMessage::BlockInfos(..) => "BlockInfos",
+            FDBChainStoreMessage::BlockInfosByValidity(..) => "BlockInfosByValidity",
+            FDBChainStoreMessage::MainChainInfos(_) => "MainChainInfos",
+            FDBChainStoreMessage::StoreBlockInfo(..) => "StoreBlockInfo",
+            FDBChainStoreMessage::StoreBlockInfos(..) => "StoreBlockInfos",
+            FDBChainStoreMessage::ApplyBranch(..) => "ApplyBranch",
+            FDBChainStoreMessage::CheckWouldLink(_) => "CheckWouldLink",
+            FDBChainStoreMessage::IsOnMainChain(_) => "IsOnMainChain",
+            FDBChainStoreMessage::Summary => "Summary",
+            FDBChainStoreMessage::CheckCounters(_) => "CheckCounters",
+            FDBChainStoreMessage::CheckReachability(_) => "CheckReachability",
+            FDBChainStoreMessage::RepairLinks(_) => "RepairLinks",
+            FDBChainStoreMessage::Forks => "Forks",
+            FDBChainStoreMessage::Quality(_) => "Quality",
+            FDBChainStoreMessage::ReorgLog(_) => "ReorgLog",
+            FDBChainStoreMessage::Snapshot(..) => "Snapshot",
+            FDBChainStoreMessage::IsInitialized => "IsInitialized",
+            FDBChainStoreMessage::InfoShards => "InfoShards",
+            FDBChainStoreMessage::Split => "Split",
+            FDBChainStoreMessage::Versions => "Versions",
+            FDBChainStoreMessage::RecordVersion(_) => "RecordVersion",
+            FDBChainStoreMessage::Shutdown => "Shutdown",
+        }
+    }
+
+    /// The class of the operation for admission, None for messages that are always admitted.
+    fn class(&self) -> Option<OperationClass> {
+        match self {
+            FDBChainStoreMessage::Shutdown => None,
+            FDBChainStoreMessage::BlockInfos(..)
+            | FDBChainStoreMessage::BlockInfosByValidity(..)
+            | FDBChainStoreMessage::MainChainInfos(_) => Some(OperationClass::Stream),
+            m if m.is_write() => Some(OperationClass::Write),
+            _ => Some(OperationClass::Read),
+        }
+    }
+
+    /// Whether the message writes to the database.
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            FDBChainStoreMessage::StoreBlockInfo(..)
+                | FDBChainStoreMessage::StoreBlockInfos(..)
+                | FDBChainStoreMessage::ApplyBranch(..)
+                | FDBChainStoreMessage::CheckCounters(true)
+                | FDBChainStoreMessage::CheckReachability(Some(_))
+                | FDBChainStoreMessage::RepairLinks(true)
+                | FDBChainStoreMessage::RecordVersion(_)
+        )
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum FDBChainStoreReply {
+    ChainStateReply(ChainState<<FDBChainStore as ChainStore>::BlockId>),
+    BlockInfoReply(Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>),
+    BlockInfosReply,
+    StoredReply(Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>),
+    BriefsReply(Result<Vec<Option<BlockBrief<<FDBChainStore as ChainStore>::BlockId>>>>),
+    RawReply(Result<Option<Vec<u8>>>),
+    BoolReply(Result<bool>),
+    BranchReply(Result<BranchOutcome<<FDBChainStore as ChainStore>::BlockId>>),
+    LinkCheckReply(Result<LinkCheck<<FDBChainStore as ChainStore>::BlockId>>),
+    SummaryReply(Result<StoreSummary>),
+    CounterCheckReply(Result<Vec<CounterDrift>>),
+    ReachabilityReply(Result<ReachabilityReport>),
+    LinkRepairReply(Result<Vec<LinkRepair>>),
+    ForksReply(Result<Vec<Fork>>),
+    QualityReply(Result<ChainQuality>),
+    ReorgLogReply(Result<Vec<ReorgEvent>>),
+    SnapshotReply(Result<Snapshot>),
+    SplitReply(Result<Option<Split>>),
+    ShardsReply(u64),
+    VersionsReply(Result<VersionHistory>),
+    ErrorReply(Error),
+    Done,
+}
+
+impl FDBChainStoreReply {
+    /// The name of the reply, used in errors.
+    fn name(&self) -> &'static str {
+        match self {
+            FDBChainStoreReply::ChainStateReply(_) => "ChainStateReply",
+            FDBChainStoreReply::BlockInfoReply(_) => "BlockInfoReply",
+            FDBChainStoreReply::BlockInfosReply => "BlockInfosReply",
+            FDBChainStoreReply::StoredReply(_) => "StoredReply",
+            FDBChainStoreReply::BriefsReply(_) => "BriefsReply",
+            FDBChainStoreReply::RawReply(_) => "RawReply",
+            FDBChainStoreReply::BoolReply(_) => "BoolReply",
+            FDBChainStoreReply::BranchReply(_) => "BranchReply",
+            FDBChainStoreReply::LinkCheckReply(_) => "LinkCheckReply",
+            FDBChainStoreReply::SummaryReply(_) => "SummaryReply",
+            FDBChainStoreReply::CounterCheckReply(_) => "CounterCheckReply",
+            FDBChainStoreReply::ReachabilityReply(_) => "ReachabilityReply",
+            FDBChainStoreReply::LinkRepairReply(_) => "LinkRepairReply",
+            FDBChainStoreReply::ForksReply(_) => "ForksReply",
+            FDBChainStoreReply::QualityReply(_) => "QualityReply",
+            FDBChainStoreReply::ReorgLogReply(_) => "ReorgLogReply",
+            FDBChainStoreReply::SnapshotReply(_) => "SnapshotReply",
+            FDBChainStoreReply::SplitReply(_) => "SplitReply",
+            FDBChainStoreReply::ShardsReply(_) => "ShardsReply",
+            FDBChainStoreReply::VersionsReply(_) => "VersionsReply",
+            FDBChainStoreReply::ErrorReply(_) => "ErrorReply",
+            FDBChainStoreReply::Done => "Done",
+        }
+    }
+
+    /// The error for a reply that the command does not expect.
+    fn unexpected(self, command: &str) -> Error {
+        Error::Internal(format!("{} received {}", command, self.name()))
+    }
+}
+
+/// The BlockInfo directory and the layout of its keys.
+///
+/// BlockIds are allocated sequentially, so if the BlockId is used as the key then all inserts go
+/// to the end of the directory and are handled by the same storage servers. When sharded, the key
+/// is (shard, BlockId) where shard = BlockId % shards, which spreads the inserts across the
+/// directory. Point lookups are still a single get but ordered reads must merge the shards.
+#[derive(Clone)]
+struct InfosDir {
+    dir: DirectoryOutput,
+    // the number of shards, 0 if not sharded
+    shards: u64,
+}
+
+impl InfosDir {
+    // get the key for a BlockInfo
+    fn key(&self, block_id: <FDBChainStore as ChainStore>::BlockId) -> Result<Vec<u8>> {
+        if self.shards == 0 {
+            Ok(self.dir.pack(&block_id)?)
+        } else {
+            Ok(self.dir.pack(&(block_id % self.shards, block_id))?)
+        }
+    }
+
+    // get the ranges that contain the BlockInfos with start <= BlockId < end, one for each shard
+    fn ranges(
+        &self,
+        start: <FDBChainStore as ChainStore>::BlockId,
+        end: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<Vec<RangeOption<'static>>> {
+        if self.shards == 0 {
+            let r = (self.dir.pack(&start)?, self.dir.pack(&end)?);
+            Ok(vec![RangeOption::from(r)])
+        } else {
+            (0..self.shards)
+                .map(|s| {
+                    let r = (self.dir.pack(&(s, start))?, self.dir.pack(&(s, end))?);
+                    Ok(RangeOption::from(r))
+                })
+                .collect()
+        }
+    }
+}
+
+// The fields of a BlockInfo that are decoded by FDBChainStoreActor::decode_block_info_prefix().
+#[derive(Debug, PartialEq)]
+pub(crate) struct BlockInfoPrefix {
+    id: <FDBChainStore as ChainStore>::BlockId,
+    height: u64,
+    has_children: bool,
+    validity: BlockValidity,
+}
+
+impl BlockInfoPrefix {
+    // the brief of the block, given the prefix of the most work tip
+    fn brief(self, tip: &BlockInfoPrefix) -> BlockBrief<<FDBChainStore as ChainStore>::BlockId> {
+        let invalid = matches!(
+            self.validity,
+            BlockValidity::Invalid | BlockValidity::HeaderInvalid | BlockValidity::InvalidAncestor
+        );
+        let on_main_chain = if self.id == tip.id {
+            Some(true)
+        } else if invalid || !self.has_children || self.height >= tip.height {
+            Some(false)
+        } else {
+            None
+        };
+        BlockBrief {
+            id: self.id,
+            height: self.height,
+            on_main_chain,
+            validity: self.validity,
+        }
+    }
+}
+
+// How a walk through the chain reads the BlockInfos, see FDBChainStoreActor::sub_block_infos().
+#[derive(Debug, Clone, Copy)]
+struct WalkOptions {
+    // the number of BlockInfos that are read at once
+    batch_size: u64,
+    // how long a transaction is used before it is replaced by a fresh one
+    rotate: Duration,
+    // the number of reads after which a transaction is replaced by a fresh one
+    rotate_reads: u64,
+    // the number of times the transaction is reset after it became too old anyway
+    max_resets: u32,
+}
+
+// A run of the chain read by FDBChainStoreActor::read_walk_run(), from a block down towards
+// genesis.
+struct WalkRun {
+    // the keys and values of the BlockInfos of the run, from the highest block down
+    values: Vec<(Vec<u8>, Vec<u8>)>,
+    // the id of the parent of the last block of the run, None if the run ends at genesis
+    next: Option<<FDBChainStore as ChainStore>::BlockId>,
+    // the number of BlockInfos that were read, including guesses that were not used
+    reads: u64,
+    // whether the parent of each block of the run had the id before it
+    contiguous: bool,
+}
+
+impl WalkRun {
+    // Add the next BlockInfo of the run, the parent of the last one, and chain to its parent.
+    fn push(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<()> {
+        let (height, prev_id) = FDBChainStoreActor::decode_walk_prefix(&k, &v)?;
+        let id = self.next.unwrap_or_default();
+        self.values.push((k, v));
+        if height == 0 {
+            self.next = None;
+        } else {
+            self.contiguous &= id.checked_sub(1) == Some(prev_id);
+            self.next = Some(prev_id);
+        }
+        Ok(())
+    }
+}
+
+// The end of the tuple element that starts at pos, None if it is not a valid element.
+//
+// Nested tuples are followed with a depth rather than by recursion, so that a corrupt record can
+// not overflow the stack. Within a nested tuple a nil is escaped as 0x00 0xff, and an unescaped
+// 0x00 ends the tuple.
+fn skip_element(v: &[u8], pos: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut i = pos;
+    loop {
+        i = match *v.get(i)? {
+            0x00 if depth == 0 => i + 1,
+            0x00 if v.get(i + 1) == Some(&0xff) => i + 2,
+            0x00 => {
+                depth -= 1;
+                i + 1
+            }
+            // bytes and strings end at a 0x00 that is not followed by the escape 0xff
+            0x01 | 0x02 => {
+                let mut j = i + 1;
+                loop {
+                    match *v.get(j)? {
+                        0x00 if v.get(j + 1) == Some(&0xff) => j += 2,
+                        0x00 => break j + 1,
+                        _ => j += 1,
+                    }
+                }
+            }
+            0x05 => {
+                depth += 1;
+                i + 1
+            }
+            // integers too large for 8 bytes, with the length in the next byte
+            0x0b => i + 2 + !*v.get(i + 1)? as usize,
+            0x1d => i + 2 + *v.get(i + 1)? as usize,
+            c @ 0x0c..=0x1c => i + 1 + (c as i32 - 0x14).unsigned_abs() as usize,
+            // float, double, false, true, uuid and versionstamp
+            0x20 => i + 5,
+            0x21 => i + 9,
+            0x26 | 0x27 => i + 1,
+            0x30 => i + 17,
+            0x33 => i + 13,
+            _ => return None,
+        };
+        if i > v.len() {
+            return None;
+        }
+        if depth == 0 {
+            return Some(i);
+        }
+    }
+}
+
+// The integer that starts at pos and the end of it, None if it is not an integer that fits in an
+// i64.
+fn read_int(v: &[u8], pos: usize) -> Option<(i64, usize)> {
+    let c = *v.get(pos)?;
+    if !(0x0c..=0x1c).contains(&c) {
+        return None;
+    }
+    let n = (c as i32 - 0x14).unsigned_abs() as usize;
+    let bytes = v.get(pos + 1..pos + 1 + n)?;
+    let magnitude = bytes.iter().fold(0u64, |m, b| (m << 8) | *b as u64);
+    let i = if c >= 0x14 {
+        i64::try_from(magnitude).ok()?
+    } else {
+        // negative integers are stored as the ones' complement of the magnitude
+        let max = if n == 8 { u64::MAX } else { (1 << (8 * n)) - 1 };
+        0i64.checked_sub_unsigned(max - magnitude)?
+    };
+    Some((i, pos + 1 + n))
+}
+
+// merge BlockInfos read from several shards into order of BlockId
+fn merge_shards(
+    shards: Vec<Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+) -> Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>> {
+    let mut r: Vec<_> = shards.into_iter().flatten().collect();
+    r.sort_by_key(|b| b.id);
+    r
+}
+
+/// Looks for a split, raising the events when one is detected or resolved and maintaining the
+/// count of competing tips in the metrics.
+struct SplitMonitor {
+    db: Arc<foundationdb::Database>,
+    chain_dir: DirectoryOutput,
+    infos_dir: InfosDir,
+    // see ChainStoreConfig::split_distance
+    distance: u64,
+    // see ChainStoreConfig::split_window
+    window: u64,
+    // the last split found, held while looking so that the checks do not overlap
+    watch: Mutex<SplitWatch>,
+    // set when a check is requested, cleared when the check starts
+    pending: AtomicBool,
+    // set while a task is running the requested checks
+    running: AtomicBool,
+    events: broadcast::Sender<ChainEvent>,
+    metrics: Arc<MetricsRecorder>,
+}
+
+impl SplitMonitor {
+    /// Find the current split, if any.
+    async fn find(&self, trx: &Transaction) -> Result<Option<Split>> {
+        let k = FDBChainStoreActor::get_state_key(&self.chain_dir)?;
+        let v = FDBChainStoreActor::timed("get", trx.get(&k, false))
+            .await?
+            .ok_or(Error::NotInitialized)?;
+        let state = FDBChainStoreActor::decode_chain_state(&k, &v)?;
+        let blocks =
+            FDBChainStoreActor::sub_recent_blocks(trx, &self.infos_dir, state.most_work_tip)
+                .await?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(find_split(
+            &blocks,
+            state.most_work_tip,
+            self.distance,
+            self.window,
+            now,
+        ))
+    }
+
+    /// Look for a split, raising an event if a split has been detected or resolved.
+    async fn check(&self) -> Result<()> {
+        let mut watch = self.watch.lock().await;
+        let trx = self.db.create_trx()?;
+        let split = self.find(&trx).await?;
+        self.metrics
+            .set_competing_tips(split.as_ref().map_or(0, |s| s.tips.len() as u64));
+        if let Some(e) = watch.update(split) {
+            // there may be no subscribers
+            let _ = self.events.send(e);
+        }
+        Ok(())
+    }
+
+    /// Request a check, see check().
+    ///
+    /// There is at most one task running checks; the requests made while it is checking are
+    /// merged into a single further check.
+    fn request_check(self: &Arc<Self>) {
+        self.pending.store(true, AtomicOrdering::SeqCst);
+        if self.running.swap(true, AtomicOrdering::SeqCst) {
+            return;
+        }
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                while monitor.pending.swap(false, AtomicOrdering::SeqCst) {
+                    if let Err(e) = monitor.check().await {
+                        tracing::warn!("failed looking for a split: {}", e);
+                    }
+                }
+                monitor.running.store(false, AtomicOrdering::SeqCst);
+                // a request may have arrived after the last check but before running was cleared
+                if !monitor.pending.load(AtomicOrdering::SeqCst)
+                    || monitor.running.swap(true, AtomicOrdering::SeqCst)
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// the chain store actor
+///
+/// todo: update to use minactor
+struct FDBChainStoreActor {
+    receiver: Receiver<QueuedMessage>,
+    // the queue of the writes, which are taken before the other messages
+    write_receiver: Receiver<QueuedMessage>,
+    db: Arc<foundationdb::Database>,
+    // root directory for chainstore
+    chain_dir: DirectoryOutput,
+    // BlockInfo directory
+    infos_dir: InfosDir,
+    // hash index directory
+    h_index_dir: DirectoryOutput,
+    // reorg log directory
+    reorgs_dir: DirectoryOutput,
+    // next_id with lock
+    next_id_lock: Arc<Mutex<u8>>,
+    // whether the database was already initialized when the actor was created
+    was_initialized: bool,
+    // whether messages that write to the database are rejected
+    read_only: bool,
+    // metrics, shared with the handles
+    metrics: Arc<MetricsRecorder>,
+    // the guardrail against runaway imports, shared with the handles
+    guardrail: Arc<std::sync::Mutex<Guardrail>>,
+    // the maximum number of times a walk resets its transaction after it became too old
+    max_trx_resets: u32,
+    // how get_block_infos() reads the chain, see WalkOptions
+    walk: WalkOptions,
+    // how long the reads wait for the cluster, see read_trx()
+    profile: TrxProfile,
+    // looks for a split after writes
+    splits: Arc<SplitMonitor>,
+}
+
+impl FDBChainStoreActor {
+    // BlockInfo directory - key = BlockId or (shard, BlockId), value = BlockInfo, see InfosDir
+    const INFOS_DIR: &'static str = "infos";
+    // the number of blocks of the main chain, back from the most work tip, whose branches are
+    // searched for tips that compete with it
+    const SPLIT_SCAN_DEPTH: u64 = 100;
+    // the maximum number of blocks that are read when looking for a split
+    const SPLIT_SCAN_LIMIT: usize = 2_000;
+    // the directory that BlockInfos are copied to when migrating to a new layout
+    const INFOS_MIGRATION_DIR: &'static str = "infos-migration";
+    // Hash index directory - key = BlockHash, value = BlockId
+    const H_INDEX_DIR: &'static str = "hindex";
+    // Reorg log directory - key = (timestamp, new tip BlockId), value = ReorgEvent
+    const REORGS_DIR: &'static str = "reorgs";
+    // ChainState key name
+    const STATE_KEY: &'static str = "statekey";
+    // NextId key name
+    const NEXT_ID_KEY: &'static str = "nextid";
+    // Layout key name - value = (layout version, number of shards)
+    //
+    // stores created before the layout was recorded do not have this key and are not sharded
+    const LAYOUT_KEY: &'static str = "layout";
+    // layout versions
+    //
+    // a new layout version is needed to persist a new variant of an enum, such as a new
+    // BlockValidity, so that a library that does not know the variant refuses to open the store
+    // rather than misreading it, see decode_layout()
+    const LAYOUT_UNSHARDED: u64 = 1;
+    const LAYOUT_SHARDED: u64 = 2;
+    // the newest layout version, whose enum variants this library knows
+    const LAYOUT_LATEST: u64 = Self::LAYOUT_SHARDED;
+    // Counters key prefix - key = (COUNTS_KEY, name) or (COUNTS_KEY, VALIDITY_COUNT, validity),
+    // value = little-endian i64 so that it can be updated with atomic adds
+    const COUNTS_KEY: &'static str = "counts";
+    // counter names
+    const BLOCKS_COUNT: &'static str = "blocks";
+    const TIPS_COUNT: &'static str = "tips";
+    const VALIDITY_COUNT: &'static str = "validity";
+    // Version history key prefix - key = (VERSIONS_KEY, version),
+    // value = (version, git hash or "", comma separated features, first seen, last seen)
+    const VERSIONS_KEY: &'static str = "versions";
+    // the number of BlockInfos read in each transaction when scanning
+    const SCAN_BATCH_SIZE: usize = 1_000;
+    // the first byte of a BlockInfo in the compact encoding, see encode_block_info()
+    //
+    // the tuple layer reserves 0x40 to 0x4f for user type codes, so a BlockInfo in the full
+    // encoding, which is a packed tuple, never starts with it
+    const BLOCK_INFO_COMPACT: u8 = 0x40;
+    // the number of fields of a BlockInfo in the compact encoding, without the optional fields
+    // that are omitted when they are None
+    const COMPACT_FIELDS: usize = 7;
+
+    /// Create a new FDBChainStore.
+    ///
+    /// The root directory supplied as a parameter must be dedicated to the ChainStore. If the
+    /// ChainStore is part of a larger system, then this is probably a sub-directory of the larger
+    /// systems directory. (e.g.: vec!["bsvmain", "chainstore"]) When the foundationdb cluster is
+    /// shared with other systems, the prefix in the config keeps the directories apart, see
+    /// [ChainStoreConfig::directory_path()].
+    ///
+    /// If create is false, then the ChainStore must already be initialized. If read_only is true,
+    /// then nothing is written to the database and create must be false.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        config: &ChainStoreConfig,
+        genesis: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        create: bool,
+        read_only: bool,
+        receiver: Receiver<QueuedMessage>,
+        write_receiver: Receiver<QueuedMessage>,
+        events: broadcast::Sender<ChainEvent>,
+        profile: TrxProfile,
+    ) -> Result<FDBChainStoreActor> {
+        let root_dir = config.directory_path();
+        let db = Arc::new(foundationdb::Database::default()?);
+        let dirs = Self::get_directories(&db, &root_dir, create, read_only).await?;
+        let chain_dir = dirs.chain.clone();
+        let infos_dir = InfosDir {
+            dir: dirs.infos.clone(),
+            shards: Self::get_info_shards(&db, &chain_dir, config.info_shards).await?,
+        };
+        let h_index_dir = dirs.h_index.clone();
+        let reorgs_dir = dirs.reorgs.clone();
+        let was_initialized = Self::ensure_db_initialized(
+            &db,
+            &chain_dir,
+            infos_dir.clone(),
+            &h_index_dir,
+            genesis,
+            create,
+            read_only,
+        )
+        .await?;
+        // a read only handle does not commit the directories that it resolves, nor record its
+        // version
+        if !read_only {
+            Directories::insert(&root_dir, dirs);
+            let writer = FDBChainStore::library_version(VersionRecord::now());
+            Self::sub_record_version(&db, &chain_dir, writer, config.max_trx_resets).await?;
+        }
+        let metrics = Arc::new(MetricsRecorder::default());
+        metrics.set_tip_height(Self::read_tip_height(&db, &chain_dir, &infos_dir).await?);
+        let splits = Arc::new(SplitMonitor {
+            db: db.clone(),
+            chain_dir: chain_dir.clone(),
+            infos_dir: infos_dir.clone(),
+            distance: config.split_distance,
+            window: config.split_window,
+            watch: Mutex::new(SplitWatch::default()),
+            pending: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            events,
+            metrics: metrics.clone(),
+        });
+        Ok(FDBChainStoreActor {
+            receiver,
+            write_receiver,
+            db,
+            chain_dir,
+            infos_dir,
+            h_index_dir,
+            reorgs_dir,
+            next_id_lock: Arc::new(Mutex::new(0)),
+            was_initialized,
+            read_only,
+            metrics,
+            guardrail: Arc::new(std::sync::Mutex::new(Guardrail::new(config))),
+            max_trx_resets: config.max_trx_resets,
+            walk: WalkOptions {
+                batch_size: config.walk_batch_size.max(1),
+                rotate: Duration::from_millis(config.walk_trx_rotate_ms),
+                rotate_reads: config.walk_trx_rotate_reads.max(1),
+                max_resets: config.max_trx_resets,
+            },
+            profile,
+            splits,
+        })
+    }
+
+    // get the directories of the ChainStore, from the cache if they were resolved before
+    //
+    // the cached directories are only used if the ChainStore is found in them, otherwise they
+    // were removed since they were cached, the entry is dropped and they are resolved again
+    async fn get_directories(
+        db: &foundationdb::Database,
+        root_dir: &[String],
+        create: bool,
+        read_only: bool,
+    ) -> Result<Directories> {
+        if let Some(dirs) = Directories::cached(root_dir) {
+            let trx = db.create_trx()?;
+            let k = Self::get_state_key(&dirs.chain)?;
+            if Self::timed("get", trx.get(&k, false)).await?.is_some() {
+                return Ok(dirs);
+            }
+            Directories::invalidate(root_dir);
+        }
+        Self::timed(
+            "open_directories",
+            Self::open_directories(db, root_dir, create, read_only),
+        )
+        .await
+    }
+
+    // resolve the directories of the ChainStore in a single transaction, creating them if they
+    // don't exist
+    //
+    // if create is false then returns Error::NotInitialized if the root directory doesn't exist.
+    // When read only, the transaction is not committed so missing directories are not created,
+    // they are opened with a new prefix and are empty.
+    async fn open_directories(
+        db: &foundationdb::Database,
+        root_dir: &[String],
+        create: bool,
+        read_only: bool,
+    ) -> Result<Directories> {
+        let r_dir = foundationdb::directory::DirectoryLayer::default();
+        let trx = db.create_trx()?;
+        if !create && !r_dir.exists(&trx, root_dir).await? {
+            return Err(Error::NotInitialized);
+        }
+        let chain = r_dir.create_or_open(&trx, root_dir, None, None).await?;
+        let sub = |name: &str| vec![String::from(name)];
+        let infos = chain
+            .create_or_open(&trx, &sub(Self::INFOS_DIR), None, None)
+            .await?;
+        let h_index = chain
+            .create_or_open(&trx, &sub(Self::H_INDEX_DIR), None, None)
+            .await?;
+        let reorgs = chain
+            .create_or_open(&trx, &sub(Self::REORGS_DIR), None, None)
+            .await?;
+        if !read_only {
+            Self::timed("commit", trx.commit()).await?;
+        }
+        Ok(Directories {
+            chain,
+            infos,
+            h_index,
+            reorgs,
+        })
+    }
+
+    // read the height of the most work tip in a new transaction
+    async fn read_tip_height(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<u64> {
+        let trx = db.create_trx()?;
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(&k, false))
+            .await?
+            .ok_or(Error::NotInitialized)?;
+        let state = Self::decode_chain_state(&k, &v)?;
+        let tip = Self::sub_expect_block_info(&trx, infos_dir, state.most_work_tip).await?;
+        Ok(tip.height)
+    }
+
+    // ensure that database is initialized, returning true if it was already initialized
+    //
+    // if create is false then returns Error::NotInitialized instead of initializing the database
+    //
+    // if read_only is true then missing counters are not initialized
+    //
+    // if the database is already initialized then its genesis BlockInfo must have the hash of gbi,
+    // otherwise returns Error::NetworkMismatch
+    async fn ensure_db_initialized(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        info_dir: InfosDir,
+        h_index_dir: &DirectoryOutput,
+        gbi: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        create: bool,
+        read_only: bool,
+    ) -> Result<bool> {
+        let trx = db.create_trx()?;
+        let state_key = Self::get_state_key(chain_dir).unwrap();
+        let v = trx.get(&state_key, false).await?;
+        if v.is_none() {
+            if !create {
+                return Err(Error::NotInitialized);
+            }
+            // initialize database
+            // set chain_state
+            let v = Self::encode_chain_state(&ChainState {
+                most_work_tip: 0,
+                active_tips: vec![0],
+                dormant_tips: vec![],
+                invalid_tips: vec![],
+            });
+            trx.set(&state_key, &v);
+            // set next_id
+            let v = Self::encode_next_id(1);
+            let k = Self::get_next_id_key(chain_dir).unwrap();
+            trx.set(&k, &v);
+            // record the layout
+            let k = Self::get_layout_key(chain_dir)?;
+            trx.set(&k, &Self::encode_layout(info_dir.shards));
+            // store genesis BlockInfo
+            let k2 = Self::get_block_info_key(&info_dir, 0).unwrap();
+            let v2 = Self::encode_block_info(&gbi);
+            trx.set(&k2, &v2);
+            let k3 = Self::get_h_index_key(h_index_dir, &gbi.hash).unwrap();
+            let v3 = Self::encode_h_index(0);
+            trx.set(&k3, &v3);
+            // initialize counters
+            trx.set(
+                &Self::get_counter_key(chain_dir, Self::BLOCKS_COUNT)?,
+                &Self::encode_counter(1),
+            );
+            trx.set(
+                &Self::get_counter_key(chain_dir, Self::TIPS_COUNT)?,
+                &Self::encode_counter(1),
+            );
+            for validity in BlockValidity::ALL {
+                let n = if validity == gbi.validity { 1 } else { 0 };
+                trx.set(
+                    &Self::get_validity_counter_key(chain_dir, &validity)?,
+                    &Self::encode_counter(n),
+                );
+            }
+            trx.commit().await?;
+            Ok(false)
+        } else {
+            let stored = Self::sub_expect_block_info(&trx, &info_dir, 0).await?;
+            if stored.hash != gbi.hash {
+                return Err(Error::NetworkMismatch {
+                    expected: gbi.hash,
+                    found: stored.hash,
+                });
+            }
+            let k = Self::get_counter_key(chain_dir, Self::BLOCKS_COUNT)?;
+            let c = trx.get(&k, false).await?;
+            trx.cancel();
+            if c.is_none() && !read_only {
+                // store created before counters were maintained, initialize them with a scan
+                let counts = Self::scan_counters(db, chain_dir, &info_dir).await?;
+                let trx = db.create_trx()?;
+                Self::set_counters(&trx, chain_dir, &counts)?;
+                trx.commit().await?;
+            }
+            Ok(true)
+        }
+    }
+
+    // spawn a task which is traced in the current span
+    fn spawn<F>(f: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        tokio::spawn(f.in_current_span())
+    }
+
+    // run a FoundationDB operation in a child span, recording the elapsed time in microseconds
+    //
+    // op should be "get", "range", or "commit"
+    async fn timed<F: Future>(op: &'static str, f: F) -> F::Output {
+        let span = debug_span!("fdb", op, elapsed_us = field::Empty);
+        if span.is_disabled() {
+            return f.await;
+        }
+        let start = Instant::now();
+        let r = f.instrument(span.clone()).await;
+        span.record("elapsed_us", start.elapsed().as_micros() as u64);
+        r
+    }
+
+    // create the transaction of a read with a single reply, with the timeout of the profile of the
+    // actor, the outcome of the read is passed through self.profile.check()
+    fn read_trx(&self) -> Result<Transaction> {
+        self.profile.create_trx(&self.db)
+    }
+
+    // get the key for a counter
+    fn get_counter_key(chain_dir: &DirectoryOutput, name: &str) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&(Self::COUNTS_KEY, name))?)
+    }
+
+    // get the key for the counter of blocks with the given validity
+    fn get_validity_counter_key(
+        chain_dir: &DirectoryOutput,
+        validity: &BlockValidity,
+    ) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&(
+            Self::COUNTS_KEY,
+            Self::VALIDITY_COUNT,
+            u8::from(validity.clone()) as i64,
+        ))?)
+    }
+
+    // decode a counter from fdb
+    pub(crate) fn decode_counter(v: &[u8]) -> u64 {
+        let mut b = [0u8; 8];
+        let n = v.len().min(8);
+        b[..n].copy_from_slice(&v[..n]);
+        i64::from_le_bytes(b) as u64
+    }
+
+    // encode a counter into fdb
+    pub(crate) fn encode_counter(v: u64) -> Vec<u8> {
+        (v as i64).to_le_bytes().to_vec()
+    }
+
+    // add delta to a counter, as part of a larger transaction
+    fn add_to_counter(trx: &Transaction, key: &[u8], delta: i64) {
+        trx.atomic_op(key, &delta.to_le_bytes(), MutationType::Add);
+    }
+
+    // read the counters, as part of a larger transaction
+    async fn get_counters(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+    ) -> Result<Vec<(String, u64)>> {
+        let mut r = Vec::new();
+        for name in [Self::BLOCKS_COUNT, Self::TIPS_COUNT] {
+            let k = Self::get_counter_key(chain_dir, name)?;
+            let v = trx.get(&k, false).await?;
+            r.push((
+                String::from(name),
+                v.map(|v| Self::decode_counter(&v)).unwrap_or(0),
+            ));
+        }
+        for validity in BlockValidity::ALL {
+            let k = Self::get_validity_counter_key(chain_dir, &validity)?;
+            let v = trx.get(&k, false).await?;
+            r.push((
+                Self::validity_counter_name(&validity),
+                v.map(|v| Self::decode_counter(&v)).unwrap_or(0),
+            ));
+        }
+        Ok(r)
+    }
+
+    // set the counters, as part of a larger transaction
+    fn set_counters(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+        counts: &[(String, u64)],
+    ) -> Result<()> {
+        for (name, n) in counts {
+            let k = match BlockValidity::ALL
+                .iter()
+                .find(|v| Self::validity_counter_name(v) == *name)
+            {
+                Some(validity) => Self::get_validity_counter_key(chain_dir, validity)?,
+                None => Self::get_counter_key(chain_dir, name)?,
+            };
+            trx.set(&k, &Self::encode_counter(*n));
+        }
+        Ok(())
+    }
+
+    // the name of the counter of blocks with the given validity
+    fn validity_counter_name(validity: &BlockValidity) -> String {
+        format!("{}/{:?}", Self::VALIDITY_COUNT, validity)
+    }
+
+    // Call f for every BlockInfo, in order of BlockId.
+    //
+    // BlockIds are allocated sequentially, so the BlockInfos are read in windows of BlockIds
+    // with a new transaction for each window so that the scan is not limited by the transaction
+    // time limit. Each window is read from all of the shards and merged.
+    async fn scan_block_infos<F>(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(BlockInfo<<FDBChainStore as ChainStore>::BlockId>),
+    {
+        let next_id = Self::read_next_id(db, chain_dir).await?;
+        let mut start = 0;
+        while start < next_id {
+            let end = start + Self::SCAN_BATCH_SIZE as u64;
+            let trx = db.create_trx()?;
+            for b_info in Self::read_block_info_window(&trx, infos_dir, start, end).await? {
+                f(b_info);
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    // read the next_id in a new transaction
+    async fn read_next_id(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+    ) -> Result<<FDBChainStore as ChainStore>::BlockId> {
+        let trx = db.create_trx()?;
+        let k = Self::get_next_id_key(chain_dir)?;
+        match Self::timed("get", trx.get(&k, false)).await? {
+            Some(v) => Ok(Self::decode_next_id(&v)),
+            None => Err(Error::NotInitialized),
+        }
+    }
+
+    // read the BlockInfos with start <= BlockId < end, in order of BlockId
+    async fn read_block_info_window(
+        trx: &Transaction,
+        infos_dir: &InfosDir,
+        start: <FDBChainStore as ChainStore>::BlockId,
+        end: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>> {
+        let reads = infos_dir.ranges(start, end)?.into_iter().map(|opt| {
+            trx.get_ranges_keyvalues(opt, false)
+                .map_ok(|kv| Self::decode_block_info(kv.key(), kv.value()))
+                .try_collect::<Vec<_>>()
+        });
+        let shards = Self::timed("range", try_join_all(reads))
+            .await?
+            .into_iter()
+            .map(|s| s.into_iter().collect::<Result<Vec<_>>>())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(merge_shards(shards))
+    }
+
+    // Compute the counters by scanning every BlockInfo.
+    async fn scan_counters(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<Vec<(String, u64)>> {
+        let mut blocks = 0u64;
+        let mut tips = 0u64;
+        let mut validities = [0u64; 6];
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| {
+            blocks += 1;
+            if b_info.next_ids.is_empty() {
+                tips += 1;
+            }
+            validities[u8::from(b_info.validity) as usize] += 1;
+        })
+        .await?;
+        let mut r = vec![
+            (String::from(Self::BLOCKS_COUNT), blocks),
+            (String::from(Self::TIPS_COUNT), tips),
+        ];
+        for validity in BlockValidity::ALL {
+            let n = validities[u8::from(validity.clone()) as usize];
+            r.push((Self::validity_counter_name(&validity), n));
+        }
+        Ok(r)
+    }
+
+    // get the key for the state
+    fn get_state_key(chain_dir: &DirectoryOutput) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&Self::STATE_KEY)?)
+    }
+
+    // decode ChainState from fdb format, the key is only used in the error
+    pub(crate) fn decode_chain_state(
+        k: &[u8],
+        v: &[u8],
+    ) -> Result<ChainState<<FDBChainStore as ChainStore>::BlockId>> {
+        let (most_work_tip, a, d, i) = unpack::<(u64, Element, Element, Element)>(v)
+            .map_err(|_| Self::corrupt(k, "ChainState is not a tuple of four fields"))?;
+        let tips = |e: &Element| -> Result<Vec<u64>> {
+            e.as_tuple()
+                .ok_or_else(|| Self::corrupt(k, "tips are not a tuple"))?
+                .iter()
+                .map(|j| {
+                    // the most work tip is unpacked as a u64, which can not be negative
+                    j.as_i64()
+                        .and_then(|j| u64::try_from(j).ok())
+                        .ok_or_else(|| Self::corrupt(k, "tip is not a block id"))
+                })
+                .collect()
+        };
+        Ok(ChainState {
+            most_work_tip,
+            active_tips: tips(&a)?,
+            dormant_tips: tips(&d)?,
+            invalid_tips: tips(&i)?,
+        })
+    }
+
+    // encode ChainState to fdb format
+    pub(crate) fn encode_chain_state(
+        cs: &ChainState<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Vec<u8> {
+        pack(&(
+            cs.most_work_tip,
+            &cs.active_tips,
+            &cs.dormant_tips,
+            &cs.invalid_tips,
+        ))
+    }
+
+    // get the key for the next_id
+    fn get_next_id_key(chain_dir: &DirectoryOutput) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&Self::NEXT_ID_KEY)?)
+    }
+
+    // decode the next_id from fdb
+    pub(crate) fn decode_next_id(v: &[u8]) -> <FDBChainStore as ChainStore>::BlockId {
+        let (i,) = unpack(v).expect("unpack failed in decode_next_id()");
+        i
+    }
+
+    // encode the next_id into fdb
+    pub(crate) fn encode_next_id(v: <FDBChainStore as ChainStore>::BlockId) -> Vec<u8> {
+        pack(&(v,))
+    }
+
+    // get the key for the layout
+    fn get_layout_key(chain_dir: &DirectoryOutput) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&Self::LAYOUT_KEY)?)
+    }
+
+    // get the key for the record of a version in the version history
+    fn get_version_key(chain_dir: &DirectoryOutput, version: &str) -> Result<Vec<u8>> {
+        Ok(chain_dir.pack(&(Self::VERSIONS_KEY, version))?)
+    }
+
+    // get the range of the keys of the version history
+    fn get_versions_range(chain_dir: &DirectoryOutput) -> Result<(Vec<u8>, Vec<u8>)> {
+        let start = chain_dir.pack(&Self::VERSIONS_KEY)?;
+        let mut end = start.clone();
+        end.push(0xff);
+        Ok((start, end))
+    }
+
+    // encode the record of a version into fdb
+    fn encode_version(r: &VersionRecord) -> Vec<u8> {
+        pack(&(
+            r.version.as_str(),
+            r.git_hash.as_deref().unwrap_or(""),
+            r.features.join(","),
+            r.first_seen,
+            r.last_seen,
+        ))
+    }
+
+    // decode the record of a version from fdb
+    fn decode_version(k: &[u8], v: &[u8]) -> Result<VersionRecord> {
+        let (version, git_hash, features, first_seen, last_seen): (
+            String,
+            String,
+            String,
+            u64,
+            u64,
+        ) = unpack(v).map_err(|_| Self::corrupt(k, "version is not a tuple of five fields"))?;
+        Ok(VersionRecord {
+            version,
+            git_hash: (!git_hash.is_empty()).then_some(git_hash),
+            features: features
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect(),
+            first_seen,
+            last_seen,
+        })
+    }
+
+    // decode the layout from fdb, returning the number of shards
+    pub(crate) fn decode_layout(v: &[u8]) -> Result<u64> {
+        let (version, shards): (u64, u64) =
+            unpack(v).map_err(|_| Error::Internal("unpack failed in decode_layout()".into()))?;
+        match version {
+            Self::LAYOUT_UNSHARDED => Ok(0),
+            Self::LAYOUT_SHARDED => Ok(shards),
+            _ => Err(Error::UnsupportedLayout(version)),
+        }
+    }
+
+    // encode the layout into fdb
+    pub(crate) fn encode_layout(shards: u64) -> Vec<u8> {
+        if shards == 0 {
+            pack(&(Self::LAYOUT_UNSHARDED, 0u64))
+        } else {
+            pack(&(Self::LAYOUT_SHARDED, shards))
+        }
+    }
+
+    // get the number of shards of the BlockInfo directory
+    //
+    // new_shards is used if the ChainStore has not been initialized
+    async fn get_info_shards(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        new_shards: u64,
+    ) -> Result<u64> {
+        let trx = db.create_trx()?;
+        if let Some(v) = trx.get(&Self::get_layout_key(chain_dir)?, false).await? {
+            return Self::decode_layout(&v);
+        }
+        match trx.get(&Self::get_state_key(chain_dir)?, false).await? {
+            // initialized before the layout was recorded
+            Some(_) => Ok(0),
+            None => Ok(new_shards),
+        }
+    }
+
+    // Convert the BlockInfo directory to a new number of shards.
+    //
+    // The BlockInfos are copied into a new directory in batches and then the new directory
+    // replaces the old one and the layout is updated in a single transaction.
+    async fn migrate_info_shards(config: &ChainStoreConfig, shards: u64) -> Result<()> {
+        let root_dir = config.directory_path();
+        let db = foundationdb::Database::default()?;
+        let r_dir = foundationdb::directory::DirectoryLayer::default();
+        let trx = db.create_trx()?;
+        if !r_dir.exists(&trx, &root_dir).await? {
+            return Err(Error::NotInitialized);
+        }
+        let chain_dir = r_dir.open(&trx, &root_dir, None).await?;
+        let infos_path = vec![String::from(Self::INFOS_DIR)];
+        let old = InfosDir {
+            dir: chain_dir.open(&trx, &infos_path, None).await?,
+            shards: Self::get_info_shards(&db, &chain_dir, shards).await?,
+        };
+        if old.shards == shards {
+            return Ok(());
+        }
+        let next_id = Self::read_next_id(&db, &chain_dir).await?;
+        // copy the BlockInfos into the new directory
+        let tmp_path = vec![String::from(Self::INFOS_MIGRATION_DIR)];
+        let trx = db.create_trx()?;
+        chain_dir.remove_if_exists(&trx, &tmp_path).await?;
+        let new = InfosDir {
+            dir: chain_dir.create(&trx, &tmp_path, None, None).await?,
+            shards,
+        };
+        trx.commit().await?;
+        let mut start = 0;
+        while start < next_id {
+            let end = start + Self::SCAN_BATCH_SIZE as u64;
+            let trx = db.create_trx()?;
+            for b_info in Self::read_block_info_window(&trx, &old, start, end).await? {
+                trx.set(&new.key(b_info.id)?, &Self::encode_block_info(&b_info));
+            }
+            Self::timed("commit", trx.commit()).await?;
+            start = end;
+        }
+        // replace the old directory and record the new layout
+        let trx = db.create_trx()?;
+        chain_dir.remove(&trx, &infos_path).await?;
+        chain_dir.move_to(&trx, &tmp_path, &infos_path).await?;
+        trx.set(
+            &Self::get_layout_key(&chain_dir)?,
+            &Self::encode_layout(shards),
+        );
+        trx.commit().await?;
+        // the cached BlockInfo directory is the one that was replaced
+        Directories::invalidate(&root_dir);
+        Ok(())
+    }
+
+    // get the key for the BlockInfo
+    fn get_block_info_key(
+        info_dir: &InfosDir,
+        block_id: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<Vec<u8>> {
+        info_dir.key(block_id)
+    }
+
+    // decode the BlockInfo from fdb, the key is only used in the error
+    pub(crate) fn decode_block_info(
+        k: &[u8],
+        v: &[u8],
+    ) -> Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>> {
+        let i = match v.first() {
+            Some(&Self::BLOCK_INFO_COMPACT) => Self::expand_compact_block_info(k, &v[1..])?,
+            // the tuple is too large for the shortcut implementation
+            _ => unpack::<Vec<Element>>(v)
+                .map_err(|_| Self::corrupt(k, "BlockInfo is not a tuple"))?,
+        };
+        // BlockInfos stored before the received time was added have 14 fields, and those stored
+        // before the source was added have 15. The fees are only stored when they are known.
+        if i.len() < 14 {
+            return Err(Self::corrupt(k, "BlockInfo has too few fields"));
+        }
+        let int = |n: usize| {
+            i[n].as_i64()
+                .map(|j| j as u64)
+                .ok_or_else(|| Self::corrupt(k, &format!("field {} is not an integer", n)))
+        };
+        let hash = match i[1].as_bytes() {
+            Some(b) if b.len() == 32 => BlockHash::from(b.to_vec().as_slice()),
+            _ => return Err(Self::corrupt(k, "invalid block hash")),
+        };
+        let header = i[2]
+            .as_bytes()
+            .and_then(|b| BlockHeader::from_binary_buf(b).ok())
+            .ok_or_else(|| Self::corrupt(k, "invalid block header"))?;
+        let next_ids = i[5]
+            .as_tuple()
+            .ok_or_else(|| Self::corrupt(k, "next_ids are not a tuple"))?
+            .iter()
+            .map(|j| {
+                j.as_i64()
+                    .map(|j| j as u64)
+                    .ok_or_else(|| Self::corrupt(k, "next_id is not an integer"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let chain_work = i[9].as_bytes().map(|j| j.to_vec());
+        let miner = i[12].as_str().map(String::from);
+        Ok(BlockInfo {
+            id: int(0)?,
+            hash,
+            header,
+            height: int(3)?,
+            prev_id: int(4)?,
+            next_ids,
+            size: i[6].as_i64().map(|j| j as u64),
+            num_tx: i[7].as_i64().map(|j| j as u64),
+            median_time: i[8].as_i64().map(|j| j as u64),
+            chain_work,
+            total_tx: i[10].as_i64().map(|j| j as u64),
+            total_size: i[11].as_i64().map(|j| j as u64),
+            miner,
+            validity: BlockValidity::decode(int(13)? as i64, Self::LAYOUT_LATEST)?,
+            received_time: i.get(14).and_then(|j| j.as_i64()).map(|j| j as u64),
+            source: i.get(15).and_then(|j| j.as_str()).map(String::from),
+            total_fees: i.get(16).and_then(|j| j.as_i64()).map(|j| j as u64),
+        })
+    }
+
+    // Expand the fields of a BlockInfo in the compact encoding, without its first byte, to the
+    // fields of the full encoding, see encode_block_info().
+    fn expand_compact_block_info<'a>(k: &[u8], v: &'a [u8]) -> Result<Vec<Element<'a>>> {
+        let mut i =
+            unpack::<Vec<Element>>(v).map_err(|_| Self::corrupt(k, "BlockInfo is not a tuple"))?;
+        if i.len() < Self::COMPACT_FIELDS {
+            return Err(Self::corrupt(k, "BlockInfo has too few fields"));
+        }
+        if i.len() > Self::COMPACT_FIELDS + 4 {
+            return Err(Self::corrupt(k, "BlockInfo has too many fields"));
+        }
+        i.resize(Self::COMPACT_FIELDS + 4, Element::Nil);
+        let rest: [Element; 5] = i.split_off(6).try_into().expect("eleven fields");
+        let [validity, median_time, chain_work, received_time, source] = rest;
+        let nil = || Element::Nil;
+        i.extend([nil(), nil(), median_time, chain_work, nil(), nil(), nil()]);
+        i.extend([validity, received_time, source]);
+        Ok(i)
+    }
+
+    // Decode the id, height, children and validity of a BlockInfo, see encode_block_info() for
+    // the fields.
+    //
+    // The other fields are skipped without being unpacked, which saves the allocation of the hash,
+    // the header and the chain work. The header is not parsed, so a record with a corrupt header
+    // is not detected.
+    pub(crate) fn decode_block_info_prefix(k: &[u8], v: &[u8]) -> Result<BlockInfoPrefix> {
+        // the validity follows the next_ids in the compact encoding
+        let (mut pos, validity) = match v.first() {
+            Some(&Self::BLOCK_INFO_COMPACT) => (1, 6),
+            _ => (0, 13),
+        };
+        let mut ints = [0i64; 14];
+        let mut has_children = false;
+        for (n, int) in ints.iter_mut().enumerate().take(validity + 1) {
+            if pos >= v.len() {
+                return Err(Self::corrupt(k, "BlockInfo has too few fields"));
+            }
+            match n {
+                n if n == 0 || n == 3 || n == 4 || n == validity => {
+                    let (i, end) = read_int(v, pos).ok_or_else(|| {
+                        Self::corrupt(k, &format!("field {} is not an integer", n))
+                    })?;
+                    *int = i;
+                    pos = end;
+                    continue;
+                }
+                5 if v[pos] != 0x05 => {
+                    return Err(Self::corrupt(k, "next_ids are not a tuple"));
+                }
+                // the tuple is empty if it ends straight away, a nil in it is 0x00 0xff
+                5 => has_children = v.get(pos + 1) != Some(&0x00) || v.get(pos + 2) == Some(&0xff),
+                _ => {}
+            }
+            pos = skip_element(v, pos)
+                .ok_or_else(|| Self::corrupt(k, &format!("field {} is not valid", n)))?;
+        }
+        Ok(BlockInfoPrefix {
+            id: ints[0] as u64,
+            height: ints[3] as u64,
+            has_children,
+            validity: BlockValidity::decode(ints[validity], Self::LAYOUT_LATEST)?,
+        })
+    }
+
+    // Decode the height and the prev_id of a BlockInfo, see encode_block_info() for the fields,
+    // to chain the reads of a walk without decoding the rest of it.
+    pub(crate) fn decode_walk_prefix(k: &[u8], v: &[u8]) -> Result<(u64, u64)> {
+        // the first fields are the same in the compact encoding
+        let mut pos = match v.first() {
+            Some(&Self::BLOCK_INFO_COMPACT) => 1,
+            _ => 0,
+        };
+        let mut ints = [0i64; 5];
+        for (n, int) in ints.iter_mut().enumerate() {
+            if pos >= v.len() {
+                return Err(Self::corrupt(k, "BlockInfo has too few fields"));
+            }
+            if n == 1 || n == 2 {
+                pos = skip_element(v, pos)
+                    .ok_or_else(|| Self::corrupt(k, &format!("field {} is not valid", n)))?;
+                continue;
+            }
+            let (i, end) = read_int(v, pos)
+                .ok_or_else(|| Self::corrupt(k, &format!("field {} is not an integer", n)))?;
+            *int = i;
+            pos = end;
+        }
+        Ok((ints[3] as u64, ints[4] as u64))
+    }
+
+    // the error for a record that could not be decoded
+    fn corrupt(k: &[u8], reason: &str) -> Error {
+        Error::CorruptRecord(k.to_vec(), String::from(reason))
+    }
+
+    // Encode the block_info into fdb.
+    //
+    // The full encoding is a tuple of the fields, with Nil for the optional fields that are None,
+    // and the fees appended only when they are known.
+    // The BlockInfo of a header whose block has not been seen has no size, count of
+    // transactions, totals, fees or miner, and is stored in the compact encoding instead, which is
+    // BLOCK_INFO_COMPACT followed by a tuple of the id, hash, header, height, prev_id, next_ids,
+    // validity, median time, chain work, received time and source, without the trailing optional
+    // fields that are None. The other BlockInfos are stored in the full encoding.
+    pub(crate) fn encode_block_info(
+        v: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Vec<u8> {
+        let header_only = v.size.is_none()
+            && v.num_tx.is_none()
+            && v.total_tx.is_none()
+            && v.total_size.is_none()
+            && v.total_fees.is_none()
+            && v.miner.is_none();
+        let mut i = Self::block_info_fields(v);
+        if !header_only {
+            return pack(&i);
+        }
+        let rest: [Element; 10] = i.split_off(6).try_into().expect("sixteen fields");
+        let [_, _, median_time, chain_work, _, _, _, validity, received_time, source] = rest;
+        i.extend([validity, median_time, chain_work, received_time, source]);
+        while i.len() > Self::COMPACT_FIELDS && matches!(i.last(), Some(Element::Nil)) {
+            i.pop();
+        }
+        let mut r = vec![Self::BLOCK_INFO_COMPACT];
+        r.extend(pack(&i));
+        r
+    }
+
+    // encode the block_info into fdb in the full encoding, whatever its fields
+    pub(crate) fn encode_block_info_full(
+        v: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Vec<u8> {
+        pack(&Self::block_info_fields(v))
+    }
+
+    // the fields of the full encoding of the block_info
+    fn block_info_fields(
+        v: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Vec<Element<'static>> {
+        let hash = Element::Bytes(Bytes::from(Vec::from(v.hash.hash)));
+        let hdr = Element::Bytes(Bytes::from(v.header.to_binary_buf().unwrap()));
+        let m = match v.miner.clone() {
+            Some(k) => Element::String(Cow::from(k)),
+            None => Element::Nil,
+        };
+        let n_i = Element::Tuple(v.next_ids.iter().map(|i| Element::Int(*i as i64)).collect());
+        let c_w = v
+            .chain_work
+            .clone()
+            .map(|j| Element::Bytes(Bytes::from(j)))
+            .unwrap_or(Element::Nil);
+        let mut r = vec![
+            Element::Int(v.id as i64),
+            hash,
+            hdr,
+            Element::Int(v.height as i64),
+            Element::Int(v.prev_id as i64),
+            n_i,
+            v.size
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            v.num_tx
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            v.median_time
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            c_w,
+            v.total_tx
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            v.total_size
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            m,
+            Element::Int(u8::from(v.validity.clone()) as i64),
+            v.received_time
+                .map(|j| Element::Int(j as i64))
+                .unwrap_or(Element::Nil),
+            v.source
+                .clone()
+                .map(|j| Element::String(Cow::from(j)))
+                .unwrap_or(Element::Nil),
+        ];
+        // the fees were added last, and are left off when they are not known so that the other
+        // BlockInfos are encoded as they were before
+        if let Some(j) = v.total_fees {
+            r.push(Element::Int(j as i64));
+        }
+        r
+    }
+
+    // get the key for the hash index
+    fn get_h_index_key(h_index_dir: &DirectoryOutput, block_hash: &BlockHash) -> Result<Vec<u8>> {
+        Ok(h_index_dir.pack(&block_hash.to_binary_buf().unwrap())?)
+    }
+
+    // decode the hash index value from fdb
+    pub(crate) fn decode_h_index(v: &[u8]) -> <FDBChainStore as ChainStore>::BlockId {
+        let (i,) = unpack(v).expect("unpack failed in decode_h_index()");
+        i
+    }
+
+    // encode the hash index value into fdb
+    pub(crate) fn encode_h_index(v: <FDBChainStore as ChainStore>::BlockId) -> Vec<u8> {
+        pack(&(v,))
+    }
+
+    // get the key for a reorg event
+    fn get_reorg_key(reorgs_dir: &DirectoryOutput, event: &ReorgEvent) -> Result<Vec<u8>> {
+        Ok(reorgs_dir.pack(&(event.timestamp, event.new_tip))?)
+    }
+
+    // decode ReorgEvent from fdb format
+    pub(crate) fn decode_reorg_event(v: &[u8]) -> ReorgEvent {
+        let (timestamp, old_tip, new_tip, fork_height, orphaned_count) =
+            unpack::<(u64, u64, u64, u64, u64)>(v).expect("unpack failed in decode_reorg_event()");
+        ReorgEvent {
+            timestamp,
+            old_tip,
+            new_tip,
+            fork_height,
+            orphaned_count,
+        }
+    }
+
+    // encode ReorgEvent to fdb format
+    pub(crate) fn encode_reorg_event(event: &ReorgEvent) -> Vec<u8> {
+        pack(&(
+            event.timestamp,
+            event.old_tip,
+            event.new_tip,
+            event.fork_height,
+            event.orphaned_count,
+        ))
+    }
+
+    // get the BlockId from the hash
+    pub(crate) async fn get_block_id_from_hash(
+        trx: &Transaction,
+        block_hash: &BlockHash,
+        h_index_dir: &DirectoryOutput,
+    ) -> Result<Option<<FDBChainStore as ChainStore>::BlockId>> {
+        let k = Self::get_h_index_key(h_index_dir, block_hash)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false)).await?;
+        if v.is_none() {
+            return Ok(None);
+        }
+        let i = Self::decode_h_index(&v.unwrap());
+        Ok(Some(i))
+    }
+
+    async fn get_next_id(
+        trx: &Transaction,
+        next_id: &Mutex<u8>,
+        chain_dir: &DirectoryOutput,
+    ) -> Result<<FDBChainStore as ChainStore>::BlockId> {
+        // only do one of these at a time to prevent db transaction clashes
+        let k = Self::get_next_id_key(chain_dir).unwrap();
+        let _lck = next_id.lock().await;
+        let v = Self::timed("get", trx.get(&k, false))
+            .await
+            .unwrap()
+            .unwrap();
+        let id = Self::decode_next_id(&v);
+        let v2 = Self::encode_next_id(id + 1);
+        trx.set(&k, &v2);
+        Ok(id)
+    }
+
+    /// Handles the ChainState message.
+    async fn handle_get_chain_state(
+        &self,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let k = Self::get_state_key(&self.chain_dir)?;
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        Ok(Self::spawn(async move {
+            let v = Self::timed("get", trx.get(k.as_slice(), false)).await;
+            let r = profile
+                .check("get_chain_state", start, v.map_err(Error::from))
+                .and_then(|v| {
+                    let v = v.expect("chainstate missing from db"); // todo: remove
+                    Self::decode_chain_state(&k, &v)
+                });
+            let r = match r {
+                Ok(state) => FDBChainStoreReply::ChainStateReply(state),
+                Err(e) => FDBChainStoreReply::ErrorReply(e),
+            };
+            reply
+                .send(r)
+                .expect("send of reply failed in get_chain_state()"); // todo: remove
+        }))
+    }
+
+    async fn get_block_info(
+        &self,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let k = Self::get_block_info_key(&self.infos_dir, db_id)?;
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        Ok(Self::spawn(async move {
+            let r = Self::timed("get", trx.get(k.as_slice(), false)).await;
+            let r = profile
+                .check("get_block_info", start, r.map_err(Error::from))
+                .and_then(|r| r.map(|i| Self::decode_block_info(&k, &i)).transpose());
+            let r = match r {
+                Ok(b_info) => FDBChainStoreReply::BlockInfoReply(b_info),
+                Err(e) => FDBChainStoreReply::ErrorReply(e),
+            };
+            reply
+                .send(r)
+                .expect("send of reply failed in get_block_info()");
+        }))
+    }
+
+    /// Handles the RawBlockInfo message.
+    async fn get_raw_block_info(
+        &self,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let k = Self::get_block_info_key(&self.infos_dir, db_id)?;
+        let trx = self.db.create_trx()?;
+        Ok(Self::spawn(async move {
+            let r = Self::timed("get", trx.get(k.as_slice(), false))
+                .await
+                .map(|v| v.map(|v| v.to_vec()))
+                .map_err(Error::from);
+            reply
+                .send(FDBChainStoreReply::RawReply(r))
+                .expect("send of reply failed in get_raw_block_info()");
+        }))
+    }
+
+    /// Gets the block info given the hash.
+    ///
+    /// Expected to be called as part of a larger transaction.
+    async fn sub_block_info_by_hash(
+        trx: &Transaction,
+        hash: &BlockHash,
+        h_index_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>> {
+        match Self::get_block_id_from_hash(trx, hash, h_index_dir).await? {
+            None => Ok(None),
+            Some(id) => {
+                let k = Self::get_block_info_key(infos_dir, id)?;
+                let r = Self::timed("get", trx.get(k.as_slice(), false))
+                    .await
+                    .unwrap();
+                r.map(|i| Self::decode_block_info(&k, &i)).transpose()
+            }
+        }
+    }
+
+    /// Implements [ChainStore::get_block_briefs()].
+    async fn get_block_briefs(
+        &self,
+        hashes: Vec<BlockHash>,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let h_index_dir = self.h_index_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r =
+                Self::sub_block_briefs(&db, &hashes, &chain_dir, &h_index_dir, &infos_dir).await;
+            reply
+                .send(FDBChainStoreReply::BriefsReply(r))
+                .expect("send of reply failed in get_block_briefs()");
+        }))
+    }
+
+    // Look up the blocks in batches, with a transaction for each batch. The hash index gets of a
+    // batch are made together, then the BlockInfo gets, and only the prefix of each BlockInfo is
+    // decoded.
+    async fn sub_block_briefs(
+        db: &foundationdb::Database,
+        hashes: &[BlockHash],
+        chain_dir: &DirectoryOutput,
         h_index_dir: &DirectoryOutput,
-        chain: BlockchainId,
-    ) -> Result<()> {
-        let trx = db.create_trx()?;
-        let state_key = Self::get_state_key(chain_dir).unwrap();
-        let v = trx.get(&state_key, false).await?;
-        if v.is_none() {
-            // initialize database
-            // set chain_state
-            let v = Self::encode_chain_state(&ChainState {
-                most_work_tip: 0,
-                active_tips: vec![0],
-                dormant_tips: vec![],
-                invalid_tips: vec![],
+        infos_dir: &InfosDir,
+    ) -> Result<Vec<Option<BlockBrief<<FDBChainStore as ChainStore>::BlockId>>>> {
+        let mut briefs = Vec::with_capacity(hashes.len());
+        for batch in hashes.chunks(Self::SCAN_BATCH_SIZE) {
+            let trx = db.create_trx()?;
+            let k = Self::get_state_key(chain_dir)?;
+            let v = Self::timed("get", trx.get(k.as_slice(), false))
+                .await?
+                .ok_or(Error::Internal("chainstate missing from db".into()))?;
+            let tip_id = Self::decode_chain_state(&k, &v)?.most_work_tip;
+            let tip = Self::get_block_info_prefix(&trx, infos_dir, tip_id)
+                .await?
+                .ok_or(Error::BlockInfoNotFound(tip_id))?;
+            let ids = try_join_all(
+                batch
+                    .iter()
+                    .map(|h| Self::get_block_id_from_hash(&trx, h, h_index_dir)),
+            )
+            .await?;
+            let reads = ids.into_iter().map(|id| {
+                let trx = &trx;
+                async move {
+                    match id {
+                        None => Ok(None),
+                        Some(id) => Self::get_block_info_prefix(trx, infos_dir, id).await,
+                    }
+                }
             });
-            trx.set(&state_key, &v);
-            // set next_id
-            let v = Self::encode_next_id(1);
-            let k = Self::get_next_id_key(chain_dir).unwrap();
+            for prefix in try_join_all(reads).await? {
+                briefs.push(prefix.map(|p| p.brief(&tip)));
+            }
+        }
+        Ok(briefs)
+    }
+
+    // get the prefix of the BlockInfo with the id
+    async fn get_block_info_prefix(
+        trx: &Transaction,
+        infos_dir: &InfosDir,
+        id: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<Option<BlockInfoPrefix>> {
+        let k = Self::get_block_info_key(infos_dir, id)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false)).await?;
+        v.map(|v| Self::decode_block_info_prefix(&k, &v))
+            .transpose()
+    }
+
+    /// Handles the actor BlockInfo message.
+    async fn get_block_info_by_hash(
+        &self,
+        hash: BlockHash,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        let h_index_dir = self.h_index_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_block_info_by_hash(&trx, &hash, &h_index_dir, &infos_dir).await;
+            let r = match profile.check("get_block_info_by_hash", start, r) {
+                Ok(b_info) => FDBChainStoreReply::BlockInfoReply(b_info),
+                Err(e) => FDBChainStoreReply::ErrorReply(e),
+            };
+            reply.send(r).expect("failed to send reply");
+        }))
+    }
+
+    // The block ids are assigned in ascending order and there are comparatively few forks, so the
+    // parent of a block usually has the id before it. The walk reads the BlockInfos of a run of
+    // ids at once on that guess, see read_walk_run().
+    async fn get_block_infos(
+        &self,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+        max_blocks: Option<u64>,
+        tx: Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.db.create_trx()?;
+        let infos_dir = self.infos_dir.clone();
+        let num_blocks = max_blocks.unwrap_or(u64::MAX);
+        let walk = self.walk;
+        reply
+            .send(FDBChainStoreReply::BlockInfosReply)
+            .expect("failed to send reply");
+        Ok(Self::spawn(async move {
+            if let Err(e) =
+                Self::sub_block_infos(trx, &infos_dir, db_id, num_blocks, walk, &tx).await
+            {
+                let _ = tx.send(Err(e)).await;
+            }
+        }))
+    }
+
+    // send the BlockInfos from the block down towards genesis to the channel, until num_blocks
+    // have been sent, genesis is reached or the receiver is dropped
+    //
+    // an unknown starting block results in an empty stream, a missing ancestor is an error
+    //
+    // The chain is read in runs, see read_walk_run(), and the next run is read while the blocks
+    // of the current one are decoded and sent. A run guesses the ids of the blocks while they have
+    // been contiguous, and otherwise chains single reads. The transaction is replaced by a fresh
+    // one once it has been used for walk.rotate or for walk.rotate_reads reads, before
+    // foundationdb ends it as too old, which would stall the walk until the error. A transaction
+    // that is ended anyway is reset, up to walk.max_resets times.
+    async fn sub_block_infos(
+        mut trx: Transaction,
+        infos_dir: &InfosDir,
+        id: <FDBChainStore as ChainStore>::BlockId,
+        num_blocks: u64,
+        walk: WalkOptions,
+        tx: &Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ) -> Result<()> {
+        if num_blocks == 0 {
+            return Ok(());
+        }
+        let mut sent = 0u64;
+        let mut resets = 0u32;
+        let mut started = Instant::now();
+        let mut reads = 0u64;
+        let mut at = id;
+        let mut guess = true;
+        let n = walk.batch_size.min(num_blocks);
+        let mut read = Self::read_walk_run(&trx, infos_dir, at, n, guess).await;
+        loop {
+            let run = match read {
+                Ok(run) => run,
+                Err(Error::FdbError(e)) if e.code() == 1007 && resets < walk.max_resets => {
+                    // transaction too old, reset the transaction and read the run again
+                    resets += 1;
+                    trx.reset();
+                    started = Instant::now();
+                    reads = 0;
+                    let n = walk.batch_size.min(num_blocks - sent);
+                    read = Self::read_walk_run(&trx, infos_dir, at, n, guess).await;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if run.values.is_empty() {
+                return match sent {
+                    0 => Ok(()),
+                    _ => Err(Error::BlockInfoNotFound(at)),
+                };
+            }
+            let remaining = num_blocks - sent - run.values.len() as u64;
+            reads += run.reads;
+            if started.elapsed() >= walk.rotate || reads >= walk.rotate_reads {
+                trx.reset();
+                started = Instant::now();
+                reads = 0;
+            }
+            // guessing wastes the reads of a run whose ids are not contiguous, such as across a
+            // fork, so the next run chains single reads until the ids are contiguous again
+            guess = run.contiguous;
+            let ahead = run.next.filter(|_| remaining > 0);
+            let next_read = async {
+                match ahead {
+                    Some(n) => {
+                        let n_blocks = walk.batch_size.min(remaining);
+                        Some(Self::read_walk_run(&trx, infos_dir, n, n_blocks, guess).await)
+                    }
+                    None => None,
+                }
+            };
+            let (receiving, next_read) = futures::join!(Self::send_walk_run(&run, tx), next_read);
+            if !receiving? {
+                return Ok(());
+            }
+            sent += run.values.len() as u64;
+            match (ahead, next_read) {
+                (Some(n), Some(r)) => {
+                    at = n;
+                    read = r;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Read a run of the chain from the block with the id down towards genesis, of at most n
+    // blocks.
+    //
+    // If guess is set, the BlockInfos of the n ids from id down are read together, guessing that
+    // the parent of each block has the id before it, which holds for blocks that were stored in
+    // order. The guesses are chained by decoding only the height and the prev_id of each
+    // BlockInfo, see decode_walk_prefix(). After the first parent that was not guessed, and
+    // throughout if guess is not set, the run continues with single reads, each of which is
+    // issued as soon as the prev_id of the one before it is decoded. The run ends after n blocks,
+    // at genesis or at a block that is not found, and is empty if the first block is not found.
+    async fn read_walk_run(
+        trx: &Transaction,
+        infos_dir: &InfosDir,
+        id: <FDBChainStore as ChainStore>::BlockId,
+        n: u64,
+        guess: bool,
+    ) -> Result<WalkRun> {
+        let n = n.max(1);
+        let mut run = WalkRun {
+            values: Vec::with_capacity(n as usize),
+            next: Some(id),
+            reads: 0,
+            contiguous: true,
+        };
+        if guess {
+            let ids = (id.saturating_sub(n - 1)..=id).rev();
+            let mut values = try_join_all(ids.map(|i| async move {
+                let k = Self::get_block_info_key(infos_dir, i)?;
+                let v = Self::timed("get", trx.get(k.as_slice(), false)).await?;
+                Ok::<_, Error>((k, v))
+            }))
+            .await?;
+            run.reads = values.len() as u64;
+            // the values are of the ids from id down, the parents of other ids have not been read
+            while let Some(cur) = run.next {
+                let Some((k, v)) = id.checked_sub(cur).and_then(|i| values.get_mut(i as usize))
+                else {
+                    break;
+                };
+                // a block that was read and not found ends the run
+                let Some(v) = v.take() else {
+                    return Ok(run);
+                };
+                run.push(std::mem::take(k), v.to_vec())?;
+            }
+        }
+        while run.values.len() < n as usize {
+            let Some(cur) = run.next else {
+                break;
+            };
+            let k = Self::get_block_info_key(infos_dir, cur)?;
+            let v = Self::timed("get", trx.get(k.as_slice(), false)).await?;
+            run.reads += 1;
+            let Some(v) = v else {
+                break;
+            };
+            run.push(k, v.to_vec())?;
+        }
+        Ok(run)
+    }
+
+    // decode the BlockInfos of the run and send them to the channel, returning false if the
+    // receiver was dropped
+    async fn send_walk_run(
+        run: &WalkRun,
+        tx: &Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ) -> Result<bool> {
+        for (k, v) in run.values.iter() {
+            if tx.send(Ok(Self::decode_block_info(k, v)?)).await.is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Handles the BlockInfosByValidity message.
+    ///
+    /// The BlockInfos are scanned in windows like scan_block_infos(), sending the matching
+    /// BlockInfos to the stream. The scan stops early if the stream is dropped.
+    async fn get_block_infos_by_validity(
+        &self,
+        validity: BlockValidity,
+        tx: Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        reply
+            .send(FDBChainStoreReply::BlockInfosReply)
+            .expect("failed to send reply");
+        Ok(Self::spawn(async move {
+            if let Err(e) =
+                Self::sub_block_infos_by_validity(&db, &chain_dir, &infos_dir, validity, &tx).await
+            {
+                let _ = tx.send(Err(e)).await;
+            }
+        }))
+    }
+
+    // send the BlockInfos with the validity to the channel, until the receiver is dropped
+    async fn sub_block_infos_by_validity(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        validity: BlockValidity,
+        tx: &Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ) -> Result<()> {
+        let next_id = Self::read_next_id(db, chain_dir).await?;
+        let mut start = 0;
+        while start < next_id {
+            let end = start + Self::SCAN_BATCH_SIZE as u64;
+            let trx = db.create_trx()?;
+            for b_info in Self::read_block_info_window(&trx, infos_dir, start, end).await? {
+                if b_info.validity == validity && tx.send(Ok(b_info)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Handles the MainChainInfos message.
+    ///
+    /// The ids of the main chain are collected by walking down from the most work tip. A block
+    /// always has a larger id than its parent, so the BlockInfos are then scanned in windows in
+    /// order of id, which is the order of height on the main chain, and the main chain BlockInfos
+    /// are sent to the stream. The scan stops early if the stream is dropped.
+    async fn get_main_chain_infos(
+        &self,
+        tx: Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let max_resets = self.max_trx_resets;
+        reply
+            .send(FDBChainStoreReply::BlockInfosReply)
+            .expect("failed to send reply");
+        Ok(Self::spawn(async move {
+            if let Err(e) =
+                Self::sub_main_chain_infos(&db, &chain_dir, &infos_dir, max_resets, &tx).await
+            {
+                let _ = tx.send(Err(e)).await;
+            }
+        }))
+    }
+
+    // send the BlockInfos of the main chain to the channel, until the receiver is dropped
+    async fn sub_main_chain_infos(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        max_resets: u32,
+        tx: &Sender<Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    ) -> Result<()> {
+        let mut trx = db.create_trx()?;
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let tip = Self::decode_chain_state(&k, &v)?.most_work_tip;
+        let mut main_chain = IdSet::default();
+        let mut id = tip;
+        let mut resets = 0u32;
+        loop {
+            let k = Self::get_block_info_key(infos_dir, id)?;
+            match Self::timed("get", trx.get(k.as_slice(), false)).await {
+                Err(e) => match e.code() {
+                    1007 if resets < max_resets => {
+                        // transaction too old, reset the transaction and continue
+                        resets += 1;
+                        trx.reset();
+                    }
+                    _ => return Err(e.into()),
+                },
+                Ok(None) => return Err(Error::BlockInfoNotFound(id)),
+                Ok(Some(v)) => {
+                    let b_info = Self::decode_block_info(&k, &v)?;
+                    main_chain.insert(b_info.id);
+                    if b_info.height == 0 {
+                        break;
+                    }
+                    id = b_info.prev_id;
+                }
+            }
+        }
+        let mut start = 0;
+        while start <= tip {
+            let end = start + Self::SCAN_BATCH_SIZE as u64;
+            let trx = db.create_trx()?;
+            for b_info in Self::read_block_info_window(&trx, infos_dir, start, end).await? {
+                if b_info.id <= tip
+                    && main_chain.contains(b_info.id)
+                    && tx.send(Ok(b_info)).await.is_err()
+                {
+                    return Ok(());
+                }
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Handles the IsOnMainChain message.
+    async fn is_on_main_chain(
+        &self,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let max_resets = self.max_trx_resets;
+        Ok(Self::spawn(async move {
+            let r =
+                Self::sub_is_on_main_chain(trx, db_id, &chain_dir, &infos_dir, max_resets).await;
+            let r = profile.check("is_on_main_chain", start, r);
+            reply
+                .send(FDBChainStoreReply::BoolReply(r))
+                .expect("send of reply failed in is_on_main_chain()");
+        }))
+    }
+
+    // Walk down from the most work tip to the height of the block and check whether we arrive at
+    // the block.
+    async fn sub_is_on_main_chain(
+        mut trx: Transaction,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        max_resets: u32,
+    ) -> Result<bool> {
+        let k = Self::get_block_info_key(infos_dir, db_id)?;
+        let height = match Self::timed("get", trx.get(k.as_slice(), false)).await? {
+            None => return Ok(false),
+            Some(v) => Self::decode_block_info(&k, &v)?.height,
+        };
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let mut id = Self::decode_chain_state(&k, &v)?.most_work_tip;
+        let mut resets = 0u32;
+        loop {
+            let k = Self::get_block_info_key(infos_dir, id)?;
+            match Self::timed("get", trx.get(k.as_slice(), false)).await {
+                Err(e) => match e.code() {
+                    1007 if resets < max_resets => {
+                        // transaction too old, reset the transaction and continue
+                        resets += 1;
+                        trx.reset();
+                    }
+                    _ => return Err(e.into()),
+                },
+                Ok(None) => return Err(Error::BlockInfoNotFound(id)),
+                Ok(Some(v)) => {
+                    let b_info = Self::decode_block_info(&k, &v)?;
+                    if b_info.height <= height {
+                        return Ok(b_info.id == db_id);
+                    }
+                    id = b_info.prev_id;
+                }
+            }
+        }
+    }
+
+    /// Implements [FDBChainStore::store_block_info_with_options()].
+    async fn store_block_info(
+        &self,
+        block_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        options: InsertOptions,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.db.create_trx()?;
+        let h_index_dir = self.h_index_dir.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let reorgs_dir = self.reorgs_dir.clone();
+        let next_id_lck = self.next_id_lock.clone();
+        let metrics = self.metrics.clone();
+        let guardrail = self.guardrail.clone();
+        Ok(Self::spawn(async move {
+            let r = async {
+                let (block_info, new) = Self::sub_store_block_info(
+                    &trx,
+                    &h_index_dir,
+                    &chain_dir,
+                    &infos_dir,
+                    &next_id_lck,
+                    block_info,
+                )
+                .await?;
+                // the height is known once the block is linked, the transaction is dropped
+                // without being committed if the block trips the guardrail
+                guardrail.lock().unwrap().check(
+                    new as u64,
+                    block_info.height,
+                    metrics.tip_height(),
+                    options,
+                )?;
+                // update the chain state if necessary
+                let new_tip =
+                    Self::sub_update_tip(&trx, &chain_dir, &infos_dir, &reorgs_dir, &block_info)
+                        .await?;
+                Self::timed("commit", trx.commit()).await?;
+                // the block is only counted once it has been stored
+                guardrail.lock().unwrap().record(new as u64);
+                if new_tip {
+                    metrics.set_tip_height(block_info.height);
+                }
+                Ok::<_, Error>(block_info)
+            }
+            .await;
+            let r = match r {
+                Ok(block_info) => FDBChainStoreReply::BlockInfoReply(Some(block_info)),
+                Err(e) => FDBChainStoreReply::ErrorReply(e),
+            };
+            // the caller may have gone away
+            let _ = reply.send(r);
+        }))
+    }
+
+    /// Implements [FDBChainStore::store_block_infos_with_options()].
+    async fn store_block_infos(
+        &self,
+        blocks: Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+        options: InsertOptions,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let h_index_dir = self.h_index_dir.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let reorgs_dir = self.reorgs_dir.clone();
+        let next_id_lck = self.next_id_lock.clone();
+        let metrics = self.metrics.clone();
+        let guardrail = self.guardrail.clone();
+        let max_resets = self.max_trx_resets;
+        Ok(Self::spawn(async move {
+            let r = async {
+                if blocks.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let mut trx = db.create_trx()?;
+                let mut resets = 0;
+                let mut checked = false;
+                loop {
+                    let mut stored = Vec::with_capacity(blocks.len());
+                    let mut new = 0;
+                    let mut tip_height = None;
+                    for block_info in blocks.iter() {
+                        let (b, is_new) = Self::sub_store_block_info(
+                            &trx,
+                            &h_index_dir,
+                            &chain_dir,
+                            &infos_dir,
+                            &next_id_lck,
+                            block_info.clone(),
+                        )
+                        .await?;
+                        // the transaction reads its own writes, so each block is compared with
+                        // the tip as it is after the blocks before it
+                        if Self::sub_update_tip(&trx, &chain_dir, &infos_dir, &reorgs_dir, &b)
+                            .await?
+                        {
+                            tip_height = Some(b.height);
+                        }
+                        new += is_new as u64;
+                        stored.push(b);
+                    }
+                    // the blocks are checked once, before their first commit
+                    if !checked {
+                        let lowest = stored.iter().map(|b| b.height).min().unwrap_or(0);
+                        guardrail.lock().unwrap().check(
+                            new,
+                            lowest,
+                            metrics.tip_height(),
+                            options,
+                        )?;
+                        checked = true;
+                    }
+                    match Self::timed("commit", trx.commit()).await {
+                        Ok(_) => {
+                            // the new blocks are only counted once they have been stored
+                            guardrail.lock().unwrap().record(new);
+                            if let Some(height) = tip_height {
+                                metrics.set_tip_height(height);
+                            }
+                            return Ok(stored);
+                        }
+                        Err(e) if resets < max_resets => {
+                            resets += 1;
+                            trx = e.on_error().await?;
+                        }
+                        Err(e) => return Err(Error::from(e)),
+                    }
+                }
+            }
+            .await;
+            let r = match r {
+                Ok(stored) => FDBChainStoreReply::StoredReply(stored),
+                Err(e) => FDBChainStoreReply::ErrorReply(e),
+            };
+            // the caller may have gone away
+            let _ = reply.send(r);
+        }))
+    }
+
+    /// Saves the block info, linking it to its parent and updating the counters. Returns the
+    /// BlockInfo as it was saved, and whether the block is new rather than an update.
+    ///
+    /// Expected to be called as part of a larger transaction, the ChainState is not updated.
+    async fn sub_store_block_info(
+        trx: &Transaction,
+        h_index_dir: &DirectoryOutput,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        next_id_lck: &Mutex<u8>,
+        mut block_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Result<(BlockInfo<<FDBChainStore as ChainStore>::BlockId>, bool)> {
+        // lookup id from hash, creating it if it doesn't exist already
+        let existing =
+            match Self::get_block_id_from_hash(trx, &block_info.hash, h_index_dir).await? {
+                None => {
+                    let id = Self::get_next_id(trx, next_id_lck, chain_dir).await?;
+                    let k = Self::get_h_index_key(h_index_dir, &block_info.hash)?;
+                    let v = Self::encode_h_index(id);
+                    trx.set(&k, &v);
+                    block_info.id = id;
+                    None
+                }
+                Some(id) => {
+                    block_info.id = id;
+                    let k = Self::get_block_info_key(infos_dir, id)?;
+                    Self::timed("get", trx.get(k.as_slice(), false))
+                        .await?
+                        .map(|v| Self::decode_block_info(&k, &v))
+                        .transpose()?
+                }
+            };
+        let mut parent =
+            Self::sub_block_info_by_hash(trx, &block_info.header.prev_hash, h_index_dir, infos_dir)
+                .await?
+                .ok_or(Error::ParentNotFound)?;
+        let parent_was_tip = parent.next_ids.is_empty();
+        // check that the child is listed in the parents next_ids
+        if !parent.next_ids.contains(&block_info.id) {
+            // update the next_ids in the parent and save it
+            parent.next_ids.push(block_info.id);
+            let k = Self::get_block_info_key(infos_dir, parent.id)?;
+            let v = Self::encode_block_info(&parent);
             trx.set(&k, &v);
-            // store genesis BlockInfo
-            let gbi = BlockInfo::genesis_info(chain);
-            let k2 = Self::get_block_info_key(&info_dir, 0).unwrap();
-            let v2 = Self::encode_block_info(&gbi);
-            trx.set(&k2, &v2);
-            let k3 = Self::get_h_index_key(h_index_dir, &gbi.hash).unwrap();
-            let v3 = Self::encode_h_index(0);
-            trx.set(&k3, &v3);
-            trx.commit().await?;
-        } else {
-            trx.cancel();
+        }
+        // update total_size & total_tx if possible
+        if let (Some(p_total), Some(size)) = (parent.total_size, block_info.size) {
+            block_info.total_size = Some(p_total + size)
+        }
+        if let (Some(p_total), Some(num_tx)) = (parent.total_tx, block_info.num_tx) {
+            block_info.total_tx = Some(p_total + num_tx)
+        }
+        // the chain work is the chain work of the parent plus the work of the block, it is left
+        // empty if the parent has no chain work, see has_more_work()
+        if block_info.chain_work.is_none() {
+            block_info.chain_work = parent
+                .chain_work
+                .as_ref()
+                .and_then(|w| work::child_chain_work(w, block_info.header.bits));
+        }
+        // update height, prev_id, and validity
+        block_info.height = parent.height + 1;
+        block_info.prev_id = parent.id;
+        block_info.validity = Self::child_validity(&parent.validity, block_info.validity);
+        // record when the block was first stored, an update keeps the original time
+        if block_info.received_time.is_none() {
+            block_info.received_time =
+                existing
+                    .as_ref()
+                    .and_then(|old| old.received_time)
+                    .or_else(|| {
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .ok()
+                            .map(|d| d.as_secs())
+                    });
+        }
+        if block_info.source.is_none() {
+            block_info.source = existing.as_ref().and_then(|old| old.source.clone());
+        }
+        if block_info.total_fees.is_none() {
+            block_info.total_fees = existing.as_ref().and_then(|old| old.total_fees);
+        }
+        // update the counters
+        let new = existing.is_none();
+        match existing {
+            None => {
+                let k = Self::get_counter_key(chain_dir, Self::BLOCKS_COUNT)?;
+                Self::add_to_counter(trx, &k, 1);
+                let k = Self::get_validity_counter_key(chain_dir, &block_info.validity)?;
+                Self::add_to_counter(trx, &k, 1);
+                // the new block is a tip, and the parent is no longer a tip
+                if !parent_was_tip {
+                    let k = Self::get_counter_key(chain_dir, Self::TIPS_COUNT)?;
+                    Self::add_to_counter(trx, &k, 1);
+                }
+            }
+            Some(old) => {
+                if old.validity != block_info.validity {
+                    let k = Self::get_validity_counter_key(chain_dir, &old.validity)?;
+                    Self::add_to_counter(trx, &k, -1);
+                    let k = Self::get_validity_counter_key(chain_dir, &block_info.validity)?;
+                    Self::add_to_counter(trx, &k, 1);
+                }
+            }
+        }
+        // save the block info
+        let k = Self::get_block_info_key(infos_dir, block_info.id)?;
+        let v = Self::encode_block_info(&block_info);
+        trx.set(&k, &v);
+        Ok((block_info, new))
+    }
+
+    // the validity that a block gets when it is stored, given the validity of its parent
+    fn child_validity(parent: &BlockValidity, validity: BlockValidity) -> BlockValidity {
+        match parent {
+            BlockValidity::Unknown => BlockValidity::Unknown,
+            BlockValidity::Valid => validity,
+            BlockValidity::ValidHeader => {
+                if validity == BlockValidity::Valid {
+                    BlockValidity::ValidHeader
+                } else {
+                    validity
+                }
+            }
+            BlockValidity::Invalid => BlockValidity::InvalidAncestor,
+            BlockValidity::HeaderInvalid => BlockValidity::InvalidAncestor,
+            BlockValidity::InvalidAncestor => BlockValidity::InvalidAncestor,
+        }
+    }
+
+    /// Handles the CheckWouldLink message.
+    async fn check_would_link(
+        &self,
+        block_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.db.create_trx()?;
+        let h_index_dir = self.h_index_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_check_would_link(&trx, &h_index_dir, &infos_dir, &block_info).await;
+            reply
+                .send(FDBChainStoreReply::LinkCheckReply(r))
+                .expect("send of reply failed in check_would_link()");
+        }))
+    }
+
+    // work out how the block would be linked to its parent if it was stored, without writing
+    async fn sub_check_would_link(
+        trx: &Transaction,
+        h_index_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        block_info: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Result<LinkCheck<<FDBChainStore as ChainStore>::BlockId>> {
+        let parent =
+            Self::sub_block_info_by_hash(trx, &block_info.header.prev_hash, h_index_dir, infos_dir)
+                .await?;
+        let parent = match parent {
+            Some(p) => p,
+            None => return Ok(LinkCheck::ParentNotFound),
+        };
+        let existing_id = Self::get_block_id_from_hash(trx, &block_info.hash, h_index_dir).await?;
+        Ok(LinkCheck::Links {
+            height: parent.height + 1,
+            prev_id: parent.id,
+            validity: Self::child_validity(&parent.validity, block_info.validity.clone()),
+            existing_id,
+        })
+    }
+
+    /// Implements [FDBChainStore::apply_branch_with_options()].
+    async fn apply_branch(
+        &self,
+        blocks: Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+        expect_current_tip: BlockHash,
+        options: InsertOptions,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let h_index_dir = self.h_index_dir.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let reorgs_dir = self.reorgs_dir.clone();
+        let next_id_lck = self.next_id_lock.clone();
+        let metrics = self.metrics.clone();
+        let guardrail = self.guardrail.clone();
+        let max_resets = self.max_trx_resets;
+        Ok(Self::spawn(async move {
+            let r = async {
+                Self::check_branch(&blocks)?;
+                let mut trx = db.create_trx()?;
+                let mut resets = 0;
+                let mut checked = false;
+                loop {
+                    let (outcome, new) = Self::sub_apply_branch(
+                        &trx,
+                        &h_index_dir,
+                        &chain_dir,
+                        &infos_dir,
+                        &reorgs_dir,
+                        &next_id_lck,
+                        &blocks,
+                        &expect_current_tip,
+                    )
+                    .await?;
+                    if let BranchOutcome::Outdated { .. } = outcome {
+                        return Ok(outcome);
+                    }
+                    // the branch is checked once, before its first commit
+                    if let (false, BranchOutcome::Applied { blocks, .. }) = (checked, &outcome) {
+                        let lowest = blocks.first().map_or(0, |b| b.height);
+                        guardrail.lock().unwrap().check(
+                            new,
+                            lowest,
+                            metrics.tip_height(),
+                            options,
+                        )?;
+                        checked = true;
+                    }
+                    // a conflict means that another write got in first, so check the tip again
+                    match Self::timed("commit", trx.commit()).await {
+                        Ok(_) => {
+                            // the new blocks are only counted once they have been stored
+                            guardrail.lock().unwrap().record(new);
+                            return Ok(outcome);
+                        }
+                        Err(e) if resets < max_resets => {
+                            resets += 1;
+                            trx = e.on_error().await?;
+                        }
+                        Err(e) => return Err(Error::from(e)),
+                    }
+                }
+            }
+            .await;
+            if let Ok(BranchOutcome::Applied { blocks, .. }) = &r {
+                if let Some(tip) = blocks.last() {
+                    metrics.set_tip_height(tip.height);
+                }
+            }
+            reply
+                .send(FDBChainStoreReply::BranchReply(r))
+                .expect("send of reply failed in apply_branch()");
+        }))
+    }
+
+    // check that the blocks form a branch, each block the child of the block before it
+    fn check_branch(blocks: &[BlockInfo<<FDBChainStore as ChainStore>::BlockId>]) -> Result<()> {
+        if blocks.is_empty() {
+            return Err(Error::InvalidBranch("the branch is empty".into()));
+        }
+        for (i, pair) in blocks.windows(2).enumerate() {
+            if pair[1].header.prev_hash != pair[0].hash {
+                return Err(Error::InvalidBranch(format!(
+                    "block {} is not the child of block {}",
+                    i + 1,
+                    i
+                )));
+            }
         }
         Ok(())
     }
 
-    // get the key for the state
-    fn get_state_key(chain_dir: &DirectoryOutput) -> Result<Vec<u8>> {
-        Ok(chain_dir.pack(&Self::STATE_KEY)?)
+    /// Saves the blocks of the branch and makes the last block the most work tip, if the current
+    /// most work tip is the expected tip.
+    ///
+    /// Expected to be called as part of a larger transaction, which is only committed if the
+    /// branch was applied. Also returns the number of blocks that were not already stored.
+    #[allow(clippy::too_many_arguments)]
+    async fn sub_apply_branch(
+        trx: &Transaction,
+        h_index_dir: &DirectoryOutput,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        reorgs_dir: &DirectoryOutput,
+        next_id_lck: &Mutex<u8>,
+        blocks: &[BlockInfo<<FDBChainStore as ChainStore>::BlockId>],
+        expect_current_tip: &BlockHash,
+    ) -> Result<(BranchOutcome<<FDBChainStore as ChainStore>::BlockId>, u64)> {
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let state = Self::decode_chain_state(&k, &v)?;
+        let tip = Self::sub_expect_block_info(trx, infos_dir, state.most_work_tip).await?;
+        if tip.hash != *expect_current_tip {
+            let outcome = BranchOutcome::Outdated {
+                current_tip: tip.hash,
+            };
+            return Ok((outcome, 0));
+        }
+        let mut stored = Vec::with_capacity(blocks.len());
+        let mut new = 0;
+        for block_info in blocks {
+            let (b, is_new) = Self::sub_store_block_info(
+                trx,
+                h_index_dir,
+                chain_dir,
+                infos_dir,
+                next_id_lck,
+                block_info.clone(),
+            )
+            .await?;
+            new += is_new as u64;
+            stored.push(b);
+        }
+        let last = stored.last().expect("branch was checked to be non-empty");
+        match last.validity {
+            BlockValidity::Invalid
+            | BlockValidity::HeaderInvalid
+            | BlockValidity::InvalidAncestor => {
+                return Err(Error::InvalidBranch(
+                    "the last block of the branch is not valid".into(),
+                ))
+            }
+            _ => {}
+        }
+        if !Self::has_more_work(last, &tip) {
+            return Err(Error::InvalidBranch(
+                "the branch does not have more work than the most work tip".into(),
+            ));
+        }
+        let (fork_point, displaced) =
+            Self::sub_set_tip(trx, chain_dir, infos_dir, reorgs_dir, state, &tip, last).await?;
+        let outcome = BranchOutcome::Applied {
+            fork_point,
+            displaced,
+            blocks: stored,
+        };
+        Ok((outcome, new))
     }
 
-    // decode ChainState from fdb format
-    pub(crate) fn decode_chain_state(
-        v: &[u8],
-    ) -> ChainState<<FDBChainStore as ChainStore>::BlockId> {
-        let (most_work_tip, a, d, i) = unpack::<(u64, Element, Element, Element)>(v)
-            .expect("unpack failed in decode_chain_state()");
-        let active_tips = a
-            .as_tuple()
-            .unwrap()
-            .iter()
-            .map(|e| e.as_i64().unwrap() as u64)
-            .collect();
-        let dormant_tips = d
-            .as_tuple()
-            .unwrap()
-            .iter()
-            .map(|e| e.as_i64().unwrap() as u64)
-            .collect();
-        let invalid_tips = i
-            .as_tuple()
-            .unwrap()
-            .iter()
-            .map(|e| e.as_i64().unwrap() as u64)
-            .collect();
-        ChainState {
-            most_work_tip,
-            active_tips,
-            dormant_tips,
-            invalid_tips,
+    // get a BlockInfo which is expected to exist
+    async fn sub_expect_block_info(
+        trx: &Transaction,
+        infos_dir: &InfosDir,
+        db_id: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<BlockInfo<<FDBChainStore as ChainStore>::BlockId>> {
+        let k = Self::get_block_info_key(infos_dir, db_id)?;
+        match Self::timed("get", trx.get(k.as_slice(), false)).await? {
+            Some(v) => Self::decode_block_info(&k, &v),
+            None => Err(Error::BlockInfoNotFound(db_id)),
         }
     }
 
-    // encode ChainState to fdb format
-    pub(crate) fn encode_chain_state(
-        cs: &ChainState<<FDBChainStore as ChainStore>::BlockId>,
-    ) -> Vec<u8> {
-        pack(&(
-            cs.most_work_tip,
-            &cs.active_tips,
-            &cs.dormant_tips,
-            &cs.invalid_tips,
-        ))
+    // check whether a block has more work than the tip
+    //
+    // the chain work is compared if it is known for both blocks. A block without chain work never
+    // replaces a tip that has it, and a block with chain work always replaces a tip without it.
+    // The height is only used if neither has chain work, such as for a chain from a custom
+    // genesis block.
+    fn has_more_work(
+        block_info: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        tip: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> bool {
+        match (&block_info.chain_work, &tip.chain_work) {
+            (Some(b), Some(t)) => work::cmp_work(b, t) == Ordering::Greater,
+            (None, Some(_)) => false,
+            (Some(_), None) => true,
+            (None, None) => block_info.height > tip.height,
+        }
     }
 
-    // get the key for the next_id
-    fn get_next_id_key(chain_dir: &DirectoryOutput) -> Result<Vec<u8>> {
-        Ok(chain_dir.pack(&Self::NEXT_ID_KEY)?)
+    /// Makes the block the most work tip if it has more work than the current most work tip,
+    /// recording a reorg if the current tip is not an ancestor of the block. Returns true if the
+    /// block became the most work tip.
+    ///
+    /// Expected to be called as part of a larger transaction, after the block has been saved.
+    async fn sub_update_tip(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        reorgs_dir: &DirectoryOutput,
+        block_info: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Result<bool> {
+        match block_info.validity {
+            BlockValidity::Invalid
+            | BlockValidity::HeaderInvalid
+            | BlockValidity::InvalidAncestor => return Ok(false),
+            _ => {}
+        }
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let state = Self::decode_chain_state(&k, &v)?;
+        if state.most_work_tip == block_info.id {
+            return Ok(false);
+        }
+        let tip = Self::sub_expect_block_info(trx, infos_dir, state.most_work_tip).await?;
+        if !Self::has_more_work(block_info, &tip) {
+            return Ok(false);
+        }
+        Self::sub_set_tip(
+            trx, chain_dir, infos_dir, reorgs_dir, state, &tip, block_info,
+        )
+        .await?;
+        Ok(true)
     }
 
-    // decode the next_id from fdb
-    pub(crate) fn decode_next_id(v: &[u8]) -> <FDBChainStore as ChainStore>::BlockId {
-        let (i,) = unpack(v).expect("unpack failed in decode_next_id()");
-        i
+    /// Replaces the most work tip with the block, recording a reorg if the tip is not an ancestor
+    /// of the block. Returns the id of the last block that the two chains have in common and the
+    /// ids of the blocks that are no longer on the main chain, lowest first.
+    ///
+    /// Expected to be called as part of a larger transaction, after the block has been saved.
+    async fn sub_set_tip(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        reorgs_dir: &DirectoryOutput,
+        mut state: ChainState<<FDBChainStore as ChainStore>::BlockId>,
+        tip: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        block_info: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> Result<(
+        <FDBChainStore as ChainStore>::BlockId,
+        Vec<<FDBChainStore as ChainStore>::BlockId>,
+    )> {
+        // walk back from both blocks to the last common block
+        let mut old = tip.clone();
+        let mut new = block_info.clone();
+        let mut displaced = Vec::new();
+        while new.height > old.height {
+            new = Self::sub_expect_block_info(trx, infos_dir, new.prev_id).await?;
+        }
+        while old.height > new.height {
+            displaced.push(old.id);
+            old = Self::sub_expect_block_info(trx, infos_dir, old.prev_id).await?;
+        }
+        while old.id != new.id {
+            displaced.push(old.id);
+            old = Self::sub_expect_block_info(trx, infos_dir, old.prev_id).await?;
+            new = Self::sub_expect_block_info(trx, infos_dir, new.prev_id).await?;
+        }
+        displaced.reverse();
+        if old.id != tip.id {
+            // the old tip is not an ancestor of the new tip
+            let event = ReorgEvent {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                old_tip: tip.id,
+                new_tip: block_info.id,
+                fork_height: old.height,
+                orphaned_count: tip.height - old.height,
+            };
+            let r_k = Self::get_reorg_key(reorgs_dir, &event)?;
+            trx.set(&r_k, &Self::encode_reorg_event(&event));
+        }
+        state.most_work_tip = block_info.id;
+        let k = Self::get_state_key(chain_dir)?;
+        trx.set(&k, &Self::encode_chain_state(&state));
+        Ok((old.id, displaced))
     }
 
-    // encode the next_id into fdb
-    pub(crate) fn encode_next_id(v: <FDBChainStore as ChainStore>::BlockId) -> Vec<u8> {
-        pack(&(v,))
+    /// Handles the ReorgLog message.
+    async fn reorg_log(
+        &self,
+        limit: usize,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let trx = self.db.create_trx()?;
+        let reorgs_dir = self.reorgs_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_reorg_log(&trx, &reorgs_dir, limit).await;
+            reply
+                .send(FDBChainStoreReply::ReorgLogReply(r))
+                .expect("send of reply failed in reorg_log()");
+        }))
     }
 
-    // get the key for the BlockInfo
-    fn get_block_info_key(
-        info_dir: &DirectoryOutput,
-        block_id: <FDBChainStore as ChainStore>::BlockId,
-    ) -> Result<Vec<u8>> {
-        Ok(info_dir.pack(&block_id)?)
+    // get the most recent reorg events, most recent first
+    async fn sub_reorg_log(
+        trx: &Transaction,
+        reorgs_dir: &DirectoryOutput,
+        limit: usize,
+    ) -> Result<Vec<ReorgEvent>> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+        let opt = RangeOption {
+            limit: Some(limit),
+            reverse: true,
+            ..RangeOption::from(reorgs_dir.range()?)
+        };
+        let kvs = Self::timed("range", trx.get_range(&opt, 1, false)).await?;
+        Ok(kvs
+            .iter()
+            .map(|kv| Self::decode_reorg_event(kv.value()))
+            .collect())
     }
 
-    // decode the BlockInfo from fdb
-    pub(crate) fn decode_block_info(v: &[u8]) -> BlockInfo<<FDBChainStore as ChainStore>::BlockId> {
-        // the tuple is too large for the shortcut implementation
-        let i = unpack::<Vec<Element>>(v).expect("unpack failed in decode_block_info()");
-        let hash = BlockHash::from(i[1].as_bytes().unwrap().to_vec().as_slice());
-        let next_ids = i[5]
-            .as_tuple()
-            .unwrap()
-            .iter()
-            .map(|j| j.as_i64().unwrap() as u64)
-            .collect();
-        let chain_work = i[9].as_bytes().map(|j| j.to_vec());
-        let miner = i[12].as_str().map(String::from);
-        BlockInfo {
-            id: i[0].as_i64().unwrap() as u64,
-            hash,
-            header: BlockHeader::from_binary_buf(i[2].as_bytes().unwrap()).unwrap(),
-            height: i[3].as_i64().unwrap() as u64,
-            prev_id: i[4].as_i64().unwrap() as u64,
-            next_ids,
-            size: i[6].as_i64().map(|j| j as u64),
-            num_tx: i[7].as_i64().map(|j| j as u64),
-            median_time: i[8].as_i64().map(|j| j as u64),
-            chain_work,
-            total_tx: i[10].as_i64().map(|j| j as u64),
-            total_size: i[11].as_i64().map(|j| j as u64),
-            miner,
-            validity: BlockValidity::from(i[13].as_i64().unwrap() as u8),
-        }
+    /// Handles the Versions message.
+    async fn versions(&self, reply: OneshotSender<FDBChainStoreReply>) -> Result<JoinHandle<()>> {
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        let chain_dir = self.chain_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = profile.check(
+                "versions",
+                start,
+                Self::sub_versions(&trx, &chain_dir).await,
+            );
+            reply
+                .send(FDBChainStoreReply::VersionsReply(r))
+                .expect("send of reply failed in versions()");
+        }))
     }
 
-    // encode the block_info into fdb
-    pub(crate) fn encode_block_info(
-        v: &BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
-    ) -> Vec<u8> {
-        let hash = Element::Bytes(Bytes::from(Vec::from(v.hash.hash)));
-        let hdr = Element::Bytes(Bytes::from(v.header.to_binary_buf().unwrap()));
-        let m = match v.miner.clone() {
-            Some(k) => Element::String(Cow::from(k)),
-            None => Element::Nil,
-        };
-        let n_i = Element::Tuple(v.next_ids.iter().map(|i| Element::Int(*i as i64)).collect());
-        let c_w = v
-            .chain_work
-            .clone()
-            .map(|j| Element::Bytes(Bytes::from(j)))
-            .unwrap_or(Element::Nil);
-        let i = vec![
-            Element::Int(v.id as i64),
-            hash,
-            hdr,
-            Element::Int(v.height as i64),
-            Element::Int(v.prev_id as i64),
-            n_i,
-            v.size
-                .map(|j| Element::Int(j as i64))
-                .unwrap_or(Element::Nil),
-            v.num_tx
-                .map(|j| Element::Int(j as i64))
-                .unwrap_or(Element::Nil),
-            v.median_time
-                .map(|j| Element::Int(j as i64))
-                .unwrap_or(Element::Nil),
-            c_w,
-            v.total_tx
-                .map(|j| Element::Int(j as i64))
-                .unwrap_or(Element::Nil),
-            v.total_size
-                .map(|j| Element::Int(j as i64))
-                .unwrap_or(Element::Nil),
-            m,
-            Element::Int(u8::from(v.validity.clone()) as i64),
-        ];
-        pack(&i)
+    /// Handles the RecordVersion message.
+    async fn record_version(
+        &self,
+        writer: VersionRecord,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let max_resets = self.max_trx_resets;
+        Ok(Self::spawn(async move {
+            let r = Self::sub_record_version(&db, &chain_dir, writer, max_resets).await;
+            reply
+                .send(FDBChainStoreReply::VersionsReply(r))
+                .expect("send of reply failed in record_version()");
+        }))
     }
 
-    // get the key for the hash index
-    fn get_h_index_key(h_index_dir: &DirectoryOutput, block_hash: &BlockHash) -> Result<Vec<u8>> {
-        Ok(h_index_dir.pack(&block_hash.to_binary_buf().unwrap())?)
+    // read the version history
+    async fn sub_versions(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+    ) -> Result<VersionHistory> {
+        let opt = RangeOption::from(Self::get_versions_range(chain_dir)?);
+        let kvs = Self::timed("range", trx.get_range(&opt, 1, false)).await?;
+        let records = kvs
+            .iter()
+            .map(|kv| Self::decode_version(kv.key(), kv.value()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(VersionHistory::new(records))
     }
 
-    // decode the hash index value from fdb
-    pub(crate) fn decode_h_index(v: &[u8]) -> <FDBChainStore as ChainStore>::BlockId {
-        let (i,) = unpack(v).expect("unpack failed in decode_h_index()");
-        i
+    // record the writer in the version history, only the record of the writer and those of the
+    // versions that are removed from the history are written
+    //
+    // the transaction is retried if it conflicts, such as with another handle being opened
+    async fn sub_record_version(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        writer: VersionRecord,
+        max_resets: u32,
+    ) -> Result<VersionHistory> {
+        let mut trx = db.create_trx()?;
+        let mut resets = 0;
+        loop {
+            let mut history = Self::sub_versions(&trx, chain_dir).await?;
+            for removed in history.record(writer.clone()) {
+                trx.clear(&Self::get_version_key(chain_dir, &removed.version)?);
+            }
+            if let Some(r) = history.get(&writer.version) {
+                trx.set(
+                    &Self::get_version_key(chain_dir, &r.version)?,
+                    &Self::encode_version(r),
+                );
+            }
+            match Self::timed("commit", trx.commit()).await {
+                Ok(_) => return Ok(history),
+                Err(e) if resets < max_resets => {
+                    resets += 1;
+                    trx = e.on_error().await?;
+                }
+                Err(e) => return Err(Error::from(e)),
+            }
+        }
     }
 
-    // encode the hash index value into fdb
-    pub(crate) fn encode_h_index(v: <FDBChainStore as ChainStore>::BlockId) -> Vec<u8> {
-        pack(&(v,))
+    /// Handles the Split message.
+    async fn split(&self, reply: OneshotSender<FDBChainStoreReply>) -> Result<JoinHandle<()>> {
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        let splits = self.splits.clone();
+        Ok(Self::spawn(async move {
+            let r = profile.check("get_split", start, splits.find(&trx).await);
+            reply
+                .send(FDBChainStoreReply::SplitReply(r))
+                .expect("send of reply failed in split()");
+        }))
     }
 
-    // get the BlockId from the hash
-    pub(crate) async fn get_block_id_from_hash(
+    // Get the most recent part of the chain, the main chain back from the tip and the blocks
+    // that descend from it, see SPLIT_SCAN_DEPTH and SPLIT_SCAN_LIMIT.
+    async fn sub_recent_blocks(
         trx: &Transaction,
-        block_hash: &BlockHash,
-        h_index_dir: &DirectoryOutput,
-    ) -> Result<Option<<FDBChainStore as ChainStore>::BlockId>> {
-        let k = Self::get_h_index_key(h_index_dir, block_hash)?;
-        let v = trx.get(k.as_slice(), false).await?;
-        if v.is_none() {
-            return Ok(None);
+        infos_dir: &InfosDir,
+        tip: <FDBChainStore as ChainStore>::BlockId,
+    ) -> Result<Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>> {
+        let mut blocks = Vec::new();
+        let mut branches = Vec::new();
+        let mut id = tip;
+        let mut child = None;
+        for _ in 0..=Self::SPLIT_SCAN_DEPTH {
+            let b = Self::sub_expect_block_info(trx, infos_dir, id).await?;
+            branches.extend(b.next_ids.iter().filter(|n| Some(**n) != child));
+            child = Some(b.id);
+            id = b.prev_id;
+            let genesis = b.height == 0;
+            blocks.push(b);
+            if genesis {
+                break;
+            }
         }
-        let i = Self::decode_h_index(&v.unwrap());
-        Ok(Some(i))
+        while let Some(id) = branches.pop() {
+            if blocks.len() >= Self::SPLIT_SCAN_LIMIT {
+                break;
+            }
+            let b = Self::sub_expect_block_info(trx, infos_dir, id).await?;
+            branches.extend(b.next_ids.iter());
+            blocks.push(b);
+        }
+        Ok(blocks)
     }
 
-    async fn get_next_id(
+    /// Handles the Summary message.
+    async fn summary(&self, reply: OneshotSender<FDBChainStoreReply>) -> Result<JoinHandle<()>> {
+        let trx = self.read_trx()?;
+        let (profile, start) = (self.profile, Instant::now());
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_summary(&trx, &chain_dir, &infos_dir).await;
+            let r = profile.check("summary", start, r);
+            reply
+                .send(FDBChainStoreReply::SummaryReply(r))
+                .expect("send of reply failed in summary()");
+        }))
+    }
+
+    // Build the summary from the counters, the chain state and the genesis block.
+    async fn sub_summary(
         trx: &Transaction,
-        next_id: &Mutex<u8>,
         chain_dir: &DirectoryOutput,
-    ) -> Result<<FDBChainStore as ChainStore>::BlockId> {
-        // only do one of these at a time to prevent db transaction clashes
-        let k = Self::get_next_id_key(chain_dir).unwrap();
-        let _lck = next_id.lock().await;
-        let v = trx.get(&k, false).await.unwrap().unwrap();
-        let id = Self::decode_next_id(&v);
-        let v2 = Self::encode_next_id(id + 1);
-        trx.set(&k, &v2);
-        Ok(id)
+        infos_dir: &InfosDir,
+    ) -> Result<StoreSummary> {
+        let k = Self::get_counter_key(chain_dir, Self::BLOCKS_COUNT)?;
+        let num_blocks = Self::timed("get", trx.get(&k, false))
+            .await?
+            .map(|v| Self::decode_counter(&v));
+        let k = Self::get_counter_key(chain_dir, Self::TIPS_COUNT)?;
+        let num_tips = Self::timed("get", trx.get(&k, false))
+            .await?
+            .map(|v| Self::decode_counter(&v));
+        let mut validity_counts = Vec::new();
+        for validity in BlockValidity::ALL {
+            let k = Self::get_validity_counter_key(chain_dir, &validity)?;
+            let n = Self::timed("get", trx.get(&k, false))
+                .await?
+                .map(|v| Self::decode_counter(&v));
+            validity_counts.push((validity, n.unwrap_or(0)));
+        }
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let tip = Self::decode_chain_state(&k, &v)?.most_work_tip;
+        let k = Self::get_block_info_key(infos_dir, tip)?;
+        let tip_height = match Self::timed("get", trx.get(k.as_slice(), false)).await? {
+            Some(v) => Self::decode_block_info(&k, &v)?.height,
+            None => return Err(Error::BlockInfoNotFound(tip)),
+        };
+        let k = Self::get_block_info_key(infos_dir, 0)?;
+        let genesis_hash = match Self::timed("get", trx.get(k.as_slice(), false)).await? {
+            Some(v) => Self::decode_block_info(&k, &v)?.hash,
+            None => return Err(Error::BlockInfoNotFound(0)),
+        };
+        Ok(StoreSummary {
+            num_blocks: num_blocks.unwrap_or(0),
+            validity_counts,
+            num_tips: num_tips.unwrap_or(0),
+            tip_height,
+            genesis_hash,
+        })
+    }
+
+    /// Handles the CheckCounters message.
+    async fn check_counters(
+        &self,
+        fix: bool,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_check_counters(&db, fix, &chain_dir, &infos_dir).await;
+            reply
+                .send(FDBChainStoreReply::CounterCheckReply(r))
+                .expect("send of reply failed in check_counters()");
+        }))
+    }
+
+    // Compare the counters with the values computed by a scan, resetting them if fix is true.
+    async fn sub_check_counters(
+        db: &foundationdb::Database,
+        fix: bool,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<Vec<CounterDrift>> {
+        let computed = Self::scan_counters(db, chain_dir, infos_dir).await?;
+        let trx = db.create_trx()?;
+        let stored = Self::get_counters(&trx, chain_dir).await?;
+        let mut drift = Vec::new();
+        let mut fixes = Vec::new();
+        for ((name, s), (_, c)) in stored.into_iter().zip(computed.into_iter()) {
+            if s != c {
+                drift.push(CounterDrift {
+                    name: name.clone(),
+                    stored: s,
+                    computed: c,
+                });
+                fixes.push((name, c));
+            }
+        }
+        if fix && !fixes.is_empty() {
+            Self::set_counters(&trx, chain_dir, &fixes)?;
+            Self::timed("commit", trx.commit()).await?;
+        } else {
+            trx.cancel();
+        }
+        Ok(drift)
     }
 
-    /// Handles the ChainState message.
-    async fn handle_get_chain_state(
+    /// Handles the CheckReachability message.
+    async fn check_reachability(
         &self,
+        fix: Option<ReachabilityFix>,
         reply: OneshotSender<FDBChainStoreReply>,
     ) -> Result<JoinHandle<()>> {
-        let k = Self::get_state_key(&self.chain_dir)?;
-        let trx = self.db.create_trx()?;
-        Ok(tokio::spawn(async move {
-            let v = trx
-                .get(k.as_slice(), false)
-                .await
-                .unwrap()
-                .expect("chainstate missing from db"); // todo: remove
-            let r = Self::decode_chain_state(&v);
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        let h_index_dir = self.h_index_dir.clone();
+        Ok(Self::spawn(async move {
+            let r =
+                Self::sub_check_reachability(&db, fix, &chain_dir, &infos_dir, &h_index_dir).await;
             reply
-                .send(FDBChainStoreReply::ChainStateReply(r))
-                .expect("send of reply failed in get_chain_state()"); // todo: remove
+                .send(FDBChainStoreReply::ReachabilityReply(r))
+                .expect("send of reply failed in check_reachability()");
         }))
     }
 
-    async fn get_block_info(
+    // Find the BlockInfos that are not reachable from genesis, fixing them if requested.
+    async fn sub_check_reachability(
+        db: &foundationdb::Database,
+        fix: Option<ReachabilityFix>,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        h_index_dir: &DirectoryOutput,
+    ) -> Result<ReachabilityReport> {
+        let mut present = IdSet::default();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| {
+            present.insert(b_info.id);
+        })
+        .await?;
+        // forward pass from genesis, reading a batch of the frontier in each transaction
+        let mut reachable = IdSet::default();
+        reachable.insert(0);
+        let mut frontier = vec![0];
+        while !frontier.is_empty() {
+            let batch = frontier.split_off(frontier.len().saturating_sub(Self::SCAN_BATCH_SIZE));
+            let trx = db.create_trx()?;
+            let reads = batch
+                .iter()
+                .map(|id| Self::sub_expect_block_info(&trx, infos_dir, *id));
+            for b_info in try_join_all(reads).await? {
+                for next_id in b_info.next_ids {
+                    if present.contains(next_id) && reachable.insert(next_id) {
+                        frontier.push(next_id);
+                    }
+                }
+            }
+        }
+        let mut unreachable = Vec::new();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| {
+            if !reachable.contains(b_info.id) {
+                unreachable.push(b_info);
+            }
+        })
+        .await?;
+        // look for the parents by hash
+        let mut report = ReachabilityReport {
+            reachable: reachable.count(),
+            unreachable: Vec::new(),
+            deleted: 0,
+            relinked: 0,
+        };
+        for batch in unreachable.chunks(Self::SCAN_BATCH_SIZE) {
+            let trx = db.create_trx()?;
+            let reads = batch.iter().map(|b_info| {
+                Self::get_block_id_from_hash(&trx, &b_info.header.prev_hash, h_index_dir)
+            });
+            for (b_info, parent) in batch.iter().zip(try_join_all(reads).await?) {
+                report.unreachable.push(UnreachableBlock {
+                    id: b_info.id,
+                    hash: b_info.hash,
+                    height: b_info.height,
+                    prev_id: b_info.prev_id,
+                    prev_missing: !present.contains(b_info.prev_id),
+                    parent: parent.filter(|p| reachable.contains(*p)),
+                });
+            }
+        }
+        match fix {
+            None => return Ok(report),
+            Some(ReachabilityFix::Delete) => {
+                for batch in unreachable.chunks(Self::SCAN_BATCH_SIZE) {
+                    let trx = db.create_trx()?;
+                    for b_info in batch {
+                        trx.clear(&Self::get_block_info_key(infos_dir, b_info.id)?);
+                        // the hash may be indexed to another BlockInfo
+                        let id =
+                            Self::get_block_id_from_hash(&trx, &b_info.hash, h_index_dir).await?;
+                        if id == Some(b_info.id) {
+                            trx.clear(&Self::get_h_index_key(h_index_dir, &b_info.hash)?);
+                        }
+                    }
+                    Self::timed("commit", trx.commit()).await?;
+                    report.deleted += batch.len() as u64;
+                }
+                // remove the deleted blocks from the tips in the chain state
+                let trx = db.create_trx()?;
+                let k = Self::get_state_key(chain_dir)?;
+                if let Some(v) = Self::timed("get", trx.get(&k, false)).await? {
+                    let mut state = Self::decode_chain_state(&k, &v)?;
+                    for tips in [
+                        &mut state.active_tips,
+                        &mut state.dormant_tips,
+                        &mut state.invalid_tips,
+                    ] {
+                        tips.retain(|id| reachable.contains(*id));
+                    }
+                    trx.set(&k, &Self::encode_chain_state(&state));
+                    Self::timed("commit", trx.commit()).await?;
+                }
+            }
+            Some(ReachabilityFix::Relink) => {
+                for (b_info, u) in unreachable.iter().zip(report.unreachable.iter()) {
+                    let parent_id = match u.parent {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    let trx = db.create_trx()?;
+                    let mut parent =
+                        Self::sub_expect_block_info(&trx, infos_dir, parent_id).await?;
+                    if !parent.next_ids.contains(&b_info.id) {
+                        parent.next_ids.push(b_info.id);
+                        let k = Self::get_block_info_key(infos_dir, parent.id)?;
+                        trx.set(&k, &Self::encode_block_info(&parent));
+                    }
+                    let mut b_info = b_info.clone();
+                    b_info.prev_id = parent_id;
+                    let k = Self::get_block_info_key(infos_dir, b_info.id)?;
+                    trx.set(&k, &Self::encode_block_info(&b_info));
+                    Self::timed("commit", trx.commit()).await?;
+                    report.relinked += 1;
+                }
+            }
+        }
+        // the blocks and tips have changed
+        Self::sub_check_counters(db, true, chain_dir, infos_dir).await?;
+        Ok(report)
+    }
+
+    /// Handles the RepairLinks message.
+    async fn repair_links(
         &self,
-        db_id: <FDBChainStore as ChainStore>::BlockId,
+        fix: bool,
         reply: OneshotSender<FDBChainStoreReply>,
     ) -> Result<JoinHandle<()>> {
-        let k = Self::get_block_info_key(&self.infos_dir, db_id)?;
-        let trx = self.db.create_trx()?;
-        Ok(tokio::spawn(async move {
-            let r = trx.get(k.as_slice(), false).await.unwrap();
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
+        let infos_dir = self.infos_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_repair_links(&db, fix, &chain_dir, &infos_dir).await;
             reply
-                .send(FDBChainStoreReply::BlockInfoReply(
-                    r.map(|i| Self::decode_block_info(&i)),
-                ))
-                .expect("send of reply failed in get_block_info()");
+                .send(FDBChainStoreReply::LinkRepairReply(r))
+                .expect("send of reply failed in repair_links()");
         }))
     }
 
-    /// Gets the block info given the hash.
-    ///
-    /// Expected to be called as part of a larger transaction.
-    async fn sub_block_info_by_hash(
-        trx: &Transaction,
-        hash: &BlockHash,
-        h_index_dir: &DirectoryOutput,
-        infos_dir: &DirectoryOutput,
-    ) -> Result<Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>> {
-        match Self::get_block_id_from_hash(trx, hash, h_index_dir).await? {
-            None => Ok(None),
-            Some(id) => {
-                let k = Self::get_block_info_key(infos_dir, id)?;
-                let r = trx.get(k.as_slice(), false).await.unwrap();
+    // Check that every block is listed in the next_ids of its parent, and that every next_id
+    // exists, repairing the next_ids where they are not if fix is true.
+    //
+    // The first scan records which ids exist and the parent of each, indexed by id because ids
+    // are dense. The second scan checks the next_ids against them.
+    async fn sub_repair_links(
+        db: &foundationdb::Database,
+        fix: bool,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<Vec<LinkRepair>> {
+        let mut present = IdSet::default();
+        let mut prev_ids = Vec::new();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| {
+            present.insert(b_info.id);
+            let i = b_info.id as usize;
+            if i >= prev_ids.len() {
+                prev_ids.resize(i + 1, u64::MAX);
+            }
+            prev_ids[i] = b_info.prev_id;
+        })
+        .await?;
+        let mut listed = IdSet::default();
+        let mut repairs = Vec::new();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| {
+            for child in b_info.next_ids {
+                if !present.contains(child) {
+                    repairs.push(LinkRepair::RemovedChild {
+                        parent: b_info.id,
+                        child,
+                    });
+                } else if prev_ids[child as usize] == b_info.id {
+                    listed.insert(child);
+                }
+            }
+        })
+        .await?;
+        // genesis has no parent
+        for (child, parent) in prev_ids.iter().enumerate().skip(1) {
+            let child = child as u64;
+            if present.contains(child) && !listed.contains(child) && present.contains(*parent) {
+                repairs.push(LinkRepair::AddedChild {
+                    parent: *parent,
+                    child,
+                });
+            }
+        }
+        repairs.sort_by_key(|r| match r {
+            LinkRepair::RemovedChild { parent, .. } => (*parent, 0),
+            LinkRepair::AddedChild { parent, .. } => (*parent, 1),
+        });
+        if !fix {
+            return Ok(repairs);
+        }
+        // update each parent in its own transaction
+        for parent_repairs in
+            repairs.chunk_by(|a, b| Self::repair_parent(a) == Self::repair_parent(b))
+        {
+            let trx = db.create_trx()?;
+            let parent_id = Self::repair_parent(&parent_repairs[0]);
+            let mut parent = Self::sub_expect_block_info(&trx, infos_dir, parent_id).await?;
+            for r in parent_repairs {
                 match r {
-                    Some(i) => Ok(Some(Self::decode_block_info(&i))),
-                    None => Ok(None),
+                    LinkRepair::RemovedChild { child, .. } => {
+                        parent.next_ids.retain(|i| i != child)
+                    }
+                    LinkRepair::AddedChild { child, .. } => parent.next_ids.push(*child),
                 }
             }
+            let k = Self::get_block_info_key(infos_dir, parent_id)?;
+            trx.set(&k, &Self::encode_block_info(&parent));
+            Self::timed("commit", trx.commit()).await?;
+        }
+        if !repairs.is_empty() {
+            // the tips have changed
+            Self::sub_check_counters(db, true, chain_dir, infos_dir).await?;
         }
+        Ok(repairs)
     }
 
-    /// Handles the actor BlockInfo message.
-    async fn get_block_info_by_hash(
-        &self,
-        hash: BlockHash,
-        reply: OneshotSender<FDBChainStoreReply>,
-    ) -> Result<JoinHandle<()>> {
-        let trx = self.db.create_trx()?;
-        let h_index_dir = self.h_index_dir.clone();
+    // the parent whose next_ids are repaired
+    fn repair_parent(repair: &LinkRepair) -> <FDBChainStore as ChainStore>::BlockId {
+        match repair {
+            LinkRepair::RemovedChild { parent, .. } | LinkRepair::AddedChild { parent, .. } => {
+                *parent
+            }
+        }
+    }
+
+    /// Handles the Forks message.
+    async fn forks(&self, reply: OneshotSender<FDBChainStoreReply>) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
         let infos_dir = self.infos_dir.clone();
-        Ok(tokio::spawn(async move {
-            let r = Self::sub_block_info_by_hash(&trx, &hash, &h_index_dir, &infos_dir)
-                .await
-                .expect("failure during get block info by hash()");
+        Ok(Self::spawn(async move {
+            let r = Self::sub_forks(&db, &chain_dir, &infos_dir).await;
             reply
-                .send(FDBChainStoreReply::BlockInfoReply(r))
-                .expect("failed to send reply");
+                .send(FDBChainStoreReply::ForksReply(r))
+                .expect("send of reply failed in forks()");
         }))
     }
 
-    // todo: The algorithm used here is to get the block by its block_id, then get the previous by its block id, etc, etc.
-    // However, we know that the block ids are always assigned in ascending order and there are comparatively few forks.
-    // It may be more efficient to iterate through all block infos, starting with the first and going backwards, and skipping
-    // any blocks from forks.
-    async fn get_block_infos(
+    // Scan every BlockInfo and find the forks relative to the most work tip.
+    async fn sub_forks(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+    ) -> Result<Vec<Fork>> {
+        let trx = db.create_trx()?;
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        trx.cancel();
+        let tip = Self::decode_chain_state(&k, &v)?.most_work_tip;
+        let mut scanner = ForkScanner::new();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| scanner.add(&b_info)).await?;
+        Ok(scanner.forks(tip))
+    }
+
+    /// Handles the Quality message.
+    async fn quality(
         &self,
-        db_id: <FDBChainStore as ChainStore>::BlockId,
-        max_blocks: Option<u64>,
-        tx: Sender<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+        window: Window,
         reply: OneshotSender<FDBChainStoreReply>,
     ) -> Result<JoinHandle<()>> {
+        let db = self.db.clone();
+        let chain_dir = self.chain_dir.clone();
         let infos_dir = self.infos_dir.clone();
-        let mut trx = self.db.create_trx().unwrap();
-        let num_blocks = max_blocks.unwrap_or(u64::MAX);
-        let mut id = db_id;
-        reply
-            .send(FDBChainStoreReply::BlockInfosReply)
-            .expect("failed to send reply");
-        Ok(tokio::spawn(async move {
-            let mut x = 0u64;
-            loop {
-                let k = Self::get_block_info_key(&infos_dir, id).unwrap();
-                match trx.get(k.as_slice(), false).await {
-                    Err(e) => match e.code() {
-                        1007 => {
-                            // transaction too old, reset the transaction and continue
-                            trx.reset();
-                        }
-                        _ => {
-                            break;
-                        }
-                    },
-                    Ok(r) => {
-                        if r.is_none() {
-                            break;
-                        }
-                        let b_info = Self::decode_block_info(&r.unwrap());
-                        id = b_info.prev_id;
-                        let h = b_info.height;
-                        tx.send(b_info).await.unwrap();
-                        x += 1;
-                        if x >= num_blocks || h == 0 {
-                            break;
-                        }
-                    }
-                }
-            }
+        let reorgs_dir = self.reorgs_dir.clone();
+        Ok(Self::spawn(async move {
+            let r = Self::sub_quality(&db, &chain_dir, &infos_dir, &reorgs_dir, window).await;
+            reply
+                .send(FDBChainStoreReply::QualityReply(r))
+                .expect("send of reply failed in quality()");
         }))
     }
 
-    /// Implements [ChainStore::store_block_info()].
-    async fn store_block_info(
+    // Scan every BlockInfo and the reorg log and compute the chain quality over the window.
+    async fn sub_quality(
+        db: &foundationdb::Database,
+        chain_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        reorgs_dir: &DirectoryOutput,
+        window: Window,
+    ) -> Result<ChainQuality> {
+        let trx = db.create_trx()?;
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let tip = Self::decode_chain_state(&k, &v)?.most_work_tip;
+        // the reorgs are rare, they are all read
+        let opt = RangeOption::from(reorgs_dir.range()?);
+        let reorgs: Vec<ReorgEvent> = Self::timed(
+            "range",
+            trx.get_ranges_keyvalues(opt, false)
+                .map_ok(|kv| Self::decode_reorg_event(kv.value()))
+                .try_collect::<Vec<_>>(),
+        )
+        .await?;
+        trx.cancel();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut scanner = QualityScanner::new();
+        Self::scan_block_infos(db, chain_dir, infos_dir, |b_info| scanner.add(&b_info)).await?;
+        Ok(scanner.quality(tip, &reorgs, window, now))
+    }
+
+    /// Handles the Snapshot message.
+    async fn snapshot(
         &self,
-        mut block_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+        center: BlockHash,
+        radius: u64,
         reply: OneshotSender<FDBChainStoreReply>,
     ) -> Result<JoinHandle<()>> {
         let trx = self.db.create_trx()?;
-        let h_index_dir = self.h_index_dir.clone();
         let chain_dir = self.chain_dir.clone();
+        let h_index_dir = self.h_index_dir.clone();
         let infos_dir = self.infos_dir.clone();
-        let next_id_lck = self.next_id_lock.clone();
-        Ok(tokio::spawn(async move {
-            // lookup id from hash, creating it if it doesn't exist already
-            match Self::get_block_id_from_hash(&trx, &block_info.hash, &h_index_dir)
-                .await
-                .expect("couldnt get block id from hash")           // todo: remove
-            {
-                None => {
-                    let id = Self::get_next_id(&trx, &next_id_lck, &chain_dir)
-                        .await
-                        .expect("couldnt get next_id");         // todo: remove
-                    let k = Self::get_h_index_key(&h_index_dir, &block_info.hash).unwrap();
-                    let v = Self::encode_h_index(id);
-                    trx.set(&k, &v);
-                    block_info.id = id;
-                }
-                Some(id) => {
-                    block_info.id = id;
+        Ok(Self::spawn(async move {
+            let r = Self::sub_snapshot(&trx, &chain_dir, &h_index_dir, &infos_dir, center, radius)
+                .await;
+            reply
+                .send(FDBChainStoreReply::SnapshotReply(r))
+                .expect("send of reply failed in snapshot()");
+        }))
+    }
+
+    // Read the BlockInfos within radius of the center block, walking both parents and children.
+    async fn sub_snapshot(
+        trx: &Transaction,
+        chain_dir: &DirectoryOutput,
+        h_index_dir: &DirectoryOutput,
+        infos_dir: &InfosDir,
+        center: BlockHash,
+        radius: u64,
+    ) -> Result<Snapshot> {
+        let center_info = Self::sub_block_info_by_hash(trx, &center, h_index_dir, infos_dir)
+            .await?
+            .ok_or(Error::BlockNotFound(center))?;
+        let mut seen = IdSet::default();
+        seen.insert(center_info.id);
+        let mut frontier = vec![center_info.clone()];
+        let mut block_infos = vec![center_info];
+        for _ in 0..radius {
+            let mut ids = Vec::new();
+            for b_info in frontier.iter() {
+                // the genesis block is its own parent
+                if b_info.height > 0 {
+                    ids.push(b_info.prev_id);
                 }
+                ids.extend(b_info.next_ids.iter());
             }
-            let parent = Self::sub_block_info_by_hash(
-                &trx,
-                &block_info.header.prev_hash,
-                &h_index_dir,
-                &infos_dir,
+            ids.retain(|id| seen.insert(*id));
+            if ids.is_empty() {
+                break;
+            }
+            frontier = try_join_all(
+                ids.iter()
+                    .map(|id| Self::sub_expect_block_info(trx, infos_dir, *id)),
             )
-            .await
-            .expect("failed to get parent by hash"); // todo: remove
-            if parent.is_none() {
-                panic!("parent not found"); // todo
-                                            // return Err(ChainStoreError::ParentNotFound)
-            }
-            let mut parent = parent.unwrap();
-            // check that the child is listed in the parents next_ids
-            if !parent.next_ids.contains(&block_info.id) {
-                // update the next_ids in the parent and save it
-                parent.next_ids.push(block_info.id);
-                let k = Self::get_block_info_key(&infos_dir, parent.id).unwrap();
-                let v = Self::encode_block_info(&parent);
-                trx.set(&k, &v);
-            }
-            // update total_size & total_tx if possible
-            if parent.total_size.is_some() && block_info.size.is_some() {
-                block_info.total_size = Some(parent.total_size.unwrap() + block_info.size.unwrap())
-            }
-            if parent.total_tx.is_some() && block_info.num_tx.is_some() {
-                block_info.total_tx = Some(parent.total_tx.unwrap() + block_info.num_tx.unwrap())
-            }
-            // update height, prev_id, and validity
-            block_info.height = parent.height + 1;
-            block_info.prev_id = parent.id;
-            block_info.validity = match parent.validity {
-                BlockValidity::Unknown => BlockValidity::Unknown,
-                BlockValidity::Valid => block_info.validity,
-                BlockValidity::ValidHeader => {
-                    if block_info.validity == BlockValidity::Valid {
-                        BlockValidity::ValidHeader
-                    } else {
-                        block_info.validity
-                    }
-                }
-                BlockValidity::Invalid => BlockValidity::InvalidAncestor,
-                BlockValidity::HeaderInvalid => BlockValidity::InvalidAncestor,
-                BlockValidity::InvalidAncestor => BlockValidity::InvalidAncestor,
-            };
-            // save the block info
-            let k = Self::get_block_info_key(&infos_dir, block_info.id).unwrap();
-            let v = Self::encode_block_info(&block_info);
-            trx.set(&k, &v);
-            trx.commit().await.expect("couldnt commit transaction"); // todo: remove
-
-            // update the chain state if necessary - IAMHERE
+            .await?;
+            block_infos.extend(frontier.iter().cloned());
+        }
+        block_infos.sort_by_key(|b| b.id);
+        let k = Self::get_state_key(chain_dir)?;
+        let v = Self::timed("get", trx.get(k.as_slice(), false))
+            .await?
+            .ok_or(Error::Internal("chainstate missing from db".into()))?;
+        let chain_state = Self::decode_chain_state(&k, &v)?;
+        let summary = Self::sub_summary(trx, chain_dir, infos_dir).await?;
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            created,
+            center,
+            radius,
+            info_shards: infos_dir.shards,
+            chain_state,
+            summary,
+            block_infos,
+            blocks: vec![],
+            blocks_truncated: false,
+        })
+    }
 
-            // send result back
-            reply
-                .send(FDBChainStoreReply::BlockInfoReply(Option::from(block_info)))
-                .expect("send of reply failed in store_block_info()"); // todo: remove
-        }))
+    /// Handle a message, returning the task that processes it, if any.
+    ///
+    /// The handlers reply on a channel of their own, which is forwarded to the reply. A handler
+    /// that fails before it spawns its task is answered with an ErrorReply, the caller sees the
+    /// error rather than the actor stopping.
+    async fn handle_message(
+        &self,
+        msg: FDBChainStoreMessage,
+        reply: OneshotSender<FDBChainStoreReply>,
+    ) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        if self.read_only && msg.is_write() {
+            // the caller may have gone away
+            let _ = reply.send(FDBChainStoreReply::ErrorReply(Error::ReadOnly));
+            return None;
+        }
+        let (tx, rx) = oneshot_channel();
+        let r = match msg {
+            FDBChainStoreMessage::ChainState => self.handle_get_chain_state(tx).await,
+            FDBChainStoreMessage::BlockInfo(db_id) => self.get_block_info(db_id, tx).await,
+            FDBChainStoreMessage::BlockInfoByHash(block_hash) => {
+                self.get_block_info_by_hash(block_hash, tx).await
+            }
+            FDBChainStoreMessage::BlockBriefs(hashes) => self.get_block_briefs(hashes, tx).await,
+            FDBChainStoreMessage::RawBlockInfo(db_id) => self.get_raw_block_info(db_id, tx).await,
+            FDBChainStoreMessage::BlockInfos(block_id, max_blocks, r_tx) => {
+                self.get_block_infos(block_id, max_blocks, r_tx, tx).await
+            }
+            FDBChainStoreMessage::BlockInfosByValidity(validity, r_tx) => {
+                self.get_block_infos_by_validity(validity, r_tx, tx).await
+            }
+            FDBChainStoreMessage::MainChainInfos(r_tx) => self.get_main_chain_infos(r_tx, tx).await,
+            FDBChainStoreMessage::StoreBlockInfo(block_info, options) => {
+                self.store_block_info(block_info, options, tx).await
+            }
+            FDBChainStoreMessage::StoreBlockInfos(blocks, options) => {
+                self.store_block_infos(blocks, options, tx).await
+            }
+            FDBChainStoreMessage::ApplyBranch(blocks, expect_tip, options) => {
+                self.apply_branch(blocks, expect_tip, options, tx).await
+            }
+            FDBChainStoreMessage::CheckWouldLink(block_info) => {
+                self.check_would_link(block_info, tx).await
+            }
+            FDBChainStoreMessage::IsOnMainChain(db_id) => self.is_on_main_chain(db_id, tx).await,
+            FDBChainStoreMessage::Summary => self.summary(tx).await,
+            FDBChainStoreMessage::IsInitialized => {
+                let _ = reply.send(FDBChainStoreReply::BoolReply(Ok(self.was_initialized)));
+                return None;
+            }
+            FDBChainStoreMessage::InfoShards => {
+                let _ = reply.send(FDBChainStoreReply::ShardsReply(self.infos_dir.shards));
+                return None;
+            }
+            FDBChainStoreMessage::Forks => self.forks(tx).await,
+            FDBChainStoreMessage::Quality(window) => self.quality(window, tx).await,
+            FDBChainStoreMessage::ReorgLog(limit) => self.reorg_log(limit, tx).await,
+            FDBChainStoreMessage::Split => self.split(tx).await,
+            FDBChainStoreMessage::Versions => self.versions(tx).await,
+            FDBChainStoreMessage::RecordVersion(writer) => self.record_version(writer, tx).await,
+            FDBChainStoreMessage::CheckCounters(fix) => self.check_counters(fix, tx).await,
+            FDBChainStoreMessage::CheckReachability(fix) => self.check_reachability(fix, tx).await,
+            FDBChainStoreMessage::RepairLinks(fix) => self.repair_links(fix, tx).await,
+            FDBChainStoreMessage::Snapshot(center, radius) => {
+                self.snapshot(center, radius, tx).await
+            }
+            FDBChainStoreMessage::Shutdown => unreachable!("shutdown is handled in run()"),
+        };
+        match r {
+            Ok(j) => Some(Box::pin(async move {
+                // the reply is forwarded as soon as it is sent, a handler that streams its
+                // results goes on after it
+                let forward = async move {
+                    if let Ok(r) = rx.await {
+                        let _ = reply.send(r);
+                    }
+                };
+                let _ = futures::join!(j, forward);
+            })),
+            Err(e) => {
+                let _ = reply.send(FDBChainStoreReply::ErrorReply(e));
+                None
+            }
+        }
     }
 
     /// main actor thread
     async fn run(&mut self) {
         let mut tasks = vec![];
         loop {
-            tokio::select! {
-                Some((msg, reply)) = self.receiver.recv() => {
-                    match msg {
-                        FDBChainStoreMessage::ChainState => {
-                            let j = self.handle_get_chain_state(reply).await.unwrap();
-                            tasks.push(j);
-                        },
-                        FDBChainStoreMessage::BlockInfo(db_id) => {
-                            let j = self.get_block_info(db_id, reply).await.unwrap();
-                            tasks.push(j);
-                        },
-                        FDBChainStoreMessage::BlockInfoByHash(block_hash) => {
-                            let j = self.get_block_info_by_hash(block_hash, reply).await.unwrap();
-                            tasks.push(j);
-                        },
-                        FDBChainStoreMessage::BlockInfos(block_id, max_blocks, r_tx) => {
-                            let j = self.get_block_infos(block_id, max_blocks, r_tx, reply).await.unwrap();
-                            tasks.push(j);
-                        },
-                        FDBChainStoreMessage::StoreBlockInfo(block_info) => {
-                            let j = self.store_block_info(block_info, reply).await.unwrap();
-                            tasks.push(j);
-                        },
-                        FDBChainStoreMessage::Shutdown => {
-                            reply.send(FDBChainStoreReply::Done).expect("unexpected failure shutting down");
-                            break;
-                        }
-                    };
-                },
+            // writes are taken first, so that they are not held up by a flood of reads
+            let (msg, reply, parent, permit) = tokio::select! {
+                biased;
+                Some(m) = self.write_receiver.recv() => m,
+                Some(m) = self.receiver.recv() => m,
                 else => {
                     sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            if let FDBChainStoreMessage::Shutdown = msg {
+                reply
+                    .send(FDBChainStoreReply::Done)
+                    .expect("unexpected failure shutting down");
+                break;
+            }
+            // the handling of the message is traced as a child of the callers span
+            let name = msg.name();
+            // the tips may change after a write
+            let splits = msg.is_write().then(|| self.splits.clone());
+            let span = debug_span!(parent: &parent, "actor", msg = name);
+            let start = Instant::now();
+            self.metrics.start();
+            match self.handle_message(msg, reply).instrument(span).await {
+                Some(j) => {
+                    // the operation completes when the spawned task completes, the permit is
+                    // held until then
+                    let metrics = self.metrics.clone();
+                    tasks.push(tokio::spawn(async move {
+                        j.await;
+                        metrics.finish(name, start.elapsed());
+                        drop(permit);
+                        if let Some(splits) = splits {
+                            splits.request_check();
+                        }
+                    }));
+                }
+                None => {
+                    self.metrics.finish(name, start.elapsed());
+                    drop(permit);
                 }
             }
-            // todo: clean up completed handles
-            // let mut t2 = vec![];
-            // for t in tasks {
-            //
-            // }
+            tasks.retain(|t: &JoinHandle<()>| !t.is_finished());
         }
     }
 }
@@ -786,16 +4925,344 @@ mod tests {
             invalid_tips: vec![6, 7],
         };
         let p = FDBChainStoreActor::encode_chain_state(&s);
-        let u = FDBChainStoreActor::decode_chain_state(&p);
+        let u = FDBChainStoreActor::decode_chain_state(&[], &p).unwrap();
         assert_eq!(u, s);
     }
 
+    #[test]
+    fn version_encoding() {
+        for r in [
+            VersionRecord::new("0.1.0", Some("abc123"), &["a", "b"], 100),
+            VersionRecord::new("0.2.0", None, &[], 200),
+        ] {
+            let v = FDBChainStoreActor::encode_version(&r);
+            assert_eq!(FDBChainStoreActor::decode_version(&[], &v).unwrap(), r);
+        }
+        let v = pack(&("0.1.0", 1u64));
+        let r = FDBChainStoreActor::decode_version(&[1], &v);
+        assert!(matches!(r, Err(Error::CorruptRecord(k, _)) if k == vec![1]));
+    }
+
+    #[test]
+    fn unexpected_reply() {
+        let e = FDBChainStoreReply::BlockInfosReply.unexpected("store_block_info");
+        assert!(
+            matches!(e, Error::Internal(m) if m == "store_block_info received BlockInfosReply")
+        );
+    }
+
+    // Tips with chain work are preferred to tips without it, and the height is only compared if
+    // neither has chain work.
+    #[test]
+    fn more_work() {
+        let tip = BlockInfo::genesis_info(BlockchainId::Main);
+        let mut b = tip.clone();
+        b.height = 1;
+        b.chain_work = Some(vec![1, 0, 1, 0, 2]);
+        assert!(FDBChainStoreActor::has_more_work(&b, &tip));
+        b.chain_work = Some(vec![1, 0, 1, 0, 1]);
+        assert!(!FDBChainStoreActor::has_more_work(&b, &tip));
+        b.height = 100;
+        b.chain_work = None;
+        assert!(!FDBChainStoreActor::has_more_work(&b, &tip));
+        assert!(FDBChainStoreActor::has_more_work(&tip, &b));
+        let mut no_work = tip.clone();
+        no_work.chain_work = None;
+        assert!(FDBChainStoreActor::has_more_work(&b, &no_work));
+        assert!(!FDBChainStoreActor::has_more_work(&no_work, &b));
+    }
+
     #[test]
     fn block_info_encoding() {
-        let b = BlockInfo::genesis_info(BlockchainId::Main);
+        let mut b = BlockInfo::genesis_info(BlockchainId::Main);
         let p = FDBChainStoreActor::encode_block_info(&b);
-        let v = FDBChainStoreActor::decode_block_info(&p);
+        let v = FDBChainStoreActor::decode_block_info(&[], &p).unwrap();
         assert_eq!(b, v);
+        b.received_time = Some(1_700_000_000);
+        let p = FDBChainStoreActor::encode_block_info(&b);
+        let v = FDBChainStoreActor::decode_block_info(&[], &p).unwrap();
+        assert_eq!(b, v);
+        // a BlockInfo stored before the received time was added
+        let mut i = unpack::<Vec<Element>>(&p).unwrap();
+        i.truncate(14);
+        let v = FDBChainStoreActor::decode_block_info(&[], &pack(&i)).unwrap();
+        assert_eq!(v.received_time, None);
+        assert_eq!(v.hash, b.hash);
+        // and before the source was added
+        b.source = Some(String::from("rpc"));
+        let p = FDBChainStoreActor::encode_block_info(&b);
+        let mut i = unpack::<Vec<Element>>(&p).unwrap();
+        assert_eq!(b, FDBChainStoreActor::decode_block_info(&[], &p).unwrap());
+        i.truncate(15);
+        let v = FDBChainStoreActor::decode_block_info(&[], &pack(&i)).unwrap();
+        assert_eq!(v.source, None);
+        assert_eq!(v.received_time, b.received_time);
+        // the fees are only appended when they are known
+        assert_eq!(unpack::<Vec<Element>>(&p).unwrap().len(), 16);
+        b.total_fees = Some(1_000);
+        let p = FDBChainStoreActor::encode_block_info(&b);
+        assert_eq!(unpack::<Vec<Element>>(&p).unwrap().len(), 17);
+        assert_eq!(b, FDBChainStoreActor::decode_block_info(&[], &p).unwrap());
+    }
+
+    // A BlockInfo without the fields of its block is stored in the compact encoding, which
+    // decodes to the same BlockInfo as the full encoding.
+    #[test]
+    fn compact_block_info_encoding() {
+        let mut b = BlockInfo::genesis_info(BlockchainId::Main);
+        b.id = 800_000;
+        b.height = 800_000;
+        b.prev_id = 799_999;
+        b.next_ids = vec![800_001];
+        (b.size, b.num_tx, b.total_tx, b.total_size, b.miner) = (None, None, None, None, None);
+        b.validity = BlockValidity::ValidHeader;
+        let full = FDBChainStoreActor::encode_block_info_full(&b);
+        let compact = FDBChainStoreActor::encode_block_info(&b);
+        assert_eq!(compact[0], FDBChainStoreActor::BLOCK_INFO_COMPACT);
+        // the five fields of the block and the received time and source are omitted
+        assert_eq!(compact.len(), full.len() - 6);
+        let check = |b: &BlockInfo<u64>, v: &[u8]| {
+            assert_eq!(&FDBChainStoreActor::decode_block_info(&[], v).unwrap(), b);
+            let prefix = FDBChainStoreActor::decode_block_info_prefix(&[], v).unwrap();
+            assert_eq!((prefix.id, prefix.height), (b.id, b.height));
+            assert_eq!(prefix.validity, b.validity);
+            assert!(prefix.has_children);
+            let walk = FDBChainStoreActor::decode_walk_prefix(&[], v).unwrap();
+            assert_eq!(walk, (b.height, b.prev_id));
+        };
+        check(&b, &compact);
+        check(&b, &full);
+        b.received_time = Some(1_700_000_000);
+        check(&b, &FDBChainStoreActor::encode_block_info(&b));
+        b.received_time = None;
+        b.source = Some(String::from("p2p"));
+        check(&b, &FDBChainStoreActor::encode_block_info(&b));
+        b.median_time = None;
+        b.chain_work = None;
+        b.source = None;
+        let v = FDBChainStoreActor::encode_block_info(&b);
+        check(&b, &v);
+        assert_eq!(unpack::<Vec<Element>>(&v[1..]).unwrap().len(), 7);
+        // a BlockInfo with any field of its block is stored in the full encoding
+        b.miner = Some(String::new());
+        let v = FDBChainStoreActor::encode_block_info(&b);
+        assert_eq!(v, FDBChainStoreActor::encode_block_info_full(&b));
+        check(&b, &v);
+        // compact records that are not valid
+        let key = [0x15, 7];
+        let mut i = unpack::<Vec<Element>>(&compact[1..]).unwrap();
+        let compact_of = |i: &[Element]| {
+            let mut v = vec![FDBChainStoreActor::BLOCK_INFO_COMPACT];
+            v.extend(pack(&i.to_vec()));
+            v
+        };
+        let short = compact_of(&i[..6]);
+        i.extend([Element::Nil, Element::Nil, Element::Nil]);
+        let long = compact_of(&i);
+        for v in [vec![FDBChainStoreActor::BLOCK_INFO_COMPACT], short, long] {
+            let e = FDBChainStoreActor::decode_block_info(&key, &v).unwrap_err();
+            assert!(matches!(e, Error::CorruptRecord(k, _) if k == key));
+        }
+        let short = compact_of(&i[..6]);
+        let e = FDBChainStoreActor::decode_block_info_prefix(&key, &short).unwrap_err();
+        assert!(matches!(e, Error::CorruptRecord(k, _) if k == key));
+    }
+
+    // Malformed records are reported with their key instead of panicking.
+    #[test]
+    fn corrupt_records() {
+        let b = BlockInfo::genesis_info(BlockchainId::Main);
+        let mut i = unpack::<Vec<Element>>(&FDBChainStoreActor::encode_block_info(&b)).unwrap();
+        let key = [0x15, 7];
+        let corrupt = |v: &[u8]| FDBChainStoreActor::decode_block_info(&key, v).unwrap_err();
+        let mut short = i.clone();
+        short.truncate(13);
+        let mut no_header = i.clone();
+        no_header[2] = Element::Int(1);
+        i[1] = Element::Bytes(Bytes::from(vec![1, 2, 3]));
+        for v in [
+            vec![],
+            vec![0xff, 0x00],
+            pack(&(1u64, 2u64)),
+            pack(&short),
+            pack(&i),
+            pack(&no_header),
+        ] {
+            assert!(matches!(corrupt(&v), Error::CorruptRecord(k, _) if k == key));
+        }
+        let state = pack(&(1u64, vec![Element::String(Cow::from("x"))], 2u64, 3u64));
+        for v in [vec![], vec![0x05], state] {
+            let e = FDBChainStoreActor::decode_chain_state(&key, &v).unwrap_err();
+            assert!(matches!(e, Error::CorruptRecord(k, _) if k == key));
+        }
+    }
+
+    // A run chains each BlockInfo to its parent, notes whether the ids are contiguous and ends at
+    // genesis.
+    #[test]
+    fn walk_run_push() {
+        let info = |id: u64, height: u64, prev_id: u64| {
+            let mut b = BlockInfo::genesis_info(BlockchainId::Main);
+            b.id = id;
+            b.height = height;
+            b.prev_id = prev_id;
+            FDBChainStoreActor::encode_block_info(&b)
+        };
+        let mut run = WalkRun {
+            values: Vec::new(),
+            next: Some(9),
+            reads: 0,
+            contiguous: true,
+        };
+        run.push(vec![9], info(9, 3, 8)).unwrap();
+        assert_eq!((run.next, run.contiguous), (Some(8), true));
+        run.push(vec![8], info(8, 2, 4)).unwrap();
+        assert_eq!((run.next, run.contiguous), (Some(4), false));
+        run.push(vec![4], info(4, 1, 0)).unwrap();
+        run.push(vec![0], info(0, 0, 0)).unwrap();
+        assert_eq!((run.next, run.contiguous), (None, false));
+        assert_eq!(
+            run.values.iter().map(|(k, _)| k[0]).collect::<Vec<_>>(),
+            vec![9, 8, 4, 0]
+        );
+        let e = run.push(vec![1], vec![0x05]).unwrap_err();
+        assert!(matches!(e, Error::CorruptRecord(k, _) if k == [1]));
+    }
+
+    // The prefix decoder agrees with the full decoder, and skips escaped bytes, nils and the
+    // nested tuple of next_ids.
+    #[test]
+    fn block_info_prefix() {
+        let genesis = BlockInfo::genesis_info(BlockchainId::Main);
+        let mut b = genesis.clone();
+        b.id = u64::MAX - 1;
+        b.height = 70_000;
+        b.next_ids = vec![0, 300, u64::MAX];
+        b.hash = BlockHash::from(
+            (0..32)
+                .map(|i| [0, 0xff][i % 2])
+                .collect::<Vec<u8>>()
+                .as_slice(),
+        );
+        b.miner = Some(String::from("a\0b"));
+        b.chain_work = None;
+        b.validity = BlockValidity::InvalidAncestor;
+        b.received_time = Some(1_700_000_000);
+        b.prev_id = u64::MAX - 2;
+        for b in [genesis, b] {
+            let p = FDBChainStoreActor::encode_block_info(&b);
+            let full = FDBChainStoreActor::decode_block_info(&[], &p).unwrap();
+            let prefix = FDBChainStoreActor::decode_block_info_prefix(&[], &p).unwrap();
+            assert_eq!(
+                prefix,
+                BlockInfoPrefix {
+                    id: full.id,
+                    height: full.height,
+                    has_children: !full.next_ids.is_empty(),
+                    validity: full.validity,
+                }
+            );
+            let walk = FDBChainStoreActor::decode_walk_prefix(&[], &p).unwrap();
+            assert_eq!(walk, (full.height, full.prev_id));
+        }
+        let key = [0x15, 7];
+        let i = unpack::<Vec<Element>>(&FDBChainStoreActor::encode_block_info(
+            &BlockInfo::genesis_info(BlockchainId::Main),
+        ))
+        .unwrap();
+        let mut short = i.clone();
+        short.truncate(13);
+        let mut not_int = i.clone();
+        not_int[3] = Element::Nil;
+        let cut = pack(&i)[..50].to_vec();
+        for v in [vec![], vec![0x15], pack(&short), pack(&not_int), cut] {
+            let e = FDBChainStoreActor::decode_block_info_prefix(&key, &v).unwrap_err();
+            assert!(matches!(e, Error::CorruptRecord(k, _) if k == key));
+        }
+        short.truncate(4);
+        for v in [vec![], vec![0x15], pack(&short), pack(&not_int)] {
+            let e = FDBChainStoreActor::decode_walk_prefix(&key, &v).unwrap_err();
+            assert!(matches!(e, Error::CorruptRecord(k, _) if k == key));
+        }
+    }
+
+    // Every validity is persisted and read back, a discriminant that is not known is an error.
+    #[test]
+    fn validity_encoding() {
+        let mut b = BlockInfo::genesis_info(BlockchainId::Main);
+        for validity in BlockValidity::ALL {
+            b.validity = validity.clone();
+            let p = FDBChainStoreActor::encode_block_info(&b);
+            let full = FDBChainStoreActor::decode_block_info(&[], &p).unwrap();
+            assert_eq!(full.validity, validity);
+            let prefix = FDBChainStoreActor::decode_block_info_prefix(&[], &p).unwrap();
+            assert_eq!(prefix.validity, validity);
+        }
+        let mut i = unpack::<Vec<Element>>(&FDBChainStoreActor::encode_block_info(&b)).unwrap();
+        for v in [6, 255, 256, -1] {
+            i[13] = Element::Int(v);
+            let p = pack(&i);
+            for e in [
+                FDBChainStoreActor::decode_block_info(&[], &p).unwrap_err(),
+                FDBChainStoreActor::decode_block_info_prefix(&[], &p).unwrap_err(),
+            ] {
+                assert!(matches!(
+                    e,
+                    Error::UnknownEnumVariant("validity", u, FDBChainStoreActor::LAYOUT_LATEST)
+                        if u == v
+                ));
+            }
+        }
+    }
+
+    // Only the blocks whose place is known from their own record are marked on or off the main
+    // chain.
+    #[test]
+    fn brief_main_chain() {
+        let prefix = |id, height, has_children, validity| BlockInfoPrefix {
+            id,
+            height,
+            has_children,
+            validity,
+        };
+        let tip = prefix(9, 10, false, BlockValidity::ValidHeader);
+        let on_main_chain = |p: BlockInfoPrefix| p.brief(&tip).on_main_chain;
+        assert_eq!(
+            on_main_chain(prefix(9, 10, false, BlockValidity::ValidHeader)),
+            Some(true)
+        );
+        assert_eq!(
+            on_main_chain(prefix(3, 5, false, BlockValidity::ValidHeader)),
+            Some(false)
+        );
+        assert_eq!(
+            on_main_chain(prefix(3, 5, true, BlockValidity::Invalid)),
+            Some(false)
+        );
+        assert_eq!(
+            on_main_chain(prefix(3, 10, true, BlockValidity::Valid)),
+            Some(false)
+        );
+        assert_eq!(
+            on_main_chain(prefix(3, 5, true, BlockValidity::Valid)),
+            None
+        );
+        let brief = prefix(3, 5, true, BlockValidity::Valid).brief(&tip);
+        assert_eq!((brief.id, brief.height), (3, 5));
+    }
+
+    #[test]
+    fn record_encoding() {
+        let mut b = BlockInfo::genesis_info(BlockchainId::Main);
+        b.id = 5;
+        let (k, v) = FDBChainStore::block_info_record(&b, 4);
+        assert_eq!(k, vec![0x15, 1, 0x15, 5]);
+        assert_eq!(FDBChainStoreActor::decode_block_info(&k, &v).unwrap(), b);
+        let (k, _) = FDBChainStore::block_info_record(&b, 0);
+        assert_eq!(k, vec![0x15, 5]);
+        let (k, v) = FDBChainStore::hash_index_record(&b.hash, 5);
+        assert_eq!(k[0], 0x01);
+        assert_eq!(FDBChainStoreActor::decode_h_index(&v), 5);
     }
 
     #[test]
@@ -806,6 +5273,93 @@ mod tests {
         assert_eq!(i, k);
     }
 
+    #[test]
+    fn reorg_event_encoding() {
+        let e = ReorgEvent {
+            timestamp: 1_700_000_000,
+            old_tip: 10,
+            new_tip: 12,
+            fork_height: 7,
+            orphaned_count: 3,
+        };
+        let p = FDBChainStoreActor::encode_reorg_event(&e);
+        assert_eq!(FDBChainStoreActor::decode_reorg_event(&p), e);
+    }
+
+    #[test]
+    fn counter_encoding() {
+        let i = 76265u64;
+        let j = FDBChainStoreActor::encode_counter(i);
+        assert_eq!(j.len(), 8);
+        let k = FDBChainStoreActor::decode_counter(&j);
+        assert_eq!(i, k);
+    }
+
+    #[test]
+    fn layout_encoding() {
+        for shards in [0u64, 1, 16] {
+            let v = FDBChainStoreActor::encode_layout(shards);
+            assert_eq!(FDBChainStoreActor::decode_layout(&v).unwrap(), shards);
+        }
+        let v = pack(&(99u64, 16u64));
+        assert!(matches!(
+            FDBChainStoreActor::decode_layout(&v),
+            Err(Error::UnsupportedLayout(99))
+        ));
+    }
+
+    #[test]
+    fn shard_merging() {
+        let info = |id| BlockInfo {
+            id,
+            ..BlockInfo::genesis_info(BlockchainId::Main)
+        };
+        // ids 0..10 spread across 4 shards
+        let shards = (0..4u64)
+            .map(|s| (0..10u64).filter(|i| i % 4 == s).map(info).collect())
+            .collect();
+        let ids: Vec<u64> = merge_shards(shards).iter().map(|b| b.id).collect();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn child_validity() {
+        use BlockValidity::*;
+        let v = FDBChainStoreActor::child_validity;
+        assert_eq!(v(&Valid, Valid), Valid);
+        assert_eq!(v(&Valid, Invalid), Invalid);
+        assert_eq!(v(&Unknown, Valid), Unknown);
+        assert_eq!(v(&ValidHeader, Valid), ValidHeader);
+        assert_eq!(v(&ValidHeader, Unknown), Unknown);
+        assert_eq!(v(&Invalid, Valid), InvalidAncestor);
+        assert_eq!(v(&HeaderInvalid, Unknown), InvalidAncestor);
+        assert_eq!(v(&InvalidAncestor, Valid), InvalidAncestor);
+    }
+
+    #[test]
+    fn branch_checking() {
+        let child = |parent: &BlockInfo<u64>| {
+            let mut header = parent.header.clone();
+            header.prev_hash = parent.hash;
+            BlockInfo {
+                hash: header.hash(),
+                header,
+                ..parent.clone()
+            }
+        };
+        let a = child(&BlockInfo::genesis_info(BlockchainId::Main));
+        let b = child(&a);
+        let c = child(&b);
+        assert!(FDBChainStoreActor::check_branch(&[a.clone()]).is_ok());
+        assert!(FDBChainStoreActor::check_branch(&[a.clone(), b.clone(), c.clone()]).is_ok());
+        let r = FDBChainStoreActor::check_branch(&[]);
+        assert!(matches!(r, Err(Error::InvalidBranch(_))));
+        let r = FDBChainStoreActor::check_branch(&[a, c]);
+        assert!(
+            matches!(r, Err(Error::InvalidBranch(m)) if m == "block 1 is not the child of block 0")
+        );
+    }
+
     #[test]
     fn tuple_experiments() {
         let t = (1, 2, 3);
@@ -0,0 +1,715 @@
+use crate::BlockInfo;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The maximum number of comparisons in a [Filter].
+pub const MAX_FILTER_TERMS: usize = 64;
+/// The maximum nesting of parentheses and negations in a [Filter].
+pub const MAX_FILTER_DEPTH: usize = 16;
+
+/// A filter of BlockInfos, parsed from an expression such as
+/// `size > 1000000000 && miner == null && time >= 2024-01-01`.
+///
+/// An expression compares the fields of a BlockInfo with literals, using `==`, `!=`, `<`, `<=`,
+/// `>` and `>=`, and combines the comparisons with `&&`, `||`, `!` and parentheses. `&&` binds
+/// more tightly than `||`. The literals are numbers, times as `YYYY-MM-DD` or
+/// `YYYY-MM-DDTHH:MM:SS` in UTC, which are compared as seconds since the unix epoch, strings in
+/// double quotes or as bare words, and `null`.
+///
+/// The fields are id, height, prev_id, children, size, num_tx, median_time, total_tx,
+/// total_size, received_time, time, bits and nonce, which are numbers, and hash, prev_hash,
+/// miner, validity and source, which can only be tested for equality and are compared ignoring
+/// case.
+/// Fields that are not known are `null`, a comparison with a `null` field is false unless it
+/// tests for `null`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(FilterField, FilterOp, FilterLiteral),
+}
+
+/// The fields of a BlockInfo that a [Filter] can compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    Id,
+    Hash,
+    PrevHash,
+    Height,
+    PrevId,
+    /// The number of children of the block.
+    Children,
+    Size,
+    NumTx,
+    MedianTime,
+    TotalTx,
+    TotalSize,
+    Miner,
+    Validity,
+    ReceivedTime,
+    /// Where the block was imported from, see [BlockInfo::source].
+    Source,
+    /// The timestamp of the header.
+    Time,
+    Bits,
+    Nonce,
+}
+
+/// The comparison operators of a [Filter].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The literals of a [Filter].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterLiteral {
+    Null,
+    Number(u64),
+    Text(String),
+}
+
+/// An error in a [Filter] expression, with the position of the offending token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    /// The expression.
+    pub expr: String,
+    /// The position of the offending token, in characters from the start of the expression.
+    pub position: usize,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl Filter {
+    /// Whether the BlockInfo matches the filter.
+    pub fn matches(&self, b_info: &BlockInfo<u64>) -> bool {
+        match self {
+            Filter::And(l, r) => l.matches(b_info) && r.matches(b_info),
+            Filter::Or(l, r) => l.matches(b_info) || r.matches(b_info),
+            Filter::Not(f) => !f.matches(b_info),
+            Filter::Compare(field, op, literal) => compare(field.value(b_info), *op, literal),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            expr: s,
+            tokens,
+            next: 0,
+            terms: 0,
+        };
+        let filter = parser.or(0)?;
+        match parser.peek() {
+            (Token::End, _) => Ok(filter),
+            (t, pos) => Err(parser.error(pos, format!("unexpected {}", t))),
+        }
+    }
+}
+
+// Fully parenthesized, so that the structure is visible.
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::And(l, r) => write!(f, "({} && {})", l, r),
+            Filter::Or(l, r) => write!(f, "({} || {})", l, r),
+            Filter::Not(n) => write!(f, "!{}", n),
+            Filter::Compare(field, op, literal) => write!(f, "{} {} {}", field, op, literal),
+        }
+    }
+}
+
+const FIELDS: [(&str, FilterField); 18] = [
+    ("id", FilterField::Id),
+    ("hash", FilterField::Hash),
+    ("prev_hash", FilterField::PrevHash),
+    ("height", FilterField::Height),
+    ("prev_id", FilterField::PrevId),
+    ("children", FilterField::Children),
+    ("size", FilterField::Size),
+    ("num_tx", FilterField::NumTx),
+    ("median_time", FilterField::MedianTime),
+    ("total_tx", FilterField::TotalTx),
+    ("total_size", FilterField::TotalSize),
+    ("miner", FilterField::Miner),
+    ("validity", FilterField::Validity),
+    ("received_time", FilterField::ReceivedTime),
+    ("source", FilterField::Source),
+    ("time", FilterField::Time),
+    ("bits", FilterField::Bits),
+    ("nonce", FilterField::Nonce),
+];
+
+impl FilterField {
+    /// The name of the field in an expression.
+    pub fn name(&self) -> &'static str {
+        FIELDS.iter().find(|(_, f)| f == self).unwrap().0
+    }
+
+    // whether the field is compared as text rather than as a number
+    fn is_text(&self) -> bool {
+        matches!(
+            self,
+            FilterField::Hash
+                | FilterField::PrevHash
+                | FilterField::Miner
+                | FilterField::Validity
+                | FilterField::Source
+        )
+    }
+
+    // the value of the field in the BlockInfo
+    fn value(&self, b: &BlockInfo<u64>) -> FilterLiteral {
+        let number = |v: Option<u64>| v.map_or(FilterLiteral::Null, FilterLiteral::Number);
+        match self {
+            FilterField::Id => FilterLiteral::Number(b.id),
+            FilterField::Hash => FilterLiteral::Text(b.hash.to_string()),
+            FilterField::PrevHash => FilterLiteral::Text(b.header.prev_hash.to_string()),
+            FilterField::Height => FilterLiteral::Number(b.height),
+            FilterField::PrevId => FilterLiteral::Number(b.prev_id),
+            FilterField::Children => FilterLiteral::Number(b.next_ids.len() as u64),
+            FilterField::Size => number(b.size),
+            FilterField::NumTx => number(b.num_tx),
+            FilterField::MedianTime => number(b.median_time),
+            FilterField::TotalTx => number(b.total_tx),
+            FilterField::TotalSize => number(b.total_size),
+            FilterField::Miner => b
+                .miner
+                .clone()
+                .map_or(FilterLiteral::Null, FilterLiteral::Text),
+            FilterField::Validity => FilterLiteral::Text(format!("{:?}", b.validity)),
+            FilterField::ReceivedTime => number(b.received_time),
+            FilterField::Source => b
+                .source
+                .clone()
+                .map_or(FilterLiteral::Null, FilterLiteral::Text),
+            FilterField::Time => FilterLiteral::Number(b.header.timestamp as u64),
+            FilterField::Bits => FilterLiteral::Number(b.header.bits as u64),
+            FilterField::Nonce => FilterLiteral::Number(b.header.nonce as u64),
+        }
+    }
+}
+
+impl Display for FilterField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for FilterOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            FilterOp::Eq => "==",
+            FilterOp::Ne => "!=",
+            FilterOp::Lt => "<",
+            FilterOp::Le => "<=",
+            FilterOp::Gt => ">",
+            FilterOp::Ge => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Display for FilterLiteral {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterLiteral::Null => write!(f, "null"),
+            FilterLiteral::Number(n) => write!(f, "{}", n),
+            FilterLiteral::Text(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+// The message, followed by the expression with a caret below the offending token.
+impl Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at column {}\n  {}\n  {}^",
+            self.message,
+            self.position + 1,
+            self.expr,
+            " ".repeat(self.position)
+        )
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+// compare the value of a field with a literal
+fn compare(value: FilterLiteral, op: FilterOp, literal: &FilterLiteral) -> bool {
+    match (value, literal) {
+        (FilterLiteral::Null, FilterLiteral::Null) => op == FilterOp::Eq,
+        (_, FilterLiteral::Null) => op == FilterOp::Ne,
+        (FilterLiteral::Null, _) => false,
+        (FilterLiteral::Number(v), FilterLiteral::Number(l)) => match op {
+            FilterOp::Eq => v == *l,
+            FilterOp::Ne => v != *l,
+            FilterOp::Lt => v < *l,
+            FilterOp::Le => v <= *l,
+            FilterOp::Gt => v > *l,
+            FilterOp::Ge => v >= *l,
+        },
+        (FilterLiteral::Text(v), FilterLiteral::Text(l)) => match op {
+            FilterOp::Eq => v.eq_ignore_ascii_case(l),
+            FilterOp::Ne => !v.eq_ignore_ascii_case(l),
+            _ => false,
+        },
+        // the parser does not allow the other combinations
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(u64),
+    Time(u64),
+    Text(String),
+    Op(FilterOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "\"{}\"", w),
+            Token::Number(n) | Token::Time(n) => write!(f, "{}", n),
+            Token::Text(s) => write!(f, "{:?}", s),
+            Token::Op(op) => write!(f, "\"{}\"", op),
+            Token::And => write!(f, "\"&&\""),
+            Token::Or => write!(f, "\"||\""),
+            Token::Not => write!(f, "\"!\""),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+// split the expression into tokens, with their positions in characters
+fn tokenize(expr: &str) -> Result<Vec<(Token, usize)>, FilterError> {
+    let chars: Vec<char> = expr.chars().collect();
+    let error = |position, message: &str| FilterError {
+        expr: expr.to_string(),
+        position,
+        message: message.to_string(),
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let next = chars.get(i + 1).copied();
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => Token::And,
+            ('|', Some('|')) => Token::Or,
+            ('=', Some('=')) => Token::Op(FilterOp::Eq),
+            ('!', Some('=')) => Token::Op(FilterOp::Ne),
+            ('<', Some('=')) => Token::Op(FilterOp::Le),
+            ('>', Some('=')) => Token::Op(FilterOp::Ge),
+            ('<', _) => Token::Op(FilterOp::Lt),
+            ('>', _) => Token::Op(FilterOp::Gt),
+            ('!', _) => Token::Not,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or_else(|| error(start, "unterminated string"))?;
+                i += end + 2;
+                tokens.push((Token::Text(chars[start + 1..i - 1].iter().collect()), start));
+                continue;
+            }
+            (c, _) if c.is_ascii_alphanumeric() || c == '_' => {
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || "_-:".contains(chars[i]))
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push((word_token(word).map_err(|m| error(start, m))?, start));
+                continue;
+            }
+            _ => return Err(error(start, &format!("unexpected character '{}'", c))),
+        };
+        i += match token {
+            Token::Op(FilterOp::Lt) | Token::Op(FilterOp::Gt) | Token::Not => 1,
+            Token::Open | Token::Close => 1,
+            _ => 2,
+        };
+        tokens.push((token, start));
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+// classify a word as a number, a time or a bare word
+fn word_token(word: String) -> Result<Token, &'static str> {
+    if word.chars().all(|c| c.is_ascii_digit()) {
+        word.parse()
+            .map(Token::Number)
+            .map_err(|_| "number too large")
+    } else if word.starts_with(|c: char| c.is_ascii_digit()) && word.contains('-') {
+        parse_time(&word)
+            .map(Token::Time)
+            .ok_or("invalid time, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS")
+    } else if word.contains(['-', ':']) {
+        Err("invalid word")
+    } else {
+        Ok(Token::Word(word))
+    }
+}
+
+// parse a YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS time into seconds since the unix epoch
+fn parse_time(s: &str) -> Option<u64> {
+    let (date, time) = match s.split_once('T') {
+        Some((d, t)) => (d, Some(t)),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    // the year has at most four digits, larger years would overflow the calculation
+    if !(0..=9999).contains(&y) || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    let mut seconds = 0;
+    if let Some(t) = time {
+        let hms: Vec<u64> = t
+            .split(':')
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?;
+        if hms.len() != 3 || hms[0] > 23 || hms[1] > 59 || hms[2] > 59 {
+            return None;
+        }
+        seconds = hms[0] * 3600 + hms[1] * 60 + hms[2];
+    }
+    // days from the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400).ok().map(|s| s + seconds)
+}
+
+// A recursive descent parser of the tokens, the depth is limited by MAX_FILTER_DEPTH.
+struct Parser<'a> {
+    expr: &'a str,
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    // the number of comparisons so far
+    terms: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> (Token, usize) {
+        self.tokens[self.next].clone()
+    }
+
+    fn advance(&mut self) -> (Token, usize) {
+        let t = self.peek();
+        if t.0 != Token::End {
+            self.next += 1;
+        }
+        t
+    }
+
+    fn error(&self, position: usize, message: String) -> FilterError {
+        FilterError {
+            expr: self.expr.to_string(),
+            position,
+            message,
+        }
+    }
+
+    // or := and ("||" and)*
+    fn or(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut f = self.and(depth)?;
+        while self.peek().0 == Token::Or {
+            self.advance();
+            f = Filter::Or(Box::new(f), Box::new(self.and(depth)?));
+        }
+        Ok(f)
+    }
+
+    // and := unary ("&&" unary)*
+    fn and(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut f = self.unary(depth)?;
+        while self.peek().0 == Token::And {
+            self.advance();
+            f = Filter::And(Box::new(f), Box::new(self.unary(depth)?));
+        }
+        Ok(f)
+    }
+
+    // unary := "!" unary | "(" or ")" | comparison
+    fn unary(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let (token, pos) = self.peek();
+        if (token == Token::Not || token == Token::Open) && depth >= MAX_FILTER_DEPTH {
+            return Err(self.error(
+                pos,
+                format!(
+                    "expression is nested too deeply, at most {}",
+                    MAX_FILTER_DEPTH
+                ),
+            ));
+        }
+        match token {
+            Token::Not => {
+                self.advance();
+                Ok(Filter::Not(Box::new(self.unary(depth + 1)?)))
+            }
+            Token::Open => {
+                self.advance();
+                let f = self.or(depth + 1)?;
+                match self.advance() {
+                    (Token::Close, _) => Ok(f),
+                    (t, pos) => Err(self.error(pos, format!("expected \")\", found {}", t))),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    // comparison := field op literal
+    fn comparison(&mut self) -> Result<Filter, FilterError> {
+        let (token, pos) = self.advance();
+        let field = match &token {
+            Token::Word(w) => FIELDS
+                .iter()
+                .find(|(name, _)| name == w)
+                .map(|(_, f)| *f)
+                .ok_or_else(|| self.error(pos, format!("unknown field {}", token)))?,
+            t => return Err(self.error(pos, format!("expected a field, found {}", t))),
+        };
+        self.terms += 1;
+        if self.terms > MAX_FILTER_TERMS {
+            return Err(self.error(
+                pos,
+                format!("too many comparisons, at most {}", MAX_FILTER_TERMS),
+            ));
+        }
+        let (op, op_pos) = match self.advance() {
+            (Token::Op(op), pos) => (op, pos),
+            (t, pos) => {
+                return Err(self.error(pos, format!("expected a comparison operator, found {}", t)))
+            }
+        };
+        let (token, pos) = self.advance();
+        let literal = match token {
+            Token::Word(w) if w == "null" => FilterLiteral::Null,
+            Token::Word(w) | Token::Text(w) if field.is_text() => FilterLiteral::Text(w),
+            Token::Number(n) | Token::Time(n) if !field.is_text() => FilterLiteral::Number(n),
+            Token::Word(_) | Token::Text(_) | Token::Number(_) | Token::Time(_) => {
+                let kind = if field.is_text() {
+                    "text"
+                } else {
+                    "a number or a time"
+                };
+                return Err(self.error(pos, format!("{} must be compared with {}", field, kind)));
+            }
+            t => return Err(self.error(pos, format!("expected a value, found {}", t))),
+        };
+        if !matches!(op, FilterOp::Eq | FilterOp::Ne) {
+            if literal == FilterLiteral::Null {
+                return Err(self.error(
+                    op_pos,
+                    String::from("null can only be compared with == or !="),
+                ));
+            } else if field.is_text() {
+                return Err(self.error(
+                    op_pos,
+                    format!("{} can only be compared with == or !=", field),
+                ));
+            }
+        }
+        Ok(Filter::Compare(field, op, literal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockValidity;
+    use bitcoinsv::bitcoin::{BlockHeader, BlockchainId};
+
+    fn parse(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    fn error(s: &str) -> FilterError {
+        s.parse::<Filter>().unwrap_err()
+    }
+
+    fn info(size: Option<u64>, miner: Option<&str>) -> BlockInfo<u64> {
+        let mut b = BlockInfo::genesis_info(BlockchainId::Main);
+        b.id = 7;
+        b.height = 7;
+        b.size = size;
+        b.miner = miner.map(String::from);
+        b.validity = BlockValidity::Invalid;
+        b
+    }
+
+    // Valid expressions, with the precedence of the operators.
+    #[test]
+    fn parse_valid() {
+        assert_eq!(
+            parse("size > 1000000000 && miner == null && time >= 2024-01-01").to_string(),
+            "((size > 1000000000 && miner == null) && time >= 1704067200)"
+        );
+        assert_eq!(
+            parse("height < 5 || height > 10 && num_tx == 1").to_string(),
+            "(height < 5 || (height > 10 && num_tx == 1))"
+        );
+        assert_eq!(
+            parse("(height < 5 || height > 10) && !validity == Invalid").to_string(),
+            "((height < 5 || height > 10) && !validity == \"Invalid\")"
+        );
+        assert_eq!(
+            parse("miner != \"Some Pool\"").to_string(),
+            "miner != \"Some Pool\""
+        );
+        assert_eq!(
+            parse("received_time<=2024-01-01T01:02:03"),
+            Filter::Compare(
+                FilterField::ReceivedTime,
+                FilterOp::Le,
+                FilterLiteral::Number(1704070923)
+            )
+        );
+        let hash = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        assert_eq!(
+            parse(&format!("hash == {}", hash)),
+            Filter::Compare(
+                FilterField::Hash,
+                FilterOp::Eq,
+                FilterLiteral::Text(hash.to_string())
+            )
+        );
+    }
+
+    // Invalid expressions are reported at the offending token.
+    #[test]
+    fn parse_invalid() {
+        let e = error("size > 10 && )");
+        assert_eq!(
+            (e.position, e.message.as_str()),
+            (13, "expected a field, found \")\"")
+        );
+        assert_eq!(
+            e.to_string(),
+            "expected a field, found \")\" at column 14\n  size > 10 && )\n               ^"
+        );
+        assert_eq!(error("weight > 10").message, "unknown field \"weight\"");
+        assert_eq!(error("size > 10 height").position, 10);
+        assert_eq!(
+            error("size 10").message,
+            "expected a comparison operator, found 10"
+        );
+        assert_eq!(
+            error("size > big").message,
+            "size must be compared with a number or a time"
+        );
+        assert_eq!(
+            error("miner > \"a\"").message,
+            "miner can only be compared with == or !="
+        );
+        assert_eq!(error("size < null").position, 5);
+        assert_eq!(error("miner == \"a").message, "unterminated string");
+        assert_eq!(error("time > 2024-13-01").position, 7);
+        assert_eq!(error("time > 99999999999999999-01-01").position, 7);
+        assert_eq!(
+            error("size > 1 & size < 2").message,
+            "unexpected character '&'"
+        );
+        assert_eq!(
+            error("(size > 1").message,
+            "expected \")\", found end of expression"
+        );
+        assert_eq!(
+            error("").message,
+            "expected a field, found end of expression"
+        );
+    }
+
+    // Deeply nested or very long expressions are rejected.
+    #[test]
+    fn complexity() {
+        let nested = format!(
+            "{}size > 1{}",
+            "(".repeat(MAX_FILTER_DEPTH),
+            ")".repeat(MAX_FILTER_DEPTH)
+        );
+        assert!(nested.parse::<Filter>().is_ok());
+        let nested = format!("!{}", nested);
+        assert!(error(&nested)
+            .message
+            .starts_with("expression is nested too deeply"));
+        let long = vec!["size > 1"; MAX_FILTER_TERMS].join(" || ");
+        assert!(long.parse::<Filter>().is_ok());
+        let long = format!("{} || size > 1", long);
+        assert!(error(&long).message.starts_with("too many comparisons"));
+        let deep = "!".repeat(100_000) + "size > 1";
+        assert_eq!(error(&deep).position, MAX_FILTER_DEPTH);
+    }
+
+    // Evaluation against BlockInfos, including null fields.
+    #[test]
+    fn evaluate() {
+        let big = info(Some(2_000_000_000), None);
+        let small = info(Some(1000), Some("Some Pool"));
+        let unknown = info(None, None);
+        let f = parse("size > 1000000000 && miner == null");
+        assert!(f.matches(&big));
+        assert!(!f.matches(&small));
+        assert!(!f.matches(&unknown));
+        // a comparison with a null field is false
+        assert!(!parse("size < 10").matches(&unknown));
+        assert!(!parse("size >= 10").matches(&unknown));
+        assert!(parse("!size >= 10").matches(&unknown));
+        assert!(parse("size == null").matches(&unknown));
+        assert!(parse("size != null").matches(&small));
+        assert!(!parse("miner != \"Other\"").matches(&big));
+        assert!(parse("miner != \"Other\"").matches(&small));
+        assert!(parse("miner == \"some pool\"").matches(&small));
+        let mut imported = small.clone();
+        imported.source = Some(String::from("nodeindex"));
+        assert!(parse("source == NodeIndex").matches(&imported));
+        assert!(!parse("source == rpc").matches(&imported));
+        assert!(parse("source == null").matches(&small));
+        assert!(parse("validity == invalid && height == 7 && id == 7").matches(&small));
+        assert!(parse("validity != Valid || size > 0").matches(&small));
+        // the genesis block of main was mined on 2009-01-03
+        assert!(parse("time >= 2009-01-03 && time < 2009-01-04").matches(&small));
+        assert!(parse("children == 0 && prev_id == 0 && bits == 486604799").matches(&small));
+        let hash = BlockHeader::get_genesis(BlockchainId::Main)
+            .hash()
+            .to_string();
+        assert!(parse(&format!("hash == \"{}\"", hash.to_uppercase())).matches(&small));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::BlockInfo;
+use bitcoinsv::bitcoin::BlockHash;
+use std::collections::{HashMap, HashSet};
+
+/// A fork in the chain, a block with more than one child.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fork {
+    /// The id of the block at the fork point.
+    pub id: u64,
+    /// The hash of the block at the fork point.
+    pub hash: BlockHash,
+    /// The height of the block at the fork point.
+    pub height: u64,
+    /// The competing branches, one for each child of the fork point.
+    pub branches: Vec<ForkBranch>,
+    /// Forks off the losing branches of this fork.
+    pub children: Vec<Fork>,
+}
+
+/// A branch of a [Fork].
+///
+/// Where a losing branch itself forks, the branch follows the path with the most work and the
+/// fork is reported as a child of the parent [Fork].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkBranch {
+    /// The id of the first block in the branch, a child of the fork point.
+    pub first_id: u64,
+    /// The id of the tip of the branch.
+    pub tip_id: u64,
+    /// The hash of the tip of the branch.
+    pub tip_hash: BlockHash,
+    /// The number of blocks in the branch.
+    pub length: u64,
+    /// The total work of the blocks in the branch, approximated from the header targets.
+    pub work: f64,
+    /// Whether the branch is part of the main chain.
+    pub won: bool,
+    /// The timestamp of the first block in the branch.
+    pub first_time: u32,
+    /// The timestamp of the tip of the branch.
+    pub last_time: u32,
+    /// The miners of the blocks in the branch, when known. For the winning branch, only the
+    /// blocks competing with the longest losing branch are considered.
+    pub miners: Vec<String>,
+}
+
+impl Fork {
+    /// The length of the longest losing branch.
+    pub fn max_losing_length(&self) -> u64 {
+        self.branches
+            .iter()
+            .filter(|b| !b.won)
+            .map(|b| b.length)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// The information about a block that is needed to find forks.
+struct ForkNode {
+    hash: BlockHash,
+    prev_id: u64,
+    height: u64,
+    next_ids: Vec<u64>,
+    work: f64,
+    time: u32,
+    miner: Option<String>,
+}
+
+// The best path through a losing branch.
+struct Path {
+    tip_id: u64,
+    length: u64,
+    work: f64,
+    miners: Vec<String>,
+}
+
+/// Finds the forks in a chain.
+///
+/// BlockInfos are added one at a time, in any order, so that the chain can be scanned without
+/// holding every BlockInfo in memory. Only a compact summary of each block is kept.
+#[derive(Default)]
+pub struct ForkScanner {
+    nodes: HashMap<u64, ForkNode>,
+}
+
+impl ForkScanner {
+    /// Create a new, empty, ForkScanner.
+    pub fn new() -> ForkScanner {
+        ForkScanner::default()
+    }
+
+    /// Add a block.
+    pub fn add(&mut self, block_info: &BlockInfo<u64>) {
+        self.nodes.insert(
+            block_info.id,
+            ForkNode {
+                hash: block_info.hash,
+                prev_id: block_info.prev_id,
+                height: block_info.height,
+                next_ids: block_info.next_ids.clone(),
+                work: block_work(block_info.header.bits),
+                time: block_info.header.timestamp,
+                miner: block_info.miner.clone(),
+            },
+        );
+    }
+
+    /// Get the forks, given the id of the tip of the main chain.
+    ///
+    /// Forks off the main chain are returned sorted by height, descending. Forks off losing
+    /// branches are returned as children of the fork of that branch, also sorted by height.
+    pub fn forks(&self, tip: u64) -> Vec<Fork> {
+        // the main chain, with the cumulative work up to each block
+        let mut main_chain = Vec::new();
+        let mut id = tip;
+        while let Some(n) = self.nodes.get(&id) {
+            main_chain.push(id);
+            if n.height == 0 {
+                break;
+            }
+            id = n.prev_id;
+        }
+        main_chain.reverse();
+        let mut cum_work = HashMap::new();
+        let mut total = 0f64;
+        for id in main_chain.iter() {
+            total += self.nodes[id].work;
+            cum_work.insert(*id, total);
+        }
+        let on_main: HashSet<u64> = main_chain.iter().cloned().collect();
+
+        let mut fork_ids: Vec<u64> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.next_ids.len() > 1)
+            .map(|(id, _)| *id)
+            .collect();
+        fork_ids.sort_by_key(|id| self.nodes[id].height);
+        // find the parent of each fork off a losing branch, the nearest fork point below it
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut top = Vec::new();
+        for id in fork_ids.iter() {
+            if on_main.contains(id) {
+                top.push(*id);
+                continue;
+            }
+            let mut p = self.nodes[id].prev_id;
+            while let Some(n) = self.nodes.get(&p) {
+                if n.next_ids.len() > 1 || n.height == 0 {
+                    break;
+                }
+                p = n.prev_id;
+            }
+            children.entry(p).or_default().push(*id);
+        }
+        let mut r: Vec<Fork> = top
+            .iter()
+            .map(|id| self.build_fork(*id, &on_main, &cum_work, &children, tip))
+            .collect();
+        r.sort_by(|a, b| b.height.cmp(&a.height));
+        r
+    }
+
+    // Build the report for the fork at id, including its children.
+    fn build_fork(
+        &self,
+        id: u64,
+        on_main: &HashSet<u64>,
+        cum_work: &HashMap<u64, f64>,
+        children: &HashMap<u64, Vec<u64>>,
+        tip: u64,
+    ) -> Fork {
+        let node = &self.nodes[&id];
+        let mut branches = Vec::new();
+        for first_id in node.next_ids.iter() {
+            let first = match self.nodes.get(first_id) {
+                Some(n) => n,
+                None => continue,
+            };
+            if on_main.contains(first_id) {
+                branches.push(ForkBranch {
+                    first_id: *first_id,
+                    tip_id: tip,
+                    tip_hash: self.nodes[&tip].hash,
+                    length: self.nodes[&tip].height - node.height,
+                    work: cum_work[&tip] - cum_work[&id],
+                    won: true,
+                    first_time: first.time,
+                    last_time: self.nodes[&tip].time,
+                    miners: vec![],
+                });
+            } else {
+                let path = self.best_path(*first_id);
+                let path_tip = &self.nodes[&path.tip_id];
+                branches.push(ForkBranch {
+                    first_id: *first_id,
+                    tip_id: path.tip_id,
+                    tip_hash: path_tip.hash,
+                    length: path.length,
+                    work: path.work,
+                    won: false,
+                    first_time: first.time,
+                    last_time: path_tip.time,
+                    miners: path.miners,
+                });
+            }
+        }
+        // the miners of the winning branch, over the length of the longest losing branch
+        let max_losing = branches
+            .iter()
+            .filter(|b| !b.won)
+            .map(|b| b.length)
+            .max()
+            .unwrap_or(0);
+        if let Some(b) = branches.iter_mut().find(|b| b.won) {
+            let mut id = b.first_id;
+            for _ in 0..max_losing {
+                let n = &self.nodes[&id];
+                if let Some(m) = &n.miner {
+                    if !b.miners.contains(m) {
+                        b.miners.push(m.clone());
+                    }
+                }
+                match n.next_ids.iter().find(|i| on_main.contains(*i)) {
+                    Some(i) => id = *i,
+                    None => break,
+                }
+            }
+        }
+        let mut c: Vec<Fork> = children
+            .get(&id)
+            .map(|v| {
+                v.iter()
+                    .map(|i| self.build_fork(*i, on_main, cum_work, children, tip))
+                    .collect()
+            })
+            .unwrap_or_default();
+        c.sort_by(|a, b| b.height.cmp(&a.height));
+        Fork {
+            id,
+            hash: node.hash,
+            height: node.height,
+            branches,
+            children: c,
+        }
+    }
+
+    // Find the path with the most work from the block to a tip.
+    fn best_path(&self, id: u64) -> Path {
+        let node = &self.nodes[&id];
+        let best = node
+            .next_ids
+            .iter()
+            .filter(|i| self.nodes.contains_key(*i))
+            .map(|i| self.best_path(*i))
+            .fold(None, |best: Option<Path>, p| match best {
+                Some(b) if b.work >= p.work => Some(b),
+                _ => Some(p),
+            });
+        let mut path = best.unwrap_or(Path {
+            tip_id: id,
+            length: 0,
+            work: 0.0,
+            miners: vec![],
+        });
+        path.length += 1;
+        path.work += node.work;
+        if let Some(m) = &node.miner {
+            if !path.miners.contains(m) {
+                path.miners.insert(0, m.clone());
+            }
+        }
+        path
+    }
+}
+
+/// The approximate work of a block, given the compact encoding of its target.
+pub fn block_work(bits: u32) -> f64 {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = (bits & 0x00ff_ffff) as f64;
+    if mantissa == 0.0 {
+        return 0.0;
+    }
+    // work = 2^256 / target, where target = mantissa * 256^(exponent - 3)
+    2f64.powi(256 - 8 * (exponent - 3)) / mantissa
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockValidity;
+    use bitcoinsv::bitcoin::{BlockHeader, BlockchainId};
+
+    // make a block info with the given id, parent and height
+    fn info(id: u64, prev_id: u64, height: u64, next_ids: Vec<u64>) -> BlockInfo<u64> {
+        let mut header = BlockHeader::get_genesis(BlockchainId::Main);
+        header.nonce = id as u32;
+        header.timestamp = 1_000 + height as u32;
+        BlockInfo {
+            id,
+            hash: header.hash(),
+            header,
+            height,
+            prev_id,
+            next_ids,
+            size: None,
+            num_tx: None,
+            median_time: None,
+            chain_work: None,
+            total_tx: None,
+            total_size: None,
+            total_fees: None,
+            miner: None,
+            validity: BlockValidity::Unknown,
+            received_time: None,
+            source: None,
+        }
+    }
+
+    // A main chain of 0..=10, with two forks off the main chain and a fork off a losing branch.
+    //
+    //  0 - 1 - 2 - 3 - 4 - 5 - 6 - 7 - 8 - 9 - 10
+    //              |               \
+    //              |                 13
+    //              11 - 12
+    //                \
+    //                  14 - 15
+    fn fixture() -> ForkScanner {
+        let mut s = ForkScanner::new();
+        for i in 0..=10u64 {
+            let next_ids = match i {
+                3 => vec![4, 11],
+                6 => vec![7, 13],
+                10 => vec![],
+                _ => vec![i + 1],
+            };
+            s.add(&info(i, i.saturating_sub(1), i, next_ids));
+        }
+        s.add(&info(11, 3, 4, vec![12, 14]));
+        let mut b = info(12, 11, 5, vec![]);
+        b.miner = Some(String::from("miner a"));
+        s.add(&b);
+        s.add(&info(13, 6, 7, vec![]));
+        let mut b = info(14, 11, 5, vec![15]);
+        b.miner = Some(String::from("miner b"));
+        s.add(&b);
+        s.add(&info(15, 14, 6, vec![]));
+        s
+    }
+
+    #[test]
+    fn test_block_work() {
+        // genesis block work
+        assert_eq!(block_work(0x1d00ffff).round(), 4295032833.0);
+    }
+
+    #[test]
+    fn test_forks() {
+        let s = fixture();
+        let w = block_work(BlockHeader::get_genesis(BlockchainId::Main).bits);
+        let forks = s.forks(10);
+        assert_eq!(forks.len(), 2);
+
+        // sorted by height descending
+        let f = &forks[0];
+        assert_eq!((f.id, f.height), (6, 6));
+        assert_eq!(f.branches.len(), 2);
+        assert_eq!(
+            (
+                f.branches[0].first_id,
+                f.branches[0].tip_id,
+                f.branches[0].length
+            ),
+            (7, 10, 4)
+        );
+        assert!(f.branches[0].won);
+        assert!((f.branches[0].work - 4.0 * w).abs() < 1.0);
+        assert_eq!(
+            (
+                f.branches[1].first_id,
+                f.branches[1].tip_id,
+                f.branches[1].length
+            ),
+            (13, 13, 1)
+        );
+        assert!(!f.branches[1].won);
+        assert_eq!(
+            (f.branches[1].first_time, f.branches[1].last_time),
+            (1_007, 1_007)
+        );
+        assert!(f.children.is_empty());
+
+        let f = &forks[1];
+        assert_eq!((f.id, f.height), (3, 3));
+        assert_eq!(f.max_losing_length(), 3);
+        assert_eq!(
+            (
+                f.branches[0].first_id,
+                f.branches[0].tip_id,
+                f.branches[0].length
+            ),
+            (4, 10, 7)
+        );
+        assert!(f.branches[0].won);
+        assert!(f.branches[0].miners.is_empty());
+        // the losing branch follows the path with the most work
+        assert_eq!(
+            (
+                f.branches[1].first_id,
+                f.branches[1].tip_id,
+                f.branches[1].length
+            ),
+            (11, 15, 3)
+        );
+        assert!(!f.branches[1].won);
+        assert!((f.branches[1].work - 3.0 * w).abs() < 1.0);
+        assert_eq!(
+            (f.branches[1].first_time, f.branches[1].last_time),
+            (1_004, 1_006)
+        );
+        assert_eq!(f.branches[1].miners, vec![String::from("miner b")]);
+
+        // the nested fork
+        assert_eq!(f.children.len(), 1);
+        let c = &f.children[0];
+        assert_eq!((c.id, c.height), (11, 4));
+        assert!(c.children.is_empty());
+        assert_eq!(c.branches.len(), 2);
+        assert_eq!(
+            (
+                c.branches[0].first_id,
+                c.branches[0].tip_id,
+                c.branches[0].length
+            ),
+            (12, 12, 1)
+        );
+        assert_eq!(c.branches[0].miners, vec![String::from("miner a")]);
+        assert_eq!(
+            (
+                c.branches[1].first_id,
+                c.branches[1].tip_id,
+                c.branches[1].length
+            ),
+            (14, 15, 2)
+        );
+        assert!(c.branches.iter().all(|b| !b.won));
+    }
+}
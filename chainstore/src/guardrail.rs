@@ -0,0 +1,421 @@
+use crate::{Error, Result};
+use bsvdb_base::ChainStoreConfig;
+use std::time::Instant;
+
+/// What the ChainStore does when a write trips the guardrail, see
+/// [ChainStoreConfig::guardrail].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GuardrailAction {
+    /// The blocks are counted, the limits are not checked.
+    #[default]
+    Off,
+    /// A warning is logged and the write is made.
+    Log,
+    /// The write fails with Error::GuardrailTripped unless it is forced, see
+    /// [InsertOptions::force].
+    Force,
+    /// The write fails with Error::GuardrailTripped.
+    Reject,
+}
+
+impl GuardrailAction {
+    /// The action with the name used in the config, Off for an unknown name, which is reported
+    /// when the config is validated.
+    pub fn from_name(name: &str) -> GuardrailAction {
+        match name {
+            "log" => GuardrailAction::Log,
+            "force" => GuardrailAction::Force,
+            "reject" => GuardrailAction::Reject,
+            _ => GuardrailAction::Off,
+        }
+    }
+
+    /// The name of the action, as used in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GuardrailAction::Off => "off",
+            GuardrailAction::Log => "log",
+            GuardrailAction::Force => "force",
+            GuardrailAction::Reject => "reject",
+        }
+    }
+}
+
+/// The options of a write of blocks, see
+/// [FDBChainStore::store_block_info_with_options()](crate::FDBChainStore::store_block_info_with_options).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InsertOptions {
+    /// Make the write even if it trips the guardrail, when the guardrail is configured with the
+    /// "force" action.
+    pub force: bool,
+}
+
+/// The state of the guardrail, see
+/// [FDBChainStore::guardrail_stats()](crate::FDBChainStore::guardrail_stats).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GuardrailStats {
+    /// What is done when a write trips the guardrail.
+    pub action: GuardrailAction,
+    /// Whether initial sync mode is enabled, in which the depth limit is not checked.
+    pub initial_sync: bool,
+    /// The number of blocks stored in the last minute.
+    pub recent_inserts: u64,
+    /// The number of writes that tripped the limit on the number of blocks stored in a minute.
+    pub rate_trips: u64,
+    /// The number of writes that tripped the limit on the depth of a block below the most work
+    /// tip.
+    pub depth_trips: u64,
+}
+
+/// Catches runaway imports of junk blocks, by limiting the number of blocks that are stored in a
+/// minute and how far below the most work tip they can be, shared between the actor and the
+/// handles.
+///
+/// The blocks stored are counted in a ring buffer with a slot for each second of the last minute,
+/// so that a check does not depend on the number of blocks.
+pub(crate) struct Guardrail {
+    action: GuardrailAction,
+    max_rate: u64,
+    max_depth: u64,
+    initial_sync: bool,
+    // the time that the seconds of the slots are counted from
+    start: Instant,
+    // the number of blocks stored in each second, in the slot of the second modulo the window
+    counts: [u64; Self::WINDOW],
+    // the second that each slot is counting
+    seconds: [u64; Self::WINDOW],
+    rate_trips: u64,
+    depth_trips: u64,
+}
+
+impl Guardrail {
+    // the number of seconds over which the blocks stored are counted
+    const WINDOW: usize = 60;
+
+    pub(crate) fn new(config: &ChainStoreConfig) -> Self {
+        Guardrail {
+            action: GuardrailAction::from_name(&config.guardrail),
+            max_rate: config.guardrail_max_rate,
+            max_depth: config.guardrail_max_depth,
+            initial_sync: false,
+            start: Instant::now(),
+            counts: [0; Self::WINDOW],
+            seconds: [0; Self::WINDOW],
+            rate_trips: 0,
+            depth_trips: 0,
+        }
+    }
+
+    /// Enable or disable initial sync mode, in which the depth limit is not checked.
+    pub(crate) fn set_initial_sync(&mut self, enabled: bool) {
+        self.initial_sync = enabled;
+    }
+
+    /// The state of the guardrail.
+    pub(crate) fn stats(&self) -> GuardrailStats {
+        self.stats_at(Instant::now())
+    }
+
+    /// Check a write of a number of new blocks, the lowest of which is at lowest_height, against
+    /// the limits, given the height of the most work tip before the write. The blocks are not
+    /// counted until the write has been made, see record().
+    pub(crate) fn check(
+        &mut self,
+        blocks: u64,
+        lowest_height: u64,
+        tip_height: u64,
+        options: InsertOptions,
+    ) -> Result<()> {
+        self.check_at(blocks, lowest_height, tip_height, options, Instant::now())
+    }
+
+    /// Count the new blocks of a write that has been made.
+    pub(crate) fn record(&mut self, blocks: u64) {
+        self.record_at(blocks, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        blocks: u64,
+        lowest_height: u64,
+        tip_height: u64,
+        options: InsertOptions,
+        now: Instant,
+    ) -> Result<()> {
+        let second = self.second(now);
+        if self.action != GuardrailAction::Off {
+            let mut tripped = Vec::new();
+            let recent = self.recent(second) + blocks;
+            if self.max_rate > 0 && recent > self.max_rate {
+                self.rate_trips += 1;
+                tripped.push(format!(
+                    "{} blocks stored in the last minute, the limit is {}",
+                    recent, self.max_rate
+                ));
+            }
+            if self.max_depth > 0
+                && !self.initial_sync
+                && lowest_height + self.max_depth < tip_height
+            {
+                self.depth_trips += 1;
+                tripped.push(format!(
+                    "block at height {} is {} below the most work tip, the limit is {}",
+                    lowest_height,
+                    tip_height - lowest_height,
+                    self.max_depth
+                ));
+            }
+            if !tripped.is_empty() {
+                let reason = tripped.join(", ");
+                match self.action {
+                    GuardrailAction::Force if options.force => {
+                        tracing::warn!("guardrail tripped, write forced: {}", reason)
+                    }
+                    GuardrailAction::Force | GuardrailAction::Reject => {
+                        return Err(Error::GuardrailTripped(reason))
+                    }
+                    _ => tracing::warn!("guardrail tripped: {}", reason),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_at(&mut self, blocks: u64, now: Instant) {
+        let second = self.second(now);
+        let slot = second as usize % Self::WINDOW;
+        if self.seconds[slot] != second {
+            self.seconds[slot] = second;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] += blocks;
+    }
+
+    fn stats_at(&self, now: Instant) -> GuardrailStats {
+        GuardrailStats {
+            action: self.action,
+            initial_sync: self.initial_sync,
+            recent_inserts: self.recent(self.second(now)),
+            rate_trips: self.rate_trips,
+            depth_trips: self.depth_trips,
+        }
+    }
+
+    // the second of the time, counted from the start
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    // the number of blocks stored in the minute up to and including the second
+    fn recent(&self, second: u64) -> u64 {
+        self.seconds
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|(s, _)| second.saturating_sub(**s) < Self::WINDOW as u64)
+            .map(|(_, n)| n)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn guardrail(action: &str, max_rate: u64, max_depth: u64) -> Guardrail {
+        Guardrail::new(&ChainStoreConfig {
+            guardrail: String::from(action),
+            guardrail_max_rate: max_rate,
+            guardrail_max_depth: max_depth,
+            ..ChainStoreConfig::default()
+        })
+    }
+
+    const FORCE: InsertOptions = InsertOptions { force: true };
+
+    // check a write and count its blocks if it can proceed, as the chain store does once the
+    // write has been committed
+    fn store_at(
+        g: &mut Guardrail,
+        blocks: u64,
+        lowest_height: u64,
+        tip_height: u64,
+        options: InsertOptions,
+        now: Instant,
+    ) -> Result<()> {
+        g.check_at(blocks, lowest_height, tip_height, options, now)?;
+        g.record_at(blocks, now);
+        Ok(())
+    }
+
+    // Writes past the rate limit get the configured response, the limit applies to the last
+    // minute.
+    #[test]
+    fn rate_limit() {
+        for action in ["log", "force", "reject"] {
+            let mut g = guardrail(action, 10, 0);
+            let t = g.start;
+            for h in 1..=10 {
+                store_at(&mut g, 1, h, h - 1, InsertOptions::default(), t).unwrap();
+            }
+            let r = store_at(&mut g, 1, 11, 10, InsertOptions::default(), t);
+            match action {
+                "log" => assert!(r.is_ok()),
+                _ => assert!(matches!(r, Err(Error::GuardrailTripped(_)))),
+            }
+            let r = store_at(&mut g, 1, 11, 10, FORCE, t);
+            match action {
+                "reject" => assert!(matches!(r, Err(Error::GuardrailTripped(_)))),
+                _ => assert!(r.is_ok()),
+            }
+            let stats = g.stats_at(t);
+            assert_eq!(stats.action.name(), action);
+            assert_eq!(stats.rate_trips, 2);
+            assert_eq!(stats.depth_trips, 0);
+            // the rejected writes are not counted
+            let stored = match action {
+                "log" => 12,
+                "force" => 11,
+                _ => 10,
+            };
+            assert_eq!(stats.recent_inserts, stored);
+            // a minute later the blocks have left the window
+            let later = t + Duration::from_secs(60);
+            assert_eq!(g.stats_at(later).recent_inserts, 0);
+            store_at(&mut g, 10, 12, 11, InsertOptions::default(), later).unwrap();
+            assert_eq!(g.stats_at(later).rate_trips, 2);
+        }
+    }
+
+    // The blocks are counted in the second that they were stored, for a minute.
+    #[test]
+    fn window() {
+        let mut g = guardrail("reject", 100, 0);
+        let t = g.start;
+        for s in 0..90 {
+            store_at(
+                &mut g,
+                1,
+                s,
+                s,
+                InsertOptions::default(),
+                t + Duration::from_secs(s),
+            )
+            .unwrap();
+        }
+        assert_eq!(g.stats_at(t + Duration::from_secs(89)).recent_inserts, 60);
+        assert_eq!(g.stats_at(t + Duration::from_secs(119)).recent_inserts, 30);
+        assert_eq!(g.stats_at(t + Duration::from_secs(200)).recent_inserts, 0);
+        // a write of many blocks is checked as a whole
+        let r = store_at(
+            &mut g,
+            41,
+            90,
+            89,
+            InsertOptions::default(),
+            t + Duration::from_secs(89),
+        );
+        assert!(matches!(r, Err(Error::GuardrailTripped(_))));
+    }
+
+    // Blocks too far below the most work tip get the configured response, except in initial sync
+    // mode, in which they are still counted.
+    #[test]
+    fn depth_limit() {
+        for action in ["log", "force", "reject"] {
+            let mut g = guardrail(action, 0, 5);
+            let t = g.start;
+            store_at(&mut g, 1, 95, 100, InsertOptions::default(), t).unwrap();
+            let r = store_at(&mut g, 1, 94, 100, InsertOptions::default(), t);
+            match action {
+                "log" => assert!(r.is_ok()),
+                _ => assert!(matches!(r, Err(Error::GuardrailTripped(_)))),
+            }
+            let r = store_at(&mut g, 1, 94, 100, FORCE, t);
+            match action {
+                "reject" => assert!(matches!(r, Err(Error::GuardrailTripped(_)))),
+                _ => assert!(r.is_ok()),
+            }
+            assert_eq!(g.stats_at(t).depth_trips, 2);
+            let before = g.stats_at(t).recent_inserts;
+            g.set_initial_sync(true);
+            for h in 0..10 {
+                store_at(&mut g, 1, h, 100, InsertOptions::default(), t).unwrap();
+            }
+            let stats = g.stats_at(t);
+            assert!(stats.initial_sync);
+            assert_eq!(stats.depth_trips, 2);
+            assert_eq!(stats.recent_inserts, before + 10);
+        }
+    }
+
+    // A write that passes the check but is not made, such as one whose commit fails, is not
+    // counted.
+    #[test]
+    fn failed_write() {
+        let mut g = guardrail("reject", 2, 0);
+        let t = g.start;
+        for h in 1..=5 {
+            g.check_at(1, h, h - 1, InsertOptions::default(), t)
+                .unwrap();
+        }
+        assert_eq!(g.stats_at(t).recent_inserts, 0);
+        store_at(&mut g, 2, 1, 0, InsertOptions::default(), t).unwrap();
+        assert_eq!(g.stats_at(t).recent_inserts, 2);
+        let r = g.check_at(1, 3, 2, InsertOptions::default(), t);
+        assert!(matches!(r, Err(Error::GuardrailTripped(_))));
+    }
+
+    // Initial sync mode does not bypass the rate limit.
+    #[test]
+    fn initial_sync_rate() {
+        let mut g = guardrail("reject", 5, 5);
+        g.set_initial_sync(true);
+        let t = g.start;
+        store_at(&mut g, 5, 0, 100, InsertOptions::default(), t).unwrap();
+        let r = store_at(&mut g, 1, 5, 100, InsertOptions::default(), t);
+        assert!(matches!(r, Err(Error::GuardrailTripped(_))));
+        assert_eq!(g.stats_at(t).rate_trips, 1);
+    }
+
+    // Following the chain, a block every few minutes with the odd short reorg, never trips.
+    #[test]
+    fn follow_mode() {
+        let mut g = guardrail("reject", 60, 6);
+        let t = g.start;
+        let mut tip = 800_000;
+        for n in 0..1_000u64 {
+            let now = t + Duration::from_secs(n * 200);
+            if n % 50 == 0 {
+                // a reorg of two blocks
+                store_at(&mut g, 3, tip - 1, tip, InsertOptions::default(), now).unwrap();
+                tip += 1;
+            } else {
+                store_at(&mut g, 1, tip + 1, tip, InsertOptions::default(), now).unwrap();
+                tip += 1;
+            }
+        }
+        let stats = g.stats_at(t + Duration::from_secs(1_000 * 200));
+        assert_eq!((stats.rate_trips, stats.depth_trips), (0, 0));
+    }
+
+    // The default is off, which only counts the blocks.
+    #[test]
+    fn off() {
+        let mut g = Guardrail::new(&ChainStoreConfig {
+            guardrail_max_rate: 1,
+            guardrail_max_depth: 1,
+            ..ChainStoreConfig::default()
+        });
+        let t = g.start;
+        store_at(&mut g, 10, 0, 100, InsertOptions::default(), t).unwrap();
+        let stats = g.stats_at(t);
+        assert_eq!(stats.action, GuardrailAction::Off);
+        assert_eq!(stats.recent_inserts, 10);
+        assert_eq!((stats.rate_trips, stats.depth_trips), (0, 0));
+        assert_eq!(
+            GuardrailAction::from_name("reject"),
+            GuardrailAction::Reject
+        );
+    }
+}
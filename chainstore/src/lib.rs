@@ -1,7 +1,47 @@
+mod admission;
+mod boot;
 mod chain_store;
+mod directories;
 mod fdb_chain_store;
+mod filter;
+mod forks;
+mod guardrail;
+#[cfg(any(test, feature = "test-util"))]
+mod memory_store;
+mod metrics;
+mod profile;
+mod quality;
+mod reachability;
 mod result;
+mod snapshot;
+mod split;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+mod work;
 
-pub use chain_store::{BlockInfo, BlockValidity, ChainStore};
-pub use fdb_chain_store::FDBChainStore;
+pub use admission::{ClassMetrics, OperationClass};
+pub use boot::boot;
+pub use chain_store::{
+    BlockBrief, BlockInfo, BlockInfoStream, BlockInfoStreamFromChannel, BlockValidity,
+    BranchOutcome, ChainState, ChainStore, LinkCheck, StoreSummary,
+};
+pub use fdb_chain_store::{CounterDrift, FDBChainStore, ReorgEvent};
+pub use filter::{
+    Filter, FilterError, FilterField, FilterLiteral, FilterOp, MAX_FILTER_DEPTH, MAX_FILTER_TERMS,
+};
+pub use forks::{block_work, Fork, ForkBranch, ForkScanner};
+pub use guardrail::{GuardrailAction, GuardrailStats, InsertOptions};
+pub use metrics::{Metrics, OperationMetrics, LATENCY_BUCKETS};
+pub use profile::OperationProfile;
+pub use quality::{ChainQuality, IntervalStats, QualityScanner, ReorgDepth, Window};
+pub use reachability::{LinkRepair, ReachabilityFix, ReachabilityReport, UnreachableBlock};
 pub use result::{Error, Result};
+pub use snapshot::{Snapshot, SNAPSHOT_VERSION};
+pub use split::{find_split, ChainEvent, Split, SplitTip};
+
+// Compile-time checks that the public handles and streams can be shared between tasks.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<FDBChainStore>();
+    assert_send_sync::<BlockInfoStreamFromChannel<u64>>();
+};
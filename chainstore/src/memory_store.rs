@@ -0,0 +1,326 @@
+use crate::{
+    BlockBrief, BlockInfo, BlockInfoStreamFromChannel, BlockValidity, BranchOutcome, ChainState,
+    ChainStore, Error, LinkCheck, Result, StoreSummary,
+};
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
+use futures::{stream, Stream};
+use std::collections::HashMap;
+use std::future::{ready, Future};
+use std::sync::Mutex;
+use tokio::sync::mpsc::channel;
+
+/// A ChainStore that is kept in memory and uses the block hash as the BlockId, for the tests of
+/// code that uses a ChainStore without a foundationdb cluster.
+///
+/// It also shows that the trait and the shared checks in
+/// [check_chain_store()](crate::test_util::check_chain_store) do not depend on integer ids. The
+/// most work tip is the highest block that is not invalid, there are no dormant tips.
+pub struct MemoryChainStore {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    infos: HashMap<BlockHash, BlockInfo<BlockHash>>,
+    genesis: BlockHash,
+    tip: BlockHash,
+}
+
+impl MemoryChainStore {
+    /// A ChainStore that holds only the genesis block of the blockchain.
+    pub fn new(blockchain: BlockchainId) -> MemoryChainStore {
+        let hash = BlockHeader::get_genesis(blockchain).hash();
+        let genesis = BlockInfo::genesis_info_with_id(blockchain, hash);
+        MemoryChainStore {
+            inner: Mutex::new(Inner {
+                infos: HashMap::from([(hash, genesis)]),
+                genesis: hash,
+                tip: hash,
+            }),
+        }
+    }
+}
+
+impl Inner {
+    fn link(&self, info: &BlockInfo<BlockHash>) -> LinkCheck<BlockHash> {
+        match self.infos.get(&info.header.prev_hash) {
+            None => LinkCheck::ParentNotFound,
+            Some(parent) => LinkCheck::Links {
+                height: parent.height + 1,
+                prev_id: parent.id,
+                validity: child_validity(&parent.validity, info.validity.clone()),
+                existing_id: self.infos.get(&info.hash).map(|b| b.id),
+            },
+        }
+    }
+
+    fn store(&mut self, mut info: BlockInfo<BlockHash>) -> Result<BlockInfo<BlockHash>> {
+        let (height, prev_id, validity) = match self.link(&info) {
+            LinkCheck::ParentNotFound => return Err(Error::ParentNotFound),
+            LinkCheck::Links {
+                height,
+                prev_id,
+                validity,
+                ..
+            } => (height, prev_id, validity),
+        };
+        info.id = info.hash;
+        info.height = height;
+        info.prev_id = prev_id;
+        info.validity = validity;
+        info.next_ids = self
+            .infos
+            .get(&info.hash)
+            .map(|b| b.next_ids.clone())
+            .unwrap_or_default();
+        let parent = self.infos.get_mut(&prev_id).expect("parent was found");
+        if !parent.next_ids.contains(&info.id) {
+            parent.next_ids.push(info.id);
+        }
+        self.infos.insert(info.id, info.clone());
+        Ok(info)
+    }
+
+    // the blocks from the id down to the genesis block, at most max_blocks
+    fn walk(&self, id: BlockHash, max_blocks: Option<u64>) -> Vec<Result<BlockInfo<BlockHash>>> {
+        let mut r = Vec::new();
+        let mut id = Some(id);
+        while let Some(b) = id.and_then(|id| self.infos.get(&id)) {
+            if max_blocks.is_some_and(|m| r.len() as u64 >= m) {
+                break;
+            }
+            r.push(Ok(b.clone()));
+            id = (b.id != self.genesis).then_some(b.prev_id);
+        }
+        r
+    }
+
+    // the brief of the block, which is on the main chain if the walk from the tip reaches it
+    fn brief(&self, hash: &BlockHash) -> Option<BlockBrief<BlockHash>> {
+        let b = self.infos.get(hash)?;
+        let main_chain = self.walk(self.tip, None);
+        let on_main_chain = main_chain.iter().any(|m| m.as_ref().unwrap().id == b.id);
+        Some(BlockBrief {
+            id: b.id,
+            height: b.height,
+            on_main_chain: Some(on_main_chain),
+            validity: b.validity.clone(),
+        })
+    }
+}
+
+// the validity that a block gets when it is stored, as for the FDBChainStore
+fn child_validity(parent: &BlockValidity, validity: BlockValidity) -> BlockValidity {
+    match parent {
+        BlockValidity::Unknown => BlockValidity::Unknown,
+        BlockValidity::Valid => validity,
+        BlockValidity::ValidHeader if validity == BlockValidity::Valid => {
+            BlockValidity::ValidHeader
+        }
+        BlockValidity::ValidHeader => validity,
+        _ => BlockValidity::InvalidAncestor,
+    }
+}
+
+fn is_invalid(validity: &BlockValidity) -> bool {
+    matches!(
+        validity,
+        BlockValidity::Invalid | BlockValidity::HeaderInvalid | BlockValidity::InvalidAncestor
+    )
+}
+
+#[async_trait]
+impl ChainStore for MemoryChainStore {
+    type BlockId = BlockHash;
+
+    fn get_chain_state(&self) -> impl Future<Output = Result<ChainState<BlockHash>>> + Send {
+        let inner = self.inner.lock().unwrap();
+        let (invalid_tips, active_tips) = inner
+            .infos
+            .values()
+            .filter(|b| b.next_ids.is_empty() || b.id == inner.tip)
+            .map(|b| (b.id, is_invalid(&b.validity)))
+            .partition::<Vec<_>, _>(|(_, invalid)| *invalid);
+        ready(Ok(ChainState {
+            most_work_tip: inner.tip,
+            active_tips: active_tips.into_iter().map(|(id, _)| id).collect(),
+            dormant_tips: vec![],
+            invalid_tips: invalid_tips.into_iter().map(|(id, _)| id).collect(),
+        }))
+    }
+
+    fn get_block_info(
+        &self,
+        db_id: BlockHash,
+    ) -> impl Future<Output = Result<Option<BlockInfo<BlockHash>>>> + Send {
+        ready(Ok(self.inner.lock().unwrap().infos.get(&db_id).cloned()))
+    }
+
+    fn get_block_info_by_hash(
+        &self,
+        hash: BlockHash,
+    ) -> impl Future<Output = Result<Option<BlockInfo<BlockHash>>>> + Send {
+        self.get_block_info(hash)
+    }
+
+    fn get_block_brief(
+        &self,
+        hash: BlockHash,
+    ) -> impl Future<Output = Result<Option<BlockBrief<BlockHash>>>> + Send {
+        ready(Ok(self.inner.lock().unwrap().brief(&hash)))
+    }
+
+    fn get_block_briefs(
+        &self,
+        hashes: Vec<BlockHash>,
+    ) -> impl Future<Output = Result<Vec<Option<BlockBrief<BlockHash>>>>> + Send {
+        let inner = self.inner.lock().unwrap();
+        ready(Ok(hashes.iter().map(|h| inner.brief(h)).collect()))
+    }
+
+    async fn get_block_infos(
+        &self,
+        db_id: BlockHash,
+        max_blocks: Option<u64>,
+    ) -> Result<BlockInfoStreamFromChannel<BlockHash>> {
+        let infos = self.inner.lock().unwrap().walk(db_id, max_blocks);
+        let (tx, rx) = channel(infos.len().max(1));
+        for i in infos {
+            tx.try_send(i).expect("channel has room for every block");
+        }
+        Ok(BlockInfoStreamFromChannel::new(rx))
+    }
+
+    fn store_block_info(
+        &self,
+        block_info: BlockInfo<BlockHash>,
+    ) -> impl Future<Output = Result<BlockInfo<BlockHash>>> + Send {
+        let mut inner = self.inner.lock().unwrap();
+        let r = inner.store(block_info);
+        if let Ok(b) = &r {
+            let tip = &inner.infos[&inner.tip];
+            if !is_invalid(&b.validity) && b.height > tip.height {
+                inner.tip = b.id;
+            }
+        }
+        ready(r)
+    }
+
+    fn store_block_infos(
+        &self,
+        blocks: Vec<BlockInfo<BlockHash>>,
+    ) -> impl Future<Output = Result<Vec<BlockInfo<BlockHash>>>> + Send {
+        let mut inner = self.inner.lock().unwrap();
+        // a copy of the blocks and the tip, so that nothing is changed if a block fails
+        let (infos, tip) = (inner.infos.clone(), inner.tip);
+        let mut stored = Vec::with_capacity(blocks.len());
+        for b in blocks {
+            match inner.store(b) {
+                Ok(b) => {
+                    let tip = &inner.infos[&inner.tip];
+                    if !is_invalid(&b.validity) && b.height > tip.height {
+                        inner.tip = b.id;
+                    }
+                    stored.push(b);
+                }
+                Err(e) => {
+                    inner.infos = infos;
+                    inner.tip = tip;
+                    return ready(Err(e));
+                }
+            }
+        }
+        ready(Ok(stored))
+    }
+
+    fn check_would_link(
+        &self,
+        info: &BlockInfo<BlockHash>,
+    ) -> impl Future<Output = Result<LinkCheck<BlockHash>>> + Send {
+        ready(Ok(self.inner.lock().unwrap().link(info)))
+    }
+
+    fn summary(&self) -> impl Future<Output = Result<StoreSummary>> + Send {
+        let inner = self.inner.lock().unwrap();
+        let count = |v: &BlockValidity| inner.infos.values().filter(|b| b.validity == *v).count();
+        ready(Ok(StoreSummary {
+            num_blocks: inner.infos.len() as u64,
+            validity_counts: BlockValidity::ALL
+                .iter()
+                .map(|v| (v.clone(), count(v) as u64))
+                .collect(),
+            num_tips: inner
+                .infos
+                .values()
+                .filter(|b| b.next_ids.is_empty())
+                .count() as u64,
+            tip_height: inner.infos[&inner.tip].height,
+            genesis_hash: inner.genesis,
+        }))
+    }
+
+    fn apply_branch(
+        &self,
+        blocks: Vec<BlockInfo<BlockHash>>,
+        expect_current_tip: BlockHash,
+    ) -> impl Future<Output = Result<BranchOutcome<BlockHash>>> + Send {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.tip != expect_current_tip {
+            return ready(Ok(BranchOutcome::Outdated {
+                current_tip: inner.tip,
+            }));
+        }
+        // a copy of the blocks, so that nothing is changed if the branch is not valid
+        let infos = inner.infos.clone();
+        let r: Result<Vec<_>> = blocks.into_iter().map(|b| inner.store(b)).collect();
+        let stored = match r {
+            Ok(s) if s.last().is_some_and(|b| !is_invalid(&b.validity)) => s,
+            Ok(_) => {
+                inner.infos = infos;
+                return ready(Err(Error::InvalidBranch("invalid branch".into())));
+            }
+            Err(e) => {
+                inner.infos = infos;
+                return ready(Err(e));
+            }
+        };
+        let last = stored.last().expect("branch is not empty");
+        let new_chain = inner.walk(last.id, None);
+        let old_chain = inner.walk(inner.tip, None);
+        let on_new: Vec<BlockHash> = new_chain.iter().map(|b| b.as_ref().unwrap().id).collect();
+        let mut displaced: Vec<BlockHash> = old_chain
+            .iter()
+            .map(|b| b.as_ref().unwrap().id)
+            .take_while(|id| !on_new.contains(id))
+            .collect();
+        let fork_point = old_chain[displaced.len()].as_ref().unwrap().id;
+        displaced.reverse();
+        inner.tip = last.id;
+        ready(Ok(BranchOutcome::Applied {
+            fork_point,
+            displaced,
+            blocks: stored,
+        }))
+    }
+
+    fn list_blocks_by_validity(
+        &self,
+        validity: BlockValidity,
+    ) -> impl Stream<Item = Result<BlockInfo<BlockHash>>> + Send {
+        let inner = self.inner.lock().unwrap();
+        let infos: Vec<_> = inner
+            .infos
+            .values()
+            .filter(|b| b.validity == validity)
+            .map(|b| Ok(b.clone()))
+            .collect();
+        stream::iter(infos)
+    }
+
+    fn stream_main_chain(&self) -> impl Stream<Item = Result<BlockInfo<BlockHash>>> + Send {
+        let inner = self.inner.lock().unwrap();
+        let mut infos = inner.walk(inner.tip, None);
+        infos.reverse();
+        stream::iter(infos)
+    }
+}
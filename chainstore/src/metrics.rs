@@ -0,0 +1,318 @@
+use crate::{ClassMetrics, GuardrailStats};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of the operation latency histograms, in seconds.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// A snapshot of the metrics of a ChainStore, see
+/// [FDBChainStore::metrics_snapshot()](crate::FDBChainStore::metrics_snapshot).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    /// The metrics for each type of operation that has completed, sorted by name.
+    pub operations: Vec<OperationMetrics>,
+    /// The number of operations that are being processed.
+    pub in_flight: u64,
+    /// The height of the most work tip.
+    pub tip_height: u64,
+    /// The number of tips in the current split, including the most work tip, 0 if there is no
+    /// split.
+    pub competing_tips: u64,
+    /// The number of messages waiting in the queues of the actor.
+    pub queue_depth: u64,
+    /// The admission metrics for each class of operation.
+    pub classes: Vec<ClassMetrics>,
+    /// The state of the guardrail against runaway imports.
+    pub guardrail: GuardrailStats,
+}
+
+/// The metrics for one type of operation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationMetrics {
+    /// The name of the operation, e.g. "StoreBlockInfo".
+    pub name: String,
+    /// The number of operations that have completed.
+    pub count: u64,
+    /// The total latency of the operations, in seconds.
+    pub latency_sum: f64,
+    /// The number of operations with a latency less than or equal to each of the
+    /// [LATENCY_BUCKETS].
+    pub latency_buckets: Vec<u64>,
+}
+
+impl Metrics {
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut s = String::new();
+        s.push_str("# HELP bsvdb_chainstore_operations_total Chain store operations completed.\n");
+        s.push_str("# TYPE bsvdb_chainstore_operations_total counter\n");
+        for op in self.operations.iter() {
+            writeln!(
+                s,
+                "bsvdb_chainstore_operations_total{{op=\"{}\"}} {}",
+                op.name, op.count
+            )
+            .unwrap();
+        }
+        s.push_str(
+            "# HELP bsvdb_chainstore_operation_seconds Latency of chain store operations.\n",
+        );
+        s.push_str("# TYPE bsvdb_chainstore_operation_seconds histogram\n");
+        for op in self.operations.iter() {
+            for (le, n) in LATENCY_BUCKETS.iter().zip(op.latency_buckets.iter()) {
+                writeln!(
+                    s,
+                    "bsvdb_chainstore_operation_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op.name, le, n
+                )
+                .unwrap();
+            }
+            writeln!(
+                s,
+                "bsvdb_chainstore_operation_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}",
+                op.name, op.count
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "bsvdb_chainstore_operation_seconds_sum{{op=\"{}\"}} {}",
+                op.name, op.latency_sum
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "bsvdb_chainstore_operation_seconds_count{{op=\"{}\"}} {}",
+                op.name, op.count
+            )
+            .unwrap();
+        }
+        s.push_str("# HELP bsvdb_chainstore_in_flight Chain store operations being processed.\n");
+        s.push_str("# TYPE bsvdb_chainstore_in_flight gauge\n");
+        writeln!(s, "bsvdb_chainstore_in_flight {}", self.in_flight).unwrap();
+        s.push_str("# HELP bsvdb_chainstore_tip_height The height of the most work tip.\n");
+        s.push_str("# TYPE bsvdb_chainstore_tip_height gauge\n");
+        writeln!(s, "bsvdb_chainstore_tip_height {}", self.tip_height).unwrap();
+        s.push_str("# HELP bsvdb_chainstore_competing_tips The number of tips in a split.\n");
+        s.push_str("# TYPE bsvdb_chainstore_competing_tips gauge\n");
+        writeln!(s, "bsvdb_chainstore_competing_tips {}", self.competing_tips).unwrap();
+        s.push_str(
+            "# HELP bsvdb_chainstore_queue_depth Messages waiting for the chain store actor.\n",
+        );
+        s.push_str("# TYPE bsvdb_chainstore_queue_depth gauge\n");
+        writeln!(s, "bsvdb_chainstore_queue_depth {}", self.queue_depth).unwrap();
+        s.push_str("# HELP bsvdb_chainstore_class_in_flight Chain store operations in flight.\n");
+        s.push_str("# TYPE bsvdb_chainstore_class_in_flight gauge\n");
+        for c in self.classes.iter() {
+            writeln!(
+                s,
+                "bsvdb_chainstore_class_in_flight{{class=\"{}\"}} {}",
+                c.class, c.in_flight
+            )
+            .unwrap();
+        }
+        s.push_str(
+            "# HELP bsvdb_chainstore_class_limit The limit on chain store operations in flight.\n",
+        );
+        s.push_str("# TYPE bsvdb_chainstore_class_limit gauge\n");
+        for c in self.classes.iter() {
+            writeln!(
+                s,
+                "bsvdb_chainstore_class_limit{{class=\"{}\"}} {}",
+                c.class, c.limit
+            )
+            .unwrap();
+        }
+        s.push_str("# HELP bsvdb_chainstore_rejected_total Chain store operations rejected.\n");
+        s.push_str("# TYPE bsvdb_chainstore_rejected_total counter\n");
+        for c in self.classes.iter() {
+            writeln!(
+                s,
+                "bsvdb_chainstore_rejected_total{{class=\"{}\"}} {}",
+                c.class, c.rejected
+            )
+            .unwrap();
+        }
+        s.push_str(
+            "# HELP bsvdb_chainstore_guardrail_recent_inserts Blocks stored in the last minute.\n",
+        );
+        s.push_str("# TYPE bsvdb_chainstore_guardrail_recent_inserts gauge\n");
+        writeln!(
+            s,
+            "bsvdb_chainstore_guardrail_recent_inserts {}",
+            self.guardrail.recent_inserts
+        )
+        .unwrap();
+        s.push_str(
+            "# HELP bsvdb_chainstore_guardrail_trips_total Writes that tripped the guardrail.\n",
+        );
+        s.push_str("# TYPE bsvdb_chainstore_guardrail_trips_total counter\n");
+        for (rule, n) in [
+            ("rate", self.guardrail.rate_trips),
+            ("depth", self.guardrail.depth_trips),
+        ] {
+            writeln!(
+                s,
+                "bsvdb_chainstore_guardrail_trips_total{{rule=\"{}\"}} {}",
+                rule, n
+            )
+            .unwrap();
+        }
+        s
+    }
+}
+
+// the statistics for one type of operation
+#[derive(Default)]
+struct OperationStats {
+    count: u64,
+    latency_sum: Duration,
+    // the number of operations in each bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// Records the metrics, shared between the actor and the handles.
+#[derive(Default)]
+pub(crate) struct MetricsRecorder {
+    operations: Mutex<BTreeMap<&'static str, OperationStats>>,
+    in_flight: AtomicU64,
+    tip_height: AtomicU64,
+    competing_tips: AtomicU64,
+}
+
+impl MetricsRecorder {
+    /// Record the start of an operation.
+    pub(crate) fn start(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the completion of an operation that was started with [MetricsRecorder::start()].
+    pub(crate) fn finish(&self, name: &'static str, latency: Duration) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let mut operations = self.operations.lock().unwrap();
+        let stats = operations.entry(name).or_default();
+        stats.count += 1;
+        stats.latency_sum += latency;
+        let secs = latency.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            stats.buckets[i] += 1;
+        }
+    }
+
+    /// Record the height of the most work tip.
+    pub(crate) fn set_tip_height(&self, height: u64) {
+        self.tip_height.store(height, Ordering::Relaxed);
+    }
+
+    /// The height of the most work tip that was last recorded.
+    pub(crate) fn tip_height(&self) -> u64 {
+        self.tip_height.load(Ordering::Relaxed)
+    }
+
+    /// Record the number of tips in the current split.
+    pub(crate) fn set_competing_tips(&self, tips: u64) {
+        self.competing_tips.store(tips, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of the metrics.
+    pub(crate) fn snapshot(&self) -> Metrics {
+        let operations = self
+            .operations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| OperationMetrics {
+                name: String::from(*name),
+                count: stats.count,
+                latency_sum: stats.latency_sum.as_secs_f64(),
+                latency_buckets: stats
+                    .buckets
+                    .iter()
+                    .scan(0, |total, n| {
+                        *total += n;
+                        Some(*total)
+                    })
+                    .collect(),
+            })
+            .collect();
+        Metrics {
+            operations,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            tip_height: self.tip_height.load(Ordering::Relaxed),
+            competing_tips: self.competing_tips.load(Ordering::Relaxed),
+            queue_depth: 0,
+            classes: vec![],
+            guardrail: GuardrailStats::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OperationClass;
+
+    #[test]
+    fn recording() {
+        let r = MetricsRecorder::default();
+        r.set_tip_height(10);
+        r.start();
+        r.start();
+        r.finish("BlockInfo", Duration::from_micros(50));
+        r.finish("BlockInfo", Duration::from_secs(2));
+        r.start();
+        let m = r.snapshot();
+        assert_eq!(m.in_flight, 1);
+        assert_eq!(m.tip_height, 10);
+        assert_eq!(m.operations.len(), 1);
+        let op = &m.operations[0];
+        assert_eq!(op.name, "BlockInfo");
+        assert_eq!(op.count, 2);
+        // the slow operation is only counted in the +Inf bucket
+        assert_eq!(op.latency_buckets, vec![1; LATENCY_BUCKETS.len()]);
+    }
+
+    #[test]
+    fn prometheus_format() {
+        let r = MetricsRecorder::default();
+        r.set_tip_height(7);
+        r.set_competing_tips(2);
+        r.start();
+        r.finish("StoreBlockInfo", Duration::from_millis(2));
+        let mut m = r.snapshot();
+        m.queue_depth = 3;
+        m.classes = vec![ClassMetrics {
+            class: OperationClass::Write,
+            in_flight: 2,
+            limit: 128,
+            rejected: 5,
+        }];
+        m.guardrail.recent_inserts = 40;
+        m.guardrail.depth_trips = 1;
+        let s = m.to_prometheus();
+        assert!(s.contains("bsvdb_chainstore_operations_total{op=\"StoreBlockInfo\"} 1\n"));
+        assert!(s.contains(
+            "bsvdb_chainstore_operation_seconds_bucket{op=\"StoreBlockInfo\",le=\"0.001\"} 0\n"
+        ));
+        assert!(s.contains(
+            "bsvdb_chainstore_operation_seconds_bucket{op=\"StoreBlockInfo\",le=\"0.0025\"} 1\n"
+        ));
+        assert!(s.contains(
+            "bsvdb_chainstore_operation_seconds_bucket{op=\"StoreBlockInfo\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(s.contains("bsvdb_chainstore_in_flight 0\n"));
+        assert!(s.contains("bsvdb_chainstore_tip_height 7\n"));
+        assert!(s.contains("bsvdb_chainstore_competing_tips 2\n"));
+        assert!(s.contains("bsvdb_chainstore_queue_depth 3\n"));
+        assert!(s.contains("bsvdb_chainstore_class_in_flight{class=\"write\"} 2\n"));
+        assert!(s.contains("bsvdb_chainstore_class_limit{class=\"write\"} 128\n"));
+        assert!(s.contains("bsvdb_chainstore_rejected_total{class=\"write\"} 5\n"));
+        assert!(s.contains("bsvdb_chainstore_guardrail_recent_inserts 40\n"));
+        assert!(s.contains("bsvdb_chainstore_guardrail_trips_total{rule=\"rate\"} 0\n"));
+        assert!(s.contains("bsvdb_chainstore_guardrail_trips_total{rule=\"depth\"} 1\n"));
+    }
+}
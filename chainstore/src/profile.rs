@@ -0,0 +1,140 @@
+use crate::{Error, Result};
+use bsvdb_base::ChainStoreConfig;
+use foundationdb::options::TransactionOption;
+use foundationdb::{Database, Transaction};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// How patient the reads of a ChainStore handle are when the cluster is slow, it is chosen when
+/// the handle is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OperationProfile {
+    /// Transactions wait as long as foundationdb allows, for the sync commands and other long
+    /// running processes.
+    #[default]
+    Background,
+    /// Opening the handle and its reads give up after
+    /// [ChainStoreConfig::interactive_timeout_ms], and are retried at most once, failing with
+    /// [Error::BackendSlow]. For commands that a user is waiting on, see
+    /// [FDBChainStore::new_interactive()](crate::FDBChainStore::new_interactive).
+    Interactive,
+}
+
+// the foundationdb error of a transaction that was cancelled by its timeout
+const TRANSACTION_TIMED_OUT: i32 = 1031;
+
+/// The profile of an actor, with the timeout of the interactive profile.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TrxProfile {
+    pub profile: OperationProfile,
+    pub timeout: Duration,
+}
+
+impl TrxProfile {
+    pub fn new(profile: OperationProfile, config: &ChainStoreConfig) -> TrxProfile {
+        TrxProfile {
+            profile,
+            timeout: Duration::from_millis(config.interactive_timeout_ms),
+        }
+    }
+
+    /// Create a transaction for a read, with the timeout and retry limit of the profile.
+    pub fn create_trx(&self, db: &Database) -> Result<Transaction> {
+        let trx = db.create_trx()?;
+        if self.profile == OperationProfile::Interactive {
+            let ms = self.timeout.as_millis().min(i32::MAX as u128) as i32;
+            trx.set_option(TransactionOption::Timeout(ms))?;
+            trx.set_option(TransactionOption::RetryLimit(1))?;
+        }
+        Ok(trx)
+    }
+
+    /// Check the outcome of the read op that started at start, a transaction that timed out is
+    /// reported as Error::BackendSlow.
+    pub fn check<T>(&self, op: &'static str, start: Instant, r: Result<T>) -> Result<T> {
+        match r {
+            Err(Error::FdbError(e))
+                if self.profile == OperationProfile::Interactive
+                    && e.code() == TRANSACTION_TIMED_OUT =>
+            {
+                Err(Error::BackendSlow(op, start.elapsed()))
+            }
+            r => r,
+        }
+    }
+
+    /// Open the actor of a handle, giving up after the timeout for the interactive profile.
+    ///
+    /// The directories are resolved and the chain state is read when the actor is created, which
+    /// waits for the cluster.
+    pub async fn open<T, F: Future<Output = Result<T>>>(&self, f: F) -> Result<T> {
+        match self.profile {
+            OperationProfile::Background => f.await,
+            OperationProfile::Interactive => tokio::time::timeout(self.timeout, f)
+                .await
+                .unwrap_or_else(|_| Err(Error::BackendSlow("open", self.timeout))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundationdb::FdbError;
+
+    fn profile(profile: OperationProfile) -> TrxProfile {
+        TrxProfile::new(
+            profile,
+            &ChainStoreConfig {
+                interactive_timeout_ms: 50,
+                ..ChainStoreConfig::default()
+            },
+        )
+    }
+
+    // Only a timed out transaction of the interactive profile is reported as slow.
+    #[test]
+    fn check() {
+        let start = Instant::now();
+        let p = profile(OperationProfile::Interactive);
+        let r = p.check::<()>(
+            "get_block_info",
+            start,
+            Err(FdbError::from_code(TRANSACTION_TIMED_OUT).into()),
+        );
+        assert!(matches!(r, Err(Error::BackendSlow("get_block_info", _))));
+        // transaction too old
+        let r = p.check::<()>(
+            "get_block_info",
+            start,
+            Err(FdbError::from_code(1007).into()),
+        );
+        assert!(matches!(r, Err(Error::FdbError(e)) if e.code() == 1007));
+        assert!(matches!(p.check("get_block_info", start, Ok(1)), Ok(1)));
+        let p = profile(OperationProfile::Background);
+        let r = p.check::<()>(
+            "get_block_info",
+            start,
+            Err(FdbError::from_code(TRANSACTION_TIMED_OUT).into()),
+        );
+        assert!(matches!(r, Err(Error::FdbError(e)) if e.code() == TRANSACTION_TIMED_OUT));
+    }
+
+    // Opening gives up after the timeout for the interactive profile.
+    #[tokio::test]
+    async fn open() {
+        let p = profile(OperationProfile::Interactive);
+        let start = Instant::now();
+        let r = p.open(std::future::pending::<Result<()>>()).await;
+        assert!(matches!(r, Err(Error::BackendSlow("open", t)) if t == p.timeout));
+        assert!(start.elapsed() >= p.timeout);
+        assert!(matches!(p.open(async { Ok(1) }).await, Ok(1)));
+        let p = profile(OperationProfile::Background);
+        let r = tokio::time::timeout(
+            Duration::from_millis(200),
+            p.open(std::future::pending::<Result<()>>()),
+        )
+        .await;
+        assert!(r.is_err(), "the background profile waits");
+    }
+}
@@ -0,0 +1,505 @@
+use crate::{BlockInfo, ReorgEvent};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+// The units of a time window, with their length in seconds, largest first.
+const UNITS: [(char, u64); 4] = [('d', 86_400), ('h', 3_600), ('m', 60), ('s', 1)];
+
+/// The trailing window over which [ChainQuality] is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// The last n blocks of the main chain, written as the number, e.g. "1000".
+    Blocks(u64),
+    /// The blocks of the last n seconds, written as a number with a unit of s, m, h or d, e.g.
+    /// "1d".
+    Seconds(u64),
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid window '{}', expected a number of blocks or a time such as 1d",
+                s
+            )
+        };
+        let s = s.trim();
+        if let Ok(n) = s.parse() {
+            return Ok(Window::Blocks(n));
+        }
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let (_, seconds) = UNITS.iter().find(|(u, _)| *u == unit).ok_or_else(invalid)?;
+        let n: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        n.checked_mul(*seconds)
+            .map(Window::Seconds)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Window::Blocks(n) => write!(f, "{}", n),
+            Window::Seconds(s) => {
+                // the largest unit that divides the window
+                let (unit, length) = UNITS
+                    .iter()
+                    .find(|(_, l)| *s % l == 0 && *s > 0)
+                    .unwrap_or(&('s', 1));
+                write!(f, "{}{}", s / length, unit)
+            }
+        }
+    }
+}
+
+/// The distribution of the intervals between consecutive blocks of the main chain, in seconds.
+///
+/// The intervals are negative when a block has an earlier time than its parent, which is possible
+/// for header times. The percentiles are nearest-rank, they are always one of the intervals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalStats {
+    /// The number of intervals.
+    pub count: u64,
+    /// The mean interval, 0 if there are none.
+    pub mean: f64,
+    /// The median interval, 0 if there are none.
+    pub median: i64,
+    /// The 95th percentile of the intervals, 0 if there are none.
+    pub p95: i64,
+}
+
+/// A reorg in the window of a [ChainQuality].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgDepth {
+    /// The time of the reorg, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// The number of blocks that were removed from the main chain.
+    pub depth: u64,
+    /// The height of the last block common to the old and new chains.
+    pub fork_height: u64,
+}
+
+/// Chain quality metrics over a trailing window, see
+/// [FDBChainStore::chain_quality()](crate::FDBChainStore::chain_quality).
+///
+/// The time of a block is when it was first seen, its received_time. If a block in the window
+/// does not have a received_time, then the window predates that data and the header timestamps
+/// are used for every block instead, which is flagged by header_times. An empty window has zero
+/// metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainQuality {
+    /// The window of the metrics.
+    pub window: Window,
+    /// The start of the window, in seconds since the UNIX epoch. For a window of blocks, this is
+    /// the time of its earliest block.
+    pub start_time: u64,
+    /// The number of blocks of the main chain in the window.
+    pub main_blocks: u64,
+    /// The number of blocks in the window that are not on the main chain, the blocks that were
+    /// reorged out or never became part of it.
+    pub stale_blocks: u64,
+    /// The fraction of the blocks in the window that are stale, 0 for an empty window.
+    pub stale_rate: f64,
+    /// The intervals between the main chain blocks of the window and their parents.
+    pub intervals: IntervalStats,
+    /// The reorgs in the window, oldest first.
+    pub reorgs: Vec<ReorgDepth>,
+    /// Whether the header timestamps were used because a block of the window has no
+    /// received_time.
+    pub header_times: bool,
+}
+
+impl ChainQuality {
+    /// Render the metrics as gauges in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let max_depth = self.reorgs.iter().map(|r| r.depth).max().unwrap_or(0);
+        let gauges = [
+            (
+                "main_blocks",
+                "Main chain blocks in the window.",
+                self.main_blocks as f64,
+            ),
+            (
+                "stale_blocks",
+                "Blocks in the window that are not on the main chain.",
+                self.stale_blocks as f64,
+            ),
+            (
+                "stale_rate",
+                "Fraction of the blocks in the window that are stale.",
+                self.stale_rate,
+            ),
+            (
+                "interval_mean_seconds",
+                "Mean interval between main chain blocks.",
+                self.intervals.mean,
+            ),
+            (
+                "interval_median_seconds",
+                "Median interval between main chain blocks.",
+                self.intervals.median as f64,
+            ),
+            (
+                "interval_p95_seconds",
+                "95th percentile of the intervals between main chain blocks.",
+                self.intervals.p95 as f64,
+            ),
+            ("reorgs", "Reorgs in the window.", self.reorgs.len() as f64),
+            (
+                "max_reorg_depth",
+                "Deepest reorg in the window.",
+                max_depth as f64,
+            ),
+            (
+                "header_times",
+                "1 if the header timestamps were used instead of the received times.",
+                if self.header_times { 1.0 } else { 0.0 },
+            ),
+        ];
+        let mut s = String::new();
+        for (name, help, value) in gauges {
+            writeln!(s, "# HELP bsvdb_chainstore_quality_{} {}", name, help).unwrap();
+            writeln!(s, "# TYPE bsvdb_chainstore_quality_{} gauge", name).unwrap();
+            writeln!(
+                s,
+                "bsvdb_chainstore_quality_{}{{window=\"{}\"}} {}",
+                name, self.window, value
+            )
+            .unwrap();
+        }
+        s
+    }
+}
+
+// The information about a block that is needed for the metrics.
+struct QualityNode {
+    prev_id: u64,
+    height: u64,
+    header_time: u64,
+    received_time: Option<u64>,
+}
+
+impl QualityNode {
+    // the time of the block, the header timestamp if header_times is set or it was not seen
+    fn time(&self, header_times: bool) -> u64 {
+        match self.received_time {
+            Some(t) if !header_times => t,
+            _ => self.header_time,
+        }
+    }
+}
+
+/// Computes the [ChainQuality] of a chain.
+///
+/// Like [ForkScanner](crate::ForkScanner), BlockInfos are added one at a time, in any order, and
+/// only a compact summary of each block is kept.
+#[derive(Default)]
+pub struct QualityScanner {
+    nodes: HashMap<u64, QualityNode>,
+}
+
+impl QualityScanner {
+    /// Create a new, empty, QualityScanner.
+    pub fn new() -> QualityScanner {
+        QualityScanner::default()
+    }
+
+    /// Add a block.
+    pub fn add(&mut self, block_info: &BlockInfo<u64>) {
+        self.nodes.insert(
+            block_info.id,
+            QualityNode {
+                prev_id: block_info.prev_id,
+                height: block_info.height,
+                header_time: block_info.header.timestamp as u64,
+                received_time: block_info.received_time,
+            },
+        );
+    }
+
+    /// Compute the metrics over the window ending at now, in seconds since the UNIX epoch, given
+    /// the id of the most work tip and the reorgs.
+    pub fn quality(
+        &self,
+        tip: u64,
+        reorgs: &[ReorgEvent],
+        window: Window,
+        now: u64,
+    ) -> ChainQuality {
+        // the main chain, from the tip down
+        let mut main_chain = Vec::new();
+        let mut id = tip;
+        while let Some(n) = self.nodes.get(&id) {
+            main_chain.push(id);
+            if n.height == 0 {
+                break;
+            }
+            id = n.prev_id;
+        }
+        let mut header_times = false;
+        let mut in_window = self.main_window(&main_chain, window, now, false);
+        // the parents are needed for the intervals
+        let unseen = |id: &u64| {
+            let n = &self.nodes[id];
+            let parent = (n.height > 0).then(|| self.nodes.get(&n.prev_id)).flatten();
+            n.received_time.is_none() || parent.is_some_and(|p| p.received_time.is_none())
+        };
+        if in_window.iter().any(unseen) {
+            header_times = true;
+            in_window = self.main_window(&main_chain, window, now, true);
+        }
+        let start_time = match window {
+            Window::Seconds(s) => now.saturating_sub(s),
+            Window::Blocks(_) => in_window
+                .iter()
+                .map(|id| self.nodes[id].time(header_times))
+                .min()
+                .unwrap_or(now),
+        };
+        let on_main: HashSet<u64> = main_chain.iter().cloned().collect();
+        // a window of no blocks has no start, nothing is in it
+        let empty = in_window.is_empty() && matches!(window, Window::Blocks(_));
+        let stale_blocks = if empty {
+            0
+        } else {
+            self.nodes
+                .iter()
+                .filter(|(id, n)| !on_main.contains(id) && n.time(header_times) >= start_time)
+                .count() as u64
+        };
+        let main_blocks = in_window.len() as u64;
+        let stale_rate = if main_blocks + stale_blocks == 0 {
+            0.0
+        } else {
+            stale_blocks as f64 / (main_blocks + stale_blocks) as f64
+        };
+        let intervals: Vec<i64> = in_window
+            .iter()
+            .map(|id| &self.nodes[id])
+            .filter(|n| n.height > 0)
+            .filter_map(|n| {
+                let parent = self.nodes.get(&n.prev_id)?;
+                Some(n.time(header_times) as i64 - parent.time(header_times) as i64)
+            })
+            .collect();
+        let mut reorgs: Vec<ReorgDepth> = reorgs
+            .iter()
+            .filter(|r| !empty && r.timestamp >= start_time)
+            .map(|r| ReorgDepth {
+                timestamp: r.timestamp,
+                depth: r.orphaned_count,
+                fork_height: r.fork_height,
+            })
+            .collect();
+        reorgs.sort_by_key(|r| r.timestamp);
+        ChainQuality {
+            window,
+            start_time,
+            main_blocks,
+            stale_blocks,
+            stale_rate,
+            intervals: interval_stats(intervals),
+            reorgs,
+            header_times,
+        }
+    }
+
+    // the main chain blocks in the window, from the tip down
+    fn main_window(
+        &self,
+        main_chain: &[u64],
+        window: Window,
+        now: u64,
+        header_times: bool,
+    ) -> Vec<u64> {
+        match window {
+            Window::Blocks(n) => main_chain.iter().take(n as usize).cloned().collect(),
+            Window::Seconds(s) => {
+                let start = now.saturating_sub(s);
+                main_chain
+                    .iter()
+                    .take_while(|id| self.nodes[*id].time(header_times) >= start)
+                    .cloned()
+                    .collect()
+            }
+        }
+    }
+}
+
+// the statistics of the intervals
+fn interval_stats(mut intervals: Vec<i64>) -> IntervalStats {
+    if intervals.is_empty() {
+        return IntervalStats {
+            count: 0,
+            mean: 0.0,
+            median: 0,
+            p95: 0,
+        };
+    }
+    intervals.sort();
+    let n = intervals.len();
+    // the nearest-rank percentile, the smallest interval with at least p percent at or below it
+    let percentile = |p: usize| intervals[(p * n).div_ceil(100).max(1) - 1];
+    IntervalStats {
+        count: n as u64,
+        mean: intervals.iter().sum::<i64>() as f64 / n as f64,
+        median: percentile(50),
+        p95: percentile(95),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockValidity;
+    use bitcoinsv::bitcoin::{BlockHeader, BlockchainId};
+
+    // the intervals between the received times of the main chain blocks 1..=10
+    const INTERVALS: [u64; 10] = [600, 300, 900, 600, 1200, 60, 600, 600, 300, 600];
+
+    // make a block info with the header timestamp 1_000 + 600 * height + offset
+    fn info(
+        id: u64,
+        prev_id: u64,
+        height: u64,
+        offset: u32,
+        received: Option<u64>,
+    ) -> BlockInfo<u64> {
+        let mut header = BlockHeader::get_genesis(BlockchainId::Main);
+        header.nonce = id as u32;
+        header.timestamp = 1_000 + 600 * height as u32 + offset;
+        let mut b = crate::test_util::header_info(&header, BlockValidity::Unknown);
+        b.id = id;
+        b.prev_id = prev_id;
+        b.height = height;
+        b.received_time = received;
+        b
+    }
+
+    // A main chain of 0..=10, received from 10_000 with INTERVALS, and two stale blocks.
+    //
+    //  0 - 1 - 2 - 3 - 4 - 5 - 6 - 7 - 8 - 9 - 10
+    //              \               \
+    //                11 (12_500)     12 (14_900)
+    fn fixture(unseen: Option<u64>) -> (QualityScanner, Vec<ReorgEvent>) {
+        let mut s = QualityScanner::new();
+        let mut received = 10_000;
+        for i in 0..=10u64 {
+            if i > 0 {
+                received += INTERVALS[i as usize - 1];
+            }
+            let r = (unseen != Some(i)).then_some(received);
+            s.add(&info(i, i.saturating_sub(1), i, 0, r));
+        }
+        s.add(&info(11, 3, 4, 30, Some(12_500)));
+        s.add(&info(12, 7, 8, 30, Some(14_900)));
+        let reorg = |timestamp, fork_height| ReorgEvent {
+            timestamp,
+            old_tip: 0,
+            new_tip: 0,
+            fork_height,
+            orphaned_count: 1,
+        };
+        (s, vec![reorg(15_000, 7), reorg(12_450, 3)])
+    }
+
+    #[test]
+    fn window() {
+        assert_eq!("1d".parse(), Ok(Window::Seconds(86_400)));
+        assert_eq!("1000".parse(), Ok(Window::Blocks(1000)));
+        assert_eq!("90m".parse::<Window>().unwrap().to_string(), "90m");
+        assert_eq!(Window::Seconds(7_200).to_string(), "2h");
+        assert_eq!(Window::Seconds(0).to_string(), "0s");
+        for s in ["", "d", "1w", "-1d", "x"] {
+            assert!(s.parse::<Window>().is_err());
+        }
+    }
+
+    // Each metric of the fixture, over windows of blocks and of time.
+    #[test]
+    fn metrics() {
+        let (s, reorgs) = fixture(None);
+        let q = s.quality(10, &reorgs, Window::Blocks(5), 16_000);
+        assert_eq!(q.start_time, 13_660);
+        assert_eq!((q.main_blocks, q.stale_blocks), (5, 1));
+        assert_eq!(q.stale_rate, 1.0 / 6.0);
+        let intervals = IntervalStats {
+            count: 5,
+            mean: 432.0,
+            median: 600,
+            p95: 600,
+        };
+        assert_eq!(q.intervals, intervals);
+        let reorg = ReorgDepth {
+            timestamp: 15_000,
+            depth: 1,
+            fork_height: 7,
+        };
+        assert_eq!(q.reorgs, vec![reorg.clone()]);
+        assert!(!q.header_times);
+        assert!(q
+            .to_prometheus()
+            .contains("bsvdb_chainstore_quality_stale_blocks{window=\"5\"} 1\n"));
+
+        let q = s.quality(10, &reorgs, Window::Seconds(3_000), 16_000);
+        assert_eq!(q.start_time, 13_000);
+        assert_eq!((q.main_blocks, q.stale_blocks), (6, 1));
+        assert_eq!(
+            (q.intervals.mean, q.intervals.median, q.intervals.p95),
+            (560.0, 600, 1200)
+        );
+        assert_eq!(q.reorgs.len(), 1);
+
+        let q = s.quality(10, &reorgs, Window::Blocks(100), 16_000);
+        assert_eq!((q.main_blocks, q.stale_blocks), (11, 2));
+        assert_eq!(q.stale_rate, 2.0 / 13.0);
+        assert_eq!(q.intervals.count, 10);
+        assert_eq!(
+            (q.intervals.mean, q.intervals.median, q.intervals.p95),
+            (576.0, 600, 1200)
+        );
+        assert_eq!(q.reorgs[0].timestamp, 12_450);
+        assert_eq!(q.reorgs[1], reorg);
+    }
+
+    // A window with a block that was not seen uses the header times, and is flagged.
+    #[test]
+    fn header_times() {
+        let (s, reorgs) = fixture(Some(2));
+        let q = s.quality(10, &reorgs, Window::Blocks(5), 16_000);
+        assert!(!q.header_times);
+        let q = s.quality(10, &reorgs, Window::Blocks(100), 16_000);
+        assert!(q.header_times);
+        assert_eq!(q.start_time, 1_000);
+        assert_eq!((q.intervals.mean, q.intervals.p95), (600.0, 600));
+        assert_eq!(q.stale_blocks, 2);
+        // the parent of the earliest block is needed for its interval
+        let q = s.quality(10, &reorgs, Window::Blocks(8), 16_000);
+        assert!(q.header_times);
+    }
+
+    // Empty windows have zero metrics.
+    #[test]
+    fn empty() {
+        let (s, reorgs) = fixture(None);
+        let zero = IntervalStats {
+            count: 0,
+            mean: 0.0,
+            median: 0,
+            p95: 0,
+        };
+        for q in [
+            s.quality(10, &reorgs, Window::Blocks(0), 16_000),
+            s.quality(10, &reorgs, Window::Seconds(10), 100_000),
+            QualityScanner::new().quality(0, &[], Window::Blocks(10), 16_000),
+        ] {
+            assert_eq!((q.main_blocks, q.stale_blocks, q.stale_rate), (0, 0, 0.0));
+            assert_eq!(q.intervals, zero);
+            assert!(q.reorgs.is_empty());
+        }
+    }
+}
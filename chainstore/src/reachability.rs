@@ -0,0 +1,115 @@
+use bitcoinsv::bitcoin::BlockHash;
+
+/// The result of a reachability check, see
+/// [FDBChainStore::check_reachability()](crate::FDBChainStore::check_reachability).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReachabilityReport {
+    /// The number of BlockInfos that are reachable from genesis.
+    pub reachable: u64,
+    /// The BlockInfos that are not reachable from genesis, in order of id. This is the state
+    /// before any fix was applied.
+    pub unreachable: Vec<UnreachableBlock>,
+    /// The number of unreachable BlockInfos that were deleted.
+    pub deleted: u64,
+    /// The number of unreachable BlockInfos that were linked to their parent.
+    pub relinked: u64,
+}
+
+/// A BlockInfo that can not be reached from genesis by following the next_ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnreachableBlock {
+    /// The id of the block.
+    pub id: u64,
+    /// The hash of the block.
+    pub hash: BlockHash,
+    /// The height of the block.
+    pub height: u64,
+    /// The id of the parent of the block, as recorded in the BlockInfo.
+    pub prev_id: u64,
+    /// Whether prev_id refers to a BlockInfo that does not exist.
+    pub prev_missing: bool,
+    /// The id of the reachable block with the hash of the parent in the header, if there is one.
+    /// The block can be linked to this parent with [ReachabilityFix::Relink].
+    pub parent: Option<u64>,
+}
+
+/// How the unreachable BlockInfos are fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReachabilityFix {
+    /// Delete the unreachable BlockInfos, and their hash index entries.
+    Delete,
+    /// Link the unreachable BlockInfos whose parent is found by hash to that parent. Other
+    /// unreachable BlockInfos are not changed.
+    Relink,
+}
+
+/// A repair of the links between a block and its children, see
+/// [FDBChainStore::repair_links()](crate::FDBChainStore::repair_links).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkRepair {
+    /// The child was not listed in the next_ids of its parent, and was added.
+    AddedChild { parent: u64, child: u64 },
+    /// The parent listed a child that does not exist, which was removed from the next_ids.
+    RemovedChild { parent: u64, child: u64 },
+}
+
+/// A set of BlockIds, stored as a bitset because BlockIds are allocated sequentially.
+///
+/// It uses one bit for every id up to the largest id in the set, 125KB for a million blocks.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IdSet {
+    words: Vec<u64>,
+    len: u64,
+}
+
+impl IdSet {
+    /// Add an id to the set, returning true if it was not already in the set.
+    pub(crate) fn insert(&mut self, id: u64) -> bool {
+        let (w, b) = ((id / 64) as usize, id % 64);
+        if w >= self.words.len() {
+            self.words.resize(w + 1, 0);
+        }
+        let inserted = self.words[w] & (1 << b) == 0;
+        if inserted {
+            self.words[w] |= 1 << b;
+            self.len += 1;
+        }
+        inserted
+    }
+
+    /// Check whether the id is in the set.
+    pub(crate) fn contains(&self, id: u64) -> bool {
+        match self.words.get((id / 64) as usize) {
+            Some(w) => w & (1 << (id % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// The number of ids in the set.
+    pub(crate) fn count(&self) -> u64 {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_set() {
+        let mut s = IdSet::default();
+        assert!(!s.contains(0));
+        assert!(s.insert(0));
+        assert!(s.insert(63));
+        assert!(s.insert(64));
+        assert!(s.insert(1_000_000));
+        assert!(!s.insert(64));
+        assert_eq!(s.count(), 4);
+        for i in [0, 63, 64, 1_000_000] {
+            assert!(s.contains(i));
+        }
+        for i in [1, 65, 999_999, 1_000_001, u64::MAX] {
+            assert!(!s.contains(i));
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use crate::OperationClass;
+use bitcoinsv::bitcoin::BlockHash;
 use foundationdb::directory::DirectoryError;
 use foundationdb::{FdbError, TransactionCommitError};
+use std::time::Duration;
 use tokio::sync::oneshot::error::RecvError;
 
 /// Standard Result used in the library
@@ -8,14 +11,56 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// Standard error type used in the library
 #[derive(Debug)]
 pub enum Error {
-    /// The block was not found.
-    BlockNotFound,
+    /// The block with the hash was not found.
+    BlockNotFound(BlockHash),
+    /// The BlockInfo with the id was not found, although it is referred to by the chain state or
+    /// another BlockInfo.
+    BlockInfoNotFound(u64),
     /// The block already exists.
     BlockExists,
     /// The parent of the block was not found
     ParentNotFound,
     /// The method can not be implemented.
     CantImplement,
+    /// The ChainStore has not been initialized.
+    NotInitialized,
+    /// The genesis block supplied is not valid.
+    InvalidGenesis(String),
+    /// The ChainStore was initialized with a different genesis block, it is probably for another
+    /// network. Includes the hash of the genesis block that was expected and the one found.
+    NetworkMismatch {
+        expected: BlockHash,
+        found: BlockHash,
+    },
+    /// The ChainStore was opened in read-only mode and the operation would write to it.
+    ReadOnly,
+    /// The ChainStore was created with a layout version that is not supported by this library.
+    UnsupportedLayout(u64),
+    /// The foundationdb API version in the config is not supported, either by this library or by
+    /// the installed client library. Includes the version and the newest version supported.
+    UnsupportedApiVersion(i32, i32),
+    /// A record read from foundationdb could not be decoded. Includes the key of the record and
+    /// the reason.
+    CorruptRecord(Vec<u8>, String),
+    /// A persisted enum has a discriminant that is not known to this library, it was probably
+    /// written by a newer version. Includes the field, the discriminant and the newest version of
+    /// the layout or of the snapshot format that this library knows.
+    UnknownEnumVariant(&'static str, i64, u64),
+    /// The snapshot could not be decoded.
+    InvalidSnapshot(String),
+    /// The blocks do not form a branch that can become the main chain.
+    InvalidBranch(String),
+    /// Too many operations of the class are queued or being processed, the operation was not
+    /// started and can be retried later. Includes the number of messages queued for the actor.
+    Overloaded(OperationClass, u64),
+    /// The write tripped the guardrail against runaway imports and was not made, see
+    /// [ChainStoreConfig::guardrail](bsvdb_base::ChainStoreConfig::guardrail). Includes the rule
+    /// that tripped.
+    GuardrailTripped(String),
+    /// The cluster did not answer an operation of an interactive handle in time, see
+    /// [OperationProfile::Interactive](crate::OperationProfile::Interactive). Includes the
+    /// operation and how long was waited. The cluster may be degraded or unreachable.
+    BackendSlow(&'static str, Duration),
     /// error sending data through a channel
     SendError(String),
     /// miscellaneous error
@@ -31,10 +76,50 @@ pub enum Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Error::BlockNotFound => write!(f, "Block not found"),
+            Error::BlockNotFound(h) => write!(f, "Block not found: {}", h),
+            Error::BlockInfoNotFound(id) => write!(f, "BlockInfo not found: {}", id),
             Error::BlockExists => write!(f, "Block exists"),
             Error::ParentNotFound => write!(f, "Parent not found"),
             Error::CantImplement => write!(f, "Can't implement"),
+            Error::NotInitialized => write!(f, "ChainStore not initialized"),
+            Error::InvalidGenesis(s) => write!(f, "Invalid genesis: {}", s),
+            Error::NetworkMismatch { expected, found } => write!(
+                f,
+                "ChainStore is for another network: expected genesis {}, found {}",
+                expected, found
+            ),
+            Error::ReadOnly => write!(f, "ChainStore is read-only"),
+            Error::UnsupportedLayout(v) => write!(f, "Unsupported ChainStore layout: {}", v),
+            Error::UnsupportedApiVersion(v, max) => write!(
+                f,
+                "Unsupported foundationdb API version: {}, expected {} to {}",
+                v,
+                bsvdb_base::FDB_API_VERSIONS.start(),
+                max
+            ),
+            Error::CorruptRecord(k, s) => {
+                write!(f, "Corrupt record at key {}: {}", hex::encode(k), s)
+            }
+            Error::UnknownEnumVariant(field, v, layout) => write!(
+                f,
+                "Unknown {} {}, this library knows version {} of the format, the record may \
+                 have been written by a newer version",
+                field, v, layout
+            ),
+            Error::InvalidSnapshot(s) => write!(f, "Invalid snapshot: {}", s),
+            Error::InvalidBranch(s) => write!(f, "Invalid branch: {}", s),
+            Error::Overloaded(class, depth) => write!(
+                f,
+                "ChainStore overloaded: too many {} operations, {} queued",
+                class, depth
+            ),
+            Error::GuardrailTripped(s) => write!(f, "ChainStore guardrail tripped: {}", s),
+            Error::BackendSlow(op, waited) => write!(
+                f,
+                "ChainStore backend slow: {} gave up after {} ms",
+                op,
+                waited.as_millis()
+            ),
             Error::SendError(s) => write!(f, "error sending data through channel: {}", s),
             Error::Internal(err) => write!(f, "internal error {}", err),
             Error::IoError(err) => write!(f, "IO error: {}", err),
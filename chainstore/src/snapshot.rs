@@ -0,0 +1,431 @@
+use crate::chain_store::{ChainState, StoreSummary};
+use crate::{BlockInfo, BlockValidity, Error, Result};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
+use foundationdb::tuple::{pack, unpack, Bytes, Element};
+use std::borrow::Cow;
+use std::fmt;
+
+/// The version of the snapshot format written by this library.
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+// identifies a snapshot file
+const SNAPSHOT_MAGIC: &str = "bsvdb-chainstore-snapshot";
+
+/// A self-contained snapshot of the chain metadata around a block, for offline analysis, see
+/// [FDBChainStore::snapshot()](crate::FDBChainStore::snapshot).
+///
+/// The snapshot is encoded with [Snapshot::encode()] into a small versioned bundle which can be
+/// decoded with [Snapshot::decode()] and displayed without access to the ChainStore. The
+/// BlockInfos contain the raw block headers. The raw blocks can optionally be added with
+/// [Snapshot::add_block()].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// The version of the snapshot format.
+    pub version: u64,
+    /// The time that the snapshot was taken, in seconds since the UNIX epoch.
+    pub created: u64,
+    /// The hash of the block that the snapshot was taken around.
+    pub center: BlockHash,
+    /// The maximum distance of the BlockInfos from the center block.
+    pub radius: u64,
+    /// The number of shards of the BlockInfo directory, 0 if it is not sharded.
+    pub info_shards: u64,
+    /// The state of the chain.
+    pub chain_state: ChainState<u64>,
+    /// The summary statistics of the ChainStore.
+    pub summary: StoreSummary,
+    /// The BlockInfos within the radius of the center block, in order of id.
+    pub block_infos: Vec<BlockInfo<u64>>,
+    /// The raw bytes of blocks, in the order that they were added.
+    pub blocks: Vec<(BlockHash, Vec<u8>)>,
+    /// Whether blocks were left out because they would exceed the size limit.
+    pub blocks_truncated: bool,
+}
+
+impl Snapshot {
+    /// Add the raw bytes of a block, unless the total size of the blocks would exceed max_bytes.
+    ///
+    /// Returns false, and marks the snapshot as truncated, if the block was not added.
+    pub fn add_block(&mut self, hash: BlockHash, block: Vec<u8>, max_bytes: u64) -> bool {
+        if self.blocks_size() + block.len() as u64 > max_bytes {
+            self.blocks_truncated = true;
+            return false;
+        }
+        self.blocks.push((hash, block));
+        true
+    }
+
+    /// The total size of the raw blocks in the snapshot.
+    pub fn blocks_size(&self) -> u64 {
+        self.blocks.iter().map(|(_, b)| b.len() as u64).sum()
+    }
+
+    /// Encode the snapshot.
+    pub fn encode(&self) -> Vec<u8> {
+        let cs = &self.chain_state;
+        let ids = |v: &[u64]| Element::Tuple(v.iter().map(|i| Element::Int(*i as i64)).collect());
+        let summary = Element::Tuple(vec![
+            Element::Int(self.summary.num_blocks as i64),
+            Element::Tuple(
+                self.summary
+                    .validity_counts
+                    .iter()
+                    .map(|(v, n)| {
+                        Element::Tuple(vec![
+                            Element::Int(u8::from(v.clone()) as i64),
+                            Element::Int(*n as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Element::Int(self.summary.num_tips as i64),
+            Element::Int(self.summary.tip_height as i64),
+            hash_element(&self.summary.genesis_hash),
+        ]);
+        let i = vec![
+            Element::String(Cow::from(SNAPSHOT_MAGIC)),
+            Element::Int(self.version as i64),
+            Element::Int(self.created as i64),
+            hash_element(&self.center),
+            Element::Int(self.radius as i64),
+            Element::Int(self.info_shards as i64),
+            Element::Tuple(vec![
+                Element::Int(cs.most_work_tip as i64),
+                ids(&cs.active_tips),
+                ids(&cs.dormant_tips),
+                ids(&cs.invalid_tips),
+            ]),
+            summary,
+            Element::Tuple(self.block_infos.iter().map(encode_block_info).collect()),
+            Element::Tuple(
+                self.blocks
+                    .iter()
+                    .map(|(h, b)| {
+                        Element::Tuple(vec![
+                            hash_element(h),
+                            Element::Bytes(Bytes::from(b.clone())),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Element::Int(self.blocks_truncated as i64),
+        ];
+        pack(&i)
+    }
+
+    /// Decode a snapshot, returning Error::InvalidSnapshot if it is not a valid snapshot or was
+    /// written with an unsupported version of the format.
+    pub fn decode(v: &[u8]) -> Result<Snapshot> {
+        let i = unpack::<Vec<Element>>(v).map_err(|_| invalid("not a snapshot"))?;
+        if i.first().and_then(|e| e.as_str()) != Some(SNAPSHOT_MAGIC) {
+            return Err(invalid("not a snapshot"));
+        }
+        let version = int(i.get(1))?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        if i.len() != 11 {
+            return Err(invalid("wrong number of fields"));
+        }
+        let cs = tuple(&i[6])?;
+        if cs.len() != 4 {
+            return Err(invalid("wrong number of chain state fields"));
+        }
+        let chain_state = ChainState {
+            most_work_tip: int(cs.first())?,
+            active_tips: ints(&cs[1])?,
+            dormant_tips: ints(&cs[2])?,
+            invalid_tips: ints(&cs[3])?,
+        };
+        let s = tuple(&i[7])?;
+        if s.len() != 5 {
+            return Err(invalid("wrong number of summary fields"));
+        }
+        let validity_counts = tuple(&s[1])?
+            .iter()
+            .map(|e| {
+                let c = tuple(e)?;
+                let validity = BlockValidity::decode(int(c.first())? as i64, SNAPSHOT_VERSION)?;
+                Ok((validity, int(c.get(1))?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let summary = StoreSummary {
+            num_blocks: int(s.first())?,
+            validity_counts,
+            num_tips: int(s.get(2))?,
+            tip_height: int(s.get(3))?,
+            genesis_hash: hash(&s[4])?,
+        };
+        let block_infos = tuple(&i[8])?
+            .iter()
+            .map(decode_block_info)
+            .collect::<Result<Vec<_>>>()?;
+        let blocks = tuple(&i[9])?
+            .iter()
+            .map(|e| {
+                let b = tuple(e)?;
+                match (b.first(), b.get(1).and_then(|j| j.as_bytes())) {
+                    (Some(h), Some(bytes)) => Ok((hash(h)?, bytes.to_vec())),
+                    _ => Err(invalid("invalid block")),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Snapshot {
+            version,
+            created: int(i.get(2))?,
+            center: hash(&i[3])?,
+            radius: int(i.get(4))?,
+            info_shards: int(i.get(5))?,
+            chain_state,
+            summary,
+            block_infos,
+            blocks,
+            blocks_truncated: int(i.get(10))? != 0,
+        })
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "snapshot version {}, created {}",
+            self.version, self.created
+        )?;
+        writeln!(f, "center {}, radius {}", self.center, self.radius)?;
+        writeln!(f, "info shards: {}", self.info_shards)?;
+        writeln!(f, "genesis: {}", self.summary.genesis_hash)?;
+        writeln!(
+            f,
+            "blocks: {}, tips: {}, tip height: {}",
+            self.summary.num_blocks, self.summary.num_tips, self.summary.tip_height
+        )?;
+        for (validity, n) in self.summary.validity_counts.iter() {
+            writeln!(f, "  {:?}: {}", validity, n)?;
+        }
+        let cs = &self.chain_state;
+        writeln!(f, "most work tip: {}", cs.most_work_tip)?;
+        writeln!(f, "active tips: {:?}", cs.active_tips)?;
+        writeln!(f, "dormant tips: {:?}", cs.dormant_tips)?;
+        writeln!(f, "invalid tips: {:?}", cs.invalid_tips)?;
+        writeln!(f, "block infos: {}", self.block_infos.len())?;
+        for b in self.block_infos.iter() {
+            writeln!(
+                f,
+                "  {} {} height {} prev {} next {:?} {:?}",
+                b.id, b.hash, b.height, b.prev_id, b.next_ids, b.validity
+            )?;
+        }
+        write!(
+            f,
+            "raw blocks: {}, {} bytes",
+            self.blocks.len(),
+            self.blocks_size()
+        )?;
+        if self.blocks_truncated {
+            write!(f, " (truncated by the size limit)")?;
+        }
+        writeln!(f)
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSnapshot(String::from(reason))
+}
+
+fn hash_element(h: &BlockHash) -> Element<'static> {
+    Element::Bytes(Bytes::from(Vec::from(h.hash)))
+}
+
+fn int(e: Option<&Element>) -> Result<u64> {
+    e.and_then(|j| j.as_i64())
+        .map(|j| j as u64)
+        .ok_or_else(|| invalid("expected an integer"))
+}
+
+fn ints(e: &Element) -> Result<Vec<u64>> {
+    tuple(e)?.iter().map(|j| int(Some(j))).collect()
+}
+
+fn tuple<'a, 'b>(e: &'a Element<'b>) -> Result<&'a [Element<'b>]> {
+    e.as_tuple()
+        .map(|t| &t[..])
+        .ok_or_else(|| invalid("expected a tuple"))
+}
+
+fn hash(e: &Element) -> Result<BlockHash> {
+    match e.as_bytes() {
+        Some(b) if b.len() == 32 => Ok(BlockHash::from(b.to_vec().as_slice())),
+        _ => Err(invalid("expected a block hash")),
+    }
+}
+
+fn opt_int(e: &Element) -> Option<u64> {
+    e.as_i64().map(|j| j as u64)
+}
+
+// encode a BlockInfo, the FDBChainStore encoding is not used so that the format of the snapshot
+// does not change with the layout of the ChainStore
+fn encode_block_info(v: &BlockInfo<u64>) -> Element<'static> {
+    let opt = |j: Option<u64>| j.map(|j| Element::Int(j as i64)).unwrap_or(Element::Nil);
+    let mut r = vec![
+        Element::Int(v.id as i64),
+        hash_element(&v.hash),
+        Element::Bytes(Bytes::from(v.header.to_binary_buf().unwrap())),
+        Element::Int(v.height as i64),
+        Element::Int(v.prev_id as i64),
+        Element::Tuple(v.next_ids.iter().map(|i| Element::Int(*i as i64)).collect()),
+        opt(v.size),
+        opt(v.num_tx),
+        opt(v.median_time),
+        v.chain_work
+            .clone()
+            .map(|j| Element::Bytes(Bytes::from(j)))
+            .unwrap_or(Element::Nil),
+        opt(v.total_tx),
+        opt(v.total_size),
+        v.miner
+            .clone()
+            .map(|m| Element::String(Cow::from(m)))
+            .unwrap_or(Element::Nil),
+        Element::Int(u8::from(v.validity.clone()) as i64),
+        opt(v.received_time),
+        v.source
+            .clone()
+            .map(|m| Element::String(Cow::from(m)))
+            .unwrap_or(Element::Nil),
+    ];
+    // the fees are left off when they are not known, so that the snapshot can be read by a
+    // version that does not have them
+    if let Some(j) = v.total_fees {
+        r.push(Element::Int(j as i64));
+    }
+    Element::Tuple(r)
+}
+
+// decode a BlockInfo encoded by encode_block_info()
+fn decode_block_info(e: &Element) -> Result<BlockInfo<u64>> {
+    let i = tuple(e)?;
+    // the received time, the source and the fees were added after version 1 snapshots were first
+    // written
+    if !(14..=17).contains(&i.len()) {
+        return Err(invalid("wrong number of block info fields"));
+    }
+    let header = i[2]
+        .as_bytes()
+        .and_then(|b| BlockHeader::from_binary_buf(b).ok())
+        .ok_or_else(|| invalid("invalid block header"))?;
+    Ok(BlockInfo {
+        id: int(i.first())?,
+        hash: hash(&i[1])?,
+        header,
+        height: int(i.get(3))?,
+        prev_id: int(i.get(4))?,
+        next_ids: ints(&i[5])?,
+        size: opt_int(&i[6]),
+        num_tx: opt_int(&i[7]),
+        median_time: opt_int(&i[8]),
+        chain_work: i[9].as_bytes().map(|j| j.to_vec()),
+        total_tx: opt_int(&i[10]),
+        total_size: opt_int(&i[11]),
+        miner: i[12].as_str().map(String::from),
+        validity: BlockValidity::decode(int(i.get(13))? as i64, SNAPSHOT_VERSION)?,
+        received_time: i.get(14).and_then(opt_int),
+        source: i.get(15).and_then(|j| j.as_str()).map(String::from),
+        total_fees: i.get(16).and_then(opt_int),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::BlockchainId;
+
+    fn snapshot() -> Snapshot {
+        let genesis = BlockInfo::genesis_info(BlockchainId::Main);
+        let mut child = genesis.clone();
+        child.id = 1;
+        child.height = 1;
+        child.header.prev_hash = genesis.hash;
+        child.hash = child.header.hash();
+        child.miner = Some(String::from("miner"));
+        child.chain_work = Some(vec![1, 2, 3]);
+        child.total_fees = Some(1_000);
+        child.validity = BlockValidity::ValidHeader;
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            created: 1_700_000_000,
+            center: genesis.hash,
+            radius: 1,
+            info_shards: 16,
+            chain_state: ChainState {
+                most_work_tip: 1,
+                active_tips: vec![1],
+                dormant_tips: vec![],
+                invalid_tips: vec![],
+            },
+            summary: StoreSummary {
+                num_blocks: 2,
+                validity_counts: vec![(BlockValidity::Valid, 1), (BlockValidity::ValidHeader, 1)],
+                num_tips: 1,
+                tip_height: 1,
+                genesis_hash: genesis.hash,
+            },
+            block_infos: vec![genesis, child],
+            blocks: vec![],
+            blocks_truncated: false,
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let mut s = snapshot();
+        assert_eq!(Snapshot::decode(&s.encode()).unwrap(), s);
+        let h = s.center;
+        assert!(s.add_block(h, vec![1u8; 100], 150));
+        assert_eq!(Snapshot::decode(&s.encode()).unwrap(), s);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        assert!(matches!(
+            Snapshot::decode(b"not a snapshot"),
+            Err(Error::InvalidSnapshot(_))
+        ));
+        let mut s = snapshot();
+        s.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            Snapshot::decode(&s.encode()),
+            Err(Error::InvalidSnapshot(_))
+        ));
+        let v = pack(&(SNAPSHOT_MAGIC, SNAPSHOT_VERSION));
+        assert!(matches!(
+            Snapshot::decode(&v),
+            Err(Error::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut s = snapshot();
+        let h = s.center;
+        assert!(s.add_block(h, vec![0u8; 60], 100));
+        assert!(!s.add_block(h, vec![0u8; 60], 100));
+        assert!(s.add_block(h, vec![0u8; 40], 100));
+        assert_eq!(s.blocks.len(), 2);
+        assert_eq!(s.blocks_size(), 100);
+        assert!(s.blocks_truncated);
+        let d = format!("{}", Snapshot::decode(&s.encode()).unwrap());
+        assert!(d.contains("raw blocks: 2, 100 bytes (truncated by the size limit)"));
+    }
+
+    #[test]
+    fn display() {
+        let s = snapshot();
+        let d = format!("{}", s);
+        assert!(d.contains(&format!("center {}, radius 1", s.center)));
+        assert!(d.contains("info shards: 16"));
+        assert!(d.contains("block infos: 2"));
+        assert!(d.contains(&format!("  1 {} height 1 prev 0", s.block_infos[1].hash)));
+    }
+}
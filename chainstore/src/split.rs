@@ -0,0 +1,311 @@
+use crate::{block_work, BlockInfo, BlockValidity};
+use bitcoinsv::bitcoin::BlockHash;
+use std::collections::HashMap;
+
+/// An event in the chain, delivered to the subscribers of
+/// [FDBChainStore::subscribe()](crate::FDBChainStore::subscribe).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    /// Tips are competing with the most work tip, see [find_split()].
+    SplitDetected(Split),
+    /// The tips are no longer competing, with the split as it was last seen.
+    SplitResolved(Split),
+}
+
+/// A live contest between tips that are close to each other in height and work, which can
+/// precede a deep reorg.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    /// The competing tips, the most work tip first, followed by the others in order of height,
+    /// highest first.
+    pub tips: Vec<SplitTip>,
+    /// The id of the last block that all of the competing tips have in common.
+    pub common_ancestor: u64,
+    /// The height of the common ancestor.
+    pub ancestor_height: u64,
+    /// The largest difference between the work of the most work tip and a competing tip,
+    /// approximated from the header targets.
+    pub work_gap: f64,
+}
+
+/// A tip that is part of a [Split].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitTip {
+    pub id: u64,
+    pub hash: BlockHash,
+    pub height: u64,
+    /// The time that the tip was received, see [BlockInfo::received_time].
+    pub received_time: Option<u64>,
+    /// The work of the most work tip less the work of this tip, 0 for the most work tip.
+    pub work_gap: f64,
+}
+
+/// Find the tips that compete with the most work tip.
+///
+/// The blocks must include the most work tip and the recent part of the chain around it, a tip
+/// whose fork point is not in the blocks is not considered. A tip competes if it is valid, it is
+/// within distance blocks of the most work tip in height, the difference in work is no more
+/// than the work of distance blocks at the difficulty of the most work tip, and it was received
+/// within window seconds of now. The most work tip must also have been received within the
+/// window, so that stale forks are not reported as a split.
+pub fn find_split(
+    blocks: &[BlockInfo<u64>],
+    most_work_tip: u64,
+    distance: u64,
+    window: u64,
+    now: u64,
+) -> Option<Split> {
+    let nodes: HashMap<u64, &BlockInfo<u64>> = blocks.iter().map(|b| (b.id, b)).collect();
+    let best = *nodes.get(&most_work_tip)?;
+    let recent = |b: &BlockInfo<u64>| b.received_time.is_some_and(|t| t + window >= now);
+    if !recent(best) {
+        return None;
+    }
+    // the work of each block and its ancestors amongst the blocks, parents before children
+    let mut by_height: Vec<&BlockInfo<u64>> = blocks.iter().collect();
+    by_height.sort_by_key(|b| b.height);
+    let mut work: HashMap<u64, f64> = HashMap::new();
+    for b in by_height {
+        let w = work.get(&b.prev_id).copied().unwrap_or(0.0) + block_work(b.header.bits);
+        work.insert(b.id, w);
+    }
+    let limit = distance as f64 * block_work(best.header.bits);
+    let mut tips = Vec::new();
+    let mut ancestor: Option<&BlockInfo<u64>> = None;
+    for b in blocks.iter() {
+        if b.id == best.id
+            || !b.next_ids.is_empty()
+            || is_invalid(&b.validity)
+            || !recent(b)
+            || b.height.abs_diff(best.height) > distance
+        {
+            continue;
+        }
+        let gap = work[&best.id] - work[&b.id];
+        // allow for the rounding of the sums of the work
+        if gap.abs() > limit * (1.0 + 1e-9) {
+            continue;
+        }
+        let a = match common_ancestor(&nodes, best, b) {
+            Some(a) => a,
+            None => continue,
+        };
+        if ancestor.map_or(true, |x| a.height < x.height) {
+            ancestor = Some(a);
+        }
+        tips.push(split_tip(b, gap));
+    }
+    let ancestor = ancestor?;
+    tips.sort_by(|a, b| b.height.cmp(&a.height).then(a.id.cmp(&b.id)));
+    let work_gap = tips.iter().map(|t| t.work_gap).fold(0.0, f64::max);
+    tips.insert(0, split_tip(best, 0.0));
+    Some(Split {
+        tips,
+        common_ancestor: ancestor.id,
+        ancestor_height: ancestor.height,
+        work_gap,
+    })
+}
+
+fn is_invalid(validity: &BlockValidity) -> bool {
+    matches!(
+        validity,
+        BlockValidity::Invalid | BlockValidity::HeaderInvalid | BlockValidity::InvalidAncestor
+    )
+}
+
+fn split_tip(b: &BlockInfo<u64>, work_gap: f64) -> SplitTip {
+    SplitTip {
+        id: b.id,
+        hash: b.hash,
+        height: b.height,
+        received_time: b.received_time,
+        work_gap,
+    }
+}
+
+// The last block that a and b have in common, None if it is not amongst the nodes.
+fn common_ancestor<'a>(
+    nodes: &HashMap<u64, &'a BlockInfo<u64>>,
+    mut a: &'a BlockInfo<u64>,
+    mut b: &'a BlockInfo<u64>,
+) -> Option<&'a BlockInfo<u64>> {
+    while a.id != b.id {
+        if a.height >= b.height {
+            a = nodes.get(&a.prev_id)?;
+        } else {
+            b = nodes.get(&b.prev_id)?;
+        }
+        if a.height == 0 && b.height == 0 && a.id != b.id {
+            return None;
+        }
+    }
+    Some(a)
+}
+
+/// Tracks whether there is a split, turning the splits that are found into events.
+#[derive(Default)]
+pub(crate) struct SplitWatch {
+    current: Option<Split>,
+}
+
+impl SplitWatch {
+    /// Record the split that was found, if any, returning the event if the state has changed.
+    pub(crate) fn update(&mut self, split: Option<Split>) -> Option<ChainEvent> {
+        match (self.current.take(), split) {
+            (None, Some(s)) => {
+                self.current = Some(s.clone());
+                Some(ChainEvent::SplitDetected(s))
+            }
+            (Some(old), None) => Some(ChainEvent::SplitResolved(old)),
+            (_, s) => {
+                self.current = s;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::BlockchainId;
+
+    const NOW: u64 = 1_700_000_000;
+
+    // A chain that blocks can be added to, the ids are in order of addition.
+    struct Chain {
+        blocks: Vec<BlockInfo<u64>>,
+    }
+
+    impl Chain {
+        fn new() -> Chain {
+            let mut genesis = BlockInfo::genesis_info(BlockchainId::Regtest);
+            genesis.received_time = Some(NOW - 100_000);
+            Chain {
+                blocks: vec![genesis],
+            }
+        }
+
+        // add a child of the parent, received at the time
+        fn add(&mut self, parent: u64, received_time: u64) -> u64 {
+            let id = self.blocks.len() as u64;
+            let p = &mut self.blocks[parent as usize];
+            p.next_ids.push(id);
+            let mut header = p.header.clone();
+            header.prev_hash = p.hash;
+            header.nonce = id as u32;
+            let height = p.height + 1;
+            self.blocks.push(BlockInfo {
+                id,
+                hash: header.hash(),
+                header,
+                height,
+                prev_id: parent,
+                next_ids: vec![],
+                size: None,
+                num_tx: None,
+                median_time: None,
+                chain_work: None,
+                total_tx: None,
+                total_size: None,
+                total_fees: None,
+                miner: None,
+                validity: BlockValidity::Valid,
+                received_time: Some(received_time),
+                source: None,
+            });
+            id
+        }
+
+        fn split(&self, tip: u64) -> Option<Split> {
+            find_split(&self.blocks, tip, 3, 3600, NOW)
+        }
+    }
+
+    // Two branches extend alternately, then one pulls away.
+    #[test]
+    fn contest() {
+        let mut c = Chain::new();
+        let mut watch = SplitWatch::default();
+        let mut events = Vec::new();
+        let mut gauges = Vec::new();
+        let mut record = |c: &Chain, tip: u64| {
+            let split = c.split(tip);
+            gauges.push(split.as_ref().map_or(0, |s| s.tips.len()));
+            if let Some(e) = watch.update(split) {
+                events.push(e);
+            }
+        };
+        let a1 = c.add(0, NOW);
+        record(&c, a1);
+        let b1 = c.add(0, NOW);
+        record(&c, a1);
+        let a2 = c.add(a1, NOW);
+        record(&c, a2);
+        let b2 = c.add(b1, NOW);
+        record(&c, a2);
+        let b3 = c.add(b2, NOW);
+        record(&c, b3);
+        let mut a = a2;
+        for _ in 0..5 {
+            a = c.add(a, NOW);
+            record(&c, a);
+        }
+        assert_eq!(gauges, vec![0, 2, 2, 2, 2, 2, 2, 2, 2, 0]);
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            ChainEvent::SplitDetected(s) => {
+                let ids: Vec<u64> = s.tips.iter().map(|t| t.id).collect();
+                assert_eq!(ids, vec![a1, b1]);
+                assert_eq!(s.common_ancestor, 0);
+                assert_eq!(s.work_gap, 0.0);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        match &events[1] {
+            ChainEvent::SplitResolved(s) => {
+                // the split as it was last seen, three blocks behind
+                assert_eq!(s.tips[1].id, b3);
+                assert_eq!(s.tips[0].height, 6);
+                assert!(s.work_gap > 0.0);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    // Stale and invalid forks do not make a split.
+    #[test]
+    fn ignored_tips() {
+        let mut c = Chain::new();
+        let a1 = c.add(0, NOW);
+        let b1 = c.add(0, NOW - 7200);
+        assert_eq!(c.split(a1), None);
+        let _b2 = c.add(b1, NOW - 7000);
+        let a2 = c.add(a1, NOW);
+        assert_eq!(c.split(a2), None);
+        let x = c.add(a1, NOW);
+        c.blocks[x as usize].validity = BlockValidity::Invalid;
+        assert_eq!(c.split(a2), None);
+        // a tip that is recent competes
+        let y = c.add(a1, NOW);
+        let s = c.split(a2).unwrap();
+        assert_eq!(s.tips.len(), 2);
+        assert_eq!(s.tips[1].id, y);
+        assert_eq!(s.common_ancestor, a1);
+        assert_eq!(s.ancestor_height, 1);
+        // a stale most work tip is not a live contest
+        c.blocks[a2 as usize].received_time = Some(NOW - 7200);
+        assert_eq!(c.split(a2), None);
+    }
+
+    #[test]
+    fn fork_not_in_blocks() {
+        let mut c = Chain::new();
+        let a1 = c.add(0, NOW);
+        let b1 = c.add(0, NOW);
+        let blocks: Vec<BlockInfo<u64>> = c.blocks[1..].to_vec();
+        assert_eq!(find_split(&blocks, a1, 3, 3600, NOW), None);
+        assert_eq!(c.split(a1).unwrap().tips[1].id, b1);
+    }
+}
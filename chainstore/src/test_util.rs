@@ -0,0 +1,352 @@
+use crate::{BlockInfo, BlockValidity, BranchOutcome, ChainStore, LinkCheck};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
+use futures::StreamExt;
+
+pub use crate::memory_store::MemoryChainStore;
+
+// Generators of synthetic chains for tests, available to other crates with the test-util feature.
+//
+// The headers are linked by their prev_hash and are deterministic, the same arguments always give
+// the same headers. They take the version and bits of the genesis block of the chain, and a
+// timestamp ten minutes after their parent. The proof of work is only solved for regtest, where
+// about every second nonce meets the target. The chain store does not check the proof of work, so
+// the headers of other chains can still be stored.
+
+// the time between generated blocks, in seconds
+const BLOCK_INTERVAL: u32 = 600;
+
+/// Generate a chain of len headers following the genesis block of the blockchain. The genesis
+/// header is not included.
+pub fn generate_chain(len: usize, blockchain: BlockchainId) -> Vec<BlockHeader> {
+    let genesis = BlockHeader::get_genesis(blockchain);
+    let solve = matches!(blockchain, BlockchainId::Regtest);
+    generate_branch(&genesis, len, 0, solve)
+}
+
+/// Generate a branch of len headers following the parent. Branches from the same parent with a
+/// different branch number have different hashes, which can be used to generate forks. When
+/// solve is set, the nonce of each header is chosen so that its hash meets the target of its
+/// bits, which is only practical for regtest.
+pub fn generate_branch(
+    parent: &BlockHeader,
+    len: usize,
+    branch: u32,
+    solve: bool,
+) -> Vec<BlockHeader> {
+    let mut headers: Vec<BlockHeader> = Vec::with_capacity(len);
+    for _ in 0..len {
+        let prev = headers.last().unwrap_or(parent);
+        let mut header = prev.clone();
+        header.prev_hash = prev.hash();
+        header.timestamp = prev.timestamp + BLOCK_INTERVAL;
+        // stands in for the transactions, it only needs to differ between branches
+        let mut root = prev.hash().hash.to_vec();
+        root.extend(branch.to_le_bytes());
+        header.merkle_root = BlockHash::sha256d(&root);
+        header.nonce = 0;
+        if solve {
+            while !meets_target(&header.hash(), header.bits) {
+                header.nonce += 1;
+            }
+        }
+        headers.push(header);
+    }
+    headers
+}
+
+/// The BlockInfo of a header, ready to be stored. The id, height and links are set by the
+/// ChainStore when it is stored.
+pub fn header_info(header: &BlockHeader, validity: BlockValidity) -> BlockInfo<u64> {
+    header_info_with_id(header, validity, 0)
+}
+
+/// The BlockInfo of a header for a ChainStore whose ids are not integers, with a placeholder id
+/// that is replaced when it is stored, such as the id of the genesis block.
+pub fn header_info_with_id<Id: Clone>(
+    header: &BlockHeader,
+    validity: BlockValidity,
+    id: Id,
+) -> BlockInfo<Id> {
+    BlockInfo {
+        prev_id: id.clone(),
+        id,
+        hash: header.hash(),
+        header: header.clone(),
+        height: 0,
+        next_ids: vec![],
+        size: None,
+        num_tx: None,
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity,
+        received_time: None,
+        source: None,
+    }
+}
+
+/// Check the behaviour of a ChainStore through the trait alone, so that it can be run against
+/// every implementation, whatever its BlockId.
+///
+/// The ChainStore must be new, holding only the genesis block of the blockchain. Panics if a
+/// check fails.
+pub async fn check_chain_store<S: ChainStore>(store: &S, blockchain: BlockchainId) {
+    let genesis_hash = BlockHeader::get_genesis(blockchain).hash();
+    let state = store.get_chain_state().await.unwrap();
+    let genesis = store
+        .get_block_info(state.most_work_tip.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(genesis.hash, genesis_hash);
+    assert_eq!(genesis.height, 0);
+    genesis.check_genesis_with_id(&genesis.id).unwrap();
+    let by_hash = store.get_block_info_by_hash(genesis_hash).await.unwrap();
+    assert_eq!(by_hash, Some(genesis.clone()));
+    // the ids of the blocks to store are placeholders
+    let info =
+        |h: &BlockHeader| header_info_with_id(h, BlockValidity::ValidHeader, genesis.id.clone());
+
+    // a chain of 10 blocks, each linked to its parent and becoming the most work tip
+    let chain = generate_chain(10, blockchain);
+    let mut main = vec![genesis.clone()];
+    for h in chain.iter() {
+        let b = store.store_block_info(info(h)).await.unwrap();
+        let parent = main.last().unwrap();
+        assert_eq!(b.prev_id, parent.id);
+        assert_eq!(b.height, parent.height + 1);
+        assert_eq!(
+            store.get_block_info(b.id.clone()).await.unwrap(),
+            Some(b.clone())
+        );
+        main.push(b);
+    }
+    let tip = main.last().unwrap().clone();
+    assert_eq!(store.get_chain_state().await.unwrap().most_work_tip, tip.id);
+    let genesis = store
+        .get_block_info(genesis.id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(genesis.next_ids, vec![main[1].id.clone()]);
+
+    // walks down the chain, limited or to the genesis block
+    let walk = store
+        .get_block_infos(tip.id.clone(), Some(5))
+        .await
+        .unwrap();
+    let walk: Vec<_> = walk.map(|r| r.unwrap().id).collect().await;
+    let expected: Vec<_> = main[6..].iter().rev().map(|b| b.id.clone()).collect();
+    assert_eq!(walk, expected);
+    let walk = store.get_block_infos(tip.id.clone(), None).await.unwrap();
+    let walk: Vec<_> = walk.map(|r| r.unwrap().hash).collect().await;
+    assert_eq!(walk.len(), 11);
+    assert_eq!(walk.last(), Some(&genesis_hash));
+
+    // a fork from height 5, which is linked but does not become the most work tip
+    let fork = generate_branch(&chain[4], 3, 1, false);
+    match store.check_would_link(&info(&fork[0])).await.unwrap() {
+        LinkCheck::Links {
+            height,
+            prev_id,
+            existing_id,
+            ..
+        } => {
+            assert_eq!(height, 6);
+            assert_eq!(prev_id, main[5].id);
+            assert_eq!(existing_id, None);
+        }
+        LinkCheck::ParentNotFound => panic!("expected the fork to link"),
+    }
+    assert_eq!(
+        store.check_would_link(&info(&fork[1])).await.unwrap(),
+        LinkCheck::ParentNotFound
+    );
+    let mut fork_ids = Vec::new();
+    for h in fork.iter() {
+        fork_ids.push(store.store_block_info(info(h)).await.unwrap().id);
+    }
+    assert_eq!(store.get_chain_state().await.unwrap().most_work_tip, tip.id);
+    let fork_point = store
+        .get_block_info(main[5].id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fork_point.next_ids.len(), 2);
+    assert!(fork_point.next_ids.contains(&fork_ids[0]));
+
+    // the briefs match the block infos, on and off the main chain, and unknown blocks are None
+    let mut hashes: Vec<_> = main.iter().map(|b| b.hash).collect();
+    hashes.extend(fork.iter().map(|h| h.hash()));
+    hashes.push(generate_branch(&fork[2], 1, 2, false)[0].hash());
+    let briefs = store.get_block_briefs(hashes.clone()).await.unwrap();
+    assert_eq!(briefs.len(), hashes.len());
+    assert_eq!(briefs.last(), Some(&None));
+    for (i, (hash, brief)) in hashes.iter().zip(briefs.iter()).take(14).enumerate() {
+        let b_info = store.get_block_info_by_hash(*hash).await.unwrap().unwrap();
+        let brief = brief.clone().unwrap();
+        assert_eq!(brief.id, b_info.id);
+        assert_eq!(brief.height, b_info.height);
+        assert_eq!(brief.validity, b_info.validity);
+        if let Some(on_main_chain) = brief.on_main_chain {
+            assert_eq!(on_main_chain, i < main.len());
+        }
+        assert_eq!(
+            store.get_block_brief(*hash).await.unwrap(),
+            Some(brief.clone())
+        );
+    }
+    assert_eq!(briefs[10].as_ref().unwrap().on_main_chain, Some(true));
+    assert_eq!(briefs[13].as_ref().unwrap().on_main_chain, Some(false));
+
+    let summary = store.summary().await.unwrap();
+    assert_eq!(summary.num_blocks, 14);
+    assert_eq!(summary.num_tips, 2);
+    assert_eq!(summary.tip_height, 10);
+    assert_eq!(summary.genesis_hash, genesis_hash);
+    let valid: Vec<_> = store
+        .list_blocks_by_validity(BlockValidity::ValidHeader)
+        .collect()
+        .await;
+    assert_eq!(valid.len(), 13);
+    let main_chain: Vec<_> = store
+        .stream_main_chain()
+        .map(|r| r.unwrap().id)
+        .collect()
+        .await;
+    let expected: Vec<_> = main.iter().map(|b| b.id.clone()).collect();
+    assert_eq!(main_chain, expected);
+
+    // extending the fork past the main chain makes it the main chain, once
+    let branch: Vec<_> = generate_branch(&fork[2], 4, 1, false)
+        .iter()
+        .map(info)
+        .collect();
+    match store.apply_branch(branch.clone(), tip.hash).await.unwrap() {
+        BranchOutcome::Applied {
+            fork_point,
+            displaced,
+            blocks,
+        } => {
+            assert_eq!(fork_point, main[5].id);
+            let expected: Vec<_> = main[6..].iter().map(|b| b.id.clone()).collect();
+            assert_eq!(displaced, expected);
+            assert_eq!(blocks.len(), 4);
+            assert_eq!(blocks[0].prev_id, fork_ids[2]);
+            let state = store.get_chain_state().await.unwrap();
+            assert_eq!(state.most_work_tip, blocks[3].id);
+        }
+        BranchOutcome::Outdated { .. } => panic!("expected the branch to be applied"),
+    }
+    match store.apply_branch(branch, tip.hash).await.unwrap() {
+        BranchOutcome::Outdated { current_tip } => assert_ne!(current_tip, tip.hash),
+        BranchOutcome::Applied { .. } => panic!("expected the branch to be outdated"),
+    }
+
+    // a batch that does not extend the most work tip is stored at once, parent before child
+    let most_work_tip = store.get_chain_state().await.unwrap().most_work_tip;
+    let batch: Vec<_> = generate_branch(&chain[2], 3, 1, false)
+        .iter()
+        .map(info)
+        .collect();
+    let stored = store.store_block_infos(batch).await.unwrap();
+    assert_eq!(stored.len(), 3);
+    assert_eq!(stored[0].prev_id, main[3].id);
+    assert_eq!(stored[2].prev_id, stored[1].id);
+    assert_eq!(stored[2].height, 6);
+    let state = store.get_chain_state().await.unwrap();
+    assert_eq!(state.most_work_tip, most_work_tip);
+    // a batch with a block whose parent is missing stores none of its blocks
+    let linked = generate_branch(&chain[9], 1, 2, false);
+    let orphan = generate_branch(&linked[0], 2, 2, false)[1].clone();
+    let r = store
+        .store_block_infos(vec![info(&linked[0]), info(&orphan)])
+        .await;
+    assert!(r.is_err());
+    let linked = store
+        .get_block_info_by_hash(linked[0].hash())
+        .await
+        .unwrap();
+    assert_eq!(linked, None);
+}
+
+/// Whether the hash is not above the target encoded in the bits.
+pub fn meets_target(hash: &BlockHash, bits: u32) -> bool {
+    let target = target(bits);
+    // both are little-endian, compare from the most significant byte
+    for i in (0..32).rev() {
+        if hash.hash[i] != target[i] {
+            return hash.hash[i] < target[i];
+        }
+    }
+    true
+}
+
+// the target of the compact bits, as a little-endian 256 bit number
+fn target(bits: u32) -> [u8; 32] {
+    let mut target = [0u8; 32];
+    let exponent = (bits >> 24) as usize;
+    let mantissa = (bits & 0x00ff_ffff).to_le_bytes();
+    for (i, b) in mantissa.iter().take(3).enumerate() {
+        // target = mantissa * 256^(exponent - 3)
+        if let Some(pos) = (i + exponent).checked_sub(3) {
+            if pos < 32 {
+                target[pos] = *b;
+            }
+        }
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The headers are linked to genesis and to each other, and are deterministic.
+    #[test]
+    fn chain_is_linked() {
+        for blockchain in [BlockchainId::Main, BlockchainId::Regtest] {
+            let genesis = BlockHeader::get_genesis(blockchain);
+            let chain = generate_chain(10, blockchain);
+            assert_eq!(chain.len(), 10);
+            assert_eq!(chain[0].prev_hash, genesis.hash());
+            for w in chain.windows(2) {
+                assert_eq!(w[1].prev_hash, w[0].hash());
+                assert_eq!(w[1].timestamp, w[0].timestamp + BLOCK_INTERVAL);
+                assert_eq!(w[1].bits, genesis.bits);
+            }
+            assert_eq!(chain, generate_chain(10, blockchain));
+        }
+    }
+
+    // Regtest headers meet their target, and branches from the same parent differ.
+    #[test]
+    fn regtest_is_solved() {
+        let chain = generate_chain(20, BlockchainId::Regtest);
+        assert!(chain.iter().all(|h| meets_target(&h.hash(), h.bits)));
+        let fork = generate_branch(&chain[9], 5, 1, true);
+        let main = generate_branch(&chain[9], 5, 0, true);
+        assert_eq!(main[..], chain[10..15]);
+        assert_eq!(fork[0].prev_hash, chain[9].hash());
+        assert_ne!(fork[0].hash(), main[0].hash());
+    }
+
+    // The genesis blocks meet their own targets, and a target can be missed.
+    #[test]
+    fn targets() {
+        for blockchain in [
+            BlockchainId::Main,
+            BlockchainId::Test,
+            BlockchainId::Regtest,
+        ] {
+            let genesis = BlockHeader::get_genesis(blockchain);
+            assert!(meets_target(&genesis.hash(), genesis.bits));
+        }
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        assert!(!meets_target(&genesis.hash(), 0x1d00ffff));
+        assert_eq!(target(0x1d00ffff)[26..29], [0xff, 0xff, 0x00]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::cmp::Ordering;
+
+// Exact chain work, as the big-endian 256 bit numbers stored in the chain_work of a BlockInfo.
+//
+// The numbers are held as little-endian 64 bit limbs, with a fifth limb so that the remainder of
+// the division can not overflow.
+type Limbs = [u64; 5];
+
+/// The chain work of a block, which is the chain work of its parent plus the work of the block
+/// given the bits of its header. None if the chain work of the parent is longer than 32 bytes or
+/// the sum does not fit in 32 bytes.
+pub(crate) fn child_chain_work(parent_work: &[u8], bits: u32) -> Option<Vec<u8>> {
+    let parent = from_bytes(parent_work)?;
+    let sum = add(&parent, &header_work(bits));
+    if sum[4] != 0 {
+        return None;
+    }
+    Some(to_bytes(&sum))
+}
+
+/// Whether the stored chain work of a block is the chain work of its parent plus the work of the
+/// block given the bits of its header, as [child_chain_work()] computes it.
+///
+/// A block whose parent has no chain work can not be checked, so it agrees. A block whose sum does
+/// not fit in 32 bytes agrees only if it has no chain work, as it is stored.
+pub(crate) fn chain_work_agrees(
+    parent_work: Option<&[u8]>,
+    stored: Option<&[u8]>,
+    bits: u32,
+) -> bool {
+    let Some(parent_work) = parent_work else {
+        return true;
+    };
+    match (child_chain_work(parent_work, bits), stored) {
+        (Some(expected), Some(stored)) => cmp_work(&expected, stored) == Ordering::Equal,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Compare two chain works, which may be of different lengths.
+pub(crate) fn cmp_work(a: &[u8], b: &[u8]) -> Ordering {
+    let a = trim(a);
+    let b = trim(b);
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+// the work of a header, 2^256 / (target + 1), which is computed as ~target / (target + 1) + 1
+//
+// a target which is zero, negative or too large to be encoded has no work
+fn header_work(bits: u32) -> Limbs {
+    let exponent = bits >> 24;
+    let mantissa = (bits & 0x007f_ffff) as u64;
+    if mantissa == 0 || bits & 0x0080_0000 != 0 || exponent > 34 {
+        return [0; 5];
+    }
+    // target = mantissa * 256^(exponent - 3)
+    let mut target: Limbs = [mantissa, 0, 0, 0, 0];
+    if exponent < 3 {
+        target[0] >>= 8 * (3 - exponent);
+    } else {
+        for _ in 0..8 * (exponent - 3) {
+            target = shl1(&target);
+        }
+    }
+    if target[4] != 0 {
+        return [0; 5];
+    }
+    let mut not_target = target.map(|l| !l);
+    not_target[4] = 0;
+    let divisor = add(&target, &[1, 0, 0, 0, 0]);
+    add(&div(&not_target, &divisor), &[1, 0, 0, 0, 0])
+}
+
+// the quotient of a 256 bit number by a non-zero divisor, by shifting and subtracting
+fn div(n: &Limbs, d: &Limbs) -> Limbs {
+    let mut q = [0; 5];
+    let mut r = [0; 5];
+    for i in (0..256).rev() {
+        r = shl1(&r);
+        r[0] |= (n[i / 64] >> (i % 64)) & 1;
+        if cmp(&r, d) != Ordering::Less {
+            r = sub(&r, d);
+            q[i / 64] |= 1 << (i % 64);
+        }
+    }
+    q
+}
+
+fn add(a: &Limbs, b: &Limbs) -> Limbs {
+    let mut r = [0; 5];
+    let mut carry = false;
+    for (r, (a, b)) in r.iter_mut().zip(a.iter().zip(b)) {
+        let (s, c1) = a.overflowing_add(*b);
+        let (s, c2) = s.overflowing_add(carry as u64);
+        *r = s;
+        carry = c1 || c2;
+    }
+    r
+}
+
+// a - b, where a is not less than b
+fn sub(a: &Limbs, b: &Limbs) -> Limbs {
+    let mut r = [0; 5];
+    let mut borrow = false;
+    for (r, (a, b)) in r.iter_mut().zip(a.iter().zip(b)) {
+        let (s, b1) = a.overflowing_sub(*b);
+        let (s, b2) = s.overflowing_sub(borrow as u64);
+        *r = s;
+        borrow = b1 || b2;
+    }
+    r
+}
+
+fn shl1(a: &Limbs) -> Limbs {
+    let mut r = [0; 5];
+    let mut carry = 0;
+    for (r, a) in r.iter_mut().zip(a) {
+        *r = (a << 1) | carry;
+        carry = a >> 63;
+    }
+    r
+}
+
+fn cmp(a: &Limbs, b: &Limbs) -> Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+// the bytes without leading zeros
+fn trim(b: &[u8]) -> &[u8] {
+    let start = b.iter().position(|j| *j != 0).unwrap_or(b.len());
+    &b[start..]
+}
+
+fn from_bytes(b: &[u8]) -> Option<Limbs> {
+    let b = trim(b);
+    if b.len() > 32 {
+        return None;
+    }
+    let mut r = [0; 5];
+    for (i, j) in b.iter().rev().enumerate() {
+        r[i / 8] |= (*j as u64) << (8 * (i % 8));
+    }
+    Some(r)
+}
+
+// the 32 big-endian bytes of a number which fits in 256 bits
+fn to_bytes(a: &Limbs) -> Vec<u8> {
+    a[..4].iter().rev().flat_map(|l| l.to_be_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn work(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    // The work of the genesis headers matches the chain work of the genesis BlockInfos.
+    #[test]
+    fn genesis_work() {
+        let zero = [0u8; 32];
+        assert_eq!(
+            child_chain_work(&zero, 0x1d00ffff),
+            Some(work(
+                "0000000000000000000000000000000000000000000000000000000100010001"
+            ))
+        );
+        assert_eq!(
+            child_chain_work(&zero, 0x207fffff),
+            Some(work(
+                "0000000000000000000000000000000000000000000000000000000000000002"
+            ))
+        );
+        let genesis = work("0000000000000000000000000000000000000000000000000000000100010001");
+        assert_eq!(
+            child_chain_work(&genesis, 0x1d00ffff),
+            Some(work(
+                "0000000000000000000000000000000000000000000000000000000200020002"
+            ))
+        );
+    }
+
+    // Targets that can not be encoded have no work, and the sum must fit in 32 bytes.
+    #[test]
+    fn invalid() {
+        let zero = [0u8; 32];
+        for bits in [0, 0x1d000000, 0x1d800001, 0xff00ffff] {
+            assert_eq!(child_chain_work(&zero, bits), Some(zero.to_vec()));
+        }
+        assert_eq!(child_chain_work(&[0xff; 32], 0x1d00ffff), None);
+        assert_eq!(child_chain_work(&[1; 33], 0x1d00ffff), None);
+        // a leading zero does not make the work too long
+        let mut w = vec![0];
+        w.extend([0; 32]);
+        assert_eq!(child_chain_work(&w, 0x207fffff).unwrap().len(), 32);
+    }
+
+    // The stored work must be the parent work plus the work of the bits, of any length.
+    #[test]
+    fn agrees() {
+        let genesis = work("0000000000000000000000000000000000000000000000000000000100010001");
+        let child = work("0000000000000000000000000000000000000000000000000000000200020002");
+        assert!(chain_work_agrees(Some(&genesis), Some(&child), 0x1d00ffff));
+        assert!(chain_work_agrees(
+            Some(&genesis),
+            Some(&child[20..]),
+            0x1d00ffff
+        ));
+        assert!(!chain_work_agrees(
+            Some(&genesis),
+            Some(&genesis),
+            0x1d00ffff
+        ));
+        assert!(!chain_work_agrees(Some(&genesis), Some(&child), 0x207fffff));
+        assert!(!chain_work_agrees(Some(&genesis), None, 0x1d00ffff));
+        // the parent has no work to check against
+        assert!(chain_work_agrees(None, Some(&genesis), 0x1d00ffff));
+        assert!(chain_work_agrees(None, None, 0x1d00ffff));
+        // the sum does not fit, so the block has no work
+        assert!(chain_work_agrees(Some(&[0xff; 32]), None, 0x1d00ffff));
+        assert!(!chain_work_agrees(
+            Some(&[0xff; 32]),
+            Some(&child),
+            0x1d00ffff
+        ));
+        // the work of a genesis block is the work of its header
+        assert!(chain_work_agrees(Some(&[]), Some(&genesis), 0x1d00ffff));
+    }
+
+    // Works of different lengths are compared as numbers.
+    #[test]
+    fn compare() {
+        assert_eq!(cmp_work(&[0, 0, 5], &[5]), Ordering::Equal);
+        assert_eq!(cmp_work(&[1, 0], &[0, 0xff]), Ordering::Greater);
+        assert_eq!(cmp_work(&[], &[0, 1]), Ordering::Less);
+    }
+}
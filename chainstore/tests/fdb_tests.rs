@@ -1,14 +1,46 @@
 use bitcoinsv::bitcoin::{BlockHash, BlockHeader, BlockchainId};
-use bsvdb_base::ChainStoreConfig;
-use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
-use foundationdb;
+use bsvdb_base::{ChainStoreConfig, VersionRecord, MAX_VERSIONS};
+use bsvdb_chainstore::test_util::{
+    check_chain_store, generate_branch, generate_chain, header_info,
+};
+use bsvdb_chainstore::{
+    BlockBrief, BlockInfo, BlockValidity, BranchOutcome, ChainEvent, ChainStore, CounterDrift,
+    Error, FDBChainStore, Filter, InsertOptions, LinkCheck, LinkRepair, OperationClass,
+    ReachabilityFix, Snapshot, Window,
+};
 use foundationdb::directory::Directory;
+use foundationdb::tuple::{pack, unpack, Element};
+use futures::StreamExt;
 use hex::FromHex;
 use rand::random;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
 
 #[tokio::test]
 async fn run_fdb_tests() {
-    let network = unsafe { foundationdb::boot() };
+    // versions that are not supported fail before the network is started
+    for fdb_api_version in [500, 720] {
+        let c = ChainStoreConfig {
+            fdb_api_version,
+            ..ChainStoreConfig::default()
+        };
+        match unsafe { bsvdb_chainstore::boot(&c) } {
+            Err(Error::UnsupportedApiVersion(v, _)) => assert_eq!(v, fdb_api_version),
+            _ => panic!("expected UnsupportedApiVersion"),
+        }
+    }
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    // capture spans from all threads, the actor runs in its own task
+    let capture = CaptureLayer::default();
+    tracing::subscriber::set_global_default(Registry::default().with(capture.clone()))
+        .expect("failed to set subscriber");
 
     // get a unique root
     let r_id: u16 = random();
@@ -16,32 +48,818 @@ async fn run_fdb_tests() {
     let config = ChainStoreConfig {
         enabled: true,
         root_path: root,
+        info_shards: 4,
+        ..ChainStoreConfig::default()
     };
     let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
         .await
         .unwrap();
 
+    check_initialized(&chain_store, &config).await;
     check_clone_store(&chain_store).await;
     check_multi_spawn(&chain_store).await;
     check_store(&chain_store).await;
+    check_is_on_main_chain(&chain_store).await;
+    check_summary(&chain_store).await;
+    check_counter_drift(&chain_store, &config.root_path).await;
+    check_tracing(&chain_store, &capture).await;
+    check_reorgs(&chain_store).await;
+    check_quality(&chain_store).await;
+    check_metrics(&chain_store).await;
+    check_reachability(&chain_store, &config.root_path).await;
+    check_list_by_validity(&chain_store).await;
+    check_main_chain(&chain_store).await;
+    check_repair_links(&chain_store).await;
+    check_snapshot(&chain_store).await;
+    check_raw_block_info(&chain_store, &config.root_path).await;
+    check_apply_branch(&chain_store).await;
+    check_would_link(&chain_store).await;
+    check_read_only(&config).await;
+
+    chain_store.shutdown().await.expect("failed shutting down");
+    j.await.expect("failed waiting for task to terminate.");
+
+    remove_root(&config.root_path).await;
+
+    check_custom_genesis(r_id).await;
+    check_sharding(r_id).await;
+    check_prefix(r_id).await;
+    check_overload(r_id).await;
+    check_split(r_id).await;
+    check_guardrail(r_id).await;
+    check_generated_chain(r_id).await;
+    check_batched_walk(r_id).await;
+    check_versions(r_id).await;
+    check_audit_chain_work(r_id).await;
+    check_shared(r_id).await;
+    check_directory_cache(r_id, &capture).await;
+
+    drop(network);
+}
+
+/// Run the checks that are shared by every ChainStore against a new chain store.
+async fn check_shared(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}shared", r_id),
+        info_shards: 4,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    check_chain_store(&chain_store, BlockchainId::Main).await;
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    remove_root(&config.root_path).await;
+}
+
+/// Check that a generated regtest chain with a fork can be stored, and that the longer branch
+/// becomes the main chain.
+async fn check_generated_chain(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}generated", r_id),
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Regtest)
+        .await
+        .unwrap();
+    let chain = generate_chain(20, BlockchainId::Regtest);
+    let mut tip = None;
+    for h in chain.iter() {
+        tip = Some(
+            chain_store
+                .store_block_info(header_info(h, BlockValidity::Valid))
+                .await
+                .unwrap(),
+        );
+    }
+    let tip = tip.unwrap();
+    assert_eq!(tip.height, 20);
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        tip.id
+    );
+    // a longer branch from height 15 takes over
+    let fork = generate_branch(&chain[14], 7, 1, true);
+    let mut fork_tip = None;
+    for h in fork.iter() {
+        fork_tip = Some(
+            chain_store
+                .store_block_info(header_info(h, BlockValidity::Valid))
+                .await
+                .unwrap(),
+        );
+    }
+    let fork_tip = fork_tip.unwrap();
+    assert_eq!(fork_tip.height, 22);
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        fork_tip.id
+    );
+    assert!(!chain_store.is_on_main_chain(tip.id).await.unwrap());
+    // the blocks are filtered as they are streamed, the fork point has two children
+    let filter: Filter = "height >= 10 && height < 17 && children == 1"
+        .parse()
+        .unwrap();
+    let heights: Vec<u64> = chain_store
+        .get_block_infos(fork_tip.id, None)
+        .await
+        .unwrap()
+        .filter_map(|b| {
+            let b = b.unwrap();
+            futures::future::ready(filter.matches(&b).then_some(b.height))
+        })
+        .collect()
+        .await;
+    assert_eq!(heights, vec![16, 14, 13, 12, 11, 10]);
+    chain_store.shutdown().await.expect("failed shutting down");
+    j.await.expect("failed waiting for task to terminate.");
+
+    remove_root(&config.root_path).await;
+}
 
+/// Check that a walk in small batches, with a transaction that is rotated between them, gives the
+/// same blocks in the same order as a walk of one block at a time, across a fork where the ids of
+/// parent and child are not adjacent.
+async fn check_batched_walk(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}walk", r_id),
+        walk_batch_size: 3,
+        walk_trx_rotate_ms: 1,
+        walk_trx_rotate_reads: 4,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Regtest)
+        .await
+        .unwrap();
+    let chain = generate_chain(20, BlockchainId::Regtest);
+    let fork = generate_branch(&chain[14], 7, 1, true);
+    let mut fork_tip = None;
+    for h in chain.iter().chain(fork.iter()) {
+        fork_tip = Some(
+            chain_store
+                .store_block_info(header_info(h, BlockValidity::Valid))
+                .await
+                .unwrap(),
+        );
+    }
+    let fork_tip = fork_tip.unwrap();
+    // the fork, then the chain below the fork point, then genesis
+    let mut expected: Vec<BlockHash> = fork.iter().rev().map(|h| h.hash()).collect();
+    expected.extend(chain[..15].iter().rev().map(|h| h.hash()));
+    expected.push(chain[0].prev_hash);
+    assert_eq!(expected.len(), 23);
+    // either side of the batch size of 3, of the fork point and of the length of the chain
+    for max in [0, 1, 2, 3, 4, 7, 8, 22, 23, 100]
+        .into_iter()
+        .map(Some)
+        .chain([None])
+    {
+        let infos: Vec<BlockInfo<u64>> = chain_store
+            .get_block_infos(fork_tip.id, max)
+            .await
+            .unwrap()
+            .map(|b| b.unwrap())
+            .collect()
+            .await;
+        let n = max.map_or(expected.len(), |m| (m as usize).min(expected.len()));
+        assert_eq!(
+            infos.iter().map(|b| b.hash).collect::<Vec<_>>(),
+            expected[..n],
+            "max_blocks {:?}",
+            max
+        );
+        for (i, b) in infos.iter().enumerate() {
+            assert_eq!(b.height, 22 - i as u64);
+            if let Some(parent) = infos.get(i + 1) {
+                assert_eq!(b.prev_id, parent.id);
+            }
+        }
+    }
+    // a walk from a block that does not exist gives nothing
+    let empty = chain_store
+        .get_block_infos(u64::MAX - 1, None)
+        .await
+        .unwrap()
+        .count()
+        .await;
+    assert_eq!(empty, 0);
     chain_store.shutdown().await.expect("failed shutting down");
     j.await.expect("failed waiting for task to terminate.");
 
+    remove_root(&config.root_path).await;
+}
+
+/// Check that a block whose chain work does not follow from its parent is found by the audit, and
+/// only that block.
+async fn check_audit_chain_work(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}audit", r_id),
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Regtest)
+        .await
+        .unwrap();
+    let chain = generate_chain(12, BlockchainId::Regtest);
+    for h in chain[..10].iter() {
+        chain_store
+            .store_block_info(header_info(h, BlockValidity::Valid))
+            .await
+            .unwrap();
+    }
+    assert_eq!(chain_store.audit_chain_work().await.unwrap(), vec![]);
+    // a chain work that is given is stored as it is, its child is computed from it
+    let mut wrong = header_info(&chain[10], BlockValidity::Valid);
+    wrong.chain_work = Some(vec![1; 32]);
+    let wrong = chain_store.store_block_info(wrong).await.unwrap();
+    let child = chain_store
+        .store_block_info(header_info(&chain[11], BlockValidity::Valid))
+        .await
+        .unwrap();
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        child.id
+    );
+    assert_eq!(
+        chain_store.audit_chain_work().await.unwrap(),
+        vec![wrong.id]
+    );
+    chain_store.shutdown().await.expect("failed shutting down");
+    j.await.expect("failed waiting for task to terminate.");
+
+    remove_root(&config.root_path).await;
+}
+
+/// Check that the versions that write to a store are recorded, that a known version only has its
+/// last seen time updated, and that the history is bounded.
+async fn check_versions(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}versions", r_id),
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let library = FDBChainStore::library_version(0).version;
+    let history = chain_store.versions().await.unwrap();
+    assert_eq!(history.records().len(), 1);
+    let opened = history.get(&library).unwrap().clone();
+    assert_eq!(opened.first_seen, opened.last_seen);
+    assert!(opened.first_seen > 0);
+    // two other versions, one of them seen twice
+    let old = VersionRecord::new("0.0.1", Some("abc"), &["x"], 100);
+    chain_store.record_version(old).await.unwrap();
+    let new = VersionRecord::new("99.0.0", None, &[], 200);
+    chain_store.record_version(new).await.unwrap();
+    let old = VersionRecord::new("0.0.1", Some("abc"), &["x"], 300);
+    let history = chain_store.record_version(old).await.unwrap();
+    assert_eq!(history, chain_store.versions().await.unwrap());
+    assert_eq!(history.records().len(), 3);
+    let old = history.get("0.0.1").unwrap();
+    assert_eq!((old.first_seen, old.last_seen), (100, 300));
+    assert_eq!(old.git_hash.as_deref(), Some("abc"));
+    assert_eq!(old.features, vec![String::from("x")]);
+    let new = history.get("99.0.0").unwrap();
+    assert_eq!((new.first_seen, new.last_seen), (200, 200));
+    assert_eq!(history.newer_than(&library).unwrap().version, "99.0.0");
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    // opening again only updates the last seen time of the library, a read-only handle does not
+    // record its version and can not record one
+    let (chain_store, j) = FDBChainStore::new_readonly(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert_eq!(
+        chain_store.versions().await.unwrap().get(&library),
+        Some(&opened)
+    );
+    let r = chain_store
+        .record_version(VersionRecord::new("0.0.2", None, &[], 400))
+        .await;
+    assert!(matches!(r, Err(Error::ReadOnly)));
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let history = chain_store.versions().await.unwrap();
+    let reopened = history.get(&library).unwrap();
+    assert_eq!(reopened.first_seen, opened.first_seen);
+    assert!(reopened.last_seen >= opened.last_seen);
+
+    // the least recently seen versions are removed
+    for i in 0..MAX_VERSIONS as u64 {
+        let v = VersionRecord::new(&format!("1.{}.0", i), None, &[], 1000 + i);
+        chain_store.record_version(v).await.unwrap();
+    }
+    let history = chain_store.versions().await.unwrap();
+    assert_eq!(history.records().len(), MAX_VERSIONS);
+    assert!(history.get("0.0.1").is_none());
+    assert!(history.get("99.0.0").is_none());
+    assert!(history.get(&library).is_some());
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    remove_root(&config.root_path).await;
+}
+
+/// Check that the directories are resolved in a single transaction, that they are reused by the
+/// next handles on the same store, and that they are resolved again after the store was removed.
+async fn check_directory_cache(r_id: u16, capture: &CaptureLayer) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}dirs", r_id),
+        ..ChainStoreConfig::default()
+    };
+    // the spans of the directory resolutions since start
+    let resolutions = |start: usize| {
+        let spans = capture.spans.lock().unwrap().clone();
+        let found: Vec<usize> = (start..spans.len())
+            .filter(|i| {
+                spans[*i].name == "fdb" && spans[*i].field("op") == Some("open_directories")
+            })
+            .collect();
+        (spans, found)
+    };
+
+    let start = capture.spans.lock().unwrap().len();
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    let (spans, found) = resolutions(start);
+    assert_eq!(found.len(), 1);
+    let commits = (start..spans.len())
+        .filter(|i| {
+            spans[*i].field("op") == Some("commit")
+                && CapturedSpan::descends_from(&spans, *i, found[0])
+        })
+        .count();
+    assert_eq!(commits, 1);
+
+    // the next handles reuse the directories
+    let start = capture.spans.lock().unwrap().len();
+    let (chain_store, j) = FDBChainStore::new_if_exists(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert!(chain_store.is_initialized().await.unwrap());
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    let (chain_store, j) = FDBChainStore::new_readonly(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert_eq!(chain_store.summary().await.unwrap().num_blocks, 1);
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    assert!(resolutions(start).1.is_empty());
+
+    // the store is removed by another process and created again
+    remove_root(&config.root_path).await;
+    let r = FDBChainStore::new_if_exists(&config, BlockchainId::Main).await;
+    assert!(matches!(r, Err(Error::NotInitialized)));
+    let start = capture.spans.lock().unwrap().len();
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert!(!chain_store.is_initialized().await.unwrap());
+    assert_eq!(chain_store.summary().await.unwrap().num_blocks, 1);
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    assert_eq!(resolutions(start).1.len(), 1);
+    remove_root(&config.root_path).await;
+}
+
+/// Remove the test directory.
+async fn remove_root(root_path: &str) {
     let db = foundationdb::Database::default().expect("failed opening db for cleanup");
-    let root_dir: Vec<String> = config
-        .root_path
-        .split('/')
-        .map(|i| String::from(i))
-        .collect();
+    let root_dir: Vec<String> = root_path.split('/').map(String::from).collect();
     let tx = db.create_trx().expect("failed creating transaction");
     let d = foundationdb::directory::DirectoryLayer::default();
     d.remove(&tx, &root_dir)
         .await
         .expect("error removing test directory");
     tx.commit().await.expect("failed committing transaction");
+}
 
-    drop(network);
+/// Check that a custom genesis block can be used for regtest, and is validated.
+async fn check_custom_genesis(r_id: u16) {
+    let mut hdr = BlockHeader::get_genesis(BlockchainId::Regtest);
+    hdr.nonce += 1;
+    let genesis = BlockInfo::custom_genesis_info(hdr.clone());
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}genesis", r_id),
+        ..ChainStoreConfig::default()
+    };
+
+    // not allowed for other networks
+    let r =
+        FDBChainStore::new_with_genesis(&config, BlockchainId::Main, Some(genesis.clone())).await;
+    assert!(matches!(r, Err(Error::InvalidGenesis(_))));
+    // hash must match the header
+    let mut bad = genesis.clone();
+    bad.hash = BlockHeader::get_genesis(BlockchainId::Regtest).hash();
+    let r = FDBChainStore::new_with_genesis(&config, BlockchainId::Regtest, Some(bad)).await;
+    assert!(matches!(r, Err(Error::InvalidGenesis(_))));
+
+    let (chain_store, j) =
+        FDBChainStore::new_with_genesis(&config, BlockchainId::Regtest, Some(genesis.clone()))
+            .await
+            .unwrap();
+    let g_block = chain_store.get_block_info(0).await.unwrap().unwrap();
+    assert_eq!(g_block.hash, hdr.hash());
+    let g_block = chain_store
+        .get_block_info_by_hash(hdr.hash())
+        .await
+        .unwrap();
+    assert!(g_block.is_some());
+    chain_store.shutdown().await.expect("failed shutting down");
+    j.await.expect("failed waiting for task to terminate.");
+    // it opens again with the same genesis, but not with the built-in one
+    let (chain_store, j) =
+        FDBChainStore::new_with_genesis(&config, BlockchainId::Regtest, Some(genesis))
+            .await
+            .unwrap();
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    match FDBChainStore::new_if_exists(&config, BlockchainId::Regtest).await {
+        Err(Error::NetworkMismatch { expected, found }) => {
+            assert_eq!(
+                expected,
+                BlockHeader::get_genesis(BlockchainId::Regtest).hash()
+            );
+            assert_eq!(found, hdr.hash());
+        }
+        _ => panic!("expected NetworkMismatch"),
+    }
+
+    remove_root(&config.root_path).await;
+}
+
+/// Check that the layout of an existing store is kept, that it can be migrated to a sharded
+/// layout, and that an unknown layout is rejected.
+async fn check_sharding(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}shards", r_id),
+        info_shards: 0,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert_eq!(chain_store.info_shards().await.unwrap(), 0);
+    // a chain of blocks on top of genesis
+    let mut prev = chain_store.get_block_info(0).await.unwrap().unwrap();
+    for _ in 0..20 {
+        let mut hdr = prev.header.clone();
+        hdr.prev_hash = prev.hash;
+        let info = BlockInfo {
+            id: 0,
+            hash: hdr.hash(),
+            header: hdr,
+            height: 0,
+            prev_id: 0,
+            next_ids: vec![],
+            size: None,
+            num_tx: None,
+            median_time: None,
+            chain_work: None,
+            total_tx: None,
+            total_size: None,
+            total_fees: None,
+            miner: None,
+            validity: BlockValidity::Unknown,
+            received_time: None,
+            source: None,
+        };
+        prev = chain_store.store_block_info(info).await.unwrap();
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    // the configuration does not change the layout of an existing store
+    let sharded = ChainStoreConfig {
+        info_shards: 4,
+        ..config.clone()
+    };
+    let (chain_store, j) = FDBChainStore::new(&sharded, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert_eq!(chain_store.info_shards().await.unwrap(), 0);
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    FDBChainStore::migrate_info_shards(&config, 4)
+        .await
+        .unwrap();
+    let (chain_store, j) = FDBChainStore::new_if_exists(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert_eq!(chain_store.info_shards().await.unwrap(), 4);
+    for id in 0..=prev.id {
+        let b_info = chain_store.get_block_info(id).await.unwrap().unwrap();
+        assert_eq!(b_info.height, id);
+        let by_hash = chain_store
+            .get_block_info_by_hash(b_info.hash)
+            .await
+            .unwrap();
+        assert_eq!(by_hash, Some(b_info));
+    }
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        prev.id
+    );
+    // the scans read every shard
+    assert_eq!(chain_store.summary().await.unwrap().num_blocks, 21);
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+    let forks = chain_store.forks().await.unwrap();
+    assert!(forks.is_empty());
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    // a layout from a newer version of the library
+    let db = foundationdb::Database::default().expect("failed opening db");
+    let root_dir: Vec<String> = config.root_path.split('/').map(String::from).collect();
+    let tx = db.create_trx().expect("failed creating transaction");
+    let d = foundationdb::directory::DirectoryLayer::default();
+    let chain_dir = d.open(&tx, &root_dir, None).await.unwrap();
+    tx.set(
+        &chain_dir.pack(&"layout").unwrap(),
+        &foundationdb::tuple::pack(&(99u64, 4u64)),
+    );
+    tx.commit().await.expect("failed committing transaction");
+    let r = FDBChainStore::new_if_exists(&config, BlockchainId::Main).await;
+    assert!(matches!(r, Err(Error::UnsupportedLayout(99))));
+
+    remove_root(&config.root_path).await;
+}
+
+/// Check that stores with the same root path but different prefixes are kept apart.
+async fn check_prefix(r_id: u16) {
+    let config_a = ChainStoreConfig {
+        enabled: true,
+        prefix: format!("testing{}a", r_id),
+        root_path: String::from("bsvmain"),
+        info_shards: 4,
+        ..ChainStoreConfig::default()
+    };
+    let config_b = ChainStoreConfig {
+        prefix: format!("testing{}b", r_id),
+        ..config_a.clone()
+    };
+    let (store_a, j_a) = FDBChainStore::new(&config_a, BlockchainId::Main)
+        .await
+        .unwrap();
+    let genesis = store_a.get_block_info(0).await.unwrap().unwrap();
+    let child = store_child(&store_a, &genesis, 1, BlockValidity::Valid).await;
+    // b is a new store, it does not see the block stored in a
+    let r = FDBChainStore::new_if_exists(&config_b, BlockchainId::Main).await;
+    assert!(matches!(r, Err(Error::NotInitialized)));
+    let (store_b, j_b) = FDBChainStore::new(&config_b, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert!(store_b
+        .get_block_info_by_hash(child.hash)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(store_b.summary().await.unwrap().num_blocks, 1);
+    assert_eq!(store_a.summary().await.unwrap().num_blocks, 2);
+    for (store, j) in [(store_a, j_a), (store_b, j_b)] {
+        store.shutdown().await.unwrap();
+        j.await.unwrap();
+    }
+
+    remove_root(&config_a.prefix).await;
+    remove_root(&config_b.prefix).await;
+}
+
+/// Check that a flood of reads is rejected rather than queued without bound, and that writes
+/// keep being processed while it lasts.
+async fn check_overload(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}overload", r_id),
+        info_shards: 4,
+        max_reads: 2,
+        max_streams: 1,
+        max_writes: 2,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let readers: Vec<_> = (0..32)
+        .map(|_| {
+            let c = chain_store.clone();
+            tokio::spawn(async move {
+                let mut rejected = 0;
+                for _ in 0..100 {
+                    match c.get_block_info(0).await {
+                        Ok(Some(_)) => {}
+                        Err(Error::Overloaded(OperationClass::Read, _)) => rejected += 1,
+                        r => panic!("unexpected result of a read: {:?}", r.map(|_| ())),
+                    }
+                }
+                rejected
+            })
+        })
+        .collect();
+    // no operation waits for space in the queues, so the writes complete while the reads flood
+    let writes = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+        let mut parent = genesis;
+        for n in 0..20 {
+            parent = store_child(&chain_store, &parent, n, BlockValidity::Valid).await;
+        }
+        parent
+    })
+    .await
+    .expect("writes were held up by the reads");
+    assert_eq!(writes.height, 20);
+    let mut rejected = 0;
+    for r in readers {
+        rejected += tokio::time::timeout(std::time::Duration::from_secs(30), r)
+            .await
+            .expect("a read waited too long")
+            .unwrap();
+    }
+    // operations are counted when their task completes, after the reply has been sent
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let metrics = chain_store.metrics_snapshot();
+    let classes: Vec<(OperationClass, u64, u64, u64)> = metrics
+        .classes
+        .iter()
+        .map(|c| (c.class, c.in_flight, c.limit, c.rejected))
+        .collect();
+    assert_eq!(
+        classes,
+        vec![
+            (OperationClass::Read, 0, 2, rejected),
+            (OperationClass::Stream, 0, 1, 0),
+            (OperationClass::Write, 0, 2, 0),
+        ]
+    );
+    assert_eq!(metrics.queue_depth, 0);
+    assert!(metrics.to_prometheus().contains(&format!(
+        "bsvdb_chainstore_rejected_total{{class=\"read\"}} {}\n",
+        rejected
+    )));
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    remove_root(&config.root_path).await;
+}
+
+/// Check that two branches extending alternately are detected as a split, which is resolved
+/// when one of them pulls away.
+async fn check_split(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}split", r_id),
+        info_shards: 4,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Regtest)
+        .await
+        .unwrap();
+    let mut events = chain_store.subscribe();
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let a1 = store_child(&chain_store, &genesis, 1, BlockValidity::Valid).await;
+    assert_eq!(chain_store.get_split().await.unwrap(), None);
+    let b1 = store_child(&chain_store, &genesis, 2, BlockValidity::Valid).await;
+    match next_event(&mut events).await {
+        ChainEvent::SplitDetected(s) => {
+            let ids: Vec<u64> = s.tips.iter().map(|t| t.id).collect();
+            assert_eq!(ids, vec![a1.id, b1.id]);
+            assert_eq!(s.common_ancestor, genesis.id);
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(chain_store.metrics_snapshot().competing_tips, 2);
+    let split = chain_store.get_split().await.unwrap().unwrap();
+    assert_eq!(split.tips.len(), 2);
+    // the branches take turns, the split remains
+    let a2 = store_child(&chain_store, &a1, 3, BlockValidity::Valid).await;
+    let b2 = store_child(&chain_store, &b1, 4, BlockValidity::Valid).await;
+    let b3 = store_child(&chain_store, &b2, 5, BlockValidity::Valid).await;
+    let mut a = store_child(&chain_store, &a2, 6, BlockValidity::Valid).await;
+    assert!(chain_store.get_split().await.unwrap().is_some());
+    // the a branch pulls away, four blocks ahead of b3
+    for n in 7..=10 {
+        a = store_child(&chain_store, &a, n, BlockValidity::Valid).await;
+    }
+    match next_event(&mut events).await {
+        ChainEvent::SplitResolved(s) => {
+            assert_eq!(s.tips[0].height, 6);
+            assert_eq!(s.tips[1].id, b3.id);
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(a.height, 7);
+    assert_eq!(chain_store.get_split().await.unwrap(), None);
+    assert_eq!(chain_store.metrics_snapshot().competing_tips, 0);
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    remove_root(&config.root_path).await;
+}
+
+/// Check that blocks too far below the most work tip trip the guardrail, unless they are forced or
+/// the ChainStore is in initial sync mode, and that following the chain does not.
+async fn check_guardrail(r_id: u16) {
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: format!("testing{}guardrail", r_id),
+        info_shards: 4,
+        guardrail: String::from("force"),
+        guardrail_max_depth: 2,
+        ..ChainStoreConfig::default()
+    };
+    let (chain_store, j) = FDBChainStore::new(&config, BlockchainId::Regtest)
+        .await
+        .unwrap();
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let mut tip = genesis.clone();
+    for n in 1..=5 {
+        tip = store_child(&chain_store, &tip, n, BlockValidity::Valid).await;
+    }
+    assert_eq!(tip.height, 5);
+    assert_eq!(chain_store.guardrail_stats().depth_trips, 0);
+    // a block at height 1 is three below the tip, it is not stored unless it is forced
+    let deep = child_info(&genesis, 100, BlockValidity::Valid);
+    let r = chain_store.store_block_info(deep.clone()).await;
+    assert!(matches!(r, Err(Error::GuardrailTripped(_))));
+    assert!(chain_store
+        .get_block_info_by_hash(deep.hash)
+        .await
+        .unwrap()
+        .is_none());
+    let forced = chain_store
+        .store_block_info_with_options(deep, InsertOptions { force: true })
+        .await
+        .unwrap();
+    assert_eq!(forced.height, 1);
+    // a branch is checked from its lowest block
+    let mut branch = vec![child_info(&genesis, 200, BlockValidity::Valid)];
+    for n in 201..206 {
+        branch.push(child_info(branch.last().unwrap(), n, BlockValidity::Valid));
+    }
+    let r = chain_store.apply_branch(branch, tip.hash).await;
+    assert!(matches!(r, Err(Error::GuardrailTripped(_))));
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        tip.id
+    );
+    // the depth is not checked in initial sync mode
+    chain_store.set_initial_sync(true);
+    store_child(&chain_store, &genesis, 300, BlockValidity::Valid).await;
+    let stats = chain_store.guardrail_stats();
+    assert!(stats.initial_sync);
+    assert_eq!((stats.rate_trips, stats.depth_trips), (0, 3));
+    assert_eq!(stats.recent_inserts, 7);
+    let metrics = chain_store.metrics_snapshot();
+    assert_eq!(metrics.guardrail, stats);
+    assert!(metrics
+        .to_prometheus()
+        .contains("bsvdb_chainstore_guardrail_trips_total{rule=\"depth\"} 3\n"));
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+
+    remove_root(&config.root_path).await;
+}
+
+/// Wait for the next event, which is raised after the reply to the write has been sent.
+async fn next_event(events: &mut tokio::sync::broadcast::Receiver<ChainEvent>) -> ChainEvent {
+    tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+        .await
+        .expect("no event was raised")
+        .unwrap()
+}
+
+/// Check that a new store is detected as new, and can then be opened as an existing store.
+async fn check_initialized(chain_store: &FDBChainStore, config: &ChainStoreConfig) {
+    assert!(!chain_store.is_initialized().await.unwrap());
+    let (c2, j) = FDBChainStore::new_if_exists(config, BlockchainId::Main)
+        .await
+        .unwrap();
+    assert!(c2.is_initialized().await.unwrap());
+    c2.shutdown().await.unwrap();
+    j.await.unwrap();
+    // a root that doesnt exist
+    let c3 = ChainStoreConfig {
+        root_path: format!("{}missing", config.root_path),
+        ..config.clone()
+    };
+    let r = FDBChainStore::new_if_exists(&c3, BlockchainId::Main).await;
+    assert!(matches!(r, Err(Error::NotInitialized)));
 }
 
 /// Check that we can clone the chainstore into a separate task
@@ -63,8 +881,7 @@ async fn check_multi_spawn(chain_store: &FDBChainStore) {
         let j = tokio::spawn(i);
         v.push(j);
     }
-    while !v.is_empty() {
-        let j = v.pop().unwrap();
+    while let Some(j) = v.pop() {
         let r = j.await;
         assert!(r.is_ok());
         let i = r.unwrap();
@@ -110,8 +927,11 @@ async fn check_store(chain_store: &FDBChainStore) {
         chain_work: None,
         total_tx: None,   // should be updated
         total_size: None, // should be updated
+        total_fees: None,
         miner: None,
         validity: BlockValidity::Valid,
+        received_time: None,
+        source: Some(String::from("rpc")),
     };
     assert_eq!(
         info1.header.hash(),
@@ -129,6 +949,880 @@ async fn check_store(chain_store: &FDBChainStore) {
     assert_eq!(i2.prev_id, 0);
     assert_eq!(i2.total_tx, Some(2));
     assert_eq!(i2.total_size, Some(500));
+    // the chain work is the chain work of genesis plus the work of the block
+    assert_eq!(
+        i2.chain_work,
+        Some(
+            hex::decode("0000000000000000000000000000000000000000000000000000000200020002")
+                .unwrap()
+        )
+    );
     let g2 = chain_store.get_block_info(0).await.unwrap().unwrap();
     assert_eq!(g2.next_ids, vec![1]);
+    // the received time is set when the block is first stored, and kept by an update, as is
+    // the source
+    let received = i2.received_time.expect("received time not set");
+    let mut update = i2.clone();
+    update.received_time = None;
+    update.source = None;
+    let i3 = chain_store.store_block_info(update).await.unwrap();
+    assert_eq!(i3.received_time, Some(received));
+    assert_eq!(i3.source, Some(String::from("rpc")));
+}
+
+/// Check main chain membership for the tip, an ancestor of the tip, and a side-chain block.
+///
+/// Expects check_store() to have been run first.
+async fn check_is_on_main_chain(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    // the tip itself
+    assert!(chain_store
+        .is_on_main_chain(cs.most_work_tip)
+        .await
+        .unwrap());
+    // genesis is an ancestor of every tip
+    assert!(chain_store.is_on_main_chain(0).await.unwrap());
+    // a sibling of block 1 which is not the most work tip
+    let mut hdr = BlockHeader::from_hex("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299").unwrap();
+    hdr.nonce += 1;
+    let info = BlockInfo {
+        id: 0,
+        hash: hdr.hash(),
+        header: hdr,
+        height: 0,
+        prev_id: 0,
+        next_ids: vec![],
+        size: None,
+        num_tx: None,
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity: BlockValidity::Unknown,
+        received_time: None,
+        source: None,
+    };
+    let side = chain_store.store_block_info(info).await.unwrap();
+    assert_ne!(side.id, cs.most_work_tip);
+    assert!(!chain_store.is_on_main_chain(side.id).await.unwrap());
+    // unknown block
+    assert!(!chain_store.is_on_main_chain(u64::MAX).await.unwrap());
+
+    // the next block of genesis is the child on the main chain, not the sibling
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let tip = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(genesis.next_ids.len(), 2);
+    assert_eq!(
+        chain_store.get_neighbors(0).await.unwrap(),
+        (None, Some(tip.hash))
+    );
+    assert_eq!(
+        chain_store.get_neighbors(tip.id).await.unwrap(),
+        (Some(genesis.hash), None)
+    );
+    assert_eq!(
+        chain_store.get_neighbors(side.id).await.unwrap(),
+        (Some(genesis.hash), None)
+    );
+    let r = chain_store.get_neighbors(u64::MAX).await;
+    assert!(matches!(r, Err(Error::BlockInfoNotFound(u64::MAX))));
+
+    // the briefs mark the tip and the sibling, genesis is left to is_on_main_chain()
+    let on_main_chain = |b: Option<BlockBrief<u64>>| b.unwrap().on_main_chain;
+    let brief = |h| chain_store.get_block_brief(h);
+    assert_eq!(on_main_chain(brief(tip.hash).await.unwrap()), Some(true));
+    assert_eq!(on_main_chain(brief(side.hash).await.unwrap()), Some(false));
+    assert_eq!(on_main_chain(brief(genesis.hash).await.unwrap()), None);
+    // more hashes than are looked up in one transaction
+    let hashes: Vec<_> = (0..2_500)
+        .map(|i| [genesis.hash, tip.hash, side.hash][i % 3])
+        .collect();
+    let briefs = chain_store.get_block_briefs(hashes).await.unwrap();
+    assert_eq!(briefs.len(), 2_500);
+    for (i, b) in briefs.into_iter().enumerate() {
+        assert_eq!(b.unwrap().id, [0, tip.id, side.id][i % 3]);
+    }
+}
+
+/// Check that the counters track the blocks stored so far.
+///
+/// Expects check_is_on_main_chain() to have been run first, leaving genesis, block 1 and a
+/// sibling of block 1 with unknown validity.
+async fn check_summary(chain_store: &FDBChainStore) {
+    let summary = chain_store.summary().await.unwrap();
+    assert_eq!(summary.num_blocks, 3);
+    assert_eq!(summary.num_tips, 2);
+    assert_eq!(
+        summary.genesis_hash,
+        BlockHeader::get_genesis(BlockchainId::Main).hash()
+    );
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let tip = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(summary.tip_height, tip.height);
+    for (validity, n) in summary.validity_counts {
+        let expected = match validity {
+            BlockValidity::Valid => 2,
+            BlockValidity::Unknown => 1,
+            _ => 0,
+        };
+        assert_eq!(n, expected, "count for {:?}", validity);
+    }
+    // storing an existing block again with a different validity moves it between the counts
+    let mut info = chain_store.get_block_info(1).await.unwrap().unwrap();
+    info.validity = BlockValidity::ValidHeader;
+    chain_store.store_block_info(info).await.unwrap();
+    let summary = chain_store.summary().await.unwrap();
+    assert_eq!(summary.num_blocks, 3);
+    assert_eq!(summary.num_tips, 2);
+    assert!(summary
+        .validity_counts
+        .contains(&(BlockValidity::ValidHeader, 1)));
+    assert!(summary.validity_counts.contains(&(BlockValidity::Valid, 1)));
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+}
+
+/// Check that a corrupted counter is detected and fixed.
+async fn check_counter_drift(chain_store: &FDBChainStore, root_path: &str) {
+    // corrupt the block count
+    let db = foundationdb::Database::default().expect("failed opening db");
+    let root_dir: Vec<String> = root_path.split('/').map(String::from).collect();
+    let tx = db.create_trx().expect("failed creating transaction");
+    let d = foundationdb::directory::DirectoryLayer::default();
+    let chain_dir = d.open(&tx, &root_dir, None).await.unwrap();
+    let k = chain_dir.pack(&("counts", "blocks")).unwrap();
+    tx.atomic_op(
+        &k,
+        &5i64.to_le_bytes(),
+        foundationdb::options::MutationType::Add,
+    );
+    tx.commit().await.expect("failed committing transaction");
+
+    let drift = chain_store.check_counters(false).await.unwrap();
+    assert_eq!(
+        drift,
+        vec![CounterDrift {
+            name: String::from("blocks"),
+            stored: 8,
+            computed: 3,
+        }]
+    );
+    // fix it
+    let drift = chain_store.check_counters(true).await.unwrap();
+    assert_eq!(drift.len(), 1);
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+    assert_eq!(chain_store.summary().await.unwrap().num_blocks, 3);
+}
+
+/// Check that the actor traces a message as a child of the callers span, and records the timing
+/// of the FDB operations.
+///
+/// Expects check_store() to have been run first.
+async fn check_tracing(chain_store: &FDBChainStore, capture: &CaptureLayer) {
+    let b_info = chain_store.get_block_info(1).await.unwrap().unwrap();
+    let start = capture.spans.lock().unwrap().len();
+    // storing an existing block is an update
+    chain_store.store_block_info(b_info).await.unwrap();
+    let spans = capture.spans.lock().unwrap().clone();
+    let caller = (start..spans.len())
+        .find(|i| spans[*i].name == "store_block_info")
+        .expect("no span for store_block_info()");
+    let actor = (start..spans.len())
+        .find(|i| spans[*i].name == "actor" && spans[*i].parent == Some(caller))
+        .expect("actor span is not a child of the caller span");
+    assert_eq!(spans[actor].field("msg"), Some("StoreBlockInfo"));
+    // the fdb operations are descendants of the actor span and are timed
+    for op in ["get", "commit"] {
+        assert!((start..spans.len()).any(|i| {
+            spans[i].name == "fdb"
+                && spans[i].field("op") == Some(op)
+                && spans[i].field("elapsed_us").is_some()
+                && CapturedSpan::descends_from(&spans, i, actor)
+        }));
+    }
+}
+
+/// Check that the most work tip follows the chain with the most work, and that a reorg is logged.
+///
+/// Expects check_is_on_main_chain() to have been run first, leaving block 1 as the most work tip
+/// and a sibling of block 1.
+async fn check_reorgs(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    assert_eq!(cs.most_work_tip, 1);
+    assert!(chain_store.get_reorg_log(10).await.unwrap().is_empty());
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let sibling = *genesis.next_ids.iter().find(|i| **i != 1).unwrap();
+    let sibling = chain_store.get_block_info(sibling).await.unwrap().unwrap();
+    // extending the sibling gives it more work than block 1
+    let mut hdr = sibling.header.clone();
+    hdr.prev_hash = sibling.hash;
+    let info = BlockInfo {
+        id: 0,
+        hash: hdr.hash(),
+        header: hdr,
+        height: 0,
+        prev_id: 0,
+        next_ids: vec![],
+        size: None,
+        num_tx: None,
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity: BlockValidity::Unknown,
+        received_time: None,
+        source: None,
+    };
+    let child = chain_store.store_block_info(info).await.unwrap();
+    let cs = chain_store.get_chain_state().await.unwrap();
+    assert_eq!(cs.most_work_tip, child.id);
+    let log = chain_store.get_reorg_log(10).await.unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].old_tip, 1);
+    assert_eq!(log[0].new_tip, child.id);
+    assert_eq!(log[0].fork_height, 0);
+    assert_eq!(log[0].orphaned_count, 1);
+    assert!(chain_store.get_reorg_log(0).await.unwrap().is_empty());
+}
+
+/// Check that the chain quality counts the block that was reorged out, and the reorg.
+///
+/// Expects check_reorgs() to have been run first.
+async fn check_quality(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let tip = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    let q = chain_store
+        .chain_quality(Window::Blocks(1000))
+        .await
+        .unwrap();
+    assert_eq!(q.main_blocks, tip.height + 1);
+    assert!(q.stale_blocks >= 1);
+    assert_eq!(q.intervals.count, tip.height);
+    assert_eq!(q.reorgs.len(), 1);
+    assert_eq!(q.reorgs[0].depth, 1);
+    let q = chain_store.chain_quality(Window::Blocks(0)).await.unwrap();
+    assert_eq!((q.main_blocks, q.stale_blocks), (0, 0));
+    assert!(q.reorgs.is_empty());
+}
+
+/// Check that the operations and the tip height are recorded in the metrics.
+///
+/// Expects check_reorgs() to have been run first.
+async fn check_metrics(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let tip = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    // operations are counted when their task completes, after the reply has been sent
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let metrics = chain_store.metrics_snapshot();
+    assert_eq!(metrics.tip_height, tip.height);
+    assert_eq!(metrics.in_flight, 0);
+    let count = |name: &str| {
+        metrics
+            .operations
+            .iter()
+            .find(|op| op.name == name)
+            .map(|op| op.count)
+            .unwrap_or(0)
+    };
+    assert!(count("BlockInfo") > 0);
+    assert!(count("StoreBlockInfo") >= 3);
+    // a clone shares the metrics
+    assert_eq!(chain_store.clone().metrics_snapshot(), metrics);
+    assert!(metrics
+        .to_prometheus()
+        .contains(&format!("bsvdb_chainstore_tip_height {}\n", tip.height)));
+}
+
+/// Check that unreachable BlockInfos are found, and can be relinked or deleted.
+///
+/// Expects the ChainStore to have 4 info shards.
+async fn check_reachability(chain_store: &FDBChainStore, root_path: &str) {
+    let report = chain_store.check_reachability(None).await.unwrap();
+    assert!(report.unreachable.is_empty());
+    assert_eq!(
+        report.reachable,
+        chain_store.summary().await.unwrap().num_blocks
+    );
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    // a child of a side block, unlinked by storing the side block without its next_ids
+    let side = store_child(chain_store, &genesis, 2, BlockValidity::Unknown).await;
+    let orphan = store_child(chain_store, &side, 0, BlockValidity::Unknown).await;
+    let mut unlinked = chain_store.get_block_info(side.id).await.unwrap().unwrap();
+    unlinked.next_ids.clear();
+    chain_store.store_block_info(unlinked).await.unwrap();
+    // a child of a side block, whose parent record is deleted
+    let lost = store_child(chain_store, &genesis, 3, BlockValidity::Unknown).await;
+    let dangling = store_child(chain_store, &lost, 0, BlockValidity::Unknown).await;
+    clear_block_info(root_path, lost.id, 4).await;
+    // walking down from the dangling block fails at the missing parent, instead of ending quietly
+    let walk: Vec<_> = chain_store
+        .get_block_infos(dangling.id, None)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(walk.len(), 2);
+    assert_eq!(walk[0].as_ref().unwrap().id, dangling.id);
+    assert!(matches!(walk[1], Err(Error::BlockInfoNotFound(id)) if id == lost.id));
+    // an unknown block is an empty stream
+    let mut walk = chain_store.get_block_infos(u64::MAX, None).await.unwrap();
+    assert!(walk.next().await.is_none());
+
+    let report = chain_store.check_reachability(None).await.unwrap();
+    assert_eq!(report.unreachable.len(), 2);
+    let u = &report.unreachable[0];
+    assert_eq!(
+        (u.id, u.hash, u.height),
+        (orphan.id, orphan.hash, orphan.height)
+    );
+    assert!(!u.prev_missing);
+    assert_eq!(u.parent, Some(side.id));
+    let u = &report.unreachable[1];
+    assert_eq!(u.id, dangling.id);
+    assert!(u.prev_missing);
+    assert_eq!(u.parent, None);
+
+    // relinking fixes the first only
+    let report = chain_store
+        .check_reachability(Some(ReachabilityFix::Relink))
+        .await
+        .unwrap();
+    assert_eq!((report.relinked, report.deleted), (1, 0));
+    let side = chain_store.get_block_info(side.id).await.unwrap().unwrap();
+    assert_eq!(side.next_ids, vec![orphan.id]);
+    let report = chain_store.check_reachability(None).await.unwrap();
+    assert_eq!(report.unreachable.len(), 1);
+    assert_eq!(report.unreachable[0].id, dangling.id);
+
+    // deleting removes the second
+    let report = chain_store
+        .check_reachability(Some(ReachabilityFix::Delete))
+        .await
+        .unwrap();
+    assert_eq!((report.relinked, report.deleted), (0, 1));
+    assert!(chain_store
+        .get_block_info(dangling.id)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(chain_store
+        .get_block_info_by_hash(dangling.hash)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(chain_store
+        .check_reachability(None)
+        .await
+        .unwrap()
+        .unreachable
+        .is_empty());
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+}
+
+/// Check that the main chain is streamed from genesis to the most work tip, skipping the forks
+/// created by the earlier checks.
+async fn check_main_chain(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let main_chain: Vec<_> = chain_store
+        .stream_main_chain()
+        .map(|r| r.unwrap())
+        .collect()
+        .await;
+    let mut expected: Vec<_> = chain_store
+        .get_block_infos(cs.most_work_tip, None)
+        .await
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect()
+        .await;
+    expected.reverse();
+    assert!(main_chain.len() > 1);
+    assert_eq!(main_chain, expected);
+    assert_eq!(main_chain[0].height, 0);
+    assert_eq!(main_chain.last().unwrap().id, cs.most_work_tip);
+    for (h, b) in main_chain.iter().enumerate() {
+        assert_eq!(b.height, h as u64);
+    }
+}
+
+/// Check that the blocks listed for each validity match the counters.
+async fn check_list_by_validity(chain_store: &FDBChainStore) {
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let invalid = store_child(chain_store, &genesis, 4, BlockValidity::Invalid).await;
+    assert_eq!(invalid.validity, BlockValidity::Invalid);
+    let summary = chain_store.summary().await.unwrap();
+    for (validity, n) in summary.validity_counts {
+        let blocks: Vec<_> = chain_store
+            .list_blocks_by_validity(validity.clone())
+            .collect()
+            .await;
+        assert_eq!(blocks.len() as u64, n, "count for {:?}", validity);
+        for b in blocks {
+            assert_eq!(b.unwrap().validity, validity);
+        }
+    }
+    let mut s = chain_store.list_blocks_by_validity(BlockValidity::Invalid);
+    assert_eq!(s.next().await.unwrap().unwrap().id, invalid.id);
+    assert!(s.next().await.is_none());
+}
+
+/// Check that a missing child and a child that does not exist are repaired in the next_ids.
+async fn check_repair_links(chain_store: &FDBChainStore) {
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    let parent = store_child(chain_store, &genesis, 5, BlockValidity::Unknown).await;
+    let child = store_child(chain_store, &parent, 0, BlockValidity::Unknown).await;
+    // replace the child with an id that does not exist
+    let mut broken = chain_store
+        .get_block_info(parent.id)
+        .await
+        .unwrap()
+        .unwrap();
+    broken.next_ids = vec![u64::MAX];
+    chain_store.store_block_info(broken).await.unwrap();
+
+    // checking finds the repairs without making them
+    let planned = chain_store.check_links().await.unwrap();
+    assert_eq!(chain_store.check_links().await.unwrap(), planned);
+    let repairs = chain_store.repair_links().await.unwrap();
+    assert_eq!(repairs, planned);
+    assert!(repairs.contains(&LinkRepair::RemovedChild {
+        parent: parent.id,
+        child: u64::MAX
+    }));
+    assert!(repairs.contains(&LinkRepair::AddedChild {
+        parent: parent.id,
+        child: child.id
+    }));
+    let parent = chain_store
+        .get_block_info(parent.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(parent.next_ids, vec![child.id]);
+    assert!(chain_store.repair_links().await.unwrap().is_empty());
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+}
+
+/// Check that a snapshot around the fork at genesis contains the blocks within the radius on
+/// every branch, and that it can be decoded.
+///
+/// Expects check_reorgs() to have been run first.
+async fn check_snapshot(chain_store: &FDBChainStore) {
+    let genesis = chain_store.get_block_info(0).await.unwrap().unwrap();
+    assert!(genesis.next_ids.len() > 1);
+    let s = chain_store.snapshot(genesis.hash, 1).await.unwrap();
+    let mut expected = genesis.next_ids.clone();
+    expected.push(0);
+    expected.sort();
+    let ids: Vec<u64> = s.block_infos.iter().map(|b| b.id).collect();
+    assert_eq!(ids, expected);
+    assert_eq!(s.center, genesis.hash);
+    assert_eq!(s.info_shards, 4);
+    assert_eq!(s.chain_state, chain_store.get_chain_state().await.unwrap());
+    assert_eq!(s.summary, chain_store.summary().await.unwrap());
+    assert_eq!(Snapshot::decode(&s.encode()).unwrap(), s);
+    // every block within two blocks of genesis is a descendant with a height of at most 2
+    let s = chain_store.snapshot(genesis.hash, 2).await.unwrap();
+    assert!(s.block_infos.len() > expected.len());
+    assert!(s.block_infos.iter().all(|b| b.height <= 2));
+    let tip = chain_store
+        .get_block_info(s.chain_state.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(s.block_infos.contains(&tip), tip.height <= 2);
+    let unknown = BlockHash::from(&[7u8; 32][..]);
+    let r = chain_store.snapshot(unknown, 1).await;
+    assert!(matches!(r, Err(Error::BlockNotFound(h)) if h == unknown));
+}
+
+/// Check that the stored bytes of a BlockInfo can be read without decoding them.
+async fn check_raw_block_info(chain_store: &FDBChainStore, root_path: &str) {
+    let raw = chain_store.get_raw_block_info(1).await.unwrap().unwrap();
+    // read the record directly
+    let db = foundationdb::Database::default().expect("failed opening db");
+    let root_dir: Vec<String> = root_path.split('/').map(String::from).collect();
+    let tx = db.create_trx().expect("failed creating transaction");
+    let d = foundationdb::directory::DirectoryLayer::default();
+    let chain_dir = d.open(&tx, &root_dir, None).await.unwrap();
+    let infos_dir = chain_dir
+        .open(&tx, &[String::from("infos")], None)
+        .await
+        .unwrap();
+    let v = tx
+        .get(&infos_dir.pack(&(1u64 % 4, 1u64)).unwrap(), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(raw, v.to_vec());
+    // a validity written by a newer version is an error rather than being read as Unknown
+    let k = infos_dir.pack(&(1u64 % 4, 1u64)).unwrap();
+    let mut fields = unpack::<Vec<Element>>(&raw).unwrap();
+    fields[13] = Element::Int(6);
+    tx.set(&k, &pack(&fields));
+    tx.commit().await.expect("failed committing transaction");
+    let r = chain_store.get_block_info(1).await;
+    assert!(matches!(
+        r,
+        Err(Error::UnknownEnumVariant("validity", 6, _))
+    ));
+    let tx = db.create_trx().expect("failed creating transaction");
+    tx.set(&k, &raw);
+    tx.commit().await.expect("failed committing transaction");
+    assert!(chain_store.get_block_info(1).await.unwrap().is_some());
+    assert!(chain_store
+        .get_raw_block_info(u64::MAX)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+/// Check that a branch is applied atomically and only if the tip is the expected tip.
+async fn check_apply_branch(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let fork = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    // extend the main chain by three blocks
+    let mut main = vec![];
+    let mut parent = fork.clone();
+    for _ in 0..3 {
+        parent = store_child(chain_store, &parent, 1, BlockValidity::Valid).await;
+        main.push(parent.clone());
+    }
+    let old_tip = main.last().unwrap().clone();
+    assert_eq!(
+        chain_store.get_chain_state().await.unwrap().most_work_tip,
+        old_tip.id
+    );
+    // a competing branch of five blocks from the same fork point
+    let mut branch = vec![];
+    let mut parent = fork.clone();
+    for _ in 0..5 {
+        parent = child_info(&parent, 100, BlockValidity::Valid);
+        branch.push(parent.clone());
+    }
+    let new_tip_hash = branch.last().unwrap().hash;
+
+    // nothing is changed if the tip is not the expected tip
+    let r = chain_store
+        .apply_branch(branch.clone(), fork.hash)
+        .await
+        .unwrap();
+    assert_eq!(
+        r,
+        BranchOutcome::Outdated {
+            current_tip: old_tip.hash
+        }
+    );
+    for b in branch.iter() {
+        assert!(chain_store
+            .get_block_info_by_hash(b.hash)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    // the blocks must form a branch
+    let r = chain_store.apply_branch(vec![], old_tip.hash).await;
+    assert!(matches!(r, Err(Error::InvalidBranch(_))));
+    let gapped = vec![branch[0].clone(), branch[2].clone()];
+    let r = chain_store.apply_branch(gapped, old_tip.hash).await;
+    assert!(matches!(r, Err(Error::InvalidBranch(_))));
+    // a branch with less work than the tip is rejected
+    let r = chain_store
+        .apply_branch(branch[..2].to_vec(), old_tip.hash)
+        .await;
+    assert!(matches!(r, Err(Error::InvalidBranch(_))));
+    assert!(chain_store
+        .get_block_info_by_hash(branch[0].hash)
+        .await
+        .unwrap()
+        .is_none());
+
+    // readers only ever see the complete old chain or the complete new chain
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let chain_store = chain_store.clone();
+        let done = done.clone();
+        let old_tip_hash = old_tip.hash;
+        tokio::spawn(async move {
+            while !done.load(Ordering::SeqCst) {
+                let cs = chain_store.get_chain_state().await.unwrap();
+                let walk: Vec<_> = chain_store
+                    .get_block_infos(cs.most_work_tip, None)
+                    .await
+                    .unwrap()
+                    .map(|r| r.unwrap())
+                    .collect()
+                    .await;
+                let tip = &walk[0];
+                assert!(tip.hash == old_tip_hash || tip.hash == new_tip_hash);
+                assert_eq!(walk.len() as u64, tip.height + 1);
+            }
+        })
+    };
+    let log_len = chain_store.get_reorg_log(1000).await.unwrap().len();
+    let r = chain_store
+        .apply_branch(branch.clone(), old_tip.hash)
+        .await
+        .unwrap();
+    done.store(true, Ordering::SeqCst);
+    reader.await.unwrap();
+    let BranchOutcome::Applied {
+        fork_point,
+        displaced,
+        blocks,
+    } = r
+    else {
+        panic!("branch was not applied");
+    };
+    assert_eq!(fork_point, fork.id);
+    assert_eq!(displaced, main.iter().map(|b| b.id).collect::<Vec<_>>());
+    assert_eq!(blocks.len(), 5);
+    for (i, b) in blocks.iter().enumerate() {
+        assert_eq!(b.hash, branch[i].hash);
+        assert_eq!(b.height, fork.height + 1 + i as u64);
+    }
+    let new_tip = blocks.last().unwrap();
+    let cs = chain_store.get_chain_state().await.unwrap();
+    assert_eq!(cs.most_work_tip, new_tip.id);
+    // the switch is recorded as a single reorg
+    let log = chain_store.get_reorg_log(1000).await.unwrap();
+    assert_eq!(log.len(), log_len + 1);
+    assert_eq!(log[0].old_tip, old_tip.id);
+    assert_eq!(log[0].new_tip, new_tip.id);
+    assert_eq!(log[0].orphaned_count, 3);
+}
+
+/// Check that the linkage of a block can be checked without storing it.
+async fn check_would_link(chain_store: &FDBChainStore) {
+    let cs = chain_store.get_chain_state().await.unwrap();
+    let tip = chain_store
+        .get_block_info(cs.most_work_tip)
+        .await
+        .unwrap()
+        .unwrap();
+    let summary = chain_store.summary().await.unwrap();
+    let info = child_info(&tip, 1, BlockValidity::Valid);
+    let r = chain_store.check_would_link(&info).await.unwrap();
+    assert_eq!(
+        r,
+        LinkCheck::Links {
+            height: tip.height + 1,
+            prev_id: tip.id,
+            validity: tip.validity.clone(),
+            existing_id: None,
+        }
+    );
+    // nothing was written
+    assert_eq!(chain_store.summary().await.unwrap(), summary);
+    assert!(chain_store
+        .get_block_info_by_hash(info.hash)
+        .await
+        .unwrap()
+        .is_none());
+    // a block that is already stored would be updated
+    let stored = chain_store.store_block_info(info.clone()).await.unwrap();
+    let r = chain_store.check_would_link(&info).await.unwrap();
+    assert!(matches!(r, LinkCheck::Links { existing_id: Some(id), .. } if id == stored.id));
+    // an orphan is reported rather than failing
+    let orphan = child_info(
+        &child_info(&stored, 1, BlockValidity::Valid),
+        1,
+        BlockValidity::Valid,
+    );
+    let r = chain_store.check_would_link(&orphan).await.unwrap();
+    assert_eq!(r, LinkCheck::ParentNotFound);
+}
+
+/// Store a child of the parent, with the nonce of the parent increased by n so that siblings
+/// have different hashes.
+async fn store_child(
+    chain_store: &FDBChainStore,
+    parent: &BlockInfo<u64>,
+    n: u32,
+    validity: BlockValidity,
+) -> BlockInfo<u64> {
+    let info = child_info(parent, n, validity);
+    chain_store.store_block_info(info).await.unwrap()
+}
+
+/// Create the BlockInfo of a child of the parent without storing it, see store_child().
+fn child_info(parent: &BlockInfo<u64>, n: u32, validity: BlockValidity) -> BlockInfo<u64> {
+    let mut hdr = parent.header.clone();
+    hdr.prev_hash = parent.hash;
+    hdr.nonce = hdr.nonce.wrapping_add(n);
+    BlockInfo {
+        id: 0,
+        hash: hdr.hash(),
+        header: hdr,
+        height: 0,
+        prev_id: 0,
+        next_ids: vec![],
+        size: None,
+        num_tx: None,
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity,
+        received_time: None,
+        source: None,
+    }
+}
+
+/// Delete a BlockInfo record directly, leaving any references to it.
+async fn clear_block_info(root_path: &str, id: u64, shards: u64) {
+    let db = foundationdb::Database::default().expect("failed opening db");
+    let root_dir: Vec<String> = root_path.split('/').map(String::from).collect();
+    let tx = db.create_trx().expect("failed creating transaction");
+    let d = foundationdb::directory::DirectoryLayer::default();
+    let chain_dir = d.open(&tx, &root_dir, None).await.unwrap();
+    let infos_dir = chain_dir
+        .open(&tx, &[String::from("infos")], None)
+        .await
+        .unwrap();
+    tx.clear(&infos_dir.pack(&(id % shards, id)).unwrap());
+    tx.commit().await.expect("failed committing transaction");
+}
+
+/// Check that a read-only handle can read but not write.
+///
+/// Expects check_store() to have been run first.
+async fn check_read_only(config: &ChainStoreConfig) {
+    let (chain_store, j) = FDBChainStore::new_readonly(config, BlockchainId::Main)
+        .await
+        .unwrap();
+    let b_info = chain_store.get_block_info(1).await.unwrap().unwrap();
+    assert!(chain_store.summary().await.unwrap().num_blocks > 1);
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+    let r = chain_store.store_block_info(b_info).await;
+    assert!(matches!(r, Err(Error::ReadOnly)));
+    let r = chain_store.check_counters(true).await;
+    assert!(matches!(r, Err(Error::ReadOnly)));
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    // a read-only handle does not create a ChainStore
+    let missing = ChainStoreConfig {
+        root_path: format!("{}missing", config.root_path),
+        ..config.clone()
+    };
+    let r = FDBChainStore::new_readonly(&missing, BlockchainId::Main).await;
+    assert!(matches!(r, Err(Error::NotInitialized)));
+    // nor does it open the ChainStore of another network
+    match FDBChainStore::new_readonly(config, BlockchainId::Test).await {
+        Err(Error::NetworkMismatch { expected, found }) => {
+            assert_eq!(
+                expected,
+                BlockHeader::get_genesis(BlockchainId::Test).hash()
+            );
+            assert_eq!(found, BlockHeader::get_genesis(BlockchainId::Main).hash());
+        }
+        _ => panic!("expected NetworkMismatch"),
+    }
+    let r = FDBChainStore::new(config, BlockchainId::Regtest).await;
+    assert!(matches!(r, Err(Error::NetworkMismatch { .. })));
+}
+
+/// A span captured by the CaptureLayer.
+#[derive(Clone, Debug)]
+struct CapturedSpan {
+    id: u64,
+    name: &'static str,
+    // index of the parent in the captured spans
+    parent: Option<usize>,
+    fields: Vec<(String, String)>,
+}
+
+impl CapturedSpan {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // check whether the span at index is a descendant of the span at ancestor
+    fn descends_from(spans: &[CapturedSpan], index: usize, ancestor: usize) -> bool {
+        let mut i = spans[index].parent;
+        while let Some(p) = i {
+            if p == ancestor {
+                return true;
+            }
+            i = spans[p].parent;
+        }
+        false
+    }
+}
+
+/// A tracing layer that captures the spans and their fields.
+#[derive(Clone, Default)]
+struct CaptureLayer {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+}
+
+struct FieldVisitor<'a>(&'a mut Vec<(String, String)>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = vec![];
+        attrs.record(&mut FieldVisitor(&mut fields));
+        let parent_id = ctx
+            .span(id)
+            .and_then(|s| s.parent())
+            .map(|p| p.id().into_u64());
+        let mut spans = self.spans.lock().unwrap();
+        // span ids are re-used, the parent is the most recent span with the id
+        let parent = parent_id.and_then(|p| spans.iter().rposition(|s| s.id == p));
+        spans.push(CapturedSpan {
+            id: id.into_u64(),
+            name: attrs.metadata().name(),
+            parent,
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        let mut spans = self.spans.lock().unwrap();
+        if let Some(s) = spans.iter_mut().rev().find(|s| s.id == id.into_u64()) {
+            values.record(&mut FieldVisitor(&mut s.fields));
+        }
+    }
 }
@@ -0,0 +1,10 @@
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_chainstore::test_util::{check_chain_store, MemoryChainStore};
+
+// The shared checks pass for a ChainStore keyed by the block hash.
+#[tokio::test]
+async fn hash_keyed_store() {
+    for blockchain in [BlockchainId::Main, BlockchainId::Regtest] {
+        check_chain_store(&MemoryChainStore::new(blockchain), blockchain).await;
+    }
+}
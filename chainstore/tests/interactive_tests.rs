@@ -0,0 +1,45 @@
+use bitcoinsv::bitcoin::BlockchainId;
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::{Error, FDBChainStore};
+use std::time::{Duration, Instant};
+
+// The tests point the client at a cluster that can not be reached, so they run in their own
+// process, the cluster file can not be changed once the network is started.
+#[tokio::test]
+async fn run_unreachable_tests() {
+    let cluster_file =
+        std::env::temp_dir().join(format!("bsvdb_unreachable_{}.cluster", std::process::id()));
+    // nothing listens on port 1
+    std::fs::write(&cluster_file, "bsvdbtest:unreachable@127.0.0.1:1\n").unwrap();
+    std::env::set_var("FDB_CLUSTER_FILE", &cluster_file);
+    let network = unsafe { bsvdb_chainstore::boot(&ChainStoreConfig::default()) }.unwrap();
+    let config = ChainStoreConfig {
+        enabled: true,
+        root_path: String::from("unreachable"),
+        interactive_timeout_ms: 500,
+        ..ChainStoreConfig::default()
+    };
+
+    // an interactive handle gives up after the timeout, rather than waiting for the cluster
+    let start = Instant::now();
+    match FDBChainStore::new_interactive(&config, BlockchainId::Main).await {
+        Err(Error::BackendSlow(op, waited)) => {
+            assert_eq!(op, "open");
+            assert_eq!(waited, Duration::from_millis(500));
+        }
+        Err(e) => panic!("expected BackendSlow, got {}", e),
+        Ok(_) => panic!("expected BackendSlow, the cluster is not reachable"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // the other handles keep waiting, as before
+    let r = tokio::time::timeout(
+        Duration::from_secs(2),
+        FDBChainStore::new_readonly(&config, BlockchainId::Main),
+    )
+    .await;
+    assert!(r.is_err(), "expected a background handle to keep waiting");
+
+    drop(network);
+    std::fs::remove_file(&cluster_file).unwrap();
+}
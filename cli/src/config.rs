@@ -0,0 +1,41 @@
+use bsvdb_base::{BSVDBConfig, BsvDbBaseError};
+
+/// Load the configuration and report every problem found, returns the exit code.
+pub fn config_validate(config_path: Option<String>) -> i32 {
+    match BSVDBConfig::load(config_path) {
+        Ok(config) => match config.validate() {
+            Ok(()) => {
+                println!("configuration is valid");
+                0
+            }
+            Err(violations) => {
+                println!("{}", BsvDbBaseError::InvalidConfig(violations));
+                1
+            }
+        },
+        // the file could not be read or parsed, or has unknown keys
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_config_validate() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("bsvdb.toml");
+        let path_str = String::from(path.to_str().unwrap());
+        std::fs::write(&path, "[import]\nmax_parallel = 2\n").unwrap();
+        assert_eq!(config_validate(Some(path_str.clone())), 0);
+        std::fs::write(&path, "[import]\nmax_parallel = 0\n").unwrap();
+        assert_eq!(config_validate(Some(path_str.clone())), 1);
+        std::fs::write(&path, "[import]\nmax_paralel = 2\n").unwrap();
+        assert_eq!(config_validate(Some(path_str)), 1);
+    }
+}
@@ -1,27 +1,618 @@
-use bitcoinsv::bitcoin::BlockHash;
+use bitcoinsv::bitcoin::{BlockHash, FullBlockStream};
 use tokio_stream::StreamExt;
-use bsvdb_base::BSVDBConfig;
-use bsvdb_chainstore::{ChainStore, FDBChainStore};
+use bsvdb_base::{BSVDBConfig, ChainStoreConfig};
+use bsvdb_blockarchive::{compute_merkle_root, BlockArchive, Error as BlockArchiveError, Finding, FindingCode, Reporter, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainQuality, ChainStore, Error, FDBChainStore, Filter, Fork, LinkRepair, ReachabilityFix, Snapshot, Window};
+use tokio::io::AsyncReadExt;
+use std::collections::HashSet;
+use crate::dryrun::Effects;
+use crate::result::{CliError, CliResult};
+use crate::status::print_versions;
 
-pub async fn get_block_info(config: &BSVDBConfig, block_hash: BlockHash) {
-    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
-    match chain_store.get_block_info_by_hash(block_hash).await.unwrap() {
+// The interactive commands open the chain store with the interactive profile, so that they fail
+// with Error::BackendSlow rather than hanging when the cluster is degraded.
+pub async fn get_block_info(config: &BSVDBConfig, block_hash: BlockHash) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new_interactive(&config.chain_store, config.get_blockchain_id()).await?;
+    match chain_store.get_block_info_by_hash(block_hash).await? {
         None => println!("block not found"),
         Some(b_info) => {
             println!("{:?}", b_info);
+            if let Some(t) = b_info.received_time {
+                println!("received {}s after the header timestamp", t as i64 - b_info.header.timestamp as i64);
+            }
+            println!("source: {}", b_info.source.as_deref().unwrap_or("unknown"));
+            let (prev, next) = chain_store.get_neighbors(b_info.id).await?;
+            println!("prev: {}", prev.map_or(String::from("none"), |h| h.to_string()));
+            println!("next: {}", next.map_or(String::from("none"), |h| h.to_string()));
         }
     }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+// Find a block by its hash or by its height on the main chain, so that the commands do not
+// depend on the ids of the chain store.
+async fn find_block(chain_store: &FDBChainStore, block: &str) -> CliResult<Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>> {
+    if let Ok(h) = block.parse::<BlockHash>() {
+        return Ok(chain_store.get_block_info_by_hash(h).await?);
+    }
+    let height: u64 = block.parse().map_err(|_| CliError::Select(format!("expected a block hash or a height, not '{}'", block)))?;
+    let state = chain_store.get_chain_state().await?;
+    let tip = chain_store.get_block_info(state.most_work_tip).await?.ok_or(Error::BlockInfoNotFound(state.most_work_tip))?;
+    if height > tip.height {
+        return Ok(None);
+    }
+    let stream = chain_store.get_block_infos(tip.id, Some(tip.height - height + 1)).await?;
+    Ok(stream.filter(|r| !matches!(r, Ok(b) if b.height != height)).next().await.transpose()?)
+}
+
+pub async fn cs_list_blocks(config: &BSVDBConfig, block: &str, filter: Option<Filter>) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    match find_block(&chain_store, block).await? {
+        None => println!("block not found"),
+        Some(start) => {
+            let mut stream = chain_store.get_block_infos(start.id, None).await?;
+            while let Some(b_i) = stream.next().await {
+                let b_i = b_i?;
+                if filter.as_ref().map_or(true, |f| f.matches(&b_i)) {
+                    println!("{:?}", b_i);
+                }
+            }
+        }
+    }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+pub async fn cs_state(config: &BSVDBConfig) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new_interactive(&config.chain_store, config.get_blockchain_id()).await?;
+    let state = chain_store.get_chain_state().await?;
+    if let Some(split) = chain_store.get_split().await? {
+        println!("*** SPLIT: {} tips are competing, forked at height {} ***", split.tips.len(), split.ancestor_height);
+        for tip in split.tips.iter() {
+            println!("    {} height {} work gap {:e}", tip.hash, tip.height, tip.work_gap);
+        }
+    }
+    println!("{:?}", state);
+    let summary = chain_store.summary().await?;
+    println!("genesis: {}", summary.genesis_hash);
+    println!("tip height: {}", summary.tip_height);
+    println!("blocks: {}", summary.num_blocks);
+    println!("tips: {}", summary.num_tips);
+    println!("info shards: {}", chain_store.info_shards().await?);
+    for (validity, n) in summary.validity_counts {
+        println!("{:?}: {}", validity, n);
+    }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+// Print the versions that have written to the chain store, and its layout version.
+pub async fn cs_versions(config: &BSVDBConfig) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new_interactive(&config.chain_store, config.get_blockchain_id()).await?;
+    let history = chain_store.versions().await?;
+    let layout = chain_store.layout_version().await?;
+    chain_store.shutdown().await?;
+    j.await?;
+    println!("layout version: {} (this version knows {})", layout, FDBChainStore::LAYOUT_VERSION);
+    print_versions(&history);
+    Ok(())
+}
+
+// Audit the chain work of the main chain, listing the blocks whose chain work does not follow
+// from their parent, returning true if there are none.
+pub async fn cs_audit_work(config: &BSVDBConfig) -> CliResult<bool> {
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+    let mismatched = chain_store.audit_chain_work().await?;
+    for id in mismatched.iter() {
+        match chain_store.get_block_info(*id).await? {
+            Some(b) => println!("{} height {} chain work does not follow from its parent", b.hash, b.height),
+            None => println!("block id {} chain work does not follow from its parent", id),
+        }
+    }
+    println!("{} blocks with a wrong chain work", mismatched.len());
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(mismatched.is_empty())
+}
+
+// Compare the main chains of the chain store and the one at the other root path, returning true
+// if they are identical up to the shorter tip.
+pub async fn cs_compare(config: &BSVDBConfig, other_root: &str) -> bool {
+    let other_config = ChainStoreConfig { root_path: String::from(other_root), ..config.chain_store.clone() };
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let (other, k) = FDBChainStore::new_readonly(&other_config, config.get_blockchain_id()).await.unwrap();
+    let root = config.get_chain_store_root_path();
+    // walk both main chains from genesis, in step
+    let mut ours = chain_store.stream_main_chain();
+    let mut theirs = other.stream_main_chain();
+    let mut compared = 0u64;
+    let mut divergence = None;
+    while let (Some(a), Some(b)) = (ours.next().await, theirs.next().await) {
+        let (a, b) = (a.unwrap(), b.unwrap());
+        if a.hash != b.hash {
+            divergence = Some((a, b));
+            break;
+        }
+        compared += 1;
+    }
+    drop(ours);
+    drop(theirs);
+    let tip = chain_store.summary().await.unwrap().tip_height;
+    let other_tip = other.summary().await.unwrap().tip_height;
+    println!("compared {} blocks", compared);
+    println!("{}: tip at height {}", root, tip);
+    println!("{}: tip at height {}", other_root, other_tip);
+    match divergence.as_ref() {
+        Some((a, b)) => {
+            println!("DIVERGED at height {}", a.height);
+            println!("{}: {}", root, a.hash);
+            println!("{}: {}", other_root, b.hash);
+            // whether each store has the block that the other has on its main chain
+            for (c, c_root, hash) in [(&chain_store, root.as_str(), b.hash), (&other, other_root, a.hash)] {
+                match c.get_block_brief(hash).await.unwrap() {
+                    Some(brief) => println!("{}: has {} at height {}, {:?}", c_root, hash, brief.height, brief.validity),
+                    None => println!("{}: does not have {}", c_root, hash),
+                }
+            }
+        }
+        None => println!("OK: identical up to height {}", tip.min(other_tip)),
+    }
+    for (c, j) in [(chain_store, j), (other, k)] {
+        c.shutdown().await.unwrap();
+        j.await.unwrap();
+    }
+    divergence.is_none()
+}
+
+pub async fn cs_check_counters<W: std::io::Write>(config: &BSVDBConfig, fix: bool, reporter: &mut Reporter<W>, effects: &mut dyn Effects) {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let drift = if fix {
+        effects.fix_counters(&chain_store).await.unwrap()
+    } else {
+        chain_store.check_counters(false).await.unwrap()
+    };
+    if drift.is_empty() {
+        reporter.note("OK: counters are consistent").unwrap();
+    }
+    for d in drift.iter() {
+        reporter.report(Finding::new(FindingCode::CounterDrift, &d.name).with_detail("stored", d.stored).with_detail("computed", d.computed)).unwrap();
+    }
+    if fix && !drift.is_empty() && effects.plan().is_none() {
+        reporter.note(&format!("reset {} counters", drift.len())).unwrap();
+    }
     chain_store.shutdown().await.unwrap();
     j.await.unwrap();
 }
 
-pub async fn cs_list_blocks(config: &BSVDBConfig, block_id: u64) {
+pub async fn cs_check_reachability<W: std::io::Write>(config: &BSVDBConfig, fix: Option<ReachabilityFix>, reporter: &mut Reporter<W>, effects: &mut dyn Effects) {
     let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
-    let mut stream = chain_store.get_block_infos(block_id, None).await.unwrap();
-    while let Some(b_i) = stream.next().await {
-        println!("{:?}", b_i);
+    let report = match fix {
+        Some(f) => effects.fix_reachability(&chain_store, f).await.unwrap(),
+        None => chain_store.check_reachability(None).await.unwrap(),
+    };
+    reporter.note(&format!("reachable: {}", report.reachable)).unwrap();
+    if report.unreachable.is_empty() {
+        reporter.note("OK: all blocks are reachable from genesis").unwrap();
+    }
+    for u in report.unreachable.iter() {
+        let mut f = Finding::new(FindingCode::UnreachableBlock, u.id).with_detail("hash", u.hash).with_detail("height", u.height);
+        f = f.with_detail("prev_id", u.prev_id).with_detail("prev_missing", u.prev_missing);
+        if let Some(p) = u.parent {
+            f = f.with_detail("parent_by_hash", p);
+        }
+        reporter.report(f).unwrap();
+    }
+    match fix {
+        _ if effects.plan().is_some() => {}
+        Some(ReachabilityFix::Delete) => reporter.note(&format!("deleted {} blocks", report.deleted)).unwrap(),
+        Some(ReachabilityFix::Relink) => reporter.note(&format!("relinked {} blocks", report.relinked)).unwrap(),
+        None => {}
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_list_by_validity(config: &BSVDBConfig, validity: Vec<BlockValidity>, filter: Option<Filter>) {
+    let validity = if validity.is_empty() {
+        vec![BlockValidity::Invalid, BlockValidity::HeaderInvalid, BlockValidity::InvalidAncestor]
+    } else {
+        validity
+    };
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    println!("{:>10}  {:>8}  {:<64}  validity", "id", "height", "hash");
+    for v in validity {
+        let mut blocks = chain_store.list_blocks_by_validity(v);
+        while let Some(b) = blocks.next().await {
+            let b = b.unwrap();
+            if filter.as_ref().map_or(true, |f| f.matches(&b)) {
+                println!("{:>10}  {:>8}  {:<64}  {:?}", b.id, b.height, b.hash, b.validity);
+            }
+        }
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_repair_links(config: &BSVDBConfig, effects: &mut dyn Effects) {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let repairs = effects.repair_links(&chain_store).await.unwrap();
+    if repairs.is_empty() {
+        println!("OK: all links are consistent");
+    }
+    for r in repairs.iter() {
+        match r {
+            LinkRepair::AddedChild { parent, child } => println!("added child {} to the next_ids of block {}", child, parent),
+            LinkRepair::RemovedChild { parent, child } => println!("removed missing child {} from the next_ids of block {}", child, parent),
+        }
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_raw(config: &BSVDBConfig, block: &str) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    let raw = match find_block(&chain_store, block).await? {
+        None => None,
+        Some(b_info) => chain_store.get_raw_block_info(b_info.id).await?,
+    };
+    match raw {
+        None => println!("block not found"),
+        Some(raw) => {
+            // offset, 16 bytes in hex, and the printable characters
+            for (i, line) in raw.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+                let text: String = line.iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect();
+                println!("{:08x}  {:<47}  {}", i * 16, hex.join(" "), text);
+            }
+            println!("{} bytes", raw.len());
+        }
+    }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+pub async fn cs_reorgs(config: &BSVDBConfig, limit: usize) {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let reorgs = chain_store.get_reorg_log(limit).await.unwrap();
+    println!("{:>10}  {:<64}  {:<64}  {:>8}  {:>8}", "time", "old tip", "new tip", "fork", "orphaned");
+    for r in reorgs.iter() {
+        let old_tip = chain_store.get_block_info(r.old_tip).await.unwrap().unwrap();
+        let new_tip = chain_store.get_block_info(r.new_tip).await.unwrap().unwrap();
+        println!("{:>10}  {:<64}  {:<64}  {:>8}  {:>8}", r.timestamp, old_tip.hash, new_tip.hash, r.fork_height, r.orphaned_count);
     }
     chain_store.shutdown().await.unwrap();
     j.await.unwrap();
+}
 
-}
\ No newline at end of file
+pub async fn cs_quality(config: &BSVDBConfig, window: Window, json: bool) {
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let q = chain_store.chain_quality(window).await.unwrap();
+    if json {
+        println!("{}", quality_to_json(&q));
+    } else {
+        println!("window:           {} (from {})", q.window, q.start_time);
+        if q.header_times {
+            println!("                  header times, blocks of the window were stored without a received time");
+        }
+        println!("main blocks:      {}", q.main_blocks);
+        println!("stale blocks:     {} ({:.2}%)", q.stale_blocks, q.stale_rate * 100.0);
+        println!("intervals:        mean {:.1}s, median {}s, p95 {}s", q.intervals.mean, q.intervals.median, q.intervals.p95);
+        println!("reorgs:           {}", q.reorgs.len());
+        for r in q.reorgs.iter() {
+            println!("  {:>10}  depth {:>4}  fork height {}", r.timestamp, r.depth, r.fork_height);
+        }
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_reshard(config: &BSVDBConfig, shards: u64, effects: &mut dyn Effects) {
+    effects.reshard(config, shards).await.unwrap();
+    if effects.plan().is_some() {
+        return;
+    }
+    let (chain_store, j) = FDBChainStore::new_if_exists(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    println!("info shards: {}", chain_store.info_shards().await.unwrap());
+    assert!(chain_store.check_counters(false).await.unwrap().is_empty());
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_forks(config: &BSVDBConfig, min_length: u64, json: bool, filter: Option<Filter>) {
+    let (chain_store, j) = FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let mut forks = filter_forks(chain_store.forks().await.unwrap(), min_length);
+    if let Some(filter) = filter {
+        let mut ids = Vec::new();
+        fork_ids(&forks, &mut ids);
+        let mut matching = HashSet::new();
+        for id in ids {
+            if chain_store.get_block_info(id).await.unwrap().is_some_and(|b| filter.matches(&b)) {
+                matching.insert(id);
+            }
+        }
+        forks = filter_forks_by_point(forks, &matching);
+    }
+    if json {
+        let v: Vec<String> = forks.iter().map(fork_to_json).collect();
+        println!("[{}]", v.join(","));
+    } else {
+        println!("{:>8}  {:<64}  {:<4}  {:>6}  {:>12}  {:<21}  {:<64}  miners", "height", "block", "won", "length", "work", "time span", "tip");
+        for f in forks.iter() {
+            print_fork(f, 0);
+        }
+    }
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+}
+
+pub async fn cs_snapshot(config: &BSVDBConfig, around: &str, radius: u64, out: &str, with_blocks: bool, max_block_bytes: u64) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+    // the center is either a block hash or a height on the main chain
+    let center = find_block(&chain_store, around).await?.map(|b| b.hash);
+    match center {
+        None => println!("block not found"),
+        Some(center) => {
+            let mut snapshot = chain_store.snapshot(center, radius).await?;
+            if with_blocks {
+                add_snapshot_blocks(config, &mut snapshot, max_block_bytes).await?;
+            }
+            tokio::fs::write(out, snapshot.encode()).await?;
+            println!("wrote snapshot of {} blocks around {} to {}", snapshot.block_infos.len(), center, out);
+        }
+    }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+// add the raw blocks of the snapshot from the block archive, until the size limit is reached
+async fn add_snapshot_blocks(config: &BSVDBConfig, snapshot: &mut Snapshot, max_block_bytes: u64) -> CliResult<()> {
+    if !config.block_archive.enabled {
+        println!("WARNING: BlockArchive is not enabled, raw blocks not included");
+        return Ok(());
+    }
+    let mut archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    archive.genesis = Some(config.get_blockchain_id());
+    let hashes: Vec<BlockHash> = snapshot.block_infos.iter().map(|b| b.hash).collect();
+    for h in hashes {
+        let size = match archive.block_size(&h).await {
+            Ok(size) => size as u64,
+            Err(_) => {
+                println!("block {} not in the archive, not included", h);
+                continue;
+            }
+        };
+        // check the size before reading the block, blocks can be very large
+        if snapshot.blocks_size() + size > max_block_bytes {
+            snapshot.blocks_truncated = true;
+            continue;
+        }
+        let mut bytes = Vec::with_capacity(size as usize);
+        archive.get_block(&h).await?.read_to_end(&mut bytes).await?;
+        snapshot.add_block(h, bytes, max_block_bytes);
+    }
+    if snapshot.blocks_truncated {
+        println!("WARNING: raw blocks limited to {} bytes, {} of {} blocks included", max_block_bytes, snapshot.blocks.len(), snapshot.block_infos.len());
+    }
+    Ok(())
+}
+
+pub async fn cs_snapshot_inspect(file: &str) {
+    let v = tokio::fs::read(file).await.unwrap();
+    match Snapshot::decode(&v) {
+        Ok(snapshot) => print!("{}", snapshot),
+        Err(e) => println!("ERROR: {}", e),
+    }
+}
+
+pub async fn cs_tree(config: &BSVDBConfig, block: &str, depth: u64) -> CliResult<()> {
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+    match find_block(&chain_store, block).await? {
+        None => println!("block not found"),
+        Some(center) => {
+            // the tree starts at the ancestor depth levels below the block
+            let mut root = center.clone();
+            let mut stream = chain_store.get_block_infos(center.id, Some(depth + 1)).await?;
+            while let Some(b_i) = stream.next().await {
+                root = b_i?;
+            }
+            let max_height = center.height + depth;
+            // each entry is a block, the text before it on its line and the text before the lines below it
+            let mut stack = vec![(root, String::new(), String::new())];
+            while let Some((b_i, lead, rest)) = stack.pop() {
+                let more = if b_i.height == max_height && !b_i.next_ids.is_empty() { " ..." } else { "" };
+                let marker = if b_i.id == center.id { "  <--" } else { "" };
+                println!("{}{} {:>8} {:?}{}{}", lead, short_hash(&b_i.hash), b_i.height, b_i.validity, more, marker);
+                if b_i.height == max_height {
+                    continue;
+                }
+                let mut children = Vec::new();
+                for id in b_i.next_ids.iter() {
+                    if let Some(c) = chain_store.get_block_info(*id).await? {
+                        children.push(c);
+                    }
+                }
+                // a chain without branches continues on the next line, a branch point indents its children
+                if children.len() == 1 {
+                    stack.push((children.pop().unwrap(), rest.clone(), rest));
+                    continue;
+                }
+                let last = children.len().saturating_sub(1);
+                for (i, c) in children.into_iter().enumerate().rev() {
+                    if i == last {
+                        stack.push((c, format!("{}`-- ", rest), format!("{}    ", rest)));
+                    } else {
+                        stack.push((c, format!("{}|-- ", rest), format!("{}|   ", rest)));
+                    }
+                }
+            }
+        }
+    }
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+// Compute the merkle root of the transactions of a block in the archive and compare it with the
+// merkle roots of the headers in the archive and the chain store, returning true if all three
+// match.
+pub async fn cs_verify_body(config: &BSVDBConfig, block_hash: BlockHash) -> bool {
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await.unwrap();
+    let b_info = chain_store.get_block_info_by_hash(block_hash).await.unwrap();
+    chain_store.shutdown().await.unwrap();
+    j.await.unwrap();
+    let Some(b_info) = b_info else {
+        println!("ERROR: block {} not found in the chain store", block_hash);
+        return false;
+    };
+    let mut archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await.unwrap();
+    archive.genesis = Some(config.get_blockchain_id());
+    let reader = match archive.get_block(&block_hash).await {
+        Ok(r) => r,
+        Err(BlockArchiveError::BlockNotFound(_)) => {
+            println!("ERROR: block {} not found in the archive", block_hash);
+            return false;
+        }
+        Err(e) => panic!("{}", e),
+    };
+    let computed = match FullBlockStream::new(reader).await {
+        Ok(block) => {
+            let archive_root = block.block_header.merkle_root;
+            compute_merkle_root(block).await.map(|m| (m, archive_root))
+        }
+        Err(e) => Err(e.into()),
+    };
+    let (computed, archive_root) = match computed {
+        Ok(r) => r,
+        Err(e) => {
+            println!("ERROR: error reading block {} from the archive: {}", block_hash, e);
+            return false;
+        }
+    };
+    let cs_root = b_info.header.merkle_root;
+    if computed == archive_root && computed == cs_root {
+        println!("OK: merkle root {} of block {} matches the archive and chain store headers", computed, block_hash);
+        true
+    } else {
+        println!("ERROR: merkle root mismatch for block {}", block_hash);
+        println!("computed from the archived transactions: {}", computed);
+        println!("archive header:                          {}", archive_root);
+        println!("chain store header:                      {}", cs_root);
+        false
+    }
+}
+
+// the last 16 hex digits of a block hash, the leading digits are mostly zeros
+fn short_hash(hash: &BlockHash) -> String {
+    let h = hash.to_string();
+    h[h.len() - 16..].to_string()
+}
+
+// remove forks whose losing branches are all shorter than min_length
+fn filter_forks(forks: Vec<Fork>, min_length: u64) -> Vec<Fork> {
+    forks
+        .into_iter()
+        .filter(|f| f.max_losing_length() >= min_length)
+        .map(|mut f| {
+            f.children = filter_forks(f.children, min_length);
+            f
+        })
+        .collect()
+}
+
+// the ids of the fork points of the forks and their children
+fn fork_ids(forks: &[Fork], ids: &mut Vec<u64>) {
+    for f in forks.iter() {
+        ids.push(f.id);
+        fork_ids(&f.children, ids);
+    }
+}
+
+// keep the forks whose fork point is in matching, the matching children of a fork that is not
+// kept take its place
+fn filter_forks_by_point(forks: Vec<Fork>, matching: &HashSet<u64>) -> Vec<Fork> {
+    let mut kept = Vec::new();
+    for mut f in forks {
+        let children = filter_forks_by_point(std::mem::take(&mut f.children), matching);
+        if matching.contains(&f.id) {
+            f.children = children;
+            kept.push(f);
+        } else {
+            kept.extend(children);
+        }
+    }
+    kept
+}
+
+// print a fork as rows of a table, with nested forks indented below it
+fn print_fork(fork: &Fork, depth: usize) {
+    let indent = "  ".repeat(depth);
+    println!("{}{:>8}  {}", indent, fork.height, fork.hash);
+    for b in fork.branches.iter() {
+        println!(
+            "{}{:>8}  {:<64}  {:<4}  {:>6}  {:>12.4e}  {:>10}-{:<10}  {}  {}",
+            indent,
+            "",
+            "",
+            if b.won { "yes" } else { "no" },
+            b.length,
+            b.work,
+            b.first_time,
+            b.last_time,
+            b.tip_hash,
+            b.miners.join(", ")
+        );
+    }
+    for c in fork.children.iter() {
+        print_fork(c, depth + 1);
+    }
+}
+
+fn fork_to_json(fork: &Fork) -> String {
+    let branches: Vec<String> = fork
+        .branches
+        .iter()
+        .map(|b| {
+            let miners: Vec<String> = b.miners.iter().map(|m| format!("{:?}", m)).collect();
+            format!(
+                "{{\"first_id\":{},\"tip_id\":{},\"tip_hash\":\"{}\",\"length\":{},\"work\":{},\"won\":{},\"first_time\":{},\"last_time\":{},\"miners\":[{}]}}",
+                b.first_id, b.tip_id, b.tip_hash, b.length, b.work, b.won, b.first_time, b.last_time, miners.join(",")
+            )
+        })
+        .collect();
+    let children: Vec<String> = fork.children.iter().map(fork_to_json).collect();
+    format!(
+        "{{\"id\":{},\"hash\":\"{}\",\"height\":{},\"branches\":[{}],\"children\":[{}]}}",
+        fork.id,
+        fork.hash,
+        fork.height,
+        branches.join(","),
+        children.join(",")
+    )
+}
+
+fn quality_to_json(q: &ChainQuality) -> String {
+    let reorgs: Vec<String> = q
+        .reorgs
+        .iter()
+        .map(|r| format!("{{\"timestamp\":{},\"depth\":{},\"fork_height\":{}}}", r.timestamp, r.depth, r.fork_height))
+        .collect();
+    format!(
+        "{{\"window\":\"{}\",\"start_time\":{},\"main_blocks\":{},\"stale_blocks\":{},\"stale_rate\":{},\"intervals\":{{\"count\":{},\"mean\":{},\"median\":{},\"p95\":{}}},\"reorgs\":[{}],\"header_times\":{}}}",
+        q.window,
+        q.start_time,
+        q.main_blocks,
+        q.stale_blocks,
+        q.stale_rate,
+        q.intervals.count,
+        q.intervals.mean,
+        q.intervals.median,
+        q.intervals.p95,
+        reorgs.join(","),
+        q.header_times
+    )
+}
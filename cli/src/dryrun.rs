@@ -0,0 +1,742 @@
+use crate::import::{import_hashes, BlockSource, ImportOutcome};
+use crate::result::CliResult;
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::BlockHash;
+use bsvdb_base::BSVDBConfig;
+use bsvdb_blockarchive::{BlockArchive, IntentLog, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{
+    BlockInfo, ChainStore, CounterDrift, FDBChainStore, LinkRepair, ReachabilityFix,
+    ReachabilityReport,
+};
+use clap::ValueEnum;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+// The side effects of the mutating commands go through the [Effects] trait. The commands are given
+// either [Apply], which performs the effects, or [DryRun], which records them in a [Plan] and
+// only reads. A command has no other way to change the archive or the chain store, so a dry run
+// can not change anything.
+
+// the number of examples of each kind of effect in a rendered plan
+const PLAN_EXAMPLES: usize = 20;
+
+/// An effect of a mutating command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// A file is removed.
+    RemoveFile { path: PathBuf, bytes: u64 },
+    /// An empty directory is removed.
+    RemoveDir { path: PathBuf },
+    /// A block is fetched from a source and stored in the archive, with its size if known.
+    FetchBlock { hash: BlockHash, bytes: Option<u64> },
+    /// Keys are written in a subspace of the chain store.
+    WriteKeys { subspace: String, keys: u64 },
+    /// Keys are cleared in a subspace of the chain store.
+    ClearKeys { subspace: String, keys: u64 },
+}
+
+impl Effect {
+    // the kind of the effect, in the order that the kinds are rendered
+    fn kind(&self) -> usize {
+        match self {
+            Effect::RemoveFile { .. } => 0,
+            Effect::RemoveDir { .. } => 1,
+            Effect::FetchBlock { .. } => 2,
+            Effect::WriteKeys { .. } => 3,
+            Effect::ClearKeys { .. } => 4,
+        }
+    }
+
+    // the number of items of the effect, and the bytes
+    fn count(&self) -> (u64, u64) {
+        match self {
+            Effect::RemoveFile { bytes, .. } => (1, *bytes),
+            Effect::RemoveDir { .. } => (1, 0),
+            Effect::FetchBlock { bytes, .. } => (1, bytes.unwrap_or_default()),
+            Effect::WriteKeys { keys, .. } | Effect::ClearKeys { keys, .. } => (*keys, 0),
+        }
+    }
+
+    fn example(&self) -> String {
+        match self {
+            Effect::RemoveFile { path, bytes } => format!("{} ({} bytes)", path.display(), bytes),
+            Effect::RemoveDir { path } => format!("{}", path.display()),
+            Effect::FetchBlock { hash, bytes } => match bytes {
+                Some(b) => format!("{} ({} bytes)", hash, b),
+                None => format!("{} (size unknown)", hash),
+            },
+            Effect::WriteKeys { subspace, keys } | Effect::ClearKeys { subspace, keys } => {
+                format!("{}: {} keys", subspace, keys)
+            }
+        }
+    }
+}
+
+// the names of the kinds of effects
+const KIND_NAMES: [&str; 5] = [
+    "remove-file",
+    "remove-dir",
+    "fetch-block",
+    "write-keys",
+    "clear-keys",
+];
+
+/// The output formats of a [Plan].
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PlanFormat {
+    Text,
+    Json,
+}
+
+/// The effects that a command would have had, recorded by a [DryRun].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    effects: Vec<Effect>,
+}
+
+impl Plan {
+    /// Record an effect, the keys written or cleared in a subspace are added together.
+    pub fn record(&mut self, effect: Effect) {
+        if let Effect::WriteKeys { subspace, keys } | Effect::ClearKeys { subspace, keys } = &effect
+        {
+            let existing = self.effects.iter_mut().find(|e| match e {
+                Effect::WriteKeys { subspace: s, .. } | Effect::ClearKeys { subspace: s, .. } => {
+                    e.kind() == effect.kind() && s == subspace
+                }
+                _ => false,
+            });
+            if let Some(Effect::WriteKeys { keys: k, .. } | Effect::ClearKeys { keys: k, .. }) =
+                existing
+            {
+                *k += keys;
+                return;
+            }
+        }
+        self.effects.push(effect);
+    }
+
+    /// The recorded effects, in the order that they were recorded.
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    /// The total bytes of the files removed and the blocks fetched.
+    pub fn total_bytes(&self) -> u64 {
+        self.effects.iter().map(|e| e.count().1).sum()
+    }
+
+    // for each kind of effect that was recorded, the kind, the number of items, the bytes and the
+    // examples
+    fn summary(&self) -> Vec<(usize, u64, u64, Vec<String>)> {
+        let mut summary = Vec::new();
+        for kind in 0..KIND_NAMES.len() {
+            let effects: Vec<&Effect> = self.effects.iter().filter(|e| e.kind() == kind).collect();
+            if effects.is_empty() {
+                continue;
+            }
+            let count = effects.iter().map(|e| e.count().0).sum();
+            let bytes = effects.iter().map(|e| e.count().1).sum();
+            let examples = effects
+                .iter()
+                .take(PLAN_EXAMPLES)
+                .map(|e| e.example())
+                .collect();
+            summary.push((kind, count, bytes, examples));
+        }
+        summary
+    }
+
+    /// Render the plan, with the number of items and bytes of each kind of effect and the first
+    /// examples of each.
+    pub fn render(&self, format: PlanFormat) -> String {
+        let summary = self.summary();
+        match format {
+            PlanFormat::Text => {
+                let mut lines = vec![String::from("DRY RUN: nothing was changed")];
+                if summary.is_empty() {
+                    lines.push(String::from("nothing to do"));
+                }
+                for (kind, count, bytes, examples) in summary.iter() {
+                    lines.push(format!(
+                        "{}: {} ({} bytes)",
+                        KIND_NAMES[*kind], count, bytes
+                    ));
+                    for e in examples.iter() {
+                        lines.push(format!("  {}", e));
+                    }
+                    let more = self.effects.iter().filter(|e| e.kind() == *kind).count();
+                    if more > examples.len() {
+                        lines.push(format!("  ... and {} more", more - examples.len()));
+                    }
+                }
+                lines.push(format!("total: {} bytes", self.total_bytes()));
+                lines.join("\n")
+            }
+            PlanFormat::Json => {
+                let kinds: Vec<String> = summary
+                    .iter()
+                    .map(|(kind, count, bytes, examples)| {
+                        let examples: Vec<String> =
+                            examples.iter().map(|e| json_string(e)).collect();
+                        format!(
+                            "{{\"kind\":\"{}\",\"count\":{},\"bytes\":{},\"examples\":[{}]}}",
+                            KIND_NAMES[*kind],
+                            count,
+                            bytes,
+                            examples.join(",")
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"dry_run\":true,\"total_bytes\":{},\"effects\":[{}]}}",
+                    self.total_bytes(),
+                    kinds.join(",")
+                )
+            }
+        }
+    }
+}
+
+// a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+/// Performs the side effects of the mutating commands, see [Apply] and [DryRun].
+///
+/// The functions that only read are here too when a dry run must take the recorded effects into
+/// account.
+#[async_trait]
+pub trait Effects: Send {
+    /// The plan of the effects, None if the effects are performed.
+    fn plan(&self) -> Option<&Plan>;
+
+    /// Remove a block from the archive, returning its size, see
+    /// [SimpleFileBasedBlockArchive::remove_block()].
+    async fn remove_block(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        block_hash: &BlockHash,
+    ) -> CliResult<u64>;
+
+    /// Remove an empty directory of the archive. A directory that is no longer empty is left.
+    async fn remove_dir(&mut self, dir: &Path) -> CliResult<()>;
+
+    /// Ensure that the blocks are in the archive, fetching missing blocks from the sources, see
+    /// [import_hashes()].
+    async fn import_blocks(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        hashes: Vec<BlockHash>,
+        sources: &[Box<dyn BlockSource>],
+        max_parallel: usize,
+        intents: Option<&IntentLog>,
+    ) -> Vec<(BlockHash, ImportOutcome)>;
+
+    /// Check whether a block is in the ChainStore.
+    async fn has_block_info(
+        &self,
+        chain_store: &FDBChainStore,
+        block_hash: BlockHash,
+    ) -> CliResult<bool>;
+
+    /// Store a BlockInfo in the ChainStore.
+    async fn store_block_info(
+        &mut self,
+        chain_store: &FDBChainStore,
+        b_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> CliResult<()>;
+
+    /// Reset the counters that have drifted, returning the drift.
+    async fn fix_counters(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<CounterDrift>>;
+
+    /// Fix the unreachable BlockInfos, returning the report.
+    async fn fix_reachability(
+        &mut self,
+        chain_store: &FDBChainStore,
+        fix: ReachabilityFix,
+    ) -> CliResult<ReachabilityReport>;
+
+    /// Repair the links between blocks, returning the repairs.
+    async fn repair_links(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<LinkRepair>>;
+
+    /// Spread the BlockInfo keys across the number of shards.
+    async fn reshard(&mut self, config: &BSVDBConfig, shards: u64) -> CliResult<()>;
+}
+
+/// Performs the effects.
+pub struct Apply;
+
+#[async_trait]
+impl Effects for Apply {
+    fn plan(&self) -> Option<&Plan> {
+        None
+    }
+
+    async fn remove_block(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        block_hash: &BlockHash,
+    ) -> CliResult<u64> {
+        Ok(archive.remove_block(block_hash).await?)
+    }
+
+    async fn remove_dir(&mut self, dir: &Path) -> CliResult<()> {
+        match tokio::fs::remove_dir(dir).await {
+            Ok(()) => Ok(()),
+            // a block has been stored in it since it was scanned
+            Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn import_blocks(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        hashes: Vec<BlockHash>,
+        sources: &[Box<dyn BlockSource>],
+        max_parallel: usize,
+        intents: Option<&IntentLog>,
+    ) -> Vec<(BlockHash, ImportOutcome)> {
+        import_hashes(archive, hashes, sources, max_parallel, intents).await
+    }
+
+    async fn has_block_info(
+        &self,
+        chain_store: &FDBChainStore,
+        block_hash: BlockHash,
+    ) -> CliResult<bool> {
+        Ok(chain_store
+            .get_block_info_by_hash(block_hash)
+            .await?
+            .is_some())
+    }
+
+    async fn store_block_info(
+        &mut self,
+        chain_store: &FDBChainStore,
+        b_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> CliResult<()> {
+        chain_store.store_block_info(b_info).await?;
+        Ok(())
+    }
+
+    async fn fix_counters(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<CounterDrift>> {
+        Ok(chain_store.check_counters(true).await?)
+    }
+
+    async fn fix_reachability(
+        &mut self,
+        chain_store: &FDBChainStore,
+        fix: ReachabilityFix,
+    ) -> CliResult<ReachabilityReport> {
+        Ok(chain_store.check_reachability(Some(fix)).await?)
+    }
+
+    async fn repair_links(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<LinkRepair>> {
+        Ok(chain_store.repair_links().await?)
+    }
+
+    async fn reshard(&mut self, config: &BSVDBConfig, shards: u64) -> CliResult<()> {
+        Ok(FDBChainStore::migrate_info_shards(&config.chain_store, shards).await?)
+    }
+}
+
+/// Records the effects in a [Plan] instead of performing them.
+#[derive(Default)]
+pub struct DryRun {
+    plan: Plan,
+    // the BlockInfos that would have been stored
+    stored: BTreeSet<BlockHash>,
+}
+
+#[async_trait]
+impl Effects for DryRun {
+    fn plan(&self) -> Option<&Plan> {
+        Some(&self.plan)
+    }
+
+    async fn remove_block(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        block_hash: &BlockHash,
+    ) -> CliResult<u64> {
+        if archive.block_protected(block_hash).await? {
+            return Err(bsvdb_blockarchive::Error::BlockProtected.into());
+        }
+        let bytes = archive.block_size(block_hash).await? as u64;
+        self.plan.record(Effect::RemoveFile {
+            path: archive.block_path(block_hash),
+            bytes,
+        });
+        Ok(bytes)
+    }
+
+    async fn remove_dir(&mut self, dir: &Path) -> CliResult<()> {
+        self.plan.record(Effect::RemoveDir {
+            path: dir.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    async fn import_blocks(
+        &mut self,
+        archive: &SimpleFileBasedBlockArchive,
+        hashes: Vec<BlockHash>,
+        _sources: &[Box<dyn BlockSource>],
+        _max_parallel: usize,
+        _intents: Option<&IntentLog>,
+    ) -> Vec<(BlockHash, ImportOutcome)> {
+        let mut results = Vec::with_capacity(hashes.len());
+        for h in hashes {
+            let outcome = match archive.block_exists(&h).await {
+                Ok(true) => ImportOutcome::AlreadyPresent,
+                Ok(false) => {
+                    self.plan.record(Effect::FetchBlock {
+                        hash: h,
+                        bytes: None,
+                    });
+                    ImportOutcome::Planned
+                }
+                Err(e) => ImportOutcome::Failed(format!("error checking archive: {}", e)),
+            };
+            results.push((h, outcome));
+        }
+        results
+    }
+
+    async fn has_block_info(
+        &self,
+        chain_store: &FDBChainStore,
+        block_hash: BlockHash,
+    ) -> CliResult<bool> {
+        Ok(self.stored.contains(&block_hash)
+            || chain_store
+                .get_block_info_by_hash(block_hash)
+                .await?
+                .is_some())
+    }
+
+    async fn store_block_info(
+        &mut self,
+        chain_store: &FDBChainStore,
+        b_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    ) -> CliResult<()> {
+        // an existing BlockInfo is updated, a new one is also indexed by its hash
+        if !self.has_block_info(chain_store, b_info.hash).await? {
+            self.plan.record(Effect::WriteKeys {
+                subspace: String::from("hindex"),
+                keys: 1,
+            });
+        }
+        self.plan.record(Effect::WriteKeys {
+            subspace: String::from("infos"),
+            keys: 1,
+        });
+        self.stored.insert(b_info.hash);
+        Ok(())
+    }
+
+    async fn fix_counters(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<CounterDrift>> {
+        let drift = chain_store.check_counters(false).await?;
+        if !drift.is_empty() {
+            self.plan.record(Effect::WriteKeys {
+                subspace: String::from("chain"),
+                keys: drift.len() as u64,
+            });
+        }
+        Ok(drift)
+    }
+
+    async fn fix_reachability(
+        &mut self,
+        chain_store: &FDBChainStore,
+        fix: ReachabilityFix,
+    ) -> CliResult<ReachabilityReport> {
+        let report = chain_store.check_reachability(None).await?;
+        let n = report.unreachable.len() as u64;
+        match fix {
+            ReachabilityFix::Delete if n > 0 => {
+                for subspace in ["infos", "hindex"] {
+                    self.plan.record(Effect::ClearKeys {
+                        subspace: String::from(subspace),
+                        keys: n,
+                    });
+                }
+            }
+            ReachabilityFix::Relink => {
+                let parents: BTreeSet<u64> =
+                    report.unreachable.iter().filter_map(|u| u.parent).collect();
+                if !parents.is_empty() {
+                    self.plan.record(Effect::WriteKeys {
+                        subspace: String::from("infos"),
+                        keys: parents.len() as u64,
+                    });
+                }
+            }
+            _ => {}
+        }
+        Ok(report)
+    }
+
+    async fn repair_links(&mut self, chain_store: &FDBChainStore) -> CliResult<Vec<LinkRepair>> {
+        let repairs = chain_store.check_links().await?;
+        let parents: BTreeSet<u64> = repairs
+            .iter()
+            .map(|r| match r {
+                LinkRepair::AddedChild { parent, .. } | LinkRepair::RemovedChild { parent, .. } => {
+                    *parent
+                }
+            })
+            .collect();
+        if !parents.is_empty() {
+            self.plan.record(Effect::WriteKeys {
+                subspace: String::from("infos"),
+                keys: parents.len() as u64,
+            });
+        }
+        Ok(repairs)
+    }
+
+    async fn reshard(&mut self, config: &BSVDBConfig, shards: u64) -> CliResult<()> {
+        let (chain_store, j) =
+            FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+        let current = chain_store.info_shards().await;
+        let summary = chain_store.summary().await;
+        chain_store.shutdown().await?;
+        j.await?;
+        if current? != shards {
+            // every BlockInfo is copied into a new directory which replaces the old one
+            let n = summary?.num_blocks;
+            self.plan.record(Effect::WriteKeys {
+                subspace: String::from("infos-migration"),
+                keys: n,
+            });
+            self.plan.record(Effect::ClearKeys {
+                subspace: String::from("infos"),
+                keys: n,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ba::{compact, remove_blocks, PruneReport};
+    use bsvdb_base::BlockArchiveConfig;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    const BLOCK_1: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    const BLOCK_UNKNOWN: &str = "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1";
+
+    // Copy the directory tree.
+    fn copy_tree(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_tree(&entry.path(), &target);
+            } else {
+                std::fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    // The paths in the directory tree, with the contents of the files.
+    fn snapshot(dir: &Path) -> BTreeMap<PathBuf, Option<Vec<u8>>> {
+        let mut files = BTreeMap::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                files.insert(entry.path(), None);
+                files.extend(snapshot(&entry.path()));
+            } else {
+                files.insert(entry.path(), Some(std::fs::read(entry.path()).unwrap()));
+            }
+        }
+        files
+    }
+
+    // A copy of the test archive, with an empty block directory.
+    fn fixture(root: &Path) -> BlockArchiveConfig {
+        let archive = root.join("archive");
+        copy_tree(Path::new("../testdata/blockarchive"), &archive);
+        std::fs::create_dir_all(archive.join("ff").join("ff")).unwrap();
+        BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(archive.to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        }
+    }
+
+    #[test]
+    fn keys_are_grouped() {
+        let mut plan = Plan::default();
+        for subspace in ["infos", "hindex", "infos"] {
+            plan.record(Effect::WriteKeys {
+                subspace: String::from(subspace),
+                keys: 2,
+            });
+        }
+        plan.record(Effect::ClearKeys {
+            subspace: String::from("infos"),
+            keys: 1,
+        });
+        assert_eq!(
+            plan.effects(),
+            &[
+                Effect::WriteKeys {
+                    subspace: String::from("infos"),
+                    keys: 4
+                },
+                Effect::WriteKeys {
+                    subspace: String::from("hindex"),
+                    keys: 2
+                },
+                Effect::ClearKeys {
+                    subspace: String::from("infos"),
+                    keys: 1
+                },
+            ]
+        );
+    }
+
+    // The rendered plan has the counts and totals, and at most 20 examples of each kind.
+    #[test]
+    fn render() {
+        let mut plan = Plan::default();
+        assert_eq!(
+            plan.render(PlanFormat::Text),
+            "DRY RUN: nothing was changed\nnothing to do\ntotal: 0 bytes"
+        );
+        assert_eq!(
+            plan.render(PlanFormat::Json),
+            "{\"dry_run\":true,\"total_bytes\":0,\"effects\":[]}"
+        );
+        for i in 0..25 {
+            plan.record(Effect::RemoveFile {
+                path: PathBuf::from(format!("/a/{}.bin", i)),
+                bytes: 10,
+            });
+        }
+        plan.record(Effect::FetchBlock {
+            hash: BLOCK_1.parse().unwrap(),
+            bytes: None,
+        });
+        plan.record(Effect::WriteKeys {
+            subspace: String::from("infos"),
+            keys: 3,
+        });
+        let text = plan.render(PlanFormat::Text);
+        assert!(text.contains("remove-file: 25 (250 bytes)\n  /a/0.bin (10 bytes)\n"));
+        assert!(text.contains("  /a/19.bin (10 bytes)\n  ... and 5 more\n"));
+        assert!(!text.contains("/a/20.bin"));
+        assert!(text.contains(&format!(
+            "fetch-block: 1 (0 bytes)\n  {} (size unknown)",
+            BLOCK_1
+        )));
+        assert!(text.contains("write-keys: 3 (0 bytes)\n  infos: 3 keys\n"));
+        assert!(text.ends_with("total: 250 bytes"));
+        let json = plan.render(PlanFormat::Json);
+        assert!(json.starts_with(
+            "{\"dry_run\":true,\"total_bytes\":250,\"effects\":[{\"kind\":\"remove-file\",\"count\":25,\"bytes\":250,\"examples\":[\"/a/0.bin (10 bytes)\","
+        ));
+        assert!(json.contains(
+            "{\"kind\":\"write-keys\",\"count\":3,\"bytes\":0,\"examples\":[\"infos: 3 keys\"]}"
+        ));
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    // A dry run of prune plans the removal of the blocks without changing the archive.
+    #[tokio::test]
+    async fn prune_plan() {
+        let root = tempdir().unwrap();
+        let c = fixture(root.path());
+        let before = snapshot(root.path());
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let mut effects = DryRun::default();
+        let mut report = PruneReport::default();
+        let hashes = vec![BLOCK_1.parse().unwrap()];
+        remove_blocks(&archive, hashes, &mut effects, &mut report)
+            .await
+            .unwrap();
+        assert_eq!(snapshot(root.path()), before);
+        let size = std::fs::metadata(archive.block_path(&BLOCK_1.parse().unwrap()))
+            .unwrap()
+            .len();
+        assert_eq!(report.removed, 1);
+        assert_eq!(report.bytes, size);
+        assert_eq!(
+            effects.plan().unwrap().effects(),
+            &[Effect::RemoveFile {
+                path: archive.block_path(&BLOCK_1.parse().unwrap()),
+                bytes: size
+            }]
+        );
+        // a block that is not in the archive is an error, as it is when applied
+        let mut report = PruneReport::default();
+        let hashes = vec![BLOCK_UNKNOWN.parse().unwrap()];
+        assert!(remove_blocks(&archive, hashes, &mut effects, &mut report)
+            .await
+            .is_err());
+    }
+
+    // A dry run of compact plans the removal of the empty directories without removing them.
+    #[tokio::test]
+    async fn compact_plan() {
+        let root = tempdir().unwrap();
+        let c = fixture(root.path());
+        let before = snapshot(root.path());
+        let mut effects = DryRun::default();
+        compact(&c, &mut effects).await.unwrap();
+        assert_eq!(snapshot(root.path()), before);
+        let plan = effects.plan().unwrap();
+        assert!(plan.effects().contains(&Effect::RemoveDir {
+            path: PathBuf::from(&c.root_path).join("ff").join("ff")
+        }));
+        assert!(plan
+            .effects()
+            .iter()
+            .all(|e| matches!(e, Effect::RemoveDir { .. })));
+        // when applied the directories are removed
+        compact(&c, &mut Apply).await.unwrap();
+        assert!(!PathBuf::from(&c.root_path).join("ff").exists());
+    }
+
+    // A dry run of an import plans the fetches of the missing blocks without fetching them.
+    #[tokio::test]
+    async fn import_plan() {
+        let root = tempdir().unwrap();
+        let c = fixture(root.path());
+        let before = snapshot(root.path());
+        let archive = SimpleFileBasedBlockArchive::new(&c).await.unwrap();
+        let mut effects = DryRun::default();
+        let hashes = vec![BLOCK_1.parse().unwrap(), BLOCK_UNKNOWN.parse().unwrap()];
+        let results = effects.import_blocks(&archive, hashes, &[], 4, None).await;
+        assert_eq!(results[0].1, ImportOutcome::AlreadyPresent);
+        assert_eq!(results[1].1, ImportOutcome::Planned);
+        assert_eq!(snapshot(root.path()), before);
+        assert_eq!(
+            effects.plan().unwrap().effects(),
+            &[Effect::FetchBlock {
+                hash: BLOCK_UNKNOWN.parse().unwrap(),
+                bytes: None
+            }]
+        );
+    }
+}
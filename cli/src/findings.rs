@@ -0,0 +1,276 @@
+use crate::result::{CliError, CliResult};
+use bsvdb_blockarchive::{Finding, FindingCode, Partition, ReportFormat, Reporter, Suppressions};
+use std::collections::BTreeMap;
+use std::io::Stdout;
+use std::path::Path;
+
+// A partial result is written by a check of one partition of the blocks, see Partition. It is a
+// text file with a header line "bsvdb-partial 1 <kind> <K>/<N>", followed by a line for each
+// finding with the code, the subject, the detected time and a key=value for each detail,
+// separated by tabs. Backslashes, tabs, newlines and '=' in the fields are escaped.
+
+// the first word and the version of the header of a partial result
+const PARTIAL_HEADER: &str = "bsvdb-partial 1";
+
+/// Print every finding code with its name, severity and description.
+pub fn list_codes() {
+    println!(
+        "{:<6}  {:<16}  {:<8}  description",
+        "code", "name", "severity"
+    );
+    for c in FindingCode::ALL {
+        println!(
+            "{:<6}  {:<16}  {:<8}  {}",
+            c.id(),
+            c.name(),
+            c.severity().name(),
+            c.description()
+        );
+    }
+}
+
+/// Create a reporter that writes the findings to stdout, with the suppressions from the file if
+/// one is given. The notes are written to stderr for the formats other than text.
+pub async fn open_reporter(
+    format: ReportFormat,
+    suppressions: Option<String>,
+) -> CliResult<Reporter<Stdout>> {
+    let suppressions = match suppressions {
+        Some(path) => Suppressions::load(path).await?,
+        None => Suppressions::default(),
+    };
+    Ok(Reporter::new(std::io::stdout(), format, suppressions).with_notes(std::io::stderr()))
+}
+
+/// Write the findings of a check of a partition to a partial result file, which can be merged
+/// with the results of the other partitions by [merge_results()].
+pub async fn write_partial<'a>(
+    path: impl AsRef<Path>,
+    kind: &str,
+    partition: Partition,
+    findings: impl Iterator<Item = &'a Finding>,
+) -> CliResult<()> {
+    let mut text = format!("{} {} {}\n", PARTIAL_HEADER, kind, partition);
+    for f in findings {
+        let mut fields = vec![
+            String::from(f.code.id()),
+            escape(&f.subject),
+            f.detected.to_string(),
+        ];
+        fields.extend(
+            f.details
+                .iter()
+                .map(|(k, v)| format!("{}={}", escape(k), escape(v))),
+        );
+        text.push_str(&fields.join("\t"));
+        text.push('\n');
+    }
+    tokio::fs::write(path, text).await?;
+    Ok(())
+}
+
+/// Merge the partial results of the partitions of a check and report the findings.
+///
+/// The partial results must be of the same kind of check and the same number of partitions N,
+/// with exactly one result for each partition from 1 to N. The findings are reported in the order
+/// of their subject and code, so the output does not depend on how the check was partitioned.
+pub async fn merge_results<W: std::io::Write>(
+    paths: &[String],
+    reporter: &mut Reporter<W>,
+) -> CliResult<()> {
+    let mut kind: Option<String> = None;
+    let mut parts: BTreeMap<u32, &str> = BTreeMap::new();
+    let mut count = None;
+    let mut findings = Vec::new();
+    for path in paths {
+        let invalid = |m: &str| CliError::Merge(format!("{}: {}", path, m));
+        let text = tokio::fs::read_to_string(path).await?;
+        let mut lines = text.lines();
+        let header = lines
+            .next()
+            .and_then(|h| h.strip_prefix(PARTIAL_HEADER))
+            .ok_or_else(|| invalid("not a partial result"))?;
+        let (k, partition) = header
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| invalid("no partition in the header"))?;
+        let partition: Partition = partition.parse().map_err(|e: String| invalid(&e))?;
+        if *kind.get_or_insert_with(|| String::from(k)) != k {
+            return Err(invalid("the results are from different checks"));
+        }
+        if *count.get_or_insert(partition.count()) != partition.count() {
+            return Err(invalid("the results have different numbers of partitions"));
+        }
+        if let Some(other) = parts.insert(partition.index(), path) {
+            return Err(invalid(&format!(
+                "partition {} is also in {}",
+                partition, other
+            )));
+        }
+        for (n, line) in lines.enumerate() {
+            let f = parse_finding(line)
+                .ok_or_else(|| invalid(&format!("line {}: invalid finding", n + 2)))?;
+            findings.push(f);
+        }
+    }
+    let count = count.ok_or_else(|| CliError::Merge(String::from("no partial results")))?;
+    if let Some(k) = (1..=count).find(|k| !parts.contains_key(k)) {
+        return Err(CliError::Merge(format!(
+            "partition {}/{} is missing",
+            k, count
+        )));
+    }
+    findings.sort_by(|a, b| (&a.subject, a.code.id()).cmp(&(&b.subject, b.code.id())));
+    for f in findings {
+        reporter.report(f)?;
+    }
+    Ok(())
+}
+
+// parse a finding line of a partial result
+fn parse_finding(line: &str) -> Option<Finding> {
+    let mut fields = line.split('\t');
+    let code = FindingCode::from_id(fields.next()?)?;
+    let mut f = Finding::new(code, unescape(fields.next()?)?);
+    f.detected = fields.next()?.parse().ok()?;
+    for detail in fields {
+        let (k, v) = detail.split_once('=')?;
+        f.details.insert(unescape(k)?, unescape(v)?);
+    }
+    Some(f)
+}
+
+// escape the backslashes, tabs and newlines of a field, and the '=' that ends a detail key
+fn escape(s: &str) -> String {
+    let mut e = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => e.push_str("\\\\"),
+            '\t' => e.push_str("\\t"),
+            '\n' => e.push_str("\\n"),
+            '=' => e.push_str("\\e"),
+            c => e.push(c),
+        }
+    }
+    e
+}
+
+// reverse escape(), None if there is an unknown escape
+fn unescape(s: &str) -> Option<String> {
+    let mut u = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            u.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => u.push('\\'),
+            't' => u.push('\t'),
+            'n' => u.push('\n'),
+            'e' => u.push('='),
+            _ => return None,
+        }
+    }
+    Some(u)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ba::check_all_blocks;
+    use crate::select::BlockSelector;
+    use bsvdb_base::{BSVDBConfig, BlockArchiveConfig};
+    use tempfile::tempdir;
+
+    fn testdata_config() -> BSVDBConfig {
+        let mut config = BSVDBConfig::new(None).unwrap();
+        config.block_archive = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from("../testdata/blockarchive"),
+            ..BlockArchiveConfig::default()
+        };
+        config
+    }
+
+    fn reporter() -> Reporter<Vec<u8>> {
+        Reporter::new(Vec::new(), ReportFormat::Text, Suppressions::default())
+    }
+
+    // Check the blocks of each partition, writing the partial results to the directory.
+    async fn check_partitions(dir: &Path, n: u32) -> Vec<String> {
+        let config = testdata_config();
+        // the unknown block is in the last partition, the others in the first two
+        let selector: BlockSelector = "hash:\
+            00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048,\
+            000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f,\
+            0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1,\
+            0000000000000000000000000000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        let mut paths = Vec::new();
+        for k in 1..=n {
+            let partition = Partition::new(k, n).unwrap();
+            let mut r = reporter();
+            check_all_blocks(&config, selector.clone(), partition, &mut r, false)
+                .await
+                .unwrap();
+            let path = dir.join(format!("part-{}-{}.txt", k, n));
+            write_partial(&path, "check-blocks", partition, r.findings())
+                .await
+                .unwrap();
+            paths.push(String::from(path.to_str().unwrap()));
+        }
+        paths
+    }
+
+    // Merge the partial results, returning the exit code.
+    async fn merge(paths: &[String]) -> CliResult<i32> {
+        let mut r = reporter();
+        merge_results(paths, &mut r).await?;
+        Ok(r.finish()?)
+    }
+
+    // The merged results of a partitioned check are those of the check of the whole archive.
+    #[tokio::test]
+    async fn merge_partitions() {
+        let dir = tempdir().unwrap();
+        let whole = check_partitions(dir.path(), 1).await;
+        let parts = check_partitions(dir.path(), 3).await;
+        let text = std::fs::read_to_string(&parts[2]).unwrap();
+        assert!(text.starts_with("bsvdb-partial 1 check-blocks 3/3\n"));
+        assert_eq!(text.lines().count(), 2);
+        let f = parse_finding(text.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(f.code.id(), "BA005");
+
+        let mut out = Vec::new();
+        let mut r = Reporter::new(&mut out, ReportFormat::Text, Suppressions::default());
+        merge_results(&whole, &mut r).await.unwrap();
+        assert_eq!(r.finish().unwrap(), 2);
+        let mut merged = Vec::new();
+        let mut r = Reporter::new(&mut merged, ReportFormat::Text, Suppressions::default());
+        let reversed: Vec<String> = parts.iter().rev().cloned().collect();
+        merge_results(&reversed, &mut r).await.unwrap();
+        assert_eq!(r.finish().unwrap(), 2);
+        assert_eq!(merged, out);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+        // the partitions must be complete and disjoint, and have the same count
+        assert!(merge(&parts[..2]).await.is_err());
+        let overlapping = vec![parts[0].clone(), parts[1].clone(), parts[1].clone()];
+        assert!(merge(&overlapping).await.is_err());
+        let mixed = vec![parts[0].clone(), parts[1].clone(), whole[0].clone()];
+        assert!(merge(&mixed).await.is_err());
+        assert_eq!(merge(&parts).await.unwrap(), 2);
+    }
+
+    // The fields are escaped so that they can hold any text.
+    #[test]
+    fn escaped_fields() {
+        for s in ["a\tb\nc", "x=y", "\\e", ""] {
+            assert_eq!(unescape(&escape(s)).unwrap(), s);
+            assert!(!escape(s).contains(['\t', '\n', '=']));
+        }
+        assert!(unescape("\\x").is_none());
+    }
+}
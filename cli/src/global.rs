@@ -1,272 +1,592 @@
-use crate::result::CliResult;
-use bitcoinsv::bitcoin::{BlockHash, FullBlockStream};
+use crate::ba::verify_block;
+use crate::dryrun::Apply;
+use crate::import::{read_block_info, recover_intents};
+use crate::result::{CliError, CliResult};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
 use bsvdb_base::BSVDBConfig;
-use bsvdb_blockarchive::{BlockArchive, SimpleFileBasedBlockArchive};
-use bsvdb_chainstore::Result;
-use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
-use futures::StreamExt;
+use bsvdb_blockarchive::{BlockArchive, IntentLog, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, Error, FDBChainStore};
+use futures::{stream, StreamExt};
 use std::collections::{BTreeMap, BTreeSet};
-use std::future::Future;
-use std::pin::Pin;
+use std::path::Path;
 use std::time::Instant;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
-// synchronize chainstore from blockstore, multi-threaded approach
-pub async fn sync_piped(config: &BSVDBConfig) -> CliResult<()> {
-    // single-threaded approach has achieved 40-43 blocks/sec
-    // lets see if we can do dramatically better with a pipeline approach
+/// How the bodies of the blocks are validated by [sync_piped()].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BodyValidation {
+    /// Whether the bodies are validated, otherwise the blocks are stored with an unknown
+    /// validity.
+    pub enabled: bool,
+    /// The blocks at or below this height are stored as valid without checking them, like the
+    /// assumevalid option of bitcoin nodes. This trusts the integrity of the archive below the
+    /// height.
+    pub assume_valid_height: Option<u64>,
+    /// Whether the fees claimed by the coinbase of each block are stored, see
+    /// [BlockInfo::total_fees]. The coinbase is read whatever the assumed valid height.
+    pub fees: bool,
+}
 
-    // pipeline is a number of stages, where each stage is a spawned task
-    // the stages communicate through channels which are configured with reasonably sized buffers
-    // a stage will generally receive a future from the source channel, do some processing which culminates in spawning another task,
-    // then send the future for the result down the output channel to the next stage
-    // the buffers enable multi-processing to continue while a stage waits for the next result
-    //
-    // 1 - iterate over block hashes from block archive and in launch a task to fetch it from the chain store
-    //      send future to next stage
-    // 2 - incoming is futures of check for block in chain store
-    //      if the block is in chain store then abandon
-    //      if the block is not in the chain store then
-    //          spawn a task to spool the block, collecting the header and the number of transactions and send to next stage
-    // 3 - incoming is future of fetching the header & the number of tx for unknown block
-    //      create a BlockInfo and add the header and number of tx
-    //      spawn a task to get the block size and send the task and the blockinfo to the next stage
-    // 4 - incoming is a future for getting the block size and the partial block info
-    //      update the partial block info with the file size
-    //      spawn a task to check if the parent is in the chainstore
-    //      send future to next stage with the partial block info
-    // 5 - collect all inputs - (block info, joinhandle of check for parent)
-    //      build map of hash -> block info, parent hash -> child hashes, known_parent set
-    //      until pipeline done
-    //      return maps & set
+impl BodyValidation {
+    // the validity of the block at the height, checking its merkle root unless it is trusted
     //
-    //      while known_parent set not empty:
-    //          create empty list of futures
-    //          for each known_parent:
-    //              for each child of known_parent, if any: (from parent hash -> child hashes)
-    //                  get header from hash -> header map
-    //                  spawn task to store in chain store, returning new block info, adding future to list of futures
-    //          clear known_parent set
-    //          wait for each storage task to complete, storing hash of block stored in known_parent set
-
-    const BUFFER_SIZE: usize = 1000;
-
-    type Stage1Result = (
-        Pin<
-            Box<
-                dyn Future<
-                        Output = Result<
-                            std::option::Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
-                        >,
-                    > + Send,
-            >,
-        >,
-        BlockHash,
-    );
-    pub async fn stage1(
-        block_hashes: Vec<BlockHash>,
-        chain_store: FDBChainStore,
-        sender: Sender<Stage1Result>,
-    ) -> CliResult<()> {
-        for block_hash in block_hashes {
-            let j = chain_store.get_block_info_by_hash(block_hash);
-            sender
-                .send((j, block_hash))
-                .await
-                .expect("TODO: panic message");
+    // a block that can not be read is reported and stored with an unknown validity
+    async fn validity(
+        &self,
+        archive: &SimpleFileBasedBlockArchive,
+        block_hash: &BlockHash,
+        height: u64,
+    ) -> BlockValidity {
+        if !self.enabled {
+            return BlockValidity::Unknown;
+        }
+        if self.assume_valid_height.is_some_and(|h| height <= h) {
+            return BlockValidity::Valid;
+        }
+        match verify_block(archive, block_hash).await {
+            Ok(v) if v.passed(block_hash) => BlockValidity::Valid,
+            Ok(_) => BlockValidity::Invalid,
+            Err(e) => {
+                println!("could not validate block {}: {}", block_hash, e);
+                BlockValidity::Unknown
+            }
         }
-        Ok(())
     }
 
-    type Stage2Result = BlockInfo<<FDBChainStore as ChainStore>::BlockId>;
-    async fn stage2(
-        mut receiver: Receiver<Stage1Result>,
-        config: BSVDBConfig,
-        sender: Sender<Stage2Result>,
-    ) -> CliResult<()> {
-        // unfortunately we cant send futures for retrieving block data at the moment, so we have to do it in the foreground here
-        let block_archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
-        while let Some((j, block_hash)) = receiver.recv().await {
-            let r = j.await.unwrap();
-            if r.is_none() {
-                let r = block_archive
-                    .get_block(&block_hash)
-                    .await
-                    .expect("get_block failed in stage2");
-                let it = FullBlockStream::new(r)
-                    .await
-                    .expect("couldnt get block stream");
-                let b_info = BlockInfo {
-                    id: 0u64,
-                    hash: it.block_header.hash(),
-                    header: it.block_header,
-                    height: 0,
-                    prev_id: 0u64,
-                    next_ids: vec![],
-                    size: None,
-                    num_tx: Some(it.num_tx),
-                    median_time: None,
-                    chain_work: None,
-                    total_tx: None,
-                    total_size: None,
-                    miner: None,
-                    validity: BlockValidity::Unknown,
-                };
-                sender.send(b_info).await.expect("sending failed in stage2");
+    // the fees claimed by the coinbase of the block at the height, if they are stored
+    //
+    // a block whose coinbase can not be read is reported and stored without fees
+    async fn fees(
+        &self,
+        archive: &SimpleFileBasedBlockArchive,
+        block_hash: &BlockHash,
+        height: u64,
+    ) -> Option<u64> {
+        if !self.enabled || !self.fees {
+            return None;
+        }
+        let fees = match archive.get_block(block_hash).await {
+            Ok(reader) => coinbase_fees(reader, height).await.map_err(CliError::from),
+            Err(e) => Err(e.into()),
+        };
+        match fees {
+            Ok(f) => Some(f),
+            Err(e) => {
+                println!("could not read the coinbase of block {}: {}", block_hash, e);
+                None
             }
         }
-        Ok(())
     }
+}
 
-    type Stage3Result = BlockInfo<<FDBChainStore as ChainStore>::BlockId>;
-    async fn stage3(
-        mut receiver: Receiver<Stage2Result>,
-        config: BSVDBConfig,
-        sender: Sender<Stage3Result>,
-    ) -> CliResult<()> {
-        let block_archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
-        while let Some(mut r) = receiver.recv().await {
-            let sz = block_archive
-                .block_size(&r.hash)
-                .await
-                .expect("get block size failed in stage3");
-            r.size = Some(sz as u64);
-            sender.send(r).await.expect("msg sending failed in stage3");
-        }
-        Ok(())
+// the subsidy of a block at the height, in satoshis, which halves every 210,000 blocks
+fn block_subsidy(height: u64) -> u64 {
+    5_000_000_000u64
+        .checked_shr((height / 210_000) as u32)
+        .unwrap_or(0)
+}
+
+// the fees claimed by the coinbase of the encoded block at the height, which is the value of its
+// outputs less the subsidy
+//
+// only the header and the coinbase, the first transaction, are read
+async fn coinbase_fees<R: AsyncRead + Unpin>(mut block: R, height: u64) -> std::io::Result<u64> {
+    let mut header = [0; 80];
+    block.read_exact(&mut header).await?;
+    read_compact_size(&mut block).await?;
+    // version and inputs
+    block.read_u32_le().await?;
+    for _ in 0..read_compact_size(&mut block).await? {
+        skip(&mut block, 36).await?;
+        let script = read_compact_size(&mut block).await?;
+        skip(&mut block, script + 4).await?;
+    }
+    let mut value = 0u64;
+    for _ in 0..read_compact_size(&mut block).await? {
+        value = value.saturating_add(block.read_u64_le().await?);
+        let script = read_compact_size(&mut block).await?;
+        skip(&mut block, script).await?;
     }
+    Ok(value.saturating_sub(block_subsidy(height)))
+}
 
-    type Stage4Result = (
-        BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
-        Pin<
-            Box<
-                dyn Future<
-                        Output = Result<
-                            std::option::Option<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
-                        >,
-                    > + Send,
-            >,
-        >,
-    );
-    async fn stage4(
-        mut receiver: Receiver<Stage3Result>,
-        chain_store: FDBChainStore,
-        sender: Sender<Stage4Result>,
-    ) -> CliResult<()> {
-        while let Some(r) = receiver.recv().await {
-            let f = chain_store.get_block_info_by_hash(r.header.prev_hash);
-            sender.send((r, f)).await.expect("sending failed in stage4");
+// read a CompactSize, the variable length integer of the transactions
+async fn read_compact_size<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<u64> {
+    Ok(match r.read_u8().await? {
+        0xfd => r.read_u16_le().await? as u64,
+        0xfe => r.read_u32_le().await? as u64,
+        0xff => r.read_u64_le().await?,
+        n => n as u64,
+    })
+}
+
+// skip the next bytes of the reader, it is an error if there are fewer
+async fn skip<R: AsyncRead + Unpin>(r: &mut R, n: u64) -> std::io::Result<()> {
+    if tokio::io::copy(&mut r.take(n), &mut tokio::io::sink()).await? < n {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+// the number of hashes whose briefs are fetched from the chain store in one request
+const BRIEF_BATCH: usize = 1000;
+// the number of blocks that are read from the archive, or validated, at once
+const READ_PARALLEL: usize = 64;
+// the most blocks that are stored in a single transaction
+const STORE_BATCH: usize = 1000;
+// the file in the state directory of the archive that holds the checkpoint of the sync
+const CHECKPOINT_FILE: &str = "sync.checkpoint";
+
+/// The progress of [sync_piped()], a height up to which every block of the archive that links to
+/// genesis has been stored in the chain store, with the hash of a stored block at that height.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncCheckpoint {
+    pub height: u64,
+    pub hash: BlockHash,
+}
+
+impl SyncCheckpoint {
+    /// Read the checkpoint from the file, None if there is none or it can not be parsed.
+    pub async fn read(path: &Path) -> CliResult<Option<SyncCheckpoint>> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(text) => Ok(Self::parse(text.trim_end())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
         }
+    }
+
+    /// Write the checkpoint to the file, replacing it in a single step.
+    pub async fn write(&self, path: &Path) -> CliResult<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, format!("{} {}\n", self.height, self.hash)).await?;
+        tokio::fs::rename(&tmp, path).await?;
         Ok(())
     }
 
-    type Stage5Result = (
-        BTreeMap<BlockHash, BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
-        BTreeMap<BlockHash, Vec<BlockHash>>,
-        BTreeSet<BlockHash>,
-    );
-    async fn stage5(mut receiver: Receiver<Stage4Result>) -> CliResult<Stage5Result> {
-        let mut b_infos = BTreeMap::new();
-        let mut parents_children = BTreeMap::new();
-        let mut known_parents = BTreeSet::new();
-        let start_time = Instant::now();
-        let mut found = 0;
-        while let Some((b_info, j)) = receiver.recv().await {
-            let r = j.await.unwrap();
-            if let Some(h) = r {
-                known_parents.insert(h.hash);
-            }
-            match parents_children.get(&b_info.header.prev_hash) {
-                None => {
-                    parents_children.insert(b_info.header.prev_hash, vec![b_info.hash]);
-                }
-                Some(v) => {
-                    let mut v2 = v.clone();
-                    v2.push(b_info.hash);
-                    parents_children.insert(b_info.header.prev_hash, v2);
-                }
-            }
-            b_infos.insert(b_info.hash, b_info);
-            found += 1;
-            if found % 10_000 == 0 {
-                let dur = start_time.elapsed();
-                println!(
-                    "found {} blocks, {} blocks/sec",
-                    found,
-                    (found as f32) / dur.as_secs_f32()
-                );
-            }
-        }
-        Ok((b_infos, parents_children, known_parents))
+    // parse the line "<height> <hash>" of the file
+    fn parse(line: &str) -> Option<SyncCheckpoint> {
+        let (height, hash) = line.split_once(' ')?;
+        Some(SyncCheckpoint {
+            height: height.parse().ok()?,
+            hash: hash.parse().ok()?,
+        })
     }
+}
 
+// synchronize chainstore from blockstore, storing the missing blocks in batches in height order
+//
+// the single-threaded approach achieved 40-43 blocks/sec, it read the blocks and stored their
+// block infos one at a time. the sync is now in three stages:
+//
+// 1 - list the hashes of the archive and fetch their briefs from the chain store in batches, the
+//      blocks that are already stored are skipped
+// 2 - read the headers of all of the missing blocks from the archive, READ_PARALLEL at a time,
+//      which is 80 bytes for each block, then chain them to their parents and order them by
+//      height, see plan_batches(). each batch of at most STORE_BATCH blocks is a branch whose
+//      parent is stored before it
+// 3 - for each batch, in height order, read the number of transactions and the size of its
+//      blocks, validate their bodies if enabled, READ_PARALLEL at a time, and store the batch in a
+//      single transaction with store_block_infos()
+//
+// each block is read once for its header and once for its body, whatever the order of the
+// archive, and only the headers are held in memory. the rate of each batch is printed as it is
+// stored. the throughput of this version has not been measured yet.
+//
+// a batch is stored atomically and after its parent, so the stored blocks always reach back to
+// genesis, and a sync that was interrupted is resumed by running it again, which skips the blocks
+// that were stored in stage 1. after each batch the highest height up to which all of the blocks
+// are stored is written to a checkpoint, see SyncCheckpoint, in the state directory of the
+// archive. a rerun resumes from the checkpoint if its block is still in the chain store, and
+// reports the blocks at or below it that have been added to the archive since, which are stored
+// along with the rest. a checkpoint whose block is not in the chain store, for example because
+// the chain store was replaced, is ignored and the sync relies on the stored blocks alone.
+//
+// the bodies of the blocks are validated before their block infos are stored if validation is
+// enabled, see BodyValidation
+pub async fn sync_piped(config: &BSVDBConfig, validation: BodyValidation) -> CliResult<()> {
     config.check_block_archive_enabled()?;
     config.check_chain_store_enabled()?;
-    let fdb_boot = unsafe { foundationdb::boot() };
+    let fdb_boot = unsafe { bsvdb_chainstore::boot(&config.chain_store) }?;
 
     println!("starting sync from blockstore to chainstore");
     let mut block_archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    let (chain_store, j) =
+        FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    // the whole archive is stored, most of it far below the tip
+    chain_store.set_initial_sync(true);
+    let tip = most_work_tip(&chain_store).await?;
+    println!("the chainstore has blocks up to height {}", tip.height);
+    tokio::fs::create_dir_all(block_archive.state_path()).await?;
+    let checkpoint_path = block_archive.state_path().join(CHECKPOINT_FILE);
+    let checkpoint = SyncCheckpoint::read(&checkpoint_path).await?;
+
     println!("fetching all block hashes...");
     let mut i = block_archive.block_list().await?;
     let mut block_hashes = vec![];
     while let Some(b) = i.next().await {
-        block_hashes.push(b);
+        block_hashes.push(b?);
     }
+    i.finish().await?;
     println!("done got {} hashes", block_hashes.len());
 
-    let (chain_store, _j_chain_store) =
-        FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
-    let (sender, receiver) = channel(BUFFER_SIZE);
-    let f_stage1 = stage1(block_hashes, chain_store.clone(), sender);
-    let j_stage1 = tokio::spawn(f_stage1);
+    // stage 1
+    let mut known = known_heights(&chain_store, &block_hashes).await?;
+    let missing: Vec<BlockHash> = block_hashes
+        .into_iter()
+        .filter(|h| !known.contains_key(h))
+        .collect();
+    println!("{} blocks are missing from the chainstore", missing.len());
+    let resume_height = match checkpoint {
+        Some(c) if known.get(&c.hash) == Some(&c.height) => {
+            println!("resuming from the checkpoint at height {}", c.height);
+            Some(c.height)
+        }
+        Some(c) => {
+            println!(
+                "the checkpoint at height {} is not in the chainstore, it is ignored",
+                c.height
+            );
+            None
+        }
+        None => None,
+    };
 
-    let (s2, r2) = channel(BUFFER_SIZE);
-    let c = (*config).clone();
-    let f_stage2 = stage2(receiver, c, s2);
-    let j_stage2 = tokio::spawn(f_stage2);
+    // stage 2, the parents outside of the archive are looked up in the chain store
+    let mut waiting = Vec::with_capacity(missing.len());
+    for header in block_archive.block_headers(&missing, READ_PARALLEL).await {
+        waiting.push(header_block_info(header?));
+    }
+    let missing_set: BTreeSet<BlockHash> = missing.into_iter().collect();
+    let lookup: Vec<BlockHash> = waiting
+        .iter()
+        .map(|b| b.header.prev_hash)
+        .filter(|h| !missing_set.contains(h) && !known.contains_key(h))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    known.extend(known_heights(&chain_store, &lookup).await?);
+    let (batches, orphans) = plan_batches(waiting, &known, STORE_BATCH);
+    if let Some(height) = resume_height {
+        let below = batches
+            .iter()
+            .flatten()
+            .filter(|b| b.height <= height)
+            .count();
+        if below > 0 {
+            println!(
+                "{} blocks at or below the checkpoint were added to the blockarchive since",
+                below
+            );
+        }
+    }
+    // a stored block at each height, for the checkpoints
+    let mut at_height: BTreeMap<u64, BlockHash> = known.iter().map(|(h, n)| (*n, *h)).collect();
+    at_height.extend(batches.iter().flatten().map(|b| (b.height, b.hash)));
+    let top = at_height.keys().last().copied();
+    let starts: Vec<u64> = batches.iter().map(|b| b[0].height).collect();
 
-    let (s3, r3) = channel(BUFFER_SIZE);
-    let f_stage3 = stage3(r2, (*config).clone(), s3);
-    let j_stage3 = tokio::spawn(f_stage3);
+    // stage 3
+    let start_time = Instant::now();
+    let mut added = 0;
+    for (n, batch) in batches.into_iter().enumerate() {
+        let batch_start = Instant::now();
+        let heights = (batch[0].height, batch[batch.len() - 1].height);
+        let stored = store_batch(&block_archive, &chain_store, batch, validation).await?;
+        added += stored;
+        // every block below the start of the next batch has been stored
+        if let Some(start) = starts.get(n + 1) {
+            write_checkpoint(&checkpoint_path, &at_height, start - 1).await?;
+        }
+        println!(
+            "stored heights {}-{}, {:.0} blocks/sec, added {} blocks, {:.0} blocks/sec overall",
+            heights.0,
+            heights.1,
+            stored as f32 / batch_start.elapsed().as_secs_f32(),
+            added,
+            added as f32 / start_time.elapsed().as_secs_f32()
+        );
+    }
+    if let Some(top) = top {
+        write_checkpoint(&checkpoint_path, &at_height, top).await?;
+    }
+    println!("finished sync. added {} blocks.", added);
+    if !orphans.is_empty() {
+        println!(
+            "{} blocks were not added, their parents are not in the blockarchive or the chainstore",
+            orphans.len()
+        );
+    }
 
-    let (s4, r4) = channel(BUFFER_SIZE);
-    let f_stage4 = stage4(r3, chain_store.clone(), s4);
-    let j_stage4 = tokio::spawn(f_stage4);
+    chain_store.shutdown().await?;
+    j.await?;
+    drop(fdb_boot);
+    Ok(())
+}
 
-    let f_stage5 = stage5(r4);
-    let j_stage5 = tokio::spawn(f_stage5);
+// write the checkpoint at the height, with the hash of the stored block at that height
+async fn write_checkpoint(
+    path: &Path,
+    at_height: &BTreeMap<u64, BlockHash>,
+    height: u64,
+) -> CliResult<()> {
+    match at_height.get(&height) {
+        Some(hash) => {
+            SyncCheckpoint {
+                height,
+                hash: *hash,
+            }
+            .write(path)
+            .await
+        }
+        None => Ok(()),
+    }
+}
 
-    let _ = j_stage1.await?;
-    let _ = j_stage2.await?;
-    let _ = j_stage3.await?;
-    let _ = j_stage4.await?;
+// a BlockInfo of the header alone, the rest is filled in when the block is read for its batch
+fn header_block_info(header: BlockHeader) -> BlockInfo<<FDBChainStore as ChainStore>::BlockId> {
+    BlockInfo {
+        id: 0u64,
+        hash: header.hash(),
+        header,
+        height: 0,
+        prev_id: 0u64,
+        next_ids: vec![],
+        size: None,
+        num_tx: None,
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity: BlockValidity::Unknown,
+        received_time: None,
+        source: None,
+    }
+}
 
-    let (block_infos, mut parent_children, mut known_parents) = j_stage5.await?.unwrap();
-    println!(
-        "found {} new headers, with {} known parents",
-        block_infos.len(),
-        known_parents.len()
-    );
+// the block info of the most work tip
+async fn most_work_tip(
+    chain_store: &FDBChainStore,
+) -> CliResult<BlockInfo<<FDBChainStore as ChainStore>::BlockId>> {
+    let state = chain_store.get_chain_state().await?;
+    Ok(chain_store
+        .get_block_info(state.most_work_tip)
+        .await?
+        .ok_or(Error::BlockInfoNotFound(state.most_work_tip))?)
+}
 
-    let mut added = 0;
-    while !known_parents.is_empty() {
-        let p_hash = known_parents.pop_first().unwrap();
-        if let Some(c_hashes) = parent_children.get(&p_hash) {
-            for c_hash in c_hashes {
-                let b_info = block_infos.get(c_hash).unwrap();
-                let b_info = chain_store.store_block_info(b_info.clone()).await.unwrap();
-                // this can now be a known parent
-                known_parents.insert(b_info.hash);
-                added += 1;
-                if added % 10_000 == 0 {
-                    println!("added {} blocks.", added);
-                }
+// the heights of the blocks that are in the chain store, fetching their briefs in batches
+async fn known_heights(
+    chain_store: &FDBChainStore,
+    block_hashes: &[BlockHash],
+) -> CliResult<BTreeMap<BlockHash, u64>> {
+    let mut briefs = stream::iter(block_hashes.chunks(BRIEF_BATCH))
+        .map(|batch| async move {
+            let briefs = chain_store.get_block_briefs(batch.to_vec()).await?;
+            Ok::<_, Error>(batch.iter().copied().zip(briefs).collect::<Vec<_>>())
+        })
+        .buffer_unordered(4);
+    let mut heights = BTreeMap::new();
+    while let Some(r) = briefs.next().await {
+        for (h, brief) in r? {
+            if let Some(brief) = brief {
+                heights.insert(h, brief.height);
             }
-            parent_children.remove(&p_hash);
         }
     }
-    println!("finished sync. added {} blocks.", added);
+    Ok(heights)
+}
 
+/// Plan the storage of the blocks, whose parents are either among the blocks or in the chain
+/// store, at the heights in stored.
+///
+/// The blocks are given their heights and ordered by height, then split into batches of at most
+/// batch_size blocks which each form a branch, every block the child of the block before it. A
+/// batch is always after the batch or the stored block that holds the parent of its first block,
+/// so the batches can be stored in order. Returns the batches, and the blocks whose parent is
+/// neither among the blocks nor stored.
+pub fn plan_batches(
+    blocks: Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+    stored: &BTreeMap<BlockHash, u64>,
+    batch_size: usize,
+) -> (
+    Vec<Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+    Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+) {
+    let mut children: BTreeMap<BlockHash, Vec<_>> = BTreeMap::new();
+    for b in blocks {
+        children.entry(b.header.prev_hash).or_default().push(b);
+    }
+    // give the blocks their heights, working up from the stored parents
+    let mut parents: Vec<(BlockHash, u64)> = stored
+        .iter()
+        .filter(|(h, _)| children.contains_key(h))
+        .map(|(h, height)| (*h, *height))
+        .collect();
+    let mut placed = Vec::new();
+    while let Some((hash, height)) = parents.pop() {
+        for mut b in children.remove(&hash).unwrap_or_default() {
+            b.height = height + 1;
+            parents.push((b.hash, b.height));
+            placed.push(b);
+        }
+    }
+    let orphans = children.into_values().flatten().collect();
+    placed.sort_by_key(|b| b.height);
+    // extend the batch that ends with the parent, if it is not full, otherwise start a new one
+    let mut batches: Vec<Vec<_>> = Vec::new();
+    let mut ends = BTreeMap::new();
+    for b in placed {
+        let hash = b.hash;
+        let i = match ends.remove(&b.header.prev_hash) {
+            Some(i) if batches[i].len() < batch_size => i,
+            _ => {
+                batches.push(Vec::new());
+                batches.len() - 1
+            }
+        };
+        batches[i].push(b);
+        ends.insert(hash, i);
+    }
+    (batches, orphans)
+}
+
+// read the bodies of the batch for their number of transactions and size, validate them and read
+// their fees if enabled, and store the batch in a single transaction, returns the number of blocks stored
+async fn store_batch(
+    archive: &SimpleFileBasedBlockArchive,
+    chain_store: &FDBChainStore,
+    batch: Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>,
+    validation: BodyValidation,
+) -> CliResult<usize> {
+    let batch: Vec<_> = stream::iter(batch)
+        .map(|b| async move {
+            // the archive does not record where its blocks came from
+            let mut full = read_block_info(archive, &b.hash, None).await?;
+            full.height = b.height;
+            full.validity = validation.validity(archive, &b.hash, b.height).await;
+            full.total_fees = validation.fees(archive, &b.hash, b.height).await;
+            Ok::<_, CliError>(full)
+        })
+        .buffered(READ_PARALLEL)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<CliResult<_>>()?;
+    Ok(chain_store.store_block_infos(batch).await?.len())
+}
+
+// resolve the outstanding intents of an interrupted import, see recover_intents()
+pub async fn sync_recover_intents(config: &BSVDBConfig) -> CliResult<()> {
+    config.check_block_archive_enabled()?;
+    config.check_chain_store_enabled()?;
+    let fdb_boot = unsafe { bsvdb_chainstore::boot(&config.chain_store) }?;
+    let mut archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    archive.genesis = Some(config.get_blockchain_id());
+    let log = IntentLog::open(&archive).await?;
+    let (chain_store, j) =
+        FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    let (complete, discarded) = recover_intents(&archive, &chain_store, &log, &mut Apply).await?;
+    println!(
+        "resolved {} intents, {} blocks complete, {} blocks discarded",
+        complete + discarded,
+        complete,
+        discarded
+    );
+    chain_store.shutdown().await?;
+    j.await?;
     drop(fdb_boot);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::{BlockHeader, BlockchainId};
+    use bsvdb_chainstore::test_util::{generate_branch, generate_chain, header_info};
+
+    // The checkpoint is written and read back, and a missing or unreadable file is no checkpoint.
+    #[tokio::test]
+    async fn checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CHECKPOINT_FILE);
+        assert_eq!(SyncCheckpoint::read(&path).await.unwrap(), None);
+        let c = SyncCheckpoint {
+            height: 12,
+            hash: BlockHeader::get_genesis(BlockchainId::Main).hash(),
+        };
+        c.write(&path).await.unwrap();
+        assert_eq!(SyncCheckpoint::read(&path).await.unwrap(), Some(c));
+        tokio::fs::write(&path, "12").await.unwrap();
+        assert_eq!(SyncCheckpoint::read(&path).await.unwrap(), None);
+    }
+
+    // The fees are the value of the outputs of the coinbase less the subsidy at the height.
+    #[tokio::test]
+    async fn fees() {
+        // a header, one transaction, and a coinbase with an input of a 3 byte script and two
+        // outputs
+        let mut block = vec![0; 80];
+        block.extend([1, 1, 0, 0, 0, 1]);
+        block.extend([0; 32]);
+        block.extend([0xff; 4]);
+        block.extend([3, 1, 2, 3]);
+        block.extend([0xff; 4]);
+        block.push(2);
+        block.extend(5_000_000_000u64.to_le_bytes());
+        block.extend([1, 0x51]);
+        block.extend(1_000u64.to_le_bytes());
+        block.extend([0]);
+        block.extend([0; 4]);
+        assert_eq!(coinbase_fees(block.as_slice(), 1).await.unwrap(), 1_000);
+        assert_eq!(
+            coinbase_fees(block.as_slice(), 210_000).await.unwrap(),
+            2_500_001_000
+        );
+        assert_eq!(
+            coinbase_fees(block.as_slice(), 64 * 210_000).await.unwrap(),
+            5_000_001_000
+        );
+        assert!(coinbase_fees(&block[..120], 1).await.is_err());
+    }
+
+    // The blocks are given their heights and split into branches, each stored after its parent.
+    #[test]
+    fn plan() {
+        let genesis = BlockHeader::get_genesis(BlockchainId::Main).hash();
+        let chain = generate_chain(10, BlockchainId::Main);
+        let fork = generate_branch(&chain[4], 3, 1, false);
+        let orphan = generate_branch(&chain[9], 2, 2, false)[1].clone();
+        let mut headers: Vec<_> = chain.iter().chain(fork.iter()).collect();
+        headers.push(&orphan);
+        headers.reverse();
+        let blocks = headers
+            .into_iter()
+            .map(|h| header_info(h, BlockValidity::Unknown))
+            .collect();
+        let stored = BTreeMap::from([(genesis, 0)]);
+        let (batches, orphans) = plan_batches(blocks, &stored, 4);
+        let orphans: Vec<_> = orphans.iter().map(|b| b.hash).collect();
+        assert_eq!(orphans, vec![orphan.hash()]);
+        let first: Vec<_> = batches[0].iter().map(|b| b.hash).collect();
+        let expected: Vec<_> = chain[..4].iter().map(|h| h.hash()).collect();
+        assert_eq!(first, expected);
+        let mut heights = stored.clone();
+        for batch in batches.iter() {
+            assert!(!batch.is_empty() && batch.len() <= 4);
+            assert!(heights.contains_key(&batch[0].header.prev_hash));
+            for pair in batch.windows(2) {
+                assert_eq!(pair[1].header.prev_hash, pair[0].hash);
+            }
+            for b in batch {
+                assert_eq!(b.height, heights[&b.header.prev_hash] + 1);
+                heights.insert(b.hash, b.height);
+            }
+        }
+        assert_eq!(heights.len(), 14);
+        assert_eq!(heights[&chain[9].hash()], 10);
+        assert_eq!(heights[&fork[2].hash()], 8);
+    }
+}
@@ -0,0 +1,707 @@
+use crate::ba::{rpc_client_from_uri, verify_block};
+use crate::dryrun::Effects;
+use crate::result::{CliError, CliResult};
+use crate::select::BlockSelector;
+use async_trait::async_trait;
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader, FullBlockStream};
+use bitcoinsv_rpc::Client;
+use bsvdb_base::BSVDBConfig;
+use bsvdb_blockarchive::{
+    BlockArchive, Error as BlockArchiveError, Intent, IntentLog, SimpleFileBasedBlockArchive,
+};
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+// the size of an encoded block header
+const HEADER_SIZE: usize = 80;
+
+/// A source of blocks that are not yet in the BlockArchive.
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    /// The name of the source, as used in the configuration and in the results.
+    fn name(&self) -> &str;
+
+    /// Fetch a block from the source, returning a reader for the encoded block.
+    async fn get_block(
+        &self,
+        block_hash: &BlockHash,
+    ) -> CliResult<Box<dyn AsyncRead + Unpin + Send>>;
+}
+
+/// A [BlockSource] that fetches blocks from an SV Node over RPC.
+pub struct RpcBlockSource {
+    client: Client,
+}
+
+impl RpcBlockSource {
+    /// Create a new RpcBlockSource, returns None if the URI could not be parsed.
+    pub fn new(rpc_uri: &str) -> Option<RpcBlockSource> {
+        rpc_client_from_uri(rpc_uri).map(|client| RpcBlockSource { client })
+    }
+}
+
+#[async_trait]
+impl BlockSource for RpcBlockSource {
+    fn name(&self) -> &str {
+        "rpc"
+    }
+
+    async fn get_block(
+        &self,
+        block_hash: &BlockHash,
+    ) -> CliResult<Box<dyn AsyncRead + Unpin + Send>> {
+        self.client
+            .get_block_binary(block_hash)
+            .await
+            .map_err(|e| CliError::Source(format!("{}", e)))
+    }
+}
+
+/// The outcome of importing a single block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportOutcome {
+    /// The block was already in the archive.
+    AlreadyPresent,
+    /// The block was fetched from the named source.
+    Fetched(String),
+    /// The block could not be imported, for the given reason.
+    Failed(String),
+    /// The block is not in the archive and would have been fetched, see
+    /// [DryRun](crate::dryrun::DryRun).
+    Planned,
+}
+
+impl std::fmt::Display for ImportOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportOutcome::AlreadyPresent => write!(f, "already-present"),
+            ImportOutcome::Fetched(source) => write!(f, "fetched-from-{}", source),
+            ImportOutcome::Failed(reason) => write!(f, "failed-with-reason: {}", reason),
+            ImportOutcome::Planned => write!(f, "planned"),
+        }
+    }
+}
+
+/// Blocks whose parent is not yet in the ChainStore, indexed by the hash of the parent.
+#[derive(Default)]
+pub struct OrphanPool {
+    orphans: BTreeMap<BlockHash, Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>>,
+}
+
+impl OrphanPool {
+    /// Add a block to the pool.
+    pub fn add(&mut self, block_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>) {
+        self.orphans
+            .entry(block_info.header.prev_hash)
+            .or_default()
+            .push(block_info);
+    }
+
+    /// Remove and return the children of the given block.
+    pub fn take_children(
+        &mut self,
+        parent: &BlockHash,
+    ) -> Vec<BlockInfo<<FDBChainStore as ChainStore>::BlockId>> {
+        self.orphans.remove(parent).unwrap_or_default()
+    }
+
+    /// The hashes of the blocks remaining in the pool.
+    pub fn hashes(&self) -> Vec<BlockHash> {
+        self.orphans.values().flatten().map(|i| i.hash).collect()
+    }
+}
+
+/// Build the list of sources to try, either the single source given or the configured sources in
+/// priority order.
+///
+/// Only the "rpc" source is currently available.
+pub fn build_sources(
+    config: &BSVDBConfig,
+    source: Option<String>,
+    rpc_uri: Option<String>,
+) -> CliResult<Vec<Box<dyn BlockSource>>> {
+    let names = match source {
+        Some(s) => vec![s],
+        None => config.import.sources.clone(),
+    };
+    let rpc_uri = rpc_uri.unwrap_or_else(|| config.import.rpc_uri.clone());
+    let mut sources: Vec<Box<dyn BlockSource>> = Vec::new();
+    for name in names {
+        match name.as_str() {
+            "rpc" => match RpcBlockSource::new(&rpc_uri) {
+                Some(s) => sources.push(Box::new(s)),
+                None => {
+                    return Err(CliError::Source(String::from("could not parse RPC URI")));
+                }
+            },
+            "bsvdb" | "p2p" => {
+                return Err(CliError::Source(format!(
+                    "source {} is not supported yet",
+                    name
+                )));
+            }
+            _ => return Err(CliError::Source(format!("unknown source {}", name))),
+        }
+    }
+    Ok(sources)
+}
+
+// Fetch a block from the source and check that the hash of its header matches the expected hash.
+//
+// Returns a reader for the complete encoded block.
+async fn fetch_verified(
+    source: &dyn BlockSource,
+    block_hash: &BlockHash,
+) -> CliResult<Box<dyn AsyncRead + Unpin + Send>> {
+    let mut reader = source.get_block(block_hash).await?;
+    let mut hdr = vec![0u8; HEADER_SIZE];
+    reader
+        .read_exact(&mut hdr)
+        .await
+        .map_err(|e| CliError::Source(format!("error reading header: {}", e)))?;
+    let header = BlockHeader::from_binary_buf(&hdr)
+        .map_err(|e| CliError::Source(format!("error decoding header: {}", e)))?;
+    if header.hash() != *block_hash {
+        return Err(CliError::Source(format!(
+            "header hash mismatch, received {}",
+            header.hash()
+        )));
+    }
+    Ok(Box::new(Cursor::new(hdr).chain(reader)))
+}
+
+// Import a single block, trying each source in turn until one succeeds.
+//
+// If intents is given, then an intent is recorded before the block is stored, which is cleared
+// once the BlockInfo of the block has been inserted in the ChainStore.
+async fn import_block<A>(
+    archive: &A,
+    block_hash: BlockHash,
+    sources: &[Box<dyn BlockSource>],
+    intents: Option<&IntentLog>,
+) -> ImportOutcome
+where
+    A: BlockArchive + Sync + ?Sized,
+{
+    match archive.block_exists(&block_hash).await {
+        Ok(true) => return ImportOutcome::AlreadyPresent,
+        Ok(false) => {}
+        Err(e) => return ImportOutcome::Failed(format!("error checking archive: {}", e)),
+    }
+    let mut reasons = Vec::new();
+    for source in sources {
+        match fetch_verified(source.as_ref(), &block_hash).await {
+            Ok(mut reader) => {
+                if let Some(log) = intents {
+                    if let Err(e) = log.record(&block_hash, None).await {
+                        return ImportOutcome::Failed(format!("error recording intent: {}", e));
+                    }
+                }
+                return match archive.store_block(&block_hash, &mut reader).await {
+                    Ok(_) => ImportOutcome::Fetched(String::from(source.name())),
+                    Err(BlockArchiveError::BlockExists) => ImportOutcome::AlreadyPresent,
+                    Err(e) => ImportOutcome::Failed(format!("error storing block: {}", e)),
+                };
+            }
+            Err(e) => reasons.push(format!("{}: {}", source.name(), e)),
+        }
+    }
+    if reasons.is_empty() {
+        ImportOutcome::Failed(String::from("no sources available"))
+    } else {
+        ImportOutcome::Failed(reasons.join("; "))
+    }
+}
+
+/// Ensure that the blocks are in the archive, fetching missing blocks from the sources.
+///
+/// Fetches at most max_parallel blocks concurrently. The results are in the same order as the
+/// hashes. If intents is given, then an intent is recorded for each block before it is stored, see
+/// [IntentLog].
+pub async fn import_hashes<A>(
+    archive: &A,
+    hashes: Vec<BlockHash>,
+    sources: &[Box<dyn BlockSource>],
+    max_parallel: usize,
+    intents: Option<&IntentLog>,
+) -> Vec<(BlockHash, ImportOutcome)>
+where
+    A: BlockArchive + Sync + ?Sized,
+{
+    futures::stream::iter(hashes)
+        .map(|h| async move { (h, import_block(archive, h, sources, intents).await) })
+        .buffered(max_parallel.max(1))
+        .collect()
+        .await
+}
+
+/// Write the results to a file, one line per hash.
+pub async fn write_results(path: &str, results: &[(BlockHash, ImportOutcome)]) -> CliResult<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    for (h, outcome) in results {
+        file.write_all(format!("{}\t{}\n", h, outcome).as_bytes())
+            .await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Build a BlockInfo for a block in the archive, that was imported from the source.
+pub async fn read_block_info<A>(
+    archive: &A,
+    block_hash: &BlockHash,
+    source: Option<String>,
+) -> CliResult<BlockInfo<<FDBChainStore as ChainStore>::BlockId>>
+where
+    A: BlockArchive + Sync + ?Sized,
+{
+    let reader = archive.get_block(block_hash).await?;
+    let it = FullBlockStream::new(reader)
+        .await
+        .map_err(BlockArchiveError::from)?;
+    let size = archive.block_size(block_hash).await?;
+    Ok(BlockInfo {
+        id: 0u64,
+        hash: it.block_header.hash(),
+        header: it.block_header,
+        height: 0,
+        prev_id: 0u64,
+        next_ids: vec![],
+        size: Some(size as u64),
+        num_tx: Some(it.num_tx),
+        median_time: None,
+        chain_work: None,
+        total_tx: None,
+        total_size: None,
+        total_fees: None,
+        miner: None,
+        validity: BlockValidity::Unknown,
+        received_time: None,
+        source,
+    })
+}
+
+/// Insert the BlockInfos for the blocks into the ChainStore, each with the name of the source
+/// that it was imported from, if it is known, see [BlockInfo::source].
+///
+/// Blocks whose parent is not yet in the ChainStore are queued in an [OrphanPool] until their
+/// parent is stored. Returns the hashes of the blocks that are still orphaned at the end.
+pub async fn insert_block_infos<A>(
+    archive: &A,
+    chain_store: &FDBChainStore,
+    blocks: Vec<(BlockHash, Option<String>)>,
+    effects: &mut dyn Effects,
+) -> CliResult<Vec<BlockHash>>
+where
+    A: BlockArchive + Sync + ?Sized,
+{
+    let mut pool = OrphanPool::default();
+    for (h, source) in blocks {
+        if effects.has_block_info(chain_store, h).await? {
+            continue;
+        }
+        let b_info = read_block_info(archive, &h, source).await?;
+        store_or_hold(chain_store, &mut pool, b_info, effects).await?;
+    }
+    Ok(pool.hashes())
+}
+
+/// Store a BlockInfo in the ChainStore followed by any orphans in the pool that were waiting for
+/// it, or add it to the pool if its parent is not yet in the ChainStore.
+///
+/// Returns the number of BlockInfos that were stored.
+pub async fn store_or_hold(
+    chain_store: &FDBChainStore,
+    pool: &mut OrphanPool,
+    b_info: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    effects: &mut dyn Effects,
+) -> CliResult<u64> {
+    if !effects
+        .has_block_info(chain_store, b_info.header.prev_hash)
+        .await?
+    {
+        pool.add(b_info);
+        return Ok(0);
+    }
+    let mut stored = 0;
+    let mut stack = vec![b_info];
+    while let Some(b_info) = stack.pop() {
+        let hash = b_info.hash;
+        effects.store_block_info(chain_store, b_info).await?;
+        stored += 1;
+        stack.extend(pool.take_children(&hash));
+    }
+    Ok(stored)
+}
+
+/// The resolution of an outstanding intent, see [resolve_intent()].
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntentResolution {
+    /// The block is complete in the archive, its BlockInfo is inserted if it is missing.
+    Insert,
+    /// The block was not stored, or was incomplete and has been removed.
+    Discard,
+}
+
+/// Resolve an outstanding intent by checking its block in the archive.
+///
+/// A block that can not be read, does not match its hash or merkle root, or does not have the
+/// expected size, is the remnant of an interrupted write and is removed.
+pub async fn resolve_intent(
+    archive: &SimpleFileBasedBlockArchive,
+    intent: &Intent,
+) -> CliResult<IntentResolution> {
+    let intact = match verify_block(archive, &intent.hash).await {
+        Ok(v) => v.passed(&intent.hash),
+        Err(BlockArchiveError::BlockNotFound(_)) => return Ok(IntentResolution::Discard),
+        Err(_) => false,
+    };
+    let complete = match intent.expected_size {
+        Some(size) => intact && archive.block_size(&intent.hash).await? as u64 == size,
+        None => intact,
+    };
+    if complete {
+        Ok(IntentResolution::Insert)
+    } else {
+        archive.remove_block(&intent.hash).await?;
+        Ok(IntentResolution::Discard)
+    }
+}
+
+/// Resolve the outstanding intents of an interrupted import, so that the archive and the
+/// ChainStore agree on the blocks, see [IntentLog].
+///
+/// Only the blocks with an outstanding intent are checked, see [resolve_intent()]. The intents
+/// are cleared once they are resolved. Returns the number of intents of complete blocks and of
+/// discarded blocks.
+pub async fn recover_intents(
+    archive: &SimpleFileBasedBlockArchive,
+    chain_store: &FDBChainStore,
+    log: &IntentLog,
+    effects: &mut dyn Effects,
+) -> CliResult<(u64, u64)> {
+    let mut complete = Vec::new();
+    let mut discarded = 0;
+    for intent in log.outstanding().await? {
+        match resolve_intent(archive, &intent).await? {
+            IntentResolution::Insert => complete.push(intent.hash),
+            IntentResolution::Discard => {
+                log.clear(&intent.hash).await?;
+                discarded += 1;
+            }
+        }
+    }
+    // the source of a block is not recorded in its intent
+    let blocks = complete.iter().map(|h| (*h, None)).collect();
+    let orphans = insert_block_infos(archive, chain_store, blocks, effects).await?;
+    for h in orphans.iter() {
+        println!("parent of block {} not in chainstore, not inserted", h);
+    }
+    for h in complete.iter() {
+        log.clear(h).await?;
+    }
+    Ok((complete.len() as u64, discarded))
+}
+
+/// Import the blocks selected by the selector, such as the hashes listed in a file.
+///
+/// Invalid lines are reported and skipped. The outcome for each hash is written to the results
+/// file, except in a dry run. If with_chain_store is set, then the BlockInfos of the blocks are
+/// also inserted into the ChainStore, and the intents of an earlier import that was interrupted
+/// are recovered first, see [recover_intents()].
+#[allow(clippy::too_many_arguments)]
+pub async fn hashes_import(
+    config: &BSVDBConfig,
+    selector: BlockSelector,
+    source: Option<String>,
+    rpc_uri: Option<String>,
+    with_chain_store: bool,
+    results_file: String,
+    verbose: bool,
+    effects: &mut dyn Effects,
+) -> CliResult<()> {
+    config.check_block_archive_enabled()?;
+    let fdb_boot = (with_chain_store || selector.needs_chain_store())
+        .then(|| unsafe { bsvdb_chainstore::boot(&config.chain_store) })
+        .transpose()?;
+    let select_store = selector.open_chain_store(config).await?;
+    let selection = selector
+        .resolve(config, select_store.as_ref().map(|(c, _)| c))
+        .await?;
+    let mut hashes = Vec::new();
+    let mut selected = selection.hashes;
+    while let Some(h) = selected.next().await {
+        match h {
+            Ok(h) => hashes.push(h),
+            Err(e @ CliError::Select(_)) => println!("{}, skipped", e),
+            Err(e) => return Err(e),
+        }
+    }
+    drop(selected);
+    if let Some((chain_store, j)) = select_store {
+        chain_store.shutdown().await?;
+        j.await?;
+    }
+    let sources = build_sources(config, source, rpc_uri)?;
+    // the genesis block is never fetched, it is always present
+    let mut archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    archive.genesis = Some(config.get_blockchain_id());
+    let chain_store = if with_chain_store {
+        config.check_chain_store_enabled()?;
+        Some(FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?)
+    } else {
+        None
+    };
+    // the stores are only paired when they are both written
+    let intents = match &chain_store {
+        Some((chain_store, _)) if effects.plan().is_none() => {
+            let log = IntentLog::open(&archive).await?;
+            let (complete, discarded) =
+                recover_intents(&archive, chain_store, &log, effects).await?;
+            if complete + discarded > 0 {
+                println!(
+                    "recovered {} blocks and discarded {} blocks of an interrupted import",
+                    complete, discarded
+                );
+            }
+            Some(log)
+        }
+        _ => None,
+    };
+    let results = effects
+        .import_blocks(
+            &archive,
+            hashes,
+            &sources,
+            config.import.max_parallel,
+            intents.as_ref(),
+        )
+        .await;
+    let mut fetched = 0;
+    let mut failed = 0;
+    for (h, outcome) in results.iter() {
+        match outcome {
+            ImportOutcome::AlreadyPresent => {}
+            ImportOutcome::Fetched(_) | ImportOutcome::Planned => fetched += 1,
+            ImportOutcome::Failed(_) => failed += 1,
+        }
+        if verbose {
+            println!("{} {}", h, outcome);
+        }
+    }
+    if effects.plan().is_some() {
+        println!("checked {} blocks, {} to fetch", results.len(), fetched);
+    } else {
+        write_results(&results_file, &results).await?;
+        println!(
+            "checked {} blocks, fetched {}, failed {}. results written to {}",
+            results.len(),
+            fetched,
+            failed,
+            results_file
+        );
+    }
+
+    if let Some((chain_store, j)) = chain_store {
+        let present = results
+            .iter()
+            .filter_map(|(h, o)| match o {
+                ImportOutcome::AlreadyPresent => Some((*h, None)),
+                ImportOutcome::Fetched(source) => Some((*h, Some(source.clone()))),
+                _ => None,
+            })
+            .collect();
+        let orphans = insert_block_infos(&archive, &chain_store, present, effects).await?;
+        for h in orphans.iter() {
+            println!("parent of block {} not in chainstore, not inserted", h);
+        }
+        // the orphans are reported and are not the remnants of an interruption
+        if let Some(log) = intents.as_ref() {
+            for (h, _) in results
+                .iter()
+                .filter(|(_, o)| matches!(o, ImportOutcome::Fetched(_)))
+            {
+                log.clear(h).await?;
+            }
+        }
+        chain_store.shutdown().await?;
+        j.await?;
+    }
+    drop(fdb_boot);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsvdb_base::BlockArchiveConfig;
+    use tempfile::tempdir;
+
+    const BLOCK_1: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    const BLOCK_A8: &str = "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f";
+    const BLOCK_UNKNOWN: &str = "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1";
+
+    // A source that serves blocks from memory.
+    struct MockSource {
+        name: String,
+        blocks: BTreeMap<BlockHash, Vec<u8>>,
+    }
+
+    #[async_trait]
+    impl BlockSource for MockSource {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn get_block(
+            &self,
+            block_hash: &BlockHash,
+        ) -> CliResult<Box<dyn AsyncRead + Unpin + Send>> {
+            match self.blocks.get(block_hash) {
+                Some(b) => Ok(Box::new(Cursor::new(b.clone()))),
+                None => Err(CliError::Source(String::from("block not found"))),
+            }
+        }
+    }
+
+    fn hash(s: &str) -> BlockHash {
+        s.parse().unwrap()
+    }
+
+    // read a block from the test data
+    fn block_bytes(s: &str) -> Vec<u8> {
+        let path = format!(
+            "../testdata/blockarchive/{}/{}/{}.bin",
+            &s[62..],
+            &s[60..62],
+            s
+        );
+        std::fs::read(path).unwrap()
+    }
+
+    async fn empty_archive(root: &tempfile::TempDir) -> SimpleFileBasedBlockArchive {
+        let c = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from(root.path().to_str().unwrap()),
+            ..BlockArchiveConfig::default()
+        };
+        SimpleFileBasedBlockArchive::new(&c).await.unwrap()
+    }
+
+    // The first source fails so the block should be fetched from the second.
+    #[tokio::test]
+    async fn test_import_fallback() {
+        let root = tempdir().unwrap();
+        let archive = empty_archive(&root).await;
+        // store one block beforehand
+        let mut r: Box<dyn AsyncRead + Unpin + Send> = Box::new(Cursor::new(block_bytes(BLOCK_A8)));
+        archive.store_block(&hash(BLOCK_A8), &mut r).await.unwrap();
+        let rpc = MockSource {
+            name: String::from("rpc"),
+            blocks: BTreeMap::new(),
+        };
+        let mut blocks = BTreeMap::new();
+        blocks.insert(hash(BLOCK_1), block_bytes(BLOCK_1));
+        let second = MockSource {
+            name: String::from("mock"),
+            blocks,
+        };
+        let sources: Vec<Box<dyn BlockSource>> = vec![Box::new(rpc), Box::new(second)];
+        let hashes = vec![hash(BLOCK_A8), hash(BLOCK_1), hash(BLOCK_UNKNOWN)];
+        let results = import_hashes(&archive, hashes, &sources, 2, None).await;
+        assert_eq!(results[0], (hash(BLOCK_A8), ImportOutcome::AlreadyPresent));
+        assert_eq!(
+            results[1],
+            (hash(BLOCK_1), ImportOutcome::Fetched(String::from("mock")))
+        );
+        assert!(matches!(results[2].1, ImportOutcome::Failed(_)));
+        assert!(archive.block_exists(&hash(BLOCK_1)).await.unwrap());
+        assert_eq!(archive.block_size(&hash(BLOCK_1)).await.unwrap(), 215);
+
+        // check the results file
+        let path = root.path().join("results.txt");
+        let path = path.to_str().unwrap();
+        write_results(path, &results).await.unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("{}\talready-present", BLOCK_A8));
+        assert_eq!(lines[1], format!("{}\tfetched-from-mock", BLOCK_1));
+        assert!(lines[2].starts_with(&format!("{}\tfailed-with-reason: rpc: ", BLOCK_UNKNOWN)));
+        assert!(lines[2].contains("mock: "));
+    }
+
+    // A block whose header does not match the requested hash is rejected.
+    #[tokio::test]
+    async fn test_import_hash_mismatch() {
+        let root = tempdir().unwrap();
+        let archive = empty_archive(&root).await;
+        let mut blocks = BTreeMap::new();
+        blocks.insert(hash(BLOCK_1), block_bytes(BLOCK_A8));
+        let rpc = MockSource {
+            name: String::from("rpc"),
+            blocks,
+        };
+        let sources: Vec<Box<dyn BlockSource>> = vec![Box::new(rpc)];
+        let results = import_hashes(&archive, vec![hash(BLOCK_1)], &sources, 1, None).await;
+        assert!(matches!(&results[0].1, ImportOutcome::Failed(r) if r.contains("mismatch")));
+        assert!(!archive.block_exists(&hash(BLOCK_1)).await.unwrap());
+    }
+
+    // An interrupted import is resolved for each point at which it can stop: after the intent is
+    // recorded, while the block is stored, and after the block is stored but before its BlockInfo
+    // is inserted.
+    #[tokio::test]
+    async fn test_resolve_intents() {
+        let root = tempdir().unwrap();
+        let archive = empty_archive(&root).await;
+        let log = IntentLog::open(&archive).await.unwrap();
+        // only part of the first block is written
+        let partial = block_bytes(BLOCK_A8);
+        let mut blocks = BTreeMap::new();
+        blocks.insert(hash(BLOCK_A8), partial[..partial.len() / 2].to_vec());
+        blocks.insert(hash(BLOCK_1), block_bytes(BLOCK_1));
+        let source = MockSource {
+            name: String::from("mock"),
+            blocks,
+        };
+        let sources: Vec<Box<dyn BlockSource>> = vec![Box::new(source)];
+        let hashes = vec![hash(BLOCK_A8), hash(BLOCK_1)];
+        import_hashes(&archive, hashes, &sources, 1, Some(&log)).await;
+        log.record(&hash(BLOCK_UNKNOWN), None).await.unwrap();
+
+        let intents = log.outstanding().await.unwrap();
+        let mut resolutions = Vec::new();
+        for intent in intents.iter() {
+            resolutions.push(resolve_intent(&archive, intent).await.unwrap());
+        }
+        assert_eq!(
+            resolutions,
+            vec![
+                IntentResolution::Discard,
+                IntentResolution::Insert,
+                IntentResolution::Discard
+            ]
+        );
+        assert!(!archive.block_exists(&hash(BLOCK_A8)).await.unwrap());
+        assert!(archive.block_exists(&hash(BLOCK_1)).await.unwrap());
+        // resolving again gives the same result
+        assert_eq!(
+            resolve_intent(&archive, &intents[0]).await.unwrap(),
+            IntentResolution::Discard
+        );
+
+        // a block of the wrong size is incomplete
+        let mut intent = intents[1].clone();
+        intent.expected_size = Some(215);
+        let r = resolve_intent(&archive, &intent).await.unwrap();
+        assert_eq!(r, IntentResolution::Insert);
+        intent.expected_size = Some(214);
+        let r = resolve_intent(&archive, &intent).await.unwrap();
+        assert_eq!(r, IntentResolution::Discard);
+        assert!(!archive.block_exists(&hash(BLOCK_1)).await.unwrap());
+    }
+}
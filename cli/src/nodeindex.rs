@@ -0,0 +1,420 @@
+use crate::dryrun::Effects;
+use crate::import::{store_or_hold, OrphanPool};
+use crate::result::{CliError, CliResult};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
+use bsvdb_base::BSVDBConfig;
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
+use rusty_leveldb::{LdbIterator, Options, DB};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Reads the block index of an SV Node, the LevelDB database in blocks/index, to seed the
+// ChainStore without reading every block.
+//
+// Each block is a record whose key is 'b' followed by the block hash and whose value is the
+// serialized CDiskBlockIndex: the client version, the height, the status, the number of
+// transactions and the position of the block and undo data as VARINTs, followed by the block
+// header. Newer versions of the node insert extra fields before the header, so the header is
+// found by its hash rather than by its position.
+
+// the prefix of the keys of the block records
+const BLOCK_PREFIX: u8 = b'b';
+// the size of an encoded block header
+const HEADER_SIZE: usize = 80;
+
+// the flags of the status of a block in the node
+const VALIDITY_MASK: u64 = 0x07;
+const VALID_SCRIPTS: u64 = 5;
+const HAVE_DATA: u64 = 0x08;
+const HAVE_UNDO: u64 = 0x10;
+const FAILED_VALID: u64 = 0x20;
+const FAILED_CHILD: u64 = 0x40;
+const HAS_DISK_META_DATA: u64 = 0x80;
+// the size of the disk meta data, the hash and the size of the block data
+const DISK_META_DATA_SIZE: usize = 40;
+// the network magic that precedes each block in the blk files, for mainnet, testnet, regtest
+// and stn
+const DISK_MAGICS: [[u8; 4]; 4] = [
+    [0xe3, 0xe1, 0xf3, 0xe8],
+    [0xf4, 0xe5, 0xf3, 0xf4],
+    [0xda, 0xb5, 0xbf, 0xfa],
+    [0xfb, 0xce, 0xc4, 0xf9],
+];
+
+/// A block record of the block index of an SV Node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRecord {
+    pub hash: BlockHash,
+    pub header: BlockHeader,
+    pub height: u64,
+    /// The status flags of the block in the node.
+    pub status: u64,
+    pub num_tx: u64,
+    /// The number of the blk file that contains the block, if the node has the block data.
+    pub file: Option<u64>,
+    /// The position of the block data in the blk file.
+    pub data_pos: Option<u64>,
+    /// The size of the block, when it is recorded in the index.
+    pub size: Option<u64>,
+}
+
+impl IndexRecord {
+    /// The validity of the block, mapped from the status flags.
+    pub fn validity(&self) -> BlockValidity {
+        if self.status & FAILED_VALID != 0 {
+            BlockValidity::Invalid
+        } else if self.status & FAILED_CHILD != 0 {
+            BlockValidity::InvalidAncestor
+        } else {
+            match self.status & VALIDITY_MASK {
+                0 => BlockValidity::Unknown,
+                v if v >= VALID_SCRIPTS => BlockValidity::Valid,
+                _ => BlockValidity::ValidHeader,
+            }
+        }
+    }
+
+    /// The BlockInfo of the block, ready to be stored.
+    pub fn block_info(&self) -> BlockInfo<<FDBChainStore as ChainStore>::BlockId> {
+        BlockInfo {
+            id: 0u64,
+            hash: self.hash,
+            header: self.header.clone(),
+            height: self.height,
+            prev_id: 0u64,
+            next_ids: vec![],
+            size: self.size,
+            num_tx: Some(self.num_tx),
+            median_time: None,
+            chain_work: None,
+            total_tx: None,
+            total_size: None,
+            total_fees: None,
+            miner: None,
+            validity: self.validity(),
+            received_time: None,
+            source: Some(String::from("nodeindex")),
+        }
+    }
+}
+
+// Read a VARINT in the format of the node, which is not the same as the CompactSize used in
+// blocks.
+fn read_varint(data: &[u8], pos: &mut usize) -> CliResult<u64> {
+    let mut n: u64 = 0;
+    loop {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| CliError::NodeIndex(String::from("record is truncated")))?;
+        *pos += 1;
+        if n > u64::MAX >> 7 {
+            return Err(CliError::NodeIndex(String::from("VARINT is too large")));
+        }
+        n = (n << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+        n = n
+            .checked_add(1)
+            .ok_or_else(|| CliError::NodeIndex(String::from("VARINT is too large")))?;
+    }
+}
+
+/// Decode a block record of the block index.
+///
+/// Returns None if the key is not that of a block record. The fields that follow the position of
+/// the data and are not understood are skipped, the header is the first 80 bytes after the
+/// known fields that hash to the hash in the key.
+pub fn decode_record(key: &[u8], value: &[u8]) -> CliResult<Option<IndexRecord>> {
+    if key.len() != 33 || key[0] != BLOCK_PREFIX {
+        return Ok(None);
+    }
+    let hash = BlockHash::from(&key[1..]);
+    let mut pos = 0;
+    let _version = read_varint(value, &mut pos)?;
+    let height = read_varint(value, &mut pos)?;
+    let status = read_varint(value, &mut pos)?;
+    let num_tx = read_varint(value, &mut pos)?;
+    let file = if status & (HAVE_DATA | HAVE_UNDO) != 0 {
+        Some(read_varint(value, &mut pos)?)
+    } else {
+        None
+    };
+    let data_pos = if status & HAVE_DATA != 0 {
+        Some(read_varint(value, &mut pos)?)
+    } else {
+        None
+    };
+    if status & HAVE_UNDO != 0 {
+        read_varint(value, &mut pos)?;
+    }
+    let start = pos;
+    let offset = (start..=value.len().saturating_sub(HEADER_SIZE))
+        .find(|i| BlockHash::sha256d(&value[*i..*i + HEADER_SIZE]) == hash)
+        .ok_or_else(|| CliError::NodeIndex(format!("no header found for block {}", hash)))?;
+    let header = BlockHeader::from_binary_buf(&value[offset..offset + HEADER_SIZE])
+        .map_err(|e| CliError::NodeIndex(format!("error decoding header: {}", e)))?;
+    // the size of the data is the last field of the disk meta data
+    let size = (status & HAS_DISK_META_DATA != 0 && offset == start + DISK_META_DATA_SIZE)
+        .then(|| u64::from_le_bytes(value[offset - 8..offset].try_into().unwrap()));
+    Ok(Some(IndexRecord {
+        hash,
+        header,
+        height,
+        status,
+        num_tx,
+        file,
+        data_pos,
+        size,
+    }))
+}
+
+// Read the size of a block from the blk file that contains it, which precedes the block data
+// together with the network magic.
+//
+// Blocks of 4GB or more have a size of 0xffffffff followed by the 64 bit size.
+fn blk_file_size(blocks_dir: &Path, file: u64, data_pos: u64) -> Option<u64> {
+    let len = data_pos.min(16);
+    let mut f = std::fs::File::open(blocks_dir.join(format!("blk{:05}.dat", file))).ok()?;
+    f.seek(SeekFrom::Start(data_pos - len)).ok()?;
+    let mut buf = vec![0u8; len as usize];
+    f.read_exact(&mut buf).ok()?;
+    let n = buf.len();
+    let magic_at = |i: usize| DISK_MAGICS.iter().any(|m| buf[i..i + 4] == *m);
+    if n >= 8 && magic_at(n - 8) {
+        Some(u32::from_le_bytes(buf[n - 4..].try_into().unwrap()) as u64)
+    } else if n >= 16 && magic_at(n - 16) && buf[n - 12..n - 8] == [0xff; 4] {
+        Some(u64::from_le_bytes(buf[n - 8..].try_into().unwrap()))
+    } else {
+        None
+    }
+}
+
+/// Read the block records of the block index in the directory, in order of height.
+///
+/// The blk files are expected in the parent of the directory, as they are in the node, and are
+/// used for the size of the blocks that the index does not record. Records that can not be
+/// decoded are reported and skipped, returning the number that were skipped.
+pub fn read_index(path: &str) -> CliResult<(Vec<IndexRecord>, u64)> {
+    let options = Options {
+        create_if_missing: false,
+        ..Options::default()
+    };
+    let mut db = DB::open(path, options)
+        .map_err(|e| CliError::NodeIndex(format!("error opening {}: {}", path, e)))?;
+    let mut iter = db
+        .new_iter()
+        .map_err(|e| CliError::NodeIndex(format!("error reading {}: {}", path, e)))?;
+    let blocks_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    while let Some((key, value)) = iter.next() {
+        match decode_record(&key, &value) {
+            Ok(Some(mut r)) => {
+                if r.size.is_none() {
+                    if let (Some(file), Some(data_pos)) = (r.file, r.data_pos) {
+                        r.size = blk_file_size(blocks_dir, file, data_pos);
+                    }
+                }
+                records.push(r);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                println!("{}, skipped", e);
+                skipped += 1;
+            }
+        }
+    }
+    records.sort_by_key(|r| r.height);
+    Ok((records, skipped))
+}
+
+/// Import the block index of an SV Node into the ChainStore, see "cs import-nodeindex".
+///
+/// The blocks are stored in order of height, so that each parent is normally stored before its
+/// children. Blocks whose parent is not in the ChainStore are held in an [OrphanPool] and are
+/// reported at the end.
+pub async fn import_node_index(
+    config: &BSVDBConfig,
+    path: &str,
+    effects: &mut dyn Effects,
+) -> CliResult<()> {
+    config.check_chain_store_enabled()?;
+    let p = path.to_string();
+    let (records, skipped) = tokio::task::spawn_blocking(move || read_index(&p)).await??;
+    println!("read {} blocks from {}", records.len(), path);
+    let (chain_store, j) =
+        FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    // the index is a backfill, most of it far below the tip
+    chain_store.set_initial_sync(true);
+    let mut pool = OrphanPool::default();
+    let mut stored = 0;
+    let mut present = 0;
+    for r in records.iter() {
+        if effects.has_block_info(&chain_store, r.hash).await? {
+            present += 1;
+            continue;
+        }
+        stored += store_or_hold(&chain_store, &mut pool, r.block_info(), effects).await?;
+    }
+    let orphans = pool.hashes();
+    for h in orphans.iter() {
+        println!("parent of block {} not in chainstore, not inserted", h);
+    }
+    println!(
+        "stored {}, already present {}, orphaned {}, skipped {}",
+        stored,
+        present,
+        orphans.len(),
+        skipped
+    );
+    chain_store.shutdown().await?;
+    j.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::{AsyncEncodable, BlockchainId};
+    use tempfile::tempdir;
+
+    fn varint(mut n: u64) -> Vec<u8> {
+        let mut out = vec![(n & 0x7f) as u8];
+        while n > 0x7f {
+            n = (n >> 7) - 1;
+            out.push((n & 0x7f) as u8 | 0x80);
+        }
+        out.reverse();
+        out
+    }
+
+    // A child of the header, with the nonce to make it distinct.
+    fn child(parent: &BlockHeader, nonce: u32) -> BlockHeader {
+        let mut header = parent.clone();
+        header.prev_hash = parent.hash();
+        header.nonce = nonce;
+        header
+    }
+
+    // A record as the node writes it, with the extra fields before the header.
+    fn record(header: &BlockHeader, fields: &[u64], extra: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut key = vec![BLOCK_PREFIX];
+        key.extend(header.hash().hash);
+        let mut value: Vec<u8> = fields.iter().flat_map(|f| varint(*f)).collect();
+        value.extend(extra);
+        value.extend(header.to_binary_buf().unwrap());
+        (key, value)
+    }
+
+    #[test]
+    fn varints() {
+        for n in [0, 1, 127, 128, 255, 16511, 16512, 1 << 40, u64::MAX] {
+            let v = varint(n);
+            let mut pos = 0;
+            assert_eq!(read_varint(&v, &mut pos).unwrap(), n);
+            assert_eq!(pos, v.len());
+        }
+        // the encodings of the node
+        assert_eq!(varint(128), vec![0x80, 0x00]);
+        assert_eq!(varint(16511), vec![0xff, 0x7f]);
+        let mut pos = 0;
+        assert!(read_varint(&[0x80], &mut pos).is_err());
+        let mut pos = 0;
+        assert!(read_varint(&[0xff; 11], &mut pos).is_err());
+    }
+
+    // Records with and without data, with the disk meta data, and with unknown fields.
+    #[test]
+    fn records() {
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let h1 = child(&genesis, 1);
+        // version, height, status, num_tx, file, data position, undo position
+        let status = VALID_SCRIPTS | HAVE_DATA | HAVE_UNDO;
+        let (k, v) = record(&h1, &[220000, 1, status, 3, 0, 293, 120], &[]);
+        let r = decode_record(&k, &v).unwrap().unwrap();
+        assert_eq!(r.hash, h1.hash());
+        assert_eq!(r.header, h1);
+        assert_eq!(r.height, 1);
+        assert_eq!(r.num_tx, 3);
+        assert_eq!(r.file, Some(0));
+        assert_eq!(r.data_pos, Some(293));
+        assert_eq!(r.size, None);
+        assert_eq!(r.validity(), BlockValidity::Valid);
+        assert_eq!(r.block_info().num_tx, Some(3));
+
+        // the size from the disk meta data
+        let status = 3 | HAVE_DATA | HAS_DISK_META_DATA;
+        let mut meta = vec![7u8; 32];
+        meta.extend(1234u64.to_le_bytes());
+        let (k, v) = record(&h1, &[220000, 1, status, 3, 2, 8], &meta);
+        let r = decode_record(&k, &v).unwrap().unwrap();
+        assert_eq!(r.size, Some(1234));
+        assert_eq!(r.validity(), BlockValidity::ValidHeader);
+
+        // a header only block, with a field that is not known
+        let (k, v) = record(&h1, &[220000, 1, 1 | FAILED_CHILD, 0], &[1, 2, 3]);
+        let r = decode_record(&k, &v).unwrap().unwrap();
+        assert_eq!(r.file, None);
+        assert_eq!(r.size, None);
+        assert_eq!(r.validity(), BlockValidity::InvalidAncestor);
+        let (k, v) = record(&h1, &[220000, 1, 5 | FAILED_VALID, 0], &[]);
+        assert_eq!(
+            decode_record(&k, &v).unwrap().unwrap().validity(),
+            BlockValidity::Invalid
+        );
+
+        // other records are ignored, a corrupt record is an error
+        assert_eq!(decode_record(b"R", &[1]).unwrap(), None);
+        assert_eq!(decode_record(&[b'f', 0, 0, 0, 0], &[1]).unwrap(), None);
+        let (k, mut v) = record(&h1, &[220000, 1, 0, 0], &[]);
+        v[10] ^= 1;
+        assert!(decode_record(&k, &v).is_err());
+        assert!(decode_record(&k, &v[..3]).is_err());
+    }
+
+    // The records are read from a LevelDB database in order of height, with the sizes from the
+    // blk file.
+    #[test]
+    fn read() {
+        let root = tempdir().unwrap();
+        let index = root.path().join("index");
+        let genesis = BlockHeader::get_genesis(BlockchainId::Regtest);
+        let h1 = child(&genesis, 1);
+        let h2 = child(&h1, 2);
+        let h2b = child(&h1, 3);
+        // the blk file with the magic and the size before the data of the block at height 1
+        let mut blk = vec![0xda, 0xb5, 0xbf, 0xfa];
+        blk.extend(300u32.to_le_bytes());
+        std::fs::write(root.path().join("blk00000.dat"), blk).unwrap();
+        {
+            let options = Options {
+                create_if_missing: true,
+                ..Options::default()
+            };
+            let mut db = DB::open(&index, options).unwrap();
+            let status = VALID_SCRIPTS | HAVE_DATA;
+            for (h, fields) in [
+                (&h2, vec![220000, 2, 1, 0]),
+                (&genesis, vec![220000, 0, status, 1, 0, 0]),
+                (&h1, vec![220000, 1, status, 2, 0, 8]),
+                (&h2b, vec![220000, 2, 1, 0]),
+            ] {
+                let (k, v) = record(h, &fields, &[]);
+                db.put(&k, &v).unwrap();
+            }
+            db.put(b"R", &[0]).unwrap();
+            db.put(&[BLOCK_PREFIX; 33], &[0]).unwrap();
+            db.flush().unwrap();
+        }
+        let (records, skipped) = read_index(index.to_str().unwrap()).unwrap();
+        assert_eq!(skipped, 1);
+        let heights: Vec<u64> = records.iter().map(|r| r.height).collect();
+        assert_eq!(heights, vec![0, 1, 2, 2]);
+        assert_eq!(records[1].hash, h1.hash());
+        assert_eq!(records[1].size, Some(300));
+        assert_eq!(records[0].size, None);
+        assert_eq!(records[2].validity(), BlockValidity::ValidHeader);
+        assert!(read_index(root.path().join("missing").to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,605 @@
+use crate::result::CliResult;
+use askama::Template;
+use bitcoinsv::bitcoin::BlockHash;
+use bsvdb_base::BSVDBConfig;
+use bsvdb_chainstore::{
+    BlockInfo, ChainQuality, ChainStore, Error, FDBChainStore, Fork, ForkBranch, ForkScanner,
+    QualityScanner, Window,
+};
+use futures::{Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::pin::pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A static website of the recent activity of the chain, see "report html".
+//
+// Everything is read from the chain store, the block bodies are not read. The last blocks of the
+// main chain are read twice from the tip down: first into the fork and quality scanners, together
+// with the branches that fork off them, and then to render the pages as the blocks arrive. Only
+// the compact summaries of the scanners and one page of the block list are held in memory. The
+// site is laid out as:
+//
+//   index.html          the chain summary, the quality metrics and the miners of the blocks
+//   list-<n>.html       the pages of the block list, the tip is on the first
+//   forks.html          the forks off the reported blocks
+//   blocks/<hash>.html  a page for each reported block
+//
+// The pages only depend on the state of the chain store, except for the line at the foot of the
+// index page with the time that the site was generated. Regenerating the site from the same state
+// rewrites every other file byte for byte.
+
+// the number of blocks on each page of the block list
+const PAGE_SIZE: usize = 100;
+
+/// Render the static website of the last blocks of the main chain into the directory, see "report
+/// html".
+///
+/// Files of an earlier report in the directory that are not rewritten, such as the pages of
+/// blocks that are no longer among the last blocks, are left in place.
+pub async fn report_html(config: &BSVDBConfig, out: &str, last: u64) -> CliResult<()> {
+    let fdb_boot = unsafe { bsvdb_chainstore::boot(&config.chain_store) }?;
+    let (chain_store, j) =
+        FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+    let r = write_report(&chain_store, Path::new(out), last).await;
+    chain_store.shutdown().await?;
+    j.await?;
+    drop(fdb_boot);
+    println!("{} files written to {}", r?, out);
+    Ok(())
+}
+
+// Scan the chain store and render the site, returning the number of files written.
+async fn write_report(chain_store: &FDBChainStore, out: &Path, last: u64) -> CliResult<usize> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let state = chain_store.get_chain_state().await?;
+    let tip = chain_store
+        .get_block_info(state.most_work_tip)
+        .await?
+        .ok_or(Error::BlockInfoNotFound(state.most_work_tip))?;
+    let summary = chain_store.summary().await?;
+    let mut forks = ForkScanner::new();
+    let mut quality = QualityScanner::new();
+    // the parent of the last block is scanned for the interval to it
+    let mut blocks = pin!(chain_store.get_block_infos(tip.id, Some(last + 1)).await?);
+    let mut branches = Vec::new();
+    let mut above = None;
+    while let Some(b) = blocks.next().await {
+        let b = b?;
+        branches.extend(b.next_ids.iter().filter(|id| Some(**id) != above));
+        above = Some(b.id);
+        forks.add(&b);
+        quality.add(&b);
+    }
+    // the branches are short, they are read a block at a time
+    while let Some(id) = branches.pop() {
+        if let Some(b) = chain_store.get_block_info(id).await? {
+            branches.extend(b.next_ids.iter());
+            forks.add(&b);
+            quality.add(&b);
+        }
+    }
+    let reorgs = chain_store.get_reorg_log(last as usize).await?;
+    let overview = Overview {
+        num_blocks: summary.num_blocks,
+        num_tips: summary.num_tips,
+        reported: last.min(tip.height + 1),
+        quality: quality.quality(tip.id, &reorgs, Window::Blocks(last), now),
+        tip,
+    };
+    let forks = forks.forks(overview.tip.id);
+    let blocks = chain_store
+        .get_block_infos(overview.tip.id, Some(last))
+        .await?;
+    render_site(out, &overview, &forks, blocks, now).await
+}
+
+// The chain-wide values of the index page.
+struct Overview {
+    tip: BlockInfo<u64>,
+    num_blocks: u64,
+    num_tips: u64,
+    // the number of blocks that are reported
+    reported: u64,
+    quality: ChainQuality,
+}
+
+#[derive(Template)]
+#[template(path = "report/index.html")]
+struct IndexPage {
+    title: String,
+    root: &'static str,
+    tip_height: u64,
+    tip_hash: String,
+    tip_time: String,
+    tip_received: String,
+    num_blocks: u64,
+    num_tips: u64,
+    reported: u64,
+    quality: ChainQuality,
+    stale_rate: String,
+    mean_interval: String,
+    miners: Vec<MinerRow>,
+    miners_json: String,
+    generated: String,
+}
+
+struct MinerRow {
+    name: String,
+    blocks: u64,
+    share: String,
+}
+
+#[derive(Template)]
+#[template(path = "report/list.html")]
+struct ListPage {
+    title: String,
+    root: &'static str,
+    page: usize,
+    pages: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
+    rows: Vec<BlockRow>,
+}
+
+struct BlockRow {
+    height: u64,
+    hash: String,
+    time: String,
+    size: String,
+    num_tx: String,
+    miner: String,
+}
+
+#[derive(Template)]
+#[template(path = "report/block.html")]
+struct BlockPage {
+    title: String,
+    root: &'static str,
+    hash: String,
+    height: u64,
+    version: String,
+    prev_hash: String,
+    // whether the previous block has a page
+    prev_link: bool,
+    next_hash: Option<String>,
+    merkle_root: String,
+    time: String,
+    bits: String,
+    nonce: u32,
+    size: String,
+    num_tx: String,
+    miner: String,
+    validity: String,
+    received: String,
+    branches: Vec<BranchRow>,
+}
+
+#[derive(Template)]
+#[template(path = "report/forks.html")]
+struct ForksPage {
+    title: String,
+    root: &'static str,
+    lowest: u64,
+    forks: Vec<ForkRow>,
+}
+
+struct ForkRow {
+    depth: usize,
+    height: u64,
+    hash: String,
+    // whether the fork point has a page, only the fork points on the main chain do
+    link: bool,
+    branches: Vec<BranchRow>,
+}
+
+struct BranchRow {
+    won: &'static str,
+    length: u64,
+    work: String,
+    first_time: String,
+    last_time: String,
+    tip_hash: String,
+    // whether the tip has a page, only the tip of the winning branch off the main chain does
+    tip_link: bool,
+    miners: String,
+}
+
+// Render the site from the main chain blocks, the tip first, returning the number of files
+// written.
+async fn render_site<S>(
+    out: &Path,
+    overview: &Overview,
+    forks: &[Fork],
+    blocks: S,
+    generated: u64,
+) -> CliResult<usize>
+where
+    S: Stream<Item = bsvdb_chainstore::Result<BlockInfo<u64>>>,
+{
+    tokio::fs::create_dir_all(out.join("blocks")).await?;
+    let lowest = overview.tip.height + 1 - overview.reported;
+    let forks: Vec<&Fork> = forks.iter().filter(|f| f.height >= lowest).collect();
+    let fork_points: HashMap<u64, &Fork> = forks.iter().map(|f| (f.id, *f)).collect();
+    let pages = (overview.reported as usize).div_ceil(PAGE_SIZE).max(1);
+    let mut written = 0;
+    let mut page = 1;
+    let mut rows = Vec::with_capacity(PAGE_SIZE);
+    let mut miners: BTreeMap<String, u64> = BTreeMap::new();
+    let mut above: Option<BlockHash> = None;
+    let mut blocks = pin!(blocks);
+    while let Some(b) = blocks.next().await {
+        let b = b?;
+        let block_page = BlockPage {
+            title: format!("Block {}", b.height),
+            root: "../",
+            hash: b.hash.to_string(),
+            height: b.height,
+            version: b.header.version.to_string(),
+            prev_hash: b.header.prev_hash.to_string(),
+            prev_link: b.height > lowest,
+            next_hash: above.map(|h| h.to_string()),
+            merkle_root: b.header.merkle_root.to_string(),
+            time: utc(b.header.timestamp as u64),
+            bits: format!("{:08x}", b.header.bits),
+            nonce: b.header.nonce,
+            size: optional(b.size),
+            num_tx: optional(b.num_tx),
+            miner: miner_name(&b),
+            validity: format!("{:?}", b.validity),
+            received: b
+                .received_time
+                .map(utc)
+                .unwrap_or_else(|| String::from("-")),
+            branches: fork_points
+                .get(&b.id)
+                .map(|f| f.branches.iter().map(branch_row).collect())
+                .unwrap_or_default(),
+        };
+        let file = out.join("blocks").join(format!("{}.html", b.hash));
+        tokio::fs::write(file, block_page.render()?).await?;
+        written += 1;
+        *miners.entry(miner_name(&b)).or_default() += 1;
+        rows.push(BlockRow {
+            height: b.height,
+            hash: b.hash.to_string(),
+            time: utc(b.header.timestamp as u64),
+            size: optional(b.size),
+            num_tx: optional(b.num_tx),
+            miner: miner_name(&b),
+        });
+        above = Some(b.hash);
+        if rows.len() == PAGE_SIZE {
+            write_list_page(out, page, pages, std::mem::take(&mut rows)).await?;
+            written += 1;
+            page += 1;
+        }
+    }
+    if !rows.is_empty() || page == 1 {
+        write_list_page(out, page, pages, rows).await?;
+        written += 1;
+    }
+    let mut fork_rows = Vec::new();
+    for f in forks {
+        fork_rows_of(f, 0, &mut fork_rows);
+    }
+    let forks_page = ForksPage {
+        title: String::from("Forks"),
+        root: "",
+        lowest,
+        forks: fork_rows,
+    };
+    tokio::fs::write(out.join("forks.html"), forks_page.render()?).await?;
+    written += 1;
+    let index = index_page(overview, miners, generated);
+    tokio::fs::write(out.join("index.html"), index.render()?).await?;
+    written += 1;
+    Ok(written)
+}
+
+async fn write_list_page(
+    out: &Path,
+    page: usize,
+    pages: usize,
+    rows: Vec<BlockRow>,
+) -> CliResult<()> {
+    let list = ListPage {
+        title: format!("Blocks, page {}", page),
+        root: "",
+        page,
+        pages,
+        prev: (page > 1).then(|| page - 1),
+        next: (page < pages).then(|| page + 1),
+        rows,
+    };
+    let file = out.join(format!("list-{}.html", page));
+    tokio::fs::write(file, list.render()?).await?;
+    Ok(())
+}
+
+fn index_page(overview: &Overview, miners: BTreeMap<String, u64>, generated: u64) -> IndexPage {
+    let total: u64 = miners.values().sum();
+    let mut miners: Vec<(String, u64)> = miners.into_iter().collect();
+    // the most blocks first, ties by name so that the order is stable
+    miners.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let json: Vec<String> = miners
+        .iter()
+        .map(|(name, blocks)| format!("{{\"miner\":{},\"blocks\":{}}}", json_string(name), blocks))
+        .collect();
+    let q = &overview.quality;
+    IndexPage {
+        title: String::from("Chain summary"),
+        root: "",
+        tip_height: overview.tip.height,
+        tip_hash: overview.tip.hash.to_string(),
+        tip_time: utc(overview.tip.header.timestamp as u64),
+        tip_received: overview
+            .tip
+            .received_time
+            .map(utc)
+            .unwrap_or_else(|| String::from("-")),
+        num_blocks: overview.num_blocks,
+        num_tips: overview.num_tips,
+        reported: overview.reported,
+        quality: q.clone(),
+        stale_rate: format!("{:.2}%", q.stale_rate * 100.0),
+        mean_interval: format!("{:.1}", q.intervals.mean),
+        miners: miners
+            .into_iter()
+            .map(|(name, blocks)| MinerRow {
+                name,
+                blocks,
+                share: format!("{:.1}%", blocks as f64 * 100.0 / total as f64),
+            })
+            .collect(),
+        miners_json: format!("[{}]", json.join(",")),
+        generated: utc(generated),
+    }
+}
+
+// Add the rows of the fork, followed by those of its children, which are ordered by height and
+// then by hash so that forks at the same height are always in the same order.
+fn fork_rows_of(fork: &Fork, depth: usize, rows: &mut Vec<ForkRow>) {
+    rows.push(ForkRow {
+        depth,
+        height: fork.height,
+        hash: fork.hash.to_string(),
+        link: depth == 0,
+        branches: fork.branches.iter().map(branch_row).collect(),
+    });
+    let mut children: Vec<&Fork> = fork.children.iter().collect();
+    children.sort_by(|a, b| {
+        b.height
+            .cmp(&a.height)
+            .then_with(|| a.hash.hash.cmp(&b.hash.hash))
+    });
+    for c in children {
+        fork_rows_of(c, depth + 1, rows);
+    }
+}
+
+fn branch_row(b: &ForkBranch) -> BranchRow {
+    BranchRow {
+        won: if b.won { "yes" } else { "no" },
+        length: b.length,
+        work: format!("{:.4e}", b.work),
+        first_time: utc(b.first_time as u64),
+        last_time: utc(b.last_time as u64),
+        tip_hash: b.tip_hash.to_string(),
+        tip_link: b.won,
+        miners: b.miners.join(", "),
+    }
+}
+
+fn miner_name(b: &BlockInfo<u64>) -> String {
+    b.miner.clone().unwrap_or_else(|| String::from("unknown"))
+}
+
+fn optional(v: Option<u64>) -> String {
+    v.map(|v| v.to_string())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+// A string as JSON that can be embedded in a script element, "<" is escaped so that the string
+// can not end the element.
+fn json_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if c == '<' || c.is_control() => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// The time, in seconds since the unix epoch, as YYYY-MM-DD HH:MM:SS in UTC.
+fn utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let s = secs % 86_400;
+    // the civil date from the days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        y,
+        m,
+        d,
+        s / 3_600,
+        s % 3_600 / 60,
+        s % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::generate_chain;
+    use futures::stream;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    const GENERATED: u64 = 1_700_000_000;
+
+    // The overview, forks and reported main chain blocks of a generated chain whose main chain
+    // has blocks after genesis and forks from its middle. The blocks are mined by three miners, one
+    // of whose names must be escaped.
+    fn fixture(blocks: u64, last: u64) -> (Overview, Vec<Fork>, Vec<BlockInfo<u64>>) {
+        let mut chain: Vec<BlockInfo<u64>> = generate_chain(1, blocks + 1)
+            .into_iter()
+            .map(|(b, _)| b)
+            .collect();
+        for b in chain.iter_mut() {
+            b.miner = Some(["alpha", "beta", "pool <a>"][b.id as usize % 3].to_string());
+        }
+        let mut forks = ForkScanner::new();
+        let mut quality = QualityScanner::new();
+        for b in chain.iter() {
+            forks.add(b);
+            quality.add(b);
+        }
+        let tip = chain[blocks as usize].clone();
+        let main: Vec<BlockInfo<u64>> = chain[..=blocks as usize]
+            .iter()
+            .rev()
+            .take(last as usize)
+            .cloned()
+            .collect();
+        let overview = Overview {
+            num_blocks: chain.len() as u64,
+            num_tips: 2,
+            reported: main.len() as u64,
+            quality: quality.quality(tip.id, &[], Window::Blocks(last), GENERATED),
+            tip,
+        };
+        (overview, forks.forks(blocks), main)
+    }
+
+    async fn render(out: &Path, blocks: u64, last: u64, generated: u64) -> usize {
+        let (overview, forks, main) = fixture(blocks, last);
+        let main = stream::iter(main.into_iter().map(Ok));
+        render_site(out, &overview, &forks, main, generated)
+            .await
+            .unwrap()
+    }
+
+    // the files below the directory with their contents, in order of their paths
+    fn files(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut r = BTreeMap::new();
+        for e in std::fs::read_dir(dir).unwrap() {
+            let path = e.unwrap().path();
+            if path.is_dir() {
+                r.extend(files(&path));
+            } else {
+                r.insert(path.clone(), std::fs::read(&path).unwrap());
+            }
+        }
+        r
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        std::fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    // The pages of the last 150 of 250 blocks, which fork at height 125.
+    #[tokio::test]
+    async fn pages() {
+        let (_, _, main) = fixture(250, 150);
+        let dir = tempdir().unwrap();
+        let out = dir.path();
+        // a page for each block, two pages of the block list, the forks and the index
+        assert_eq!(render(out, 250, 150, GENERATED).await, 154);
+        assert_eq!(files(out).len(), 154);
+        let tip = main[0].hash.to_string();
+        let fork_point = main.iter().find(|b| b.height == 125).unwrap();
+        let fork_point = fork_point.hash.to_string();
+
+        let index = read(out, "index.html");
+        assert!(index.contains(&format!("<a href=\"blocks/{}.html\">{}</a>", tip, tip)));
+        assert!(index.contains("<td>250</td>"));
+        assert!(index.contains("pool &lt;a&gt;"));
+        assert!(index.contains("{\"miner\":\"pool \\u003ca>\",\"blocks\":50}"));
+        assert!(index.contains("Generated 2023-11-14 22:13:20 UTC"));
+
+        let list = read(out, "list-1.html");
+        assert!(list.contains(&format!("<a href=\"blocks/{}.html\">", tip)));
+        assert!(list.contains("<a href=\"list-2.html\">"));
+        assert!(!list.contains("<a href=\"list-0.html\">"));
+        let list = read(out, "list-2.html");
+        assert!(list.contains("<td class=\"num\">101</td>"));
+        assert!(!list.contains("<td class=\"num\">100</td>"));
+        assert!(!list.contains("<a href=\"list-3.html\">"));
+
+        let block = read(out, &format!("blocks/{}.html", fork_point));
+        assert!(block.contains("<h2>Fork</h2>"));
+        assert!(block.contains(&format!("<a href=\"{}.html\">", tip)));
+        assert!(block.contains(&format!("<a href=\"{}.html\">", main[124].hash)));
+        // the lowest block links to the block above but not below it
+        let block = read(out, &format!("blocks/{}.html", main[149].hash));
+        assert!(!block.contains("<h2>Fork</h2>"));
+        assert!(!block.contains(&format!("<a href=\"{}.html\">", main[149].header.prev_hash)));
+        assert!(block.contains(&format!("<a href=\"{}.html\">", main[148].hash)));
+
+        let forks = read(out, "forks.html");
+        assert!(forks.contains(&format!("<a href=\"blocks/{}.html\">", fork_point)));
+        assert!(forks.contains("<td>no</td>"));
+
+        // the fork is below the last 100 blocks
+        let dir = tempdir().unwrap();
+        render(dir.path(), 250, 100, GENERATED).await;
+        assert!(read(dir.path(), "forks.html").contains("There are no forks"));
+    }
+
+    // Regenerating the site gives the same files, only the index differs when the time of
+    // generation does.
+    #[tokio::test]
+    async fn regenerate() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        let third = tempdir().unwrap();
+        render(first.path(), 120, 1000, GENERATED).await;
+        render(second.path(), 120, 1000, GENERATED).await;
+        render(third.path(), 120, 1000, GENERATED + 60).await;
+        let strip = |dir: &Path| {
+            files(dir)
+                .into_iter()
+                .map(|(p, c)| (p.strip_prefix(dir).unwrap().to_path_buf(), c))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let (first, second, third) = (
+            strip(first.path()),
+            strip(second.path()),
+            strip(third.path()),
+        );
+        assert_eq!(first, second);
+        let differ: Vec<&PathBuf> = first.keys().filter(|p| first[*p] != third[*p]).collect();
+        assert_eq!(differ, vec![Path::new("index.html")]);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(utc(0), "1970-01-01 00:00:00");
+        assert_eq!(utc(1_231_006_505), "2009-01-03 18:15:05");
+        assert_eq!(utc(951_825_600), "2000-02-29 12:00:00");
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("pool"), "\"pool\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("</script>\n"), "\"\\u003c/script>\\u000a\"");
+    }
+}
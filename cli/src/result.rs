@@ -1,3 +1,4 @@
+use askama::Error as TemplateError;
 use bsvdb_base::BsvDbBaseError;
 use bsvdb_blockarchive::Error as BlockArchiveError;
 use bsvdb_chainstore::Error;
@@ -13,6 +14,25 @@ pub enum CliError {
     BlockArchive(BlockArchiveError),
     ChainStore(Error),
     Join(JoinError),
+    Io(std::io::Error),
+    /// A page of a report could not be rendered.
+    Template(TemplateError),
+    /// A block source failed.
+    Source(String),
+    /// The trace exporter could not be installed.
+    Tracing(String),
+    /// The blocks could not be selected.
+    Select(String),
+    /// The test vectors could not be read.
+    Vectors(String),
+    /// The block index of an SV Node could not be read.
+    NodeIndex(String),
+    /// The partial results could not be merged.
+    Merge(String),
+    /// A file of transaction counts could not be read.
+    TxCounts(String),
+    /// The JSON-RPC server failed.
+    RpcServer(String),
 }
 
 impl std::fmt::Display for CliError {
@@ -20,8 +40,24 @@ impl std::fmt::Display for CliError {
         match self {
             CliError::BsvDbBase(err) => write!(f, "BSVDB base error: {}", err),
             CliError::BlockArchive(err) => write!(f, "Block Archive error: {}", err),
+            CliError::ChainStore(err @ Error::BackendSlow(..)) => write!(
+                f,
+                "Chain Store error: {}\nhint: check the health of the foundationdb cluster, e.g. \
+                 with `fdbcli --exec status`",
+                err
+            ),
             CliError::ChainStore(err) => write!(f, "Chain Store error: {}", err),
             CliError::Join(err) => write!(f, "Join error: {}", err),
+            CliError::Io(err) => write!(f, "IO error: {}", err),
+            CliError::Template(err) => write!(f, "Template error: {}", err),
+            CliError::Source(err) => write!(f, "Source error: {}", err),
+            CliError::Tracing(err) => write!(f, "Tracing error: {}", err),
+            CliError::Select(err) => write!(f, "Selection error: {}", err),
+            CliError::Vectors(err) => write!(f, "Test vectors error: {}", err),
+            CliError::NodeIndex(err) => write!(f, "Node index error: {}", err),
+            CliError::Merge(err) => write!(f, "Merge error: {}", err),
+            CliError::TxCounts(err) => write!(f, "Transaction counts error: {}", err),
+            CliError::RpcServer(err) => write!(f, "RPC server error: {}", err),
         }
     }
 }
@@ -49,3 +85,15 @@ impl From<JoinError> for CliError {
         CliError::Join(err)
     }
 }
+
+impl From<std::io::Error> for CliError {
+    fn from(err: std::io::Error) -> CliError {
+        CliError::Io(err)
+    }
+}
+
+impl From<TemplateError> for CliError {
+    fn from(err: TemplateError) -> CliError {
+        CliError::Template(err)
+    }
+}
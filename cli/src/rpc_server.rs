@@ -0,0 +1,1167 @@
+use crate::ba::open_replicated;
+use crate::result::{CliError, CliResult};
+use base64::Engine;
+use bitcoinsv::bitcoin::{BlockHash, BlockchainId, FullBlockStream, ToHex};
+use bsvdb_base::BSVDBConfig;
+use bsvdb_blockarchive::{BlockArchive, Error as BlockArchiveError};
+use bsvdb_chainstore::{BlockInfo, BlockValidity, ChainStore, FDBChainStore};
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+// A JSON-RPC server that answers a subset of the block RPCs of an SV Node from the archive and
+// the chain store, for tooling that only speaks bitcoind-style JSON-RPC, see "rpc-server".
+//
+// The methods are read-only and return the same shapes as an SV Node. A block is never held in
+// memory: getblock streams the raw block as hex for verbosity 0, and streams the ids of its
+// transactions for verbosity 1, in a response with chunked encoding. The chain store has no
+// index of the heights of the main chain, so getblockhash, the confirmations and next block of a
+// block, and the branch lengths of getchaintips walk down the main chain from the most work tip,
+// which takes longer the further below the tip they are.
+//
+// Clients authenticate with basic auth, with the configured user and password, or with the
+// credentials that are written to a cookie file when the server starts, as for an SV Node. Batch
+// requests and named parameters are not supported.
+
+// the codes of the errors, as returned by an SV Node
+const RPC_MISC_ERROR: i64 = -1;
+const RPC_TYPE_ERROR: i64 = -3;
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+const RPC_INVALID_PARAMETER: i64 = -8;
+const RPC_DATABASE_ERROR: i64 = -20;
+const RPC_INVALID_REQUEST: i64 = -32600;
+const RPC_METHOD_NOT_FOUND: i64 = -32601;
+const RPC_PARSE_ERROR: i64 = -32700;
+
+// the user name of the credentials in a cookie file, as for an SV Node
+const COOKIE_USER: &str = "__cookie__";
+// how long a request that fails to authenticate waits before it is answered, to slow down the
+// guessing of passwords as an SV Node does
+const AUTH_FAILURE_DELAY: Duration = Duration::from_millis(250);
+// the number of bytes of a block that are read at once when it is streamed as hex
+const HEX_CHUNK: usize = 64 * 1024;
+
+/// An error of a call, with the code and message that an SV Node returns for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn block_not_found() -> RpcError {
+        RpcError::new(RPC_INVALID_ADDRESS_OR_KEY, "Block not found")
+    }
+
+    // the error as the error member of a response
+    fn json(&self) -> String {
+        format!(
+            "{{\"code\":{},\"message\":{}}}",
+            self.code,
+            json_string(&self.message)
+        )
+    }
+
+    // the status of the response that carries the error, as for an SV Node
+    fn status(&self) -> StatusCode {
+        match self.code {
+            RPC_INVALID_REQUEST => StatusCode::BAD_REQUEST,
+            RPC_METHOD_NOT_FOUND => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<bsvdb_chainstore::Error> for RpcError {
+    fn from(err: bsvdb_chainstore::Error) -> RpcError {
+        RpcError::new(RPC_DATABASE_ERROR, err.to_string())
+    }
+}
+
+impl From<BlockArchiveError> for RpcError {
+    fn from(err: BlockArchiveError) -> RpcError {
+        match err {
+            BlockArchiveError::BlockNotFound(_) => {
+                RpcError::new(RPC_MISC_ERROR, "Block not available")
+            }
+            err => RpcError::new(RPC_MISC_ERROR, err.to_string()),
+        }
+    }
+}
+
+/// The result of a call, either JSON or the pieces of JSON of a result that is too large to be
+/// held in memory, which are sent as they are read.
+pub enum RpcResult {
+    Json(String),
+    Stream(BoxStream<'static, Result<String, RpcError>>),
+}
+
+/// Answer a call of the method with the params, which are given by position.
+pub async fn call<C, A>(
+    chain_store: &C,
+    archive: &A,
+    chain: BlockchainId,
+    method: &str,
+    params: &[Value],
+) -> Result<RpcResult, RpcError>
+where
+    C: ChainStore + Sync,
+    A: BlockArchive + Sync,
+{
+    let usage = match method {
+        "getbestblockhash" => "getbestblockhash",
+        "getblock" => "getblock \"blockhash\" ( verbosity )",
+        "getblockchaininfo" => "getblockchaininfo",
+        "getblockhash" => "getblockhash height",
+        "getblockheader" => "getblockheader \"hash\" ( verbose )",
+        "getchaintips" => "getchaintips",
+        _ => return Err(RpcError::new(RPC_METHOD_NOT_FOUND, "Method not found")),
+    };
+    let (required, optional) = match method {
+        "getblock" | "getblockheader" => (1, 1),
+        "getblockhash" => (1, 0),
+        _ => (0, 0),
+    };
+    if params.len() < required || params.len() > required + optional {
+        return Err(RpcError::new(RPC_MISC_ERROR, usage));
+    }
+    match method {
+        "getbestblockhash" => get_best_block_hash(chain_store).await.map(RpcResult::Json),
+        "getblock" => {
+            let verbosity = match params.get(1) {
+                None => 1,
+                Some(Value::Bool(verbose)) => *verbose as u64,
+                Some(v) => v
+                    .as_u64()
+                    .ok_or_else(|| RpcError::new(RPC_TYPE_ERROR, "Expected type number"))?,
+            };
+            get_block(chain_store, archive, hash_param(&params[0])?, verbosity).await
+        }
+        "getblockchaininfo" => get_blockchain_info(chain_store, chain)
+            .await
+            .map(RpcResult::Json),
+        "getblockhash" => {
+            let height = params[0]
+                .as_i64()
+                .ok_or_else(|| RpcError::new(RPC_TYPE_ERROR, "Expected type number"))?;
+            get_block_hash(chain_store, height)
+                .await
+                .map(RpcResult::Json)
+        }
+        "getblockheader" => {
+            let verbose = match params.get(1) {
+                None => true,
+                Some(v) => v
+                    .as_bool()
+                    .ok_or_else(|| RpcError::new(RPC_TYPE_ERROR, "Expected type bool"))?,
+            };
+            get_block_header(chain_store, hash_param(&params[0])?, verbose)
+                .await
+                .map(RpcResult::Json)
+        }
+        _ => get_chain_tips(chain_store).await.map(RpcResult::Json),
+    }
+}
+
+/// The hash of the most work tip, as a JSON string.
+pub async fn get_best_block_hash<C: ChainStore + Sync>(
+    chain_store: &C,
+) -> Result<String, RpcError> {
+    Ok(json_string(
+        &most_work_tip(chain_store).await?.hash.to_string(),
+    ))
+}
+
+/// The hash of the block of the main chain at the height, as a JSON string.
+pub async fn get_block_hash<C: ChainStore + Sync>(
+    chain_store: &C,
+    height: i64,
+) -> Result<String, RpcError> {
+    let tip = most_work_tip(chain_store).await?;
+    let out_of_range = || RpcError::new(RPC_INVALID_PARAMETER, "Block height out of range");
+    let height = u64::try_from(height).map_err(|_| out_of_range())?;
+    let mut main_chain = MainChain::new(chain_store, &tip).await?;
+    match main_chain.at(height).await? {
+        Some(hash) => Ok(json_string(&hash.to_string())),
+        None => Err(out_of_range()),
+    }
+}
+
+/// The header of the block, as the hex of the header if verbose is false, otherwise as an object
+/// with its fields and those of its BlockInfo.
+pub async fn get_block_header<C: ChainStore + Sync>(
+    chain_store: &C,
+    hash: BlockHash,
+    verbose: bool,
+) -> Result<String, RpcError> {
+    let block = chain_store
+        .get_block_info_by_hash(hash)
+        .await?
+        .ok_or_else(RpcError::block_not_found)?;
+    if !verbose {
+        let x: String = block.header.encode_hex();
+        return Ok(json_string(&x));
+    }
+    let tip = most_work_tip(chain_store).await?;
+    let mut main_chain = MainChain::new(chain_store, &tip).await?;
+    let (confirmations, next) = main_chain.position(&block).await?;
+    let fields = header_fields(&block, confirmations, next, block.size, block.num_tx);
+    Ok(json_object(&fields))
+}
+
+/// The block, streamed from the archive as the hex of the raw block for verbosity 0, or as an
+/// object with the fields of its header and the ids of its transactions for verbosity 1.
+///
+/// The block must be in the chain store, and is not available if it is not in the archive.
+pub async fn get_block<C, A>(
+    chain_store: &C,
+    archive: &A,
+    hash: BlockHash,
+    verbosity: u64,
+) -> Result<RpcResult, RpcError>
+where
+    C: ChainStore + Sync,
+    A: BlockArchive + Sync,
+{
+    if verbosity > 1 {
+        return Err(RpcError::new(
+            RPC_INVALID_PARAMETER,
+            "Verbosity must be 0 or 1",
+        ));
+    }
+    let block = chain_store
+        .get_block_info_by_hash(hash)
+        .await?
+        .ok_or_else(RpcError::block_not_found)?;
+    if verbosity == 0 {
+        let reader = archive.get_block(&hash).await?;
+        let quote = || stream::once(async { Ok(String::from("\"")) });
+        return Ok(RpcResult::Stream(
+            quote().chain(hex_stream(reader)).chain(quote()).boxed(),
+        ));
+    }
+    let size = archive.block_size(&hash).await? as u64;
+    let reader = archive.get_block(&hash).await?;
+    let txs = FullBlockStream::new(reader)
+        .await
+        .map_err(BlockArchiveError::from)?;
+    let tip = most_work_tip(chain_store).await?;
+    let mut main_chain = MainChain::new(chain_store, &tip).await?;
+    let (confirmations, next) = main_chain.position(&block).await?;
+    let fields = header_fields(&block, confirmations, next, Some(size), Some(txs.num_tx));
+    // the transactions follow the count of transactions
+    let split = fields.iter().position(|(k, _)| *k == "num_tx").unwrap_or(0) + 1;
+    let head = json_members(&fields[..split]);
+    let prefix = format!("{{{},\"tx\":[", head);
+    let suffix = match json_members(&fields[split..]) {
+        tail if tail.is_empty() => String::from("]}"),
+        tail => format!("],{}}}", tail),
+    };
+    let ids = txs.enumerate().map(|(i, tx)| match tx {
+        Ok(tx) => {
+            let sep = if i == 0 { "" } else { "," };
+            Ok(format!("{}\"{}\"", sep, tx.hash()))
+        }
+        Err(e) => Err(RpcError::from(BlockArchiveError::from(e))),
+    });
+    Ok(RpcResult::Stream(
+        stream::once(async { Ok(prefix) })
+            .chain(ids)
+            .chain(stream::once(async { Ok(suffix) }))
+            .boxed(),
+    ))
+}
+
+/// The tips of the chain store, with their height, hash, the length of their branch from the
+/// main chain and their status, highest first.
+pub async fn get_chain_tips<C: ChainStore + Sync>(chain_store: &C) -> Result<String, RpcError> {
+    let state = chain_store.get_chain_state().await?;
+    let tip = most_work_tip(chain_store).await?;
+    let mut main_chain = MainChain::new(chain_store, &tip).await?;
+    let mut ids = state.active_tips.clone();
+    ids.extend(state.dormant_tips.iter().cloned());
+    ids.extend(state.invalid_tips.iter().cloned());
+    let mut seen = HashSet::new();
+    let mut tips = Vec::new();
+    for id in ids {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let block = match chain_store.get_block_info(id).await? {
+            Some(b) => b,
+            None => continue,
+        };
+        let (branch_len, status) = if block.id == tip.id {
+            (0, "active")
+        } else {
+            let len = main_chain.branch_len(chain_store, &block).await?;
+            (len, tip_status(&block.validity))
+        };
+        tips.push((block, branch_len, status));
+    }
+    tips.sort_by(|a, b| b.0.height.cmp(&a.0.height));
+    let tips: Vec<String> = tips
+        .iter()
+        .map(|(b, branch_len, status)| {
+            json_object(&[
+                ("height", b.height.to_string()),
+                ("hash", json_string(&b.hash.to_string())),
+                ("branchlen", branch_len.to_string()),
+                ("status", json_string(status)),
+            ])
+        })
+        .collect();
+    Ok(format!("[{}]", tips.join(",")))
+}
+
+/// The state of the chain: the name of the chain, the heights of the most work tip and of the
+/// highest tip that is not invalid, and the fields of the most work tip.
+///
+/// The chain store holds the headers of blocks that are not in the archive, so the blocks are not
+/// checked. The verification progress is always 1 and the chain store is never pruned.
+pub async fn get_blockchain_info<C: ChainStore + Sync>(
+    chain_store: &C,
+    chain: BlockchainId,
+) -> Result<String, RpcError> {
+    let state = chain_store.get_chain_state().await?;
+    let tip = most_work_tip(chain_store).await?;
+    let mut headers = tip.height;
+    for id in state.active_tips.iter().chain(state.dormant_tips.iter()) {
+        if let Some(b) = chain_store.get_block_info(id.clone()).await? {
+            if !is_invalid(&b.validity) {
+                headers = headers.max(b.height);
+            }
+        }
+    }
+    let mut fields = vec![
+        ("chain", json_string(chain_name(chain))),
+        ("blocks", tip.height.to_string()),
+        ("headers", headers.to_string()),
+        ("bestblockhash", json_string(&tip.hash.to_string())),
+        ("difficulty", difficulty(tip.header.bits).to_string()),
+    ];
+    if let Some(t) = tip.median_time {
+        fields.push(("mediantime", t.to_string()));
+    }
+    fields.push(("verificationprogress", String::from("1")));
+    if let Some(w) = tip.chain_work.as_ref() {
+        fields.push(("chainwork", json_string(&chain_work_hex(w))));
+    }
+    fields.push(("pruned", String::from("false")));
+    Ok(json_object(&fields))
+}
+
+/// The status of a tip that is not the most work tip, as an SV Node reports it in getchaintips.
+///
+/// An SV Node has no dormant tips, a dormant tip has the status of its validity.
+pub fn tip_status(validity: &BlockValidity) -> &'static str {
+    match validity {
+        BlockValidity::Valid => "valid-fork",
+        BlockValidity::ValidHeader => "valid-headers",
+        BlockValidity::Unknown => "headers-only",
+        BlockValidity::Invalid | BlockValidity::HeaderInvalid | BlockValidity::InvalidAncestor => {
+            "invalid"
+        }
+    }
+}
+
+/// The difficulty of the bits, the ratio of the target of the lowest difficulty to the target of
+/// the bits, as computed by an SV Node.
+pub fn difficulty(bits: u32) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut diff = 0x0000ffff as f64 / (bits & 0x00ffffff) as f64;
+    while shift < 29 {
+        diff *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        diff /= 256.0;
+        shift -= 1;
+    }
+    diff
+}
+
+/// The name of the chain, as an SV Node reports it.
+pub fn chain_name(chain: BlockchainId) -> &'static str {
+    match chain {
+        BlockchainId::Main => "main",
+        BlockchainId::Test => "test",
+        BlockchainId::Stn => "stn",
+        BlockchainId::Regtest => "regtest",
+    }
+}
+
+// a block hash parameter, which must be 64 hex digits
+fn hash_param(param: &Value) -> Result<BlockHash, RpcError> {
+    let s = param
+        .as_str()
+        .ok_or_else(|| RpcError::new(RPC_TYPE_ERROR, "Expected type string"))?;
+    if s.len() != 64 {
+        let msg = format!(
+            "blockhash must be of length 64 (not {}, for '{}')",
+            s.len(),
+            s
+        );
+        return Err(RpcError::new(RPC_INVALID_PARAMETER, msg));
+    }
+    s.parse::<BlockHash>().map_err(|_| {
+        let msg = format!("blockhash must be hexadecimal string (not '{}')", s);
+        RpcError::new(RPC_INVALID_PARAMETER, msg)
+    })
+}
+
+// the chain work as 64 hex digits
+fn chain_work_hex(work: &[u8]) -> String {
+    let start = work.iter().position(|b| *b != 0).unwrap_or(work.len());
+    format!("{:0>64}", hex::encode(&work[start..]))
+}
+
+fn is_invalid(validity: &BlockValidity) -> bool {
+    matches!(
+        validity,
+        BlockValidity::Invalid | BlockValidity::HeaderInvalid | BlockValidity::InvalidAncestor
+    )
+}
+
+// the fields of the header of the block and of its BlockInfo, in the order of an SV Node,
+// without those that are not known
+fn header_fields<Id>(
+    block: &BlockInfo<Id>,
+    confirmations: i64,
+    next: Option<BlockHash>,
+    size: Option<u64>,
+    num_tx: Option<u64>,
+) -> Vec<(&'static str, String)> {
+    let h = &block.header;
+    let mut fields = vec![
+        ("hash", json_string(&block.hash.to_string())),
+        ("confirmations", confirmations.to_string()),
+    ];
+    if let Some(size) = size {
+        fields.push(("size", size.to_string()));
+    }
+    fields.push(("height", block.height.to_string()));
+    fields.push(("version", h.version.to_string()));
+    fields.push(("versionHex", json_string(&format!("{:08x}", h.version))));
+    fields.push(("merkleroot", json_string(&h.merkle_root.to_string())));
+    if let Some(num_tx) = num_tx {
+        fields.push(("num_tx", num_tx.to_string()));
+    }
+    fields.push(("time", h.timestamp.to_string()));
+    if let Some(t) = block.median_time {
+        fields.push(("mediantime", t.to_string()));
+    }
+    fields.push(("nonce", h.nonce.to_string()));
+    fields.push(("bits", json_string(&format!("{:08x}", h.bits))));
+    fields.push(("difficulty", difficulty(h.bits).to_string()));
+    if let Some(w) = block.chain_work.as_ref() {
+        fields.push(("chainwork", json_string(&chain_work_hex(w))));
+    }
+    if block.height > 0 {
+        fields.push(("previousblockhash", json_string(&h.prev_hash.to_string())));
+    }
+    if let Some(next) = next {
+        fields.push(("nextblockhash", json_string(&next.to_string())));
+    }
+    fields
+}
+
+async fn most_work_tip<C: ChainStore + Sync>(
+    chain_store: &C,
+) -> Result<BlockInfo<C::BlockId>, RpcError> {
+    let state = chain_store.get_chain_state().await?;
+    chain_store
+        .get_block_info(state.most_work_tip.clone())
+        .await?
+        .ok_or_else(|| RpcError::new(RPC_DATABASE_ERROR, "the most work tip is missing"))
+}
+
+// The main chain, which is walked down from the most work tip as far as it is needed.
+struct MainChain<'a, Id> {
+    tip_height: u64,
+    // the hashes from the tip down
+    hashes: Vec<BlockHash>,
+    walk: Pin<Box<dyn Stream<Item = bsvdb_chainstore::Result<BlockInfo<Id>>> + Send + 'a>>,
+}
+
+impl<'a, Id: Clone + PartialEq> MainChain<'a, Id> {
+    async fn new<C>(chain_store: &'a C, tip: &BlockInfo<Id>) -> Result<Self, RpcError>
+    where
+        C: ChainStore<BlockId = Id> + Sync,
+    {
+        let walk = chain_store.get_block_infos(tip.id.clone(), None).await?;
+        Ok(MainChain {
+            tip_height: tip.height,
+            hashes: Vec::new(),
+            walk: Box::pin(walk),
+        })
+    }
+
+    // the hash of the block of the main chain at the height, None if it is above the tip
+    async fn at(&mut self, height: u64) -> Result<Option<BlockHash>, RpcError> {
+        if height > self.tip_height {
+            return Ok(None);
+        }
+        let i = (self.tip_height - height) as usize;
+        while self.hashes.len() <= i {
+            match self.walk.next().await {
+                Some(b) => self.hashes.push(b?.hash),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.hashes[i]))
+    }
+
+    // the confirmations of the block and the hash of the block after it on the main chain, -1
+    // and None if it is not on the main chain
+    async fn position(
+        &mut self,
+        block: &BlockInfo<Id>,
+    ) -> Result<(i64, Option<BlockHash>), RpcError> {
+        if self.at(block.height).await? != Some(block.hash) {
+            return Ok((-1, None));
+        }
+        let next = self.at(block.height + 1).await?;
+        Ok(((self.tip_height - block.height + 1) as i64, next))
+    }
+
+    // the number of blocks from the block down to the block where its branch leaves the main
+    // chain
+    async fn branch_len<C>(
+        &mut self,
+        chain_store: &C,
+        block: &BlockInfo<Id>,
+    ) -> Result<u64, RpcError>
+    where
+        C: ChainStore<BlockId = Id> + Sync,
+    {
+        let branch = chain_store.get_block_infos(block.id.clone(), None).await?;
+        let mut branch = Box::pin(branch);
+        while let Some(b) = branch.next().await {
+            let b = b?;
+            if self.at(b.height).await? == Some(b.hash) {
+                return Ok(block.height - b.height);
+            }
+        }
+        Ok(block.height)
+    }
+}
+
+// the raw bytes that are read as hex, a piece at a time
+fn hex_stream(
+    reader: Box<dyn AsyncRead + Unpin + Send>,
+) -> BoxStream<'static, Result<String, RpcError>> {
+    stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0u8; HEX_CHUNK];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => Some((Ok(hex::encode(&buf[..n])), Some(reader))),
+            Err(e) => Some((Err(RpcError::new(RPC_MISC_ERROR, e.to_string())), None)),
+        }
+    })
+    .boxed()
+}
+
+// a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// the members of a JSON object, whose values are already JSON
+fn json_members(fields: &[(&str, String)]) -> String {
+    let members: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), v))
+        .collect();
+    members.join(",")
+}
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    format!("{{{}}}", json_members(fields))
+}
+
+/// The user name and password that clients authenticate with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Whether the value of the Authorization header of a request carries the credentials, with
+    /// basic auth.
+    pub fn accepts(&self, authorization: Option<&str>) -> bool {
+        let encoded = match authorization.and_then(|a| a.strip_prefix("Basic ")) {
+            Some(e) => e.trim(),
+            None => return false,
+        };
+        let given = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+            Ok(g) => g,
+            Err(_) => return false,
+        };
+        let expected = format!("{}:{}", self.user, self.password).into_bytes();
+        // every byte is compared, so that the time taken does not reveal a matching prefix
+        given.len() == expected.len()
+            && given
+                .iter()
+                .zip(expected.iter())
+                .fold(0, |d, (a, b)| d | (a ^ b))
+                == 0
+    }
+}
+
+// Write the cookie file with a new random password, readable only by the user, returning its
+// credentials. It is written to a temporary file first so that a client never reads part of it.
+async fn write_cookie(path: &str) -> CliResult<Credentials> {
+    let mut random = [0u8; 32];
+    tokio::fs::File::open("/dev/urandom")
+        .await?
+        .read_exact(&mut random)
+        .await?;
+    let credentials = Credentials {
+        user: String::from(COOKIE_USER),
+        password: hex::encode(random),
+    };
+    let tmp = format!("{}.tmp", path);
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut f = options.open(&tmp).await?;
+    let line = format!("{}:{}", credentials.user, credentials.password);
+    f.write_all(line.as_bytes()).await?;
+    f.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(credentials)
+}
+
+/// The chain store and archive that the server answers from.
+pub struct RpcServer<C, A> {
+    pub chain_store: C,
+    pub archive: A,
+    pub chain: BlockchainId,
+    pub credentials: Credentials,
+}
+
+/// Answer a request, which must be a POST with the credentials of the server and a single call.
+pub async fn serve<C, A>(
+    server: Arc<RpcServer<C, A>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    C: ChainStore + Send + Sync + 'static,
+    A: BlockArchive + Send + Sync + 'static,
+{
+    if req.method() != Method::POST {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let authorization = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !server.credentials.accepts(authorization) {
+        tokio::time::sleep(AUTH_FAILURE_DELAY).await;
+        let mut r = empty_response(StatusCode::UNAUTHORIZED);
+        r.headers_mut().insert(
+            WWW_AUTHENTICATE,
+            "Basic realm=\"jsonrpc\"".parse().expect("valid header"),
+        );
+        return Ok(r);
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(e) => {
+            return Ok(error_response(
+                &RpcError::new(RPC_PARSE_ERROR, e.to_string()),
+                "null",
+            ))
+        }
+    };
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(_) => {
+            return Ok(error_response(
+                &RpcError::new(RPC_PARSE_ERROR, "Parse error"),
+                "null",
+            ))
+        }
+    };
+    let id = request
+        .get("id")
+        .map_or(String::from("null"), |v| v.to_string());
+    let method = match request.get("method") {
+        _ if !request.is_object() => {
+            let e = RpcError::new(RPC_INVALID_REQUEST, "Batch requests are not supported");
+            return Ok(error_response(&e, &id));
+        }
+        Some(Value::String(m)) => m.clone(),
+        _ => {
+            let e = RpcError::new(RPC_INVALID_REQUEST, "Method must be a string");
+            return Ok(error_response(&e, &id));
+        }
+    };
+    let params = match request.get("params") {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Array(p)) => p.clone(),
+        Some(_) => {
+            let e = RpcError::new(RPC_INVALID_REQUEST, "Params must be an array");
+            return Ok(error_response(&e, &id));
+        }
+    };
+    let r = call(
+        &server.chain_store,
+        &server.archive,
+        server.chain,
+        &method,
+        &params,
+    )
+    .await;
+    Ok(match r {
+        Ok(RpcResult::Json(result)) => json_response(
+            StatusCode::OK,
+            format!("{{\"result\":{},\"error\":null,\"id\":{}}}\n", result, id),
+        ),
+        Ok(RpcResult::Stream(result)) => {
+            // an error after the response has started can only end it early
+            let head = stream::once(async { Ok(String::from("{\"result\":")) });
+            let tail = format!(",\"error\":null,\"id\":{}}}\n", id);
+            let body = head
+                .chain(result)
+                .chain(stream::once(async { Ok(tail) }))
+                .map(|r| r.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.message)));
+            let mut response = Response::new(Body::wrap_stream(body));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                "application/json".parse().expect("valid header"),
+            );
+            response
+        }
+        Err(e) => error_response(&e, &id),
+    })
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut r = Response::new(Body::empty());
+    *r.status_mut() = status;
+    r
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut r = Response::new(Body::from(body));
+    *r.status_mut() = status;
+    r.headers_mut().insert(
+        CONTENT_TYPE,
+        "application/json".parse().expect("valid header"),
+    );
+    r
+}
+
+fn error_response(e: &RpcError, id: &str) -> Response<Body> {
+    json_response(
+        e.status(),
+        format!("{{\"result\":null,\"error\":{},\"id\":{}}}\n", e.json(), id),
+    )
+}
+
+/// Serve the JSON-RPC methods from the archive and the chain store until the process is
+/// interrupted.
+///
+/// When no user is configured, the credentials are written to the cookie file, which is removed
+/// when the server stops.
+pub async fn rpc_server(config: &BSVDBConfig) -> CliResult<()> {
+    let addr: SocketAddr =
+        config.rpc_server.bind.parse().map_err(|_| {
+            CliError::RpcServer(format!("invalid address {}", config.rpc_server.bind))
+        })?;
+    let chain = config.get_blockchain_id();
+    let builder = hyper::Server::try_bind(&addr)
+        .map_err(|e| CliError::RpcServer(format!("can not listen on {}: {}", addr, e)))?;
+    let fdb_boot = unsafe { bsvdb_chainstore::boot(&config.chain_store) }?;
+    let archive = open_replicated(&config.block_archive, chain).await?;
+    let (chain_store, j) = FDBChainStore::new_readonly(&config.chain_store, chain).await?;
+    let cookie = config
+        .rpc_server
+        .user
+        .is_empty()
+        .then(|| config.rpc_server.cookie_file.clone());
+    let credentials = match cookie.as_ref() {
+        Some(path) => write_cookie(path).await?,
+        None => Credentials {
+            user: config.rpc_server.user.clone(),
+            password: config.rpc_server.password.clone(),
+        },
+    };
+    let server = Arc::new(RpcServer {
+        chain_store,
+        archive,
+        chain,
+        credentials,
+    });
+    let s = server.clone();
+    let make_service = make_service_fn(move |_| {
+        let s = s.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| serve(s.clone(), req))) }
+    });
+    println!("listening on {}", addr);
+    let r = builder
+        .serve(make_service)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await;
+    if let Some(path) = cookie {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    server.chain_store.shutdown().await?;
+    j.await?;
+    drop(fdb_boot);
+    r.map_err(|e| CliError::RpcServer(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::BlockHeader;
+    use bsvdb_base::BlockArchiveConfig;
+    use bsvdb_blockarchive::SimpleFileBasedBlockArchive;
+    use bsvdb_chainstore::test_util::{generate_branch, header_info_with_id, MemoryChainStore};
+    use serde_json::json;
+
+    const GENESIS: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const BLOCK_1: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+
+    async fn archive() -> SimpleFileBasedBlockArchive {
+        let config = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from("../testdata/blockarchive"),
+            ..BlockArchiveConfig::default()
+        };
+        SimpleFileBasedBlockArchive::new(&config).await.unwrap()
+    }
+
+    // A chain store that holds the first two blocks of the main chain.
+    async fn chain_store(archive: &SimpleFileBasedBlockArchive) -> MemoryChainStore {
+        let store = MemoryChainStore::new(BlockchainId::Main);
+        let hash = BLOCK_1.parse().unwrap();
+        let header = archive.block_header(&hash).await.unwrap();
+        let mut info = header_info_with_id(&header, BlockValidity::Valid, hash);
+        info.size = Some(215);
+        info.num_tx = Some(1);
+        info.median_time = Some(1231469665);
+        info.chain_work = Some(
+            hex::decode("0000000000000000000000000000000000000000000000000000000200020002")
+                .unwrap(),
+        );
+        store.store_block_info(info).await.unwrap();
+        store
+    }
+
+    async fn result(r: Result<RpcResult, RpcError>) -> Result<Value, RpcError> {
+        let json = match r? {
+            RpcResult::Json(j) => j,
+            RpcResult::Stream(s) => s
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<String, _>>()?,
+        };
+        Ok(serde_json::from_str(&json).unwrap())
+    }
+
+    async fn rpc(
+        store: &MemoryChainStore,
+        archive: &SimpleFileBasedBlockArchive,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<Value, RpcError> {
+        result(call(store, archive, BlockchainId::Main, method, &params).await).await
+    }
+
+    // the expected response in testdata/rpc-handwritten, see its README
+    fn handwritten(name: &str) -> Value {
+        let path = format!("../testdata/rpc-handwritten/{}.json", name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    // The responses have the shapes and values of the documented responses of an SV Node. The
+    // expected responses were written by hand and not recorded from a node, so this does not show
+    // that a node gives the same responses.
+    #[tokio::test]
+    async fn responses() {
+        let archive = archive().await;
+        let store = chain_store(&archive).await;
+        let call = |method, params| rpc(&store, &archive, method, params);
+        let cases = [
+            ("getbestblockhash", vec![], "getbestblockhash"),
+            ("getblockhash", vec![json!(0)], "getblockhash_0"),
+            ("getblockheader", vec![json!(GENESIS)], "getblockheader_0"),
+            (
+                "getblockheader",
+                vec![json!(BLOCK_1), json!(true)],
+                "getblockheader_1",
+            ),
+            ("getblock", vec![json!(BLOCK_1)], "getblock_1"),
+            ("getblock", vec![json!(BLOCK_1), json!(1)], "getblock_1"),
+            ("getblockchaininfo", vec![], "getblockchaininfo"),
+            ("getchaintips", vec![], "getchaintips"),
+        ];
+        for (method, params, name) in cases {
+            assert_eq!(
+                call(method, params).await.unwrap(),
+                handwritten(name),
+                "{}",
+                name
+            );
+        }
+        let header = BlockHeader::get_genesis(BlockchainId::Main).encode_hex::<String>();
+        let r = call("getblockheader", vec![json!(GENESIS), json!(false)]).await;
+        assert_eq!(r.unwrap(), json!(header));
+        // the raw block is streamed as hex
+        let raw = std::fs::read(format!("../testdata/blockarchive/6f/e2/{}.bin", GENESIS)).unwrap();
+        let r = call("getblock", vec![json!(GENESIS), json!(0)]).await;
+        assert_eq!(r.unwrap(), json!(hex::encode(&raw)));
+        let r = call("getblock", vec![json!(GENESIS), json!(false)]).await;
+        assert_eq!(r.unwrap(), json!(hex::encode(&raw)));
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let archive = archive().await;
+        let store = chain_store(&archive).await;
+        let call = |method, params| rpc(&store, &archive, method, params);
+        let code = |r: Result<Value, RpcError>| r.unwrap_err().code;
+        assert_eq!(
+            code(call("getrawtransaction", vec![]).await),
+            RPC_METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(call("getblockhash", vec![json!(2)]).await),
+            RPC_INVALID_PARAMETER
+        );
+        assert_eq!(
+            code(call("getblockhash", vec![json!(-1)]).await),
+            RPC_INVALID_PARAMETER
+        );
+        assert_eq!(
+            code(call("getblockhash", vec![json!("0")]).await),
+            RPC_TYPE_ERROR
+        );
+        assert_eq!(code(call("getblockhash", vec![]).await), RPC_MISC_ERROR);
+        assert_eq!(
+            code(call("getblockheader", vec![json!("00")]).await),
+            RPC_INVALID_PARAMETER
+        );
+        let unknown = "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1";
+        let r = call("getblockheader", vec![json!(unknown)]).await;
+        assert_eq!(r.unwrap_err(), RpcError::block_not_found());
+        let r = call("getblock", vec![json!(BLOCK_1), json!(2)]).await;
+        assert_eq!(code(r), RPC_INVALID_PARAMETER);
+        // a block in the chain store that is not in the archive
+        let fork = &generate_branch(&BlockHeader::get_genesis(BlockchainId::Main), 1, 1, false)[0];
+        let info = header_info_with_id(fork, BlockValidity::Unknown, fork.hash());
+        store.store_block_info(info).await.unwrap();
+        let r = call("getblock", vec![json!(fork.hash().to_string())]).await;
+        assert_eq!(
+            r.unwrap_err(),
+            RpcError::new(RPC_MISC_ERROR, "Block not available")
+        );
+    }
+
+    // A fork is reported with its branch and is not on the main chain. A fork with less work
+    // whose validity is unknown is headers-only, an invalid fork is invalid and is not counted
+    // in the headers even though it is higher than the main chain.
+    #[tokio::test]
+    async fn forks() {
+        let archive = archive().await;
+        let store = chain_store(&archive).await;
+        let genesis = BlockHeader::get_genesis(BlockchainId::Main);
+        let headers_only = &generate_branch(&genesis, 1, 1, false)[0];
+        let info = header_info_with_id(headers_only, BlockValidity::Unknown, headers_only.hash());
+        store.store_block_info(info).await.unwrap();
+        let invalid = generate_branch(&genesis, 2, 2, false);
+        for h in invalid.iter() {
+            let info = header_info_with_id(h, BlockValidity::HeaderInvalid, h.hash());
+            store.store_block_info(info).await.unwrap();
+        }
+        let tips: Value = serde_json::from_str(&get_chain_tips(&store).await.unwrap()).unwrap();
+        // the tips of the same height are in no particular order
+        let mut tips = tips.as_array().unwrap().clone();
+        tips.sort_by_key(|t| (-t["height"].as_i64().unwrap(), t["status"].to_string()));
+        assert_eq!(
+            tips,
+            vec![
+                json!({"height": 2, "hash": invalid[1].hash().to_string(), "branchlen": 2,
+                       "status": "invalid"}),
+                json!({"height": 1, "hash": BLOCK_1, "branchlen": 0, "status": "active"}),
+                json!({"height": 1, "hash": headers_only.hash().to_string(), "branchlen": 1,
+                       "status": "headers-only"}),
+            ]
+        );
+        let header = get_block_header(&store, headers_only.hash(), true)
+            .await
+            .unwrap();
+        let header: Value = serde_json::from_str(&header).unwrap();
+        assert_eq!(header["confirmations"], json!(-1));
+        assert!(header.get("nextblockhash").is_none());
+        let info: Value = serde_json::from_str(
+            &get_blockchain_info(&store, BlockchainId::Main)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!((&info["blocks"], &info["headers"]), (&json!(1), &json!(1)));
+    }
+
+    #[test]
+    fn difficulties() {
+        assert_eq!(difficulty(0x1d00ffff), 1.0);
+        assert!((difficulty(0x1b0404cb) - 16307.420938523983).abs() < 1e-6);
+        assert_eq!(tip_status(&BlockValidity::InvalidAncestor), "invalid");
+        assert_eq!(tip_status(&BlockValidity::ValidHeader), "valid-headers");
+    }
+
+    // Requests must be posted with the credentials, the response carries the id of the request.
+    #[tokio::test]
+    async fn requests() {
+        let archive = archive().await;
+        let chain_store = chain_store(&archive).await;
+        let server = Arc::new(RpcServer {
+            chain_store,
+            archive,
+            chain: BlockchainId::Main,
+            credentials: Credentials {
+                user: String::from("user"),
+                password: String::from("secret"),
+            },
+        });
+        let auth = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("user:secret")
+        );
+        let post = |auth: &str, body: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .header(AUTHORIZATION, auth)
+                .body(Body::from(String::from(body)))
+                .unwrap()
+        };
+        let send = |req: Request<Body>| {
+            let server = server.clone();
+            async move {
+                let r = serve(server, req).await.unwrap();
+                let status = r.status();
+                let body = hyper::body::to_bytes(r.into_body()).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let (status, body) = send(post(&auth, r#"{"method":"getbestblockhash","id":7}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            format!("{{\"result\":\"{}\",\"error\":null,\"id\":7}}\n", BLOCK_1)
+        );
+        let (status, body) = send(post(
+            &auth,
+            r#"{"method":"getblock","params":["00"],"id":"a"}"#,
+        ))
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            (&body["error"]["code"], &body["id"]),
+            (&json!(-8), &json!("a"))
+        );
+        let (status, body) = send(post(&auth, r#"{"method":"getblock","params":["#)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("-32700"));
+        let (status, _) = send(post(&auth, r#"{"method":"stop","id":1}"#)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(post(&auth, r#"[{"method":"getbestblockhash"}]"#)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // a streamed result is wrapped in the same envelope
+        let (status, body) = send(post(
+            &auth,
+            &format!(r#"{{"method":"getblock","params":["{}"],"id":1}}"#, BLOCK_1),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["result"], handwritten("getblock_1"));
+        let wrong = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("user:wrong")
+        );
+        let (status, _) = send(post(&wrong, r#"{"method":"getbestblockhash"}"#)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let get = Request::builder().body(Body::empty()).unwrap();
+        assert_eq!(send(get).await.0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn credentials() {
+        let c = Credentials {
+            user: String::from(COOKIE_USER),
+            password: String::from("abc"),
+        };
+        let basic = |s: &str| {
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(s)
+            )
+        };
+        assert!(c.accepts(Some(&basic("__cookie__:abc"))));
+        assert!(!c.accepts(Some(&basic("__cookie__:abd"))));
+        assert!(!c.accepts(Some(&basic("__cookie__:ab"))));
+        assert!(!c.accepts(Some("Bearer abc")));
+        assert!(!c.accepts(None));
+    }
+
+    // The cookie file holds the credentials and is readable only by the user.
+    #[tokio::test]
+    async fn cookie() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookie");
+        let path = path.to_str().unwrap();
+        let c = write_cookie(path).await.unwrap();
+        assert_eq!(c.user, COOKIE_USER);
+        assert_eq!(c.password.len(), 64);
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents, format!("__cookie__:{}", c.password));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_ne!(write_cookie(path).await.unwrap(), c);
+    }
+}
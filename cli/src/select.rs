@@ -0,0 +1,455 @@
+use crate::result::{CliError, CliResult};
+use bitcoinsv::bitcoin::BlockHash;
+use bsvdb_base::BSVDBConfig;
+use bsvdb_blockarchive::{BlockArchive, Partition, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{ChainStore, FDBChainStore};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::LinesStream;
+
+/// A set of blocks for a bulk operation, given by a --select expression.
+///
+/// The expressions are:
+///  * "all-archive" - all the blocks in the BlockArchive
+///  * "file:<path>" - the block hashes listed in a file, one per line
+///  * "stdin" - the block hashes read from standard input, one per line
+///  * "chainstore:height A..B" - the blocks on the main chain with a height from A up to, but not
+///    including, B
+///  * "chainstore:last N" - the last N blocks on the main chain
+///  * "hash:<h>,[...]" - the block hashes given, separated by commas
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockSelector {
+    AllArchive,
+    File(String),
+    Stdin,
+    ChainStoreHeights { start: u64, end: u64 },
+    ChainStoreLast(u64),
+    Hashes(Vec<BlockHash>),
+}
+
+/// The hashes selected by a [BlockSelector].
+pub struct Selection {
+    /// The hashes of the selected blocks. Lines that are not valid block hashes are returned as
+    /// errors, the stream continues after them.
+    pub hashes: BoxStream<'static, CliResult<BlockHash>>,
+    /// The number of hashes that will be returned, if it is known in advance.
+    pub count: Option<u64>,
+}
+
+impl FromStr for BlockSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "all-archive" {
+            Ok(BlockSelector::AllArchive)
+        } else if s == "stdin" {
+            Ok(BlockSelector::Stdin)
+        } else if let Some(path) = s.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err(String::from("file selector needs a path"));
+            }
+            Ok(BlockSelector::File(String::from(path)))
+        } else if let Some(hashes) = s.strip_prefix("hash:") {
+            let hashes = hashes
+                .split(',')
+                .map(|h| h.trim().parse::<BlockHash>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("invalid block hash in '{}'", hashes))?;
+            Ok(BlockSelector::Hashes(hashes))
+        } else if let Some(range) = s.strip_prefix("chainstore:") {
+            let mut words = range.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("height"), Some(heights), None) => {
+                    let (start, end) = heights
+                        .split_once("..")
+                        .ok_or_else(|| format!("invalid height range '{}'", heights))?;
+                    let start = start.parse::<u64>();
+                    let end = end.parse::<u64>();
+                    match (start, end) {
+                        (Ok(start), Ok(end)) if start <= end => {
+                            Ok(BlockSelector::ChainStoreHeights { start, end })
+                        }
+                        _ => Err(format!("invalid height range '{}'", heights)),
+                    }
+                }
+                (Some("last"), Some(n), None) => n
+                    .parse::<u64>()
+                    .map(BlockSelector::ChainStoreLast)
+                    .map_err(|_| format!("invalid number of blocks '{}'", n)),
+                _ => Err(format!("unknown chainstore selector '{}'", range)),
+            }
+        } else {
+            Err(format!("unknown selector '{}'", s))
+        }
+    }
+}
+
+impl BlockSelector {
+    /// Whether the BlockArchive is needed to resolve the selector.
+    pub fn needs_block_archive(&self) -> bool {
+        matches!(self, BlockSelector::AllArchive)
+    }
+
+    /// Whether the ChainStore is needed to resolve the selector.
+    pub fn needs_chain_store(&self) -> bool {
+        matches!(
+            self,
+            BlockSelector::ChainStoreHeights { .. } | BlockSelector::ChainStoreLast(_)
+        )
+    }
+
+    /// Check that the components needed to resolve the selector are enabled.
+    pub fn check_enabled(&self, config: &BSVDBConfig) -> CliResult<()> {
+        if self.needs_block_archive() {
+            config.check_block_archive_enabled()?;
+        }
+        if self.needs_chain_store() {
+            config.check_chain_store_enabled()?;
+        }
+        Ok(())
+    }
+
+    /// Open the ChainStore read-only if it is needed to resolve the selector, returning None
+    /// otherwise.
+    ///
+    /// The FoundationDB network must have been started.
+    pub async fn open_chain_store(
+        &self,
+        config: &BSVDBConfig,
+    ) -> CliResult<Option<(FDBChainStore, JoinHandle<()>)>> {
+        if !self.needs_chain_store() {
+            return Ok(None);
+        }
+        config.check_chain_store_enabled()?;
+        let r =
+            FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+        Ok(Some(r))
+    }
+
+    /// Resolve the selector to a stream of block hashes.
+    ///
+    /// The chain_store must be given if the selector needs it, see
+    /// [BlockSelector::needs_chain_store()]. The chainstore selectors walk the main chain
+    /// backwards from the tip, so the hashes are returned from the highest block down and blocks
+    /// above a height range are read but not selected.
+    pub async fn resolve(
+        &self,
+        config: &BSVDBConfig,
+        chain_store: Option<&FDBChainStore>,
+    ) -> CliResult<Selection> {
+        self.resolve_partition(config, chain_store, Partition::WHOLE)
+            .await
+    }
+
+    /// Resolve the selector to a stream of the block hashes in the partition, see
+    /// [resolve()](Self::resolve).
+    ///
+    /// Only the directories of the partition are walked to list the archive, the hashes of the
+    /// other selectors are filtered.
+    pub async fn resolve_partition(
+        &self,
+        config: &BSVDBConfig,
+        chain_store: Option<&FDBChainStore>,
+        partition: Partition,
+    ) -> CliResult<Selection> {
+        self.check_enabled(config)?;
+        let selection = match self {
+            BlockSelector::AllArchive => {
+                let mut archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+                let list = if partition.is_whole() {
+                    archive.block_list().await?
+                } else {
+                    archive.block_list_partition(partition).await?
+                };
+                // when the list is exhausted, finish() reports a failed walk of the archive,
+                // unless the stream has already returned the error
+                let hashes =
+                    futures::stream::unfold((Some(list), false), |(list, failed)| async move {
+                        let mut list = list?;
+                        match list.next().await {
+                            Some(Ok(h)) => Some((Ok(h), (Some(list), failed))),
+                            Some(Err(e)) => Some((Err(CliError::from(e)), (Some(list), true))),
+                            None => match list.finish().await {
+                                Err(e) if !failed => Some((Err(CliError::from(e)), (None, true))),
+                                _ => None,
+                            },
+                        }
+                    })
+                    .boxed();
+                // the list only holds the blocks of the partition
+                return Ok(Selection {
+                    hashes,
+                    count: None,
+                });
+            }
+            BlockSelector::File(path) => {
+                let file = tokio::fs::File::open(path).await?;
+                Ok(Selection {
+                    hashes: hashes_from_lines(BufReader::new(file)),
+                    count: None,
+                })
+            }
+            BlockSelector::Stdin => Ok(Selection {
+                hashes: hashes_from_lines(BufReader::new(tokio::io::stdin())),
+                count: None,
+            }),
+            BlockSelector::Hashes(hashes) => Ok(Selection {
+                hashes: futures::stream::iter(hashes.clone()).map(Ok).boxed(),
+                count: Some(hashes.len() as u64),
+            }),
+            BlockSelector::ChainStoreHeights { start, end } => {
+                main_chain_hashes(chain_store, |_| (*start, *end)).await
+            }
+            BlockSelector::ChainStoreLast(n) => {
+                main_chain_hashes(chain_store, |tip| ((tip + 1).saturating_sub(*n), tip + 1)).await
+            }
+        }?;
+        if partition.is_whole() {
+            return Ok(selection);
+        }
+        // the errors are kept, they are reported by every partition
+        let hashes = selection
+            .hashes
+            .filter(move |h| {
+                let keep = h.as_ref().map_or(true, |h| partition.contains(h));
+                futures::future::ready(keep)
+            })
+            .boxed();
+        Ok(Selection {
+            hashes,
+            count: None,
+        })
+    }
+}
+
+// Read block hashes from a reader, one per line, skipping blank lines.
+fn hashes_from_lines<R>(reader: R) -> BoxStream<'static, CliResult<BlockHash>>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    LinesStream::new(reader.lines())
+        .enumerate()
+        .filter_map(|(n, line)| async move {
+            match line {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        None
+                    } else {
+                        Some(line.parse::<BlockHash>().map_err(|_| {
+                            CliError::Select(format!(
+                                "line {}: invalid block hash '{}'",
+                                n + 1,
+                                line
+                            ))
+                        }))
+                    }
+                }
+                Err(e) => Some(Err(CliError::from(e))),
+            }
+        })
+        .boxed()
+}
+
+// The hashes of the blocks on the main chain with a height in start..end, from the highest down.
+//
+// The range is calculated from the height of the most work tip.
+async fn main_chain_hashes<F>(chain_store: Option<&FDBChainStore>, range: F) -> CliResult<Selection>
+where
+    F: FnOnce(u64) -> (u64, u64),
+{
+    let chain_store = chain_store.ok_or_else(|| {
+        CliError::Select(String::from("the ChainStore is needed for this selector"))
+    })?;
+    let state = chain_store.get_chain_state().await?;
+    let tip = chain_store
+        .get_block_info(state.most_work_tip)
+        .await?
+        .ok_or_else(|| CliError::Select(String::from("most work tip not found")))?;
+    let (start, end) = range(tip.height);
+    if start > tip.height {
+        return Ok(Selection {
+            hashes: futures::stream::empty().boxed(),
+            count: Some(0),
+        });
+    }
+    let end = end.min(tip.height + 1);
+    let hashes = chain_store
+        .get_block_infos(tip.id, Some(tip.height - start + 1))
+        .await?
+        .filter(move |r| futures::future::ready(!matches!(r, Ok(b_info) if b_info.height >= end)))
+        .map(|r| r.map(|b_info| b_info.hash).map_err(CliError::from))
+        .boxed();
+    Ok(Selection {
+        hashes,
+        count: Some(end.saturating_sub(start)),
+    })
+}
+
+/// Estimate the time needed to process the rest of a selection, after done blocks have been
+/// processed in elapsed time.
+///
+/// Returns None if the number of blocks in the selection is not known.
+pub fn eta(done: u64, count: Option<u64>, elapsed: Duration) -> Option<Duration> {
+    match count {
+        Some(count) if done > 0 => {
+            Some(elapsed.mul_f64(count.saturating_sub(done) as f64 / done as f64))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsvdb_base::BlockArchiveConfig;
+    use tempfile::tempdir;
+
+    const BLOCK_1: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    const BLOCK_A8: &str = "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f";
+
+    fn hash(s: &str) -> BlockHash {
+        s.parse().unwrap()
+    }
+
+    fn testdata_config() -> BSVDBConfig {
+        let mut config = BSVDBConfig::new(None).unwrap();
+        config.block_archive = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from("../testdata/blockarchive"),
+            ..BlockArchiveConfig::default()
+        };
+        config
+    }
+
+    // Collect the hashes and the errors from a selection.
+    async fn collect(selection: Selection) -> (Vec<BlockHash>, Vec<String>) {
+        let mut hashes = Vec::new();
+        let mut errors = Vec::new();
+        let mut s = selection.hashes;
+        while let Some(r) = s.next().await {
+            match r {
+                Ok(h) => hashes.push(h),
+                Err(e) => errors.push(format!("{}", e)),
+            }
+        }
+        (hashes, errors)
+    }
+
+    #[test]
+    fn parse_selectors() {
+        let p = |s: &str| s.parse::<BlockSelector>();
+        assert_eq!(p("all-archive"), Ok(BlockSelector::AllArchive));
+        assert_eq!(p("stdin"), Ok(BlockSelector::Stdin));
+        assert_eq!(
+            p("file:hashes.txt"),
+            Ok(BlockSelector::File(String::from("hashes.txt")))
+        );
+        assert_eq!(
+            p("chainstore:height 10..20"),
+            Ok(BlockSelector::ChainStoreHeights { start: 10, end: 20 })
+        );
+        assert_eq!(p("chainstore:last 5"), Ok(BlockSelector::ChainStoreLast(5)));
+        assert_eq!(
+            p(&format!("hash:{},{}", BLOCK_1, BLOCK_A8)),
+            Ok(BlockSelector::Hashes(vec![hash(BLOCK_1), hash(BLOCK_A8)]))
+        );
+        for s in [
+            "",
+            "all",
+            "file:",
+            "hash:1234",
+            "chainstore:last",
+            "chainstore:last five",
+            "chainstore:height 20..10",
+            "chainstore:height 10",
+            "chainstore:first 5",
+        ] {
+            assert!(p(s).is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn needed_components() {
+        let mut config = testdata_config();
+        config.block_archive.enabled = false;
+        config.chain_store.enabled = false;
+        assert!(BlockSelector::AllArchive.check_enabled(&config).is_err());
+        assert!(BlockSelector::ChainStoreLast(5)
+            .check_enabled(&config)
+            .is_err());
+        assert!(BlockSelector::Stdin.check_enabled(&config).is_ok());
+        assert!(BlockSelector::Hashes(vec![]).check_enabled(&config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn stream_hashes() {
+        let config = testdata_config();
+        let s = BlockSelector::Hashes(vec![hash(BLOCK_1), hash(BLOCK_A8)]);
+        let selection = s.resolve(&config, None).await.unwrap();
+        assert_eq!(selection.count, Some(2));
+        let (hashes, errors) = collect(selection).await;
+        assert_eq!(hashes, vec![hash(BLOCK_1), hash(BLOCK_A8)]);
+        assert!(errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_file() {
+        let config = testdata_config();
+        let root = tempdir().unwrap();
+        let path = root.path().join("hashes.txt");
+        std::fs::write(
+            &path,
+            format!("{}\n\nnot a hash\n {} \n", BLOCK_1, BLOCK_A8),
+        )
+        .unwrap();
+        let s = BlockSelector::File(String::from(path.to_str().unwrap()));
+        let selection = s.resolve(&config, None).await.unwrap();
+        assert_eq!(selection.count, None);
+        let (hashes, errors) = collect(selection).await;
+        assert_eq!(hashes, vec![hash(BLOCK_1), hash(BLOCK_A8)]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("line 3"));
+        // a missing file is an error when resolving
+        let s = BlockSelector::File(String::from(root.path().join("none").to_str().unwrap()));
+        assert!(s.resolve(&config, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_archive() {
+        let config = testdata_config();
+        let selection = BlockSelector::AllArchive
+            .resolve(&config, None)
+            .await
+            .unwrap();
+        let (hashes, errors) = collect(selection).await;
+        assert_eq!(hashes.len(), 3);
+        assert!(hashes.contains(&hash(BLOCK_1)));
+        assert!(errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn chain_store_needed() {
+        let mut config = testdata_config();
+        config.chain_store.enabled = true;
+        let s = BlockSelector::ChainStoreLast(5);
+        assert!(matches!(
+            s.resolve(&config, None).await,
+            Err(CliError::Select(_))
+        ));
+    }
+
+    #[test]
+    fn estimate() {
+        let d = Duration::from_secs(10);
+        assert_eq!(eta(10, Some(30), d), Some(Duration::from_secs(20)));
+        assert_eq!(eta(30, Some(30), d), Some(Duration::ZERO));
+        assert_eq!(eta(0, Some(30), d), None);
+        assert_eq!(eta(10, None, d), None);
+    }
+}
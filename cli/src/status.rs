@@ -0,0 +1,138 @@
+use crate::result::CliResult;
+use bsvdb_base::{BSVDBConfig, VersionHistory};
+use bsvdb_blockarchive::{BlockArchive, ReplicatedBlockArchive, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{BlockInfo, ChainStore, FDBChainStore};
+
+/// The readiness of the system, as reported by [tip_readiness].
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    /// The most work tip of the ChainStore.
+    pub tip: BlockInfo<<FDBChainStore as ChainStore>::BlockId>,
+    /// Whether the full block of the tip is in the BlockArchive.
+    pub tip_in_archive: bool,
+}
+
+impl Readiness {
+    /// The system is ready when the BlockArchive has the block for the ChainStore tip.
+    pub fn is_ready(&self) -> bool {
+        self.tip_in_archive
+    }
+}
+
+/// Check whether the block of the ChainStore most work tip is in the BlockArchive.
+pub async fn tip_readiness<A: BlockArchive>(
+    chain_store: &FDBChainStore,
+    archive: &A,
+) -> CliResult<Readiness> {
+    let state = chain_store.get_chain_state().await?;
+    let tip = chain_store
+        .get_block_info(state.most_work_tip)
+        .await?
+        .ok_or(bsvdb_chainstore::Error::BlockInfoNotFound(
+            state.most_work_tip,
+        ))?;
+    let tip_in_archive = archive.block_exists(&tip.hash).await?;
+    Ok(Readiness {
+        tip,
+        tip_in_archive,
+    })
+}
+
+/// A warning that the store was written by a newer version than the running version, if it was.
+///
+/// The newer version may have changed the store in ways that the running version does not know
+/// about.
+pub fn newer_writer_warning(
+    store: &str,
+    history: &VersionHistory,
+    running: &str,
+) -> Option<String> {
+    history.newer_than(running).map(|r| {
+        format!(
+            "WARNING: the {} was written by version {} (last seen {}), this is version {}",
+            store, r.version, r.last_seen, running
+        )
+    })
+}
+
+/// Print the versions of the history, most recently seen first.
+pub fn print_versions(history: &VersionHistory) {
+    println!(
+        "{:<12}  {:>10}  {:>10}  {:<40}  features",
+        "version", "first seen", "last seen", "git hash"
+    );
+    for r in history.records() {
+        println!(
+            "{:<12}  {:>10}  {:>10}  {:<40}  {}",
+            r.version,
+            r.first_seen,
+            r.last_seen,
+            r.git_hash.as_deref().unwrap_or("-"),
+            r.features.join(",")
+        );
+    }
+}
+
+/// Print the status of the system, returning whether it is ready.
+pub async fn status(config: &BSVDBConfig) -> CliResult<bool> {
+    config.check_block_archive_enabled()?;
+    config.check_chain_store_enabled()?;
+    let fdb_boot = unsafe { bsvdb_chainstore::boot(&config.chain_store) }?;
+    let archive = ReplicatedBlockArchive::from_config(&config.block_archive).await?;
+    let (chain_store, j) =
+        FDBChainStore::new(&config.chain_store, config.get_blockchain_id()).await?;
+    let r = tip_readiness(&chain_store, &archive).await;
+    let versions = chain_store.versions().await;
+    let layout = chain_store.layout_version().await;
+    chain_store.shutdown().await?;
+    j.await?;
+    drop(fdb_boot);
+    let r = r?;
+    let running = FDBChainStore::library_version(0).version;
+    if let Some(w) = newer_writer_warning("chain store", &versions?, &running) {
+        println!("{}", w);
+    }
+    let layout = layout?;
+    if layout > FDBChainStore::LAYOUT_VERSION {
+        println!(
+            "WARNING: the chain store has layout version {}, this version knows layout version {}",
+            layout,
+            FDBChainStore::LAYOUT_VERSION
+        );
+    }
+    let primary = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    if let Some(w) = newer_writer_warning("block archive", &primary.versions().await?, &running) {
+        println!("{}", w);
+    }
+    println!("chainstore tip height: {}", r.tip.height);
+    println!("chainstore tip: {}", r.tip.hash);
+    println!("tip block in archive: {}", r.tip_in_archive);
+    if !config.block_archive.replicas.is_empty() {
+        archive.probe().await;
+        for replica in archive.status() {
+            println!("archive replica {}: {}", replica.name, replica.state.name());
+        }
+    }
+    println!("{}", if r.is_ready() { "ready" } else { "not ready" });
+    Ok(r.is_ready())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsvdb_base::VersionRecord;
+
+    #[test]
+    fn newer_writer() {
+        let mut history = VersionHistory::default();
+        assert!(newer_writer_warning("chain store", &history, "0.2.0").is_none());
+        history.record(VersionRecord::new("0.1.0", None, &[], 100));
+        history.record(VersionRecord::new("0.2.0", None, &[], 200));
+        assert!(newer_writer_warning("chain store", &history, "0.2.0").is_none());
+        history.record(VersionRecord::new("0.10.0", None, &[], 150));
+        assert_eq!(
+            newer_writer_warning("chain store", &history, "0.2.0").unwrap(),
+            "WARNING: the chain store was written by version 0.10.0 (last seen 150), this is version 0.2.0"
+        );
+    }
+}
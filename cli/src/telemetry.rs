@@ -0,0 +1,46 @@
+use crate::result::{CliError, CliResult};
+use bsvdb_base::TracingConfig;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{config as trace_config, Sampler};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// Install the OTLP trace exporter, if an endpoint has been configured.
+///
+/// Returns true if the exporter was installed, in which case [shutdown_tracing()] must be called
+/// before exiting so that buffered spans are exported.
+pub fn init_tracing(config: &TracingConfig, service_name: &str) -> CliResult<bool> {
+    if config.endpoint.is_empty() {
+        return Ok(false);
+    }
+    // sample a ratio of new traces, but always follow the decision of the parent
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(config.endpoint.clone()),
+        )
+        .with_trace_config(
+            trace_config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    service_name.to_string(),
+                )])),
+        )
+        .install_batch(runtime::Tokio)
+        .map_err(|e| CliError::Tracing(e.to_string()))?;
+    let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| CliError::Tracing(e.to_string()))?;
+    Ok(true)
+}
+
+/// Export any buffered spans and shut down the exporter.
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
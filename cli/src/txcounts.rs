@@ -0,0 +1,513 @@
+use crate::result::{CliError, CliResult};
+use crate::select::BlockSelector;
+use bitcoinsv::bitcoin::{BlockHash, FullBlockStream};
+use bsvdb_base::BSVDBConfig;
+use bsvdb_blockarchive::{BlockArchive, Error as BlockArchiveError, SimpleFileBasedBlockArchive};
+use bsvdb_chainstore::{BlockInfo, ChainStore, FDBChainStore};
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::pin;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::task::JoinHandle;
+
+// The transaction counts of blocks, for reconciling an external indexer, see "ba txcounts" and
+// "report reconcile-txcounts".
+//
+// The count of a block is taken from the first of these that has it: the num_tx of the BlockInfo
+// of the block in the chain store, if the chain store is enabled, and the count at the start of
+// the block in the archive, which is read without reading the transactions. The source of each
+// count is recorded with it, so that a systematic disagreement, such as a chain store that is
+// stale after a repair, shows up as the counts of one source.
+//
+// The counts are written as csv, with the columns hash, height, num_tx and source. The height is
+// empty if the block is not in the chain store.
+
+// the header of a csv file of counts
+const CSV_HEADER: &str = "hash,height,num_tx,source";
+
+/// Where the transaction count of a block was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountSource {
+    /// The num_tx of the BlockInfo in the chain store.
+    ChainStore,
+    /// The count at the start of the block in the archive.
+    Block,
+}
+
+impl fmt::Display for CountSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CountSource::ChainStore => write!(f, "chainstore"),
+            CountSource::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// The transaction count of a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxCount {
+    pub hash: BlockHash,
+    /// The height of the block, if it is in the chain store.
+    pub height: Option<u64>,
+    pub num_tx: u64,
+    pub source: CountSource,
+}
+
+/// The outcome of a reconciliation, see [reconcile()].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Reconciliation {
+    /// The number of blocks that are in both.
+    pub compared: u64,
+    /// The number of blocks whose counts differ.
+    pub mismatched: u64,
+    /// The number of our blocks that are not in theirs.
+    pub missing_theirs: u64,
+    /// The number of their blocks that are not in ours.
+    pub missing_ours: u64,
+}
+
+impl Reconciliation {
+    /// Whether the counts agree, with no block missing from either side.
+    pub fn agrees(&self) -> bool {
+        self.mismatched == 0 && self.missing_theirs == 0 && self.missing_ours == 0
+    }
+}
+
+/// The transaction count of a block, from its BlockInfo in the chain store if it has one with
+/// the count, otherwise from the block in the archive.
+///
+/// Returns None if the count is in neither.
+pub async fn tx_count<A>(
+    archive: &A,
+    block_hash: BlockHash,
+    stored: Option<BlockInfo<u64>>,
+) -> CliResult<Option<TxCount>>
+where
+    A: BlockArchive + Sync + ?Sized,
+{
+    let height = stored.as_ref().map(|b| b.height);
+    if let Some(num_tx) = stored.and_then(|b| b.num_tx) {
+        return Ok(Some(TxCount {
+            hash: block_hash,
+            height,
+            num_tx,
+            source: CountSource::ChainStore,
+        }));
+    }
+    let reader = match archive.get_block(&block_hash).await {
+        Ok(r) => r,
+        Err(BlockArchiveError::BlockNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // only the header and the count are read
+    let block = FullBlockStream::new(reader)
+        .await
+        .map_err(BlockArchiveError::from)?;
+    Ok(Some(TxCount {
+        hash: block_hash,
+        height,
+        num_tx: block.num_tx,
+        source: CountSource::Block,
+    }))
+}
+
+/// The counts of the blocks, in their order, see [tx_count()]. The BlockInfos are looked up with
+/// stored.
+///
+/// Lines of a selection that are not block hashes, and blocks whose count is in neither the
+/// chain store nor the archive, are reported and skipped.
+pub fn tx_counts<'a, A, F, Fut>(
+    archive: &'a A,
+    hashes: BoxStream<'a, CliResult<BlockHash>>,
+    stored: F,
+) -> impl Stream<Item = CliResult<TxCount>> + 'a
+where
+    A: BlockArchive + Sync + ?Sized,
+    F: Fn(BlockHash) -> Fut + 'a,
+    Fut: Future<Output = CliResult<Option<BlockInfo<u64>>>> + 'a,
+{
+    hashes
+        .then(move |h| {
+            let b_info = h.as_ref().ok().map(|h| stored(*h));
+            async move {
+                let h = h?;
+                let b_info = match b_info {
+                    Some(b_info) => b_info.await?,
+                    None => None,
+                };
+                Ok((h, tx_count(archive, h, b_info).await?))
+            }
+        })
+        .filter_map(|r| async move {
+            match r {
+                Ok((_, Some(count))) => Some(Ok(count)),
+                Ok((h, None)) => {
+                    println!("block {} not found, skipped", h);
+                    None
+                }
+                Err(e @ CliError::Select(_)) => {
+                    println!("{}, skipped", e);
+                    None
+                }
+                Err(e) => Some(Err(e)),
+            }
+        })
+}
+
+/// Write the counts to the csv writer, returning the number written.
+pub async fn write_counts<S, W>(counts: S, out: &mut W) -> CliResult<u64>
+where
+    S: Stream<Item = CliResult<TxCount>>,
+    W: AsyncWrite + Unpin,
+{
+    out.write_all(format!("{}\n", CSV_HEADER).as_bytes())
+        .await?;
+    let mut counts = pin!(counts);
+    let mut n = 0;
+    while let Some(c) = counts.next().await {
+        let c = c?;
+        let height = c.height.map(|h| h.to_string()).unwrap_or_default();
+        let row = format!("{},{},{},{}\n", c.hash, height, c.num_tx, c.source);
+        out.write_all(row.as_bytes()).await?;
+        n += 1;
+    }
+    out.flush().await?;
+    Ok(n)
+}
+
+/// Read the counts of a csv file, by block hash.
+///
+/// The first line is a header that names the columns, the columns named hash and num_tx are read
+/// and the others are ignored. A file written by "ba txcounts" can be read.
+pub async fn read_counts<R>(reader: R) -> CliResult<BTreeMap<BlockHash, u64>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = reader.lines();
+    let header = lines
+        .next_line()
+        .await?
+        .ok_or_else(|| CliError::TxCounts(String::from("the file is empty")))?;
+    let column = |name: &str| {
+        header
+            .split(',')
+            .position(|c| c.trim() == name)
+            .ok_or_else(|| CliError::TxCounts(format!("no {} column in the header", name)))
+    };
+    let (hash_col, count_col) = (column("hash")?, column("num_tx")?);
+    let mut counts = BTreeMap::new();
+    let mut n = 1;
+    while let Some(line) = lines.next_line().await? {
+        n += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let hash = fields
+            .get(hash_col)
+            .and_then(|h| h.parse::<BlockHash>().ok())
+            .ok_or_else(|| CliError::TxCounts(format!("line {}: invalid block hash", n)))?;
+        let num_tx = fields
+            .get(count_col)
+            .and_then(|c| c.parse::<u64>().ok())
+            .ok_or_else(|| CliError::TxCounts(format!("line {}: invalid num_tx", n)))?;
+        if counts.insert(hash, num_tx).is_some() {
+            return Err(CliError::TxCounts(format!(
+                "line {}: block {} is listed twice",
+                n, hash
+            )));
+        }
+    }
+    Ok(counts)
+}
+
+/// Compare our counts with theirs, writing a line to out for each block whose counts differ or
+/// that is missing from either side.
+///
+/// Our counts are streamed, the blocks that are left in theirs at the end are missing from ours.
+pub async fn reconcile<S, W>(
+    ours: S,
+    mut theirs: BTreeMap<BlockHash, u64>,
+    out: &mut W,
+) -> CliResult<Reconciliation>
+where
+    S: Stream<Item = CliResult<TxCount>>,
+    W: std::io::Write,
+{
+    let mut r = Reconciliation::default();
+    let mut ours = pin!(ours);
+    while let Some(c) = ours.next().await {
+        let c = c?;
+        match theirs.remove(&c.hash) {
+            Some(num_tx) => {
+                r.compared += 1;
+                if num_tx != c.num_tx {
+                    r.mismatched += 1;
+                    writeln!(
+                        out,
+                        "{} mismatch: ours {} from {}, theirs {}",
+                        c.hash, c.num_tx, c.source, num_tx
+                    )?;
+                }
+            }
+            None => {
+                r.missing_theirs += 1;
+                writeln!(
+                    out,
+                    "{} missing from theirs: ours {} from {}",
+                    c.hash, c.num_tx, c.source
+                )?;
+            }
+        }
+    }
+    for (h, num_tx) in theirs {
+        r.missing_ours += 1;
+        writeln!(out, "{} missing from ours: theirs {}", h, num_tx)?;
+    }
+    Ok(r)
+}
+
+// Open the chain store read-only if it is enabled, the counts are preferred from it and a
+// selector that needs it uses it.
+//
+// The FoundationDB network must have been started.
+async fn open_chain_store(
+    config: &BSVDBConfig,
+) -> CliResult<Option<(FDBChainStore, JoinHandle<()>)>> {
+    if !config.chain_store.enabled {
+        return Ok(None);
+    }
+    let r = FDBChainStore::new_readonly(&config.chain_store, config.get_blockchain_id()).await?;
+    Ok(Some(r))
+}
+
+// Look up the BlockInfo of a block, if there is a chain store.
+async fn stored_info(
+    chain_store: Option<&FDBChainStore>,
+    block_hash: BlockHash,
+) -> CliResult<Option<BlockInfo<u64>>> {
+    match chain_store {
+        Some(c) => Ok(c.get_block_info_by_hash(block_hash).await?),
+        None => Ok(None),
+    }
+}
+
+/// Write the transaction counts of the selected blocks to a csv file, see "ba txcounts".
+pub async fn export_tx_counts(
+    config: &BSVDBConfig,
+    selector: BlockSelector,
+    out: &str,
+) -> CliResult<()> {
+    config.check_block_archive_enabled()?;
+    let fdb_boot = config
+        .chain_store
+        .enabled
+        .then(|| unsafe { bsvdb_chainstore::boot(&config.chain_store) })
+        .transpose()?;
+    let chain_store = open_chain_store(config).await?;
+    let c = chain_store.as_ref().map(|(c, _)| c);
+    let selection = selector.resolve(config, c).await?;
+    let archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    let mut file = BufWriter::new(tokio::fs::File::create(out).await?);
+    let counts = tx_counts(&archive, selection.hashes, |h| stored_info(c, h));
+    let r = write_counts(counts, &mut file).await;
+    if let Some((chain_store, j)) = chain_store {
+        chain_store.shutdown().await?;
+        j.await?;
+    }
+    drop(fdb_boot);
+    println!("{} transaction counts written to {}", r?, out);
+    Ok(())
+}
+
+/// Reconcile the transaction counts of the selected blocks with the counts in a csv file, such as
+/// one produced by an external indexer, see "report reconcile-txcounts".
+///
+/// Returns whether the counts agree. Their counts are held in memory, ours are streamed.
+pub async fn reconcile_tx_counts(
+    config: &BSVDBConfig,
+    selector: BlockSelector,
+    theirs: &str,
+) -> CliResult<bool> {
+    config.check_block_archive_enabled()?;
+    let theirs = read_counts(BufReader::new(tokio::fs::File::open(theirs).await?)).await?;
+    let fdb_boot = config
+        .chain_store
+        .enabled
+        .then(|| unsafe { bsvdb_chainstore::boot(&config.chain_store) })
+        .transpose()?;
+    let chain_store = open_chain_store(config).await?;
+    let c = chain_store.as_ref().map(|(c, _)| c);
+    let selection = selector.resolve(config, c).await?;
+    let archive = SimpleFileBasedBlockArchive::new(&config.block_archive).await?;
+    let ours = tx_counts(&archive, selection.hashes, |h| stored_info(c, h));
+    let r = reconcile(ours, theirs, &mut std::io::stdout()).await;
+    if let Some((chain_store, j)) = chain_store {
+        chain_store.shutdown().await?;
+        j.await?;
+    }
+    drop(fdb_boot);
+    let r = r?;
+    println!(
+        "compared {} blocks, {} mismatched, {} missing from theirs, {} missing from ours",
+        r.compared, r.mismatched, r.missing_theirs, r.missing_ours
+    );
+    Ok(r.agrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoinsv::bitcoin::BlockchainId;
+    use bsvdb_base::BlockArchiveConfig;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    const GENESIS: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const BLOCK_1: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    const BLOCK_A: &str = "00000000000000a86c0a6d7b3445ff9e64908d6417cd6b256dbc23efd01de26f";
+    const BLOCK_UNKNOWN: &str = "0000000000000000094cc2ba6cc08514bcf9cbae26719d0a654a7754f3c75ef1";
+
+    async fn archive() -> SimpleFileBasedBlockArchive {
+        let config = BlockArchiveConfig {
+            enabled: true,
+            root_path: String::from("../testdata/blockarchive"),
+            ..BlockArchiveConfig::default()
+        };
+        SimpleFileBasedBlockArchive::new(&config).await.unwrap()
+    }
+
+    fn hash(s: &str) -> BlockHash {
+        s.parse().unwrap()
+    }
+
+    // The BlockInfos of a chain store in which genesis has a stale count and block 1 has no
+    // count, the other blocks are not in it.
+    fn stored() -> HashMap<BlockHash, BlockInfo<u64>> {
+        let mut genesis = BlockInfo::genesis_info(BlockchainId::Main);
+        genesis.num_tx = Some(7);
+        let mut block_1 = genesis.clone();
+        block_1.hash = hash(BLOCK_1);
+        block_1.height = 1;
+        block_1.num_tx = None;
+        HashMap::from([(genesis.hash, genesis), (block_1.hash, block_1)])
+    }
+
+    // The counts of the blocks, with the stored BlockInfos.
+    async fn counts(archive: &SimpleFileBasedBlockArchive, blocks: &[&str]) -> Vec<TxCount> {
+        let stored = stored();
+        let hashes = futures::stream::iter(blocks.iter().map(|h| Ok(hash(h)))).boxed();
+        tx_counts(archive, hashes, |h| {
+            let b_info = stored.get(&h).cloned();
+            async move { Ok(b_info) }
+        })
+        .map(|c| c.unwrap())
+        .collect()
+        .await
+    }
+
+    // A count is taken from the chain store if it has one, otherwise from the block, and blocks
+    // that are in neither are skipped.
+    #[tokio::test]
+    async fn count_sources() {
+        let archive = archive().await;
+        let counts = counts(&archive, &[GENESIS, BLOCK_1, BLOCK_A, BLOCK_UNKNOWN]).await;
+        let rows: Vec<_> = counts
+            .iter()
+            .map(|c| (c.hash, c.height, c.num_tx, c.source))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (hash(GENESIS), Some(0), 7, CountSource::ChainStore),
+                (hash(BLOCK_1), Some(1), 1, CountSource::Block),
+                (hash(BLOCK_A), None, 1, CountSource::Block),
+            ]
+        );
+    }
+
+    // The csv that is written can be read back.
+    #[tokio::test]
+    async fn csv() {
+        let archive = archive().await;
+        let mut out = Vec::new();
+        let n = write_counts(
+            futures::stream::iter(counts(&archive, &[GENESIS, BLOCK_A]).await).map(Ok),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(n, 2);
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "{}\n{},0,7,chainstore\n{},,1,block\n",
+                CSV_HEADER, GENESIS, BLOCK_A
+            )
+        );
+        let read = read_counts(out.as_slice()).await.unwrap();
+        assert_eq!(
+            read,
+            BTreeMap::from([(hash(GENESIS), 7), (hash(BLOCK_A), 1)])
+        );
+        // the columns are found by name
+        let other = format!("num_tx,extra,hash\n3,x,{}\n", BLOCK_1);
+        let read = read_counts(other.as_bytes()).await.unwrap();
+        assert_eq!(read, BTreeMap::from([(hash(BLOCK_1), 3)]));
+        for bad in [
+            String::new(),
+            String::from("hash,count\n"),
+            String::from("hash,num_tx\nnot-a-hash,1\n"),
+            format!("hash,num_tx\n{},many\n", BLOCK_1),
+            format!("hash,num_tx\n{},1\n{},1\n", BLOCK_1, BLOCK_1),
+        ] {
+            let r = read_counts(bad.as_bytes()).await;
+            assert!(matches!(r, Err(CliError::TxCounts(_))), "{:?}", bad);
+        }
+    }
+
+    // A file with one mismatched count and one missing block, and a block that we do not have.
+    #[tokio::test]
+    async fn reconciliation() {
+        let archive = archive().await;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("theirs.csv");
+        std::fs::write(
+            &path,
+            format!(
+                "hash,num_tx\n{},7\n{},2\n{},1\n",
+                GENESIS, BLOCK_1, BLOCK_UNKNOWN
+            ),
+        )
+        .unwrap();
+        let theirs = read_counts(BufReader::new(tokio::fs::File::open(&path).await.unwrap()))
+            .await
+            .unwrap();
+        let ours = futures::stream::iter(counts(&archive, &[GENESIS, BLOCK_1, BLOCK_A]).await);
+        let mut out = Vec::new();
+        let r = reconcile(ours.map(Ok), theirs, &mut out).await.unwrap();
+        assert_eq!(
+            r,
+            Reconciliation {
+                compared: 2,
+                mismatched: 1,
+                missing_theirs: 1,
+                missing_ours: 1,
+            }
+        );
+        assert!(!r.agrees());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "{} mismatch: ours 1 from block, theirs 2\n\
+                 {} missing from theirs: ours 1 from block\n\
+                 {} missing from ours: theirs 1\n",
+                BLOCK_1, BLOCK_A, BLOCK_UNKNOWN
+            )
+        );
+    }
+}
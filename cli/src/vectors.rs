@@ -0,0 +1,317 @@
+use crate::result::{CliError, CliResult};
+use bitcoinsv::bitcoin::{AsyncEncodable, BlockHash, BlockchainId};
+use bsvdb_blockarchive::genesis_block;
+use bsvdb_chainstore::{BlockInfo, BlockValidity, FDBChainStore};
+use std::path::Path;
+
+// Deterministic test vectors of the encodings of a small chain, for the developers of readers of
+// the layout in other languages.
+//
+// The chain is generated from a seed with a fixed generator, and the timestamps are derived from
+// the seed, so the same seed gives byte-identical files on every platform. A vector directory
+// contains, for each block, the raw block in block-<id>.bin and the BlockInfo as JSON with its
+// foundationdb records in block-<id>.json, and a manifest.json that lists them.
+
+// the version of the format of the vectors, recorded in the manifest
+const VECTORS_VERSION: u64 = 2;
+// the number of shards that the BlockInfo keys are encoded for, the default for a new ChainStore
+const VECTORS_SHARDS: u64 = 16;
+// the name of the manifest file
+const MANIFEST: &str = "manifest.json";
+
+// The splitmix64 generator, which is defined by its algorithm rather than by a library version.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Generate the test vectors for the seed in the directory, see "dev gen-vectors".
+pub async fn gen_vectors(seed: u64, blocks: u64, out: &str) -> CliResult<()> {
+    tokio::fs::create_dir_all(out).await?;
+    let files = vector_files(seed, blocks);
+    for (name, data) in files.iter() {
+        tokio::fs::write(Path::new(out).join(name), data).await?;
+    }
+    println!("{} files written to {}", files.len(), out);
+    Ok(())
+}
+
+/// Check that the test vectors in the directory are still produced, byte for byte, by the current
+/// code, returning whether they are.
+pub async fn verify_vectors(dir: &str) -> CliResult<bool> {
+    let manifest = tokio::fs::read_to_string(Path::new(dir).join(MANIFEST)).await?;
+    let number = |name| {
+        manifest_number(&manifest, name)
+            .ok_or_else(|| CliError::Vectors(format!("{} not found in {}", name, MANIFEST)))
+    };
+    let version = number("version")?;
+    if version != VECTORS_VERSION {
+        return Err(CliError::Vectors(format!(
+            "version {} is not supported, expected {}",
+            version, VECTORS_VERSION
+        )));
+    }
+    let files = vector_files(number("seed")?, number("blocks")?);
+    let mut passed = true;
+    for (name, data) in files.iter() {
+        match tokio::fs::read(Path::new(dir).join(name)).await {
+            Ok(d) if d == *data => {}
+            Ok(_) => {
+                println!("FAIL: {} differs", name);
+                passed = false;
+            }
+            Err(e) => {
+                println!("FAIL: {}: {}", name, e);
+                passed = false;
+            }
+        }
+    }
+    if passed {
+        println!("PASS: {} files match", files.len());
+    }
+    Ok(passed)
+}
+
+// Get a number from the top level of a manifest written by vector_files().
+fn manifest_number(manifest: &str, name: &str) -> Option<u64> {
+    let key = format!("\"{}\":", name);
+    let start = manifest.find(&key)? + key.len();
+    let digits: String = manifest[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+// The files of the vectors, with their names.
+fn vector_files(seed: u64, blocks: u64) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut entries = Vec::new();
+    for (info, raw) in generate_chain(seed, blocks) {
+        let block_file = format!("block-{}.bin", info.id);
+        let info_file = format!("block-{}.json", info.id);
+        let (info_key, info_value) = FDBChainStore::block_info_record(&info, VECTORS_SHARDS);
+        let (index_key, index_value) = FDBChainStore::hash_index_record(&info.hash, info.id);
+        let json = format!(
+            "{{\"block_info\":{},\"records\":[{},{}]}}\n",
+            block_info_json(&info),
+            record_json("infos", &info_key, &info_value),
+            record_json("hindex", &index_key, &index_value)
+        );
+        entries.push(format!(
+            "{{\"id\":{},\"hash\":\"{}\",\"height\":{},\"block\":\"{}\",\"info\":\"{}\"}}",
+            info.id, info.hash, info.height, block_file, info_file
+        ));
+        files.push((block_file, raw));
+        files.push((info_file, json.into_bytes()));
+    }
+    let manifest = format!(
+        "{{\"version\":{},\"seed\":{},\"blocks\":{},\"chain\":\"regtest\",\"info_shards\":{},\"entries\":[{}]}}\n",
+        VECTORS_VERSION,
+        seed,
+        blocks,
+        VECTORS_SHARDS,
+        entries.join(",")
+    );
+    files.push((String::from(MANIFEST), manifest.into_bytes()));
+    files
+}
+
+// Generate the regtest genesis block followed by a main chain of blocks, and a block that forks
+// from the middle of the main chain when there are at least two blocks. The ids are in order of
+// generation, so the fork has the last id.
+pub(crate) fn generate_chain(seed: u64, blocks: u64) -> Vec<(BlockInfo<u64>, Vec<u8>)> {
+    let mut rng = SplitMix64(seed);
+    let mut chain = vec![(
+        BlockInfo::genesis_info(BlockchainId::Regtest),
+        genesis_block(BlockchainId::Regtest),
+    )];
+    let main_len = if blocks >= 2 { blocks - 1 } else { blocks };
+    for id in 1..=main_len {
+        let b = child_block(&chain[id as usize - 1].0, id, &mut rng);
+        chain.push(b);
+    }
+    if blocks >= 2 {
+        let b = child_block(&chain[(main_len / 2) as usize].0, blocks, &mut rng);
+        chain.push(b);
+    }
+    for id in 1..chain.len() {
+        let prev_id = chain[id].0.prev_id as usize;
+        chain[prev_id].0.next_ids.push(id as u64);
+    }
+    chain
+}
+
+// Generate a block with a coinbase transaction on the parent.
+fn child_block(
+    parent: &BlockInfo<u64>,
+    id: u64,
+    rng: &mut SplitMix64,
+) -> (BlockInfo<u64>, Vec<u8>) {
+    let height = parent.height + 1;
+    let tx = coinbase(height, rng.next_u64());
+    let mut header = parent.header.clone();
+    header.prev_hash = parent.hash;
+    header.merkle_root = BlockHash::sha256d(&tx);
+    header.timestamp = parent.header.timestamp + 1 + (rng.next_u64() % 1200) as u32;
+    header.nonce = rng.next_u64() as u32;
+    let mut raw = header.to_binary_buf().unwrap();
+    // the number of transactions
+    raw.push(1);
+    raw.extend(tx);
+    let size = raw.len() as u64;
+    let info = BlockInfo {
+        id,
+        hash: header.hash(),
+        height,
+        prev_id: parent.id,
+        next_ids: vec![],
+        size: Some(size),
+        num_tx: Some(1),
+        median_time: None,
+        chain_work: None,
+        total_tx: parent.total_tx.map(|t| t + 1),
+        total_size: parent.total_size.map(|t| t + size),
+        total_fees: None,
+        miner: None,
+        validity: BlockValidity::Valid,
+        received_time: Some(header.timestamp as u64 + rng.next_u64() % 30),
+        source: None,
+        header,
+    };
+    (info, raw)
+}
+
+// A coinbase transaction that pays 50 coins to OP_TRUE, with the height and the extra nonce in
+// the script.
+fn coinbase(height: u64, extra_nonce: u64) -> Vec<u8> {
+    let mut script = vec![8];
+    script.extend(height.to_le_bytes());
+    script.push(8);
+    script.extend(extra_nonce.to_le_bytes());
+    // version and one input, which spends nothing
+    let mut tx = vec![1, 0, 0, 0, 1];
+    tx.extend([0; 32]);
+    tx.extend([0xff; 4]);
+    tx.push(script.len() as u8);
+    tx.extend(script);
+    tx.extend([0xff; 4]);
+    // one output
+    tx.push(1);
+    tx.extend(5_000_000_000u64.to_le_bytes());
+    tx.extend([1, 0x51]);
+    // lock time
+    tx.extend([0; 4]);
+    tx
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A foundationdb record, the key is relative to the directory within the ChainStore directory.
+fn record_json(directory: &str, key: &[u8], value: &[u8]) -> String {
+    format!(
+        "{{\"directory\":\"{}\",\"key\":\"{}\",\"value\":\"{}\"}}",
+        directory,
+        to_hex(key),
+        to_hex(value)
+    )
+}
+
+fn block_info_json(b: &BlockInfo<u64>) -> String {
+    let number = |v: Option<u64>| {
+        v.map(|i| i.to_string())
+            .unwrap_or_else(|| String::from("null"))
+    };
+    let next_ids: Vec<String> = b.next_ids.iter().map(|i| i.to_string()).collect();
+    format!(
+        "{{\"id\":{},\"hash\":\"{}\",\"header\":\"{}\",\"height\":{},\"prev_id\":{},\"next_ids\":[{}],\"size\":{},\"num_tx\":{},\"median_time\":{},\"chain_work\":{},\"total_tx\":{},\"total_size\":{},\"miner\":{},\"validity\":{},\"received_time\":{},\"source\":{}}}",
+        b.id,
+        b.hash,
+        to_hex(&b.header.to_binary_buf().unwrap()),
+        b.height,
+        b.prev_id,
+        next_ids.join(","),
+        number(b.size),
+        number(b.num_tx),
+        number(b.median_time),
+        b.chain_work
+            .as_ref()
+            .map(|w| format!("\"{}\"", to_hex(w)))
+            .unwrap_or_else(|| String::from("null")),
+        number(b.total_tx),
+        number(b.total_size),
+        b.miner
+            .as_ref()
+            .map(|m| format!("{:?}", m))
+            .unwrap_or_else(|| String::from("null")),
+        u8::from(b.validity.clone()),
+        number(b.received_time),
+        b.source
+            .as_ref()
+            .map(|m| format!("{:?}", m))
+            .unwrap_or_else(|| String::from("null"))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    // The same seed gives the same files, another seed different ones.
+    #[test]
+    fn deterministic() {
+        assert_eq!(vector_files(7, 6), vector_files(7, 6));
+        assert_ne!(vector_files(7, 6), vector_files(8, 6));
+    }
+
+    // The chain has a main chain and a fork from its middle, and the blocks link.
+    #[test]
+    fn chain_shape() {
+        let chain = generate_chain(1, 6);
+        assert_eq!(chain.len(), 7);
+        let heights: Vec<u64> = chain.iter().map(|(b, _)| b.height).collect();
+        assert_eq!(heights, vec![0, 1, 2, 3, 4, 5, 3]);
+        assert_eq!(chain[2].0.next_ids, vec![3, 6]);
+        assert_eq!(chain[6].0.prev_id, 2);
+        for (b, raw) in chain.iter().skip(1) {
+            let parent = &chain[b.prev_id as usize].0;
+            assert_eq!(b.header.prev_hash, parent.hash);
+            assert!(b.header.timestamp > parent.header.timestamp);
+            assert_eq!(raw.len() as u64, b.size.unwrap());
+            assert_eq!(&raw[..80], b.header.to_binary_buf().unwrap().as_slice());
+        }
+        assert_eq!(generate_chain(1, 1).len(), 2);
+    }
+
+    #[test]
+    fn manifest_numbers() {
+        let files = vector_files(42, 3);
+        let manifest = String::from_utf8(files.last().unwrap().1.clone()).unwrap();
+        assert_eq!(manifest_number(&manifest, "version"), Some(VECTORS_VERSION));
+        assert_eq!(manifest_number(&manifest, "seed"), Some(42));
+        assert_eq!(manifest_number(&manifest, "blocks"), Some(3));
+        assert_eq!(manifest_number(&manifest, "height"), Some(0));
+        assert_eq!(manifest_number(&manifest, "other"), None);
+    }
+
+    // Generated vectors verify, and a changed file is found.
+    #[tokio::test]
+    async fn verify() {
+        let root = tempdir().unwrap();
+        let dir = root.path().to_str().unwrap();
+        gen_vectors(3, 4, dir).await.unwrap();
+        assert!(verify_vectors(dir).await.unwrap());
+        std::fs::write(root.path().join("block-2.json"), "{}").unwrap();
+        assert!(!verify_vectors(dir).await.unwrap());
+    }
+}
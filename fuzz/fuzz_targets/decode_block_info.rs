@@ -0,0 +1,23 @@
+#![no_main]
+
+// A BlockInfo record is either rejected or decoded to a BlockInfo that encodes and decodes to
+// itself. The brief of a record that decodes has the same fields.
+
+use bsvdb_chainstore::FDBChainStore;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let brief = FDBChainStore::decode_block_brief_record(&[], data);
+    if let Ok(b_info) = FDBChainStore::decode_block_info_record(&[], data) {
+        let brief = brief.unwrap();
+        assert_eq!(
+            (brief.id, brief.height, &brief.validity),
+            (b_info.id, b_info.height, &b_info.validity)
+        );
+        let (_, v) = FDBChainStore::block_info_record(&b_info, 0);
+        assert_eq!(
+            FDBChainStore::decode_block_info_record(&[], &v).unwrap(),
+            b_info
+        );
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+// A ChainState record is either rejected or decoded to a ChainState that encodes and decodes to
+// itself.
+
+use bsvdb_chainstore::FDBChainStore;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(state) = FDBChainStore::decode_chain_state_record(&[], data) {
+        let v = FDBChainStore::chain_state_record(&state);
+        assert_eq!(
+            FDBChainStore::decode_chain_state_record(&[], &v).unwrap(),
+            state
+        );
+    }
+});
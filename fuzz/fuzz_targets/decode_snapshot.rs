@@ -0,0 +1,14 @@
+#![no_main]
+
+// A snapshot file is either rejected or decoded to a snapshot that can be printed and encodes and
+// decodes to itself.
+
+use bsvdb_chainstore::Snapshot;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(snapshot) = Snapshot::decode(data) {
+        let _ = snapshot.to_string();
+        assert_eq!(Snapshot::decode(&snapshot.encode()).unwrap(), snapshot);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+// A filter expression is either parsed or rejected with an error at a position in the
+// expression, without panicking.
+
+use bsvdb_chainstore::Filter;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|expr: &str| {
+    if let Err(e) = expr.parse::<Filter>() {
+        assert!(e.position <= expr.chars().count());
+    }
+});
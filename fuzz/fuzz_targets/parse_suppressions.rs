@@ -0,0 +1,10 @@
+#![no_main]
+
+// A suppression file is either rejected or parsed, without panicking.
+
+use bsvdb_blockarchive::Suppressions;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    let _ = Suppressions::parse(text);
+});
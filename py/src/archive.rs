@@ -0,0 +1,154 @@
+use crate::{
+    block_on, header_dict, load_config, parse_hash, runtime, BackendUnavailable, BlockNotFound,
+    BsvdbError,
+};
+use bitcoinsv::bitcoin::BlockHash;
+use bsvdb_blockarchive::{
+    BlockArchive as _, BlockHashListStream, Error, SimpleFileBasedBlockArchive,
+};
+use futures::StreamExt;
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+
+type HashStream = Pin<Box<dyn BlockHashListStream<Item = bsvdb_blockarchive::Result<BlockHash>>>>;
+
+// Map an error of the block archive to an exception.
+pub(crate) fn archive_error(e: Error) -> PyErr {
+    match e {
+        Error::BlockNotFound(h) => BlockNotFound::new_err(h.to_string()),
+        Error::Timeout => BackendUnavailable::new_err(e.to_string()),
+        Error::IoError(e) => PyOSError::new_err(e.to_string()),
+        e => BsvdbError::new_err(e.to_string()),
+    }
+}
+
+/// A block archive opened for reading.
+///
+/// The genesis block of the configured chain is returned by get_block_bytes() and block_header()
+/// if it is not stored, as it is by the CLI.
+#[pyclass(frozen, module = "bsvdb")]
+pub struct BlockArchive {
+    // block_list() needs the archive mutably, the other reads share it
+    archive: Arc<RwLock<SimpleFileBasedBlockArchive>>,
+}
+
+#[pymethods]
+impl BlockArchive {
+    /// Open the block archive of the configuration, which is loaded as by the CLI with the file
+    /// at config_path, if given, overriding the others.
+    #[staticmethod]
+    #[pyo3(signature = (config_path=None))]
+    fn open(py: Python<'_>, config_path: Option<String>) -> PyResult<Self> {
+        let config = load_config(config_path)?;
+        config
+            .check_block_archive_enabled()
+            .map_err(|e| BsvdbError::new_err(e.to_string()))?;
+        let mut archive = block_on(py, SimpleFileBasedBlockArchive::new(&config.block_archive))
+            .map_err(archive_error)?;
+        archive.genesis = Some(config.get_blockchain_id());
+        Ok(BlockArchive {
+            archive: Arc::new(RwLock::new(archive)),
+        })
+    }
+
+    /// The encoded block, raises BlockNotFound if it is not in the archive.
+    ///
+    /// The whole block is read into memory.
+    fn get_block_bytes<'py>(&self, py: Python<'py>, hash: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let hash = parse_hash(hash)?;
+        let archive = self.archive.clone();
+        let block = block_on(py, async move {
+            let mut reader = archive.read().await.get_block(&hash).await?;
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await?;
+            Ok::<_, Error>(buf)
+        })
+        .map_err(archive_error)?;
+        Ok(PyBytes::new_bound(py, &block))
+    }
+
+    /// The header of the block as a dict, raises BlockNotFound if it is not in the archive.
+    fn block_header<'py>(&self, py: Python<'py>, hash: &str) -> PyResult<Bound<'py, PyDict>> {
+        let hash = parse_hash(hash)?;
+        let archive = self.archive.clone();
+        let header = block_on(
+            py,
+            async move { archive.read().await.block_header(&hash).await },
+        )
+        .map_err(archive_error)?;
+        header_dict(py, &header)
+    }
+
+    /// Whether the block is in the archive.
+    fn block_exists(&self, py: Python<'_>, hash: &str) -> PyResult<bool> {
+        let hash = parse_hash(hash)?;
+        let archive = self.archive.clone();
+        block_on(
+            py,
+            async move { archive.read().await.block_exists(&hash).await },
+        )
+        .map_err(archive_error)
+    }
+
+    /// An iterator over the hashes of the blocks in the archive, in no particular order.
+    ///
+    /// An error listing the archive is raised by the iterator, which then ends.
+    fn block_hashes(&self, py: Python<'_>) -> PyResult<BlockHashIterator> {
+        let archive = self.archive.clone();
+        let stream = block_on(py, async move { archive.write().await.block_list().await })
+            .map_err(archive_error)?;
+        Ok(BlockHashIterator {
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+}
+
+/// The iterator returned by BlockArchive.block_hashes().
+#[pyclass(module = "bsvdb")]
+pub struct BlockHashIterator {
+    // None once the listing has ended
+    stream: Mutex<Option<HashStream>>,
+}
+
+#[pymethods]
+impl BlockHashIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<String>> {
+        // the lock is taken without the GIL, so that a thread waiting for it does not block the
+        // thread that holds it
+        let next = py.allow_threads(|| {
+            let mut stream = self.stream.lock().unwrap();
+            runtime().block_on(next_hash(&mut stream))
+        });
+        next.map(|h| h.map(|h| h.to_string()))
+            .map_err(archive_error)
+    }
+}
+
+// The next hash of the listing, the outcome of the listing is checked once it has ended.
+async fn next_hash(
+    stream: &mut Option<HashStream>,
+) -> bsvdb_blockarchive::Result<Option<BlockHash>> {
+    let Some(s) = stream.as_mut() else {
+        return Ok(None);
+    };
+    match s.next().await {
+        Some(Ok(h)) => Ok(Some(h)),
+        Some(Err(e)) => {
+            *stream = None;
+            Err(e)
+        }
+        None => {
+            stream.take().unwrap().finish().await?;
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    block_on, header_dict, load_config, parse_hash, runtime, BackendUnavailable, BlockNotFound,
+    BsvdbError,
+};
+use bsvdb_base::ChainStoreConfig;
+use bsvdb_chainstore::{BlockInfo, ChainStore as _, Error, FDBChainStore, Result};
+use futures::{Stream, StreamExt};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::pin::{pin, Pin};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+type InfoStream = Pin<Box<dyn Stream<Item = Result<BlockInfo<u64>>> + Send>>;
+
+// The foundationdb errors which mean that the cluster could not be reached in time:
+// transaction_timed_out and process_behind.
+const UNAVAILABLE_CODES: [i32; 2] = [1031, 1037];
+
+// Map an error of the chain store to an exception.
+pub(crate) fn chain_store_error(e: Error) -> PyErr {
+    match e {
+        Error::BlockNotFound(h) => BlockNotFound::new_err(h.to_string()),
+        Error::Overloaded(..) | Error::BackendSlow(..) => {
+            BackendUnavailable::new_err(e.to_string())
+        }
+        Error::FdbError(ref f) if UNAVAILABLE_CODES.contains(&f.code()) => {
+            BackendUnavailable::new_err(e.to_string())
+        }
+        e => BsvdbError::new_err(e.to_string()),
+    }
+}
+
+// The outcome of starting the foundationdb network, which can only be started once in a process.
+static NETWORK: OnceLock<std::result::Result<(), String>> = OnceLock::new();
+
+// Start the foundationdb network if it has not been started. It runs until the process exits, as
+// python does not unload extension modules, so the handle that would stop it is never dropped.
+fn boot(config: &ChainStoreConfig) -> PyResult<()> {
+    NETWORK
+        .get_or_init(|| match unsafe { bsvdb_chainstore::boot(config) } {
+            Ok(network) => {
+                std::mem::forget(network);
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        })
+        .clone()
+        .map_err(BsvdbError::new_err)
+}
+
+/// A key of get_block_info(), either the hash of a block or a height on the main chain.
+#[derive(FromPyObject)]
+enum BlockKey {
+    Height(u64),
+    Hash(String),
+}
+
+/// A chain store opened in read-only mode.
+#[pyclass(frozen, module = "bsvdb")]
+pub struct ChainStore {
+    store: FDBChainStore,
+}
+
+#[pymethods]
+impl ChainStore {
+    /// Open the chain store of the configuration, which is loaded as by the CLI with the file at
+    /// config_path, if given, overriding the others.
+    ///
+    /// The chain store must have been initialized. Raises BackendUnavailable if it can not be
+    /// opened within timeout seconds, which is how an unreachable cluster shows.
+    #[staticmethod]
+    #[pyo3(signature = (config_path=None, timeout=10.0))]
+    fn open(py: Python<'_>, config_path: Option<String>, timeout: f64) -> PyResult<Self> {
+        let config = load_config(config_path)?;
+        config
+            .check_chain_store_enabled()
+            .map_err(|e| BsvdbError::new_err(e.to_string()))?;
+        boot(&config.chain_store)?;
+        let chain = config.get_blockchain_id();
+        let open = FDBChainStore::new_readonly(&config.chain_store, chain);
+        let (store, _j) = block_on(py, async {
+            tokio::time::timeout(Duration::from_secs_f64(timeout), open).await
+        })
+        .map_err(|_| {
+            BackendUnavailable::new_err(format!(
+                "chain store not opened within {} seconds",
+                timeout
+            ))
+        })?
+        .map_err(chain_store_error)?;
+        Ok(ChainStore { store })
+    }
+
+    /// The info of a block as a dict, given its hash or its height on the main chain. Raises
+    /// BlockNotFound if there is no such block.
+    fn get_block_info<'py>(&self, py: Python<'py>, key: BlockKey) -> PyResult<Bound<'py, PyDict>> {
+        let store = self.store.clone();
+        let info = match key {
+            BlockKey::Hash(h) => {
+                let hash = parse_hash(&h)?;
+                block_on(py, async move { store.get_block_info_by_hash(hash).await })
+                    .map_err(chain_store_error)?
+                    .ok_or_else(|| BlockNotFound::new_err(h))?
+            }
+            BlockKey::Height(height) => {
+                block_on(py, async move { info_at_height(&store, height).await })
+                    .map_err(chain_store_error)?
+                    .ok_or_else(|| {
+                        BlockNotFound::new_err(format!("no block at height {}", height))
+                    })?
+            }
+        };
+        info_dict(py, &info)
+    }
+
+    /// An iterator over the infos of the blocks of the main chain from height start up to, but
+    /// not including, height end, or up to the most work tip if end is None.
+    ///
+    /// The main chain is read from the genesis block, the blocks below start are read and
+    /// skipped.
+    #[pyo3(signature = (start=0, end=None))]
+    fn main_chain(&self, start: u64, end: Option<u64>) -> BlockInfoIterator {
+        BlockInfoIterator {
+            stream: Mutex::new(Some(self.store.stream_main_chain())),
+            start,
+            end,
+        }
+    }
+
+    /// A summary of the chain store and its chain state as a dict.
+    fn summary<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let store = self.store.clone();
+        let (summary, state, tip) = block_on(py, async move {
+            let summary = store.summary().await?;
+            let state = store.get_chain_state().await?;
+            let tip = store
+                .get_block_info(state.most_work_tip)
+                .await?
+                .ok_or(Error::BlockInfoNotFound(state.most_work_tip))?;
+            Ok::<_, Error>((summary, state, tip))
+        })
+        .map_err(chain_store_error)?;
+        let d = PyDict::new_bound(py);
+        d.set_item("num_blocks", summary.num_blocks)?;
+        let counts = PyDict::new_bound(py);
+        for (validity, n) in summary.validity_counts {
+            counts.set_item(format!("{:?}", validity), n)?;
+        }
+        d.set_item("validity_counts", counts)?;
+        d.set_item("num_tips", summary.num_tips)?;
+        d.set_item("tip_height", summary.tip_height)?;
+        d.set_item("tip_hash", tip.hash.to_string())?;
+        d.set_item("genesis_hash", summary.genesis_hash.to_string())?;
+        d.set_item("active_tips", state.active_tips.len())?;
+        d.set_item("dormant_tips", state.dormant_tips.len())?;
+        d.set_item("invalid_tips", state.invalid_tips.len())?;
+        Ok(d)
+    }
+}
+
+/// The iterator returned by ChainStore.main_chain().
+#[pyclass(module = "bsvdb")]
+pub struct BlockInfoIterator {
+    // None once the stream has ended
+    stream: Mutex<Option<InfoStream>>,
+    start: u64,
+    end: Option<u64>,
+}
+
+#[pymethods]
+impl BlockInfoIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        // the lock is taken without the GIL, so that a thread waiting for it does not block the
+        // thread that holds it
+        let next = py.allow_threads(|| {
+            let mut stream = self.stream.lock().unwrap();
+            runtime().block_on(self.next_info(&mut stream))
+        });
+        match next.map_err(chain_store_error)? {
+            Some(info) => Ok(Some(info_dict(py, &info)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BlockInfoIterator {
+    // The next info of the main chain within the heights.
+    async fn next_info(&self, stream: &mut Option<InfoStream>) -> Result<Option<BlockInfo<u64>>> {
+        let Some(s) = stream.as_mut() else {
+            return Ok(None);
+        };
+        loop {
+            match s.next().await {
+                Some(Ok(info)) if info.height < self.start => continue,
+                Some(Ok(info)) if self.end.map_or(true, |end| info.height < end) => {
+                    return Ok(Some(info))
+                }
+                Some(Err(e)) => {
+                    *stream = None;
+                    return Err(e);
+                }
+                // the end of the stream or a block at or above end
+                _ => {
+                    *stream = None;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+// The info of the block at the height on the main chain, found by walking down from the most work
+// tip.
+async fn info_at_height(store: &FDBChainStore, height: u64) -> Result<Option<BlockInfo<u64>>> {
+    let state = store.get_chain_state().await?;
+    let tip = store
+        .get_block_info(state.most_work_tip)
+        .await?
+        .ok_or(Error::BlockInfoNotFound(state.most_work_tip))?;
+    if height > tip.height {
+        return Ok(None);
+    }
+    let mut infos = pin!(
+        store
+            .get_block_infos(tip.id, Some(tip.height - height + 1))
+            .await?
+    );
+    let mut last = None;
+    while let Some(info) = infos.next().await {
+        last = Some(info?);
+    }
+    Ok(last.filter(|i| i.height == height))
+}
+
+fn info_dict<'py>(py: Python<'py>, info: &BlockInfo<u64>) -> PyResult<Bound<'py, PyDict>> {
+    let d = PyDict::new_bound(py);
+    d.set_item("hash", info.hash.to_string())?;
+    d.set_item("height", info.height)?;
+    d.set_item("header", header_dict(py, &info.header)?)?;
+    d.set_item("size", info.size)?;
+    d.set_item("num_tx", info.num_tx)?;
+    d.set_item("median_time", info.median_time)?;
+    d.set_item("chain_work", info.chain_work.as_ref().map(hex::encode))?;
+    d.set_item("total_tx", info.total_tx)?;
+    d.set_item("total_size", info.total_size)?;
+    d.set_item("miner", info.miner.clone())?;
+    d.set_item("validity", format!("{:?}", info.validity))?;
+    d.set_item("received_time", info.received_time)?;
+    d.set_item("source", info.source.clone())?;
+    Ok(d)
+}
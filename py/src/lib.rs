@@ -0,0 +1,99 @@
+//! Python bindings for the read APIs of the block archive and the chain store.
+//!
+//! The bindings are blocking: each call runs the async API to completion on a runtime that is
+//! owned by the module, and releases the GIL while it waits, so that other Python threads can run
+//! during the IO.
+//!
+//! Errors are raised as exceptions of the module, [BlockNotFound] is a KeyError so that a missing
+//! block can be handled like a missing key, and [BackendUnavailable] is a ConnectionError.
+mod archive;
+mod chain_store;
+
+use archive::{BlockArchive, BlockHashIterator};
+use bitcoinsv::bitcoin::{BlockHash, BlockHeader};
+use bsvdb_base::BSVDBConfig;
+use chain_store::{BlockInfoIterator, ChainStore};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyConnectionError, PyException, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+
+create_exception!(
+    bsvdb,
+    BsvdbError,
+    PyException,
+    "An error reading the block archive or the chain store."
+);
+create_exception!(
+    bsvdb,
+    BlockNotFound,
+    PyKeyError,
+    "The block is not in the block archive or the chain store."
+);
+create_exception!(
+    bsvdb,
+    BackendUnavailable,
+    PyConnectionError,
+    "The block archive or the chain store could not be reached, the call can be retried."
+);
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+// the runtime on which the async APIs are run, it lives as long as the process as python does not
+// unload extension modules
+pub(crate) fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+// run a future to completion with the GIL released
+pub(crate) fn block_on<F>(py: Python<'_>, f: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    py.allow_threads(|| runtime().block_on(f))
+}
+
+// load and validate the configuration, as the CLI does
+pub(crate) fn load_config(config_path: Option<String>) -> PyResult<BSVDBConfig> {
+    BSVDBConfig::new(config_path).map_err(|e| BsvdbError::new_err(e.to_string()))
+}
+
+pub(crate) fn parse_hash(hash: &str) -> PyResult<BlockHash> {
+    hash.parse::<BlockHash>()
+        .map_err(|_| PyValueError::new_err(format!("not a block hash: {}", hash)))
+}
+
+pub(crate) fn header_dict<'py>(
+    py: Python<'py>,
+    header: &BlockHeader,
+) -> PyResult<Bound<'py, PyDict>> {
+    let d = PyDict::new_bound(py);
+    d.set_item("hash", header.hash().to_string())?;
+    d.set_item("version", header.version)?;
+    d.set_item("prev_hash", header.prev_hash.to_string())?;
+    d.set_item("merkle_root", header.merkle_root.to_string())?;
+    d.set_item("timestamp", header.timestamp)?;
+    d.set_item("bits", header.bits)?;
+    d.set_item("nonce", header.nonce)?;
+    Ok(d)
+}
+
+#[pymodule]
+fn bsvdb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("BsvdbError", py.get_type_bound::<BsvdbError>())?;
+    m.add("BlockNotFound", py.get_type_bound::<BlockNotFound>())?;
+    m.add(
+        "BackendUnavailable",
+        py.get_type_bound::<BackendUnavailable>(),
+    )?;
+    m.add_class::<BlockArchive>()?;
+    m.add_class::<BlockHashIterator>()?;
+    m.add_class::<ChainStore>()?;
+    m.add_class::<BlockInfoIterator>()?;
+    Ok(())
+}
@@ -0,0 +1,30 @@
+import os
+import pathlib
+
+import pytest
+
+TESTDATA = pathlib.Path(__file__).resolve().parents[2] / "testdata"
+
+
+@pytest.fixture
+def archive_config(tmp_path):
+    """A configuration with the block archive of the test data."""
+    path = tmp_path / "bsvdb.toml"
+    path.write_text(
+        "[block_archive]\n"
+        "enabled = true\n"
+        f'root_path = "{TESTDATA / "blockarchive"}"\n'
+    )
+    return str(path)
+
+
+@pytest.fixture
+def chain_store_config():
+    """The configuration of an initialized chain store, given by BSVDB_TEST_CHAIN_STORE.
+
+    There is no chain store that does not need foundationdb, so the tests of the chain store are
+    skipped unless one is given."""
+    path = os.environ.get("BSVDB_TEST_CHAIN_STORE")
+    if not path:
+        pytest.skip("BSVDB_TEST_CHAIN_STORE is not set")
+    return path
@@ -0,0 +1,82 @@
+import threading
+
+import pytest
+
+import bsvdb
+
+GENESIS = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+BLOCK_1 = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+UNKNOWN = "00000000000000000000000000000000000000000000000000000000000000ff"
+
+
+def test_get_block_bytes(archive_config):
+    archive = bsvdb.BlockArchive.open(archive_config)
+    block = archive.get_block_bytes(BLOCK_1)
+    assert isinstance(block, bytes)
+    assert len(block) == 215
+
+
+def test_block_header(archive_config):
+    archive = bsvdb.BlockArchive.open(archive_config)
+    header = archive.block_header(BLOCK_1)
+    assert header == {
+        "hash": BLOCK_1,
+        "version": 1,
+        "prev_hash": GENESIS,
+        "merkle_root": "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098",
+        "timestamp": 1231469665,
+        "bits": 0x1D00FFFF,
+        "nonce": 2573394689,
+    }
+
+
+def test_block_not_found(archive_config):
+    archive = bsvdb.BlockArchive.open(archive_config)
+    assert not archive.block_exists(UNKNOWN)
+    with pytest.raises(bsvdb.BlockNotFound):
+        archive.get_block_bytes(UNKNOWN)
+    # a missing block can be handled as a missing key
+    with pytest.raises(KeyError):
+        archive.block_header(UNKNOWN)
+
+
+def test_invalid_hash(archive_config):
+    archive = bsvdb.BlockArchive.open(archive_config)
+    with pytest.raises(ValueError):
+        archive.block_header("not a hash")
+
+
+def test_block_hashes(archive_config):
+    archive = bsvdb.BlockArchive.open(archive_config)
+    hashes = list(archive.block_hashes())
+    assert BLOCK_1 in hashes
+    assert GENESIS in hashes
+    assert len(hashes) == len(set(hashes))
+    # the misplaced block is not listed
+    assert "000000001ee3392a6b6ba0bf2480a0f6bf9cdaaefa331bc0dfb243523af41a44" not in hashes
+    for h in hashes:
+        assert archive.block_exists(h)
+
+
+def test_threads(archive_config):
+    # the GIL is released during the reads, so that they can be made from several threads
+    archive = bsvdb.BlockArchive.open(archive_config)
+    results = []
+
+    def read():
+        results.append(archive.get_block_bytes(BLOCK_1))
+
+    threads = [threading.Thread(target=read) for _ in range(8)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    assert len(results) == 8
+    assert all(r == results[0] for r in results)
+
+
+def test_not_enabled(tmp_path):
+    path = tmp_path / "bsvdb.toml"
+    path.write_text("[block_archive]\nenabled = false\n")
+    with pytest.raises(bsvdb.BsvdbError):
+        bsvdb.BlockArchive.open(str(path))
@@ -0,0 +1,43 @@
+import pytest
+
+import bsvdb
+
+UNKNOWN = "00000000000000000000000000000000000000000000000000000000000000ff"
+
+
+def test_not_enabled(tmp_path):
+    path = tmp_path / "bsvdb.toml"
+    path.write_text("[chain_store]\nenabled = false\n")
+    with pytest.raises(bsvdb.BsvdbError):
+        bsvdb.ChainStore.open(str(path))
+
+
+def test_exceptions():
+    assert issubclass(bsvdb.BlockNotFound, KeyError)
+    assert issubclass(bsvdb.BackendUnavailable, ConnectionError)
+
+
+def test_get_block_info(chain_store_config):
+    store = bsvdb.ChainStore.open(chain_store_config)
+    summary = store.summary()
+    genesis = store.get_block_info(0)
+    assert genesis["hash"] == summary["genesis_hash"]
+    assert store.get_block_info(genesis["hash"]) == genesis
+    tip = store.get_block_info(summary["tip_height"])
+    assert tip["hash"] == summary["tip_hash"]
+    with pytest.raises(bsvdb.BlockNotFound):
+        store.get_block_info(summary["tip_height"] + 1)
+    with pytest.raises(KeyError):
+        store.get_block_info(UNKNOWN)
+
+
+def test_main_chain(chain_store_config):
+    store = bsvdb.ChainStore.open(chain_store_config)
+    tip_height = store.summary()["tip_height"]
+    infos = list(store.main_chain())
+    assert [i["height"] for i in infos] == list(range(tip_height + 1))
+    for parent, child in zip(infos, infos[1:]):
+        assert child["header"]["prev_hash"] == parent["hash"]
+    end = min(tip_height + 1, 3)
+    assert list(store.main_chain(1, end)) == infos[1:end]
+    assert list(store.main_chain(tip_height + 1)) == []